@@ -0,0 +1,120 @@
+// Benchmarks of building images from the synthetic trees makeiso bench generates
+//
+//     cargo bench                      every tree with every variant
+//     cargo bench -- small mmap        only the cases whose name contains one of the words
+//
+// Each case builds the image MAKEISO_BENCH_RUNS times (5 by default) after a warm-up run and
+// reports the fastest, median and slowest run, the mean with its standard deviation (also as
+// a share of the mean), and the median throughput. MAKEISO_BENCH_SCALE
+// is passed on to makeiso bench --scale, and the trees are kept in MAKEISO_BENCH_DIR (the
+// temporary directory by default) between runs, so they are only generated once.
+//
+// This is a plain harness = false binary rather than criterion: makeiso has no dependencies
+// beyond the windows bindings, and the benchmark shouldn't be the thing that adds them.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+const TREES: [&str; 3] = ["small", "huge", "deep"];
+const VARIANTS: [(&str, &[&str]); 5] = [
+    ("default", &[]),
+    ("read-workers", &["--read-workers", "4"]),
+    ("pipeline", &["--pipeline-depth", "8"]),
+    ("mmap", &["--mmap"]),
+    ("single-pass", &["--single-pass"]),
+];
+
+fn makeiso() -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_makeiso"));
+    command.stdout(Stdio::null()).stderr(Stdio::null());
+    command
+}
+
+fn setting(name: &str, default: u64) -> u64 {
+    env::var(name).ok().and_then(|value| value.parse().ok()).unwrap_or(default)
+}
+
+// The trees, generated unless they are there from an earlier run at the same scale
+fn trees(scale: u64) -> PathBuf {
+    let base = env::var_os("MAKEISO_BENCH_DIR").map_or_else(env::temp_dir, PathBuf::from);
+    let directory = base.join(format!("makeiso-bench-{}", scale));
+    if !directory.join("done").exists() {
+        println!("Generating the trees in {}", directory.display());
+        let status = makeiso().arg("bench").arg(&directory).args(["all", "--scale", &scale.to_string()]).status().expect("failed to run makeiso");
+        assert!(status.success(), "makeiso bench failed");
+        fs::write(directory.join("done"), "").unwrap();
+    }
+    directory
+}
+
+fn build(source: &Path, output: &Path, args: &[&str]) -> Duration {
+    let started = Instant::now();
+    let status = makeiso().args(["--force", "--deep-dirs", "relocate"]).args(args).arg(source).arg(output).status().expect("failed to run makeiso");
+    assert!(status.success(), "makeiso failed for {}", source.display());
+    started.elapsed()
+}
+
+fn main() {
+    // cargo bench passes --bench; anything else narrows down the cases
+    let filters: Vec<String> = env::args().skip(1).filter(|arg| !arg.starts_with("--")).collect();
+    let runs = setting("MAKEISO_BENCH_RUNS", 5).max(1) as usize;
+    let directory = trees(setting("MAKEISO_BENCH_SCALE", 1).max(1));
+    let output = directory.join("out.iso");
+
+    for tree in TREES {
+        let source = directory.join(tree);
+        let bytes = du(&source);
+        for (variant, args) in VARIANTS {
+            let name = format!("{}/{}", tree, variant);
+            if !filters.is_empty() && !filters.iter().any(|filter| name.contains(filter.as_str())) {
+                continue;
+            }
+            build(&source, &output, args);
+            let mut times: Vec<Duration> = (0..runs).map(|_| build(&source, &output, args)).collect();
+            times.sort();
+            let median = times[times.len() / 2];
+            let (mean, deviation) = spread(&times);
+            println!(
+                "{:<20} min {:>8.3}s  median {:>8.3}s  max {:>8.3}s  mean {:>8.3}s ± {:>7.3}s ({:>4.1}%)  {:>9.1} MiB/s",
+                name,
+                times[0].as_secs_f64(),
+                median.as_secs_f64(),
+                times[times.len() - 1].as_secs_f64(),
+                mean,
+                deviation,
+                deviation / mean * 100.0,
+                bytes as f64 / (1 << 20) as f64 / median.as_secs_f64()
+            );
+        }
+    }
+    let _ = fs::remove_file(&output);
+}
+
+// Mean and sample standard deviation of the runs in seconds, so a difference between two
+// cases can be told from noise (the deviation is 0 for a single run)
+fn spread(times: &[Duration]) -> (f64, f64) {
+    let seconds: Vec<f64> = times.iter().map(Duration::as_secs_f64).collect();
+    let mean = seconds.iter().sum::<f64>() / seconds.len() as f64;
+    if seconds.len() < 2 {
+        return (mean, 0.0);
+    }
+    let variance = seconds.iter().map(|time| (time - mean).powi(2)).sum::<f64>() / (seconds.len() - 1) as f64;
+    (mean, variance.sqrt())
+}
+
+// Bytes of file contents under a directory
+fn du(path: &Path) -> u64 {
+    fs::read_dir(path)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| match entry.metadata() {
+            Ok(metadata) if metadata.is_dir() => du(&entry.path()),
+            Ok(metadata) => metadata.len(),
+            Err(_) => 0,
+        })
+        .sum()
+}
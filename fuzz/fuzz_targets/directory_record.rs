@@ -0,0 +1,12 @@
+// A directory record, ISO 9660 or High Sierra, from arbitrary bytes, and the times read from it
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use makeiso::reader::DirectoryRecord;
+
+fuzz_target!(|data: &[u8]| {
+    for record in [DirectoryRecord::from_bytes(data), DirectoryRecord::from_high_sierra(data)].into_iter().flatten() {
+        let _ = record.modified();
+        let _ = record.is_self_or_parent();
+    }
+});
@@ -0,0 +1,10 @@
+// A primary volume descriptor, ISO 9660 or High Sierra, from arbitrary bytes
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use makeiso::reader::PrimaryVolumeDescriptor;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = PrimaryVolumeDescriptor::from_bytes(data);
+    let _ = PrimaryVolumeDescriptor::from_high_sierra(data);
+});
@@ -0,0 +1,26 @@
+// A whole image: its descriptors, directory tree, file contents and boot catalog. Seed the
+// corpus with small images makeiso built (deep directories relocated, --trans-tbl, a
+// --boot-image, --xattrs), so the mutations start from well-formed structures.
+#![no_main]
+
+use std::io::Cursor;
+
+use libfuzzer_sys::fuzz_target;
+use makeiso::reader::IsoReader;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(mut reader) = IsoReader::open(Cursor::new(data)) else {
+        return;
+    };
+    if let Ok(tree) = reader.read_tree() {
+        let mut buffer = [0u8; 4096];
+        for entry in &tree {
+            let _ = reader.read_file_at(entry, 0, &mut buffer);
+        }
+    }
+    if let Ok(entries) = reader.boot_entries() {
+        for entry in &entries {
+            let _ = reader.read_boot_image(entry);
+        }
+    }
+});
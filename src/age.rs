@@ -0,0 +1,690 @@
+// Encrypted images (--encrypt): the image is written as an age file (age-encryption.org/v1),
+// so `age -d` decrypts it too, and the readers decrypt such images transparently
+//
+// The file key is wrapped for each X25519 recipient (age1...) or with a passphrase through
+// scrypt. The payload is cut into 64 KiB chunks, each sealed with ChaCha20-Poly1305 under a
+// nonce made of its number, so any chunk can be read and checked on its own: listing or
+// extracting a single file only decrypts the chunks it touches. Readers take identities
+// (AGE-SECRET-KEY-1... lines, as age-keygen writes them) from --identity FILE, and
+// passphrases from --passphrase-file, MAKEISO_PASSPHRASE or the terminal.
+//
+// Images are encrypted as they are written, front to back, so nothing can be rewritten in
+// place (--if-changed reread).
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+use super::backend::ImageSink;
+use super::chacha20poly1305::{self as aead, TAG_SIZE};
+use super::reader::ReadAt;
+use super::scrypt::scrypt;
+use super::sha256::hmac;
+use super::x25519::{x25519, BASEPOINT};
+use super::ImageOutput;
+
+const VERSION_LINE: &[u8] = b"age-encryption.org/v1\n";
+const X25519_LABEL: &[u8] = b"age-encryption.org/v1/X25519";
+const SCRYPT_LABEL: &[u8] = b"age-encryption.org/v1/scrypt";
+const CHUNK_SIZE: usize = 64 * 1024;
+const SEALED_CHUNK_SIZE: u64 = (CHUNK_SIZE + TAG_SIZE) as u64;
+const NONCE_SIZE: u64 = 16;
+const SCRYPT_LOG_N: u8 = if cfg!(test) { 10 } else { 18 }; // What age itself uses; less in unit tests
+const MAX_SCRYPT_LOG_N: u8 = 22; // 4 GiB of memory
+const MAX_HEADER_SIZE: usize = 1024 * 1024;
+const WRAP_COLUMNS: usize = 64;
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const BECH32: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+static IDENTITY_FILES: OnceLock<Vec<PathBuf>> = OnceLock::new();
+static PASSPHRASE: OnceLock<String> = OnceLock::new();
+static SCRYPT_KEYS: Mutex<Vec<ScryptKey>> = Mutex::new(Vec::new());
+
+type ScryptKey = (Vec<u8>, u8, [u8; 32]); // Salt, work factor and the key they give
+
+// Who can decrypt an image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Recipient {
+    X25519([u8; 32]),
+    Passphrase, // Asked for when the image is written
+}
+
+impl Recipient {
+    // age:<age1...> or passphrase
+    pub fn parse(text: &str) -> io::Result<Recipient> {
+        if text == "passphrase" {
+            return Ok(Recipient::Passphrase);
+        }
+        let invalid = |why: &str| io::Error::new(ErrorKind::InvalidInput, format!("invalid --encrypt {}: {} (expected age:<age1...> or passphrase)", text, why));
+        let key = text.strip_prefix("age:").ok_or_else(|| invalid("unknown kind"))?;
+        match bech32_decode(key) {
+            Some((hrp, data)) if hrp == "age" => Ok(Recipient::X25519(data.try_into().map_err(|_| invalid("not an X25519 key"))?)),
+            _ => Err(invalid("not an age recipient")),
+        }
+    }
+}
+
+// Check that a set of recipients can go in one header
+pub fn check_recipients(recipients: &[Recipient]) -> io::Result<()> {
+    if recipients.len() > 1 && recipients.contains(&Recipient::Passphrase) {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "--encrypt passphrase can't be combined with other recipients"));
+    }
+    Ok(())
+}
+
+// The identity files readers try, for all readers of this run
+pub fn set_identity_files(files: Vec<PathBuf>) {
+    let _ = IDENTITY_FILES.set(files);
+}
+
+// Take the passphrase from the first line of a file rather than asking for it
+pub fn set_passphrase_file(path: &Path) -> io::Result<()> {
+    let text = fs::read_to_string(path).map_err(|e| io::Error::new(e.kind(), format!("could not read {}: {}", path.display(), e)))?;
+    let passphrase = text.lines().next().unwrap_or_default();
+    if passphrase.is_empty() {
+        return Err(io::Error::new(ErrorKind::InvalidInput, format!("{} holds no passphrase", path.display())));
+    }
+    let _ = PASSPHRASE.set(passphrase.to_string());
+    Ok(())
+}
+
+// Whether a passphrase was given, so nobody has to be asked
+pub fn has_passphrase() -> bool {
+    PASSPHRASE.get().is_some() || env::var_os("MAKEISO_PASSPHRASE").is_some()
+}
+
+// A header for whom to encrypt for; every payload sealed with it gets a nonce of its own
+pub struct Encryptor {
+    header: Vec<u8>, // Up to and including the MAC line
+    file_key: [u8; 16],
+}
+
+impl Encryptor {
+    pub fn new(recipients: &[Recipient]) -> io::Result<Encryptor> {
+        check_recipients(recipients)?;
+        let mut file_key = [0u8; 16];
+        random_bytes(&mut file_key)?;
+
+        let mut header = VERSION_LINE.to_vec();
+        for recipient in recipients {
+            match recipient {
+                Recipient::X25519(public) => {
+                    let mut ephemeral = [0u8; 32];
+                    random_bytes(&mut ephemeral)?;
+                    let share = x25519(&ephemeral, &BASEPOINT);
+                    let shared = x25519(&ephemeral, public);
+                    let wrap_key = hkdf(&shared, &[share.as_slice(), public.as_slice()].concat(), X25519_LABEL);
+                    header.extend_from_slice(format!("-> X25519 {}\n", base64_encode(&share)).as_bytes());
+                    write_body(&mut header, &aead::seal(&wrap_key, &[0; 12], &file_key));
+                }
+                Recipient::Passphrase => {
+                    let passphrase = read_passphrase("Enter a passphrase for the image:", true)?;
+                    let mut salt = [0u8; 16];
+                    random_bytes(&mut salt)?;
+                    let key = scrypt_key(&passphrase, &salt, SCRYPT_LOG_N);
+                    header.extend_from_slice(format!("-> scrypt {} {}\n", base64_encode(&salt), SCRYPT_LOG_N).as_bytes());
+                    write_body(&mut header, &aead::seal(&key, &[0; 12], &file_key));
+                }
+            }
+        }
+        header.extend_from_slice(b"---");
+        let mac = hmac(&hkdf(&file_key, &[], b"header"), &header);
+        header.extend_from_slice(format!(" {}\n", base64_encode(&mac)).as_bytes());
+        Ok(Encryptor { header, file_key })
+    }
+
+    // How large an age file for the given number of bytes is
+    pub fn encrypted_size(&self, size: u64) -> u64 {
+        let chunks = size.div_ceil(CHUNK_SIZE as u64).max(1);
+        self.header.len() as u64 + NONCE_SIZE + size + chunks * TAG_SIZE as u64
+    }
+
+    // The header with a fresh payload nonce, and the key for that payload
+    fn start_payload(&self) -> io::Result<(Vec<u8>, [u8; 32])> {
+        let mut nonce = [0u8; NONCE_SIZE as usize];
+        random_bytes(&mut nonce)?;
+        let mut start = self.header.clone();
+        start.extend_from_slice(&nonce);
+        Ok((start, hkdf(&self.file_key, &nonce, b"payload")))
+    }
+
+    // A whole age file holding the given contents
+    pub fn seal(&self, contents: &[u8]) -> io::Result<Vec<u8>> {
+        let (mut sealed, payload_key) = self.start_payload()?;
+        let chunks: Vec<&[u8]> = if contents.is_empty() { vec![&[]] } else { contents.chunks(CHUNK_SIZE).collect() };
+        for (index, chunk) in chunks.iter().enumerate() {
+            sealed.extend_from_slice(&aead::seal(&payload_key, &chunk_nonce(index as u64, index + 1 == chunks.len()), chunk));
+        }
+        Ok(sealed)
+    }
+}
+
+// Encrypts the image on its way to the real output, one chunk at a time
+pub struct EncryptingSink<S> {
+    output: S,
+    payload_key: [u8; 32],
+    chunk: Vec<u8>,
+    sealed: u64, // Chunks written so far
+    position: u64,
+}
+
+impl<S: ImageSink> EncryptingSink<S> {
+    pub fn new(mut output: S, encryptor: &Encryptor) -> io::Result<EncryptingSink<S>> {
+        let (start, payload_key) = encryptor.start_payload()?;
+        output.write_all(&start)?;
+        Ok(EncryptingSink {
+            output,
+            payload_key,
+            chunk: Vec::with_capacity(CHUNK_SIZE),
+            sealed: 0,
+            position: 0,
+        })
+    }
+
+    // A full chunk only goes out once more data follows, so the last one can be marked
+    fn seal_chunk(&mut self, last: bool) -> io::Result<()> {
+        let sealed = aead::seal(&self.payload_key, &chunk_nonce(self.sealed, last), &self.chunk);
+        self.output.write_all(&sealed)?;
+        self.chunk.clear();
+        self.sealed += 1;
+        Ok(())
+    }
+}
+
+impl<S: ImageSink> Write for EncryptingSink<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut rest = buf;
+        while !rest.is_empty() {
+            if self.chunk.len() == CHUNK_SIZE {
+                self.seal_chunk(false)?;
+            }
+            let take = rest.len().min(CHUNK_SIZE - self.chunk.len());
+            self.chunk.extend_from_slice(&rest[..take]);
+            rest = &rest[take..];
+        }
+        self.position += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+impl<S: ImageSink> Seek for EncryptingSink<S> {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        let target = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(_) => None,
+        };
+        match target {
+            Some(target) if target >= self.position => {
+                io::copy(&mut io::repeat(0).take(target - self.position), self)?;
+                Ok(self.position)
+            }
+            _ => Err(io::Error::new(ErrorKind::Unsupported, "encrypted images are written as a stream and can't be rewritten")),
+        }
+    }
+}
+
+impl<S: ImageSink> ImageOutput for EncryptingSink<S> {}
+
+impl<S: ImageSink> ImageSink for EncryptingSink<S> {
+    fn finish(&mut self) -> io::Result<()> {
+        self.seal_chunk(true)?;
+        self.output.finish()
+    }
+}
+
+// Whether an image starts with an age header
+pub fn is_encrypted<R: ReadAt>(source: &mut R) -> bool {
+    let mut start = [0u8; VERSION_LINE.len()];
+    source.read_exact_at(0, &mut start).is_ok() && start == VERSION_LINE
+}
+
+// The contents of a whole age file held in memory
+pub fn decrypt(sealed: Vec<u8>) -> io::Result<Vec<u8>> {
+    let len = sealed.len() as u64;
+    let mut decryptor = Decryptor::open(io::Cursor::new(sealed), len)?;
+    let mut contents = vec![0u8; decryptor.len as usize];
+    decryptor.read_exact_at(0, &mut contents)?;
+    Ok(contents)
+}
+
+// Random access to the plain image inside an age file
+pub struct Decryptor<R> {
+    source: R,
+    payload_key: [u8; 32],
+    payload_start: u64, // Where the first sealed chunk is
+    chunks: u64,
+    len: u64, // Of the plain image
+    cached: Option<(u64, Vec<u8>)>,
+}
+
+impl<R: ReadAt> Decryptor<R> {
+    // Length of the plain image
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    // Read the header of an age file of len bytes and unwrap its file key
+    pub fn open(mut source: R, len: u64) -> io::Result<Decryptor<R>> {
+        let header = read_header(&mut source, len)?;
+        let text = std::str::from_utf8(&header).map_err(|_| invalid_header())?;
+        let (stanzas, mac_line) = text.rsplit_once("\n--- ").ok_or_else(invalid_header)?;
+        let mac = base64_decode(mac_line.trim_end_matches('\n')).ok_or_else(invalid_header)?;
+
+        let file_key = unwrap_file_key(stanzas)?;
+        let mac_input = &header[..stanzas.len() + 4]; // Up to and including "---"
+        if hmac(&hkdf(&file_key, &[], b"header"), mac_input).as_slice() != mac {
+            return Err(io::Error::new(ErrorKind::InvalidData, "the header of the encrypted image was tampered with"));
+        }
+
+        let mut nonce = [0u8; NONCE_SIZE as usize];
+        source.read_exact_at(header.len() as u64, &mut nonce)?;
+        let payload_start = header.len() as u64 + NONCE_SIZE;
+        let sealed_len = len.checked_sub(payload_start).filter(|&sealed_len| sealed_len >= TAG_SIZE as u64).ok_or_else(truncated)?;
+        let chunks = sealed_len.div_ceil(SEALED_CHUNK_SIZE);
+        if !sealed_len.is_multiple_of(SEALED_CHUNK_SIZE) && sealed_len % SEALED_CHUNK_SIZE < TAG_SIZE as u64 {
+            return Err(truncated());
+        }
+        Ok(Decryptor {
+            source,
+            payload_key: hkdf(&file_key, &nonce, b"payload"),
+            payload_start,
+            chunks,
+            len: sealed_len - chunks * TAG_SIZE as u64,
+            cached: None,
+        })
+    }
+
+    fn chunk(&mut self, index: u64) -> io::Result<&[u8]> {
+        if self.cached.as_ref().is_none_or(|(cached, _)| *cached != index) {
+            let start = self.payload_start + index * SEALED_CHUNK_SIZE;
+            let end = (start + SEALED_CHUNK_SIZE).min(self.payload_start + self.len + self.chunks * TAG_SIZE as u64);
+            let mut sealed = vec![0u8; (end - start) as usize];
+            self.source.read_exact_at(start, &mut sealed)?;
+            let plain = aead::open(&self.payload_key, &chunk_nonce(index, index + 1 == self.chunks), &sealed)
+                .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, format!("chunk {} of the encrypted image is damaged or was tampered with", index)))?;
+            self.cached = Some((index, plain));
+        }
+        Ok(&self.cached.as_ref().expect("chunk just cached").1)
+    }
+}
+
+impl<R: ReadAt> ReadAt for Decryptor<R> {
+    fn read_exact_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        if offset + buf.len() as u64 > self.len {
+            return Err(io::Error::new(ErrorKind::UnexpectedEof, "read past the end of the encrypted image"));
+        }
+        let mut done = 0;
+        while done < buf.len() {
+            let position = offset + done as u64;
+            let index = position / CHUNK_SIZE as u64;
+            let at = (position % CHUNK_SIZE as u64) as usize;
+            let chunk = self.chunk(index)?;
+            let len = (buf.len() - done).min(chunk.len() - at);
+            buf[done..done + len].copy_from_slice(&chunk[at..at + len]);
+            done += len;
+        }
+        Ok(())
+    }
+}
+
+// The header up to and including the MAC line
+fn read_header<R: ReadAt>(source: &mut R, len: u64) -> io::Result<Vec<u8>> {
+    let mut header = Vec::new();
+    let mut block = vec![0u8; 4096];
+    while header.len() < MAX_HEADER_SIZE {
+        let take = block.len().min((len - header.len() as u64) as usize);
+        if take == 0 {
+            break;
+        }
+        source.read_exact_at(header.len() as u64, &mut block[..take])?;
+        let searched_from = header.len().saturating_sub(5);
+        header.extend_from_slice(&block[..take]);
+        if let Some(at) = header[searched_from..].windows(5).position(|window| window == b"\n--- ") {
+            let mac_start = searched_from + at + 5;
+            if let Some(end) = header[mac_start..].iter().position(|&byte| byte == b'\n') {
+                header.truncate(mac_start + end + 1);
+                return Ok(header);
+            }
+        }
+    }
+    Err(invalid_header())
+}
+
+// Try the identities (or a passphrase) on the stanzas until one unwraps the file key
+fn unwrap_file_key(stanzas: &str) -> io::Result<[u8; 16]> {
+    let mut lines = stanzas.strip_prefix(std::str::from_utf8(VERSION_LINE).expect("ASCII")).ok_or_else(invalid_header)?.split('\n');
+    let mut parsed = Vec::new();
+    while let Some(line) = lines.next() {
+        let arguments: Vec<&str> = line.strip_prefix("-> ").ok_or_else(invalid_header)?.split(' ').collect();
+        let mut body = String::new();
+        loop {
+            let line = lines.next().ok_or_else(invalid_header)?;
+            body.push_str(line);
+            if line.len() < WRAP_COLUMNS {
+                break;
+            }
+        }
+        parsed.push((arguments, base64_decode(&body).ok_or_else(invalid_header)?));
+    }
+
+    let unwrap = |key: &[u8; 32], body: &[u8]| aead::open(key, &[0; 12], body).and_then(|key| <[u8; 16]>::try_from(key).ok());
+    let mut identities = None;
+    let mut x25519_seen = false;
+    for (arguments, body) in &parsed {
+        match arguments.as_slice() {
+            ["X25519", share] => {
+                x25519_seen = true;
+                let share: [u8; 32] = base64_decode(share).and_then(|share| share.try_into().ok()).ok_or_else(invalid_header)?;
+                if identities.is_none() {
+                    identities = Some(load_identities()?);
+                }
+                for identity in identities.iter().flatten() {
+                    let shared = x25519(identity, &share);
+                    if shared == [0; 32] {
+                        continue;
+                    }
+                    let public = x25519(identity, &BASEPOINT);
+                    if let Some(file_key) = unwrap(&hkdf(&shared, &[share.as_slice(), public.as_slice()].concat(), X25519_LABEL), body) {
+                        return Ok(file_key);
+                    }
+                }
+            }
+            ["scrypt", salt, log_n] => {
+                if parsed.len() != 1 {
+                    return Err(invalid_header()); // Passphrases stand alone
+                }
+                let salt = base64_decode(salt).filter(|salt| salt.len() == 16).ok_or_else(invalid_header)?;
+                let log_n: u8 = log_n.parse().map_err(|_| invalid_header())?;
+                if log_n == 0 || log_n > MAX_SCRYPT_LOG_N {
+                    return Err(io::Error::new(ErrorKind::InvalidData, format!("the passphrase of the encrypted image asks for too much work (2^{})", log_n)));
+                }
+                let passphrase = read_passphrase("Enter the passphrase of the image:", false)?;
+                return unwrap(&scrypt_key(&passphrase, &salt, log_n), body).ok_or_else(|| io::Error::new(ErrorKind::PermissionDenied, "wrong passphrase for the encrypted data"));
+            }
+            _ => {} // Recipient types we don't know
+        }
+    }
+    Err(match (x25519_seen, identities.as_ref().is_some_and(|identities| !identities.is_empty())) {
+        (true, false) => io::Error::new(ErrorKind::PermissionDenied, "the image is encrypted; give the key with --identity FILE"),
+        _ => io::Error::new(ErrorKind::PermissionDenied, "none of the identities given can decrypt the image"),
+    })
+}
+
+// The X25519 secret keys of the --identity files
+fn load_identities() -> io::Result<Vec<[u8; 32]>> {
+    let mut identities = Vec::new();
+    for path in IDENTITY_FILES.get().into_iter().flatten() {
+        let text = fs::read_to_string(path).map_err(|e| io::Error::new(e.kind(), format!("could not read {}: {}", path.display(), e)))?;
+        for line in text.lines().map(str::trim).filter(|line| line.starts_with("AGE-SECRET-KEY-1")) {
+            match bech32_decode(line) {
+                Some((hrp, key)) if hrp == "age-secret-key-" && key.len() == 32 => identities.push(key.try_into().expect("32 bytes")),
+                _ => return Err(io::Error::new(ErrorKind::InvalidData, format!("{} holds an invalid age identity", path.display()))),
+            }
+        }
+    }
+    Ok(identities)
+}
+
+// Files encrypted in one run share their header, so the expensive derivation is done once
+fn scrypt_key(passphrase: &str, salt: &[u8], log_n: u8) -> [u8; 32] {
+    let mut keys = SCRYPT_KEYS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Some((_, _, key)) = keys.iter().find(|(known, known_log_n, _)| known == salt && *known_log_n == log_n) {
+        return *key;
+    }
+    let key: [u8; 32] = scrypt(passphrase.as_bytes(), &[SCRYPT_LABEL, salt].concat(), log_n, 32).try_into().expect("32 bytes");
+    keys.push((salt.to_vec(), log_n, key));
+    key
+}
+
+// From --passphrase-file, MAKEISO_PASSPHRASE, or typed at the terminal without echo (once per run)
+fn read_passphrase(prompt: &str, confirm: bool) -> io::Result<String> {
+    if let Some(passphrase) = PASSPHRASE.get() {
+        return Ok(passphrase.clone());
+    }
+    if let Ok(passphrase) = env::var("MAKEISO_PASSPHRASE") {
+        return Ok(passphrase);
+    }
+    let ask = |prompt: &str| -> io::Result<String> {
+        eprintln!("{}", prompt);
+        #[cfg(unix)]
+        let hidden = io::IsTerminal::is_terminal(&io::stdin()) && std::process::Command::new("stty").arg("-echo").status().is_ok_and(|status| status.success());
+        let mut line = String::new();
+        let read = io::stdin().read_line(&mut line);
+        #[cfg(unix)]
+        if hidden {
+            let _ = std::process::Command::new("stty").arg("echo").status();
+        }
+        read?;
+        Ok(line.trim_end_matches(['\r', '\n']).to_string())
+    };
+    let passphrase = ask(prompt)?;
+    if passphrase.is_empty() {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "the passphrase can't be empty"));
+    }
+    if confirm && ask("Enter it again:")? != passphrase {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "the passphrases don't match"));
+    }
+    let _ = PASSPHRASE.set(passphrase.clone());
+    Ok(passphrase)
+}
+
+// The nonce of a payload chunk: its number, big-endian in 11 bytes, and whether it's the last
+fn chunk_nonce(index: u64, last: bool) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[3..11].copy_from_slice(&index.to_be_bytes());
+    nonce[11] = last as u8;
+    nonce
+}
+
+// HKDF-SHA-256 (RFC 5869) for one 32-byte key
+fn hkdf(key_material: &[u8], salt: &[u8], info: &[u8]) -> [u8; 32] {
+    let pseudo_random_key = hmac(salt, key_material);
+    hmac(&pseudo_random_key, &[info, &[1u8]].concat())
+}
+
+#[cfg(unix)]
+fn random_bytes(buf: &mut [u8]) -> io::Result<()> {
+    File::open("/dev/urandom")?.read_exact(buf)
+}
+
+#[cfg(not(unix))]
+fn random_bytes(_buf: &mut [u8]) -> io::Result<()> {
+    Err(io::Error::new(ErrorKind::Unsupported, "--encrypt is not supported on this platform"))
+}
+
+fn invalid_header() -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, "the image has an invalid age header")
+}
+
+fn truncated() -> io::Error {
+    io::Error::new(ErrorKind::UnexpectedEof, "the encrypted image is truncated")
+}
+
+// Base64 as age writes it: the standard alphabet without padding
+pub fn base64_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for group in data.chunks(3) {
+        let bits = group.iter().enumerate().fold(0u32, |bits, (i, &byte)| bits | (byte as u32) << (16 - 8 * i));
+        for i in 0..=group.len() {
+            out.push(BASE64[(bits >> (18 - 6 * i) & 0x3F) as usize] as char);
+        }
+    }
+    out
+}
+
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    if text.len() % 4 == 1 {
+        return None;
+    }
+    let mut out = Vec::with_capacity(text.len() * 3 / 4);
+    for group in text.as_bytes().chunks(4) {
+        let mut bits = 0u32;
+        for (i, &c) in group.iter().enumerate() {
+            bits |= (BASE64.iter().position(|&b| b == c)? as u32) << (18 - 6 * i);
+        }
+        let bytes = group.len() - 1;
+        out.extend_from_slice(&bits.to_be_bytes()[1..1 + bytes]);
+        // Leftover bits must be zero, so every value has a single encoding
+        if bits & (0xFFFFFF >> (8 * bytes)) != 0 {
+            return None;
+        }
+    }
+    Some(out)
+}
+
+// A stanza body, wrapped at 64 columns and ended by a shorter (possibly empty) line
+fn write_body(header: &mut Vec<u8>, body: &[u8]) {
+    let encoded = base64_encode(body);
+    for line in encoded.as_bytes().chunks(WRAP_COLUMNS) {
+        header.extend_from_slice(line);
+        header.push(b'\n');
+    }
+    if encoded.len().is_multiple_of(WRAP_COLUMNS) {
+        header.push(b'\n');
+    }
+}
+
+fn bech32_polymod(values: impl Iterator<Item = u8>) -> u32 {
+    const GENERATOR: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+    values.fold(1, |check, value| {
+        let top = check >> 25;
+        let check = (check & 0x1ffffff) << 5 ^ value as u32;
+        (0..5).filter(|i| (top >> i) & 1 == 1).fold(check, |check, i| check ^ GENERATOR[i])
+    })
+}
+
+// The human-readable part and the data of a bech32 string such as an age recipient
+fn bech32_decode(text: &str) -> Option<(String, Vec<u8>)> {
+    let text = text.to_ascii_lowercase();
+    let (hrp, data) = text.rsplit_once('1')?;
+    let values: Vec<u8> = data.bytes().map(|c| BECH32.iter().position(|&b| b == c).map(|at| at as u8)).collect::<Option<_>>()?;
+    if hrp.is_empty() || values.len() < 6 {
+        return None;
+    }
+    let expanded = hrp.bytes().map(|c| c >> 5).chain([0]).chain(hrp.bytes().map(|c| c & 31));
+    if bech32_polymod(expanded.chain(values.iter().copied())) != 1 {
+        return None;
+    }
+
+    // Regroup the 5-bit values into bytes; leftover bits must be zero padding
+    let mut bytes = Vec::new();
+    let (mut accumulator, mut bits) = (0u32, 0);
+    for value in &values[..values.len() - 6] {
+        accumulator = (accumulator << 5 | *value as u32) & 0xFFF;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((accumulator >> bits) as u8);
+        }
+    }
+    if bits >= 5 || accumulator & ((1 << bits) - 1) != 0 {
+        return None;
+    }
+    Some((hrp.to_string(), bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("makeiso-age-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // What age-keygen would print for the data
+    fn bech32_encode(hrp: &str, data: &[u8]) -> String {
+        let mut values = Vec::new();
+        let (mut accumulator, mut bits) = (0u32, 0);
+        for &byte in data {
+            accumulator = accumulator << 8 | byte as u32;
+            bits += 8;
+            while bits >= 5 {
+                bits -= 5;
+                values.push((accumulator >> bits & 31) as u8);
+            }
+        }
+        if bits > 0 {
+            values.push((accumulator << (5 - bits) & 31) as u8);
+        }
+        let expanded = hrp.bytes().map(|c| c >> 5).chain([0]).chain(hrp.bytes().map(|c| c & 31));
+        let check = bech32_polymod(expanded.chain(values.iter().copied()).chain([0; 6])) ^ 1;
+        values.extend((0..6).map(|i| (check >> (5 * (5 - i)) & 31) as u8));
+        format!("{}1{}", hrp, values.iter().map(|&value| BECH32[value as usize] as char).collect::<String>())
+    }
+
+    // Two and a half chunks, so there is a short last one
+    fn contents() -> Vec<u8> {
+        (0..CHUNK_SIZE * 5 / 2).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn recipient_round_trip() {
+        let identity: [u8; 32] = std::array::from_fn(|i| (i * 7 + 1) as u8);
+        let public = x25519(&identity, &BASEPOINT);
+        let scratch = scratch_dir("recipient");
+        let key_file = scratch.join("key.txt");
+        fs::write(&key_file, format!("# public key: {}\n{}\n", bech32_encode("age", &public), bech32_encode("age-secret-key-", &identity).to_uppercase())).unwrap();
+        set_identity_files(vec![key_file]);
+        let recipient = Recipient::parse(&format!("age:{}", bech32_encode("age", &public))).unwrap();
+        assert_eq!(recipient, Recipient::X25519(public));
+
+        let contents = contents();
+        let encryptor = Encryptor::new(&[recipient]).unwrap();
+        let sealed = encryptor.seal(&contents).unwrap();
+        assert!(sealed.starts_with(VERSION_LINE));
+        assert_eq!(sealed.len() as u64, encryptor.encrypted_size(contents.len() as u64));
+        assert!(is_encrypted(&mut io::Cursor::new(sealed.clone())));
+        assert_eq!(decrypt(sealed.clone()).unwrap(), contents);
+        assert_eq!(decrypt(encryptor.seal(b"").unwrap()).unwrap(), b"");
+
+        // A read across a chunk boundary decrypts just the chunks it needs
+        let mut decryptor = Decryptor::open(io::Cursor::new(sealed.clone()), sealed.len() as u64).unwrap();
+        assert_eq!(decryptor.len(), contents.len() as u64);
+        let mut middle = vec![0u8; 100];
+        decryptor.read_exact_at(CHUNK_SIZE as u64 - 50, &mut middle).unwrap();
+        assert_eq!(middle, contents[CHUNK_SIZE - 50..CHUNK_SIZE + 50]);
+
+        // A changed payload, a changed header or a missing last chunk is refused
+        let mut tampered = sealed.clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        assert_eq!(decrypt(tampered).unwrap_err().kind(), ErrorKind::InvalidData);
+        let mut tampered = sealed.clone();
+        tampered[VERSION_LINE.len() + 3] ^= 1; // In "X25519"
+        assert!(decrypt(tampered).is_err());
+        let truncated = sealed[..sealed.len() - (CHUNK_SIZE / 2 + TAG_SIZE)].to_vec();
+        assert!(decrypt(truncated).is_err());
+
+        // Nor can the key open an image sealed for someone else
+        let other = Recipient::X25519(x25519(&[9; 32], &BASEPOINT));
+        let sealed = Encryptor::new(&[other]).unwrap().seal(&contents).unwrap();
+        assert_eq!(decrypt(sealed).unwrap_err().kind(), ErrorKind::PermissionDenied);
+        fs::remove_dir_all(&scratch).unwrap();
+    }
+
+    #[test]
+    fn passphrase_round_trip() {
+        let scratch = scratch_dir("passphrase");
+        let passphrase_file = scratch.join("passphrase.txt");
+        fs::write(&passphrase_file, "correct horse battery staple\n").unwrap();
+        set_passphrase_file(&passphrase_file).unwrap();
+        assert!(has_passphrase());
+
+        let contents = contents();
+        let sealed = Encryptor::new(&[Recipient::Passphrase]).unwrap().seal(&contents).unwrap();
+        let header = String::from_utf8_lossy(&sealed[..200]).into_owned();
+        assert!(header.contains("\n-> scrypt ") && header.contains(&format!(" {}\n", SCRYPT_LOG_N)), "{}", header);
+        assert_eq!(decrypt(sealed).unwrap(), contents);
+        assert!(check_recipients(&[Recipient::Passphrase, Recipient::X25519([9; 32])]).is_err());
+        fs::remove_dir_all(&scratch).unwrap();
+    }
+}
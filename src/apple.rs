@@ -0,0 +1,120 @@
+// Apple extensions to ISO 9660 (--apple): the Finder type and creator codes and Finder flags
+// of each file, recorded in an "AA" field of its system use area so classic Mac OS and
+// macOS show the files with the right application, as Apple's own CD mastering tools did
+//
+// The field keeps the SUSP layout (signature, length, then the system use ID where SUSP has
+// its version), so it sits next to Rock Ridge entries without confusing either:
+//
+//     "AA" 14 2 (HFS) | type (4) | creator (4) | Finder flags (2, big-endian)
+//
+// ProDOS files use ID 1 with a file type byte and a 2-byte auxiliary type (little-endian, as
+// ProDOS keeps it) instead, which the Finder shows as type 'p' + file type + aux type and
+// creator 'pdos'. Some older discs use the signature "BA"; the reader takes both.
+//
+// Finder info comes from the com.apple.FinderInfo extended attribute (user.com.apple.FinderInfo
+// once a tree was copied to Linux) or, failing that, from the AppleDouble "._name" file next to
+// it, which is what copies to other file systems and zip archives keep. The AppleDouble files
+// themselves stay out of the image.
+
+use std::io::{self, ErrorKind, Read};
+use std::path::Path;
+
+use super::backend::SourceProvider;
+
+const HFS: u8 = 2;
+const PRODOS: u8 = 1;
+const FINDER_INFO_ATTRIBUTES: &[&str] = &["com.apple.FinderInfo", "user.com.apple.FinderInfo"];
+pub const APPLE_DOUBLE_PREFIX: &str = "._";
+const APPLE_DOUBLE_MAGIC: u32 = 0x0005_1607;
+const APPLE_DOUBLE_FINDER_INFO: u32 = 9; // Entry ID of the Finder info in an AppleDouble file
+const MAX_APPLE_DOUBLE_HEADER: usize = 4096; // Header and entry table; resource forks that follow aren't read
+
+// The part of a file's Finder info the extensions record
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FinderInfo {
+    pub file_type: [u8; 4], // e.g. "TEXT", "APPL"
+    pub creator: [u8; 4],   // e.g. "ttxt" for SimpleText
+    pub flags: u16,         // Finder flags, e.g. 0x4000 for invisible
+}
+
+impl FinderInfo {
+    // From the 32 bytes of Finder info macOS keeps; files without a type or creator have none
+    fn from_bytes(info: &[u8]) -> Option<FinderInfo> {
+        let info = info.get(..10)?;
+        let finder = FinderInfo {
+            file_type: info[0..4].try_into().ok()?,
+            creator: info[4..8].try_into().ok()?,
+            flags: u16::from_be_bytes([info[8], info[9]]),
+        };
+        (finder.file_type != [0; 4] || finder.creator != [0; 4]).then_some(finder)
+    }
+
+    // The AA field for a directory record
+    pub fn system_use(&self) -> Vec<u8> {
+        let mut field = vec![b'A', b'A', 14, HFS];
+        field.extend_from_slice(&self.file_type);
+        field.extend_from_slice(&self.creator);
+        field.extend_from_slice(&self.flags.to_be_bytes());
+        field
+    }
+
+    // Type and creator as the Finder shows them, e.g. "TEXT/ttxt"
+    pub fn codes(&self) -> String {
+        let code = |bytes: &[u8; 4]| bytes.iter().map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '?' }).collect::<String>();
+        format!("{}/{}", code(&self.file_type), code(&self.creator))
+    }
+}
+
+// The Finder info of a source file, from its extended attribute or its AppleDouble file
+pub fn read(source: &dyn SourceProvider, path: &Path, file_name: &str) -> io::Result<Option<FinderInfo>> {
+    let attributes = source.xattrs(path)?;
+    if let Some((_, value)) = attributes.iter().find(|(name, _)| FINDER_INFO_ATTRIBUTES.contains(&name.as_str())) {
+        return Ok(FinderInfo::from_bytes(value));
+    }
+
+    let sidecar = path.with_file_name(format!("{}{}", APPLE_DOUBLE_PREFIX, file_name));
+    let mut header = Vec::new();
+    match source.open(&sidecar) {
+        Ok(file) => file.take(MAX_APPLE_DOUBLE_HEADER as u64).read_to_end(&mut header)?,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    Ok(apple_double_finder_info(&header))
+}
+
+// The Finder info entry of an AppleDouble header: magic, version, 16 bytes of filler, the
+// number of entries, then an ID, offset and length for each
+fn apple_double_finder_info(header: &[u8]) -> Option<FinderInfo> {
+    let field = |at: usize| header.get(at..at + 4).map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+    if field(0)? != APPLE_DOUBLE_MAGIC {
+        return None;
+    }
+    let count = u16::from_be_bytes([*header.get(24)?, *header.get(25)?]) as usize;
+    (0..count).map(|entry| 26 + entry * 12).find(|&at| field(at) == Some(APPLE_DOUBLE_FINDER_INFO)).and_then(|at| {
+        let (offset, length) = (field(at + 4)? as usize, field(at + 8)? as usize);
+        FinderInfo::from_bytes(header.get(offset..offset.checked_add(length)?)?)
+    })
+}
+
+// The Finder info in the AA or BA field of a system use area (past the SUSP bytes to skip)
+pub fn decode(system_use: &[u8]) -> Option<FinderInfo> {
+    let mut offset = 0;
+    while offset + 4 <= system_use.len() {
+        let length = system_use[offset + 2] as usize;
+        if length < 4 || offset + length > system_use.len() {
+            return None;
+        }
+        let field = &system_use[offset..offset + length];
+        if &field[..2] == b"AA" || &field[..2] == b"BA" {
+            match field[3] {
+                HFS if length >= 14 => return FinderInfo::from_bytes(&field[4..14]),
+                PRODOS if length >= 7 => {
+                    return Some(FinderInfo { file_type: [b'p', field[4], field[6], field[5]], creator: *b"pdos", flags: 0 });
+                }
+                _ => {}
+            }
+        }
+        offset += length;
+    }
+    None
+}
@@ -0,0 +1,290 @@
+// makeiso audio [--data <image.iso>] --tracks <track.wav|track.flac>... --cue <out.cue>
+// [--bin <out.bin>]: master an audio CD, as a .bin of raw 2352-byte sectors with a cue sheet for
+// cdrdao, cdrecord and the other burning tools
+//
+// Red Book audio is 44.1 kHz stereo with 16-bit samples, little-endian in a BINARY cue file, 588
+// sample frames to a sector. Tracks are WAV (PCM, WAVE_FORMAT_EXTENSIBLE too) or FLAC files at
+// 44.1 kHz: mono is doubled to both channels and other sample sizes are cut or widened to 16
+// bits, but nothing is resampled. Each track is padded with silence to a whole sector and
+// starts at the INDEX 01 the cue sheet gives; burners add the 2-second pregap of the first.
+// The .bin defaults to the cue sheet's name with a .bin extension.
+//
+// With --data, the disc is mixed mode, as games and multimedia discs were: an ISO 9660 image
+// as track 1 in Mode 1 sectors, then the audio tracks, the first of them after the 2-second
+// PREGAP that a change from data to audio needs (written by the burner, not in the .bin).
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+use super::events;
+use super::flac::FlacReader;
+use super::rawsector::{self, FRAMES_PER_SECOND, RAW_SECTOR_SIZE};
+use super::BLOCK_SIZE;
+
+const USAGE: &str = "Usage: makeiso audio [--data <image.iso>] --tracks <track.wav|track.flac>... --cue <out.cue> [--bin <out.bin>]";
+const SAMPLE_RATE: u32 = 44100;
+const FRAME_SIZE: u64 = 4; // Bytes of a stereo sample frame
+const FRAMES_PER_SECTOR: u64 = RAW_SECTOR_SIZE / FRAME_SIZE;
+const MAX_TRACKS: usize = 99;
+const MIN_TRACK_SECTORS: u64 = 4 * FRAMES_PER_SECOND; // Red Book minimum track length
+const DATA_PREGAP: &str = "00:02:00"; // Ahead of the first audio track after a data track
+const DISC_SECTORS: u64 = 80 * 60 * FRAMES_PER_SECOND; // An 80-minute disc
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+const MAX_FMT_SIZE: u64 = 1024;
+const FRAMES_PER_READ: usize = 4096;
+
+pub fn audio(args: &[String]) -> io::Result<()> {
+    let mut tracks = Vec::new();
+    let mut cue_path = None;
+    let mut bin_path = None;
+    let mut data = None;
+    let mut args = args.iter().peekable();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--tracks" => {
+                while let Some(track) = args.next_if(|arg| !arg.starts_with("--")) {
+                    tracks.push(PathBuf::from(track));
+                }
+            }
+            "--cue" => cue_path = Some(PathBuf::from(args.next().ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "--cue requires a path"))?)),
+            "--data" => data = Some(PathBuf::from(args.next().ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "--data requires a path"))?)),
+            "--bin" => bin_path = Some(PathBuf::from(args.next().ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "--bin requires a path"))?)),
+            _ => return Err(io::Error::new(ErrorKind::InvalidInput, USAGE)),
+        }
+    }
+    let cue_path = cue_path.ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, USAGE))?;
+    let bin_path = bin_path.unwrap_or_else(|| cue_path.with_extension("bin"));
+    if tracks.is_empty() && data.is_none() {
+        return Err(io::Error::new(ErrorKind::InvalidInput, USAGE));
+    }
+    let max_tracks = MAX_TRACKS - data.is_some() as usize;
+    if tracks.len() > max_tracks {
+        return Err(io::Error::new(ErrorKind::InvalidInput, format!("{} audio tracks given, the disc holds at most {}", tracks.len(), max_tracks)));
+    }
+
+    let mut output = BufWriter::new(File::create(&bin_path)?);
+    let written = data
+        .as_deref()
+        .map_or(Ok(0), |image| write_data_track(image, &mut output))
+        .and_then(|data_sectors| write_tracks(&tracks, data_sectors, &mut output))
+        .and_then(|starts| output.flush().map(|()| starts));
+    drop(output);
+    let (starts, sectors) = match written {
+        Ok(written) => written,
+        Err(e) => {
+            let _ = fs::remove_file(&bin_path);
+            return Err(e);
+        }
+    };
+    if sectors > DISC_SECTORS {
+        events::warn(&format!("The tracks run {}, longer than the 80 minutes of the largest common CD-R", msf(sectors)));
+    }
+
+    fs::write(&cue_path, cue_sheet(&cue_path, &bin_path, data.is_some(), &starts)?)?;
+    println!("Wrote {} ({} tracks, {}) and {}", bin_path.display(), starts.len() + data.is_some() as usize, msf(sectors), cue_path.display());
+    Ok(())
+}
+
+// Write an ISO 9660 image as a data track of Mode 1 sectors; returns its length in sectors
+fn write_data_track<W: Write>(image: &Path, output: &mut W) -> io::Result<u64> {
+    let mut input = BufReader::new(File::open(image)?);
+    let size = input.get_ref().metadata()?.len();
+    if size % BLOCK_SIZE as u64 != 0 {
+        return Err(io::Error::new(ErrorKind::InvalidInput, format!("{} is not a whole number of 2048-byte sectors", image.display())));
+    }
+    // Short data tracks are padded to the 4 seconds a track lasts at least
+    let sectors = (size / BLOCK_SIZE as u64).max(MIN_TRACK_SECTORS);
+    let mut data = [0u8; BLOCK_SIZE];
+    for sector in 0..sectors {
+        if sector < size / BLOCK_SIZE as u64 {
+            input.read_exact(&mut data)?;
+        } else {
+            data = [0u8; BLOCK_SIZE];
+        }
+        output.write_all(&rawsector::mode1_sector(sector, &data))?;
+    }
+    println!("Track 01: {} (data, {})", image.display(), msf(sectors));
+    Ok(sectors)
+}
+
+// Write the audio tracks one after the other, from the given sector; returns the sector each
+// starts at and the total
+fn write_tracks<W: Write>(tracks: &[PathBuf], mut sector: u64, output: &mut W) -> io::Result<(Vec<u64>, u64)> {
+    let mut starts = Vec::with_capacity(tracks.len());
+    let first_number = if sector > 0 { 2 } else { 1 };
+    for (number, path) in tracks.iter().enumerate() {
+        let frames = write_track(path, output).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+        let padding = (FRAMES_PER_SECTOR - frames % FRAMES_PER_SECTOR) % FRAMES_PER_SECTOR;
+        output.write_all(&vec![0u8; (padding * FRAME_SIZE) as usize])?;
+        let sectors = (frames + padding) / FRAMES_PER_SECTOR;
+        if sectors < MIN_TRACK_SECTORS {
+            events::warn(&format!("{} is shorter than the 4 seconds a CD track should last", path.display()));
+        }
+        println!("Track {:02}: {} ({})", number + first_number, path.display(), msf(sectors));
+        starts.push(sector);
+        sector += sectors;
+    }
+    Ok((starts, sector))
+}
+
+// Write one track as CD sample frames, by the format its first bytes show; returns the frames
+fn write_track<W: Write>(path: &Path, output: &mut W) -> io::Result<u64> {
+    let mut input = BufReader::new(File::open(path)?);
+    let mut magic = [0u8; 4];
+    input.read_exact(&mut magic)?;
+    let input = io::Cursor::new(magic).chain(input);
+    match &magic {
+        b"RIFF" => write_wav(input, output),
+        b"fLaC" => write_flac(input, output),
+        _ if &magic[..3] == b"ID3" => write_flac(input, output),
+        _ => Err(io::Error::new(ErrorKind::InvalidData, "not a WAV or FLAC file")),
+    }
+}
+
+// Refuse formats that would need resampling or a downmix
+fn check_format(sample_rate: u32, channels: u32) -> io::Result<()> {
+    if sample_rate != SAMPLE_RATE {
+        return Err(io::Error::new(ErrorKind::InvalidData, format!("{} Hz audio, CD audio is {} Hz (resample it first)", sample_rate, SAMPLE_RATE)));
+    }
+    if !(1..=2).contains(&channels) {
+        return Err(io::Error::new(ErrorKind::InvalidData, format!("{} channels, CD audio is stereo", channels)));
+    }
+    Ok(())
+}
+
+// A sample of the given size as a 16-bit one, little-endian
+fn cd_sample(sample: i32, bits: u32) -> [u8; 2] {
+    let sample = if bits >= 16 { sample >> (bits - 16) } else { sample << (16 - bits) };
+    (sample as i16).to_le_bytes()
+}
+
+fn write_wav<R: Read, W: Write>(mut input: R, output: &mut W) -> io::Result<u64> {
+    let mut header = [0u8; 12];
+    input.read_exact(&mut header)?;
+    if &header[8..12] != b"WAVE" {
+        return Err(io::Error::new(ErrorKind::InvalidData, "not a WAVE file"));
+    }
+
+    let mut format = None; // Channels and bytes per sample
+    loop {
+        let mut chunk = [0u8; 8];
+        input.read_exact(&mut chunk).map_err(|_| io::Error::new(ErrorKind::InvalidData, "no data chunk"))?;
+        let size = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]) as u64;
+        match &chunk[..4] {
+            b"fmt " if size > MAX_FMT_SIZE => return Err(io::Error::new(ErrorKind::InvalidData, "oversized fmt chunk")),
+            b"fmt " => {
+                let mut fmt = vec![0u8; size as usize];
+                input.read_exact(&mut fmt)?;
+                if fmt.len() < 16 {
+                    return Err(io::Error::new(ErrorKind::InvalidData, "short fmt chunk"));
+                }
+                let field = |at: usize| u16::from_le_bytes([fmt[at], fmt[at + 1]]);
+                let tag = if field(0) == WAVE_FORMAT_EXTENSIBLE && fmt.len() >= 26 { field(24) } else { field(0) };
+                if tag != WAVE_FORMAT_PCM {
+                    return Err(io::Error::new(ErrorKind::InvalidData, format!("WAV format {:#06x}, only integer PCM can go on a CD", tag)));
+                }
+                let (channels, block_align) = (field(2) as u32, field(12) as u32);
+                check_format(u32::from_le_bytes([fmt[4], fmt[5], fmt[6], fmt[7]]), channels)?;
+                let bytes = block_align / channels;
+                if !(1..=4).contains(&bytes) || bytes * channels != block_align {
+                    return Err(io::Error::new(ErrorKind::InvalidData, format!("{}-byte sample frames for {} channels", block_align, channels)));
+                }
+                format = Some((channels, bytes));
+                if size % 2 == 1 {
+                    input.read_exact(&mut [0u8; 1])?;
+                }
+            }
+            b"data" => {
+                let (channels, bytes) = format.ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "data chunk before the fmt chunk"))?;
+                // Streamed files leave the size at its maximum; they end with the file
+                return wav_samples(input.take(size), output, channels, bytes);
+            }
+            _ => {
+                io::copy(&mut (&mut input).take(size + size % 2), &mut io::sink())?;
+            }
+        }
+    }
+}
+
+fn wav_samples<R: Read, W: Write>(mut input: R, output: &mut W, channels: u32, bytes: u32) -> io::Result<u64> {
+    let frame_bytes = (channels * bytes) as usize;
+    let mut buffer = vec![0u8; FRAMES_PER_READ * frame_bytes];
+    let mut converted = Vec::with_capacity(FRAMES_PER_READ * FRAME_SIZE as usize);
+    let mut filled = 0;
+    let mut frames = 0;
+    loop {
+        let read = input.read(&mut buffer[filled..])?;
+        filled += read;
+        let whole = filled / frame_bytes * frame_bytes;
+        converted.clear();
+        for frame in buffer[..whole].chunks_exact(frame_bytes) {
+            let sample = |channel: u32| {
+                let at = (channel * bytes) as usize;
+                let raw = frame[at..at + bytes as usize].iter().rev().fold(0u32, |value, &byte| (value << 8) | byte as u32);
+                let shift = 32 - 8 * bytes;
+                // 8-bit WAV samples are unsigned, wider ones two's complement
+                let value = if bytes == 1 { raw as i32 - 128 } else { ((raw << shift) as i32) >> shift };
+                cd_sample(value, 8 * bytes)
+            };
+            converted.extend_from_slice(&sample(0));
+            converted.extend_from_slice(&sample(channels - 1));
+        }
+        output.write_all(&converted)?;
+        frames += (whole / frame_bytes) as u64;
+        buffer.copy_within(whole..filled, 0);
+        filled -= whole;
+        if read == 0 {
+            if filled > 0 {
+                events::warn("The audio data ends in a partial sample frame, leaving it out");
+            }
+            return Ok(frames);
+        }
+    }
+}
+
+fn write_flac<R: Read, W: Write>(input: R, output: &mut W) -> io::Result<u64> {
+    let mut reader = FlacReader::new(input)?;
+    let info = reader.info;
+    check_format(info.sample_rate, info.channels)?;
+    let mut frames = 0;
+    let mut converted = Vec::new();
+    while let Some(channels) = reader.next_frame()? {
+        if channels.len() as u32 != info.channels {
+            return Err(io::Error::new(ErrorKind::InvalidData, "a FLAC frame with another number of channels"));
+        }
+        converted.clear();
+        for (left, right) in channels[0].iter().zip(&channels[channels.len() - 1]) {
+            converted.extend_from_slice(&cd_sample(*left, info.bits_per_sample));
+            converted.extend_from_slice(&cd_sample(*right, info.bits_per_sample));
+        }
+        output.write_all(&converted)?;
+        frames += channels[0].len() as u64;
+    }
+    Ok(frames)
+}
+
+// The cue sheet: the .bin by its name when it is beside the cue sheet, else by its full path
+fn cue_sheet(cue_path: &Path, bin_path: &Path, data: bool, starts: &[u64]) -> io::Result<String> {
+    let beside = bin_path.parent().unwrap_or(Path::new("")) == cue_path.parent().unwrap_or(Path::new(""));
+    let bin = if beside { PathBuf::from(bin_path.file_name().unwrap_or_default()) } else { fs::canonicalize(bin_path)? };
+    let mut sheet = format!("FILE \"{}\" BINARY\n", bin.display());
+    if data {
+        sheet += "  TRACK 01 MODE1/2352\n    INDEX 01 00:00:00\n";
+    }
+    for (number, &start) in starts.iter().enumerate() {
+        sheet += &format!("  TRACK {:02} AUDIO\n", number + 1 + data as usize);
+        if data && number == 0 {
+            sheet += &format!("    PREGAP {}\n", DATA_PREGAP);
+        }
+        sheet += &format!("    INDEX 01 {}\n", msf(start));
+    }
+    Ok(sheet)
+}
+
+// Sectors as mm:ss:ff
+fn msf(sectors: u64) -> String {
+    let seconds = sectors / FRAMES_PER_SECOND;
+    format!("{:02}:{:02}:{:02}", seconds / 60, seconds % 60, sectors % FRAMES_PER_SECOND)
+}
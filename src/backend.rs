@@ -0,0 +1,580 @@
+// Where the source tree is read from and where the image is written to
+//
+// Building an image walks the source through a SourceProvider and writes through an ImageSink.
+// The local filesystem is always available; S3 (s3://bucket/prefix) and SFTP (sftp://host/path)
+// are built with the s3 and sftp features and go through curl, which brings request signing,
+// SSH keys and agents, proxies and TLS. Remote outputs are streamed front to back, so they
+// can't be rewritten in place (--if-changed reread needs a local output).
+
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+#[cfg(any(feature = "s3", feature = "sftp"))]
+use std::collections::HashMap;
+#[cfg(any(feature = "s3", feature = "sftp"))]
+use std::io::{Seek, SeekFrom, Write};
+#[cfg(any(feature = "s3", feature = "sftp"))]
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+#[cfg(any(feature = "s3", feature = "sftp"))]
+use std::sync::Mutex;
+
+use super::{preallocate, winmeta, xattr, ImageOutput};
+
+// What a source path turned out to be; anything but files and directories is left out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceKind {
+    File,
+    Directory,
+    Other,
+}
+
+// Size and modification time of a source file, at scan time or after it was read
+#[derive(Debug, Clone, Copy)]
+pub struct SourceMetadata {
+    pub len: u64,
+    pub modified: Option<SystemTime>,
+    pub identity: Option<FileIdentity>, // Local files only, where the platform has one
+}
+
+// Device and inode of a local file, the same for every path (and hard link) to it
+pub type FileIdentity = (u64, u64);
+
+impl From<fs::Metadata> for SourceMetadata {
+    fn from(metadata: fs::Metadata) -> SourceMetadata {
+        SourceMetadata {
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+            identity: file_identity(&metadata),
+        }
+    }
+}
+
+#[cfg(unix)]
+pub fn file_identity(metadata: &fs::Metadata) -> Option<FileIdentity> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+// Stable Rust has no file index on other platforms
+#[cfg(not(unix))]
+pub fn file_identity(_metadata: &fs::Metadata) -> Option<FileIdentity> {
+    None
+}
+
+// The identity of whatever a path leads to, links followed
+#[cfg(unix)]
+fn path_identity(path: &Path) -> Option<FileIdentity> {
+    file_identity(&fs::metadata(path).ok()?)
+}
+
+// On Windows the volume serial number and file index, from a handle opened with backup
+// semantics so directories can be opened too
+#[cfg(windows)]
+fn path_identity(path: &Path) -> Option<FileIdentity> {
+    use std::os::windows::fs::OpenOptionsExt;
+    use std::os::windows::io::AsRawHandle;
+    use windows::Win32::Foundation::HANDLE;
+    use windows::Win32::Storage::FileSystem::{GetFileInformationByHandle, BY_HANDLE_FILE_INFORMATION, FILE_FLAG_BACKUP_SEMANTICS};
+
+    let file = fs::OpenOptions::new().read(true).custom_flags(FILE_FLAG_BACKUP_SEMANTICS.0).open(path).ok()?;
+    let mut information = BY_HANDLE_FILE_INFORMATION::default();
+    // SAFETY: the handle stays open for the call and information is a valid out pointer
+    unsafe { GetFileInformationByHandle(HANDLE(file.as_raw_handle()), &mut information) }.ok()?;
+    Some((information.dwVolumeSerialNumber as u64, ((information.nFileIndexHigh as u64) << 32) | information.nFileIndexLow as u64))
+}
+
+#[cfg(not(any(unix, windows)))]
+fn path_identity(_path: &Path) -> Option<FileIdentity> {
+    None
+}
+
+// A directory entry as the scan found it, so nothing has to ask the source about it again
+pub struct ScannedEntry {
+    pub path: PathBuf,
+    pub kind: SourceKind,
+    pub metadata: io::Result<SourceMetadata>, // Fails like file_metadata for files that couldn't be written later
+}
+
+// An open source file; local ones can be mapped or copied kernel-side
+pub enum SourceFile {
+    Local(File),
+    Stream { reader: Box<dyn Read + Send>, metadata: SourceMetadata },
+}
+
+impl SourceFile {
+    // The file behind the reader, if it is a local one
+    pub fn local(&mut self) -> Option<&mut File> {
+        match self {
+            SourceFile::Local(file) => Some(file),
+            SourceFile::Stream { .. } => None,
+        }
+    }
+
+    // Current metadata of a local file; streams only know what the listing said
+    pub fn metadata(&self) -> io::Result<SourceMetadata> {
+        match self {
+            SourceFile::Local(file) => Ok(file.metadata()?.into()),
+            SourceFile::Stream { metadata, .. } => Ok(*metadata),
+        }
+    }
+}
+
+impl Read for SourceFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            SourceFile::Local(file) => file.read(buf),
+            SourceFile::Stream { reader, .. } => reader.read(buf),
+        }
+    }
+}
+
+// A tree of files to put into an image
+pub trait SourceProvider: Sync {
+    // The entries of a directory, each looked up once; entries that can't be read are errors
+    // in the list
+    fn entries(&self, dir: &Path) -> io::Result<Vec<io::Result<ScannedEntry>>>;
+    fn kind(&self, path: &Path) -> SourceKind;
+    fn directory_modified(&self, dir: &Path) -> Option<SystemTime>;
+    // Fails (with PermissionDenied for unreadable files) when the file couldn't be written later
+    fn file_metadata(&self, path: &Path) -> io::Result<SourceMetadata>;
+    fn open(&self, path: &Path) -> io::Result<SourceFile>;
+    // Extended attributes of a file or directory; remote sources have none
+    fn xattrs(&self, _path: &Path) -> io::Result<xattr::Attributes> {
+        Ok(Vec::new())
+    }
+    // Windows attributes, creation time and streams (see winmeta.rs); remote sources have none
+    fn win_metadata(&self, _path: &Path) -> io::Result<xattr::Attributes> {
+        Ok(Vec::new())
+    }
+    // The device a directory is on, for --one-file-system; remote sources have none
+    fn device(&self, _path: &Path) -> Option<u64> {
+        None
+    }
+    // Whether an entry is a symbolic link (or on Windows a junction or mounted folder), which
+    // the scan follows, for --type and --symlinks; remote sources have none
+    fn is_symlink(&self, _path: &Path) -> bool {
+        false
+    }
+    // The identity of a directory, for telling links back to one another; remote sources have none
+    fn directory_identity(&self, _path: &Path) -> Option<FileIdentity> {
+        None
+    }
+    // Where a symbolic link points, with '/' between the components, and when the link itself
+    // was changed, for --symlinks store
+    fn link_target(&self, path: &Path) -> io::Result<(String, Option<SystemTime>)> {
+        Err(io::Error::new(ErrorKind::Unsupported, format!("{} can't be stored as a link from this source", path.display())))
+    }
+}
+
+// Files and directories on the local filesystem
+pub struct LocalSource;
+
+impl SourceProvider for LocalSource {
+    fn entries(&self, dir: &Path) -> io::Result<Vec<io::Result<ScannedEntry>>> {
+        Ok(fs::read_dir(dir)?.map(|entry| entry.map(|entry| scan_local(entry.path()))).collect())
+    }
+
+    fn kind(&self, path: &Path) -> SourceKind {
+        match fs::metadata(path) {
+            Ok(metadata) if metadata.is_dir() => SourceKind::Directory,
+            Ok(metadata) if metadata.is_file() => SourceKind::File,
+            _ => SourceKind::Other,
+        }
+    }
+
+    fn directory_modified(&self, dir: &Path) -> Option<SystemTime> {
+        fs::metadata(dir).and_then(|metadata| metadata.modified()).ok()
+    }
+
+    fn file_metadata(&self, path: &Path) -> io::Result<SourceMetadata> {
+        Ok(File::open(path)?.metadata()?.into())
+    }
+
+    fn open(&self, path: &Path) -> io::Result<SourceFile> {
+        File::open(path).map(SourceFile::Local)
+    }
+
+    fn xattrs(&self, path: &Path) -> io::Result<xattr::Attributes> {
+        xattr::read(path)
+    }
+
+    fn win_metadata(&self, path: &Path) -> io::Result<xattr::Attributes> {
+        winmeta::read(path)
+    }
+
+    fn device(&self, path: &Path) -> Option<u64> {
+        file_identity(&fs::metadata(path).ok()?).map(|(device, _)| device)
+    }
+
+    fn is_symlink(&self, path: &Path) -> bool {
+        // Windows counts junctions and mounted folders (name surrogate reparse points) in
+        fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_symlink())
+    }
+
+    fn directory_identity(&self, path: &Path) -> Option<FileIdentity> {
+        path_identity(path)
+    }
+
+    fn link_target(&self, path: &Path) -> io::Result<(String, Option<SystemTime>)> {
+        let mut target = fs::read_link(path)?.to_string_lossy().into_owned();
+        if cfg!(windows) {
+            // Junctions and mounted folders read back as \\?\C:\target or \\?\Volume{...}\
+            target = target.strip_prefix(r"\\?\").unwrap_or(&target).replace('\\', "/");
+        }
+        Ok((target, fs::symlink_metadata(path)?.modified().ok()))
+    }
+}
+
+// Look up a local entry with a single stat, following symbolic links like kind does; files are
+// opened too, to leave out the ones that couldn't be read later
+pub fn scan_local(path: PathBuf) -> ScannedEntry {
+    let (kind, metadata) = match fs::metadata(&path) {
+        Ok(metadata) if metadata.is_file() => (SourceKind::File, File::open(&path).map(|_| metadata.into())),
+        Ok(metadata) if metadata.is_dir() => (SourceKind::Directory, Ok(metadata.into())),
+        Ok(metadata) => (SourceKind::Other, Ok(metadata.into())),
+        Err(e) => (SourceKind::Other, Err(e)),
+    };
+    ScannedEntry { path, kind, metadata }
+}
+
+// Where an image is written
+pub trait ImageSink: ImageOutput + Send {
+    // Complete the image once everything was written
+    fn finish(&mut self) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+impl ImageSink for File {}
+
+impl ImageSink for Box<dyn ImageSink> {
+    fn finish(&mut self) -> io::Result<()> {
+        (**self).finish()
+    }
+}
+
+impl ImageOutput for Box<dyn ImageSink> {
+    fn output_file(&self) -> Option<&File> {
+        (**self).output_file()
+    }
+}
+
+// A local image file, opened for reading too so contents already written can be copied within it
+pub fn create_file(path: &Path) -> io::Result<File> {
+    fs::OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path)
+}
+
+// Create a local image output with room made for the `size` bytes it will hold
+pub fn create_output(path: &Path, size: u64) -> io::Result<File> {
+    let file = create_file(path)?;
+    preallocate::reserve(&file, size, path)?;
+    Ok(file)
+}
+
+// Whether a source or output is in a remote store rather than on the local filesystem
+pub fn is_remote(location: &Path) -> bool {
+    scheme(location).is_some()
+}
+
+fn scheme(location: &Path) -> Option<&str> {
+    let location = location.to_str()?;
+    ["s3://", "sftp://"].into_iter().find(|scheme| location.starts_with(scheme))
+}
+
+#[cfg(not(all(feature = "s3", feature = "sftp")))]
+fn feature_missing(location: &Path, feature: &str) -> io::Error {
+    io::Error::new(ErrorKind::Unsupported, format!("{} needs makeiso built with the {} feature", location.display(), feature))
+}
+
+// The provider for a source directory given on the command line
+pub fn open_source(location: &Path) -> io::Result<Box<dyn SourceProvider>> {
+    match scheme(location) {
+        #[cfg(feature = "s3")]
+        Some("s3://") => Ok(Box::new(RemoteSource::new(super::s3::S3Store::new(location)?))),
+        #[cfg(feature = "sftp")]
+        Some("sftp://") => Ok(Box::new(RemoteSource::new(super::sftp::SftpStore::new(location)?))),
+        #[cfg(not(feature = "s3"))]
+        Some("s3://") => Err(feature_missing(location, "s3")),
+        #[cfg(not(feature = "sftp"))]
+        Some("sftp://") => Err(feature_missing(location, "sftp")),
+        _ => Ok(Box::new(LocalSource)),
+    }
+}
+
+// The sink for an image of the given size (remote stores want to know it up front)
+pub fn create_sink(location: &Path, size: u64) -> io::Result<Box<dyn ImageSink>> {
+    match scheme(location) {
+        #[cfg(feature = "s3")]
+        Some("s3://") => Ok(Box::new(super::s3::upload(location, size)?)),
+        #[cfg(feature = "sftp")]
+        Some("sftp://") => Ok(Box::new(super::sftp::upload(location, size)?)),
+        #[cfg(not(feature = "s3"))]
+        Some("s3://") => Err(feature_missing(location, "s3")),
+        #[cfg(not(feature = "sftp"))]
+        Some("sftp://") => Err(feature_missing(location, "sftp")),
+        _ => Ok(Box::new(create_output(location, size)?)),
+    }
+}
+
+// A file or directory in a remote listing
+#[cfg(any(feature = "s3", feature = "sftp"))]
+#[derive(Debug, Clone)]
+pub struct RemoteEntry {
+    pub name: String,
+    pub is_directory: bool,
+    pub len: u64,
+    pub modified: Option<SystemTime>,
+}
+
+// A remote store that can list directories and read files; paths are full locations
+#[cfg(any(feature = "s3", feature = "sftp"))]
+pub trait RemoteStore: Sync {
+    fn list(&self, dir: &str) -> io::Result<Vec<RemoteEntry>>;
+    fn get(&self, path: &str) -> io::Result<Box<dyn Read + Send>>;
+}
+
+// A remote tree; what each path is comes from the listing of its directory
+#[cfg(any(feature = "s3", feature = "sftp"))]
+struct RemoteSource<S> {
+    store: S,
+    listed: Mutex<HashMap<PathBuf, RemoteEntry>>,
+}
+
+#[cfg(any(feature = "s3", feature = "sftp"))]
+impl<S: RemoteStore> RemoteSource<S> {
+    fn new(store: S) -> RemoteSource<S> {
+        RemoteSource { store, listed: Mutex::new(HashMap::new()) }
+    }
+
+    fn listed(&self, path: &Path) -> Option<RemoteEntry> {
+        self.listed.lock().unwrap_or_else(|e| e.into_inner()).get(path).cloned()
+    }
+}
+
+#[cfg(any(feature = "s3", feature = "sftp"))]
+impl<S: RemoteStore> SourceProvider for RemoteSource<S> {
+    fn entries(&self, dir: &Path) -> io::Result<Vec<io::Result<ScannedEntry>>> {
+        let location = dir.to_str().ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "remote paths must be valid UTF-8"))?;
+        let entries = self.store.list(location.trim_end_matches('/'))?;
+        let mut listed = self.listed.lock().unwrap_or_else(|e| e.into_inner());
+        Ok(entries
+            .into_iter()
+            .map(|entry| {
+                let path = dir.join(&entry.name);
+                let kind = if entry.is_directory { SourceKind::Directory } else { SourceKind::File };
+                let metadata = Ok(SourceMetadata { len: entry.len, modified: entry.modified, identity: None });
+                listed.insert(path.clone(), entry);
+                Ok(ScannedEntry { path, kind, metadata })
+            })
+            .collect())
+    }
+
+    fn kind(&self, path: &Path) -> SourceKind {
+        match self.listed(path) {
+            Some(entry) if entry.is_directory => SourceKind::Directory,
+            Some(_) => SourceKind::File,
+            None => SourceKind::Other,
+        }
+    }
+
+    fn directory_modified(&self, dir: &Path) -> Option<SystemTime> {
+        self.listed(dir).and_then(|entry| entry.modified)
+    }
+
+    fn file_metadata(&self, path: &Path) -> io::Result<SourceMetadata> {
+        let entry = self.listed(path).ok_or_else(|| io::Error::new(ErrorKind::NotFound, format!("{} was not listed", path.display())))?;
+        Ok(SourceMetadata { len: entry.len, modified: entry.modified, identity: None })
+    }
+
+    fn open(&self, path: &Path) -> io::Result<SourceFile> {
+        let metadata = self.file_metadata(path)?;
+        let location = path.to_str().ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "remote paths must be valid UTF-8"))?;
+        Ok(SourceFile::Stream { reader: self.store.get(location)?, metadata })
+    }
+}
+
+// Run curl to completion, returning what it printed
+#[cfg(any(feature = "s3", feature = "sftp"))]
+pub fn curl_output(mut command: Command, what: &str) -> io::Result<Vec<u8>> {
+    let output = command.output().map_err(|e| io::Error::new(e.kind(), format!("could not run curl for {}: {}", what, e)))?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!("curl could not fetch {}: {}", what, String::from_utf8_lossy(&output.stderr).trim())));
+    }
+    Ok(output.stdout)
+}
+
+// The output of a running curl download; its exit status is checked at the end of the data
+#[cfg(any(feature = "s3", feature = "sftp"))]
+pub struct CurlReader {
+    child: Child,
+    stdout: ChildStdout,
+    what: String,
+}
+
+#[cfg(any(feature = "s3", feature = "sftp"))]
+impl CurlReader {
+    pub fn spawn(mut command: Command, what: &str) -> io::Result<CurlReader> {
+        let mut child = command
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| io::Error::new(e.kind(), format!("could not run curl for {}: {}", what, e)))?;
+        let stdout = child.stdout.take().expect("curl stdout is piped");
+        Ok(CurlReader { child, stdout, what: what.to_string() })
+    }
+}
+
+#[cfg(any(feature = "s3", feature = "sftp"))]
+impl Read for CurlReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let bytes_read = self.stdout.read(buf)?;
+        if bytes_read == 0 && !buf.is_empty() {
+            let status = self.child.wait()?;
+            if !status.success() {
+                return Err(io::Error::other(format!("curl failed ({}) while reading {}", status, self.what)));
+            }
+        }
+        Ok(bytes_read)
+    }
+}
+
+#[cfg(any(feature = "s3", feature = "sftp"))]
+impl Drop for CurlReader {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// An image streamed into a curl upload; gaps are written as zeros, there's no going back
+#[cfg(any(feature = "s3", feature = "sftp"))]
+pub struct UploadSink {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    position: u64,
+    location: String,
+}
+
+#[cfg(any(feature = "s3", feature = "sftp"))]
+impl UploadSink {
+    pub fn spawn(mut command: Command, location: &str) -> io::Result<UploadSink> {
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+            .map_err(|e| io::Error::new(e.kind(), format!("could not run curl for {}: {}", location, e)))?;
+        let stdin = child.stdin.take();
+        Ok(UploadSink { child, stdin, position: 0, location: location.to_string() })
+    }
+}
+
+#[cfg(any(feature = "s3", feature = "sftp"))]
+impl Write for UploadSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let stdin = self.stdin.as_mut().ok_or_else(|| io::Error::other(format!("the upload to {} was already finished", self.location)))?;
+        let written = stdin.write(buf)?;
+        self.position += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stdin.as_mut().map_or(Ok(()), |stdin| stdin.flush())
+    }
+}
+
+#[cfg(any(feature = "s3", feature = "sftp"))]
+impl Seek for UploadSink {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        let target = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(_) => None,
+        };
+        match target {
+            Some(target) if target >= self.position => {
+                io::copy(&mut io::repeat(0).take(target - self.position), self)?;
+                Ok(self.position)
+            }
+            _ => Err(io::Error::new(ErrorKind::Unsupported, format!("{} is written as a stream and can't be rewritten", self.location))),
+        }
+    }
+}
+
+#[cfg(any(feature = "s3", feature = "sftp"))]
+impl ImageOutput for UploadSink {}
+
+#[cfg(any(feature = "s3", feature = "sftp"))]
+impl ImageSink for UploadSink {
+    fn finish(&mut self) -> io::Result<()> {
+        drop(self.stdin.take());
+        let status = self.child.wait()?;
+        if !status.success() {
+            return Err(io::Error::other(format!("curl failed ({}) while uploading {}", status, self.location)));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(any(feature = "s3", feature = "sftp"))]
+impl Drop for UploadSink {
+    fn drop(&mut self) {
+        // An unfinished upload is cut short, so the remote side never sees a complete image
+        if self.stdin.take().is_some() {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+// An image written to two sinks at once (--upload: the output and its upload). Files can't be
+// copied into it kernel-side, since the copy has to see every byte.
+pub struct TeeSink {
+    sink: Box<dyn ImageSink>,
+    copy: Box<dyn ImageSink>,
+}
+
+impl TeeSink {
+    pub fn new(sink: Box<dyn ImageSink>, copy: Box<dyn ImageSink>) -> TeeSink {
+        TeeSink { sink, copy }
+    }
+}
+
+impl io::Write for TeeSink {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let written = self.sink.write(data)?;
+        self.copy.write_all(&data[..written])?;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.sink.flush()?;
+        self.copy.flush()
+    }
+}
+
+impl io::Seek for TeeSink {
+    fn seek(&mut self, to: io::SeekFrom) -> io::Result<u64> {
+        let position = self.sink.seek(to)?;
+        self.copy.seek(io::SeekFrom::Start(position))
+    }
+}
+
+impl ImageOutput for TeeSink {}
+
+impl ImageSink for TeeSink {
+    // The copy is only completed once the output is
+    fn finish(&mut self) -> io::Result<()> {
+        self.sink.finish()?;
+        self.copy.finish()
+    }
+}
+
+// Upload a finished image to location (--upload, where the image couldn't be sent as it was
+// written)
+pub fn upload_file(path: &Path, location: &Path) -> io::Result<()> {
+    let mut file = File::open(path)?;
+    let mut upload = create_sink(location, file.metadata()?.len())?;
+    io::copy(&mut file, &mut upload)?;
+    upload.finish()
+}
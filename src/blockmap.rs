@@ -0,0 +1,281 @@
+// Block maps: which blocks of an image hold what. The build writes the one of the layout it
+// made (--block-map), and read_map reads one back from an image that is already there, from
+// its descriptors, path tables and directory records. Either way the areas are named the same:
+// the parts of the volume structure in parentheses, directories by their path ending in "/",
+// files by their path.
+//
+// Directories of the hierarchies names aren't taken from (the ISO 9660 one of an image read
+// through Joliet, say) get the name of their hierarchy, as the tree only has the one. Files
+// are shared between the hierarchies, and files stored once for several names have an area
+// for each.
+//
+// A map is written as text (a line per area), as JSON, or as a bmap, the format of bmaptool
+// (version 2.0): the ranges of blocks anything uses, each with its SHA-256, so tools that
+// know the format copy or fetch those and skip the rest (padding, and blocks nothing uses).
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, ErrorKind, Write};
+use std::path::Path;
+
+use super::reader::{open_image, DirectoryRecord, IsoEntry, IsoReader, ReadAt, VolumeDescriptor};
+use super::sha256::{self, Sha256};
+use super::{blocks_for, json, BLOCK_SIZE, SYSTEM_AREA_BLOCKS, VOLUME_DESCRIPTOR_TERMINATOR};
+
+const USAGE: &str = "Usage: makeiso block-map [--format text|json|bmap] <image.iso> [map]";
+const HASH_CHUNK_SIZE: usize = 1024 * 1024;
+const PADDING: &str = "(padding)";
+
+// How a block map is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapFormat {
+    Text,
+    Json,
+    Bmap,
+}
+
+impl MapFormat {
+    pub fn parse(text: &str) -> io::Result<MapFormat> {
+        match text {
+            "text" => Ok(MapFormat::Text),
+            "json" => Ok(MapFormat::Json),
+            "bmap" => Ok(MapFormat::Bmap),
+            _ => Err(io::Error::new(ErrorKind::InvalidInput, format!("invalid block map format: {} (expected text, json or bmap)", text))),
+        }
+    }
+}
+
+// One area of the image: a run of blocks and what is in them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Area {
+    pub start: u32,
+    pub blocks: u32,
+    pub contents: String,
+    pub file: Option<usize>, // The entry of the tree, for files and directories
+}
+
+impl Area {
+    pub fn new(start: u32, blocks: u32, contents: String) -> Area {
+        Area { start, blocks, contents, file: None }
+    }
+
+    pub fn contains(&self, block: u32) -> bool {
+        block >= self.start && block - self.start < self.blocks
+    }
+}
+
+// makeiso block-map [--format text|json|bmap] <image.iso> [map]: the map goes to standard
+// output without a path
+pub fn block_map(args: &[String]) -> io::Result<()> {
+    let mut format = MapFormat::Text;
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.split_once('=') {
+            Some(("--format", value)) => format = MapFormat::parse(value)?,
+            _ if arg == "--format" => format = MapFormat::parse(args.next().ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "--format requires a value"))?)?,
+            _ if arg.starts_with("--") => return Err(io::Error::new(ErrorKind::InvalidInput, format!("unknown option: {}\n{}", arg, USAGE))),
+            _ => paths.push(Path::new(arg)),
+        }
+    }
+    let (location, output) = match paths[..] {
+        [location] => (location, None),
+        [location, output] => (location, Some(output)),
+        _ => return Err(io::Error::new(ErrorKind::InvalidInput, USAGE)),
+    };
+
+    let mut reader = open_image(location)?;
+    let entries = reader.read_tree()?;
+    let areas = read_map(&mut reader, &entries)?;
+    let total_blocks = u32::try_from(reader.source_mut().size()?.div_ceil(BLOCK_SIZE as u64)).map_err(|_| io::Error::new(ErrorKind::InvalidData, "the image has more blocks than ISO 9660 can count"))?;
+    let mut out: Box<dyn Write> = match output {
+        Some(path) => Box::new(io::BufWriter::new(File::create(path).map_err(|e| io::Error::new(e.kind(), format!("could not create {}: {}", path.display(), e)))?)),
+        None => Box::new(io::BufWriter::new(io::stdout().lock())),
+    };
+    write_map(&mut out, &areas, total_blocks, format, Some(reader.source_mut()))?;
+    out.flush()
+}
+
+// The areas of the image the tree was read from, by first block
+pub fn read_map<R: ReadAt>(reader: &mut IsoReader<R>, entries: &[IsoEntry]) -> io::Result<Vec<Area>> {
+    let mut areas = vec![structure(0, SYSTEM_AREA_BLOCKS, "system area")];
+    let mut roots = vec![(reader.pvd.root_directory_extent, reader.pvd.root_directory_size, "ISO 9660")];
+    for (block, descriptor) in reader.descriptors.clone() {
+        let (name, hierarchy) = match &descriptor {
+            VolumeDescriptor::Boot(system) if system == "EL TORITO SPECIFICATION" => ("el torito boot record".to_string(), None),
+            VolumeDescriptor::Boot(_) => ("boot record".to_string(), None),
+            VolumeDescriptor::Primary => ("primary volume descriptor".to_string(), Some("ISO 9660")),
+            VolumeDescriptor::Supplementary(Some(_)) => ("joliet volume descriptor".to_string(), Some("Joliet")),
+            VolumeDescriptor::Supplementary(None) => ("supplementary volume descriptor".to_string(), Some("supplementary")),
+            VolumeDescriptor::Enhanced => ("enhanced volume descriptor".to_string(), Some("enhanced")),
+            VolumeDescriptor::Partition { id, block, blocks } => {
+                areas.push(structure(*block, *blocks, &format!("volume partition {}", id)));
+                ("volume partition descriptor".to_string(), None)
+            }
+            VolumeDescriptor::Other(kind) => (format!("volume descriptor of type {}", kind), None),
+        };
+        areas.push(structure(block, 1, &name));
+        // High Sierra keeps its path tables elsewhere in the descriptor; the directories are enough
+        let Some(hierarchy) = hierarchy.filter(|_| !reader.high_sierra) else { continue };
+        let data = reader.read_blocks(block, 1)?;
+        let size = u32::from_le_bytes(data[132..136].try_into().unwrap());
+        let primary = descriptor == VolumeDescriptor::Primary;
+        let prefix = if primary { String::new() } else { format!("{} ", hierarchy.to_lowercase()) };
+        for (at, endian, optional) in [(140, "little", false), (144, "little", true), (148, "big", false), (152, "big", true)] {
+            let field: [u8; 4] = data[at..at + 4].try_into().unwrap();
+            let table = if endian == "little" { u32::from_le_bytes(field) } else { u32::from_be_bytes(field) };
+            if table != 0 && size > 0 {
+                let what = format!("{}{}path table, {}-endian", prefix, if optional { "optional " } else { "" }, endian);
+                areas.push(structure(table, blocks_for(size as u64), &what));
+            }
+        }
+        if !primary {
+            if let Some(root) = DirectoryRecord::from_bytes(&data[156..190]) {
+                roots.push((root.extent_location, root.data_length, hierarchy));
+            }
+        }
+    }
+    if let Some(&(last, _)) = reader.descriptors.last() {
+        let data = reader.read_blocks(last + 1, 1)?;
+        let kind = if reader.high_sierra { data[8] } else { data[0] };
+        if kind == VOLUME_DESCRIPTOR_TERMINATOR {
+            areas.push(structure(last + 1, 1, "volume descriptor set terminator"));
+        }
+    }
+    if let Some(catalog) = reader.boot_catalog {
+        areas.push(structure(catalog, 1, "boot catalog"));
+        for entry in reader.boot_entries().unwrap_or_default() {
+            areas.push(structure(entry.load_rba, blocks_for(entry.sector_count as u64 * 512).max(1), "boot image"));
+        }
+    }
+
+    let mut named = HashSet::new();
+    for (index, entry) in entries.iter().enumerate() {
+        if entry.size == 0 {
+            continue; // Recorded with any extent, or none
+        }
+        let contents = if !entry.is_directory {
+            entry.path.clone()
+        } else if entry.path == "/" {
+            "/".to_string()
+        } else {
+            format!("{}/", entry.path)
+        };
+        if entry.is_directory {
+            named.insert(entry.extent);
+        }
+        areas.push(Area { start: entry.extent, blocks: blocks_for(entry.size as u64), contents, file: Some(index) });
+    }
+    for (extent, size, hierarchy) in roots {
+        for (extent, size) in directories(reader, extent, size)? {
+            if named.insert(extent) {
+                areas.push(structure(extent, blocks_for(size as u64), &format!("directory of the {} hierarchy", hierarchy)));
+            }
+        }
+    }
+    areas.sort_by_key(|area| area.start);
+    Ok(areas)
+}
+
+fn structure(start: u32, blocks: u32, what: &str) -> Area {
+    Area::new(start, blocks, format!("({})", what))
+}
+
+// The extents of the directories of a hierarchy, from its root
+fn directories<R: ReadAt>(reader: &mut IsoReader<R>, extent: u32, size: u32) -> io::Result<Vec<(u32, u32)>> {
+    let mut found = vec![(extent, size)];
+    let mut seen = HashSet::from([extent]);
+    let mut next = 0;
+    while next < found.len() {
+        let (extent, size) = found[next];
+        next += 1;
+        let records = reader.read_records(extent, size).map_err(|e| io::Error::new(e.kind(), format!("could not read the directory in block {}: {}", extent, e)))?;
+        for record in records {
+            if record.is_directory() && !record.is_self_or_parent() && seen.insert(record.extent_location) {
+                found.push((record.extent_location, record.data_length));
+            }
+        }
+    }
+    Ok(found)
+}
+
+// Write the map of an image of total_blocks blocks; a bmap reads the image to checksum the ranges
+pub fn write_map(out: &mut dyn Write, areas: &[Area], total_blocks: u32, format: MapFormat, image: Option<&mut dyn ReadAt>) -> io::Result<()> {
+    match format {
+        MapFormat::Text => {
+            writeln!(out, "# lba\tblocks\tcontents ({} blocks of {} bytes)", total_blocks, BLOCK_SIZE)?;
+            for area in areas {
+                writeln!(out, "{}\t{}\t{}", area.start, area.blocks, area.contents)?;
+            }
+        }
+        MapFormat::Json => {
+            writeln!(out, "{{\"block_size\":{},\"blocks\":{},\"areas\":[", BLOCK_SIZE, total_blocks)?;
+            for (index, area) in areas.iter().enumerate() {
+                let kind = if area.contents.starts_with('(') {
+                    "structure"
+                } else if area.contents.ends_with('/') {
+                    "directory"
+                } else {
+                    "file"
+                };
+                let comma = if index + 1 < areas.len() { "," } else { "" };
+                writeln!(out, "{{\"lba\":{},\"blocks\":{},\"type\":\"{}\",\"contents\":{}}}{}", area.start, area.blocks, kind, json::string(&area.contents), comma)?;
+            }
+            writeln!(out, "]}}")?;
+        }
+        MapFormat::Bmap => {
+            let image = image.ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "a bmap needs the image to checksum"))?;
+            out.write_all(bmap(&mapped_ranges(areas, total_blocks), total_blocks, image)?.as_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+// The runs of blocks something uses, merged, as first and last block
+fn mapped_ranges(areas: &[Area], total_blocks: u32) -> Vec<(u32, u32)> {
+    let mut used: Vec<(u32, u32)> = areas
+        .iter()
+        .filter(|area| area.blocks > 0 && area.start < total_blocks && area.contents != PADDING)
+        .map(|area| (area.start, (area.start + area.blocks).min(total_blocks) - 1))
+        .collect();
+    used.sort();
+    let mut ranges: Vec<(u32, u32)> = Vec::new();
+    for (first, last) in used {
+        match ranges.last_mut() {
+            Some(run) if first <= run.1 + 1 => run.1 = run.1.max(last),
+            _ => ranges.push((first, last)),
+        }
+    }
+    ranges
+}
+
+// The bmap itself; its own checksum is taken with the field all zeroes, then filled in
+fn bmap(ranges: &[(u32, u32)], total_blocks: u32, image: &mut dyn ReadAt) -> io::Result<String> {
+    let mapped: u64 = ranges.iter().map(|&(first, last)| (last - first + 1) as u64).sum();
+    let mut text = String::from("<?xml version=\"1.0\" ?>\n<bmap version=\"2.0\">\n");
+    text += &format!("    <ImageSize> {} </ImageSize>\n", total_blocks as u64 * BLOCK_SIZE as u64);
+    text += &format!("    <BlockSize> {} </BlockSize>\n", BLOCK_SIZE);
+    text += &format!("    <BlocksCount> {} </BlocksCount>\n", total_blocks);
+    text += &format!("    <MappedBlocksCount> {} </MappedBlocksCount>\n", mapped);
+    text += "    <ChecksumType> sha256 </ChecksumType>\n";
+    text += &format!("    <BmapFileChecksum> {} </BmapFileChecksum>\n", "0".repeat(64));
+    text += "    <BlockMap>\n";
+    let mut buffer = vec![0u8; HASH_CHUNK_SIZE];
+    for &(first, last) in ranges {
+        let mut sha = Sha256::new();
+        let (mut offset, end) = (first as u64 * BLOCK_SIZE as u64, (last as u64 + 1) * BLOCK_SIZE as u64);
+        while offset < end {
+            let len = (end - offset).min(HASH_CHUNK_SIZE as u64) as usize;
+            image.read_exact_at(offset, &mut buffer[..len]).map_err(|e| io::Error::new(e.kind(), format!("could not read blocks {} to {} to checksum them: {}", first, last, e)))?;
+            sha.update(&buffer[..len]);
+            offset += len as u64;
+        }
+        let blocks = if first == last { first.to_string() } else { format!("{}-{}", first, last) };
+        text += &format!("        <Range chksum=\"{}\"> {} </Range>\n", sha256::hex(&sha.finish()), blocks);
+    }
+    text += "    </BlockMap>\n</bmap>\n";
+
+    let mut sha = Sha256::new();
+    sha.update(text.as_bytes());
+    Ok(text.replacen(&"0".repeat(64), &sha256::hex(&sha.finish()), 1))
+}
@@ -0,0 +1,478 @@
+// An interactive browser for the contents of an image, in the terminal
+//
+// The tree is read once with IsoReader and shown a directory at a time; files open as text or
+// as a hex dump, reading only the part on screen, and the files and directories marked are
+// extracted with extract.rs. The terminal is driven directly: raw mode through stty and ANSI
+// escape sequences for drawing, so it needs a Unix terminal (any local or remote image works).
+// That stands in for ratatui and crossterm, which the crate's std-only build can't take on;
+// one list and one preview pane don't need their layout engine, but Windows consoles go
+// without a browser.
+//
+// Keys: up/down (or k/j), PgUp/PgDn, Home/End; Enter or right to open, left or Backspace to go
+// back; Space marks, x extracts what is marked (or the entry under the cursor), Tab switches a
+// preview between text and hex, q quits.
+
+use std::collections::BTreeSet;
+use std::io::{self, ErrorKind, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use super::extract::{self, ExtractOptions};
+use super::reader::{open_image, ImageSource, IsoEntry, IsoReader};
+
+const USAGE: &str = "Usage: makeiso browse <image.iso|URL>";
+const TEXT_LIMIT: usize = 1024 * 1024; // Text previews show the start of larger files
+const SNIFF_SIZE: usize = 8192;
+pub const HEX_WIDTH: usize = 16;
+
+enum Key {
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    Left,
+    Right,
+    Enter,
+    Escape,
+    Backspace,
+    Tab,
+    Byte(u8),
+    Nothing, // No key within a tenth of a second
+}
+
+// The terminal in raw mode on the alternate screen, put back as it was when dropped
+struct Terminal {
+    saved: String,
+}
+
+impl Terminal {
+    fn open() -> io::Result<Terminal> {
+        if !io::stdin().is_terminal() || !io::stdout().is_terminal() {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "browse needs a terminal"));
+        }
+        let saved = Command::new("stty").arg("-g").stdin(Stdio::inherit()).stderr(Stdio::null()).output().ok().filter(|output| output.status.success());
+        let Some(saved) = saved else {
+            return Err(io::Error::other("browse needs stty to control the terminal"));
+        };
+        let terminal = Terminal { saved: String::from_utf8_lossy(&saved.stdout).trim().to_string() };
+        terminal.resume()?;
+        Ok(terminal)
+    }
+
+    // Raw input that gives up after a tenth of a second, so a lone Escape can be told apart
+    fn resume(&self) -> io::Result<()> {
+        stty(&["raw", "-echo", "min", "0", "time", "1"])?;
+        print!("\x1b[?1049h\x1b[?25l");
+        io::stdout().flush()
+    }
+
+    fn suspend(&self) -> io::Result<()> {
+        print!("\x1b[?25h\x1b[?1049l");
+        io::stdout().flush()?;
+        stty(&[&self.saved])
+    }
+
+    // Rows and columns, re-read every frame so resizing just works
+    fn size(&self) -> (usize, usize) {
+        let output = Command::new("stty").arg("size").stdin(Stdio::inherit()).stderr(Stdio::null()).output();
+        let size = output.ok().and_then(|output| {
+            let text = String::from_utf8_lossy(&output.stdout).to_string();
+            let (rows, columns) = text.trim().split_once(' ')?;
+            Some((rows.parse().ok()?, columns.parse().ok()?))
+        });
+        match size {
+            Some((rows, columns)) if rows > 3 && columns > 10 => (rows, columns),
+            _ => (24, 80),
+        }
+    }
+
+    fn key(&self) -> io::Result<Key> {
+        let Some(byte) = read_byte()? else {
+            return Ok(Key::Nothing);
+        };
+        Ok(match byte {
+            b'\r' | b'\n' => Key::Enter,
+            b'\t' => Key::Tab,
+            0x7f | 0x08 => Key::Backspace,
+            0x1b => match read_byte()? {
+                Some(b'[') | Some(b'O') => match read_byte()? {
+                    Some(b'A') => Key::Up,
+                    Some(b'B') => Key::Down,
+                    Some(b'C') => Key::Right,
+                    Some(b'D') => Key::Left,
+                    Some(b'H') => Key::Home,
+                    Some(b'F') => Key::End,
+                    Some(digit @ b'1'..=b'8') => {
+                        let _ = read_byte()?; // The closing "~"
+                        match digit {
+                            b'1' | b'7' => Key::Home,
+                            b'4' | b'8' => Key::End,
+                            b'5' => Key::PageUp,
+                            b'6' => Key::PageDown,
+                            _ => Key::Nothing,
+                        }
+                    }
+                    _ => Key::Nothing,
+                },
+                _ => Key::Escape,
+            },
+            byte if byte >= 0x20 => Key::Byte(byte),
+            _ => Key::Nothing,
+        })
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        let _ = self.suspend();
+    }
+}
+
+fn stty(args: &[&str]) -> io::Result<()> {
+    let status = Command::new("stty").args(args).status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("stty {} failed", args.join(" "))));
+    }
+    Ok(())
+}
+
+fn read_byte() -> io::Result<Option<u8>> {
+    let mut byte = [0u8];
+    match io::stdin().lock().read(&mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
+
+// A line cut or padded to exactly the given width, control characters shown as "?"
+fn fit(text: &str, width: usize) -> String {
+    let mut line: String = text.chars().map(|c| if c.is_control() { '?' } else { c }).take(width).collect();
+    let used = line.chars().count();
+    line.extend(std::iter::repeat_n(' ', width - used));
+    line
+}
+
+pub fn human_size(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < units.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, units[unit])
+    }
+}
+
+// Whether the start of a file looks like text rather than binary data
+fn looks_like_text(data: &[u8]) -> bool {
+    let control = data.iter().filter(|&&byte| byte < 0x20 && !b"\t\n\r\x0c".contains(&byte)).count();
+    !data.contains(&0) && control * 20 <= data.len()
+}
+
+pub fn hex_line(offset: u64, data: &[u8]) -> String {
+    let mut line = format!("{:08x} ", offset);
+    for i in 0..HEX_WIDTH {
+        match data.get(i) {
+            Some(byte) => line.push_str(&format!(" {:02x}", byte)),
+            None => line.push_str("   "),
+        }
+        if i == 7 {
+            line.push(' ');
+        }
+    }
+    line.push_str("  |");
+    line.extend(data.iter().map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' }));
+    line.push('|');
+    line
+}
+
+// A file opened for viewing
+struct Preview {
+    entry: usize,
+    hex: bool,
+    scroll: usize,
+    text: Vec<String>, // Lines of the text view, from the first TEXT_LIMIT bytes
+    truncated: bool,
+}
+
+struct Browser {
+    location: PathBuf,
+    reader: IsoReader<ImageSource>,
+    entries: Vec<IsoEntry>,
+    directory: usize,
+    cursor: usize,
+    top: usize,
+    marked: BTreeSet<usize>,
+    preview: Option<Preview>,
+    status: String,
+}
+
+impl Browser {
+    fn children(&self) -> &[usize] {
+        &self.entries[self.directory].children
+    }
+
+    fn read(&mut self, entry: usize, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        let entry = &self.entries[entry];
+        let len = len.min((entry.size as u64).saturating_sub(offset) as usize);
+        let mut data = vec![0u8; len];
+        let mut done = 0;
+        while done < len {
+            match self.reader.read_file_at(entry, offset + done as u64, &mut data[done..])? {
+                0 => break,
+                read => done += read,
+            }
+        }
+        data.truncate(done);
+        Ok(data)
+    }
+
+    fn open(&mut self) -> io::Result<()> {
+        let Some(&index) = self.children().get(self.cursor) else {
+            return Ok(());
+        };
+        if self.entries[index].is_directory {
+            self.directory = index;
+            (self.cursor, self.top) = (0, 0);
+            return Ok(());
+        }
+        if let Some(link) = &self.entries[index].symlink {
+            self.status = format!("{} is a symbolic link to {}", self.entries[index].name, link);
+            return Ok(());
+        }
+        let start = self.read(index, 0, TEXT_LIMIT)?;
+        let hex = !looks_like_text(&start[..start.len().min(SNIFF_SIZE)]);
+        let text = String::from_utf8_lossy(&start).replace('\t', "    ").lines().map(str::to_string).collect();
+        self.preview = Some(Preview { entry: index, hex, scroll: 0, text, truncated: self.entries[index].size as usize > TEXT_LIMIT });
+        Ok(())
+    }
+
+    fn back(&mut self) {
+        if self.directory == 0 {
+            return;
+        }
+        let from = self.directory;
+        self.directory = self.entries[from].parent;
+        self.cursor = self.children().iter().position(|&child| child == from).unwrap_or(0);
+    }
+
+    fn toggle_mark(&mut self) {
+        if let Some(&index) = self.children().get(self.cursor) {
+            if !self.marked.remove(&index) {
+                self.marked.insert(index);
+            }
+            self.cursor = (self.cursor + 1).min(self.children().len().saturating_sub(1));
+        }
+    }
+
+    // Lines in the preview, for scrolling
+    fn preview_lines(&self, preview: &Preview) -> usize {
+        if preview.hex {
+            (self.entries[preview.entry].size as usize).div_ceil(HEX_WIDTH)
+        } else {
+            preview.text.len()
+        }
+    }
+
+    fn draw(&mut self, rows: usize, columns: usize) -> io::Result<()> {
+        let body = rows - 2;
+        let mut screen = String::from("\x1b[H");
+        let mut highlight = None;
+        let (title, lines, help) = match self.preview.take() {
+            Some(preview) => {
+                let entry = &self.entries[preview.entry];
+                let view = if preview.hex { "hex" } else if preview.truncated { "text, first 1 MiB" } else { "text" };
+                let title = format!(" {}  ({}, {})", entry.path, human_size(entry.size as u64), view);
+                let lines: Vec<String> = if preview.hex {
+                    let offset = (preview.scroll * HEX_WIDTH) as u64;
+                    let data = self.read(preview.entry, offset, body * HEX_WIDTH)?;
+                    data.chunks(HEX_WIDTH).enumerate().map(|(i, chunk)| hex_line(offset + (i * HEX_WIDTH) as u64, chunk)).collect()
+                } else {
+                    preview.text.iter().skip(preview.scroll).take(body).cloned().collect()
+                };
+                self.preview = Some(preview);
+                (title, lines, " Tab text/hex  Up/Down PgUp/PgDn scroll  Left back  q quit".to_string())
+            }
+            None => {
+                let directory = &self.entries[self.directory];
+                let title = format!(" {}  {}", self.location.display(), directory.path);
+                if self.cursor < self.top {
+                    self.top = self.cursor;
+                } else if self.cursor >= self.top + body {
+                    self.top = self.cursor + 1 - body;
+                }
+                let size_width = 12;
+                let name_width = columns.saturating_sub(size_width + 4);
+                let lines = directory
+                    .children
+                    .iter()
+                    .skip(self.top)
+                    .take(body)
+                    .map(|&index| {
+                        let entry = &self.entries[index];
+                        let mark = if self.marked.contains(&index) { '*' } else { ' ' };
+                        let (name, size) = match &entry.symlink {
+                            _ if entry.is_directory => (format!("{}/", entry.name), String::new()),
+                            Some(link) => (format!("{} -> {}", entry.name, link), String::new()),
+                            None => (entry.name.clone(), human_size(entry.size as u64)),
+                        };
+                        format!(" {} {} {:>width$}", mark, fit(&name, name_width), size, width = size_width)
+                    })
+                    .collect();
+                highlight = Some(self.cursor - self.top);
+                let help = format!(" Enter open  Left back  Space mark  x extract ({} marked)  q quit", self.marked.len());
+                (title, lines, help)
+            }
+        };
+
+        screen.push_str(&format!("\x1b[7m{}\x1b[0m\r\n", fit(&title, columns)));
+        for i in 0..body {
+            let line = fit(lines.get(i).map_or("", String::as_str), columns);
+            if highlight == Some(i) {
+                screen.push_str(&format!("\x1b[7m{}\x1b[0m\r\n", line));
+            } else {
+                screen.push_str(&line);
+                screen.push_str("\r\n");
+            }
+        }
+        let footer = if self.status.is_empty() { help } else { format!(" {}", self.status) };
+        screen.push_str(&format!("\x1b[7m{}\x1b[0m", fit(&footer, columns)));
+        print!("{}", screen);
+        io::stdout().flush()
+    }
+
+    // Ask for a line on the status line; None when cancelled with Escape
+    fn prompt(&mut self, terminal: &Terminal, question: &str) -> io::Result<Option<String>> {
+        let mut answer = Vec::new();
+        loop {
+            self.status = format!("{}{}_", question, String::from_utf8_lossy(&answer));
+            let (rows, columns) = terminal.size();
+            self.draw(rows, columns)?;
+            match terminal.key()? {
+                Key::Enter => break,
+                Key::Escape => {
+                    self.status.clear();
+                    return Ok(None);
+                }
+                Key::Backspace => {
+                    while let Some(byte) = answer.pop() {
+                        if byte & 0xc0 != 0x80 {
+                            break; // Back to the first byte of a character
+                        }
+                    }
+                }
+                Key::Byte(byte) => answer.push(byte),
+                _ => {}
+            }
+        }
+        self.status.clear();
+        Ok(Some(String::from_utf8_lossy(&answer).trim().to_string()))
+    }
+
+    // Extract the marked entries, or the one under the cursor, with the terminal back to normal
+    fn extract(&mut self, terminal: &Terminal) -> io::Result<()> {
+        let selection: Vec<usize> = if self.marked.is_empty() { self.children().get(self.cursor).copied().into_iter().collect() } else { self.marked.iter().copied().collect() };
+        if selection.is_empty() {
+            return Ok(());
+        }
+        let Some(destination) = self.prompt(terminal, &format!("Extract {} entries to: ", selection.len()))? else {
+            return Ok(());
+        };
+        if destination.is_empty() {
+            return Ok(());
+        }
+        let paths: Vec<String> = selection.iter().map(|&index| self.entries[index].path.clone()).collect();
+
+        terminal.suspend()?;
+        match extract::extract(&self.location, Path::new(&destination), &paths, &ExtractOptions::default()) {
+            Ok(()) => {
+                self.status = format!("Extracted {} entries to {}", paths.len(), destination);
+                self.marked.clear();
+            }
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                self.status = format!("Extraction failed: {}", e);
+            }
+        }
+        println!("Press Enter to go back to the browser");
+        let mut line = String::new();
+        io::stdin().read_line(&mut line)?;
+        terminal.resume()
+    }
+}
+
+// makeiso browse <image.iso|URL>
+pub fn browse(args: &[String]) -> io::Result<()> {
+    let [location] = args else {
+        return Err(io::Error::new(ErrorKind::InvalidInput, USAGE));
+    };
+    let location = PathBuf::from(location);
+    let mut reader = open_image(&location)?;
+    let entries = reader.read_tree()?;
+    let mut browser = Browser { location, reader, entries, directory: 0, cursor: 0, top: 0, marked: BTreeSet::new(), preview: None, status: String::new() };
+
+    let terminal = Terminal::open()?;
+    let mut size = (0, 0);
+    let mut redraw = true;
+    loop {
+        let now = terminal.size();
+        if redraw || now != size {
+            size = now;
+            browser.draw(size.0, size.1)?;
+        }
+        let key = terminal.key()?;
+        redraw = !matches!(key, Key::Nothing);
+        if redraw {
+            browser.status.clear();
+        }
+        let page = size.0.saturating_sub(3).max(1);
+
+        if let Some(preview) = &browser.preview {
+            let last = browser.preview_lines(preview).saturating_sub(size.0 - 2); // The last line at the bottom
+            let preview = browser.preview.as_mut().expect("open preview");
+            match key {
+                Key::Up | Key::Byte(b'k') => preview.scroll = preview.scroll.saturating_sub(1),
+                Key::Down | Key::Byte(b'j') => preview.scroll = (preview.scroll + 1).min(last),
+                Key::PageUp => preview.scroll = preview.scroll.saturating_sub(page),
+                Key::PageDown | Key::Byte(b' ') => preview.scroll = (preview.scroll + page).min(last),
+                Key::Home | Key::Byte(b'g') => preview.scroll = 0,
+                Key::End | Key::Byte(b'G') => preview.scroll = last,
+                Key::Tab => {
+                    preview.hex = !preview.hex;
+                    preview.scroll = 0;
+                }
+                Key::Left | Key::Backspace | Key::Escape | Key::Byte(b'h') => browser.preview = None,
+                Key::Byte(b'q') => break,
+                _ => {}
+            }
+            continue;
+        }
+
+        let last = browser.children().len().saturating_sub(1);
+        match key {
+            Key::Up | Key::Byte(b'k') => browser.cursor = browser.cursor.saturating_sub(1),
+            Key::Down | Key::Byte(b'j') => browser.cursor = (browser.cursor + 1).min(last),
+            Key::PageUp => browser.cursor = browser.cursor.saturating_sub(page),
+            Key::PageDown => browser.cursor = (browser.cursor + page).min(last),
+            Key::Home | Key::Byte(b'g') => browser.cursor = 0,
+            Key::End | Key::Byte(b'G') => browser.cursor = last,
+            Key::Enter | Key::Right | Key::Byte(b'l') => {
+                if let Err(e) = browser.open() {
+                    browser.status = format!("Could not read the file: {}", e);
+                }
+            }
+            Key::Left | Key::Backspace | Key::Byte(b'h') => browser.back(),
+            Key::Byte(b' ') => browser.toggle_mark(),
+            Key::Byte(b'x') => browser.extract(&terminal)?,
+            Key::Byte(b'q') | Key::Escape => break,
+            _ => {}
+        }
+    }
+    Ok(())
+}
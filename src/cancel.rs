@@ -0,0 +1,84 @@
+// Stopping a build cleanly on Ctrl-C (SIGINT), SIGTERM or a console close
+//
+// The handler only sets a flag; the scan and the copy look at it between entries and chunks
+// and fail with "cancelled", so the partial image is cleaned up like after any other error
+// (see write_output in main.rs). A second signal ends the process on the spot.
+
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Once;
+
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+static INSTALL: Once = Once::new();
+
+#[cfg(unix)]
+mod sys {
+    use std::ffi::c_int;
+
+    pub const SIGHUP: c_int = 1;
+    pub const SIGINT: c_int = 2;
+    pub const SIGTERM: c_int = 15;
+
+    extern "C" {
+        pub fn signal(signum: c_int, handler: extern "C" fn(c_int)) -> usize;
+        pub fn _exit(status: c_int) -> !;
+    }
+}
+
+#[cfg(unix)]
+extern "C" fn on_signal(signum: std::ffi::c_int) {
+    // Only async-signal-safe work here: an atomic swap and _exit
+    if CANCELLED.swap(true, Ordering::SeqCst) {
+        // SAFETY: _exit is async-signal-safe and never returns
+        unsafe { sys::_exit(128 + signum) }
+    }
+}
+
+#[cfg(windows)]
+unsafe extern "system" fn on_console_event(_event: u32) -> windows::Win32::Foundation::BOOL {
+    if CANCELLED.swap(true, Ordering::SeqCst) {
+        return false.into(); // Let the default handler end the process
+    }
+    true.into()
+}
+
+#[cfg(target_os = "linux")]
+extern "C" fn ignore(_signum: std::ffi::c_int) {}
+
+// Leave Ctrl-C and a closed terminal to a child running in the foreground (makeiso shell), so
+// this process is still there to clean up after it. A handler rather than SIG_IGN, which the
+// child would inherit
+#[cfg(target_os = "linux")]
+pub fn leave_to_child() {
+    // SAFETY: the handler does nothing
+    unsafe {
+        sys::signal(sys::SIGINT, ignore);
+        sys::signal(sys::SIGHUP, ignore);
+    }
+}
+
+// Catch the signals from now on; called before an image is written
+pub fn install() {
+    INSTALL.call_once(|| {
+        #[cfg(unix)]
+        // SAFETY: the handler only touches an atomic and calls _exit
+        unsafe {
+            sys::signal(sys::SIGINT, on_signal);
+            sys::signal(sys::SIGTERM, on_signal);
+        }
+        #[cfg(windows)]
+        // SAFETY: the handler only touches an atomic
+        unsafe {
+            let _ = windows::Win32::System::Console::SetConsoleCtrlHandler(Some(on_console_event), windows::Win32::Foundation::BOOL::from(true));
+        }
+    });
+}
+
+// Fail once a signal came in
+pub fn check() -> io::Result<()> {
+    if CANCELLED.load(Ordering::Relaxed) {
+        // Not ErrorKind::Interrupted, which write_all and io::copy would retry
+        return Err(io::Error::other("cancelled"));
+    }
+    Ok(())
+}
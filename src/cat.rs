@@ -0,0 +1,48 @@
+// Write the contents of files in an image to standard output, one after another, reading each
+// file's extent straight from the image. Paths are looked up as extract looks them up, so
+// `makeiso cat image.iso /etc/FSTAB` finds /etc/fstab where the name table has no case.
+
+use std::io::{self, ErrorKind, Write};
+use std::path::Path;
+
+use super::reader::{lookup, open_image};
+
+const READ_CHUNK_SIZE: usize = 1024 * 1024;
+const USAGE: &str = "Usage: makeiso cat <image.iso> <path> [path ...]";
+
+// makeiso cat <image.iso> <path> [path ...]
+pub fn cat(args: &[String]) -> io::Result<()> {
+    let [image, paths @ ..] = args else {
+        return Err(io::Error::new(ErrorKind::InvalidInput, USAGE));
+    };
+    if paths.is_empty() {
+        return Err(io::Error::new(ErrorKind::InvalidInput, USAGE));
+    }
+
+    let mut reader = open_image(Path::new(image))?;
+    let entries = reader.read_tree()?;
+    let case_insensitive = reader.case_insensitive();
+    let mut buffer = vec![0u8; READ_CHUNK_SIZE];
+    let mut output = io::stdout().lock();
+    for path in paths {
+        let entry = lookup(&entries, path, case_insensitive)
+            .map(|index| &entries[index])
+            .ok_or_else(|| io::Error::new(ErrorKind::NotFound, format!("/{} is not in the image", path.trim_matches('/'))))?;
+        if entry.is_directory {
+            return Err(io::Error::new(ErrorKind::InvalidInput, format!("{} is a directory", entry.path)));
+        }
+        if let Some(target) = &entry.symlink {
+            return Err(io::Error::new(ErrorKind::InvalidInput, format!("{} is a symbolic link to {}", entry.path, target)));
+        }
+        let mut offset = 0;
+        while offset < entry.size as u64 {
+            let bytes_read = reader.read_file_at(entry, offset, &mut buffer)?;
+            if bytes_read == 0 {
+                break;
+            }
+            output.write_all(&buffer[..bytes_read])?;
+            offset += bytes_read as u64;
+        }
+    }
+    output.flush()
+}
@@ -0,0 +1,111 @@
+// Machine-readable inventory of an image: every entry with its location, attributes and
+// (optionally) a content hash, as one JSON document for audit and compliance records
+
+use std::fs::File;
+use std::io::{self, BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use super::json;
+use super::reader::{open_image, IsoEntry, IsoReader, NameSource, ReadAt};
+use super::sha256::{self, Sha256};
+use super::{blocks_for, time_text};
+
+pub const HASH_CHUNK_SIZE: usize = 1024 * 1024;
+
+// makeiso catalog <image.iso> [--output catalog.json] [--hash sha256]
+pub fn catalog(args: &[String]) -> io::Result<()> {
+    let usage = || io::Error::new(ErrorKind::InvalidInput, "Usage: makeiso catalog <image.iso> [--output catalog.json] [--hash sha256]");
+    let (mut image, mut output, mut hash) = (None, None, false);
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--output" | "-o" => output = Some(PathBuf::from(args.next().ok_or_else(usage)?)),
+            "--hash" => match args.next().map(String::as_str) {
+                Some("sha256") => hash = true,
+                Some(other) => return Err(io::Error::new(ErrorKind::InvalidInput, format!("Unknown --hash {} (expected sha256)", other))),
+                None => return Err(usage()),
+            },
+            _ if image.is_none() => image = Some(PathBuf::from(arg)),
+            _ => return Err(usage()),
+        }
+    }
+    let image = image.ok_or_else(usage)?;
+
+    let mut out: BufWriter<Box<dyn Write>> = BufWriter::new(match &output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout()),
+    });
+    let count = write_catalog(&image, &mut out, hash)?;
+    out.flush()?;
+
+    if let Some(path) = output {
+        eprintln!("Wrote a catalog of {} entries to {}", count, path.display());
+    }
+    Ok(())
+}
+
+// Write the catalog of an image, returning how many entries it has
+pub fn write_catalog(image: &Path, out: &mut dyn Write, hash: bool) -> io::Result<usize> {
+    let mut reader = open_image(image)?;
+    let entries = reader.read_tree()?;
+    let names = match reader.name_source() {
+        NameSource::Iso9660 => "iso9660",
+        NameSource::RockRidge => "rockridge",
+        NameSource::Joliet => "joliet",
+        NameSource::Enhanced => "iso9660:1999",
+    };
+    writeln!(
+        out,
+        "{{\"image\":{},\"volume_id\":{},\"volume_blocks\":{},\"names\":\"{}\",\"entries\":[",
+        json::string(&image.display().to_string()),
+        json::string(&reader.pvd.volume_id),
+        reader.pvd.volume_space_size,
+        names
+    )?;
+
+    let mut buffer = vec![0u8; HASH_CHUNK_SIZE];
+    for (index, entry) in entries.iter().enumerate() {
+        let kind = if entry.is_directory {
+            "directory"
+        } else if entry.symlink.is_some() {
+            "symlink"
+        } else {
+            "file"
+        };
+        let mut line = format!(
+            "{{\"path\":{},\"type\":\"{}\",\"size\":{},\"lba\":{},\"blocks\":{},\"flags\":{},\"modified\":{},\"mode\":\"{:o}\",\"links\":{},\"uid\":{},\"gid\":{}",
+            json::string(&entry.path),
+            kind,
+            entry.size,
+            entry.extent,
+            blocks_for(entry.size as u64),
+            entry.flags,
+            json::string(&time_text(entry.modified)),
+            entry.mode,
+            entry.links,
+            entry.uid,
+            entry.gid
+        );
+        if let Some(target) = &entry.symlink {
+            line += &format!(",\"target\":{}", json::string(target));
+        }
+        if hash && kind == "file" {
+            line += &format!(",\"sha256\":\"{}\"", file_sha256(&mut reader, entry, &mut buffer)?);
+        }
+        writeln!(out, "{}}}{}", line, if index + 1 < entries.len() { "," } else { "" })?;
+    }
+    writeln!(out, "]}}")?;
+    Ok(entries.len())
+}
+
+// The SHA-256 of a file in the image, read through buffer
+pub fn file_sha256<R: ReadAt>(reader: &mut IsoReader<R>, entry: &IsoEntry, buffer: &mut [u8]) -> io::Result<String> {
+    let mut sha = Sha256::new();
+    let mut offset = 0;
+    while offset < entry.size as u64 {
+        let bytes_read = reader.read_file_at(entry, offset, buffer)?;
+        sha.update(&buffer[..bytes_read]);
+        offset += bytes_read as u64;
+    }
+    Ok(sha256::hex(&sha.finish()))
+}
@@ -0,0 +1,217 @@
+// The catalog database (--catalog-db FILE): every image built with the option is added to it
+// with its manifest, each file and directory with its size, modification time and SHA-256, so
+// makeiso which-image can tell which backups hold a path without opening any of them.
+//
+// The database is SQLite, through the sqlite3 shell as gpg and curl are used elsewhere: an
+// images table (when each was built, where it went, volume ID, size) and an entries table
+// with a row per path of each image, indexed by path. Each image is added in one transaction,
+// so one cut short by a crash leaves nothing behind, and the database can be queried with
+// sqlite3 itself. What is read back comes out as hex where it could hold a tab or a line
+// break, so no path can split a row. Without sqlite3 on PATH --catalog-db is refused before
+// the build rather than failing once the image is written.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::SystemTime;
+
+use super::catalog::{file_sha256, HASH_CHUNK_SIZE};
+use super::daemon::state_dir;
+use super::reader::open_image;
+use super::{events, parse_date, time_text, unix_time};
+
+const DEFAULT_FILE: &str = "catalog.db";
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS images (id INTEGER PRIMARY KEY, built INTEGER NOT NULL, location TEXT NOT NULL, volume_id TEXT NOT NULL, size INTEGER NOT NULL);
+CREATE TABLE IF NOT EXISTS entries (image INTEGER NOT NULL REFERENCES images (id), path TEXT NOT NULL, kind TEXT NOT NULL, size INTEGER NOT NULL, modified INTEGER NOT NULL, sha256 TEXT);
+CREATE INDEX IF NOT EXISTS entries_by_path ON entries (path);
+";
+const USAGE: &str = "Usage: makeiso which-image [--catalog-db FILE] --file PATH [--newer DATE] [--older DATE]";
+
+// Add the image at path, just built, to the database
+pub fn add_image(db: &Path, image: &Path) -> io::Result<()> {
+    let mut reader = open_image(image)?;
+    let entries = reader.read_tree()?;
+    let location = fs::canonicalize(image).unwrap_or_else(|_| image.to_path_buf());
+    let size = fs::metadata(image)?.len();
+    let mut script = format!(
+        "{}BEGIN;\nINSERT INTO images (built, location, volume_id, size) VALUES ({}, {}, {}, {});\n",
+        SCHEMA,
+        unix_time(SystemTime::now()),
+        quoted(&location.display().to_string()),
+        quoted(&reader.pvd.volume_id),
+        size
+    );
+    let mut buffer = vec![0u8; HASH_CHUNK_SIZE];
+    for entry in &entries {
+        let sha256 = if entry.is_directory || entry.symlink.is_some() { "NULL".to_string() } else { quoted(&file_sha256(&mut reader, entry, &mut buffer)?) };
+        let kind = if entry.is_directory {
+            "dir"
+        } else if entry.symlink.is_some() {
+            "link"
+        } else {
+            "file"
+        };
+        script += &format!(
+            "INSERT INTO entries (image, path, kind, size, modified, sha256) VALUES ((SELECT max(id) FROM images), {}, '{}', {}, {}, {});\n",
+            quoted(&entry.path),
+            kind,
+            entry.size,
+            entry.modified,
+            sha256
+        );
+    }
+    script += "COMMIT;\n";
+    if let Some(dir) = db.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+    sqlite(db, &script)?;
+    events::info(&format!("Added {} ({} entries) to the catalog {}", location.display(), entries.len(), db.display()));
+    Ok(())
+}
+
+// When each image in the database was built (the last time, for a path built more than once);
+// a database that isn't there yet has none
+pub fn built_images(db: &Path) -> io::Result<HashMap<PathBuf, i64>> {
+    match fs::metadata(db) {
+        Ok(_) => {}
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(io::Error::new(e.kind(), format!("could not read the catalog {}: {}", db.display(), e))),
+    }
+    let rows = sqlite(db, &format!("{}SELECT hex(location), max(built) FROM images GROUP BY location;\n", SCHEMA))?;
+    let mut images = HashMap::new();
+    for line in rows.lines() {
+        if let [location, built] = line.split('\t').collect::<Vec<_>>()[..] {
+            if let (Some(location), Ok(built)) = (unhex(location), built.parse()) {
+                images.insert(PathBuf::from(location), built);
+            }
+        }
+    }
+    Ok(images)
+}
+
+// Refuse --catalog-db up front when there is no sqlite3 to keep the catalog with
+pub fn check_sqlite() -> io::Result<()> {
+    Command::new("sqlite3").arg("-version").stdin(Stdio::null()).stdout(Stdio::null()).stderr(Stdio::null()).status().map_err(run_error)?;
+    Ok(())
+}
+
+fn run_error(e: io::Error) -> io::Error {
+    if e.kind() == ErrorKind::NotFound {
+        io::Error::new(ErrorKind::NotFound, "sqlite3 is not installed (or not on PATH), and the catalog database is kept through it")
+    } else {
+        io::Error::new(e.kind(), format!("could not run sqlite3: {}", e))
+    }
+}
+
+// Run a script through sqlite3 on the database, and what it printed: one line per row, the
+// columns separated by tabs. Errors stop the script, so a transaction it left open is rolled back
+fn sqlite(db: &Path, script: &str) -> io::Result<String> {
+    let mut child = Command::new("sqlite3")
+        .args(["-batch", "-bail", "-noheader", "-separator", "\t"])
+        .arg(db)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| {
+            let e = run_error(e);
+            io::Error::new(e.kind(), format!("{} ({})", e, db.display()))
+        })?;
+    // The script is written from a thread of its own, so sqlite3 is never stuck writing output
+    // nobody reads while makeiso is stuck writing input it doesn't read yet
+    let mut input = child.stdin.take().expect("piped");
+    let output = thread::scope(|scope| {
+        scope.spawn(move || input.write_all(script.as_bytes()));
+        child.wait_with_output()
+    })?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!("sqlite3 could not use the catalog {}: {}", db.display(), String::from_utf8_lossy(&output.stderr).trim())));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+// A string as an SQL literal
+fn quoted(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "''"))
+}
+
+// Text sqlite3 printed as hex(...)
+fn unhex(hex: &str) -> Option<String> {
+    let bytes = (0..hex.len()).step_by(2).map(|at| hex.get(at..at + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok())).collect::<Option<Vec<u8>>>()?;
+    Some(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+// Where which-image and prune look without --catalog-db: catalog.db in the state directory
+pub fn default_db() -> io::Result<PathBuf> {
+    state_dir().map(|dir| dir.join(DEFAULT_FILE)).ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "no --catalog-db given and no state directory to look in"))
+}
+
+// An image in the database and what it holds at the path asked about
+struct Found {
+    built: i64,
+    image: String,
+    kind: String,
+    size: u64,
+    modified: i64,
+    sha256: String,
+}
+
+// makeiso which-image [--catalog-db FILE] --file PATH [--newer DATE] [--older DATE]
+pub fn which_image(args: &[String]) -> io::Result<()> {
+    let (mut db, mut file, mut newer, mut older) = (None, None, None, None);
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().cloned().ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, format!("{} requires a value", arg)));
+        match arg.as_str() {
+            "--catalog-db" => db = Some(PathBuf::from(value()?)),
+            "--file" => file = Some(value()?),
+            "--newer" => newer = Some(parse_date(&value()?)?),
+            "--older" => older = Some(parse_date(&value()?)?),
+            _ => return Err(io::Error::new(ErrorKind::InvalidInput, USAGE)),
+        }
+    }
+    let file = file.ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, USAGE))?;
+    let path = format!("/{}", file.trim_matches('/'));
+    let db = match db {
+        Some(db) => db,
+        None => default_db()?,
+    };
+    fs::metadata(&db).map_err(|e| io::Error::new(e.kind(), format!("could not read the catalog {}: {}", db.display(), e)))?;
+
+    let mut query = format!(
+        "{}SELECT images.built, hex(images.location), entries.kind, entries.size, entries.modified, ifnull(entries.sha256, '') FROM entries JOIN images ON images.id = entries.image WHERE entries.path = {}",
+        SCHEMA,
+        quoted(&path)
+    );
+    if let Some(newer) = newer {
+        query += &format!(" AND images.built >= {}", newer);
+    }
+    if let Some(older) = older {
+        query += &format!(" AND images.built < {}", older);
+    }
+    query += " ORDER BY images.built, images.id;\n";
+    let mut found = Vec::new();
+    for line in sqlite(&db, &query)?.lines() {
+        if let [built, location, kind, size, modified, sha256] = line.split('\t').collect::<Vec<_>>()[..] {
+            if let (Ok(built), Some(image), Ok(size), Ok(modified)) = (built.parse(), unhex(location), size.parse(), modified.parse()) {
+                found.push(Found { built, image, kind: kind.to_string(), size, modified, sha256: sha256.to_string() });
+            }
+        }
+    }
+    if found.is_empty() {
+        return Err(io::Error::new(ErrorKind::NotFound, format!("no image in {} holds {}", db.display(), path)));
+    }
+    for found in &found {
+        // Images are moved and pruned; say so rather than point at nothing
+        let gone = if Path::new(&found.image).exists() { "" } else { " (no longer there)" };
+        match found.kind.as_str() {
+            "dir" => println!("{}  {}{}  directory, modified {}", time_text(found.built), found.image, gone, time_text(found.modified)),
+            "link" => println!("{}  {}{}  symbolic link, modified {}", time_text(found.built), found.image, gone, time_text(found.modified)),
+            _ => println!("{}  {}{}  {} bytes, modified {}, sha256 {}", time_text(found.built), found.image, gone, found.size, time_text(found.modified), found.sha256),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,259 @@
+// ChaCha20-Poly1305 (RFC 8439) without associated data, as age uses it for file keys and for
+// every chunk of the payload
+
+const SIGMA: [u32; 4] = [0x61707865, 0x3320646e, 0x79622d32, 0x6b206574]; // "expand 32-byte k"
+pub const TAG_SIZE: usize = 16;
+
+// Encrypt and append the tag
+pub fn seal(key: &[u8; 32], nonce: &[u8; 12], plaintext: &[u8]) -> Vec<u8> {
+    let mut sealed = plaintext.to_vec();
+    xor_stream(key, nonce, 1, &mut sealed);
+    let tag = tag(key, nonce, &sealed);
+    sealed.extend_from_slice(&tag);
+    sealed
+}
+
+// Check the tag and decrypt; None if the data or the key is wrong
+pub fn open(key: &[u8; 32], nonce: &[u8; 12], sealed: &[u8]) -> Option<Vec<u8>> {
+    let (ciphertext, expected) = sealed.split_at_checked(sealed.len().checked_sub(TAG_SIZE)?)?;
+    let computed = tag(key, nonce, ciphertext);
+    // Compared in full rather than stopping at the first difference
+    if computed.iter().zip(expected).fold(0u8, |difference, (a, b)| difference | (a ^ b)) != 0 {
+        return None;
+    }
+    let mut plaintext = ciphertext.to_vec();
+    xor_stream(key, nonce, 1, &mut plaintext);
+    Some(plaintext)
+}
+
+fn tag(key: &[u8; 32], nonce: &[u8; 12], ciphertext: &[u8]) -> [u8; 16] {
+    let mut one_time_key = [0u8; 32];
+    one_time_key.copy_from_slice(&block(key, nonce, 0)[..32]);
+    let mut poly = Poly1305::new(&one_time_key);
+    poly.update(ciphertext);
+    let mut lengths = [0u8; 16]; // No associated data, then the length of the ciphertext
+    lengths[8..].copy_from_slice(&(ciphertext.len() as u64).to_le_bytes());
+    poly.update(&lengths);
+    poly.finish()
+}
+
+fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(12);
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
+
+// One 64-byte block of the key stream
+fn block(key: &[u8; 32], nonce: &[u8; 12], counter: u32) -> [u8; 64] {
+    let word = |bytes: &[u8]| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    let mut input = [0u32; 16];
+    input[..4].copy_from_slice(&SIGMA);
+    for (i, bytes) in key.chunks(4).enumerate() {
+        input[4 + i] = word(bytes);
+    }
+    input[12] = counter;
+    for (i, bytes) in nonce.chunks(4).enumerate() {
+        input[13 + i] = word(bytes);
+    }
+
+    let mut state = input;
+    for _ in 0..10 {
+        quarter_round(&mut state, 0, 4, 8, 12);
+        quarter_round(&mut state, 1, 5, 9, 13);
+        quarter_round(&mut state, 2, 6, 10, 14);
+        quarter_round(&mut state, 3, 7, 11, 15);
+        quarter_round(&mut state, 0, 5, 10, 15);
+        quarter_round(&mut state, 1, 6, 11, 12);
+        quarter_round(&mut state, 2, 7, 8, 13);
+        quarter_round(&mut state, 3, 4, 9, 14);
+    }
+    let mut out = [0u8; 64];
+    for (bytes, (word, original)) in out.chunks_mut(4).zip(state.iter().zip(input)) {
+        bytes.copy_from_slice(&word.wrapping_add(original).to_le_bytes());
+    }
+    out
+}
+
+fn xor_stream(key: &[u8; 32], nonce: &[u8; 12], first_counter: u32, data: &mut [u8]) {
+    for (i, chunk) in data.chunks_mut(64).enumerate() {
+        let stream = block(key, nonce, first_counter.wrapping_add(i as u32));
+        for (byte, key_byte) in chunk.iter_mut().zip(stream) {
+            *byte ^= key_byte;
+        }
+    }
+}
+
+// Poly1305 with 26-bit limbs
+struct Poly1305 {
+    r: [u32; 5],
+    h: [u32; 5],
+    pad: [u32; 4],
+}
+
+impl Poly1305 {
+    fn new(key: &[u8; 32]) -> Poly1305 {
+        let word = |at: usize| u32::from_le_bytes([key[at], key[at + 1], key[at + 2], key[at + 3]]);
+        Poly1305 {
+            r: [
+                word(0) & 0x3ffffff,
+                (word(3) >> 2) & 0x3ffff03,
+                (word(6) >> 4) & 0x3ffc0ff,
+                (word(9) >> 6) & 0x3f03fff,
+                (word(12) >> 8) & 0x00fffff,
+            ],
+            h: [0; 5],
+            pad: [word(16), word(20), word(24), word(28)],
+        }
+    }
+
+    // Data is padded with zeros to whole 16-byte blocks, as the AEAD construction wants
+    fn update(&mut self, data: &[u8]) {
+        for chunk in data.chunks(16) {
+            let mut block = [0u8; 16];
+            block[..chunk.len()].copy_from_slice(chunk);
+            self.block(&block);
+        }
+    }
+
+    fn block(&mut self, block: &[u8; 16]) {
+        let word = |at: usize| u32::from_le_bytes([block[at], block[at + 1], block[at + 2], block[at + 3]]);
+        let [r0, r1, r2, r3, r4] = self.r.map(u64::from);
+        let (s1, s2, s3, s4) = (r1 * 5, r2 * 5, r3 * 5, r4 * 5);
+        let h = &mut self.h;
+        h[0] += word(0) & 0x3ffffff;
+        h[1] += (word(3) >> 2) & 0x3ffffff;
+        h[2] += (word(6) >> 4) & 0x3ffffff;
+        h[3] += (word(9) >> 6) & 0x3ffffff;
+        h[4] += (word(12) >> 8) | (1 << 24);
+        let [h0, h1, h2, h3, h4] = h.map(u64::from);
+
+        let d0 = h0 * r0 + h1 * s4 + h2 * s3 + h3 * s2 + h4 * s1;
+        let mut d1 = h0 * r1 + h1 * r0 + h2 * s4 + h3 * s3 + h4 * s2;
+        let mut d2 = h0 * r2 + h1 * r1 + h2 * r0 + h3 * s4 + h4 * s3;
+        let mut d3 = h0 * r3 + h1 * r2 + h2 * r1 + h3 * r0 + h4 * s4;
+        let mut d4 = h0 * r4 + h1 * r3 + h2 * r2 + h3 * r1 + h4 * r0;
+
+        d1 += d0 >> 26;
+        d2 += d1 >> 26;
+        d3 += d2 >> 26;
+        d4 += d3 >> 26;
+        let mut h0 = (d0 & 0x3ffffff) + (d4 >> 26) * 5;
+        let h1 = (d1 & 0x3ffffff) + (h0 >> 26);
+        h0 &= 0x3ffffff;
+        *h = [h0 as u32, h1 as u32, (d2 & 0x3ffffff) as u32, (d3 & 0x3ffffff) as u32, (d4 & 0x3ffffff) as u32];
+    }
+
+    fn finish(self) -> [u8; 16] {
+        let mut h = self.h;
+        // Carry fully, then subtract p = 2^130 - 5 if h is at least p
+        for i in 1..5 {
+            h[i] += h[i - 1] >> 26;
+            h[i - 1] &= 0x3ffffff;
+        }
+        h[0] += (h[4] >> 26) * 5;
+        h[4] &= 0x3ffffff;
+        h[1] += h[0] >> 26;
+        h[0] &= 0x3ffffff;
+
+        let mut g = [0u32; 5];
+        let mut carry = 5;
+        for i in 0..5 {
+            let sum = h[i] + carry;
+            g[i] = sum & 0x3ffffff;
+            carry = sum >> 26;
+        }
+        let use_g = carry.wrapping_neg(); // All ones if h + 5 reached 2^130
+        for i in 0..5 {
+            h[i] = (h[i] & !use_g) | (g[i] & use_g);
+        }
+
+        let words = [h[0] | (h[1] << 26), (h[1] >> 6) | (h[2] << 20), (h[2] >> 12) | (h[3] << 14), (h[3] >> 18) | (h[4] << 8)];
+        let mut tag = [0u8; 16];
+        let mut carry = 0u64;
+        for (i, bytes) in tag.chunks_mut(4).enumerate() {
+            let sum = words[i] as u64 + self.pad[i] as u64 + carry;
+            bytes.copy_from_slice(&(sum as u32).to_le_bytes());
+            carry = sum >> 32;
+        }
+        tag
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{block, open, seal, TAG_SIZE};
+
+    fn bytes(hex: &str) -> Vec<u8> {
+        let hex: String = hex.split_whitespace().collect();
+        (0..hex.len()).step_by(2).map(|at| u8::from_str_radix(&hex[at..at + 2], 16).unwrap()).collect()
+    }
+
+    fn key(hex: &str) -> [u8; 32] {
+        bytes(hex).try_into().unwrap()
+    }
+
+    fn nonce(hex: &str) -> [u8; 12] {
+        bytes(hex).try_into().unwrap()
+    }
+
+    const SUNSCREEN: &[u8] = b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+
+    // RFC 8439, section 2.3.2
+    #[test]
+    fn key_stream_block() {
+        let key = key("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f");
+        let expected = bytes(
+            "10f1e7e4d13b5915500fdd1fa32071c4c7d1f4c733c068030422aa9ac3d46c4e
+             d2826446079faa0914c2d705d98b02a2b5129cd1de164eb9cbd083e8a2503c4e",
+        );
+        assert_eq!(block(&key, &nonce("000000090000004a00000000"), 1).to_vec(), expected);
+    }
+
+    // RFC 8439, section 2.6.2: the Poly1305 key is the start of block 0
+    #[test]
+    fn one_time_key() {
+        let key = key("808182838485868788898a8b8c8d8e8f909192939495969798999a9b9c9d9e9f");
+        let expected = bytes("8ad5a08b905f81cc815040274ab29471a833b637e3fd0da508dbb8e2fdd1a646");
+        assert_eq!(block(&key, &nonce("000000000001020304050607"), 0)[..32].to_vec(), expected);
+    }
+
+    // RFC 8439, section 2.4.2 for the ciphertext, which seal starts at block 1 too
+    #[test]
+    fn encryption() {
+        let key = key("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f");
+        let expected = bytes(
+            "6e2e359a2568f98041ba0728dd0d6981e97e7aec1d4360c20a27afccfd9fae0b
+             f91b65c5524733ab8f593dabcd62b3571639d624e65152ab8f530c359f0861d8
+             07ca0dbf500d6a6156a38e088a22b65e52bc514d16ccf806818ce91ab7793736
+             5af90bbf74a35be6b40b8eedf2785e42874d",
+        );
+        let sealed = seal(&key, &nonce("000000000000004a00000000"), SUNSCREEN);
+        assert_eq!(sealed[..sealed.len() - TAG_SIZE].to_vec(), expected);
+    }
+
+    // The key and nonce of RFC 8439, section 2.8.2, without its associated data (which age
+    // never has); the tag is the one other implementations give for that
+    #[test]
+    fn seal_and_open() {
+        let key = key("808182838485868788898a8b8c8d8e8f909192939495969798999a9b9c9d9e9f");
+        let nonce = nonce("070000004041424344454647");
+        let sealed = seal(&key, &nonce, SUNSCREEN);
+        assert_eq!(sealed[sealed.len() - TAG_SIZE..].to_vec(), bytes("6a23a4681fd59456aea1d29f82477216"));
+        assert_eq!(open(&key, &nonce, &sealed).as_deref(), Some(SUNSCREEN));
+
+        // Any change to the ciphertext or the tag, or the wrong nonce, is refused
+        for at in [0, SUNSCREEN.len() - 1, sealed.len() - 1] {
+            let mut tampered = sealed.clone();
+            tampered[at] ^= 1;
+            assert_eq!(open(&key, &nonce, &tampered), None, "byte {} changed", at);
+        }
+        assert_eq!(open(&key, &[0; 12], &sealed), None);
+        assert_eq!(open(&key, &nonce, &sealed[..TAG_SIZE - 1]), None);
+        assert_eq!(open(&key, &nonce, &seal(&key, &nonce, b"")).as_deref(), Some(&b""[..]));
+    }
+}
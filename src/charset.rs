@@ -0,0 +1,78 @@
+// Character sets for names: how source file names are read, how identifiers are recorded, and
+// how the reader decodes the identifiers (and Rock Ridge names) of an image
+//
+// Images from old systems often record names in ISO-8859-1 or in the DOS code page 437, which
+// come out as mojibake when taken for UTF-8. Characters a set can't represent are recorded as
+// "_".
+
+use std::ffi::OsStr;
+use std::io::{self, ErrorKind};
+use std::sync::OnceLock;
+
+// Code page 437 characters 0x80-0xFF; the lower half is ASCII
+const CP437_HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å', 'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ', 'á', 'í', 'ó', 'ú', 'ñ',
+    'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»', '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐', '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔',
+    '╩', '╦', '╠', '═', '╬', '╧', '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀', 'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε',
+    '∩', '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{A0}',
+];
+
+static IMAGE_NAMES: OnceLock<Charset> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Charset {
+    Utf8,
+    Latin1, // ISO-8859-1
+    Cp437,
+}
+
+impl Charset {
+    pub fn parse(text: &str) -> io::Result<Charset> {
+        match text.to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Ok(Charset::Utf8),
+            "iso-8859-1" | "iso8859-1" | "latin1" => Ok(Charset::Latin1),
+            "cp437" | "ibm437" => Ok(Charset::Cp437),
+            _ => Err(io::Error::new(ErrorKind::InvalidInput, format!("unknown character set: {} (expected utf-8, iso-8859-1 or cp437)", text))),
+        }
+    }
+
+    pub fn decode(self, bytes: &[u8]) -> String {
+        match self {
+            Charset::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+            Charset::Latin1 => bytes.iter().map(|&byte| byte as char).collect(),
+            Charset::Cp437 => bytes.iter().map(|&byte| if byte < 0x80 { byte as char } else { CP437_HIGH[byte as usize - 0x80] }).collect(),
+        }
+    }
+
+    pub fn encode(self, text: &str) -> Vec<u8> {
+        match self {
+            Charset::Utf8 => text.as_bytes().to_vec(),
+            Charset::Latin1 => text.chars().map(|c| u8::try_from(c).unwrap_or(b'_')).collect(),
+            Charset::Cp437 => text
+                .chars()
+                .map(|c| match c {
+                    _ if c.is_ascii() => c as u8,
+                    _ => CP437_HIGH.iter().position(|&high| high == c).map_or(b'_', |at| 0x80 + at as u8),
+                })
+                .collect(),
+        }
+    }
+
+    // A file name as found in a source directory; only Unix names are bytes that need decoding
+    pub fn decode_os(self, name: &OsStr) -> String {
+        #[cfg(unix)]
+        return self.decode(std::os::unix::ffi::OsStrExt::as_bytes(name));
+
+        #[cfg(not(unix))]
+        name.to_string_lossy().into_owned()
+    }
+}
+
+// The character set names in images are read in, for all readers of this run
+pub fn set_image_names(charset: Charset) {
+    let _ = IMAGE_NAMES.set(charset);
+}
+
+pub fn image_names() -> Charset {
+    IMAGE_NAMES.get().copied().unwrap_or(Charset::Utf8)
+}
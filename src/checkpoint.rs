@@ -0,0 +1,156 @@
+// Checkpoint summaries for long builds (--checkpoint-every INTERVAL, --status-file FILE): every
+// so often a line with the files and bytes written so far, the rate, the time left and the
+// warnings until then, such as
+//
+//     Checkpoint: 1204 of 5310 files, 1.2 GiB of 4.0 GiB, 35.1 MiB/s, 1:21:40 left, 2 warnings
+//
+// is printed and written to the status file, with the time and whether the build is running,
+// done or failed in front. makeiso run keeps one per profile in the state directory
+// (jobs/<profile>.status, see daemon.rs), every minute unless told otherwise, and makeiso
+// status <profile> shows it from another terminal; makeiso status <file> reads any other.
+
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use super::browse::human_size;
+use super::daemon;
+use super::{events, time_text, unix_time, ImageOptions};
+
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(60); // With a status file and no --checkpoint-every
+
+#[derive(Debug)]
+pub struct Checkpoint {
+    every: Duration,
+    status_file: Option<PathBuf>,
+    started: Instant,
+    last: Instant,
+}
+
+// An interval: seconds, or a number of seconds, minutes or hours ("90", "90s", "5m", "2h")
+pub fn parse_interval(text: &str) -> io::Result<Duration> {
+    let invalid = || io::Error::new(ErrorKind::InvalidInput, format!("invalid --checkpoint-every: {} (expected seconds, or a number followed by s, m or h)", text));
+    let (number, unit) = match text.strip_suffix(['s', 'm', 'h']) {
+        Some(number) => (number, &text[number.len()..]),
+        None => (text, "s"),
+    };
+    let count: u64 = number.parse().map_err(|_| invalid())?;
+    let seconds = count * match unit {
+        "h" => 3600,
+        "m" => 60,
+        _ => 1,
+    };
+    if seconds == 0 {
+        return Err(invalid());
+    }
+    Ok(Duration::from_secs(seconds))
+}
+
+// Where makeiso run keeps the status of a profile's build
+pub fn job_status_file(job: &str) -> Option<PathBuf> {
+    Some(daemon::state_dir()?.join("jobs").join(format!("{}.status", job)))
+}
+
+impl Checkpoint {
+    // Checkpoints as the options ask for them, if they do
+    pub fn new(options: &ImageOptions) -> Option<Checkpoint> {
+        if options.checkpoint_every.is_none() && options.status_file.is_none() {
+            return None;
+        }
+        if let Some(path) = &options.status_file {
+            write_status(path, "running", "writing the image");
+        }
+        let now = Instant::now();
+        Some(Checkpoint {
+            every: options.checkpoint_every.unwrap_or(DEFAULT_INTERVAL),
+            status_file: options.status_file.clone(),
+            started: now,
+            last: now,
+        })
+    }
+
+    // Report the counts if the last checkpoint is an interval ago
+    pub fn tick(&mut self, files: (u64, Option<u64>), bytes: (u64, Option<u64>)) {
+        if self.last.elapsed() < self.every {
+            return;
+        }
+        self.last = Instant::now();
+        let seconds = self.started.elapsed().as_secs_f64();
+        let rate = bytes.0 as f64 / seconds.max(0.001);
+        let summary = summary(files, bytes, rate);
+        if events::enabled() {
+            events::emit(
+                "checkpoint",
+                &[
+                    ("files", files.0.to_string()),
+                    ("total_files", files.1.map_or("null".to_string(), |total| total.to_string())),
+                    ("bytes", bytes.0.to_string()),
+                    ("total_bytes", bytes.1.map_or("null".to_string(), |total| total.to_string())),
+                    ("rate", format!("{:.0}", rate)),
+                    ("warnings", events::warnings().to_string()),
+                ],
+            );
+        } else {
+            println!("Checkpoint: {}", summary);
+        }
+        if let Some(path) = &self.status_file {
+            write_status(path, "running", &summary);
+        }
+    }
+}
+
+// The line itself: files and bytes done of the totals (when known), rate, time left, warnings
+fn summary(files: (u64, Option<u64>), bytes: (u64, Option<u64>), rate: f64) -> String {
+    let mut parts = vec![
+        match files.1 {
+            Some(total) => format!("{} of {} files", files.0, total),
+            None => format!("{} files", files.0),
+        },
+        match bytes.1 {
+            Some(total) => format!("{} of {}", human_size(bytes.0), human_size(total)),
+            None => human_size(bytes.0),
+        },
+        format!("{}/s", human_size(rate as u64)),
+    ];
+    if let Some(total) = bytes.1.filter(|_| rate >= 1.0) {
+        let left = (total.saturating_sub(bytes.0) as f64 / rate) as u64;
+        parts.push(format!("{}:{:02}:{:02} left", left / 3600, left / 60 % 60, left % 60));
+    }
+    parts.push(warnings());
+    parts.join(", ")
+}
+
+fn warnings() -> String {
+    let warnings = events::warnings();
+    format!("{} warning{}", warnings, if warnings == 1 { "" } else { "s" })
+}
+
+// Put how the build ended in the status file, after the last checkpoint
+pub fn finish(options: &ImageOptions, result: &io::Result<()>) {
+    let Some(path) = &options.status_file else {
+        return;
+    };
+    match result {
+        Ok(()) => write_status(path, "done", &warnings()),
+        Err(e) => write_status(path, "failed", &e.to_string()),
+    }
+}
+
+// Replace the status file in one go, so a reader never sees half a line; a status file that
+// can't be written is worth a warning but not the build
+fn write_status(path: &Path, state: &str, summary: &str) {
+    let line = format!("{}  {:<7}  {}\n", time_text(unix_time(SystemTime::now())), state, summary);
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".part");
+    let written = path.parent().filter(|parent| !parent.as_os_str().is_empty()).map_or(Ok(()), fs::create_dir_all).and_then(|()| fs::write(&partial, line)).and_then(|()| fs::rename(&partial, path));
+    if let Err(e) = written {
+        eprintln!("Could not write the status file {}: {}", path.display(), e);
+    }
+}
+
+// The last line a status file was given
+pub fn read_status(path: &Path) -> io::Result<String> {
+    let text = fs::read_to_string(path).map_err(|e| io::Error::new(e.kind(), format!("could not read {}: {}", path.display(), e)))?;
+    Ok(text.trim_end().to_string())
+}
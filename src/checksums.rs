@@ -0,0 +1,55 @@
+// The SHA-256 manifest at the root of the image (--checksums): SHA256SUMS lists every file of
+// the sources with its SHA-256, in the format sha256sum writes, so `sha256sum -c` checks a
+// mounted copy and makeiso extract checks each file it writes against it (see extract.rs).
+//
+// The files are read once more for it, after the scan and before the layout; those --dedup
+// hashed already aren't. Files makeiso generates itself (TRANS.TBL, the .makeiso directory,
+// encrypted files and the like) are left out, as are links and special files.
+
+use std::io::{self, ErrorKind, Read};
+
+use super::backend::SourceProvider;
+use super::sha256::{self, Sha256};
+use super::{entry_name, original_path, Entry, ImageTree};
+
+pub const MANIFEST: &str = "SHA256SUMS";
+
+// Hash the files of a scanned tree and add the manifest to its root
+pub fn add(tree: &mut ImageTree, source: &dyn SourceProvider) -> io::Result<()> {
+    if tree.dirs[0].entries.iter().any(|entry| entry_name(tree, entry) == MANIFEST) {
+        return Err(io::Error::new(ErrorKind::InvalidInput, format!("the sources already have a /{} (leave it out or drop --checksums)", MANIFEST)));
+    }
+    let mut files = Vec::new();
+    for (index, dir) in tree.dirs.iter().enumerate() {
+        for (entry, item) in dir.entries.iter().enumerate() {
+            if let Entry::File(file) = item {
+                if tree.generated((index, entry)).is_none() {
+                    files.push((format!("{}{}", original_path(tree, index), file.name), file.source.clone()));
+                }
+            }
+        }
+    }
+
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut lines = Vec::with_capacity(files.len());
+    for (path, source_path) in files {
+        if !tree.digests.contains_key(&source_path) {
+            let mut sha = Sha256::new();
+            let mut opened = source.open(&source_path).map_err(|e| io::Error::new(e.kind(), format!("could not read {} to checksum it: {}", source_path.display(), e)))?;
+            loop {
+                let read = opened.read(&mut buffer)?;
+                if read == 0 {
+                    break;
+                }
+                sha.update(&buffer[..read]);
+            }
+            tree.digests.insert(source_path.clone(), sha.finish());
+        }
+        lines.push((path.trim_start_matches('/').to_string(), sha256::hex(&tree.digests[&source_path])));
+    }
+    // By path, so the order the scan found them in doesn't matter
+    lines.sort();
+    let text: String = lines.iter().map(|(path, digest)| format!("{}  {}\n", digest, path)).collect();
+    tree.add_generated(0, MANIFEST, text.into_bytes());
+    Ok(())
+}
@@ -0,0 +1,428 @@
+// Comparison of two images at the file system level: which files were added, removed or
+// changed (contents and attributes), and how the El Torito boot setup differs. Meant for
+// auditing remastered images, where a byte comparison says nothing useful.
+//
+// makeiso verify-restore compares a directory the image was restored into against the image
+// the same way, by type, size, SHA-256 of the contents and modification time (and with
+// --permissions, mode and owner), so a restore can be audited long after it was made. What
+// is missing from the directory, what it has besides, and what drifted are listed, and any of
+// them makes the command fail.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Read};
+use std::path::Path;
+
+use super::json;
+use super::reader::{open_image, BootEntry, ImageSource, IsoEntry, IsoReader};
+use super::sha256::{self, Sha256};
+use super::{time_text, unix_time};
+
+const COMPARE_CHUNK_SIZE: usize = 1024 * 1024;
+
+// One attribute that differs between the two images
+struct Change {
+    field: &'static str,
+    old: String,
+    new: String,
+}
+
+impl Change {
+    fn new(field: &'static str, old: impl ToString, new: impl ToString) -> Change {
+        Change { field, old: old.to_string(), new: new.to_string() }
+    }
+}
+
+#[derive(Default)]
+struct Differences {
+    added: Vec<String>,
+    removed: Vec<String>,
+    changed: Vec<(String, Vec<Change>)>,
+    volume: Vec<Change>, // Volume identifier and boot setup
+}
+
+impl Differences {
+    fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty() && self.volume.is_empty()
+    }
+}
+
+fn kind(entry: &IsoEntry) -> &'static str {
+    if entry.is_directory {
+        "directory"
+    } else if entry.symlink.is_some() {
+        "symlink"
+    } else {
+        "file"
+    }
+}
+
+// Whether two files of the same size hold the same bytes
+fn same_contents(first: &mut IsoReader<ImageSource>, a: &IsoEntry, second: &mut IsoReader<ImageSource>, b: &IsoEntry) -> io::Result<bool> {
+    let (mut buffer_a, mut buffer_b) = (vec![0u8; COMPARE_CHUNK_SIZE], vec![0u8; COMPARE_CHUNK_SIZE]);
+    let mut offset = 0;
+    while offset < a.size as u64 {
+        let len = first.read_file_at(a, offset, &mut buffer_a)?;
+        second.read_file_at(b, offset, &mut buffer_b[..len])?;
+        if buffer_a[..len] != buffer_b[..len] {
+            return Ok(false);
+        }
+        offset += len as u64;
+    }
+    Ok(true)
+}
+
+fn entry_changes(first: &mut IsoReader<ImageSource>, a: &IsoEntry, second: &mut IsoReader<ImageSource>, b: &IsoEntry) -> io::Result<Vec<Change>> {
+    let mut changes = Vec::new();
+    if kind(a) != kind(b) {
+        changes.push(Change::new("type", kind(a), kind(b)));
+        return Ok(changes);
+    }
+    if !a.is_directory && a.symlink.is_none() {
+        if a.size != b.size {
+            changes.push(Change::new("size", a.size, b.size));
+        } else if !same_contents(first, a, second, b)? {
+            changes.push(Change::new("contents", "", ""));
+        }
+    }
+    if a.symlink != b.symlink {
+        changes.push(Change::new("target", a.symlink.as_deref().unwrap_or(""), b.symlink.as_deref().unwrap_or("")));
+    }
+    if a.modified != b.modified {
+        changes.push(Change::new("modified", time_text(a.modified), time_text(b.modified)));
+    }
+    if a.mode != b.mode {
+        changes.push(Change::new("mode", format!("{:o}", a.mode), format!("{:o}", b.mode)));
+    }
+    if (a.uid, a.gid) != (b.uid, b.gid) {
+        changes.push(Change::new("owner", format!("{}:{}", a.uid, a.gid), format!("{}:{}", b.uid, b.gid)));
+    }
+    Ok(changes)
+}
+
+fn describe_boot(entry: &BootEntry) -> String {
+    let platform = match entry.platform {
+        0 => "x86".to_string(),
+        1 => "PowerPC".to_string(),
+        2 => "Mac".to_string(),
+        0xEF => "EFI".to_string(),
+        other => format!("platform {:#04x}", other),
+    };
+    let emulation = match entry.emulation {
+        0 => "no emulation",
+        1 => "1.2M floppy",
+        2 => "1.44M floppy",
+        3 => "2.88M floppy",
+        4 => "hard disk",
+        _ => "unknown emulation",
+    };
+    format!(
+        "{}, {}, {}{} sectors at segment {:#06x}",
+        platform,
+        emulation,
+        if entry.bootable { "" } else { "not bootable, " },
+        entry.sector_count,
+        entry.load_segment
+    )
+}
+
+// Boot entries are compared by position; their images by contents, since remastering moves them
+fn boot_changes(first: &mut IsoReader<ImageSource>, second: &mut IsoReader<ImageSource>) -> io::Result<Vec<Change>> {
+    let (entries_a, entries_b) = (first.boot_entries()?, second.boot_entries()?);
+    let mut changes = Vec::new();
+    for index in 0..entries_a.len().max(entries_b.len()) {
+        let field = "boot entry";
+        match (entries_a.get(index), entries_b.get(index)) {
+            (Some(a), None) => changes.push(Change::new(field, describe_boot(a), "")),
+            (None, Some(b)) => changes.push(Change::new(field, "", describe_boot(b))),
+            (Some(a), Some(b)) => {
+                if describe_boot(a) != describe_boot(b) {
+                    changes.push(Change::new(field, describe_boot(a), describe_boot(b)));
+                } else if first.read_boot_image(a)? != second.read_boot_image(b)? {
+                    changes.push(Change::new("boot image", describe_boot(a), "contents differ"));
+                }
+            }
+            (None, None) => {}
+        }
+    }
+    Ok(changes)
+}
+
+fn compare_images(first: &Path, second: &Path) -> io::Result<Differences> {
+    let (mut reader_a, mut reader_b) = (open_image(first)?, open_image(second)?);
+    let entries_a: BTreeMap<String, IsoEntry> = reader_a.read_tree()?.into_iter().map(|entry| (entry.path.clone(), entry)).collect();
+    let entries_b: BTreeMap<String, IsoEntry> = reader_b.read_tree()?.into_iter().map(|entry| (entry.path.clone(), entry)).collect();
+
+    let mut differences = Differences::default();
+    for (path, a) in &entries_a {
+        match entries_b.get(path) {
+            None => differences.removed.push(path.clone()),
+            Some(b) => {
+                let changes = entry_changes(&mut reader_a, a, &mut reader_b, b)?;
+                if !changes.is_empty() {
+                    differences.changed.push((path.clone(), changes));
+                }
+            }
+        }
+    }
+    differences.added = entries_b.keys().filter(|path| !entries_a.contains_key(*path)).cloned().collect();
+    if reader_a.pvd.volume_id != reader_b.pvd.volume_id {
+        differences.volume.push(Change::new("volume id", &reader_a.pvd.volume_id, &reader_b.pvd.volume_id));
+    }
+    differences.volume.extend(boot_changes(&mut reader_a, &mut reader_b)?);
+    Ok(differences)
+}
+
+fn changes_json(changes: &[Change]) -> String {
+    let items: Vec<String> = changes
+        .iter()
+        .map(|change| format!("{{\"field\":{},\"old\":{},\"new\":{}}}", json::string(change.field), json::string(&change.old), json::string(&change.new)))
+        .collect();
+    format!("[{}]", items.join(","))
+}
+
+fn print_json(differences: &Differences) {
+    let paths = |paths: &[String]| paths.iter().map(|path| json::string(path)).collect::<Vec<_>>().join(",");
+    let changed: Vec<String> = differences
+        .changed
+        .iter()
+        .map(|(path, changes)| format!("{{\"path\":{},\"changes\":{}}}", json::string(path), changes_json(changes)))
+        .collect();
+    println!(
+        "{{\"added\":[{}],\"removed\":[{}],\"changed\":[{}],\"volume\":{}}}",
+        paths(&differences.added),
+        paths(&differences.removed),
+        changed.join(","),
+        changes_json(&differences.volume)
+    );
+}
+
+fn change_text(change: &Change) -> String {
+    match change.field {
+        "contents" => "contents differ".to_string(),
+        _ => format!("{} {} -> {}", change.field, if change.old.is_empty() { "(none)" } else { &change.old }, if change.new.is_empty() { "(none)" } else { &change.new }),
+    }
+}
+
+fn print_text(differences: &Differences) {
+    for path in &differences.added {
+        println!("+ {}", path);
+    }
+    for path in &differences.removed {
+        println!("- {}", path);
+    }
+    for (path, changes) in &differences.changed {
+        println!("~ {}: {}", path, changes.iter().map(change_text).collect::<Vec<_>>().join(", "));
+    }
+    for change in &differences.volume {
+        println!("! {}", change_text(change));
+    }
+    println!(
+        "{} added, {} removed, {} changed{}",
+        differences.added.len(),
+        differences.removed.len(),
+        differences.changed.len(),
+        if differences.volume.is_empty() { "" } else { ", volume or boot setup differs" }
+    );
+}
+
+// makeiso cmp [--format text|json] <a.iso> <b.iso>: fails when anything differs, so the exit
+// status says whether the images match, as cmp(1)'s does
+pub fn cmp(args: &[String]) -> io::Result<()> {
+    let mut format = "text".to_string();
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => format = args.next().cloned().unwrap_or_default(),
+            _ => match arg.strip_prefix("--format=") {
+                Some(value) => format = value.to_string(),
+                None => paths.push(arg),
+            },
+        }
+    }
+    let [first, second] = paths[..] else {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "Usage: makeiso cmp [--format text|json] <a.iso> <b.iso>"));
+    };
+
+    if format != "text" && format != "json" {
+        return Err(io::Error::new(ErrorKind::InvalidInput, format!("Unknown --format {} (expected text or json)", format)));
+    }
+
+    let differences = compare_images(Path::new(first), Path::new(second))?;
+    if format == "json" {
+        print_json(&differences);
+    } else {
+        print_text(&differences);
+    }
+    if differences.is_empty() {
+        return Ok(());
+    }
+    Err(io::Error::other(format!("{} and {} differ", first, second)))
+}
+
+// What a restored tree lacks, has besides, and has otherwise than the image
+#[derive(Default)]
+struct Drift {
+    checked: usize,
+    missing: Vec<String>,
+    extra: Vec<String>,
+    drifted: Vec<(String, Vec<Change>)>,
+}
+
+fn file_kind(metadata: &fs::Metadata) -> &'static str {
+    if metadata.is_dir() {
+        "directory"
+    } else if metadata.file_type().is_symlink() {
+        "symlink"
+    } else {
+        "file"
+    }
+}
+
+// SHA-256 of a file in the image and of its restored copy
+fn digests(reader: &mut IsoReader<ImageSource>, entry: &IsoEntry, restored: &Path) -> io::Result<(String, String)> {
+    let mut buffer = vec![0u8; COMPARE_CHUNK_SIZE];
+    let (mut image, mut copy) = (Sha256::new(), Sha256::new());
+    let mut offset = 0;
+    while offset < entry.size as u64 {
+        let len = reader.read_file_at(entry, offset, &mut buffer)?;
+        image.update(&buffer[..len]);
+        offset += len as u64;
+    }
+    let mut file = File::open(restored)?;
+    loop {
+        let len = file.read(&mut buffer)?;
+        if len == 0 {
+            break;
+        }
+        copy.update(&buffer[..len]);
+    }
+    Ok((sha256::hex(&image.finish()), sha256::hex(&copy.finish())))
+}
+
+// How a restored entry differs from the image's; modes and owners only with permissions, as
+// makeiso extract doesn't restore them
+fn restored_changes(reader: &mut IsoReader<ImageSource>, entry: &IsoEntry, restored: &Path, metadata: &fs::Metadata, permissions: bool) -> io::Result<Vec<Change>> {
+    let mut changes = Vec::new();
+    if kind(entry) != file_kind(metadata) {
+        changes.push(Change::new("type", kind(entry), file_kind(metadata)));
+        return Ok(changes);
+    }
+    if let Some(link) = &entry.symlink {
+        let target = fs::read_link(restored)?.to_string_lossy().into_owned();
+        if *link != target {
+            changes.push(Change::new("target", link, target));
+        }
+        return Ok(changes); // Link times and modes aren't restored anywhere
+    }
+    if !entry.is_directory {
+        if entry.size as u64 != metadata.len() {
+            changes.push(Change::new("size", entry.size, metadata.len()));
+        } else {
+            let (image, copy) = digests(reader, entry, restored)?;
+            if image != copy {
+                changes.push(Change::new("contents", image, copy));
+            }
+        }
+    }
+    let modified = metadata.modified().map(unix_time)?;
+    if entry.modified != modified {
+        changes.push(Change::new("modified", time_text(entry.modified), time_text(modified)));
+    }
+    #[cfg(unix)]
+    if permissions {
+        use std::os::unix::fs::MetadataExt;
+        if entry.mode & 0o7777 != metadata.mode() & 0o7777 {
+            changes.push(Change::new("mode", format!("{:o}", entry.mode & 0o7777), format!("{:o}", metadata.mode() & 0o7777)));
+        }
+        if (entry.uid, entry.gid) != (metadata.uid(), metadata.gid()) {
+            changes.push(Change::new("owner", format!("{}:{}", entry.uid, entry.gid), format!("{}:{}", metadata.uid(), metadata.gid())));
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = permissions;
+    Ok(changes)
+}
+
+// Image paths of everything below a restored directory, with what the file system says of them
+fn walk_restored(directory: &Path, path: &str, found: &mut BTreeMap<String, fs::Metadata>) -> io::Result<()> {
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let path = format!("{}/{}", path, entry.file_name().to_string_lossy());
+        let metadata = fs::symlink_metadata(entry.path())?;
+        if metadata.is_dir() {
+            walk_restored(&entry.path(), &path, found)?;
+        }
+        found.insert(path, metadata);
+    }
+    Ok(())
+}
+
+fn check_restore(image: &Path, restored: &Path, permissions: bool) -> io::Result<Drift> {
+    let mut reader = open_image(image)?;
+    let entries: BTreeMap<String, IsoEntry> = reader.read_tree()?.into_iter().skip(1).filter(|entry| Some(entry.extent) != reader.boot_catalog || entry.is_directory).map(|entry| (entry.path.clone(), entry)).collect();
+    let mut found = BTreeMap::new();
+    walk_restored(restored, "", &mut found).map_err(|e| io::Error::new(e.kind(), format!("could not read {}: {}", restored.display(), e)))?;
+
+    let mut drift = Drift { checked: entries.len(), ..Drift::default() };
+    for (path, entry) in &entries {
+        let Some(metadata) = found.get(path) else {
+            drift.missing.push(path.clone());
+            continue;
+        };
+        let target = restored.join(path.trim_start_matches('/'));
+        let changes = restored_changes(&mut reader, entry, &target, metadata, permissions).map_err(|e| io::Error::new(e.kind(), format!("could not check {}: {}", target.display(), e)))?;
+        if !changes.is_empty() {
+            drift.drifted.push((path.clone(), changes));
+        }
+    }
+    drift.extra = found.keys().filter(|path| !entries.contains_key(*path)).cloned().collect();
+    Ok(drift)
+}
+
+// makeiso verify-restore [--permissions] [--format text|json] <image.iso> <restored directory>
+pub fn verify_restore(args: &[String]) -> io::Result<()> {
+    let usage = || io::Error::new(ErrorKind::InvalidInput, "Usage: makeiso verify-restore [--permissions] [--format text|json] <image.iso> <restored directory>");
+    let (mut format, mut permissions) = ("text".to_string(), false);
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => format = args.next().cloned().ok_or_else(usage)?,
+            "--permissions" => permissions = true,
+            _ => match arg.strip_prefix("--format=") {
+                Some(value) => format = value.to_string(),
+                None => paths.push(arg),
+            },
+        }
+    }
+    let [image, restored] = paths[..] else {
+        return Err(usage());
+    };
+    if format != "text" && format != "json" {
+        return Err(io::Error::new(ErrorKind::InvalidInput, format!("Unknown --format {} (expected text or json)", format)));
+    }
+
+    let drift = check_restore(Path::new(image), Path::new(restored), permissions)?;
+    if format == "json" {
+        let paths = |paths: &[String]| paths.iter().map(|path| json::string(path)).collect::<Vec<_>>().join(",");
+        let drifted: Vec<String> = drift.drifted.iter().map(|(path, changes)| format!("{{\"path\":{},\"changes\":{}}}", json::string(path), changes_json(changes))).collect();
+        println!("{{\"checked\":{},\"missing\":[{}],\"extra\":[{}],\"drifted\":[{}]}}", drift.checked, paths(&drift.missing), paths(&drift.extra), drifted.join(","));
+    } else {
+        for path in &drift.missing {
+            println!("- {}", path);
+        }
+        for path in &drift.extra {
+            println!("+ {}", path);
+        }
+        for (path, changes) in &drift.drifted {
+            println!("~ {}: {}", path, changes.iter().map(change_text).collect::<Vec<_>>().join(", "));
+        }
+        println!("{} entries checked: {} missing, {} extra, {} drifted", drift.checked, drift.missing.len(), drift.extra.len(), drift.drifted.len());
+    }
+    if drift.missing.is_empty() && drift.extra.is_empty() && drift.drifted.is_empty() {
+        return Ok(());
+    }
+    Err(io::Error::other(format!("{} does not match {}", restored, image)))
+}
@@ -0,0 +1,354 @@
+// Shell completion scripts (makeiso completions bash|zsh|fish|powershell)
+//
+// Every script is generated from the tables below, which list each subcommand with its flags
+// and the values those flags take; a new flag only has to be added here to be completed
+// everywhere. Paths are left to the shell's own file completion.
+
+use std::io::{self, ErrorKind};
+
+use Takes::{Nothing, OneOf, Value};
+
+const USAGE: &str = "Usage: makeiso completions <bash|zsh|fish|powershell>";
+
+// What follows a flag
+enum Takes {
+    Nothing,
+    Value,                          // A path, a size or some other free-form value
+    OneOf(&'static [&'static str]), // One of a fixed set of words
+}
+
+type Flag = (&'static str, Takes);
+
+const CHARSETS: &[&str] = &["utf-8", "iso-8859-1", "cp437"];
+
+// Flags for building an image, which from-tar, from-zip and estimate take too
+const BUILD_FLAGS: &[Flag] = &[
+    ("--mmap", Nothing),
+    ("--read-workers", Value),
+    ("--pipeline-depth", Value),
+    ("--file-order", OneOf(&["directory", "size"])),
+    ("--group-small", Value),
+    ("--sort-weight", Value),
+    ("--sort-file", Value),
+    ("--dedup", OneOf(&["content", "none"])),
+    ("--dedup-report", Nothing),
+    ("--checksums", Nothing),
+    ("--embed-metadata", Nothing),
+    ("--no-embed-metadata", Nothing),
+    ("--align", Value),
+    ("--metadata", OneOf(&["first", "last"])),
+    ("--block-map", Value),
+    ("--block-map-format", OneOf(&["text", "json", "bmap"])),
+    ("--media", OneOf(&["cd74", "cd80", "dvd5", "dvd9", "bd25", "bd50", "custom:"])),
+    ("--source-date-epoch", Value),
+    ("--reproducible", Nothing),
+    ("--volume-id", Value),
+    ("--preparer", Value),
+    ("--application-use", Value),
+    ("--uuid", Value),
+    ("--input-charset", OneOf(CHARSETS)),
+    ("--normalize", OneOf(&["nfc", "nfd", "none"])),
+    ("--output-charset", OneOf(CHARSETS)),
+    ("--trans-tbl", Nothing),
+    ("--iso-level", OneOf(&["4"])),
+    ("--hide", Value),
+    ("--associated", Value),
+    ("--xattrs", Nothing),
+    ("--win-metadata", Nothing),
+    ("--apple", Nothing),
+    ("--dvd-video", Nothing),
+    ("--bwlimit", Value),
+    ("--ionice", OneOf(&["idle", "best-effort"])),
+    ("--output-hash", OneOf(&["sha256", "sha1", "sha256,sha1"])),
+    ("--sign", Value),
+    ("--zsync", Nothing),
+    ("--zsync-url", Value),
+    ("--upload", Value),
+    ("--catalog-db", Value),
+    ("--pre-hook", Value),
+    ("--post-hook", Value),
+    ("--elevate", Value),
+    ("--report-denied", Value),
+    ("--checkpoint-every", Value),
+    ("--status-file", Value),
+    ("--encrypt", Value),
+    ("--encrypt-glob", Value),
+    ("--passphrase-file", Value),
+    ("--boot-image", Value),
+    ("--efi-boot", Value),
+    ("--boot-load-size", Value),
+    ("--boot-info-table", Nothing),
+    ("--grub2-boot-info", Nothing),
+    ("--isohybrid-mbr", Value),
+    ("--isohybrid-gpt-basdat", Nothing),
+    ("--isohybrid-apm-hfsplus", Nothing),
+    ("--append-partition", Value),
+    ("--system-area", Value),
+    ("--sparc-boot", Value),
+    ("--chrp-boot", Nothing),
+    ("--prep-boot", Value),
+    ("--pad", Nothing),
+    ("--deep-dirs", OneOf(&["error", "relocate"])),
+    ("--if-changed", OneOf(&["pad", "reread", "fail"])),
+    ("--exclude", Value),
+    ("--use-gitignore", Nothing),
+    ("--stdin-name", Value),
+    ("--symlinks", OneOf(&["follow", "skip", "store"])),
+    ("--max-file-size", Value),
+    ("--min-mtime", Value),
+    ("--max-mtime", Value),
+    ("--type", Value),
+    ("--one-file-system", Nothing),
+    ("--max-depth", Value),
+    ("-x", Nothing),
+    ("--json-events", Nothing),
+    ("--force", Nothing),
+    ("--atomic", Nothing),
+    ("--keep-partial", Nothing),
+    ("--single-pass", Nothing),
+    ("--config", Value),
+];
+
+// Flags every command that reads an image takes
+const READ_FLAGS: &[Flag] = &[("--input-charset", OneOf(CHARSETS)), ("--trust", OneOf(&["le", "be", "strict"])), ("--normalize", OneOf(&["nfc", "nfd", "none"])), ("--icase", Nothing), ("--case-sensitive", Nothing), ("--identity", Value), ("--passphrase-file", Value)];
+
+// Subcommands, whether they read an image, and their own flags
+const SUBCOMMANDS: &[(&str, bool, &[Flag])] = &[
+    ("ls", true, &[("-l", Nothing)]),
+    (
+        "extract",
+        true,
+        &[
+            ("--retries", Value),
+            ("--salvage", Nothing),
+            ("--fill", OneOf(&["zero", "marker"])),
+            ("--report", Value),
+            ("--xattrs", Nothing),
+            ("--win-metadata", Nothing),
+            ("--include", Value),
+            ("--exclude", Value),
+            ("--overwrite", OneOf(&["always", "never", "newer", "prompt"])),
+            ("--keep-existing", Nothing),
+            ("--workers", Value),
+            ("--ordered", Nothing),
+        ],
+    ),
+    ("mount", true, &[]),
+    ("shell", true, &[("--fuse", Nothing)]),
+    ("cmp", true, &[("--format", OneOf(&["text", "json"]))]),
+    ("verify", true, &[]),
+    ("repair", true, &[("--force", Nothing)]),
+    ("sector", true, &[("--hex", Nothing), ("--raw", Nothing)]),
+    ("whatis", true, &[("--offset", Value)]),
+    ("block-map", true, &[("--format", OneOf(&["text", "json", "bmap"]))]),
+    ("zsync", true, &[("--url", Value), ("-o", Value), ("--output", Value)]),
+    ("which-image", false, &[("--catalog-db", Value), ("--file", Value), ("--newer", Value), ("--older", Value)]),
+    (
+        "prune",
+        false,
+        &[
+            ("--target", Value),
+            ("--keep-last", Value),
+            ("--keep-daily", Value),
+            ("--keep-weekly", Value),
+            ("--keep-monthly", Value),
+            ("--keep-yearly", Value),
+            ("--demote-to", Value),
+            ("--catalog-db", Value),
+            ("--dry-run", Nothing),
+        ],
+    ),
+    ("verify-restore", true, &[("--format", OneOf(&["text", "json"])), ("--permissions", Nothing)]),
+    ("catalog", true, &[("--output", Value), ("--hash", OneOf(&["sha256"]))]),
+    (
+        "find",
+        true,
+        &[
+            ("--name", Value),
+            ("--path", Value),
+            ("--type", OneOf(&["f", "d", "l"])),
+            ("--newer-than", Value),
+            ("--older-than", Value),
+            ("--larger", Value),
+            ("--smaller", Value),
+        ],
+    ),
+    ("grep", true, &[("-i", Nothing), ("-l", Nothing)]),
+    ("cat", true, &[]),
+    ("browse", true, &[]),
+    ("to-tar", true, &[]),
+    ("to-zip", true, &[]),
+    ("from-tar", false, BUILD_FLAGS),
+    ("from-zip", false, BUILD_FLAGS),
+    ("estimate", false, BUILD_FLAGS),
+    ("merge", false, &[("-o", Value), ("--output", Value), ("--conflict", OneOf(&["newest", "error", "rename"]))]),
+    ("run", false, BUILD_FLAGS),
+    ("daemon", false, &[("--config", Value), ("--metrics-listen", Value), ("--metrics-file", Value)]),
+    ("status", false, &[("--config", Value), ("--metrics", Nothing)]),
+    ("serve", false, &[("--listen", Value), ("--config", Value), ("--token-file", Value)]),
+    ("verify-sig", false, &[]),
+    ("bin-to-iso", false, &[]),
+    ("audio", false, &[("--data", Value), ("--tracks", Value), ("--cue", Value), ("--bin", Value)]),
+    ("completions", false, &[]),
+];
+
+const SHELLS: &[&str] = &["bash", "zsh", "fish", "powershell"];
+
+// Whether a build flag takes a value, None when there is no such flag
+pub fn build_flag_takes_value(flag: &str) -> Option<bool> {
+    BUILD_FLAGS.iter().find(|(name, _)| *name == flag).map(|(_, takes)| !matches!(takes, Nothing))
+}
+
+// All the flags a subcommand takes (the build flags outside any subcommand)
+fn flags(subcommand: Option<&str>) -> Vec<&'static Flag> {
+    let Some((_, reads, own)) = SUBCOMMANDS.iter().find(|(name, _, _)| Some(*name) == subcommand) else {
+        return BUILD_FLAGS.iter().collect();
+    };
+    let mut flags: Vec<_> = own.iter().collect();
+    if *reads {
+        flags.extend(READ_FLAGS.iter().filter(|(flag, _)| !own.iter().any(|(name, _)| name == flag)));
+    }
+    flags
+}
+
+// Every flag with a fixed set of values, once
+fn choices() -> Vec<(&'static str, &'static [&'static str])> {
+    let mut choices: Vec<(&str, &[&str])> = Vec::new();
+    let every = BUILD_FLAGS.iter().chain(READ_FLAGS).chain(SUBCOMMANDS.iter().flat_map(|(_, _, own)| own.iter()));
+    for (flag, takes) in every {
+        if let OneOf(values) = takes {
+            if !choices.iter().any(|(name, _)| name == flag) {
+                choices.push((flag, values));
+            }
+        }
+    }
+    choices
+}
+
+fn subcommand_names() -> String {
+    SUBCOMMANDS.iter().map(|(name, _, _)| *name).collect::<Vec<_>>().join(" ")
+}
+
+fn flag_names(subcommand: Option<&str>) -> String {
+    flags(subcommand).iter().map(|(flag, _)| *flag).collect::<Vec<_>>().join(" ")
+}
+
+fn bash() -> String {
+    let mut script = String::from("# bash completion for makeiso\n_makeiso() {\n");
+    script.push_str("    local cur=\"${COMP_WORDS[COMP_CWORD]}\" prev=\"${COMP_WORDS[COMP_CWORD-1]}\"\n");
+    script.push_str("    case \"$prev\" in\n");
+    for (flag, values) in choices() {
+        script.push_str(&format!("        {}) COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")); return ;;\n", flag, values.join(" ")));
+    }
+    script.push_str(&format!("        completions) COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")); return ;;\n", SHELLS.join(" ")));
+    script.push_str("    esac\n");
+    script.push_str(&format!(
+        "    if [[ $COMP_CWORD -eq 1 && \"$cur\" != -* ]]; then\n        COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))\n        return\n    fi\n",
+        subcommand_names()
+    ));
+    script.push_str("    [[ \"$cur\" == -* ]] || return\n    local flags\n    case \"${COMP_WORDS[1]}\" in\n");
+    for (name, _, _) in SUBCOMMANDS {
+        script.push_str(&format!("        {}) flags=\"{}\" ;;\n", name, flag_names(Some(name))));
+    }
+    script.push_str(&format!("        *) flags=\"{}\" ;;\n    esac\n", flag_names(None)));
+    script.push_str("    COMPREPLY=($(compgen -W \"$flags\" -- \"$cur\"))\n}\ncomplete -o default -F _makeiso makeiso\n");
+    script
+}
+
+fn zsh() -> String {
+    let mut script = String::from("#compdef makeiso\n# zsh completion for makeiso\n_makeiso() {\n");
+    script.push_str("    case \"${words[CURRENT-1]}\" in\n");
+    for (flag, values) in choices() {
+        script.push_str(&format!("        {}) compadd -- {}; return ;;\n", flag, values.join(" ")));
+    }
+    script.push_str(&format!("        completions) compadd -- {}; return ;;\n", SHELLS.join(" ")));
+    script.push_str("    esac\n");
+    script.push_str(&format!("    if (( CURRENT == 2 )) && [[ $PREFIX != -* ]]; then\n        compadd -- {}\n    fi\n", subcommand_names()));
+    script.push_str("    if [[ $PREFIX == -* ]]; then\n        case \"${words[2]}\" in\n");
+    for (name, _, _) in SUBCOMMANDS {
+        script.push_str(&format!("            {}) compadd -- {} ;;\n", name, flag_names(Some(name))));
+    }
+    script.push_str(&format!("            *) compadd -- {} ;;\n        esac\n    else\n        _files\n    fi\n}}\n", flag_names(None)));
+    script.push_str("compdef _makeiso makeiso\n");
+    script
+}
+
+fn fish() -> String {
+    let mut script = String::from("# fish completion for makeiso\n");
+    let names = subcommand_names();
+    script.push_str(&format!("complete -c makeiso -n '__fish_use_subcommand' -a '{}'\n", names));
+    let mut line = |condition: &str, flag: &str, takes: &Takes| {
+        let option = match flag.strip_prefix("--") {
+            Some(long) => format!("-l {}", long),
+            None => format!("-s {}", flag.trim_start_matches('-')),
+        };
+        let argument = match takes {
+            Nothing => String::new(),
+            Value => " -r".to_string(),
+            OneOf(values) => format!(" -x -a '{}'", values.join(" ")),
+        };
+        script.push_str(&format!("complete -c makeiso -n '{}' {}{}\n", condition, option, argument));
+    };
+    for (flag, takes) in flags(None) {
+        line(&format!("not __fish_seen_subcommand_from {}", names), flag, takes);
+    }
+    for (name, _, _) in SUBCOMMANDS {
+        for (flag, takes) in flags(Some(name)) {
+            line(&format!("__fish_seen_subcommand_from {}", name), flag, takes);
+        }
+    }
+    script.push_str(&format!("complete -c makeiso -n '__fish_seen_subcommand_from completions' -x -a '{}'\n", SHELLS.join(" ")));
+    script
+}
+
+fn powershell() -> String {
+    let list = |words: &mut dyn Iterator<Item = &str>| words.map(|word| format!("'{}'", word)).collect::<Vec<_>>().join(", ");
+    let mut script = String::from("# PowerShell completion for makeiso\nRegister-ArgumentCompleter -Native -CommandName makeiso -ScriptBlock {\n");
+    script.push_str("    param($wordToComplete, $commandAst, $cursorPosition)\n");
+    script.push_str("    $words = @($commandAst.CommandElements | ForEach-Object { $_.ToString() })\n");
+    script.push_str("    if ($wordToComplete) { $previous = $words[-2] } else { $previous = $words[-1] }\n");
+    script.push_str("    $values = @{\n");
+    for (flag, values) in choices() {
+        script.push_str(&format!("        '{}' = @({})\n", flag, list(&mut values.iter().copied())));
+    }
+    script.push_str("    }\n    $flags = @{\n");
+    for (name, _, _) in SUBCOMMANDS {
+        script.push_str(&format!("        '{}' = @({})\n", name, list(&mut flags(Some(name)).iter().map(|(flag, _)| *flag))));
+    }
+    script.push_str("    }\n");
+    script.push_str(&format!("    $build = @({})\n", list(&mut flags(None).iter().map(|(flag, _)| *flag))));
+    script.push_str(&format!("    $subcommands = @({})\n", list(&mut SUBCOMMANDS.iter().map(|(name, _, _)| *name))));
+    script.push_str(&format!("    $shells = @({})\n", list(&mut SHELLS.iter().copied())));
+    script.push_str(
+        "    if ($values.ContainsKey($previous)) {
+        $candidates = $values[$previous]
+    } elseif ($words.Count -gt 1 -and $words[1] -eq 'completions') {
+        $candidates = $shells
+    } elseif ($wordToComplete -like '-*') {
+        if ($words.Count -gt 1 -and $flags.ContainsKey($words[1])) { $candidates = $flags[$words[1]] } else { $candidates = $build }
+    } elseif ($words.Count -le 2) {
+        $candidates = $subcommands
+    } else {
+        return
+    }
+    $candidates | Where-Object { $_ -like \"$wordToComplete*\" } | ForEach-Object {
+        [System.Management.Automation.CompletionResult]::new($_, $_, 'ParameterValue', $_)
+    }
+}
+",
+    );
+    script
+}
+
+// makeiso completions <bash|zsh|fish|powershell>: print the completion script for the shell
+pub fn completions(args: &[String]) -> io::Result<()> {
+    let script = match args {
+        [shell] if shell == "bash" => bash(),
+        [shell] if shell == "zsh" => zsh(),
+        [shell] if shell == "fish" => fish(),
+        [shell] if shell == "powershell" => powershell(),
+        _ => return Err(io::Error::new(ErrorKind::InvalidInput, USAGE)),
+    };
+    print!("{}", script);
+    Ok(())
+}
@@ -0,0 +1,445 @@
+// Default options from a configuration file, so recurring jobs don't repeat the same flags
+//
+// The file is ~/.config/makeiso/config.toml ($XDG_CONFIG_HOME/makeiso/config.toml when set,
+// %APPDATA%\makeiso\config.toml on Windows), or the one given with --config. Its keys are the
+// long build flags without their dashes; they are put in front of the command line, so the
+// command line wins for single values and adds to lists. true turns a switch on, lists repeat
+// a flag, and `output` names the image when no output path is given, with strftime-style
+// fields for the current time (UTC):
+//
+//     volume-id = "HOME_BACKUP"
+//     exclude = ["*.tmp", "/cache"]
+//     reproducible = true
+//     output = "/backups/home-%Y%m%d.iso"
+//
+// Only the part of TOML this needs is read: tables, comments, basic and literal strings,
+// integers, booleans and arrays.
+
+use std::env;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use super::completions::build_flag_takes_value;
+use super::notify::Notify;
+use super::schedule::Schedule;
+use super::{unix_from_utc, unix_time, utc_date_time};
+
+const PROFILE_PREFIX: &str = "profiles.";
+
+// A value in the file
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+// A [table] and its keys in the order given; the keys before the first header have no name
+#[derive(Debug, Clone, PartialEq)]
+pub struct Table {
+    pub name: String,
+    pub entries: Vec<(String, Value)>,
+}
+
+// What the configuration adds to a build: flags for parse_args and a template for the output
+pub struct Defaults {
+    pub args: Vec<String>,
+    pub output: Option<String>,
+}
+
+// The default configuration file, when there is a home to look in
+fn default_path() -> Option<PathBuf> {
+    if cfg!(windows) {
+        return env::var_os("APPDATA").map(|appdata| PathBuf::from(appdata).join("makeiso").join("config.toml"));
+    }
+    let base = env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()).map(PathBuf::from).or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(base.join("makeiso").join("config.toml"))
+}
+
+// Take --config out of the arguments, returning the rest and the configuration file to read
+fn find(args: &[String]) -> io::Result<(Vec<String>, Option<PathBuf>)> {
+    let mut rest = Vec::with_capacity(args.len());
+    let mut given = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if let Some(path) = arg.strip_prefix("--config=") {
+            given = Some(PathBuf::from(path));
+        } else if arg == "--config" {
+            given = Some(PathBuf::from(args.next().ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "--config requires a value"))?));
+        } else {
+            rest.push(arg.clone());
+        }
+    }
+
+    let path = given.or_else(|| default_path().filter(|path| path.is_file()));
+    Ok((rest, path))
+}
+
+fn read(path: &Path) -> io::Result<Vec<Table>> {
+    let text = fs::read_to_string(path).map_err(|e| io::Error::new(e.kind(), format!("could not read {}: {}", path.display(), e)))?;
+    let tables = parse(&text).map_err(|e| io::Error::new(ErrorKind::InvalidInput, format!("{}: {}", path.display(), e)))?;
+    for table in &tables {
+        if !table.name.is_empty() && !table.name.starts_with(PROFILE_PREFIX) {
+            return Err(io::Error::new(ErrorKind::InvalidInput, format!("{}: unknown table [{}]", path.display(), table.name)));
+        }
+    }
+    Ok(tables)
+}
+
+// Take --config out of the arguments and read the defaults of the configuration file
+pub fn load(args: &[String]) -> io::Result<(Vec<String>, Defaults)> {
+    let (rest, path) = find(args)?;
+    let mut defaults = Defaults { args: Vec::new(), output: None };
+    let Some(path) = path else {
+        return Ok((rest, defaults));
+    };
+    let tables = read(&path)?;
+    let context = |e: io::Error| io::Error::new(ErrorKind::InvalidInput, format!("{}: {}", path.display(), e));
+    for (key, value) in &tables[0].entries {
+        if key == "output" {
+            defaults.output = Some(string_value(key, value).map_err(context)?);
+        } else {
+            defaults.args.extend(flag_args(key, value).map_err(context)?);
+        }
+    }
+    Ok((rest, defaults))
+}
+
+pub fn string_value(key: &str, value: &Value) -> io::Result<String> {
+    match value {
+        Value::String(text) => Ok(text.clone()),
+        _ => Err(io::Error::new(ErrorKind::InvalidInput, format!("{} must be a string", key))),
+    }
+}
+
+// A [profiles.<name>] table: a backup job with its own sources, options and output
+pub struct Profile {
+    pub config: PathBuf, // The file it came from, to pass on to parse_args
+    pub sources: Vec<String>,
+    pub args: Vec<String>,          // Its options, which come after the top-level defaults
+    pub output: String,             // Output template, the profile's or the top-level one
+    pub keep: Option<usize>,        // How many of its images to keep
+    pub schedule: Option<Schedule>, // When the daemon runs it
+    pub notify: Notify,             // Who hears about its runs
+}
+
+// Names of the profiles in the configuration file the arguments point to
+pub fn profile_names(args: &[String]) -> io::Result<Vec<String>> {
+    let (_, path) = find(args)?;
+    let Some(path) = path else {
+        return Ok(Vec::new());
+    };
+    Ok(read(&path)?.iter().filter_map(|table| table.name.strip_prefix(PROFILE_PREFIX).map(str::to_string)).collect())
+}
+
+// Take --config out of the arguments and read the named profile from the configuration file
+pub fn load_profile(args: &[String], name: &str) -> io::Result<(Vec<String>, Profile)> {
+    let (rest, path) = find(args)?;
+    let Some(path) = path else {
+        return Err(io::Error::new(ErrorKind::NotFound, "there is no configuration file to read profiles from"));
+    };
+    let tables = read(&path)?;
+    let Some(table) = tables.iter().find(|table| table.name.strip_prefix(PROFILE_PREFIX) == Some(name)) else {
+        return Err(io::Error::new(ErrorKind::NotFound, format!("{} has no profile {}", path.display(), name)));
+    };
+    let context = |e: io::Error| io::Error::new(ErrorKind::InvalidInput, format!("{}: profile {}: {}", path.display(), name, e));
+
+    let mut output = tables[0].entries.iter().find(|(key, _)| key == "output").map(|(key, value)| string_value(key, value)).transpose().map_err(context)?;
+    let (mut sources, mut args, mut keep, mut schedule, mut notify) = (Vec::new(), Vec::new(), None, None, Notify::default());
+    for (key, value) in &table.entries {
+        match (key.as_str(), value) {
+            ("output", value) => output = Some(string_value(key, value).map_err(context)?),
+            ("source" | "sources", Value::String(source)) => sources.push(source.clone()),
+            ("source" | "sources", Value::Array(values)) => {
+                for value in values {
+                    sources.push(string_value(key, value).map_err(context)?);
+                }
+            }
+            ("source" | "sources", _) => return Err(context(io::Error::new(ErrorKind::InvalidInput, format!("{} must be a path or a list of paths", key)))),
+            ("keep", Value::Integer(count)) if *count > 0 => keep = Some(*count as usize),
+            ("schedule", value) => schedule = Some(Schedule::parse(&string_value(key, value).map_err(context)?).map_err(context)?),
+            ("keep", _) => return Err(context(io::Error::new(ErrorKind::InvalidInput, "keep must be a number of images, at least 1"))),
+            (_, value) if notify.set(key, value).map_err(context)? => {}
+            (_, value) => args.extend(flag_args(key, value).map_err(context)?),
+        }
+    }
+    if sources.is_empty() {
+        return Err(context(io::Error::new(ErrorKind::InvalidInput, "no source given")));
+    }
+    let Some(output) = output else {
+        return Err(context(io::Error::new(ErrorKind::InvalidInput, "no output given")));
+    };
+    notify.check().map_err(context)?;
+    Ok((rest, Profile { config: path, sources, args, output, keep, schedule, notify }))
+}
+
+// The command-line form of one key
+pub fn flag_args(key: &str, value: &Value) -> io::Result<Vec<String>> {
+    let flag = format!("--{}", key);
+    if key == "config" {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "config can't be set from a configuration file"));
+    }
+    let Some(takes_value) = build_flag_takes_value(&flag) else {
+        return Err(io::Error::new(ErrorKind::InvalidInput, format!("unknown option: {}", key)));
+    };
+    let values = match value {
+        Value::Array(values) => values.clone(),
+        value => vec![value.clone()],
+    };
+    let mut args = Vec::new();
+    for value in values {
+        match (takes_value, value) {
+            (false, Value::Boolean(true)) => args.push(flag.clone()),
+            (false, Value::Boolean(false)) => {}
+            (false, _) => return Err(io::Error::new(ErrorKind::InvalidInput, format!("{} is a switch, so it takes true or false", key))),
+            (true, Value::String(text)) => args.push(format!("{}={}", flag, text)),
+            (true, Value::Integer(number)) => args.push(format!("{}={}", flag, number)),
+            (true, _) => return Err(io::Error::new(ErrorKind::InvalidInput, format!("{} takes a string or a number", key))),
+        }
+    }
+    Ok(args)
+}
+
+// Fill in %Y, %m, %d, %H, %M, %S, %j and %% from a UTC time
+pub fn expand_template(template: &str, timestamp: i64) -> io::Result<String> {
+    let (year, month, day, hour, minute, second) = utc_date_time(timestamp);
+    let day_of_year = timestamp.div_euclid(86_400) - unix_from_utc(year, 1, 1, 0, 0, 0) / 86_400 + 1;
+    let mut expanded = String::with_capacity(template.len());
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            expanded.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('Y') => expanded.push_str(&format!("{:04}", year)),
+            Some('m') => expanded.push_str(&format!("{:02}", month)),
+            Some('d') => expanded.push_str(&format!("{:02}", day)),
+            Some('H') => expanded.push_str(&format!("{:02}", hour)),
+            Some('M') => expanded.push_str(&format!("{:02}", minute)),
+            Some('S') => expanded.push_str(&format!("{:02}", second)),
+            Some('j') => expanded.push_str(&format!("{:03}", day_of_year)),
+            Some('%') => expanded.push('%'),
+            other => {
+                let field = other.map(String::from).unwrap_or_default();
+                return Err(io::Error::new(ErrorKind::InvalidInput, format!("unknown field %{} in output template {}", field, template)));
+            }
+        }
+    }
+    Ok(expanded)
+}
+
+// Whether a file name is one a template's file name gives at some time
+pub fn template_matches(template: &str, name: &str) -> bool {
+    let mut name = name.chars().peekable();
+    let mut template = template.chars();
+    while let Some(c) = template.next() {
+        let digits = match c {
+            '%' => match template.next() {
+                Some('Y') => 4,
+                Some('m' | 'd' | 'H' | 'M' | 'S') => 2,
+                Some('j') => 3,
+                Some('%') => 0,
+                _ => return false,
+            },
+            _ => 0,
+        };
+        if digits == 0 {
+            if name.next() != Some(c) {
+                return false;
+            }
+            continue;
+        }
+        for _ in 0..digits {
+            if !name.next().is_some_and(|c| c.is_ascii_digit()) {
+                return false;
+            }
+        }
+    }
+    name.peek().is_none()
+}
+
+// The output path a template gives now
+pub fn output_path(template: &str) -> io::Result<PathBuf> {
+    Ok(PathBuf::from(expand_template(template, unix_time(SystemTime::now()))?))
+}
+
+// Parse the subset of TOML described above
+pub fn parse(text: &str) -> io::Result<Vec<Table>> {
+    let mut parser = Parser { chars: text.chars().collect(), at: 0, line: 1 };
+    let mut tables = vec![Table { name: String::new(), entries: Vec::new() }];
+    loop {
+        parser.skip_blank(true);
+        let Some(c) = parser.peek() else {
+            break;
+        };
+        if c == '[' {
+            parser.at += 1;
+            if parser.peek() == Some('[') {
+                return Err(parser.error("arrays of tables aren't supported"));
+            }
+            let mut name = String::new();
+            while let Some(c) = parser.next() {
+                match c {
+                    ']' => break,
+                    '\n' => return Err(parser.error("unterminated table header")),
+                    c => name.push(c),
+                }
+            }
+            let name = name.split('.').map(|part| part.trim().trim_matches('"')).collect::<Vec<_>>().join(".");
+            if tables.iter().any(|table| table.name == name) {
+                return Err(parser.error(&format!("table [{}] is defined twice", name)));
+            }
+            tables.push(Table { name, entries: Vec::new() });
+        } else {
+            let key = parser.key()?;
+            parser.skip_blank(false);
+            if parser.next() != Some('=') {
+                return Err(parser.error(&format!("expected = after {}", key)));
+            }
+            parser.skip_blank(false);
+            let value = parser.value()?;
+            let table = tables.last_mut().expect("top-level table");
+            if table.entries.iter().any(|(existing, _)| *existing == key) {
+                return Err(parser.error(&format!("{} is given twice", key)));
+            }
+            table.entries.push((key, value));
+        }
+        parser.skip_blank(false);
+        match parser.next() {
+            None | Some('\n') => {}
+            Some(_) => return Err(parser.error("expected the end of the line")),
+        }
+    }
+    Ok(tables)
+}
+
+struct Parser {
+    chars: Vec<char>,
+    at: usize,
+    line: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.at).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.at += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn error(&self, message: &str) -> io::Error {
+        io::Error::new(ErrorKind::InvalidInput, format!("line {}: {}", self.line, message))
+    }
+
+    // Spaces and comments, and line ends too when asked
+    fn skip_blank(&mut self, newlines: bool) {
+        while let Some(c) = self.peek() {
+            match c {
+                ' ' | '\t' | '\r' => self.at += 1,
+                '\n' if newlines => {
+                    self.next();
+                }
+                '#' => {
+                    while self.peek().is_some_and(|c| c != '\n') {
+                        self.at += 1;
+                    }
+                }
+                _ => break,
+            }
+        }
+    }
+
+    fn key(&mut self) -> io::Result<String> {
+        if let Some(quote @ ('"' | '\'')) = self.peek() {
+            self.at += 1;
+            return self.string(quote);
+        }
+        let start = self.at;
+        while self.peek().is_some_and(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            self.at += 1;
+        }
+        if self.at == start {
+            return Err(self.error("expected a key"));
+        }
+        Ok(self.chars[start..self.at].iter().collect())
+    }
+
+    fn value(&mut self) -> io::Result<Value> {
+        match self.peek() {
+            Some(quote @ ('"' | '\'')) => {
+                self.at += 1;
+                Ok(Value::String(self.string(quote)?))
+            }
+            Some('[') => {
+                self.at += 1;
+                let mut values = Vec::new();
+                loop {
+                    self.skip_blank(true);
+                    if self.peek() == Some(']') {
+                        self.at += 1;
+                        return Ok(Value::Array(values));
+                    }
+                    values.push(self.value()?);
+                    self.skip_blank(true);
+                    match self.next() {
+                        Some(',') => {}
+                        Some(']') => return Ok(Value::Array(values)),
+                        _ => return Err(self.error("expected , or ] in an array")),
+                    }
+                }
+            }
+            _ => {
+                let start = self.at;
+                while self.peek().is_some_and(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '+') {
+                    self.at += 1;
+                }
+                let word: String = self.chars[start..self.at].iter().collect();
+                match word.as_str() {
+                    "true" => Ok(Value::Boolean(true)),
+                    "false" => Ok(Value::Boolean(false)),
+                    _ => word.replace('_', "").parse().map(Value::Integer).map_err(|_| self.error(&format!("unsupported value: {}", word))),
+                }
+            }
+        }
+    }
+
+    // The rest of a string after its opening quote; only basic (") strings have escapes
+    fn string(&mut self, quote: char) -> io::Result<String> {
+        let mut text = String::new();
+        loop {
+            let Some(c) = self.peek().filter(|&c| c != '\n') else {
+                return Err(self.error("unterminated string"));
+            };
+            self.at += 1;
+            match c {
+                c if c == quote => return Ok(text),
+                '\\' if quote == '"' => {
+                    let escaped = match self.next() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some(kind @ ('u' | 'U')) => {
+                            let digits: String = (0..if kind == 'u' { 4 } else { 8 }).filter_map(|_| self.next()).collect();
+                            u32::from_str_radix(&digits, 16).ok().and_then(char::from_u32).ok_or_else(|| self.error("invalid unicode escape"))?
+                        }
+                        _ => return Err(self.error("invalid escape in a string")),
+                    };
+                    text.push(escaped);
+                }
+                c => text.push(c),
+            }
+        }
+    }
+}
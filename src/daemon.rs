@@ -0,0 +1,297 @@
+// Profiles run on their schedules (makeiso daemon), and the record of past runs (makeiso status)
+//
+// Every `makeiso run`, scheduled or not, adds a line to runs.tsv in the state directory
+// ($MAKEISO_STATE_DIR, or $XDG_STATE_HOME/makeiso, ~/.local/state/makeiso, %LOCALAPPDATA%\makeiso
+// on Windows): when it started, the profile, ok or failed, how long it took, the size of the
+// image, how many files couldn't be read, where the image went and the error. Tabs separate
+// the fields, so the file is easy to grep and cut. The daemon can also publish the runs as
+// Prometheus metrics (see metrics.rs). The history is this file rather than a SQLite database
+// like the catalog's (see catalogdb.rs): every run appends one line, which needs neither the
+// sqlite3 shell nor a library, so scheduled runs don't depend on anything being installed.
+//
+// The daemon reads the configuration again every minute, so edits apply without a restart, and
+// runs the profiles whose schedule fires in that minute one after another, each in a makeiso
+// process of its own. A run that is still going when its next time comes isn't started twice.
+
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use super::checkpoint;
+use super::config;
+use super::metrics;
+use super::schedule::Schedule;
+use super::{time_text, unix_time};
+
+const RUNS_FILE: &str = "runs.tsv";
+const DAEMON_USAGE: &str = "Usage: makeiso daemon [--config FILE] [--metrics-listen ADDR] [--metrics-file PATH]";
+const STATUS_USAGE: &str = "Usage: makeiso status [--config FILE] [--metrics] [profile|status file]";
+
+// One run of a profile, as recorded
+#[derive(Debug, Clone)]
+pub struct Run {
+    pub start: i64,
+    pub profile: String,
+    pub ok: bool,
+    pub seconds: u64,
+    pub bytes: u64,    // Size of the image written, 0 when there is none (or it is remote)
+    pub skipped: u64,  // Files and directories left out because they couldn't be read
+    pub image: String, // Where it went
+    pub error: String, // Why it failed
+}
+
+// Where the record of runs is kept
+pub fn state_dir() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("MAKEISO_STATE_DIR").filter(|dir| !dir.is_empty()) {
+        return Some(PathBuf::from(dir));
+    }
+    if cfg!(windows) {
+        return env::var_os("LOCALAPPDATA").map(|dir| PathBuf::from(dir).join("makeiso"));
+    }
+    let base = env::var_os("XDG_STATE_HOME").filter(|dir| !dir.is_empty()).map(PathBuf::from).or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("state")))?;
+    Some(base.join("makeiso"))
+}
+
+// Tabs and line breaks would split the record, so they become spaces
+pub fn field(text: &str) -> String {
+    text.replace(['\t', '\n', '\r'], " ")
+}
+
+// Add a run to the record
+pub fn record(run: &Run) -> io::Result<()> {
+    let Some(dir) = state_dir() else {
+        return Ok(());
+    };
+    fs::create_dir_all(&dir)?;
+    let line = format!(
+        "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+        run.start,
+        field(&run.profile),
+        if run.ok { "ok" } else { "failed" },
+        run.seconds,
+        run.bytes,
+        run.skipped,
+        field(&run.image),
+        field(&run.error)
+    );
+    OpenOptions::new().create(true).append(true).open(dir.join(RUNS_FILE))?.write_all(line.as_bytes())
+}
+
+// Every run recorded, oldest first
+pub fn read_runs() -> io::Result<Vec<Run>> {
+    let Some(dir) = state_dir() else {
+        return Ok(Vec::new());
+    };
+    let text = match fs::read_to_string(dir.join(RUNS_FILE)) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut runs = Vec::new();
+    for line in text.lines() {
+        let mut fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() == 7 {
+            fields.insert(5, "0"); // Recorded before skipped files were counted
+        }
+        let [start, profile, outcome, seconds, bytes, skipped, image, error] = fields[..] else {
+            continue; // Cut short, e.g. by a crash while it was written
+        };
+        let (Ok(start), Ok(seconds), Ok(bytes), Ok(skipped)) = (start.parse(), seconds.parse(), bytes.parse(), skipped.parse()) else {
+            continue;
+        };
+        runs.push(Run { start, profile: profile.to_string(), ok: outcome == "ok", seconds, bytes, skipped, image: image.to_string(), error: error.to_string() });
+    }
+    Ok(runs)
+}
+
+// The profiles that have a schedule; ones that can't be read are reported and left out
+fn scheduled(config_args: &[String]) -> io::Result<Vec<(String, Schedule)>> {
+    let mut profiles = Vec::new();
+    for name in config::profile_names(config_args)? {
+        match config::load_profile(config_args, &name) {
+            Ok((_, profile)) => profiles.extend(profile.schedule.map(|schedule| (name, schedule))),
+            Err(e) => eprintln!("Skipping profile {}: {}", name, e),
+        }
+    }
+    Ok(profiles)
+}
+
+// The options of daemon and status, for the ones `allowed`
+#[derive(Default)]
+struct Options {
+    config: Vec<String>, // --config FILE, given back ready to pass on
+    metrics_file: Option<PathBuf>,
+    metrics_listen: Option<String>,
+    metrics: bool,
+    rest: Vec<String>,
+}
+
+fn parse_options(args: &[String], usage: &str, allowed: &[&str]) -> io::Result<Options> {
+    let mut options = Options::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if !arg.starts_with('-') {
+            options.rest.push(arg.clone());
+            continue;
+        }
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) => (flag, Some(value.to_string())),
+            None => (arg.as_str(), None),
+        };
+        if !allowed.contains(&flag) {
+            return Err(io::Error::new(ErrorKind::InvalidInput, format!("Unknown option: {}\n{}", arg, usage)));
+        }
+        if flag == "--metrics" {
+            options.metrics = true;
+            continue;
+        }
+        let value = match inline {
+            Some(value) => value,
+            None => args.next().cloned().ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, format!("{} requires a value", flag)))?,
+        };
+        match flag {
+            "--config" => options.config = vec![format!("--config={}", value)],
+            "--metrics-file" => options.metrics_file = Some(PathBuf::from(value)),
+            _ => options.metrics_listen = Some(value),
+        }
+    }
+    Ok(options)
+}
+
+// makeiso daemon [--config FILE] [--metrics-listen ADDR] [--metrics-file PATH]: run the
+// scheduled profiles until stopped
+pub fn daemon(args: &[String]) -> io::Result<()> {
+    let options = parse_options(args, DAEMON_USAGE, &["--config", "--metrics-listen", "--metrics-file"])?;
+    let config = options.config;
+    if !options.rest.is_empty() {
+        return Err(io::Error::new(ErrorKind::InvalidInput, DAEMON_USAGE));
+    }
+    let executable = env::current_exe()?;
+    let profiles = scheduled(&config)?;
+    if profiles.is_empty() {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "No profile has a schedule, so there is nothing to run"));
+    }
+    println!("makeiso daemon: {} scheduled profiles (times are UTC)", profiles.len());
+    for (name, schedule) in &profiles {
+        if let Some(next) = schedule.next_after(unix_time(SystemTime::now())) {
+            println!("    {}: next run {}", name, time_text(next));
+        }
+    }
+    if let Some(address) = &options.metrics_listen {
+        metrics::serve(address, &config)?;
+    }
+    let update_metrics = || {
+        if let Some(path) = &options.metrics_file {
+            if let Err(e) = metrics::write_file(path, &config) {
+                eprintln!("Could not write the metrics to {}: {}", path.display(), e);
+            }
+        }
+    };
+    update_metrics();
+
+    let mut last_minute = None;
+    loop {
+        let minute = unix_time(SystemTime::now()).div_euclid(60);
+        if last_minute != Some(minute) {
+            let profiles = scheduled(&config).unwrap_or_else(|e| {
+                eprintln!("Could not read the configuration: {}", e);
+                Vec::new()
+            });
+            let due: Vec<&String> = profiles.iter().filter(|(_, schedule)| schedule.matches(minute * 60)).map(|(name, _)| name).collect();
+            for name in &due {
+                println!("{} Starting profile {}", time_text(unix_time(SystemTime::now())), name);
+                match Command::new(&executable).arg("run").args(&config).arg(name).status() {
+                    Ok(status) if status.success() => println!("{} Profile {} finished", time_text(unix_time(SystemTime::now())), name),
+                    Ok(status) => eprintln!("{} Profile {} failed ({})", time_text(unix_time(SystemTime::now())), name, status),
+                    Err(e) => eprintln!("Could not start makeiso for profile {}: {}", name, e),
+                }
+            }
+            if !due.is_empty() {
+                update_metrics();
+            }
+            // Minutes spent running are skipped, not caught up on
+            last_minute = Some(unix_time(SystemTime::now()).div_euclid(60));
+        }
+
+        // Wake up just after the start of the next minute
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+        let into_minute = Duration::new(now.as_secs() % 60, now.subsec_nanos());
+        thread::sleep(Duration::from_secs(60).saturating_sub(into_minute) + Duration::from_millis(10));
+    }
+}
+
+// makeiso status [--config FILE] [--metrics] [profile|status file]: the last runs of every
+// profile, or all of one with its latest checkpoint (see checkpoint.rs), or what the status file
+// of another build says
+pub fn status(args: &[String]) -> io::Result<()> {
+    let Options { config, metrics, rest, .. } = parse_options(args, STATUS_USAGE, &["--config", "--metrics"])?;
+    if metrics {
+        if !rest.is_empty() {
+            return Err(io::Error::new(ErrorKind::InvalidInput, STATUS_USAGE));
+        }
+        return metrics::print(&config);
+    }
+    let runs = read_runs()?;
+    let now = unix_time(SystemTime::now());
+
+    if let [name] = &rest[..] {
+        let mine: Vec<&Run> = runs.iter().filter(|run| run.profile == *name).collect();
+        let job = checkpoint::job_status_file(name).filter(|path| path.is_file());
+        if mine.is_empty() && job.is_none() && Path::new(name).is_file() {
+            println!("{}", checkpoint::read_status(Path::new(name))?);
+            return Ok(());
+        }
+        if let Some(job) = job {
+            println!("{}", checkpoint::read_status(&job)?);
+        }
+        if mine.is_empty() {
+            println!("Profile {} has not run yet", name);
+        }
+        for run in mine {
+            let outcome = if run.ok { format!("ok      {:>6} s {:>14} bytes  {}", run.seconds, run.bytes, run.image) } else { format!("failed  {:>6} s  {}", run.seconds, run.error) };
+            println!("{}  {}", time_text(run.start), outcome);
+        }
+        return Ok(());
+    } else if !rest.is_empty() {
+        return Err(io::Error::new(ErrorKind::InvalidInput, STATUS_USAGE));
+    }
+
+    // Profiles from the configuration first, then any that only the record still knows
+    let mut names = config::profile_names(&config)?;
+    for run in &runs {
+        if !names.contains(&run.profile) {
+            names.push(run.profile.clone());
+        }
+    }
+    if names.is_empty() {
+        println!("No profiles are configured and none have run");
+    }
+    for name in names {
+        let mine: Vec<&Run> = runs.iter().filter(|run| run.profile == name).collect();
+        println!("{}", name);
+        match mine.last() {
+            Some(run) if run.ok => println!("    last run:     {} ok, {} s, {} bytes -> {}", time_text(run.start), run.seconds, run.bytes, run.image),
+            Some(run) => println!("    last run:     {} failed after {} s: {}", time_text(run.start), run.seconds, run.error),
+            None => println!("    last run:     never"),
+        }
+        let last_success = mine.iter().rposition(|run| run.ok);
+        match last_success {
+            Some(at) => println!("    last success: {}", time_text(mine[at].start)),
+            None if !mine.is_empty() => println!("    last success: never"),
+            None => {}
+        }
+        let failures = mine.len() - last_success.map_or(0, |at| at + 1);
+        if failures > 0 {
+            println!("    failures:     {} in a row", failures);
+        }
+        let schedule = config::load_profile(&config, &name).ok().and_then(|(_, profile)| profile.schedule);
+        if let Some(next) = schedule.and_then(|schedule| schedule.next_after(now)) {
+            println!("    next run:     {}", time_text(next));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,152 @@
+// Files stored once however many paths they have (--dedup content): after the scan, the files
+// that share their size with another are hashed, and in the layout every file whose contents
+// match one ahead of it gets that file's extent instead of its own, so their directory records
+// point at the same blocks. Only the candidates are read twice, once here and once to write
+// them; generated files, the boot file and DVD-Video title sets keep extents of their own.
+
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Read};
+
+use super::backend::SourceProvider;
+use super::sha256::Sha256;
+use super::{events, Entry, ImageTree};
+
+const REPORT_BLOCK_SIZE: usize = 64 * 1024;
+
+// Hash the contents of every file another file has the same size as
+pub fn hash_contents(tree: &mut ImageTree, source: &dyn SourceProvider) -> io::Result<()> {
+    let mut files = Vec::new();
+    for (index, dir) in tree.dirs.iter().enumerate() {
+        for (entry, item) in dir.entries.iter().enumerate() {
+            if let Entry::File(file) = item {
+                if file.size > 0 && tree.generated((index, entry)).is_none() {
+                    files.push((file.size, file.source.clone()));
+                }
+            }
+        }
+    }
+    let mut sizes: HashMap<u32, usize> = HashMap::new();
+    for (size, _) in &files {
+        *sizes.entry(*size).or_default() += 1;
+    }
+
+    let mut buffer = vec![0u8; 64 * 1024];
+    for (size, path) in files {
+        if sizes[&size] < 2 || tree.digests.contains_key(&path) {
+            continue;
+        }
+        let mut sha = Sha256::new();
+        let mut opened = source.open(&path).map_err(|e| io::Error::new(e.kind(), format!("could not read {} to compare it: {}", path.display(), e)))?;
+        loop {
+            let read = opened.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            sha.update(&buffer[..read]);
+        }
+        tree.digests.insert(path, sha.finish());
+    }
+    Ok(())
+}
+
+// Take the files whose contents match a file ahead of them out of the layout order, leaving
+// the first pinned ones alone; returns each of them with the file whose extent it shares
+pub fn share(tree: &ImageTree, files: &mut Vec<(usize, usize)>, pinned: usize) -> Vec<((usize, usize), (usize, usize))> {
+    let mut first: HashMap<(u32, [u8; 32]), (usize, usize)> = HashMap::new();
+    let mut shared = Vec::new();
+    let mut saved = 0u64;
+    let mut kept = Vec::with_capacity(files.len());
+    for (position, &(dir, entry)) in files.iter().enumerate() {
+        let key = match &tree.dirs[dir].entries[entry] {
+            Entry::File(file) if position >= pinned => tree.digests.get(&file.source).map(|digest| (file.size, *digest)),
+            _ => None,
+        };
+        match key {
+            Some(key) if first.contains_key(&key) => {
+                shared.push(((dir, entry), first[&key]));
+                saved += key.0 as u64;
+            }
+            Some(key) => {
+                first.insert(key, (dir, entry));
+                kept.push((dir, entry));
+            }
+            None => kept.push((dir, entry)),
+        }
+    }
+    *files = kept;
+    if !shared.is_empty() {
+        events::info(&format!("{} files have the same contents as another and share its extent, saving {} bytes", shared.len(), saved));
+    }
+    shared
+}
+
+// --dedup-report: how much identical whole files and identical 64 KiB blocks (at 64 KiB offsets
+// into their files) take up, which is what content dedup saves or block-level dedup and
+// compression could; every file is read through once more for it
+pub fn report(tree: &ImageTree, source: &dyn SourceProvider) -> io::Result<()> {
+    let mut files: HashSet<(u32, [u8; 32])> = HashSet::new();
+    let mut blocks: HashSet<[u8; 16]> = HashSet::new();
+    let (mut count, mut total, mut duplicate_files, mut duplicate_file_bytes, mut duplicate_block_bytes) = (0u64, 0u64, 0u64, 0u64, 0u64);
+    let mut buffer = vec![0u8; REPORT_BLOCK_SIZE];
+    for (index, dir) in tree.dirs.iter().enumerate() {
+        for (entry, item) in dir.entries.iter().enumerate() {
+            let Entry::File(file) = item else { continue };
+            if tree.generated((index, entry)).is_some() {
+                continue;
+            }
+            let mut opened = source.open(&file.source).map_err(|e| io::Error::new(e.kind(), format!("could not read {}: {}", file.source.display(), e)))?;
+            let mut sha = Sha256::new();
+            loop {
+                let read = read_block(&mut opened, &mut buffer)?;
+                if read == 0 {
+                    break;
+                }
+                sha.update(&buffer[..read]);
+                let mut block = Sha256::new();
+                block.update(&buffer[..read]);
+                let mut digest = [0u8; 16];
+                digest.copy_from_slice(&block.finish()[..16]);
+                if !blocks.insert(digest) {
+                    duplicate_block_bytes += read as u64;
+                }
+            }
+            count += 1;
+            total += file.size as u64;
+            if !files.insert((file.size, sha.finish())) && file.size > 0 {
+                duplicate_files += 1;
+                duplicate_file_bytes += file.size as u64;
+            }
+        }
+    }
+
+    let percent = |bytes: u64| if total == 0 { 0.0 } else { bytes as f64 * 100.0 / total as f64 };
+    if events::enabled() {
+        events::emit(
+            "dedup-report",
+            &[
+                ("files", count.to_string()),
+                ("bytes", total.to_string()),
+                ("duplicate_files", duplicate_files.to_string()),
+                ("duplicate_file_bytes", duplicate_file_bytes.to_string()),
+                ("duplicate_block_bytes", duplicate_block_bytes.to_string()),
+            ],
+        );
+        return Ok(());
+    }
+    println!("Duplicate contents among {} files ({} bytes):", count, total);
+    println!("  Identical files: {} copies, {} bytes ({:.1}%)", duplicate_files, duplicate_file_bytes, percent(duplicate_file_bytes));
+    println!("  Identical 64 KiB blocks: {} bytes ({:.1}%)", duplicate_block_bytes, percent(duplicate_block_bytes));
+    Ok(())
+}
+
+// Fill buffer as far as the reader goes; short only at the end
+fn read_block(reader: &mut dyn Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..])? {
+            0 => break,
+            read => filled += read,
+        }
+    }
+    Ok(filled)
+}
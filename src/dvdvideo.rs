@@ -0,0 +1,184 @@
+// DVD-Video layout (--dvd-video): players find VIDEO_TS through the UDF bridge (udf.rs) and
+// from then on read the title sets by sector, so their files have to lie where the IFO files
+// say they do:
+//
+// - the files of VIDEO_TS come ahead of all other file contents: VIDEO_TS.IFO, VIDEO_TS.VOB and
+//   VIDEO_TS.BUP (the video manager), then each title set in turn, VTS_nn_0.IFO, the menu
+//   VTS_nn_0.VOB, the titles VTS_nn_1.VOB to VTS_nn_9.VOB and VTS_nn_0.BUP
+// - every set starts on a 32 KiB boundary, the ECC block of a DVD
+// - the menu, the first title VOB and the backup IFO go at the sectors the set's IFO gives for
+//   them, with zero padding when the files ahead of them are shorter than it expects
+//
+// Names are checked rather than changed: players look for them in upper case.
+
+use std::collections::BTreeMap;
+use std::io::{self, ErrorKind, Read};
+use std::path::Path;
+
+use super::backend::SourceProvider;
+use super::{blocks_for, events, Entry, ImageTree};
+
+pub const VIDEO_TS: &str = "VIDEO_TS";
+pub const IFO_HEADER_SIZE: usize = 0xC8; // Up to the sector addresses the layout needs
+const ALIGN_BLOCKS: u32 = 16;
+const MAX_VOB_SIZE: u32 = 1 << 30;
+const MAX_GAP_BLOCKS: u32 = 1024; // Padding asked for beyond this is taken for a garbled IFO
+
+// A file of a title set, in the order they are recorded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Part {
+    Ifo,
+    Menu,
+    Title(u8),
+    Backup,
+}
+
+// A file of a title set by part, with its directory and entry index
+type SetFile = (Part, (usize, usize));
+
+// The video manager (VIDEO_TS.*, number 0) or a title set (VTS_nn_*)
+#[derive(Debug)]
+pub struct TitleSet {
+    number: u8,
+    files: Vec<SetFile>,
+}
+
+impl TitleSet {
+    fn name(&self) -> String {
+        if self.number == 0 {
+            VIDEO_TS.to_string()
+        } else {
+            format!("VTS_{:02}", self.number)
+        }
+    }
+}
+
+// The files of all title sets, in recording order
+pub fn files(sets: &[TitleSet]) -> Vec<(usize, usize)> {
+    sets.iter().flat_map(|set| set.files.iter().map(|&(_, file)| file)).collect()
+}
+
+// Which title set part a file of VIDEO_TS is, by its name
+fn classify(name: &str) -> Option<(u8, Part)> {
+    let (stem, extension) = name.split_once('.')?;
+    let (number, part) = if stem == VIDEO_TS {
+        (0, 0)
+    } else {
+        let (number, part) = stem.strip_prefix("VTS_")?.split_once('_')?;
+        if number.len() != 2 || part.len() != 1 {
+            return None;
+        }
+        (number.parse().ok().filter(|number| (1..=99).contains(number))?, part.parse().ok()?)
+    };
+    let part = match (extension, part) {
+        ("IFO", 0) => Part::Ifo,
+        ("BUP", 0) => Part::Backup,
+        ("VOB", 0) => Part::Menu,
+        ("VOB", part) if number != 0 => Part::Title(part),
+        _ => return None,
+    };
+    Some((number, part))
+}
+
+// The first bytes of an IFO file of the source, kept from the scan for the layout
+pub fn read_header(source: &dyn SourceProvider, path: &Path) -> io::Result<Vec<u8>> {
+    let mut header = Vec::new();
+    source.open(path)?.take(IFO_HEADER_SIZE as u64).read_to_end(&mut header)?;
+    Ok(header)
+}
+
+// Find the title sets in /VIDEO_TS, refusing trees that can't make a DVD-Video disc
+pub fn title_sets(tree: &ImageTree) -> io::Result<Vec<TitleSet>> {
+    let dir = tree.dirs[0]
+        .entries
+        .iter()
+        .find_map(|entry| match entry {
+            Entry::Directory(child) if tree.dirs[*child].name == VIDEO_TS => Some(*child),
+            _ => None,
+        })
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "--dvd-video needs a VIDEO_TS directory at the root of the image"))?;
+
+    let mut sets: BTreeMap<u8, Vec<SetFile>> = BTreeMap::new();
+    for (entry, file) in tree.dirs[dir].entries.iter().enumerate().filter_map(|(entry, found)| match found {
+        Entry::File(file) => Some((entry, file)),
+        _ => None,
+    }) {
+        match classify(&file.name) {
+            Some((_, Part::Menu | Part::Title(_))) if file.size > MAX_VOB_SIZE => {
+                return Err(io::Error::new(ErrorKind::InvalidInput, format!("/{}/{} is larger than the 1 GiB a DVD-Video VOB file may be", VIDEO_TS, file.name)));
+            }
+            Some((number, part)) => sets.entry(number).or_default().push((part, (dir, entry))),
+            None => events::warn(&format!("/{}/{} is not a DVD-Video file, players will ignore it", VIDEO_TS, file.name)),
+        }
+    }
+    if !sets.get(&0).is_some_and(|files| files.iter().any(|&(part, _)| part == Part::Ifo)) {
+        return Err(io::Error::new(ErrorKind::InvalidInput, format!("--dvd-video needs /{}/VIDEO_TS.IFO", VIDEO_TS)));
+    }
+    Ok(sets
+        .into_iter()
+        .map(|(number, mut files)| {
+            files.sort();
+            TitleSet { number, files }
+        })
+        .collect())
+}
+
+// Sector addresses an IFO file gives, relative to its own first sector
+#[derive(Debug, Clone, Copy)]
+struct Header {
+    last_sector: u32, // Of the whole set
+    menu: u32,        // Start of the menu VOB, 0 without one
+    titles: u32,      // Start of the first title VOB, 0 for the video manager
+}
+
+fn parse_header(header: &[u8], number: u8) -> Option<Header> {
+    let magic: &[u8] = if number == 0 { b"DVDVIDEO-VMG" } else { b"DVDVIDEO-VTS" };
+    if header.len() < IFO_HEADER_SIZE || !header.starts_with(magic) {
+        return None;
+    }
+    let field = |at: usize| u32::from_be_bytes([header[at], header[at + 1], header[at + 2], header[at + 3]]);
+    Some(Header { last_sector: field(0x0C), menu: field(0xC0), titles: if number == 0 { 0 } else { field(0xC4) } })
+}
+
+// Assign extents to the files of the title sets from next_block; returns the block after them
+pub fn place(tree: &mut ImageTree, sets: &[TitleSet], mut next_block: u32) -> u32 {
+    for set in sets {
+        let start = next_block.div_ceil(ALIGN_BLOCKS) * ALIGN_BLOCKS;
+        let header = set.files.iter().find(|&&(part, _)| part == Part::Ifo).and_then(|&(_, (dir, entry))| match &tree.dirs[dir].entries[entry] {
+            Entry::File(file) => tree.ifo_headers.get(&file.name).and_then(|header| parse_header(header, set.number)),
+            _ => None,
+        });
+
+        let mut at = start;
+        let mut first_title = true;
+        for &(part, (dir, entry)) in &set.files {
+            let Entry::File(file) = &mut tree.dirs[dir].entries[entry] else { continue };
+            let blocks = blocks_for(file.size as u64);
+            let wanted = header.and_then(|header| match part {
+                Part::Menu => Some(header.menu).filter(|&sector| sector != 0),
+                Part::Title(_) if first_title => Some(header.titles).filter(|&sector| sector != 0),
+                Part::Backup => header.last_sector.checked_add(1).and_then(|end| end.checked_sub(blocks)),
+                _ => None,
+            });
+            if let Some(wanted) = wanted {
+                let offset = at - start;
+                if wanted >= offset && wanted - offset <= MAX_GAP_BLOCKS {
+                    at = start + wanted;
+                } else {
+                    events::warn(&format!(
+                        "The IFO of {} puts {} at sector {} of the set, where the files ahead of it end at sector {}; recording it right after them",
+                        set.name(),
+                        file.name,
+                        wanted,
+                        offset
+                    ));
+                }
+            }
+            file.extent = at;
+            at += blocks;
+            first_title &= !matches!(part, Part::Title(_));
+        }
+        next_block = at;
+    }
+    next_block
+}
@@ -0,0 +1,544 @@
+// Reading what the user running makeiso can't (--elevate sudo|doas|HELPER, Linux). Files and
+// directories the scan is denied are asked of a helper with more rights: makeiso itself, run
+// as `makeiso read-helper SOCKET ROOT...` through sudo or doas. The helper connects to a
+// socket in a directory only this user can open and answers three requests, each the
+// request byte and the length-prefixed path:
+//
+//     S  stat: the kind, size, modification time, device and inode of what the path leads to
+//     L  list: the names in a directory
+//     O  open: the file, opened for reading and sent back as a descriptor (SCM_RIGHTS)
+//
+// Answers start with 0, or 1 and the errno. Opened files come back as ordinary local files,
+// so they are copied, mapped and hashed like any other. The helper stops when the build
+// closes the socket.
+//
+// The helper only serves the user sudo or doas ran it for: before connecting it checks that
+// the socket's directory belongs to that user and is theirs alone (mode 0700), and once
+// connected that the other end (SO_PEERCRED) is that user too. It only answers for paths that
+// resolve, symbolic links followed, to somewhere under the source roots it was given, opens
+// files with O_NOFOLLOW and checks again where the descriptor leads; anything else is
+// refused with EACCES. It refuses to run setuid or setgid, or with capabilities it wasn't
+// given as root, since then whoever starts it would choose the roots.
+
+use std::io;
+use std::path::PathBuf;
+
+use super::backend::SourceProvider;
+
+#[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+pub use linux::helper;
+
+// The local filesystem, with the helper --elevate names asked for what is denied
+#[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+pub fn elevated_source(how: &str, roots: &[PathBuf]) -> io::Result<Box<dyn SourceProvider>> {
+    Ok(Box::new(linux::ElevatedSource::start(how, roots)?))
+}
+
+#[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+mod linux {
+    use std::env;
+    use std::ffi::OsString;
+    use std::fs::{self, DirBuilder, File};
+    use std::io::{self, ErrorKind, Read, Write};
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+    use std::os::unix::ffi::{OsStrExt, OsStringExt};
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt};
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::{Path, PathBuf};
+    use std::process::{Child, Command};
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use crate::backend::{scan_local, FileIdentity, LocalSource, ScannedEntry, SourceFile, SourceKind, SourceMetadata, SourceProvider};
+    use crate::{events, xattr};
+
+    mod sys {
+        use std::ffi::{c_int, c_void};
+
+        pub const SOL_SOCKET: c_int = 1;
+        pub const SCM_RIGHTS: c_int = 1;
+        pub const SO_PEERCRED: c_int = 17;
+        #[cfg(any(target_arch = "aarch64", target_arch = "powerpc64"))]
+        pub const O_NOFOLLOW: c_int = 0o100000;
+        #[cfg(not(any(target_arch = "aarch64", target_arch = "powerpc64")))]
+        pub const O_NOFOLLOW: c_int = 0o400000;
+
+        // struct ucred
+        #[repr(C)]
+        #[derive(Default)]
+        pub struct PeerCredentials {
+            pub pid: i32,
+            pub uid: u32,
+            pub gid: u32,
+        }
+
+        #[repr(C)]
+        pub struct IoVec {
+            pub base: *mut c_void,
+            pub len: usize,
+        }
+
+        // struct msghdr of 64-bit Linux
+        #[repr(C)]
+        pub struct MsgHdr {
+            pub name: *mut c_void,
+            pub name_len: u32,
+            pub iov: *mut IoVec,
+            pub iov_len: usize,
+            pub control: *mut c_void,
+            pub control_len: usize,
+            pub flags: c_int,
+        }
+
+        // A control message carrying one descriptor: struct cmsghdr and the descriptor, padded
+        // to CMSG_SPACE(sizeof(int))
+        #[repr(C)]
+        #[derive(Default)]
+        pub struct FdMessage {
+            pub len: usize,
+            pub level: c_int,
+            pub kind: c_int,
+            pub fd: c_int,
+            pub padding: c_int,
+        }
+
+        // CMSG_LEN(sizeof(int))
+        pub const FD_MESSAGE_LEN: usize = 20;
+
+        extern "C" {
+            pub fn sendmsg(fd: c_int, message: *const MsgHdr, flags: c_int) -> isize;
+            pub fn recvmsg(fd: c_int, message: *mut MsgHdr, flags: c_int) -> isize;
+            pub fn getsockopt(fd: c_int, level: c_int, name: c_int, value: *mut c_void, len: *mut u32) -> c_int;
+            pub fn getuid() -> u32;
+            pub fn geteuid() -> u32;
+            pub fn getgid() -> u32;
+            pub fn getegid() -> u32;
+        }
+    }
+
+    const ACCEPT_POLL: Duration = Duration::from_millis(50);
+    const EIO: i32 = 5; // For errors without an errno of their own
+    const EACCES: i32 = 13; // For paths outside the source roots
+
+    // What a stat answer says about a path
+    struct Stat {
+        kind: SourceKind,
+        metadata: SourceMetadata,
+    }
+
+    struct Helper {
+        socket: UnixStream,
+        child: Child,
+    }
+
+    pub struct ElevatedSource {
+        helper: Mutex<Helper>,
+        used: AtomicU64, // Files and directories the helper was asked about
+    }
+
+    fn denied<T>(result: &io::Result<T>) -> bool {
+        matches!(result, Err(e) if e.kind() == ErrorKind::PermissionDenied)
+    }
+
+    impl ElevatedSource {
+        // Start the helper (sudo asks for its password here, before the scan) and wait for it
+        pub fn start(how: &str, roots: &[PathBuf]) -> io::Result<ElevatedSource> {
+            let dir = env::temp_dir().join(format!("makeiso-helper-{}", std::process::id()));
+            DirBuilder::new().mode(0o700).create(&dir).map_err(|e| io::Error::new(e.kind(), format!("could not create {}: {}", dir.display(), e)))?;
+            let socket_path = dir.join("socket");
+            let connected = UnixListener::bind(&socket_path).and_then(|listener| {
+                let mut command = match how {
+                    "sudo" | "doas" => {
+                        let mut command = Command::new(how);
+                        command.arg(env::current_exe()?);
+                        command
+                    }
+                    program => Command::new(program),
+                };
+                let roots = roots.iter().map(std::path::absolute).collect::<io::Result<Vec<_>>>()?;
+                let mut child = command.arg("read-helper").arg(&socket_path).args(&roots).spawn().map_err(|e| io::Error::new(e.kind(), format!("could not run the --elevate helper {}: {}", how, e)))?;
+                listener.set_nonblocking(true)?;
+                loop {
+                    match listener.accept() {
+                        Ok((socket, _)) => {
+                            socket.set_nonblocking(false)?;
+                            return Ok(Helper { socket, child });
+                        }
+                        Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                        Err(e) => return Err(e),
+                    }
+                    if let Some(status) = child.try_wait()? {
+                        return Err(io::Error::new(ErrorKind::PermissionDenied, format!("the --elevate helper exited with {} before it connected", status)));
+                    }
+                    thread::sleep(ACCEPT_POLL);
+                }
+            });
+            let _ = fs::remove_dir_all(&dir);
+            Ok(ElevatedSource { helper: Mutex::new(connected?), used: AtomicU64::new(0) })
+        }
+
+        // Send a request: its kind and the path, length first
+        fn request(&self, helper: &mut Helper, kind: u8, path: &Path) -> io::Result<()> {
+            self.used.fetch_add(1, Ordering::Relaxed);
+            let path = path.as_os_str().as_bytes();
+            let mut message = vec![kind];
+            message.extend_from_slice(&(path.len() as u32).to_le_bytes());
+            message.extend_from_slice(path);
+            helper.socket.write_all(&message)?;
+            Ok(())
+        }
+
+        fn stat(&self, path: &Path) -> io::Result<Stat> {
+            let mut helper = self.helper.lock().unwrap_or_else(|e| e.into_inner());
+            self.request(&mut helper, b'S', path)?;
+            read_status(&mut helper.socket)?;
+            let mut answer = [0u8; 37];
+            helper.socket.read_exact(&mut answer)?;
+            let number = |at: usize| u64::from_le_bytes(answer[at..at + 8].try_into().expect("8 bytes"));
+            let kind = match answer[0] {
+                0 => SourceKind::File,
+                1 => SourceKind::Directory,
+                _ => SourceKind::Other,
+            };
+            let (seconds, nanos) = (number(9) as i64, u32::from_le_bytes(answer[17..21].try_into().expect("4 bytes")));
+            let modified = if seconds >= 0 { UNIX_EPOCH + Duration::new(seconds as u64, nanos) } else { UNIX_EPOCH - Duration::from_secs(seconds.unsigned_abs()) + Duration::from_nanos(nanos as u64) };
+            Ok(Stat { kind, metadata: SourceMetadata { len: number(1), modified: Some(modified), identity: Some((number(21), number(29))) } })
+        }
+
+        fn list(&self, dir: &Path) -> io::Result<Vec<OsString>> {
+            let mut helper = self.helper.lock().unwrap_or_else(|e| e.into_inner());
+            self.request(&mut helper, b'L', dir)?;
+            read_status(&mut helper.socket)?;
+            let count = read_u32(&mut helper.socket)?;
+            (0..count)
+                .map(|_| {
+                    let mut name = vec![0u8; read_u32(&mut helper.socket)? as usize];
+                    helper.socket.read_exact(&mut name)?;
+                    Ok(OsString::from_vec(name))
+                })
+                .collect()
+        }
+
+        fn open_file(&self, path: &Path) -> io::Result<File> {
+            let mut helper = self.helper.lock().unwrap_or_else(|e| e.into_inner());
+            self.request(&mut helper, b'O', path)?;
+            let (status, fd) = receive_fd(&helper.socket)?;
+            if status != 0 {
+                return Err(read_errno(&mut helper.socket));
+            }
+            fd.map(File::from).ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "the --elevate helper opened the file but sent no descriptor"))
+        }
+
+        // A listed entry, looked up by the helper when this user couldn't
+        fn rescan(&self, entry: ScannedEntry) -> ScannedEntry {
+            if !denied(&entry.metadata) {
+                return entry;
+            }
+            match self.stat(&entry.path) {
+                Ok(stat) => ScannedEntry { path: entry.path, kind: stat.kind, metadata: Ok(stat.metadata) },
+                Err(_) => entry,
+            }
+        }
+
+        // The local metadata of a path, or the helper's
+        fn lookup(&self, path: &Path) -> Option<Stat> {
+            match fs::metadata(path) {
+                Ok(_) => None,
+                Err(e) if e.kind() == ErrorKind::PermissionDenied => self.stat(path).ok(),
+                Err(_) => None,
+            }
+        }
+    }
+
+    impl SourceProvider for ElevatedSource {
+        fn entries(&self, dir: &Path) -> io::Result<Vec<io::Result<ScannedEntry>>> {
+            let entries = LocalSource.entries(dir);
+            if !denied(&entries) {
+                return Ok(entries?.into_iter().map(|entry| entry.map(|entry| self.rescan(entry))).collect());
+            }
+            let names = self.list(dir).map_err(|_| entries.err().expect("denied"))?;
+            Ok(names.into_iter().map(|name| Ok(self.rescan(scan_local(dir.join(name))))).collect())
+        }
+
+        fn kind(&self, path: &Path) -> SourceKind {
+            self.lookup(path).map_or_else(|| LocalSource.kind(path), |stat| stat.kind)
+        }
+
+        fn directory_modified(&self, dir: &Path) -> Option<SystemTime> {
+            self.lookup(dir).map_or_else(|| LocalSource.directory_modified(dir), |stat| stat.metadata.modified)
+        }
+
+        fn file_metadata(&self, path: &Path) -> io::Result<SourceMetadata> {
+            let metadata = LocalSource.file_metadata(path);
+            if !denied(&metadata) {
+                return metadata;
+            }
+            self.stat(path).map(|stat| stat.metadata).or(metadata)
+        }
+
+        fn open(&self, path: &Path) -> io::Result<SourceFile> {
+            let file = LocalSource.open(path);
+            if !denied(&file) {
+                return file;
+            }
+            self.open_file(path).map(SourceFile::Local).or(file)
+        }
+
+        fn xattrs(&self, path: &Path) -> io::Result<xattr::Attributes> {
+            LocalSource.xattrs(path)
+        }
+
+        fn device(&self, path: &Path) -> Option<u64> {
+            self.lookup(path).map_or_else(|| LocalSource.device(path), |stat| stat.metadata.identity.map(|(device, _)| device))
+        }
+
+        fn is_symlink(&self, path: &Path) -> bool {
+            LocalSource.is_symlink(path)
+        }
+
+        fn directory_identity(&self, path: &Path) -> Option<FileIdentity> {
+            self.lookup(path).map_or_else(|| LocalSource.directory_identity(path), |stat| stat.metadata.identity)
+        }
+
+        fn link_target(&self, path: &Path) -> io::Result<(String, Option<SystemTime>)> {
+            LocalSource.link_target(path)
+        }
+    }
+
+    impl Drop for ElevatedSource {
+        fn drop(&mut self) {
+            let helper = self.helper.get_mut().unwrap_or_else(|e| e.into_inner());
+            // The helper stops at the end of its input
+            let _ = helper.socket.shutdown(std::net::Shutdown::Both);
+            let _ = helper.child.wait();
+            let used = *self.used.get_mut();
+            if used > 0 {
+                events::info(&format!("The --elevate helper answered {} requests for what this user couldn't read", used));
+            }
+        }
+    }
+
+    fn read_u32(socket: &mut UnixStream) -> io::Result<u32> {
+        let mut bytes = [0u8; 4];
+        socket.read_exact(&mut bytes)?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    fn read_status(socket: &mut UnixStream) -> io::Result<()> {
+        let mut status = [0u8];
+        socket.read_exact(&mut status)?;
+        match status[0] {
+            0 => Ok(()),
+            _ => Err(read_errno(socket)),
+        }
+    }
+
+    fn read_errno(socket: &mut UnixStream) -> io::Error {
+        match read_u32(socket) {
+            Ok(errno) => io::Error::from_raw_os_error(errno as i32),
+            Err(e) => e,
+        }
+    }
+
+    // The status byte of an open answer and the descriptor that came with it
+    fn receive_fd(socket: &UnixStream) -> io::Result<(u8, Option<OwnedFd>)> {
+        let mut status = 0u8;
+        let mut iov = sys::IoVec { base: (&mut status as *mut u8).cast(), len: 1 };
+        let mut control = sys::FdMessage::default();
+        let mut message = sys::MsgHdr {
+            name: std::ptr::null_mut(),
+            name_len: 0,
+            iov: &mut iov,
+            iov_len: 1,
+            control: (&mut control as *mut sys::FdMessage).cast(),
+            control_len: std::mem::size_of::<sys::FdMessage>(),
+            flags: 0,
+        };
+        // SAFETY: the message points at the status byte and a control buffer of the size given
+        let received = unsafe { sys::recvmsg(socket.as_raw_fd(), &mut message, 0) };
+        if received < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if received == 0 {
+            return Err(io::Error::new(ErrorKind::UnexpectedEof, "the --elevate helper stopped"));
+        }
+        let has_fd = message.control_len >= sys::FD_MESSAGE_LEN && control.level == sys::SOL_SOCKET && control.kind == sys::SCM_RIGHTS;
+        // SAFETY: the kernel put a descriptor of this process in the control message
+        Ok((status, has_fd.then(|| unsafe { OwnedFd::from_raw_fd(control.fd) })))
+    }
+
+    fn send_fd(socket: &UnixStream, fd: RawFd) -> io::Result<()> {
+        let mut status = 0u8;
+        let mut iov = sys::IoVec { base: (&mut status as *mut u8).cast(), len: 1 };
+        let mut control = sys::FdMessage { len: sys::FD_MESSAGE_LEN, level: sys::SOL_SOCKET, kind: sys::SCM_RIGHTS, fd, padding: 0 };
+        let message = sys::MsgHdr {
+            name: std::ptr::null_mut(),
+            name_len: 0,
+            iov: &mut iov,
+            iov_len: 1,
+            control: (&mut control as *mut sys::FdMessage).cast(),
+            control_len: std::mem::size_of::<sys::FdMessage>(),
+            flags: 0,
+        };
+        // SAFETY: the message points at the status byte and a control message of the size given
+        if unsafe { sys::sendmsg(socket.as_raw_fd(), &message, 0) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    // The user the helper works for: who sudo or doas ran it for, or whoever runs it
+    fn requesting_user() -> io::Result<u32> {
+        // SAFETY: getuid can't fail
+        let uid = unsafe { sys::getuid() };
+        if uid != 0 {
+            return Ok(uid);
+        }
+        if let Some(sudo_uid) = env::var_os("SUDO_UID") {
+            return sudo_uid.to_str().and_then(|text| text.parse().ok()).ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "SUDO_UID is not a user id"));
+        }
+        if let Some(user) = env::var_os("DOAS_USER") {
+            let passwd = fs::read_to_string("/etc/passwd")?;
+            let uid = passwd.lines().find_map(|line| {
+                let fields: Vec<&str> = line.split(':').collect();
+                (fields.len() > 2 && user.as_bytes() == fields[0].as_bytes()).then(|| fields[2].parse().ok()).flatten()
+            });
+            return uid.ok_or_else(|| io::Error::new(ErrorKind::NotFound, format!("DOAS_USER {} is not in /etc/passwd", user.to_string_lossy())));
+        }
+        Ok(0)
+    }
+
+    // Refuse to run with rights the one who started it didn't have to ask for
+    fn check_not_setuid() -> io::Result<()> {
+        // SAFETY: none of these can fail
+        let (uid, euid, gid, egid) = unsafe { (sys::getuid(), sys::geteuid(), sys::getgid(), sys::getegid()) };
+        let status = fs::read_to_string("/proc/self/status")?;
+        let capabilities = status.lines().find_map(|line| line.strip_prefix("CapEff:")).map(|hex| u64::from_str_radix(hex.trim(), 16).unwrap_or(u64::MAX));
+        if uid != euid || gid != egid || (uid != 0 && capabilities != Some(0)) {
+            return Err(io::Error::new(ErrorKind::PermissionDenied, "makeiso read-helper does not run setuid, setgid or with file capabilities; start it through sudo or doas (--elevate sudo)"));
+        }
+        Ok(())
+    }
+
+    // The socket's directory has to be the user's own and nobody else's
+    fn check_socket_directory(socket_path: &Path, user: u32) -> io::Result<()> {
+        let dir = socket_path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        let metadata = fs::symlink_metadata(dir)?;
+        if !metadata.is_dir() || metadata.uid() != user || metadata.mode() & 0o777 != 0o700 {
+            return Err(io::Error::new(ErrorKind::PermissionDenied, format!("{} is not a directory of user {} with mode 0700", dir.display(), user)));
+        }
+        Ok(())
+    }
+
+    // The user at the other end of the socket
+    fn peer_user(socket: &UnixStream) -> io::Result<u32> {
+        let mut credentials = sys::PeerCredentials::default();
+        let mut len = std::mem::size_of::<sys::PeerCredentials>() as u32;
+        // SAFETY: the buffer is a struct ucred of the length given
+        if unsafe { sys::getsockopt(socket.as_raw_fd(), sys::SOL_SOCKET, sys::SO_PEERCRED, (&mut credentials as *mut sys::PeerCredentials).cast(), &mut len) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(credentials.uid)
+    }
+
+    // Where a path leads, if that is under one of the roots
+    fn within(path: &Path, roots: &[PathBuf]) -> io::Result<PathBuf> {
+        let resolved = fs::canonicalize(path)?;
+        if roots.iter().any(|root| resolved.starts_with(root)) {
+            Ok(resolved)
+        } else {
+            Err(io::Error::from_raw_os_error(EACCES))
+        }
+    }
+
+    // Open a resolved path without following a link put in its place since, and check where the
+    // descriptor leads
+    fn open_within(path: &Path, roots: &[PathBuf]) -> io::Result<File> {
+        let file = fs::OpenOptions::new().read(true).custom_flags(sys::O_NOFOLLOW).open(within(path, roots)?)?;
+        let opened = fs::read_link(format!("/proc/self/fd/{}", file.as_raw_fd()))?;
+        if !roots.iter().any(|root| opened.starts_with(root)) {
+            return Err(io::Error::from_raw_os_error(EACCES));
+        }
+        Ok(file)
+    }
+
+    // makeiso read-helper SOCKET ROOT...: answer the requests of the build that started it,
+    // for what is under the roots
+    pub fn helper(args: &[String]) -> io::Result<()> {
+        let [socket_path, roots @ ..] = args else {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "Usage: makeiso read-helper <socket> <root>... (started by --elevate)"));
+        };
+        if roots.is_empty() {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "Usage: makeiso read-helper <socket> <root>... (started by --elevate)"));
+        }
+        check_not_setuid()?;
+        let user = requesting_user()?;
+        let socket_path = Path::new(socket_path);
+        check_socket_directory(socket_path, user)?;
+        let roots = roots.iter().map(fs::canonicalize).collect::<io::Result<Vec<_>>>()?;
+        let mut socket = UnixStream::connect(socket_path)?;
+        let peer = peer_user(&socket)?;
+        if peer != user {
+            return Err(io::Error::new(ErrorKind::PermissionDenied, format!("the socket is held by user {}, not user {}", peer, user)));
+        }
+        loop {
+            let mut header = [0u8; 5];
+            match socket.read_exact(&mut header) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+                Err(e) => return Err(e),
+            }
+            let mut path = vec![0u8; u32::from_le_bytes(header[1..].try_into().expect("4 bytes")) as usize];
+            socket.read_exact(&mut path)?;
+            let path = PathBuf::from(OsString::from_vec(path));
+            let answer = match header[0] {
+                b'S' => within(&path, &roots).and_then(fs::metadata).map(|metadata| {
+                    let mut answer = vec![0u8];
+                    answer.push(if metadata.is_file() { 0 } else if metadata.is_dir() { 1 } else { 2 });
+                    answer.extend_from_slice(&metadata.len().to_le_bytes());
+                    answer.extend_from_slice(&metadata.mtime().to_le_bytes());
+                    answer.extend_from_slice(&(metadata.mtime_nsec() as u32).to_le_bytes());
+                    answer.extend_from_slice(&metadata.dev().to_le_bytes());
+                    answer.extend_from_slice(&metadata.ino().to_le_bytes());
+                    answer
+                }),
+                b'L' => within(&path, &roots).and_then(fs::read_dir).and_then(|listing| {
+                    let names = listing.map(|entry| entry.map(|entry| entry.file_name())).collect::<io::Result<Vec<_>>>()?;
+                    let mut answer = vec![0u8];
+                    answer.extend_from_slice(&(names.len() as u32).to_le_bytes());
+                    for name in names {
+                        answer.extend_from_slice(&(name.len() as u32).to_le_bytes());
+                        answer.extend_from_slice(name.as_bytes());
+                    }
+                    Ok(answer)
+                }),
+                b'O' => match open_within(&path, &roots) {
+                    Ok(file) => {
+                        send_fd(&socket, file.as_raw_fd())?;
+                        continue;
+                    }
+                    Err(e) => Err(e),
+                },
+                _ => return Err(io::Error::new(ErrorKind::InvalidData, "unknown request from the build")),
+            };
+            let answer = answer.unwrap_or_else(|e| {
+                let mut answer = vec![1u8];
+                answer.extend_from_slice(&(e.raw_os_error().unwrap_or(EIO) as u32).to_le_bytes());
+                answer
+            });
+            socket.write_all(&answer)?;
+        }
+    }
+}
+
+#[cfg(not(all(target_os = "linux", target_pointer_width = "64")))]
+pub fn elevated_source(_how: &str, _roots: &[PathBuf]) -> io::Result<Box<dyn SourceProvider>> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "--elevate is only supported on Linux"))
+}
+
+#[cfg(not(all(target_os = "linux", target_pointer_width = "64")))]
+pub fn helper(_args: &[String]) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "the read helper is only supported on Linux"))
+}
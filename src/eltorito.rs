@@ -0,0 +1,176 @@
+// El Torito booting: a boot record volume descriptor pointing at a boot catalog, whose entries
+// have the BIOS (and UEFI firmware) load a file of the image in no-emulation mode
+//
+// Boot loaders that find the rest of themselves through the file system need to be told
+// where they are. isolinux reads a boot info table that mkisofs patches into bytes 8-63 of the
+// boot file (--boot-info-table); GRUB's eltorito.img reads where the rest of its core image is,
+// as a 512-byte sector address, at byte 2548 (--grub2-boot-info). Without them both hang after
+// loading.
+
+use std::io::{self, ErrorKind, Read};
+
+use super::backend::SourceProvider;
+use super::{Entry, FileEntry, ImageTree, Layout, BLOCK_SIZE, CD001, SYSTEM_AREA_BLOCKS};
+
+const BOOT_RECORD: u8 = 0;
+const EL_TORITO: &[u8] = b"EL TORITO SPECIFICATION";
+const PLATFORM_X86: u8 = 0;
+const PLATFORM_EFI: u8 = 0xEF;
+const CATALOG_ENTRY_SIZE: usize = 32;
+const VIRTUAL_SECTOR_SIZE: usize = 512; // Boot images are loaded in 512-byte sectors
+const GRUB2_BOOT_INFO_OFFSET: usize = 2548;
+const GRUB2_CORE_SECTOR: u64 = 5; // Where core.img continues, in 512-byte sectors into the boot file
+
+// How the image is made bootable
+#[derive(Debug, Clone, PartialEq)]
+pub struct BootOptions {
+    pub image: Option<String>,     // Path of the BIOS boot file from the root of the source, e.g. isolinux/isolinux.bin
+    pub efi_image: Option<String>, // Path of the EFI boot image (a FAT file system image), e.g. boot/efiboot.img
+    pub load_size: Option<u16>,    // 512-byte sectors the BIOS loads (the whole file when not given)
+    pub info_table: bool,
+    pub grub2_boot_info: bool,
+}
+
+// Where the boot catalog and the boot file ended up
+#[derive(Debug)]
+pub struct BootLayout {
+    pub catalog: u32,
+    pub file: Option<(usize, usize)>,     // Directory index and entry index of the BIOS boot file
+    pub efi_file: Option<(usize, usize)>, // The same for the EFI boot image
+    pub contents: Vec<u8>,                // The BIOS boot file with its tables patched in, read before writing
+}
+
+// Find the boot file in the tree by its path
+pub fn find_boot_file(tree: &ImageTree, path: &str) -> io::Result<(usize, usize)> {
+    let not_found = || io::Error::new(ErrorKind::NotFound, format!("boot image {} is not in the source tree", path));
+    let mut components: Vec<&str> = path.split('/').filter(|component| !component.is_empty()).collect();
+    let name = components.pop().ok_or_else(not_found)?;
+
+    let mut dir = 0;
+    for component in components {
+        dir = tree.dirs[dir]
+            .entries
+            .iter()
+            .find_map(|entry| match entry {
+                Entry::Directory(child) if tree.dirs[*child].name == component => Some(*child),
+                Entry::Relocated { name, target } if name == component => Some(*target),
+                _ => None,
+            })
+            .ok_or_else(not_found)?;
+    }
+    let entry = tree.dirs[dir].entries.iter().position(|entry| matches!(entry, Entry::File(file) if file.name == name)).ok_or_else(not_found)?;
+    Ok((dir, entry))
+}
+
+// The file entry at a position found by find_boot_file
+pub fn boot_file(tree: &ImageTree, (dir, entry): (usize, usize)) -> &FileEntry {
+    match &tree.dirs[dir].entries[entry] {
+        Entry::File(file) => file,
+        _ => unreachable!("boot files are file entries"),
+    }
+}
+
+// Read the BIOS boot file and patch in what the options ask for, once its extent is known
+pub fn load_boot_image(tree: &ImageTree, layout: &mut Layout, source: &dyn SourceProvider, options: &BootOptions) -> io::Result<()> {
+    let Some(boot) = &mut layout.boot else {
+        return Ok(());
+    };
+    let Some(position) = boot.file else {
+        return Ok(());
+    };
+    let file = boot_file(tree, position);
+
+    let mut contents = Vec::with_capacity(file.size as usize);
+    source.open(&file.source)?.take(file.size as u64).read_to_end(&mut contents)?;
+    if contents.len() != file.size as usize {
+        return Err(io::Error::other(format!("{} changed since it was scanned", file.source.display())));
+    }
+    if options.info_table {
+        patch_boot_info_table(&mut contents, file)?;
+    }
+    if options.grub2_boot_info {
+        patch_grub2_boot_info(&mut contents, file)?;
+    }
+    boot.contents = contents;
+    Ok(())
+}
+
+// Bytes 8-63: volume descriptor LBA, boot file LBA, boot file length and the 32-bit sum of
+// the file from byte 64 on, then 40 reserved bytes
+fn patch_boot_info_table(contents: &mut [u8], file: &FileEntry) -> io::Result<()> {
+    if contents.len() < 64 {
+        return Err(io::Error::new(ErrorKind::InvalidInput, format!("{} is too small for a boot info table", file.source.display())));
+    }
+    let checksum = contents[64..].chunks(4).fold(0u32, |sum, word| {
+        let mut bytes = [0u8; 4];
+        bytes[..word.len()].copy_from_slice(word);
+        sum.wrapping_add(u32::from_le_bytes(bytes))
+    });
+    contents[8..12].copy_from_slice(&SYSTEM_AREA_BLOCKS.to_le_bytes());
+    contents[12..16].copy_from_slice(&file.extent.to_le_bytes());
+    contents[16..20].copy_from_slice(&file.size.to_le_bytes());
+    contents[20..24].copy_from_slice(&checksum.to_le_bytes());
+    contents[24..64].fill(0);
+    Ok(())
+}
+
+// Byte 2548: the 512-byte sector address of the rest of GRUB's core image, as 64 bits
+fn patch_grub2_boot_info(contents: &mut [u8], file: &FileEntry) -> io::Result<()> {
+    if contents.len() < GRUB2_BOOT_INFO_OFFSET + 8 {
+        return Err(io::Error::new(ErrorKind::InvalidInput, format!("{} is too small for GRUB2 boot info", file.source.display())));
+    }
+    let sector = file.extent as u64 * (BLOCK_SIZE / VIRTUAL_SECTOR_SIZE) as u64 + GRUB2_CORE_SECTOR;
+    contents[GRUB2_BOOT_INFO_OFFSET..GRUB2_BOOT_INFO_OFFSET + 8].copy_from_slice(&sector.to_le_bytes());
+    Ok(())
+}
+
+// The boot record volume descriptor, which only says where the catalog is
+pub fn boot_record(catalog: u32) -> Vec<u8> {
+    let mut descriptor = vec![0u8; BLOCK_SIZE];
+    descriptor[0] = BOOT_RECORD;
+    descriptor[1..6].copy_from_slice(CD001);
+    descriptor[6] = 1;
+    descriptor[7..7 + EL_TORITO.len()].copy_from_slice(EL_TORITO);
+    descriptor[71..75].copy_from_slice(&catalog.to_le_bytes());
+    descriptor
+}
+
+// The boot catalog: a validation entry and the default entry for the BIOS boot file, and a
+// section for UEFI with the EFI boot image (which is the default entry when there's no BIOS one)
+pub fn boot_catalog(bios: Option<&FileEntry>, efi: Option<&FileEntry>, load_size: Option<u16>) -> Vec<u8> {
+    let mut catalog = vec![0u8; BLOCK_SIZE];
+
+    // Validation entry: header ID, platform, key bytes, and a checksum making the 16-bit
+    // words of the entry sum to zero
+    catalog[0] = 1;
+    catalog[1] = if bios.is_some() { PLATFORM_X86 } else { PLATFORM_EFI };
+    catalog[30] = 0x55;
+    catalog[31] = 0xAA;
+    let sum = catalog[..32].chunks(2).fold(0u16, |sum, word| sum.wrapping_add(u16::from_le_bytes([word[0], word[1]])));
+    catalog[28..30].copy_from_slice(&0u16.wrapping_sub(sum).to_le_bytes());
+
+    let whole_file = |file: &FileEntry| (file.size as usize).div_ceil(VIRTUAL_SECTOR_SIZE).min(u16::MAX as usize) as u16;
+    match (bios, efi) {
+        (Some(bios), efi) => {
+            boot_entry(&mut catalog[CATALOG_ENTRY_SIZE..], bios, load_size.unwrap_or_else(|| whole_file(bios)));
+            if let Some(efi) = efi {
+                // The last (and only) section header, with one entry
+                let header = &mut catalog[2 * CATALOG_ENTRY_SIZE..3 * CATALOG_ENTRY_SIZE];
+                header[0] = 0x91;
+                header[1] = PLATFORM_EFI;
+                header[2..4].copy_from_slice(&1u16.to_le_bytes());
+                boot_entry(&mut catalog[3 * CATALOG_ENTRY_SIZE..], efi, whole_file(efi));
+            }
+        }
+        (None, Some(efi)) => boot_entry(&mut catalog[CATALOG_ENTRY_SIZE..], efi, whole_file(efi)),
+        (None, None) => {}
+    }
+    catalog
+}
+
+// A bootable no-emulation entry at the default load segment (0x7C0)
+fn boot_entry(entry: &mut [u8], file: &FileEntry, sectors: u16) {
+    entry[0] = 0x88;
+    entry[6..8].copy_from_slice(&sectors.to_le_bytes());
+    entry[8..12].copy_from_slice(&file.extent.to_le_bytes());
+}
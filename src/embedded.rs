@@ -0,0 +1,75 @@
+// The .makeiso directory at the root of the image (--embed-metadata, on by default for
+// makeiso run): info.json says which makeiso made the image, when, on which host, from which
+// command line (both null and empty with --reproducible) and profile, and catalog.json lists what the sources held, so an image found
+// years later describes itself without the records of the machine that made it.
+//
+// The catalog is taken after the scan, before the layout, so it has no block addresses (the
+// catalog subcommand reads those from the finished image) and leaves out the files makeiso
+// generates itself, TRANS.TBL and the like.
+
+use std::env;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+use std::time::SystemTime;
+
+use super::notify::host_name;
+use super::{add_directory, entry_name, json, original_path, time_text, unix_time, Entry, ImageOptions, ImageTree};
+
+pub const DIRECTORY: &str = ".makeiso";
+const INFO: &str = "info.json";
+const CATALOG: &str = "catalog.json";
+
+// Add the directory with both files to the root of a scanned tree
+pub fn add(tree: &mut ImageTree, options: &ImageOptions) -> io::Result<()> {
+    if tree.dirs[0].entries.iter().any(|entry| entry_name(tree, entry) == DIRECTORY) {
+        return Err(io::Error::new(ErrorKind::InvalidInput, format!("the sources already have a /{} (leave it out or use --no-embed-metadata)", DIRECTORY)));
+    }
+    let catalog = catalog(tree);
+    // The host and the command line differ between two builds of the same tree, so a
+    // reproducible image leaves them out
+    let (host, command_line) = if options.reproducible {
+        ("null".to_string(), Vec::new())
+    } else {
+        (json::string(&host_name()), env::args().map(|arg| json::string(&arg)).collect())
+    };
+    let info = format!(
+        "{{\"tool\":\"makeiso\",\"version\":\"{}\",\"created\":{},\"host\":{},\"command_line\":[{}],\"profile\":{},\"volume_id\":{}}}\n",
+        env!("CARGO_PKG_VERSION"),
+        json::string(&time_text(tree.image_time)),
+        host,
+        command_line.join(","),
+        options.run_profile.as_deref().map_or("null".to_string(), json::string),
+        json::string(&options.identity.volume_id)
+    );
+
+    let dir = add_directory(tree, Path::new(&format!("/{}", DIRECTORY)), DIRECTORY.to_string(), 0, None, options.deep_dirs)?;
+    tree.add_generated(dir, INFO, info.into_bytes());
+    tree.add_generated(dir, CATALOG, catalog.into_bytes());
+    Ok(())
+}
+
+// Every directory and file of the tree where the sources had it, with its size and
+// modification time
+fn catalog(tree: &ImageTree) -> String {
+    let time = |modified: Option<SystemTime>| json::string(&time_text(if tree.fixed_times { tree.image_time } else { modified.map_or(tree.image_time, unix_time) }));
+    let mut lines = Vec::new();
+    for (index, dir) in tree.dirs.iter().enumerate().filter(|&(index, _)| Some(index) != tree.relocation_dir) {
+        let path = original_path(tree, index);
+        if index != 0 {
+            let path = path.trim_end_matches('/').to_string();
+            lines.push((path.clone(), format!("{{\"path\":{},\"type\":\"directory\",\"modified\":{}}}", json::string(&path), time(dir.modified))));
+        }
+        for entry in &dir.entries {
+            let Entry::File(file) = entry else { continue };
+            if tree.generated.get(&index).is_some_and(|files| files.contains_key(&file.name)) {
+                continue;
+            }
+            let path = format!("{}{}", path, file.name);
+            lines.push((path.clone(), format!("{{\"path\":{},\"type\":\"file\",\"size\":{},\"modified\":{}}}", json::string(&path), file.size, time(file.modified))));
+        }
+    }
+    // By path, so the order the scan found them in doesn't matter
+    lines.sort();
+    let lines: Vec<String> = lines.into_iter().map(|(_, line)| line).collect();
+    format!("{{\"entries\":[\n{}\n]}}\n", lines.join(",\n"))
+}
@@ -0,0 +1,115 @@
+// makeiso estimate: scan and lay out a source tree the way building the image would, without
+// writing anything, and print how large the image will be and where the space goes
+//
+// The total is exact for the options given (the same layout is used to write), so it counts
+// the system area, the volume descriptors, both path tables and every directory extent, each
+// file rounded up to whole blocks, alignment gaps and padding, not just the file sizes.
+
+use std::io::{self, ErrorKind};
+
+use super::{blocks_for, dedup, descriptor_set_blocks, new_tree, open_sources, parse_args, plan_image, scan_sources, udf, Entry, ImageTree, BLOCK_SIZE, STANDARD_MEDIA, SYSTEM_AREA_BLOCKS};
+
+// makeiso estimate [options] <source> [source ...]
+pub fn estimate(args: &[String]) -> io::Result<()> {
+    let options = parse_args(args)?;
+    let mut sources = options.sources;
+    sources.extend(options.iso_path); // Every path is a source here
+    if sources.is_empty() {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "Usage: makeiso estimate [options] <source> [source ...]"));
+    }
+    let mut image = options.image;
+    let media = image.media.take(); // Reported on below rather than refused
+    image.single_pass = false; // Laid out up front, the image takes the same space
+
+    let source = open_sources(&sources, &image)?;
+    let mut tree = new_tree(&image);
+    scan_sources(&mut tree, source.as_ref(), &sources, &image)?;
+    let layout = plan_image(&mut tree, &image)?;
+
+    let descriptor_blocks = SYSTEM_AREA_BLOCKS + descriptor_set_blocks(&image) + layout.boot.is_some() as u32;
+    let path_table_blocks = 2 * blocks_for(layout.path_table_size as u64);
+    let directory_blocks: u32 = layout.order.iter().map(|&index| blocks_for(tree.dirs[index].size as u64) + tree.dirs[index].continuation).sum();
+    let (file_count, file_bytes, file_blocks) = layout.files.iter().fold((0u64, 0u64, 0u32), |(count, bytes, blocks), &(index, entry)| match &tree.dirs[index].entries[entry] {
+        Entry::File(file) => (count + 1, bytes + file.size as u64, blocks + blocks_for(file.size as u64)),
+        _ => (count, bytes, blocks),
+    });
+    let gpt_blocks = if layout.gpt_backup.is_some() { super::hybrid::GPT_BACKUP_BLOCKS } else { 0 };
+    // The UDF descriptors and anchors sit in a fixed area, up to block 256, and the last block
+    let udf_blocks = layout.udf.as_ref().map_or(0, |udf| udf::PARTITION_START - descriptor_blocks + udf.blocks + 1);
+    let appended_blocks = layout.appended.first().map_or(0, |&start| layout.total_blocks - gpt_blocks - layout.udf.is_some() as u32 - start);
+    let gap_blocks =
+        layout.total_blocks - descriptor_blocks - path_table_blocks - directory_blocks - file_blocks - layout.padding_blocks - gpt_blocks - udf_blocks - appended_blocks;
+
+    let total_bytes = layout.total_blocks as u64 * BLOCK_SIZE as u64;
+    println!("Estimated image size: {} bytes ({} blocks of {} bytes)", total_bytes, layout.total_blocks, BLOCK_SIZE);
+    let line = |what: &str, blocks: u32| println!("  {:<38} {:>10} blocks {:>14} bytes", what, blocks, blocks as u64 * BLOCK_SIZE as u64);
+    line("System area and volume descriptors", descriptor_blocks);
+    if udf_blocks > 0 {
+        line("UDF bridge", udf_blocks);
+    }
+    line("Path tables", path_table_blocks);
+    line(&format!("Directory extents ({} directories)", layout.order.len()), directory_blocks);
+    line(&format!("File contents ({} files, {} bytes)", file_count, file_bytes), file_blocks);
+    if gap_blocks > 0 {
+        line("Alignment gaps", gap_blocks);
+    }
+    if layout.padding_blocks > 0 {
+        line("Padding", layout.padding_blocks);
+    }
+    if appended_blocks > 0 {
+        line(&format!("Appended partitions ({})", layout.appended.len()), appended_blocks);
+    }
+    if gpt_blocks > 0 {
+        line("Backup GPT", gpt_blocks);
+    }
+
+    println!("By top-level entry:");
+    let mut root_files = 0;
+    for entry in &tree.dirs[0].entries {
+        match entry {
+            Entry::File(file) => root_files += blocks_for(file.size as u64),
+            Entry::Directory(child) | Entry::Relocated { target: child, .. } => line(&format!("/{}", tree.dirs[*child].name), subtree_blocks(&tree, *child)),
+            Entry::Symlink { .. } | Entry::Special { .. } => {}
+        }
+    }
+    if root_files > 0 {
+        line("(files in the root)", root_files);
+    }
+
+    match media {
+        Some(media) => {
+            let verdict = if total_bytes <= media.capacity { "fits on" } else { "does not fit on" };
+            println!("The image {} {} ({} bytes)", verdict, media.name, media.capacity);
+        }
+        None => {
+            let fits: Vec<&str> = STANDARD_MEDIA.iter().filter(|(_, sectors)| total_bytes <= sectors * BLOCK_SIZE as u64).map(|(name, _)| *name).collect();
+            if fits.is_empty() {
+                println!("The image is larger than any standard medium");
+            } else {
+                println!("Fits on: {}", fits.join(", "));
+            }
+        }
+    }
+    if image.dedup_report {
+        dedup::report(&tree, source.as_ref())?;
+    }
+    Ok(())
+}
+
+// Blocks of a directory extent and everything below it; relocated directories count where
+// they belong rather than under rr_moved
+fn subtree_blocks(tree: &ImageTree, index: usize) -> u32 {
+    let dir = &tree.dirs[index];
+    blocks_for(dir.size as u64)
+        + dir.continuation
+        + dir
+            .entries
+            .iter()
+            .map(|entry| match entry {
+                Entry::File(file) => blocks_for(file.size as u64),
+                Entry::Directory(child) if tree.dirs[*child].original_parent.is_some() => 0,
+                Entry::Directory(child) | Entry::Relocated { target: child, .. } => subtree_blocks(tree, *child),
+                Entry::Symlink { .. } | Entry::Special { .. } => 0,
+            })
+            .sum::<u32>()
+}
@@ -0,0 +1,86 @@
+// Newline-delimited JSON events on stdout in place of the human-readable output (--json-events)
+//
+// Every line is one object with an "event" field, for front-ends and pipelines to follow:
+//
+//     {"event": "scan-started", "sources": ["/home"]}
+//     {"event": "file-added", "path": "/docs/a.txt", "size": 1234}
+//     {"event": "scan-progress", "directories": 12, "files": 340, "bytes": 1234}
+//     {"event": "write-started", "bytes": 1234}
+//     {"event": "percent", "percent": 41.5, "bytes": 512, "total": 1234}
+//     {"event": "written", "bytes": 1048576}
+//     {"event": "warning", "message": "Permission denied while accessing file: ..."}
+//     {"event": "info", "message": "Wrote out.iso.sha256"}
+//     {"event": "done", "image": "out.iso", "bytes": 2097152}
+//     {"event": "error", "message": "..."}
+//
+// Percent events come when the value (to hundredths) changes, not for every write; with
+// --single-pass there is no total, so written events come each MiB instead. The scan
+// reports its counts about once a second, and once more when it is done.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use super::json;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static WARNINGS: AtomicU64 = AtomicU64::new(0);
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+// Write one event; the values of the fields are JSON already
+pub fn emit(event: &str, fields: &[(&str, String)]) {
+    let mut line = format!("{{\"event\": \"{}\"", event);
+    for (name, value) in fields {
+        line += &format!(", \"{}\": {}", name, value);
+    }
+    line += "}\n";
+    // Lines are written whole, so events from worker threads don't interleave
+    let _ = io::stdout().lock().write_all(line.as_bytes());
+}
+
+// A warning, on stderr or as an event
+pub fn warn(message: &str) {
+    WARNINGS.fetch_add(1, Ordering::Relaxed);
+    if enabled() {
+        emit("warning", &[("message", json::string(message))]);
+    } else {
+        eprintln!("{}", message);
+    }
+}
+
+// How many warnings there have been so far
+pub fn warnings() -> u64 {
+    WARNINGS.load(Ordering::Relaxed)
+}
+
+// Something said along the way, on stdout or as an event
+pub fn info(message: &str) {
+    if enabled() {
+        emit("info", &[("message", json::string(message))]);
+    } else {
+        println!("{}", message);
+    }
+}
+
+// The image is finished, with its checksums and signature
+pub fn done(iso_path: &Path) {
+    if enabled() {
+        let bytes = fs::metadata(iso_path).map_or("null".to_string(), |metadata| metadata.len().to_string());
+        emit("done", &[("image", json::string(&iso_path.display().to_string())), ("bytes", bytes)]);
+    }
+}
+
+// The build failed; the error is still returned and printed as usual
+pub fn error(error: &io::Error) {
+    if enabled() {
+        emit("error", &[("message", json::string(&error.to_string()))]);
+    }
+}
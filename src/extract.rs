@@ -0,0 +1,721 @@
+// Extraction of files from an image, local or remote, into a directory
+//
+// When the image carries a SHA-256 manifest (SHA256SUMS and the like, as written by
+// sha256sum or by makeiso --checksums, see checksums.rs), every extracted file is checked
+// against it. Reads that fail, and files that
+// don't match, are tried again a few times, which is often enough for scratched discs; files
+// that still can't be recovered are reported at the end instead of stopping the extraction.
+//
+// With --salvage, blocks that can't be read at all are filled in and extraction goes on (see
+// salvage.rs). The directory tree is read first and its blocks are given more attempts than
+// file data, since a lost directory block loses every file listed in it.
+//
+// --include and --exclude narrow down what is extracted with globs over the paths and names
+// the image is read with (Rock Ridge or Joliet where it has them): a pattern with a "/"
+// matches the path from the root, others the name, and what matches an include or exclude
+// brings its whole subtree along.
+//
+// What is already in the destination is replaced by default; --overwrite never (or
+// --keep-existing) keeps it, newer replaces only what is older than the image's copy, and
+// prompt asks about each. Whatever was kept or replaced is listed at the end.
+//
+// Many small files make extraction wait on the destination rather than the image; with
+// --workers N the image is still read sequentially, but N threads create and write the files.
+// --ordered keeps them appearing in the order they are read, for sources like tapes where an
+// interrupted run should leave a clean prefix to resume from with --keep-existing.
+//
+// With --xattrs, the extended attributes recorded in the image's XATTRS.TXT (see xattr.rs)
+// are set on what is extracted; with --win-metadata, on Windows, the attributes, creation
+// times and streams in its WINMETA.TXT (see winmeta.rs).
+//
+// Files stored encrypted with --encrypt-glob (listed in ENCRYPTED.TXT, see secrets.rs) are
+// decrypted back to their own names when a passphrase is given, and left as .age files
+// otherwise.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+
+use super::reader::{lookup, open_source, IsoEntry, IsoReader, ReadAt, Truncation};
+use super::salvage::{Fill, Salvage};
+use super::secrets::{self, EXTENSION};
+use super::sha256::{self, Sha256};
+use super::tar::system_time;
+use super::unicode;
+use super::winmeta::{self, WINMETA_FILE};
+use super::xattr::{self, Attributes, XATTR_FILE};
+use super::{age, matches_any, BLOCK_SIZE};
+
+const COPY_CHUNK_SIZE: usize = 1024 * 1024;
+const DEFAULT_RETRIES: u32 = 2;
+const DIRECTORY_RETRY_FACTOR: u32 = 4; // Directory blocks get this many times the attempts when salvaging
+const CHUNKS_IN_FLIGHT: usize = 4; // Chunks read ahead of the thread writing a file
+const PART_SUFFIX: &str = ".makeiso-part"; // Name of a file being written with --ordered, after its own
+const MANIFEST_NAMES: [&str; 4] = ["/SHA256SUMS", "/SHA256SUMS.txt", "/sha256sum.txt", "/sha256sums.txt"];
+
+// What to do with a file or link that is already where an entry goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overwrite {
+    Always,
+    Never,
+    Newer, // Only when the image's copy was modified later
+    Prompt,
+}
+
+impl Overwrite {
+    fn parse(text: &str) -> io::Result<Overwrite> {
+        match text {
+            "always" => Ok(Overwrite::Always),
+            "never" => Ok(Overwrite::Never),
+            "newer" => Ok(Overwrite::Newer),
+            "prompt" => Ok(Overwrite::Prompt),
+            _ => Err(io::Error::new(ErrorKind::InvalidInput, format!("invalid --overwrite: {} (expected always, never, newer or prompt)", text))),
+        }
+    }
+}
+
+// Settings for one extraction
+pub struct ExtractOptions {
+    pub retries: u32,            // Further attempts at a failing read or a file that doesn't match the manifest
+    pub salvage: Option<Fill>,   // Fill in unreadable blocks instead of giving up on their files
+    pub report: Option<PathBuf>, // Where the map of unreadable blocks goes (next to the destination by default)
+    pub xattrs: bool,            // Restore extended attributes from XATTRS.TXT
+    pub win_metadata: bool,      // Restore Windows attributes, creation times and streams from WINMETA.TXT
+    pub include: Vec<String>,    // Globs of what to extract, everything when there are none
+    pub exclude: Vec<String>,    // Globs of what to leave out
+    pub overwrite: Overwrite,
+    pub workers: usize,          // Threads creating and writing the files, 1 to write them as they are read
+    pub ordered: bool,           // With workers, files appear in the order they are read
+}
+
+impl Default for ExtractOptions {
+    fn default() -> ExtractOptions {
+        ExtractOptions {
+            retries: DEFAULT_RETRIES,
+            salvage: None,
+            report: None,
+            xattrs: false,
+            win_metadata: false,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            overwrite: Overwrite::Always,
+            workers: 1,
+            ordered: false,
+        }
+    }
+}
+
+// Whether an entry is one of the selected paths or inside one of them
+fn selected(entry: &IsoEntry, paths: &[String]) -> bool {
+    paths.is_empty() || paths.iter().any(|path| path == "/" || entry.path == *path || entry.path.starts_with(&format!("{}/", path)))
+}
+
+// Whether an entry or a directory it is in matches one of the patterns
+fn matched(entries: &[IsoEntry], mut index: usize, patterns: &[String]) -> bool {
+    while index != 0 {
+        let entry = &entries[index];
+        if matches_any(patterns, &entry.path, &entry.name) {
+            return true;
+        }
+        index = entry.parent;
+    }
+    false
+}
+
+// Which entries are extracted: those among the selected paths that the includes take in and
+// the excludes don't leave out, and the directories on the way to them
+fn chosen(entries: &[IsoEntry], paths: &[String], options: &ExtractOptions) -> Vec<bool> {
+    let mut chosen = vec![false; entries.len()];
+    for (index, entry) in entries.iter().enumerate().skip(1) {
+        if !selected(entry, paths) || (!options.include.is_empty() && !matched(entries, index, &options.include)) || matched(entries, index, &options.exclude) {
+            continue;
+        }
+        let mut on_the_way = index;
+        while on_the_way != 0 && !chosen[on_the_way] {
+            chosen[on_the_way] = true;
+            on_the_way = entries[on_the_way].parent;
+        }
+    }
+    chosen
+}
+
+// Whether to replace what is already at target with an entry modified at the given time;
+// "all" and "none" answers to the prompt settle the rest of the run
+fn replace_existing(policy: &mut Overwrite, target: &Path, existing: &fs::Metadata, modified: i64) -> io::Result<bool> {
+    match *policy {
+        Overwrite::Always => Ok(true),
+        Overwrite::Never => Ok(false),
+        Overwrite::Newer => Ok(existing.modified().map_or(true, |found| found < system_time(modified))),
+        Overwrite::Prompt => loop {
+            print!("{} already exists; replace it? [y]es, [n]o, [a]ll, n[o]ne: ", target.display());
+            io::stdout().flush()?;
+            let mut answer = String::new();
+            if io::stdin().read_line(&mut answer)? == 0 {
+                *policy = Overwrite::Never; // Nobody left to ask
+                return Ok(false);
+            }
+            match answer.trim() {
+                "y" | "yes" => return Ok(true),
+                "n" | "no" | "" => return Ok(false),
+                "a" | "all" => *policy = Overwrite::Always,
+                "o" | "none" => *policy = Overwrite::Never,
+                _ => continue,
+            }
+            return Ok(*policy == Overwrite::Always);
+        },
+    }
+}
+
+// Path of an image entry below the destination directory
+fn destination_path(destination: &Path, entry: &IsoEntry) -> PathBuf {
+    destination.join(entry.path.trim_start_matches('/'))
+}
+
+// makeiso extract [--retries N] [--salvage [--fill zero|marker] [--report FILE]] [--xattrs] [--win-metadata] [--include GLOB] [--exclude GLOB]
+// [--overwrite always|never|newer|prompt] [--keep-existing] [--workers N [--ordered]] <image.iso|URL> <destination> [path ...]
+pub fn extract_command(args: &[String]) -> io::Result<()> {
+    let usage = || {
+        io::Error::new(
+            ErrorKind::InvalidInput,
+            "Usage: makeiso extract [--retries N] [--salvage [--fill zero|marker] [--report FILE]] [--xattrs] [--win-metadata] [--include GLOB] [--exclude GLOB] [--overwrite always|never|newer|prompt] [--keep-existing] [--workers N [--ordered]] <image.iso|URL> <destination> [path ...]",
+        )
+    };
+    let mut fill = None;
+    let mut options = ExtractOptions::default();
+    let mut positional = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--retries" => {
+                let value = args.next().ok_or_else(usage)?;
+                options.retries = value.parse().map_err(|_| io::Error::new(ErrorKind::InvalidInput, format!("invalid --retries: {}", value)))?;
+            }
+            "--salvage" => options.salvage = Some(Fill::Zeros),
+            "--fill" => {
+                let value = args.next().ok_or_else(usage)?;
+                fill = Some(Fill::parse(value).ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, format!("invalid --fill: {} (zero or marker)", value)))?);
+            }
+            "--report" => options.report = Some(PathBuf::from(args.next().ok_or_else(usage)?)),
+            "--xattrs" => options.xattrs = true,
+            "--win-metadata" => options.win_metadata = true,
+            "--include" => options.include.push(unicode::reading(args.next().ok_or_else(usage)?).into_owned()),
+            "--exclude" => options.exclude.push(unicode::reading(args.next().ok_or_else(usage)?).into_owned()),
+            "--overwrite" => options.overwrite = Overwrite::parse(args.next().ok_or_else(usage)?)?,
+            "--keep-existing" => options.overwrite = Overwrite::Never,
+            "--workers" => {
+                let value = args.next().ok_or_else(usage)?;
+                options.workers = value.parse().ok().filter(|&workers| workers > 0).ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, format!("invalid --workers: {}", value)))?;
+            }
+            "--ordered" => options.ordered = true,
+            _ => positional.push(arg.clone()),
+        }
+    }
+    if options.salvage.is_some() {
+        options.salvage = fill.or(options.salvage);
+    } else if fill.is_some() || options.report.is_some() {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "--fill and --report go with --salvage"));
+    }
+    if options.ordered && options.workers == 1 {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "--ordered goes with --workers"));
+    }
+    let [iso_path, destination, paths @ ..] = &positional[..] else {
+        return Err(usage());
+    };
+    extract(Path::new(iso_path), Path::new(destination), paths, &options)
+}
+
+// SHA-256 digests by image path, from the first manifest found in the image
+fn read_manifest<R: ReadAt>(reader: &mut IsoReader<R>, entries: &[IsoEntry]) -> io::Result<Option<(String, HashMap<String, String>)>> {
+    let Some(manifest) = MANIFEST_NAMES.iter().find_map(|name| entries.iter().find(|entry| entry.path == *name && !entry.is_directory)) else {
+        return Ok(None);
+    };
+    let contents = read_whole_file(reader, manifest)?;
+
+    // "<64 hex digits>  ./path" (or " *path" for binary mode)
+    let mut digests = HashMap::new();
+    for line in String::from_utf8_lossy(&contents).lines() {
+        let Some((digest, path)) = line.split_once(char::is_whitespace) else {
+            continue;
+        };
+        if digest.len() != 64 || !digest.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            continue;
+        }
+        let path = path.trim_start().trim_start_matches('*').trim_start_matches("./").trim_start_matches('/');
+        digests.insert(format!("/{}", path), digest.to_ascii_lowercase());
+    }
+    Ok(Some((manifest.path.clone(), digests)))
+}
+
+// Extended attributes by image path, from the XATTRS.TXT at the root of the image
+fn read_xattrs<R: ReadAt>(reader: &mut IsoReader<R>, entries: &[IsoEntry]) -> io::Result<HashMap<String, Attributes>> {
+    let path = format!("/{}", XATTR_FILE);
+    match entries.iter().find(|entry| entry.path == path && !entry.is_directory) {
+        Some(file) => Ok(xattr::parse(&String::from_utf8_lossy(&read_whole_file(reader, file)?))),
+        None => {
+            eprintln!("The image has no {}, so there are no extended attributes to restore", XATTR_FILE);
+            Ok(HashMap::new())
+        }
+    }
+}
+
+// Windows metadata by image path, from the WINMETA.TXT at the root of the image (laid out
+// like XATTRS.TXT)
+fn read_win_metadata<R: ReadAt>(reader: &mut IsoReader<R>, entries: &[IsoEntry]) -> io::Result<HashMap<String, Attributes>> {
+    let path = format!("/{}", WINMETA_FILE);
+    match entries.iter().find(|entry| entry.path == path && !entry.is_directory) {
+        Some(file) => Ok(xattr::parse(&String::from_utf8_lossy(&read_whole_file(reader, file)?))),
+        None => {
+            eprintln!("The image has no {}, so there is no Windows metadata to restore", WINMETA_FILE);
+            Ok(HashMap::new())
+        }
+    }
+}
+
+// Image paths of the files stored encrypted, from the ENCRYPTED.TXT at the root of the image
+fn read_encrypted<R: ReadAt>(reader: &mut IsoReader<R>, entries: &[IsoEntry]) -> io::Result<Vec<String>> {
+    let path = format!("/{}", secrets::MANIFEST);
+    match entries.iter().find(|entry| entry.path == path && !entry.is_directory) {
+        Some(file) => Ok(secrets::parse_manifest(&String::from_utf8_lossy(&read_whole_file(reader, file)?))),
+        None => Ok(Vec::new()),
+    }
+}
+
+// Replace an extracted .age file with what it decrypts to, returning the new path
+fn decrypt_file(target: &Path, modified: i64) -> io::Result<PathBuf> {
+    let plain = age::decrypt(fs::read(target)?)?;
+    let name = target.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+    let decrypted = target.with_file_name(name.strip_suffix(EXTENSION).unwrap_or(name));
+    let mut file = File::create(&decrypted)?;
+    file.write_all(&plain)?;
+    file.set_modified(system_time(modified))?;
+    fs::remove_file(target)?;
+    Ok(decrypted)
+}
+
+fn read_whole_file<R: ReadAt>(reader: &mut IsoReader<R>, entry: &IsoEntry) -> io::Result<Vec<u8>> {
+    let mut contents = vec![0u8; entry.size as usize];
+    let mut offset = 0;
+    while offset < contents.len() {
+        offset += reader.read_file_at(entry, offset as u64, &mut contents[offset..])?;
+    }
+    Ok(contents)
+}
+
+// Set the recorded extended attributes of an extracted entry; returns how many couldn't be set
+fn restore_xattrs(target: &Path, attributes: Option<&Attributes>) -> usize {
+    let mut failed = 0;
+    for (name, value) in attributes.into_iter().flatten() {
+        if let Err(e) = xattr::write(target, name, value) {
+            eprintln!("Could not set {} on {}: {}", name, target.display(), e);
+            failed += 1;
+        }
+    }
+    failed
+}
+
+// Read a chunk of a file, trying again when the read fails
+fn read_with_retries<R: ReadAt>(reader: &mut IsoReader<R>, entry: &IsoEntry, offset: u64, buffer: &mut [u8], retries: u32) -> io::Result<usize> {
+    let mut attempt = 0;
+    loop {
+        match reader.read_file_at(entry, offset, buffer) {
+            Ok(bytes_read) => return Ok(bytes_read),
+            Err(e) if attempt < retries => {
+                attempt += 1;
+                eprintln!("Read error in {} at offset {} ({}), retrying ({}/{})", entry.path, offset, e, attempt, retries);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// Copy one file out of the image; returns its SHA-256 when asked for, or the error that the
+// image couldn't be read past (errors writing the copy are returned as they are)
+fn copy_file<R: ReadAt>(
+    reader: &mut IsoReader<R>,
+    entry: &IsoEntry,
+    target: &Path,
+    hash: bool,
+    retries: u32,
+    buffer: &mut [u8],
+) -> io::Result<Result<Option<String>, io::Error>> {
+    let mut output = File::create(target)?;
+    let mut sha = hash.then(Sha256::new);
+    let mut offset = 0;
+    while offset < entry.size as u64 {
+        let bytes_read = match read_with_retries(reader, entry, offset, buffer, retries) {
+            Ok(bytes_read) => bytes_read,
+            Err(e) => return Ok(Err(e)),
+        };
+        output.write_all(&buffer[..bytes_read])?;
+        if let Some(sha) = &mut sha {
+            sha.update(&buffer[..bytes_read]);
+        }
+        offset += bytes_read as u64;
+    }
+    output.set_modified(system_time(entry.modified))?;
+    Ok(Ok(sha.map(|sha| sha256::hex(&sha.finish()))))
+}
+
+// Copy one file out of the image, reading it again as a whole while it doesn't match its
+// digest; Err is why it couldn't be extracted intact
+fn extract_file<R: ReadAt>(reader: &mut IsoReader<R>, entry: &IsoEntry, target: &Path, expected: Option<&String>, options: &ExtractOptions, buffer: &mut [u8]) -> io::Result<Result<(), String>> {
+    with_retries(entry, expected, options.retries, |_| copy_file(reader, entry, target, expected.is_some(), options.retries, buffer))
+}
+
+// Read a file as a whole with read (given the number of the attempt, 0 for the first), as
+// many times more as it takes to match its digest; read returns the digest when one is
+// expected, or the error the image couldn't be read past. Ok(Err) is why the file couldn't
+// be read intact, Err an error that ends the extraction
+fn with_retries(entry: &IsoEntry, expected: Option<&String>, retries: u32, mut read: impl FnMut(u32) -> io::Result<Result<Option<String>, io::Error>>) -> io::Result<Result<(), String>> {
+    let mut attempt = 0;
+    loop {
+        match read(attempt)? {
+            Ok(digest) if digest.as_ref() == expected => return Ok(Ok(())),
+            Ok(_) if attempt < retries => {
+                attempt += 1;
+                eprintln!("{} does not match the manifest, reading it again ({}/{})", entry.path, attempt, retries);
+            }
+            Ok(_) => return Ok(Err("does not match the manifest".to_string())),
+            Err(e) => return Ok(Err(e.to_string())),
+        }
+    }
+}
+
+// What the reading thread sends the thread writing a file
+enum Piece {
+    Data(Vec<u8>),
+    Again, // The file is read again from the start
+    Done,  // All of it was sent; without this the file is left as far as it got
+}
+
+// A file for a writer thread, its contents following over the channel
+struct Job {
+    sequence: usize, // Position in the order the files are read in
+    target: PathBuf,
+    modified: i64,
+    pieces: Receiver<Piece>,
+}
+
+// Whose turn it is to move a finished file into place, with --ordered
+type Turn = (Mutex<usize>, Condvar);
+
+// Extract the files with options.workers threads creating and writing them while this one
+// reads the image, in the order of their extents so the reads stay sequential. With --ordered,
+// each file is written under a temporary name and moved into place only after the ones read
+// before it, so an interrupted extraction leaves a prefix of the image's order behind
+fn write_in_parallel<R: ReadAt>(
+    reader: &mut IsoReader<Salvage<R>>,
+    entries: &[IsoEntry],
+    queued: &[(usize, PathBuf)],
+    digests: Option<&HashMap<String, String>>,
+    options: &ExtractOptions,
+    buffer: &mut [u8],
+) -> io::Result<Vec<Result<(), String>>> {
+    let mut order: Vec<usize> = (0..queued.len()).collect();
+    order.sort_by_key(|&position| entries[queued[position].0].extent);
+    let mut outcomes = vec![Ok(()); queued.len()];
+    let (sender, jobs) = mpsc::sync_channel::<Job>(options.workers);
+    let jobs = Mutex::new(jobs);
+    let turn: Turn = (Mutex::new(0), Condvar::new());
+
+    let written = thread::scope(|scope| {
+        let workers: Vec<_> = (0..options.workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut first_error = None;
+                    loop {
+                        let Ok(job) = jobs.lock().unwrap_or_else(|e| e.into_inner()).recv() else { break };
+                        let written = write_job(&job, options.ordered.then_some(&turn));
+                        if let Err(e) = written {
+                            first_error.get_or_insert_with(|| io::Error::new(e.kind(), format!("could not write {}: {}", job.target.display(), e)));
+                        }
+                    }
+                    first_error
+                })
+            })
+            .collect();
+        for (sequence, &position) in order.iter().enumerate() {
+            let (index, target) = &queued[position];
+            let entry = &entries[*index];
+            let (pieces, received) = mpsc::sync_channel(CHUNKS_IN_FLIGHT);
+            if sender.send(Job { sequence, target: target.clone(), modified: entry.modified, pieces: received }).is_err() {
+                break; // No writers left
+            }
+            reader.source_mut().context = entry.path.clone();
+            outcomes[position] = send_file(reader, entry, digests.and_then(|digests| digests.get(&entry.path)), options.retries, buffer, &pieces);
+        }
+        drop(sender);
+        workers.into_iter().filter_map(|worker| worker.join().unwrap_or(None)).next()
+    });
+    match written {
+        Some(e) => Err(e),
+        None => Ok(outcomes),
+    }
+}
+
+// Read a file for the thread writing it, again as a whole while it doesn't match its digest
+fn send_file<R: ReadAt>(reader: &mut IsoReader<R>, entry: &IsoEntry, expected: Option<&String>, retries: u32, buffer: &mut [u8], pieces: &SyncSender<Piece>) -> Result<(), String> {
+    // A writer that went away has its own error to report, so failed sends are ignored
+    let sent = with_retries(entry, expected, retries, |attempt| {
+        if attempt > 0 {
+            let _ = pieces.send(Piece::Again);
+        }
+        let mut sha = expected.is_some().then(Sha256::new);
+        let mut offset = 0;
+        while offset < entry.size as u64 {
+            let bytes_read = match read_with_retries(reader, entry, offset, buffer, retries) {
+                Ok(bytes_read) => bytes_read,
+                Err(e) => return Ok(Err(e)),
+            };
+            if let Some(sha) = &mut sha {
+                sha.update(&buffer[..bytes_read]);
+            }
+            let _ = pieces.send(Piece::Data(buffer[..bytes_read].to_vec()));
+            offset += bytes_read as u64;
+        }
+        Ok(Ok(sha.map(|sha| sha256::hex(&sha.finish()))))
+    });
+    let sent = sent.unwrap_or_else(|e| Err(e.to_string()));
+    if sent.is_ok() {
+        let _ = pieces.send(Piece::Done);
+    }
+    sent
+}
+
+// Write the file of a job, and with a turn to take, move it into place when that comes
+fn write_job(job: &Job, turn: Option<&Turn>) -> io::Result<()> {
+    let Some((next, moved)) = turn else {
+        return receive_file(&job.target, &job.pieces, job.modified);
+    };
+    let mut name = job.target.file_name().unwrap_or_default().to_os_string();
+    name.push(PART_SUFFIX);
+    let part = job.target.with_file_name(name);
+    let written = receive_file(&part, &job.pieces, job.modified);
+    // Taken whether or not the file was written, or the ones after it would wait forever
+    let mut next = moved.wait_while(next.lock().unwrap_or_else(|e| e.into_inner()), |next| *next != job.sequence).unwrap_or_else(|e| e.into_inner());
+    let placed = if part.exists() { fs::rename(&part, &job.target) } else { Ok(()) };
+    *next += 1;
+    moved.notify_all();
+    written.and(placed)
+}
+
+fn receive_file(path: &Path, pieces: &Receiver<Piece>, modified: i64) -> io::Result<()> {
+    let mut output = File::create(path)?;
+    // Ends when the reading thread is done with the file, whether or not it read all of it
+    for piece in pieces {
+        match piece {
+            Piece::Data(data) => output.write_all(&data)?,
+            Piece::Again => {
+                output.set_len(0)?;
+                output.seek(SeekFrom::Start(0))?;
+            }
+            Piece::Done => output.set_modified(system_time(modified))?,
+        }
+    }
+    Ok(())
+}
+
+// Extract the given image paths (everything when none are given) with their modification times
+pub fn extract(location: &Path, destination: &Path, paths: &[String], options: &ExtractOptions) -> io::Result<()> {
+    // Directories first, with the more patient settings when salvaging
+    let retries = if options.salvage.is_some() { options.retries * DIRECTORY_RETRY_FACTOR } else { options.retries };
+    let source = open_source(location)?;
+    let found = source.size()?;
+    let mut reader = IsoReader::open(Salvage::new(source, options.salvage, retries))?;
+    if let Some(truncation) = Truncation::of_size(reader.pvd.volume_space_size, found) {
+        eprintln!("{} is truncated: {}; files in the missing part can't be extracted{}", location.display(), truncation, if options.salvage.is_some() { " and are filled in" } else { "" });
+    }
+    reader.source_mut().context = "the directory tree".to_string();
+    let entries = reader.read_tree()?;
+    for entry in entries.iter().filter(|entry| entry.is_directory) {
+        reader.source_mut().label(entry.extent as u64, (entry.size as u64).div_ceil(BLOCK_SIZE as u64), &format!("{} (directory)", entry.path));
+    }
+    let salvage = reader.source_mut();
+    salvage.retries = options.retries;
+    if salvage.bad_blocks() > 0 {
+        eprintln!("{} directory blocks could not be read; what they listed is missing", salvage.bad_blocks());
+    }
+
+    // Paths are taken relative to the image root, with or without a leading slash, and stand for
+    // the entries they are looked up as (regardless of case, say)
+    let case_insensitive = reader.case_insensitive();
+    let paths = paths
+        .iter()
+        .map(|path| match lookup(&entries, path, case_insensitive) {
+            Some(index) => Ok(entries[index].path.clone()),
+            None => Err(io::Error::new(ErrorKind::NotFound, format!("/{} is not in the image", path.trim_matches('/')))),
+        })
+        .collect::<io::Result<Vec<String>>>()?;
+
+    let manifest = read_manifest(&mut reader, &entries)?;
+    if let Some((manifest_path, digests)) = &manifest {
+        println!("Verifying files against {} ({} entries)", manifest_path, digests.len());
+    }
+    let xattrs = if options.xattrs { read_xattrs(&mut reader, &entries)? } else { HashMap::new() };
+    let xattr_file = format!("/{}", XATTR_FILE);
+    // Elsewhere the file is extracted like any other, so its contents aren't lost
+    let win_metadata = options.win_metadata && cfg!(windows);
+    if options.win_metadata && !win_metadata {
+        eprintln!("Windows metadata can only be restored on Windows; {} is extracted as a file", WINMETA_FILE);
+    }
+    let win_records = if win_metadata { read_win_metadata(&mut reader, &entries)? } else { HashMap::new() };
+    let win_file = format!("/{}", WINMETA_FILE);
+    let encrypted = read_encrypted(&mut reader, &entries)?;
+    let encrypted_manifest = format!("/{}", secrets::MANIFEST);
+    let decrypt = !encrypted.is_empty() && age::has_passphrase();
+
+    fs::create_dir_all(destination)?;
+    let whole_image = paths.is_empty() || paths.iter().any(|path| path == "/");
+    let mut xattr_failures = if whole_image { restore_xattrs(destination, xattrs.get("/")) } else { 0 };
+    let mut win_failures = if whole_image { winmeta::restore(destination, win_records.get("/")) } else { 0 };
+    let mut buffer = vec![0u8; COPY_CHUNK_SIZE];
+    let (mut files, mut bytes, mut verified) = (0u64, 0u64, 0u64);
+    let mut directories = Vec::new();
+    let mut failed = Vec::new();
+    // Directories leading to what is extracted are created too, keeping their times
+    let chosen = chosen(&entries, &paths, options);
+    let mut overwrite = options.overwrite;
+    let (mut replaced, mut kept) = (Vec::new(), Vec::new());
+    let mut queued = Vec::new();
+
+    for (index, entry) in entries.iter().enumerate().skip(1) {
+        if (options.xattrs && entry.path == xattr_file) || (win_metadata && entry.path == win_file) {
+            continue; // Put back into place rather than extracted
+        }
+        if decrypt && entry.path == encrypted_manifest {
+            continue;
+        }
+        if !chosen[index] {
+            continue;
+        }
+
+        let target = destination_path(destination, entry);
+        if !entry.is_directory {
+            // Encrypted files end up under their own names
+            let lands = if decrypt && encrypted.contains(&entry.path) { target.with_file_name(entry.name.strip_suffix(EXTENSION).unwrap_or(&entry.name)) } else { target.clone() };
+            if let Ok(existing) = fs::symlink_metadata(&lands) {
+                if !replace_existing(&mut overwrite, &lands, &existing, entry.modified)? {
+                    kept.push(lands);
+                    continue;
+                }
+                replaced.push(lands);
+            }
+        }
+        if entry.is_directory {
+            fs::create_dir_all(&target)?;
+            xattr_failures += restore_xattrs(&target, xattrs.get(&entry.path));
+            win_failures += winmeta::restore(&target, win_records.get(&entry.path));
+            directories.push((target, entry.modified));
+        } else if let Some(link) = &entry.symlink {
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            make_symlink(link, &target)?;
+        } else {
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            queued.push((index, target));
+        }
+    }
+
+    // Regular files last, one after another or, with --workers, written by a pool of threads
+    let digests = manifest.as_ref().map(|(_, digests)| digests);
+    let outcomes = if options.workers > 1 {
+        write_in_parallel(&mut reader, &entries, &queued, digests, options, &mut buffer)?
+    } else {
+        let mut outcomes = Vec::new();
+        for (index, target) in &queued {
+            let entry = &entries[*index];
+            reader.source_mut().context = entry.path.clone();
+            outcomes.push(extract_file(&mut reader, entry, target, digests.and_then(|digests| digests.get(&entry.path)), options, &mut buffer)?);
+        }
+        outcomes
+    };
+    for ((index, target), outcome) in queued.into_iter().zip(outcomes) {
+        let entry = &entries[index];
+        // Encrypted files are decrypted once they are out, and get the attributes of the original
+        let outcome = outcome.and_then(|()| {
+            if !decrypt || !encrypted.contains(&entry.path) {
+                return Ok((target, entry.path.as_str()));
+            }
+            let original = entry.path.strip_suffix(EXTENSION).unwrap_or(&entry.path);
+            decrypt_file(&target, entry.modified).map(|decrypted| (decrypted, original)).map_err(|e| format!("could not be decrypted: {}", e))
+        });
+        match outcome {
+            Ok((target, path)) => {
+                xattr_failures += restore_xattrs(&target, xattrs.get(path));
+                win_failures += winmeta::restore(&target, win_records.get(path));
+                files += 1;
+                bytes += entry.size as u64;
+                verified += digests.is_some_and(|digests| digests.contains_key(&entry.path)) as u64;
+            }
+            Err(reason) => failed.push((entry.path.clone(), reason)),
+        }
+    }
+
+    // Directory times last, since creating their contents changed them; deepest first
+    for (directory, modified) in directories.iter().rev() {
+        File::open(directory).and_then(|directory| directory.set_modified(system_time(*modified)))?;
+    }
+
+    println!("Extracted {} files ({} bytes) to {}", files, bytes, destination.display());
+    for (what, paths) in [("Overwrote", &replaced), ("Kept", &kept)] {
+        if !paths.is_empty() {
+            println!("{} {} files that were already there:", what, paths.len());
+            for path in paths {
+                println!("    {}", path.display());
+            }
+        }
+    }
+    if manifest.is_some() {
+        println!("{} files matched the manifest", verified);
+    }
+    let still_encrypted = entries.iter().enumerate().filter(|&(index, entry)| encrypted.contains(&entry.path) && chosen[index]).count();
+    if still_encrypted > 0 && !decrypt {
+        println!("{} files are stored encrypted; give --passphrase-file (or MAKEISO_PASSPHRASE) to decrypt them", still_encrypted);
+    }
+    if xattr_failures > 0 {
+        eprintln!("{} extended attributes could not be set (security.* and trusted.* need root)", xattr_failures);
+    }
+    if win_failures > 0 {
+        eprintln!("{} attributes, creation times and streams could not be set", win_failures);
+    }
+    if options.salvage.is_some() {
+        let report = options.report.clone().unwrap_or_else(|| {
+            let mut name = destination.file_name().unwrap_or_default().to_os_string();
+            name.push(".salvage.txt");
+            destination.with_file_name(name)
+        });
+        let salvage = reader.source_mut();
+        salvage.write_report(&report, location)?;
+        println!("{} blocks could not be read; recovery report written to {}", salvage.bad_blocks(), report.display());
+    }
+    if !failed.is_empty() {
+        eprintln!("Could not recover {} files:", failed.len());
+        for (path, reason) in &failed {
+            eprintln!("    {}: {}", path, reason);
+        }
+        return Err(io::Error::other(format!("{} files could not be extracted intact", failed.len())));
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn make_symlink(link: &str, target: &Path) -> io::Result<()> {
+    match fs::remove_file(target) {
+        Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    std::os::unix::fs::symlink(link, target)
+}
+
+#[cfg(not(unix))]
+fn make_symlink(link: &str, target: &Path) -> io::Result<()> {
+    eprintln!("Skipping symbolic link {} -> {}", target.display(), link);
+    Ok(())
+}
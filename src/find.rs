@@ -0,0 +1,87 @@
+// Search an image by name, path, type, size and modification time without extracting it
+
+use std::io::{self, ErrorKind};
+use std::path::Path;
+
+use super::glob;
+use super::reader::{open_image, IsoEntry};
+use super::unicode;
+use super::{parse_date, parse_size};
+
+#[derive(Default)]
+struct Criteria {
+    name: Option<String>,
+    path: Option<String>,
+    kind: Option<char>, // f, d or l
+    newer_than: Option<i64>,
+    older_than: Option<i64>,
+    larger: Option<u64>,
+    smaller: Option<u64>,
+}
+
+impl Criteria {
+    fn matches(&self, entry: &IsoEntry) -> bool {
+        let kind = if entry.is_directory {
+            'd'
+        } else if entry.symlink.is_some() {
+            'l'
+        } else {
+            'f'
+        };
+        self.name.as_ref().is_none_or(|pattern| glob::matches(pattern, &entry.name))
+            && self.path.as_ref().is_none_or(|pattern| glob::matches(pattern.trim_start_matches('/'), entry.path.trim_start_matches('/')))
+            && self.kind.is_none_or(|wanted| wanted == kind)
+            && self.newer_than.is_none_or(|time| entry.modified > time)
+            && self.older_than.is_none_or(|time| entry.modified < time)
+            && self.larger.is_none_or(|size| !entry.is_directory && entry.size as u64 > size)
+            && self.smaller.is_none_or(|size| !entry.is_directory && (entry.size as u64) < size)
+    }
+}
+
+const USAGE: &str = "Usage: makeiso find <image.iso> [--name GLOB] [--path GLOB] [--type f|d|l] [--newer-than DATE] [--older-than DATE] [--larger SIZE] [--smaller SIZE]";
+
+// makeiso find <image.iso> [criteria]: print the paths of the entries matching all criteria
+pub fn find(args: &[String]) -> io::Result<()> {
+    let usage = || io::Error::new(ErrorKind::InvalidInput, USAGE);
+    let mut criteria = Criteria::default();
+    let mut image = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_string())),
+            _ => (arg.as_str(), None),
+        };
+        if !flag.starts_with("--") {
+            if image.replace(arg).is_some() {
+                return Err(usage());
+            }
+            continue;
+        }
+        let value = inline_value.or_else(|| args.next().cloned()).ok_or_else(usage)?;
+
+        match flag {
+            "--name" => criteria.name = Some(unicode::reading(&value).into_owned()),
+            "--path" => criteria.path = Some(unicode::reading(&value).into_owned()),
+            "--type" => {
+                criteria.kind = match value.as_str() {
+                    "f" | "d" | "l" => value.chars().next(),
+                    _ => return Err(io::Error::new(ErrorKind::InvalidInput, format!("invalid --type {} (expected f, d or l)", value))),
+                }
+            }
+            "--newer-than" => criteria.newer_than = Some(parse_date(&value)?),
+            "--older-than" => criteria.older_than = Some(parse_date(&value)?),
+            "--larger" => criteria.larger = Some(parse_size(&value)?),
+            "--smaller" => criteria.smaller = Some(parse_size(&value)?),
+            _ => return Err(io::Error::new(ErrorKind::InvalidInput, format!("Unknown option: {}\n{}", flag, USAGE))),
+        }
+    }
+    let image = image.ok_or_else(usage)?;
+
+    let mut reader = open_image(Path::new(image))?;
+    for entry in reader.read_tree()?.iter().skip(1) {
+        if criteria.matches(entry) {
+            println!("{}", entry.path);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,354 @@
+// Decoder for FLAC streams (the free lossless audio codec), as makeiso audio reads them
+//
+// Frames are decoded one at a time into a sample vector per channel: constant, verbatim, fixed
+// and LPC subframes with their Rice-coded residuals, then the stereo decorrelation. The header
+// CRC-8 and frame CRC-16 of every frame are checked; the MD5 sum of the stream isn't.
+
+use std::io::{self, ErrorKind, Read};
+
+const MAGIC: &[u8; 4] = b"fLaC";
+const STREAMINFO: u8 = 0;
+const SYNC: u32 = 0x3FFE; // 14 bits at the start of every frame
+const SAMPLE_SIZES: [u32; 8] = [0, 8, 12, 0, 16, 20, 24, 32];
+const MAX_LPC_ORDER: usize = 32;
+
+fn corrupt(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, format!("corrupt FLAC stream: {}", message))
+}
+
+// The format of a stream, from its STREAMINFO block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamInfo {
+    pub sample_rate: u32,
+    pub channels: u32,
+    pub bits_per_sample: u32,
+    pub total_samples: u64, // Per channel, 0 when unknown
+}
+
+// Bits of the stream, most significant first, with the CRCs of the bytes taken so far
+struct Bits<R> {
+    input: R,
+    buffer: u64,
+    count: u32,
+    crc8: u8,
+    crc16: u16,
+}
+
+impl<R: Read> Bits<R> {
+    // The next byte of the input, None at its end
+    fn next_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0u8; 1];
+        match self.input.read_exact(&mut byte) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        self.crc8 ^= byte[0];
+        for _ in 0..8 {
+            self.crc8 = if self.crc8 & 0x80 != 0 { (self.crc8 << 1) ^ 0x07 } else { self.crc8 << 1 };
+        }
+        self.crc16 ^= (byte[0] as u16) << 8;
+        for _ in 0..8 {
+            self.crc16 = if self.crc16 & 0x8000 != 0 { (self.crc16 << 1) ^ 0x8005 } else { self.crc16 << 1 };
+        }
+        Ok(Some(byte[0]))
+    }
+
+    fn fill(&mut self) -> io::Result<()> {
+        let byte = self.next_byte()?.ok_or_else(|| corrupt("unexpected end of stream"))?;
+        self.buffer = (self.buffer << 8) | byte as u64;
+        self.count += 8;
+        Ok(())
+    }
+
+    fn bits(&mut self, count: u32) -> io::Result<u32> {
+        if count == 0 {
+            return Ok(0);
+        }
+        while self.count < count {
+            self.fill()?;
+        }
+        self.count -= count;
+        Ok(((self.buffer >> self.count) & ((1u64 << count) - 1)) as u32)
+    }
+
+    // A two's complement number of the given width (up to 33 bits, for side channels of 32-bit audio)
+    fn signed(&mut self, count: u32) -> io::Result<i64> {
+        if count == 0 {
+            return Ok(0);
+        }
+        let value = if count > 32 { ((self.bits(count - 32)? as u64) << 32) | self.bits(32)? as u64 } else { self.bits(count)? as u64 };
+        Ok(((value << (64 - count)) as i64) >> (64 - count))
+    }
+
+    // Zeros up to the next one bit, which is taken too
+    fn unary(&mut self) -> io::Result<u32> {
+        let mut zeros = 0;
+        loop {
+            if self.count == 0 {
+                self.fill()?;
+            }
+            let left = self.buffer & ((1u64 << self.count) - 1);
+            if left == 0 {
+                zeros += self.count;
+                self.count = 0;
+                continue;
+            }
+            let highest = 63 - left.leading_zeros();
+            zeros += self.count - 1 - highest;
+            self.count = highest;
+            return Ok(zeros);
+        }
+    }
+
+    fn align(&mut self) {
+        self.count -= self.count % 8;
+    }
+
+    fn start_frame(&mut self) {
+        self.crc8 = 0;
+        self.crc16 = 0;
+    }
+}
+
+pub struct FlacReader<R> {
+    bits: Bits<R>,
+    pub info: StreamInfo,
+}
+
+impl<R: Read> FlacReader<R> {
+    // Read the metadata blocks (skipping an ID3v2 tag in front) up to the first frame
+    pub fn new(mut input: R) -> io::Result<FlacReader<R>> {
+        let mut magic = [0u8; 4];
+        input.read_exact(&mut magic)?;
+        if &magic[..3] == b"ID3" {
+            let mut header = [0u8; 6];
+            input.read_exact(&mut header)?;
+            let size = header[2..6].iter().fold(0u64, |size, &byte| (size << 7) | (byte & 0x7F) as u64);
+            io::copy(&mut (&mut input).take(size), &mut io::sink())?;
+            input.read_exact(&mut magic)?;
+        }
+        if &magic != MAGIC {
+            return Err(io::Error::new(ErrorKind::InvalidData, "not a FLAC stream"));
+        }
+
+        let mut info = None;
+        loop {
+            let mut header = [0u8; 4];
+            input.read_exact(&mut header)?;
+            let length = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+            let mut block = vec![0u8; length];
+            input.read_exact(&mut block)?;
+            if header[0] & 0x7F == STREAMINFO {
+                if length < 34 {
+                    return Err(corrupt("short STREAMINFO block"));
+                }
+                let packed = u64::from_be_bytes(block[10..18].try_into().unwrap());
+                info = Some(StreamInfo {
+                    sample_rate: (packed >> 44) as u32,
+                    channels: ((packed >> 41) & 0x7) as u32 + 1,
+                    bits_per_sample: ((packed >> 36) & 0x1F) as u32 + 1,
+                    total_samples: packed & 0xF_FFFF_FFFF,
+                });
+            }
+            if header[0] & 0x80 != 0 {
+                break;
+            }
+        }
+        let info = info.ok_or_else(|| corrupt("no STREAMINFO block"))?;
+        Ok(FlacReader { bits: Bits { input, buffer: 0, count: 0, crc8: 0, crc16: 0 }, info })
+    }
+
+    // The samples of the next frame, one vector per channel; None at the end of the stream
+    pub fn next_frame(&mut self) -> io::Result<Option<Vec<Vec<i32>>>> {
+        let bits = &mut self.bits;
+        bits.start_frame();
+        let Some(first) = bits.next_byte()? else {
+            return Ok(None);
+        };
+        bits.buffer = first as u64;
+        bits.count = 8;
+        if bits.bits(8)? << 6 | bits.bits(6)? != SYNC {
+            return Err(corrupt("lost frame sync"));
+        }
+        bits.bits(2)?; // Reserved, blocking strategy
+        let (size_code, rate_code) = (bits.bits(4)?, bits.bits(4)?);
+        let (channel_code, sample_size_code) = (bits.bits(4)?, bits.bits(3)?);
+        bits.bits(1)?;
+
+        // The frame or sample number, UTF-8 coded: only its length matters here
+        let lead = bits.bits(8)?;
+        let continuation = (lead as u8).leading_ones().saturating_sub(1);
+        for _ in 0..continuation {
+            bits.bits(8)?;
+        }
+
+        let block_size = match size_code {
+            0 => return Err(corrupt("reserved block size")),
+            1 => 192,
+            2..=5 => 576 << (size_code - 2),
+            6 => bits.bits(8)? + 1,
+            7 => bits.bits(16)? + 1,
+            _ => 256 << (size_code - 8),
+        } as usize;
+        match rate_code {
+            12 => {
+                bits.bits(8)?;
+            }
+            13 | 14 => {
+                bits.bits(16)?;
+            }
+            15 => return Err(corrupt("invalid sample rate")),
+            _ => {}
+        }
+        let expected = bits.crc8;
+        if bits.bits(8)? as u8 != expected {
+            return Err(corrupt("frame header CRC mismatch"));
+        }
+
+        let bits_per_sample = match sample_size_code {
+            0 => self.info.bits_per_sample,
+            code => SAMPLE_SIZES[code as usize],
+        };
+        if bits_per_sample == 0 {
+            return Err(corrupt("reserved sample size"));
+        }
+        let channels = match channel_code {
+            0..=7 => channel_code + 1,
+            8..=10 => 2,
+            _ => return Err(corrupt("reserved channel assignment")),
+        };
+        let mut samples = Vec::with_capacity(channels as usize);
+        for channel in 0..channels {
+            // The side channel of a decorrelated pair has one more bit
+            let side = matches!((channel_code, channel), (8, 1) | (9, 0) | (10, 1));
+            samples.push(self.subframe(block_size, bits_per_sample + side as u32)?);
+        }
+
+        let bits = &mut self.bits;
+        bits.align();
+        let expected = bits.crc16;
+        if bits.bits(16)? as u16 != expected {
+            return Err(corrupt("frame CRC mismatch"));
+        }
+
+        if channel_code >= 8 {
+            let (first, second) = samples.split_at_mut(1);
+            for (a, b) in first[0].iter_mut().zip(second[0].iter_mut()) {
+                (*a, *b) = match channel_code {
+                    8 => (*a, *a - *b),
+                    9 => (*a + *b, *b),
+                    _ => {
+                        let mid = (*a << 1) | (*b & 1);
+                        ((mid + *b) >> 1, (mid - *b) >> 1)
+                    }
+                };
+            }
+        }
+        Ok(Some(samples.into_iter().map(|channel| channel.into_iter().map(|sample| sample as i32).collect()).collect()))
+    }
+
+    fn subframe(&mut self, block_size: usize, bits_per_sample: u32) -> io::Result<Vec<i64>> {
+        let bits = &mut self.bits;
+        if bits.bits(1)? != 0 {
+            return Err(corrupt("subframe padding bit set"));
+        }
+        let kind = bits.bits(6)?;
+        let wasted = if bits.bits(1)? != 0 { bits.unary()? + 1 } else { 0 };
+        let bits_per_sample = bits_per_sample.checked_sub(wasted).filter(|&bits| bits > 0).ok_or_else(|| corrupt("too many wasted bits"))?;
+
+        let mut samples = match kind {
+            0 => vec![bits.signed(bits_per_sample)?; block_size],
+            1 => (0..block_size).map(|_| bits.signed(bits_per_sample)).collect::<io::Result<_>>()?,
+            8..=12 => {
+                let order = (kind - 8) as usize;
+                let coefficients: &[i64] = [&[][..], &[1], &[2, -1], &[3, -3, 1], &[4, -6, 4, -1]][order];
+                self.predicted(block_size, bits_per_sample, coefficients)?
+            }
+            32..=63 => {
+                let order = (kind - 31) as usize;
+                let mut warm_up = Vec::with_capacity(order);
+                for _ in 0..order {
+                    warm_up.push(bits.signed(bits_per_sample)?);
+                }
+                let precision = bits.bits(4)? + 1;
+                if precision == 16 {
+                    return Err(corrupt("invalid LPC precision"));
+                }
+                let shift = bits.signed(5)?;
+                if shift < 0 {
+                    return Err(corrupt("negative LPC shift"));
+                }
+                let mut coefficients = vec![0i64; order];
+                for coefficient in coefficients.iter_mut() {
+                    *coefficient = bits.signed(precision)?;
+                }
+                self.lpc(block_size, warm_up, &coefficients, shift as u32)?
+            }
+            _ => return Err(corrupt("reserved subframe type")),
+        };
+        if wasted > 0 {
+            for sample in samples.iter_mut() {
+                *sample <<= wasted;
+            }
+        }
+        Ok(samples)
+    }
+
+    // A fixed predictor subframe: warm-up samples, then residuals on the given polynomial
+    fn predicted(&mut self, block_size: usize, bits_per_sample: u32, coefficients: &[i64]) -> io::Result<Vec<i64>> {
+        let mut warm_up = Vec::with_capacity(coefficients.len());
+        for _ in 0..coefficients.len() {
+            warm_up.push(self.bits.signed(bits_per_sample)?);
+        }
+        self.lpc(block_size, warm_up, coefficients, 0)
+    }
+
+    // Residuals on top of a linear prediction from the samples so far
+    fn lpc(&mut self, block_size: usize, mut samples: Vec<i64>, coefficients: &[i64], shift: u32) -> io::Result<Vec<i64>> {
+        let order = coefficients.len();
+        if order > block_size || order > MAX_LPC_ORDER {
+            return Err(corrupt("predictor order beyond the block size"));
+        }
+        let residuals = self.residual(block_size, order)?;
+        samples.reserve(block_size - order);
+        for residual in residuals {
+            let at = samples.len();
+            let prediction: i64 = coefficients.iter().enumerate().map(|(back, &coefficient)| coefficient * samples[at - 1 - back]).sum();
+            samples.push(residual + (prediction >> shift));
+        }
+        Ok(samples)
+    }
+
+    // Rice-coded residuals of a subframe, by partition
+    fn residual(&mut self, block_size: usize, order: usize) -> io::Result<Vec<i64>> {
+        let bits = &mut self.bits;
+        let (parameter_bits, escape) = match bits.bits(2)? {
+            0 => (4, 15),
+            1 => (5, 31),
+            _ => return Err(corrupt("reserved residual coding method")),
+        };
+        let partition_order = bits.bits(4)?;
+        let partitions = 1usize << partition_order;
+        if !block_size.is_multiple_of(partitions) || block_size / partitions < order {
+            return Err(corrupt("partitions don't fit the block"));
+        }
+        let mut residuals = Vec::with_capacity(block_size - order);
+        for partition in 0..partitions {
+            let count = block_size / partitions - if partition == 0 { order } else { 0 };
+            let parameter = bits.bits(parameter_bits)?;
+            if parameter == escape {
+                let raw = bits.bits(5)?;
+                for _ in 0..count {
+                    residuals.push(bits.signed(raw)?);
+                }
+                continue;
+            }
+            for _ in 0..count {
+                let folded = ((bits.unary()? as u64) << parameter) | bits.bits(parameter)? as u64;
+                residuals.push((folded >> 1) as i64 ^ -((folded & 1) as i64));
+            }
+        }
+        Ok(residuals)
+    }
+}
@@ -0,0 +1,421 @@
+// Read-only FUSE filesystem over an image, spoken directly over /dev/fuse
+//
+// Only the requests needed for browsing are answered; everything else is refused with ENOSYS,
+// which the kernel remembers and stops sending. The filesystem runs in the foreground until it
+// is unmounted with umount or fusermount -u.
+
+use std::ffi::CString;
+use std::fs::{File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::Path;
+use std::process::Command;
+use std::thread::{self, JoinHandle};
+
+use super::reader::{self, open_image, IsoEntry, ImageSource, IsoReader};
+use super::BLOCK_SIZE;
+
+mod sys {
+    use std::ffi::{c_char, c_int, c_ulong, c_void};
+
+    pub const MS_RDONLY: c_ulong = 1;
+    pub const MS_NOSUID: c_ulong = 2;
+    pub const MS_NODEV: c_ulong = 4;
+    pub const AF_UNIX: c_int = 1;
+    pub const SOCK_STREAM: c_int = 1;
+    pub const SOL_SOCKET: c_int = 1;
+    pub const SCM_RIGHTS: c_int = 1;
+
+    #[repr(C)]
+    pub struct IoVec {
+        pub base: *mut c_void,
+        pub len: usize,
+    }
+
+    #[repr(C)]
+    pub struct MsgHdr {
+        pub name: *mut c_void,
+        pub name_len: u32,
+        pub iov: *mut IoVec,
+        pub iov_len: usize,
+        pub control: *mut c_void,
+        pub control_len: usize,
+        pub flags: c_int,
+    }
+
+    extern "C" {
+        pub fn mount(source: *const c_char, target: *const c_char, fstype: *const c_char, flags: c_ulong, data: *const c_void) -> c_int;
+        pub fn getuid() -> u32;
+        pub fn getgid() -> u32;
+        pub fn socketpair(domain: c_int, kind: c_int, protocol: c_int, fds: *mut c_int) -> c_int;
+        pub fn recvmsg(fd: c_int, msg: *mut MsgHdr, flags: c_int) -> isize;
+    }
+}
+
+// Request opcodes
+const LOOKUP: u32 = 1;
+const FORGET: u32 = 2;
+const GETATTR: u32 = 3;
+const READLINK: u32 = 5;
+const OPEN: u32 = 14;
+const READ: u32 = 15;
+const STATFS: u32 = 17;
+const RELEASE: u32 = 18;
+const INIT: u32 = 26;
+const OPENDIR: u32 = 27;
+const READDIR: u32 = 28;
+const RELEASEDIR: u32 = 29;
+const INTERRUPT: u32 = 36;
+const DESTROY: u32 = 38;
+const BATCH_FORGET: u32 = 42;
+
+// Error numbers
+const ENOENT: i32 = 2;
+const EIO: i32 = 5;
+const ENOTDIR: i32 = 20;
+const EISDIR: i32 = 21;
+const EINVAL: i32 = 22;
+const EROFS: i32 = 30;
+const ENOSYS: i32 = 38;
+const ENODEV: i32 = 19;
+
+const KERNEL_VERSION: u32 = 7;
+const KERNEL_MINOR_VERSION: u32 = 31;
+const MAX_WRITE: u32 = 128 * 1024;
+const REQUEST_BUFFER_SIZE: usize = MAX_WRITE as usize + 4096;
+const HEADER_SIZE: usize = 40;
+const CACHE_SECONDS: u64 = 3600; // Nothing changes, so the kernel may cache names and attributes for long
+const O_ACCMODE: u32 = 3;
+
+// Mount the image read-only at the mount point and serve it until unmounted
+pub fn mount_image(iso_path: &Path, mount_point: &Path) -> io::Result<()> {
+    let mut filesystem = mounted(iso_path, mount_point)?;
+    println!("Mounted {} ({}) at {}", iso_path.display(), filesystem.reader.pvd.volume_id, mount_point.display());
+    filesystem.serve()
+}
+
+// Mount the image the same way and serve it from a thread of its own, which ends once the
+// filesystem is unmounted (see unmount)
+pub fn mount_in_background(iso_path: &Path, mount_point: &Path) -> io::Result<JoinHandle<io::Result<()>>> {
+    let mut filesystem = mounted(iso_path, mount_point)?;
+    Ok(thread::spawn(move || filesystem.serve()))
+}
+
+// Unmount what mount_image or mount_in_background mounted
+pub fn unmount(mount_point: &Path) -> io::Result<()> {
+    let helpers: &[(&str, &[&str])] = if running_as_root() { &[("umount", &[])] } else { &[("fusermount3", &["-u"]), ("fusermount", &["-u"])] };
+    for (helper, args) in helpers {
+        match Command::new(helper).args(*args).arg(mount_point).status() {
+            Ok(exit) if exit.success() => return Ok(()),
+            Ok(_) => return Err(io::Error::other(format!("{} could not unmount {}", helper, mount_point.display()))),
+            Err(err) if err.kind() == ErrorKind::NotFound => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Err(io::Error::new(ErrorKind::NotFound, "Unmounting needs umount or the fusermount helper"))
+}
+
+pub fn running_as_root() -> bool {
+    // SAFETY: getuid cannot fail
+    unsafe { sys::getuid() == 0 }
+}
+
+fn mounted(iso_path: &Path, mount_point: &Path) -> io::Result<Filesystem> {
+    let mut reader = open_image(iso_path)?;
+    let entries = reader.read_tree()?;
+    let device = open_device(iso_path, mount_point)?;
+    let total_blocks = reader.pvd.volume_space_size as u64;
+    Ok(Filesystem { reader, entries, device, total_blocks })
+}
+
+// Open /dev/fuse and mount it, directly when allowed and otherwise through the fusermount helper
+fn open_device(iso_path: &Path, mount_point: &Path) -> io::Result<File> {
+    let fs_name = iso_path.display().to_string().replace(',', "_");
+    let target = CString::new(mount_point.as_os_str().as_bytes()).map_err(|_| io::Error::new(ErrorKind::InvalidInput, "Invalid mount point"))?;
+
+    let device = OpenOptions::new().read(true).write(true).open("/dev/fuse")?;
+    // SAFETY: getuid and getgid cannot fail
+    let (uid, gid) = unsafe { (sys::getuid(), sys::getgid()) };
+    let data = CString::new(format!("fd={},rootmode=40000,user_id={},group_id={}", device.as_raw_fd(), uid, gid)).expect("mount data");
+    let source = CString::new(fs_name.as_bytes()).map_err(|_| io::Error::new(ErrorKind::InvalidInput, "Invalid image path"))?;
+
+    // SAFETY: all pointers are valid NUL-terminated strings for the duration of the call
+    let result = unsafe {
+        sys::mount(
+            source.as_ptr(),
+            target.as_ptr(),
+            c"fuse.makeiso".as_ptr(),
+            sys::MS_RDONLY | sys::MS_NOSUID | sys::MS_NODEV,
+            data.as_ptr() as *const _,
+        )
+    };
+    if result == 0 {
+        return Ok(device);
+    }
+
+    let error = io::Error::last_os_error();
+    if error.kind() != ErrorKind::PermissionDenied {
+        return Err(error);
+    }
+    drop(device);
+    mount_with_helper(&fs_name, mount_point)
+}
+
+// Let fusermount perform the mount; it sends the opened /dev/fuse descriptor back over a socket
+fn mount_with_helper(fs_name: &str, mount_point: &Path) -> io::Result<File> {
+    let mut fds = [0; 2];
+    // SAFETY: fds has room for the two descriptors socketpair returns
+    if unsafe { sys::socketpair(sys::AF_UNIX, sys::SOCK_STREAM, 0, fds.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: both descriptors were just created and are owned here
+    let (helper_end, our_end) = unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) };
+
+    let options = format!("ro,nosuid,nodev,fsname={},subtype=makeiso", fs_name);
+    let mut status = None;
+    for helper in ["fusermount3", "fusermount"] {
+        match Command::new(helper).arg("-o").arg(&options).arg("--").arg(mount_point).env("_FUSE_COMMFD", fds[0].to_string()).status() {
+            Ok(exit) => {
+                status = Some(exit);
+                break;
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    drop(helper_end);
+    match status {
+        Some(exit) if exit.success() => {}
+        Some(_) => return Err(io::Error::other("fusermount could not mount the image")),
+        None => return Err(io::Error::new(ErrorKind::PermissionDenied, "Mounting needs root or the fusermount helper")),
+    }
+
+    let mut byte = [0u8; 1];
+    let mut control = [0u64; 3]; // cmsghdr followed by one descriptor, 8-byte aligned
+    let mut iov = sys::IoVec { base: byte.as_mut_ptr() as *mut _, len: 1 };
+    let mut message = sys::MsgHdr {
+        name: std::ptr::null_mut(),
+        name_len: 0,
+        iov: &mut iov,
+        iov_len: 1,
+        control: control.as_mut_ptr() as *mut _,
+        control_len: std::mem::size_of_val(&control),
+        flags: 0,
+    };
+    // SAFETY: message points at buffers that outlive the call
+    if unsafe { sys::recvmsg(our_end.as_raw_fd(), &mut message, 0) } <= 0 {
+        return Err(io::Error::other("fusermount did not pass the FUSE device back"));
+    }
+
+    // cmsg_len (usize), cmsg_level (i32), cmsg_type (i32), then the descriptor
+    let header = control[1].to_ne_bytes();
+    let level = i32::from_ne_bytes([header[0], header[1], header[2], header[3]]);
+    let kind = i32::from_ne_bytes([header[4], header[5], header[6], header[7]]);
+    if message.control_len < 20 || level != sys::SOL_SOCKET || kind != sys::SCM_RIGHTS {
+        return Err(io::Error::other("fusermount did not pass the FUSE device back"));
+    }
+    let fd = control[2] as u32 as i32;
+    // SAFETY: the descriptor was received with SCM_RIGHTS and is owned here
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+struct Filesystem {
+    reader: IsoReader<ImageSource>,
+    entries: Vec<IsoEntry>,
+    device: File,
+    total_blocks: u64,
+}
+
+impl Filesystem {
+    // Answer requests until the filesystem is unmounted
+    fn serve(&mut self) -> io::Result<()> {
+        let mut buffer = vec![0u8; REQUEST_BUFFER_SIZE];
+        loop {
+            let len = match self.device.read(&mut buffer) {
+                Ok(len) => len,
+                Err(err) if err.raw_os_error() == Some(ENODEV) => return Ok(()), // Unmounted
+                Err(err) if matches!(err.kind(), ErrorKind::Interrupted | ErrorKind::NotFound | ErrorKind::WouldBlock) => continue,
+                Err(err) => return Err(err),
+            };
+            if len < HEADER_SIZE {
+                return Err(io::Error::new(ErrorKind::InvalidData, "Short FUSE request"));
+            }
+
+            let opcode = u32_at(&buffer, 4);
+            let unique = u64_at(&buffer, 8);
+            let node = u64_at(&buffer, 16);
+            let body = &buffer[HEADER_SIZE..len];
+
+            let reply = match opcode {
+                FORGET | BATCH_FORGET | INTERRUPT => continue, // No reply expected
+                DESTROY => {
+                    self.reply(unique, Ok(Vec::new()))?;
+                    return Ok(());
+                }
+                INIT => Ok(init_reply(body)),
+                _ => self.handle(opcode, node, body),
+            };
+            self.reply(unique, reply)?;
+        }
+    }
+
+    fn handle(&mut self, opcode: u32, node: u64, body: &[u8]) -> Result<Vec<u8>, i32> {
+        let index = self.index(node)?;
+        match opcode {
+            LOOKUP => {
+                let name = String::from_utf8_lossy(body.split(|&byte| byte == 0).next().unwrap_or(&[])).into_owned();
+                if !self.entries[index].is_directory {
+                    return Err(ENOTDIR);
+                }
+                let child = reader::child(&self.entries, index, &name, self.reader.case_insensitive()).ok_or(ENOENT)?;
+                Ok(self.entry_reply(child))
+            }
+            GETATTR => {
+                let mut reply = Vec::with_capacity(104);
+                reply.extend_from_slice(&CACHE_SECONDS.to_le_bytes());
+                reply.extend_from_slice(&[0; 8]);
+                reply.extend_from_slice(&self.attributes(index));
+                Ok(reply)
+            }
+            READLINK => self.entries[index].symlink.clone().map(String::into_bytes).ok_or(EINVAL),
+            OPEN => {
+                if self.entries[index].is_directory {
+                    return Err(EISDIR);
+                }
+                if u32_at(body, 0) & O_ACCMODE != 0 {
+                    return Err(EROFS);
+                }
+                Ok(vec![0; 16])
+            }
+            OPENDIR if !self.entries[index].is_directory => Err(ENOTDIR),
+            OPENDIR => Ok(vec![0; 16]),
+            READ => {
+                let offset = u64_at(body, 8);
+                let size = (u32_at(body, 16) as usize).min(MAX_WRITE as usize);
+                let mut data = vec![0u8; size];
+                let entry = &self.entries[index];
+                let len = self.reader.read_file_at(entry, offset, &mut data).map_err(|_| EIO)?;
+                data.truncate(len);
+                Ok(data)
+            }
+            READDIR => Ok(self.directory_listing(index, u64_at(body, 8), u32_at(body, 16) as usize)),
+            RELEASE | RELEASEDIR => Ok(Vec::new()),
+            STATFS => {
+                let mut reply = Vec::with_capacity(80);
+                for value in [self.total_blocks, 0, 0, self.entries.len() as u64, 0] {
+                    reply.extend_from_slice(&value.to_le_bytes());
+                }
+                for value in [BLOCK_SIZE as u32, 255, BLOCK_SIZE as u32, 0, 0, 0, 0, 0, 0, 0] {
+                    reply.extend_from_slice(&value.to_le_bytes());
+                }
+                Ok(reply)
+            }
+            _ => Err(ENOSYS),
+        }
+    }
+
+    // Node ids start at 1 for the root, which is entry 0
+    fn index(&self, node: u64) -> Result<usize, i32> {
+        match node.checked_sub(1) {
+            Some(index) if (index as usize) < self.entries.len() => Ok(index as usize),
+            _ => Err(ENOENT),
+        }
+    }
+
+    // fuse_attr for an entry
+    fn attributes(&self, index: usize) -> Vec<u8> {
+        let entry = &self.entries[index];
+        let size = match &entry.symlink {
+            Some(target) => target.len() as u64,
+            None => entry.size as u64,
+        };
+        let time = entry.modified.max(0) as u64;
+
+        let mut attr = Vec::with_capacity(88);
+        for value in [index as u64 + 1, size, size.div_ceil(512), time, time, time] {
+            attr.extend_from_slice(&value.to_le_bytes());
+        }
+        for value in [0, 0, 0, entry.mode, entry.links, entry.uid, entry.gid, 0, BLOCK_SIZE as u32, 0] {
+            attr.extend_from_slice(&value.to_le_bytes());
+        }
+        attr
+    }
+
+    // fuse_entry_out for a looked up entry
+    fn entry_reply(&self, index: usize) -> Vec<u8> {
+        let mut reply = Vec::with_capacity(128);
+        for value in [index as u64 + 1, 0, CACHE_SECONDS, CACHE_SECONDS] {
+            reply.extend_from_slice(&value.to_le_bytes());
+        }
+        reply.extend_from_slice(&[0; 8]);
+        reply.extend_from_slice(&self.attributes(index));
+        reply
+    }
+
+    // fuse_dirent records from the given offset, as many as fit in size bytes
+    fn directory_listing(&self, index: usize, offset: u64, size: usize) -> Vec<u8> {
+        let entry = &self.entries[index];
+        let names = [(index, "."), (entry.parent, "..")]
+            .into_iter()
+            .chain(entry.children.iter().map(|&child| (child, self.entries[child].name.as_str())));
+
+        let mut reply = Vec::new();
+        for (position, (target, name)) in names.enumerate().skip(offset as usize) {
+            let record_len = (24 + name.len()).next_multiple_of(8);
+            if reply.len() + record_len > size {
+                break;
+            }
+            reply.extend_from_slice(&(target as u64 + 1).to_le_bytes());
+            reply.extend_from_slice(&(position as u64 + 1).to_le_bytes());
+            reply.extend_from_slice(&(name.len() as u32).to_le_bytes());
+            reply.extend_from_slice(&((self.entries[target].mode >> 12) & 0o17).to_le_bytes());
+            reply.extend_from_slice(name.as_bytes());
+            reply.resize(reply.len() + record_len - 24 - name.len(), 0);
+        }
+        reply
+    }
+
+    // Send a reply: the out header followed by the data, or a negated error number
+    fn reply(&mut self, unique: u64, reply: Result<Vec<u8>, i32>) -> io::Result<()> {
+        let (error, data) = match reply {
+            Ok(data) => (0, data),
+            Err(errno) => (-errno, Vec::new()),
+        };
+        let mut message = Vec::with_capacity(16 + data.len());
+        message.extend_from_slice(&(16 + data.len() as u32).to_le_bytes());
+        message.extend_from_slice(&error.to_le_bytes());
+        message.extend_from_slice(&unique.to_le_bytes());
+        message.extend_from_slice(&data);
+
+        match self.device.write(&message) {
+            Ok(_) => Ok(()),
+            // The request was interrupted and the kernel no longer waits for it
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+// fuse_init_out: protocol version, read-ahead and write limits
+fn init_reply(body: &[u8]) -> Vec<u8> {
+    let max_readahead = u32_at(body, 8);
+    let mut reply = Vec::with_capacity(64);
+    for value in [KERNEL_VERSION, KERNEL_MINOR_VERSION, max_readahead, 0] {
+        reply.extend_from_slice(&value.to_le_bytes());
+    }
+    reply.extend_from_slice(&0u16.to_le_bytes()); // max_background
+    reply.extend_from_slice(&0u16.to_le_bytes()); // congestion_threshold
+    reply.extend_from_slice(&MAX_WRITE.to_le_bytes());
+    reply.extend_from_slice(&1u32.to_le_bytes()); // time_gran
+    reply.resize(64, 0);
+    reply
+}
+
+fn u32_at(data: &[u8], at: usize) -> u32 {
+    data.get(at..at + 4).map_or(0, |bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn u64_at(data: &[u8], at: usize) -> u64 {
+    data.get(at..at + 8).map_or(0, |bytes| u64::from_le_bytes(bytes.try_into().expect("8 bytes")))
+}
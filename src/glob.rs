@@ -0,0 +1,65 @@
+// Shell-style wildcard patterns: "*" and "?" (not matching "/"), "**" (matching anything,
+// "/" included), and "[...]" character classes with ranges and "!" or "^" negation
+
+// Whether the whole of text matches the pattern
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    matches_at(&pattern, &text)
+}
+
+fn matches_at(pattern: &[char], text: &[char]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some('*') if pattern.get(1) == Some(&'*') => {
+            // "**/" also matches no directories at all
+            let rest = &pattern[2..];
+            if rest.first() == Some(&'/') && matches_at(&rest[1..], text) {
+                return true;
+            }
+            (0..=text.len()).any(|skip| matches_at(rest, &text[skip..]))
+        }
+        Some('*') => {
+            let rest = &pattern[1..];
+            for skip in 0..=text.len() {
+                if matches_at(rest, &text[skip..]) {
+                    return true;
+                }
+                if text.get(skip) == Some(&'/') {
+                    break;
+                }
+            }
+            false
+        }
+        Some('?') => text.first().is_some_and(|&c| c != '/') && matches_at(&pattern[1..], &text[1..]),
+        Some('[') => match (class_matches(&pattern[1..], text.first().copied()), text.first()) {
+            (Some((true, used)), Some(_)) => matches_at(&pattern[1 + used..], &text[1..]),
+            (None, Some(&'[')) => matches_at(&pattern[1..], &text[1..]), // No closing bracket: a literal "["
+            _ => false,
+        },
+        Some(&c) => text.first() == Some(&c) && matches_at(&pattern[1..], &text[1..]),
+    }
+}
+
+// Match one character against a class (after its "["); whether it matched and how much of
+// the pattern the class took, or None if the class isn't closed
+fn class_matches(class: &[char], c: Option<char>) -> Option<(bool, usize)> {
+    let negated = matches!(class.first(), Some('!' | '^'));
+    let mut at = usize::from(negated);
+    let mut found = false;
+    let mut first = true;
+    loop {
+        let &member = class.get(at)?;
+        if member == ']' && !first {
+            return Some((found != negated && c.is_some_and(|c| c != '/'), at + 1));
+        }
+        first = false;
+        if class.get(at + 1) == Some(&'-') && class.get(at + 2).is_some_and(|&end| end != ']') {
+            found |= c.is_some_and(|c| member <= c && c <= class[at + 2]);
+            at += 3;
+        } else {
+            found |= c == Some(member);
+            at += 1;
+        }
+    }
+}
@@ -0,0 +1,102 @@
+// Search the contents of the files in an image for a regular expression, line by line, reading
+// each file's extent straight from the image
+
+use std::io::{self, ErrorKind};
+use std::path::Path;
+
+use super::glob;
+use super::reader::{open_image, IsoEntry};
+use super::regex::Regex;
+use super::unicode;
+
+const READ_CHUNK_SIZE: usize = 1024 * 1024;
+const USAGE: &str = "Usage: makeiso grep [-i] [-l] <image.iso> <pattern> [path-glob]";
+
+// Whether a file is selected by the glob: against its path, or its name for globs without "/"
+fn selected(entry: &IsoEntry, path_glob: Option<&str>) -> bool {
+    match path_glob {
+        None => true,
+        Some(pattern) if pattern.contains('/') => glob::matches(pattern.trim_start_matches('/'), entry.path.trim_start_matches('/')),
+        Some(pattern) => glob::matches(pattern, &entry.name),
+    }
+}
+
+// makeiso grep [-i] [-l] <image.iso> <pattern> [path-glob]: print path:line:text for every matching line,
+// failing when nothing matches so the exit status is 1 then, as grep(1)'s is
+pub fn grep(args: &[String]) -> io::Result<()> {
+    let (mut ignore_case, mut names_only) = (false, false);
+    let mut positional = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "-i" | "--ignore-case" => ignore_case = true,
+            "-l" | "--files-with-matches" => names_only = true,
+            _ if arg.starts_with('-') && arg.len() > 1 && positional.len() != 1 => {
+                return Err(io::Error::new(ErrorKind::InvalidInput, format!("Unknown option: {}\n{}", arg, USAGE)));
+            }
+            _ => positional.push(arg.as_str()),
+        }
+    }
+    let (image, pattern, path_glob) = match positional[..] {
+        [image, pattern] => (image, pattern, None),
+        [image, pattern, path_glob] => (image, pattern, Some(path_glob)),
+        _ => return Err(io::Error::new(ErrorKind::InvalidInput, USAGE)),
+    };
+    let path_glob = path_glob.map(unicode::reading);
+    let regex = Regex::new(pattern, ignore_case)?;
+
+    let mut reader = open_image(Path::new(image))?;
+    let entries = reader.read_tree()?;
+    let mut buffer = vec![0u8; READ_CHUNK_SIZE];
+    let mut matching_files = 0;
+
+    for entry in entries.iter().filter(|entry| !entry.is_directory && entry.symlink.is_none() && selected(entry, path_glob.as_deref())) {
+        let mut pending = Vec::new(); // The start of a line continued in the next chunk
+        let (mut line_number, mut offset) = (0, 0);
+        let mut binary = false;
+        let mut matched = false;
+
+        while offset < entry.size as u64 {
+            let bytes_read = reader.read_file_at(entry, offset, &mut buffer)?;
+            if offset == 0 {
+                binary = buffer[..bytes_read].contains(&0);
+            }
+            offset += bytes_read as u64;
+            pending.extend_from_slice(&buffer[..bytes_read]);
+
+            // Hand over complete lines, and the rest at the end of the file
+            let complete = if offset == entry.size as u64 { pending.len() } else { pending.iter().rposition(|&byte| byte == b'\n').map_or(0, |at| at + 1) };
+            let lines = &pending[..complete];
+            let lines = if complete == 0 { &[][..] } else { lines.strip_suffix(b"\n").unwrap_or(lines) };
+            for line in lines.split(|&byte| byte == b'\n').take(if complete == 0 { 0 } else { usize::MAX }) {
+                line_number += 1;
+                let text = String::from_utf8_lossy(line.strip_suffix(b"\r").unwrap_or(line));
+                if !regex.is_match(&text) {
+                    continue;
+                }
+                matched = true;
+                if names_only || binary {
+                    break;
+                }
+                println!("{}:{}:{}", entry.path, line_number, text);
+            }
+            if matched && (names_only || binary) {
+                break;
+            }
+            pending.drain(..complete);
+        }
+
+        if matched {
+            matching_files += 1;
+            if names_only {
+                println!("{}", entry.path);
+            } else if binary {
+                println!("Binary file {} matches", entry.path);
+            }
+        }
+    }
+
+    if matching_files == 0 {
+        return Err(io::Error::new(ErrorKind::NotFound, format!("no matches for {}", pattern)));
+    }
+    Ok(())
+}
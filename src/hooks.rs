@@ -0,0 +1,72 @@
+// Commands run around a build (--pre-hook CMD, --post-hook CMD, or pre-hook and post-hook in a
+// profile): the pre-hook before anything is scanned, to quiesce a database or mount the media,
+// and the post-hook once the build is over, to resume the database, eject the disc or start an
+// offsite sync. Each is run by the shell (sh -c, or cmd /C on Windows) with MAKEISO_HOOK and
+// MAKEISO_IMAGE set; the post-hook gets a JSON summary of the build on its standard input:
+//
+//     {"image": "/backups/home.iso", "ok": true, "seconds": 42, "bytes": 73400320, "skipped": 0, "error": ""}
+//
+// A pre-hook that fails stops the build. The post-hook runs whether or not the build (or the
+// pre-hook) succeeded, so what the pre-hook did can be undone; when it fails the run fails too,
+// though the image is kept.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::Instant;
+
+use super::{backend, checkpoint, events, json, skipped_files, ImageOptions};
+
+// Run build between the hooks the options name
+pub fn around(iso_path: &Path, options: &ImageOptions, build: impl FnOnce() -> io::Result<()>) -> io::Result<()> {
+    let started = Instant::now();
+    let result = match &options.pre_hook {
+        Some(command) => execute("pre", command, iso_path, None).map_err(|e| io::Error::new(e.kind(), format!("the pre-hook failed: {}", e))),
+        None => Ok(()),
+    };
+    let result = result.and_then(|()| build());
+    checkpoint::finish(options, &result); // The status file, if there is one, says how it ended
+    let Some(command) = &options.post_hook else {
+        return result;
+    };
+
+    let bytes = if result.is_err() || backend::is_remote(iso_path) { 0 } else { fs::metadata(iso_path).map_or(0, |metadata| metadata.len()) };
+    let summary = format!(
+        "{{\"image\": {}, \"ok\": {}, \"seconds\": {}, \"bytes\": {}, \"skipped\": {}, \"error\": {}}}\n",
+        json::string(&iso_path.display().to_string()),
+        result.is_ok(),
+        started.elapsed().as_secs(),
+        bytes,
+        skipped_files(),
+        json::string(&result.as_ref().err().map(|e| e.to_string()).unwrap_or_default())
+    );
+    match (result, execute("post", command, iso_path, Some(&summary))) {
+        (Ok(()), Err(e)) => Err(io::Error::new(e.kind(), format!("the post-hook failed: {}", e))),
+        (Err(build), Err(e)) => {
+            events::warn(&format!("The post-hook failed too: {}", e));
+            Err(build)
+        }
+        (result, Ok(())) => result,
+    }
+}
+
+fn execute(hook: &str, command: &str, iso_path: &Path, input: Option<&str>) -> io::Result<()> {
+    let mut shell = if cfg!(windows) { Command::new("cmd") } else { Command::new("sh") };
+    shell.arg(if cfg!(windows) { "/C" } else { "-c" }).arg(command);
+    let mut child = shell
+        .env("MAKEISO_HOOK", hook)
+        .env("MAKEISO_IMAGE", iso_path)
+        .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+        .spawn()
+        .map_err(|e| io::Error::new(e.kind(), format!("could not run {}: {}", command, e)))?;
+    if let (Some(mut stdin), Some(input)) = (child.stdin.take(), input) {
+        // A hook that doesn't read the summary closes the pipe early, which is fine
+        let _ = stdin.write_all(input.as_bytes());
+    }
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("{} exited with {}", command, status)));
+    }
+    Ok(())
+}
@@ -0,0 +1,249 @@
+// Random access to remote images over HTTP range requests
+//
+// Plain http:// is spoken directly; https:// goes through curl, which brings TLS, proxies and
+// the system certificate store. Small reads (descriptors, path tables, directories) are served
+// from a cache of fixed-size chunks so walking the tree doesn't cost a request per sector;
+// large reads (file contents) fetch exactly the requested range.
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::process::Command;
+use std::time::Duration;
+
+const CACHE_CHUNK_SIZE: u64 = 64 * 1024;
+const CACHE_CHUNKS: usize = 256; // Up to 16 MiB of cached chunks
+const MAX_REDIRECTS: usize = 5;
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+// Whether a location names a remote image rather than a local file
+pub fn is_url(location: &str) -> bool {
+    location.starts_with("http://") || location.starts_with("https://")
+}
+
+// A remote image read with Range requests
+pub struct HttpSource {
+    url: String, // Final location after redirects
+    len: u64,
+    cache: HashMap<u64, Vec<u8>>,
+    cache_order: VecDeque<u64>, // Oldest chunk first
+}
+
+impl HttpSource {
+    // Connect to the image, learning its size from the first chunk
+    pub fn open(url: &str) -> io::Result<HttpSource> {
+        let mut url = url.to_string();
+        let mut redirects = 0;
+        let (status, headers, body) = loop {
+            let response = fetch(&url, 0, CACHE_CHUNK_SIZE - 1)?;
+            if !is_redirect(response.0) {
+                break response;
+            }
+            redirects += 1;
+            if redirects > MAX_REDIRECTS {
+                return Err(io::Error::other(format!("too many redirects for {}", url)));
+            }
+            let location = header(&response.1, "location").ok_or_else(|| io::Error::other(format!("redirect without a location from {}", url)))?;
+            url = resolve(&url, location);
+        };
+
+        // 206 with "Content-Range: bytes 0-65535/<total>"; a 200 means ranges aren't supported
+        let len = match status {
+            206 => header(&headers, "content-range").and_then(|range| range.rsplit('/').next()?.trim().parse().ok()),
+            200 if (body.len() as u64) < CACHE_CHUNK_SIZE => Some(body.len() as u64),
+            200 => return Err(io::Error::new(ErrorKind::Unsupported, format!("{} does not support range requests", url))),
+            _ => return Err(io::Error::other(format!("{} answered with HTTP status {}", url, status))),
+        }
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, format!("{} did not report the size of the image", url)))?;
+
+        let mut source = HttpSource {
+            url,
+            len,
+            cache: HashMap::new(),
+            cache_order: VecDeque::new(),
+        };
+        source.remember(0, body);
+        Ok(source)
+    }
+
+    // Size of the remote image in bytes
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    // Fill buf from the given offset of the image
+    pub fn read_exact_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        if offset + buf.len() as u64 > self.len {
+            return Err(io::Error::new(ErrorKind::UnexpectedEof, "read past the end of the remote image"));
+        }
+        if buf.len() as u64 > CACHE_CHUNK_SIZE {
+            let data = self.range(offset, buf.len() as u64)?;
+            buf.copy_from_slice(&data);
+            return Ok(());
+        }
+
+        // Small reads touch at most two cached chunks
+        let mut done = 0;
+        while done < buf.len() {
+            let position = offset + done as u64;
+            let chunk = position / CACHE_CHUNK_SIZE;
+            if !self.cache.contains_key(&chunk) {
+                let start = chunk * CACHE_CHUNK_SIZE;
+                let data = self.range(start, CACHE_CHUNK_SIZE.min(self.len - start))?;
+                self.remember(chunk, data);
+            }
+            let data = &self.cache[&chunk];
+            let at = (position - chunk * CACHE_CHUNK_SIZE) as usize;
+            let len = (buf.len() - done).min(data.len() - at);
+            buf[done..done + len].copy_from_slice(&data[at..at + len]);
+            done += len;
+        }
+        Ok(())
+    }
+
+    fn remember(&mut self, chunk: u64, data: Vec<u8>) {
+        if self.cache_order.len() >= CACHE_CHUNKS {
+            if let Some(oldest) = self.cache_order.pop_front() {
+                self.cache.remove(&oldest);
+            }
+        }
+        self.cache.insert(chunk, data);
+        self.cache_order.push_back(chunk);
+    }
+
+    // Fetch exactly len bytes from offset
+    fn range(&self, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+        let (status, _, body) = fetch(&self.url, offset, offset + len - 1)?;
+        if status != 206 && !(status == 200 && offset == 0 && body.len() as u64 == self.len) {
+            return Err(io::Error::other(format!("{} answered a range request with HTTP status {}", self.url, status)));
+        }
+        if status == 200 {
+            return Ok(body[..len as usize].to_vec());
+        }
+        if body.len() as u64 != len {
+            return Err(io::Error::new(ErrorKind::UnexpectedEof, format!("short range response from {}", self.url)));
+        }
+        Ok(body)
+    }
+}
+
+// Status, headers (lowercase names) and body
+type Response = (u16, Vec<(String, String)>, Vec<u8>);
+
+fn is_redirect(status: u16) -> bool {
+    matches!(status, 301 | 302 | 303 | 307 | 308)
+}
+
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+}
+
+// Resolve a redirect location against the URL it came from
+fn resolve(base: &str, location: &str) -> String {
+    if is_url(location) {
+        return location.to_string();
+    }
+    let scheme_end = base.find("://").map_or(0, |at| at + 3);
+    let host_end = base[scheme_end..].find('/').map_or(base.len(), |at| scheme_end + at);
+    if location.starts_with('/') {
+        format!("{}{}", &base[..host_end], location)
+    } else {
+        let directory_end = base.rfind('/').filter(|&at| at >= host_end).map_or(base.len(), |at| at + 1);
+        format!("{}{}{}", &base[..directory_end], if directory_end == base.len() { "/" } else { "" }, location)
+    }
+}
+
+// Request one byte range (inclusive)
+fn fetch(url: &str, first: u64, last: u64) -> io::Result<Response> {
+    match url.strip_prefix("http://") {
+        Some(rest) => fetch_plain(rest, first, last),
+        None => fetch_with_curl(url, first, last),
+    }
+}
+
+// HTTP/1.1 over a fresh connection
+fn fetch_plain(rest: &str, first: u64, last: u64) -> io::Result<Response> {
+    let (authority, path) = match rest.find('/') {
+        Some(at) => (&rest[..at], &rest[at..]),
+        None => (rest, "/"),
+    };
+    let address = if authority.contains(':') { authority.to_string() } else { format!("{}:80", authority) };
+
+    let mut stream = TcpStream::connect(&address)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    write!(
+        stream,
+        "GET {} HTTP/1.1\r\nHost: {}\r\nRange: bytes={}-{}\r\nUser-Agent: makeiso\r\nConnection: close\r\n\r\n",
+        path, authority, first, last
+    )?;
+
+    let mut reader = BufReader::new(stream);
+    let mut status_line = String::new();
+    reader.read_line(&mut status_line)?;
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, format!("not an HTTP response: {}", status_line.trim())))?;
+
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+        }
+    }
+
+    let mut body = Vec::new();
+    if header(&headers, "transfer-encoding").is_some_and(|encoding| encoding.eq_ignore_ascii_case("chunked")) {
+        loop {
+            let mut size_line = String::new();
+            reader.read_line(&mut size_line)?;
+            let size = usize::from_str_radix(size_line.trim().split(';').next().unwrap_or(""), 16)
+                .map_err(|_| io::Error::new(ErrorKind::InvalidData, "bad chunk in HTTP response"))?;
+            if size == 0 {
+                break;
+            }
+            let start = body.len();
+            body.resize(start + size, 0);
+            reader.read_exact(&mut body[start..])?;
+            reader.read_line(&mut String::new())?;
+        }
+    } else if let Some(len) = header(&headers, "content-length").and_then(|len| len.parse::<u64>().ok()) {
+        reader.take(len).read_to_end(&mut body)?;
+    } else {
+        reader.read_to_end(&mut body)?;
+    }
+    Ok((status, headers, body))
+}
+
+// HTTPS through curl: the body on stdout, the status and Content-Range written to stderr
+fn fetch_with_curl(url: &str, first: u64, last: u64) -> io::Result<Response> {
+    let output = Command::new("curl")
+        .args(["--silent", "--show-error", "--range"])
+        .arg(format!("{}-{}", first, last))
+        .args(["--max-time", &TIMEOUT.as_secs().to_string(), "--write-out", "%{stderr}\n%{http_code} %{redirect_url} %header{content-range}"])
+        .arg(url)
+        .output()
+        .map_err(|e| io::Error::new(e.kind(), format!("could not run curl for {}: {}", url, e)))?;
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let summary = stderr.lines().last().unwrap_or("");
+    let mut fields = summary.splitn(3, ' ');
+    let status = fields.next().and_then(|code| code.parse().ok()).filter(|&code| code != 0);
+    let Some(status) = status.filter(|_| output.status.success()) else {
+        return Err(io::Error::other(format!("curl could not fetch {}: {}", url, stderr.trim())));
+    };
+
+    let mut headers = Vec::new();
+    if let Some(location) = fields.next().filter(|location| !location.is_empty()) {
+        headers.push(("location".to_string(), location.to_string()));
+    }
+    if let Some(range) = fields.next().filter(|range| !range.is_empty()) {
+        headers.push(("content-range".to_string(), range.to_string()));
+    }
+    Ok((status, headers, output.stdout))
+}
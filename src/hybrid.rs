@@ -0,0 +1,284 @@
+// Hybrid images: partition tables in the system area so the same image boots from a USB stick
+// as well as from CD
+//
+// The MBR carries boot code (isolinux's isohdpfx.bin, which chains to the El Torito boot file
+// through the address patched in at byte 432), a bootable partition over the whole ISO 9660
+// volume and, with an EFI boot image, a partition for it. --isohybrid-gpt-basdat adds a GPT
+// listing the volume and the EFI boot image as Basic Data partitions for UEFI firmware that
+// only looks at GPT; --isohybrid-apm-hfsplus adds an Apple Partition Map (in 2048-byte blocks)
+// with the EFI boot image as an HFS+ partition, which is what older Macs boot from. Partitions
+// overlap the ISO 9660 volume, as on every hybrid image. GUIDs are derived from the volume
+// label and the image time, so reproducible images stay reproducible.
+//
+// --append-partition N:TYPE:FILE (xorriso's -append_partition) adds partition images after
+// the volume instead, e.g. an EFI system partition, in MBR slot N and in the GPT. Those don't
+// overlap anything: the partition over the volume ends where they start, and without
+// --isohybrid-mbr the MBR holds nothing but the partition table, the volume as type 0xCD.
+//
+// PowerPC firmware boots from the MBR too, without boot code: CHRP machines from the volume as
+// a partition of type 0x96 (--chrp-boot), PReP ones from a type 0x41 partition holding the
+// boot program (--prep-boot FILE), which is appended like the others.
+//
+// The tables are written over what --system-area put into the system area, if anything.
+
+use std::fs::File;
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::PathBuf;
+
+use super::eltorito::boot_file;
+use super::sha256::Sha256;
+use super::zip::crc32;
+use super::{base_system_area, pad_to_block, ImageOptions, ImageTree, Layout, BLOCK_SIZE, SYSTEM_AREA_BLOCKS};
+
+const SECTOR_SIZE: u64 = 512;
+const SECTORS_PER_BLOCK: u64 = BLOCK_SIZE as u64 / SECTOR_SIZE;
+const MBR_CODE_SIZE: usize = 432; // Boot code ahead of the boot file address and the disk signature
+pub const GPT_BACKUP_BLOCKS: u32 = 9; // Backup entries (32 sectors) and header (1) at the end of the image
+const GPT_ENTRIES: usize = 128;
+const GPT_ENTRY_SIZE: usize = 128;
+const GPT_ENTRY_SECTORS: u64 = (GPT_ENTRIES * GPT_ENTRY_SIZE) as u64 / SECTOR_SIZE;
+const BASIC_DATA: [u8; 16] = [0xA2, 0xA0, 0xD0, 0xEB, 0xE5, 0xB9, 0x33, 0x44, 0x87, 0xC0, 0x68, 0xB6, 0xB7, 0x26, 0x99, 0xC7];
+const EFI_SYSTEM: [u8; 16] = [0x28, 0x73, 0x2A, 0xC1, 0x1F, 0xF8, 0xD2, 0x11, 0xBA, 0x4B, 0x00, 0xA0, 0xC9, 0x3E, 0xC9, 0x3B];
+pub const MBR_SLOTS: usize = 4;
+pub const PREP_BOOT: u8 = 0x41;
+const CHRP: u8 = 0x96;
+// APM driver descriptor signature, block size 2048 and a block count x86 CPUs run through as NOPs
+const APM_HEADER: [u8; 8] = [0x45, 0x52, 0x08, 0x00, 0x00, 0x00, 0x90, 0x90];
+const APM_ENTRIES: u64 = 2; // The map itself and the HFS+ partition
+
+// Which partition tables go into the system area
+#[derive(Debug, Clone, PartialEq)]
+pub struct HybridOptions {
+    pub mbr: Vec<u8>, // Boot code, at most 432 bytes are used; empty with only appended partitions
+    pub gpt_basdat: bool,
+    pub apm_hfsplus: bool,
+    pub chrp: bool, // List the volume as a CHRP partition
+    pub appended: Vec<AppendedPartition>,
+}
+
+// A partition image written after the volume
+#[derive(Debug, Clone, PartialEq)]
+pub struct AppendedPartition {
+    pub slot: usize, // MBR partition number, 1 to 4 (in a Sun disk label 2 to 8)
+    pub kind: u8,    // MBR partition type, 0xEF for an EFI system partition (unused in a Sun disk label)
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+impl HybridOptions {
+    // How many MBR slots the partitions makeiso adds itself take: the volume, the EFI boot
+    // image and the GPT protective partition
+    pub fn own_slots(&self, efi: bool) -> usize {
+        1 + (efi && !self.mbr.is_empty()) as usize + self.gpt_basdat as usize
+    }
+
+    // Put a partition that asked for no slot (0) after the partitions makeiso adds itself
+    pub fn assign_slot(&mut self, efi: bool) {
+        let requested: Vec<usize> = self.appended.iter().map(|partition| partition.slot).collect();
+        let mut free = (1..=MBR_SLOTS).filter(|slot| !requested.contains(slot)).skip(self.own_slots(efi));
+        for partition in self.appended.iter_mut().filter(|partition| partition.slot == 0) {
+            partition.slot = free.next().unwrap_or(0);
+        }
+    }
+}
+
+// Where the volume and the boot files are, in 512-byte sectors
+struct Geometry {
+    volume_sectors: u64,     // The ISO 9660 volume, from sector 0
+    disk_sectors: u64,       // The whole image, including the backup GPT
+    bios_block: Option<u32>, // Block the MBR code chain-loads
+    efi: Option<(u64, u64)>, // First sector and length of the EFI boot image
+    gpt_entries_lba: u64,    // Primary GPT entries, after the APM when there is one
+    appended: Vec<(usize, u8, u64, u64)>, // Slot, type, first sector and length of each appended partition
+}
+
+fn geometry(tree: &ImageTree, layout: &Layout, hybrid: &HybridOptions) -> Geometry {
+    let boot = layout.boot.as_ref();
+    let tail = if hybrid.gpt_basdat { GPT_BACKUP_BLOCKS } else { 0 };
+    let appended: Vec<_> = hybrid
+        .appended
+        .iter()
+        .zip(&layout.appended)
+        .map(|(partition, &block)| (partition.slot, partition.kind, block as u64 * SECTORS_PER_BLOCK, partition.size.div_ceil(SECTOR_SIZE)))
+        .collect();
+    let volume_end = layout.appended.first().copied().unwrap_or(layout.total_blocks - tail);
+    Geometry {
+        volume_sectors: volume_end as u64 * SECTORS_PER_BLOCK,
+        disk_sectors: layout.total_blocks as u64 * SECTORS_PER_BLOCK,
+        bios_block: boot.and_then(|boot| boot.file).filter(|_| !hybrid.mbr.is_empty()).map(|file| boot_file(tree, file).extent),
+        efi: boot.and_then(|boot| boot.efi_file).filter(|_| !hybrid.mbr.is_empty()).map(|file| {
+            let file = boot_file(tree, file);
+            (file.extent as u64 * SECTORS_PER_BLOCK, (file.size as u64).div_ceil(SECTOR_SIZE))
+        }),
+        gpt_entries_lba: if hybrid.apm_hfsplus { (1 + APM_ENTRIES) * SECTORS_PER_BLOCK } else { 2 },
+        appended,
+    }
+}
+
+// A version 4 GUID (in its mixed-endian on-disk form) that only depends on the volume
+fn guid(options: &ImageOptions, image_time: i64, purpose: &str) -> [u8; 16] {
+    let mut sha = Sha256::new();
+    sha.update(options.identity.volume_id.as_bytes());
+    sha.update(&image_time.to_le_bytes());
+    sha.update(purpose.as_bytes());
+    let mut guid = [0u8; 16];
+    guid.copy_from_slice(&sha.finish()[..16]);
+    guid[7] = (guid[7] & 0x0F) | 0x40;
+    guid[8] = (guid[8] & 0x3F) | 0x80;
+    guid
+}
+
+// A primary partition table entry addressed by LBA only
+fn mbr_partition(bootable: bool, kind: u8, start: u64, sectors: u64) -> [u8; 16] {
+    let mut entry = [0u8; 16];
+    entry[0] = if bootable { 0x80 } else { 0 };
+    entry[1..4].copy_from_slice(&[0xFE, 0xFF, 0xFF]);
+    entry[4] = kind;
+    entry[5..8].copy_from_slice(&[0xFE, 0xFF, 0xFF]);
+    entry[8..12].copy_from_slice(&(start.min(u32::MAX as u64) as u32).to_le_bytes());
+    entry[12..16].copy_from_slice(&(sectors.min(u32::MAX as u64) as u32).to_le_bytes());
+    entry
+}
+
+// An Apple Partition Map entry, one 2048-byte block
+fn apm_entry(start: u64, blocks: u64, name: &str, kind: &str, status: u32) -> Vec<u8> {
+    let mut entry = vec![0u8; BLOCK_SIZE];
+    entry[0..2].copy_from_slice(b"PM");
+    entry[4..8].copy_from_slice(&(APM_ENTRIES as u32).to_be_bytes());
+    entry[8..12].copy_from_slice(&(start as u32).to_be_bytes());
+    entry[12..16].copy_from_slice(&(blocks as u32).to_be_bytes());
+    entry[16..16 + name.len()].copy_from_slice(name.as_bytes());
+    entry[48..48 + kind.len()].copy_from_slice(kind.as_bytes());
+    entry[84..88].copy_from_slice(&(blocks as u32).to_be_bytes());
+    entry[88..92].copy_from_slice(&status.to_be_bytes());
+    entry
+}
+
+// The GPT partition entry array: the volume, the EFI boot image, then the appended partitions
+fn gpt_entries(geometry: &Geometry, options: &ImageOptions, image_time: i64) -> Vec<u8> {
+    let mut entries = vec![0u8; GPT_ENTRIES * GPT_ENTRY_SIZE];
+    let volume_start = SYSTEM_AREA_BLOCKS as u64 * SECTORS_PER_BLOCK;
+    let mut partitions = vec![("ISO9660".to_string(), BASIC_DATA, volume_start, geometry.volume_sectors - volume_start)];
+    if let Some((start, sectors)) = geometry.efi {
+        partitions.push(("EFI boot image".to_string(), BASIC_DATA, start, sectors));
+    }
+    for &(slot, kind, start, sectors) in &geometry.appended {
+        partitions.push((format!("Appended{}", slot), if kind == 0xEF { EFI_SYSTEM } else { BASIC_DATA }, start, sectors));
+    }
+    for (index, (name, kind, start, sectors)) in partitions.into_iter().enumerate() {
+        let entry = &mut entries[index * GPT_ENTRY_SIZE..(index + 1) * GPT_ENTRY_SIZE];
+        entry[0..16].copy_from_slice(&kind);
+        entry[16..32].copy_from_slice(&guid(options, image_time, &name));
+        entry[32..40].copy_from_slice(&start.to_le_bytes());
+        entry[40..48].copy_from_slice(&(start + sectors - 1).to_le_bytes());
+        for (at, unit) in name.encode_utf16().enumerate() {
+            entry[56 + 2 * at..58 + 2 * at].copy_from_slice(&unit.to_le_bytes());
+        }
+    }
+    entries
+}
+
+// A GPT header, primary (at sector 1) or backup (in the last sector)
+fn gpt_header(geometry: &Geometry, options: &ImageOptions, image_time: i64, backup: bool, entries: &[u8]) -> Vec<u8> {
+    let last = geometry.disk_sectors - 1;
+    let (current, other, entries_lba) = if backup { (last, 1, last - GPT_ENTRY_SECTORS) } else { (1, last, geometry.gpt_entries_lba) };
+
+    let mut header = vec![0u8; SECTOR_SIZE as usize];
+    header[0..8].copy_from_slice(b"EFI PART");
+    header[8..12].copy_from_slice(&0x0001_0000u32.to_le_bytes());
+    header[12..16].copy_from_slice(&92u32.to_le_bytes());
+    header[24..32].copy_from_slice(&current.to_le_bytes());
+    header[32..40].copy_from_slice(&other.to_le_bytes());
+    header[40..48].copy_from_slice(&(SYSTEM_AREA_BLOCKS as u64 * SECTORS_PER_BLOCK).to_le_bytes()); // First usable sector
+    header[48..56].copy_from_slice(&(last - GPT_ENTRY_SECTORS - 1).to_le_bytes()); // Last usable sector
+    header[56..72].copy_from_slice(&guid(options, image_time, "disk"));
+    header[72..80].copy_from_slice(&entries_lba.to_le_bytes());
+    header[80..84].copy_from_slice(&(GPT_ENTRIES as u32).to_le_bytes());
+    header[84..88].copy_from_slice(&(GPT_ENTRY_SIZE as u32).to_le_bytes());
+    header[88..92].copy_from_slice(&crc32(0, entries).to_le_bytes());
+    let header_crc = crc32(0, &header[..92]);
+    header[16..20].copy_from_slice(&header_crc.to_le_bytes());
+    header
+}
+
+// The 32 KiB system area: MBR, and the GPT and APM when asked for
+pub fn system_area(tree: &ImageTree, layout: &Layout, options: &ImageOptions, hybrid: &HybridOptions) -> Vec<u8> {
+    let geometry = geometry(tree, layout, hybrid);
+    let mut area = base_system_area(options);
+
+    // Boot code, the address it loads the El Torito boot file from, and a disk signature
+    let code = &hybrid.mbr[..hybrid.mbr.len().min(MBR_CODE_SIZE)];
+    area[..code.len()].copy_from_slice(code);
+    if let Some(block) = geometry.bios_block {
+        area[432..436].copy_from_slice(&(block as u64 * SECTORS_PER_BLOCK).min(u32::MAX as u64).to_le_bytes()[..4]);
+    }
+    area[440..444].copy_from_slice(&guid(options, tree.image_time, "mbr")[..4]);
+
+    let mut partitions = vec![match (hybrid.chrp, hybrid.mbr.is_empty()) {
+        (true, _) => mbr_partition(false, CHRP, 0, geometry.volume_sectors),
+        (false, true) => mbr_partition(false, 0xCD, 0, geometry.volume_sectors),
+        (false, false) => mbr_partition(true, 0x17, 0, geometry.volume_sectors),
+    }];
+    if let Some((start, sectors)) = geometry.efi {
+        partitions.push(mbr_partition(false, 0xEF, start, sectors));
+    }
+    if hybrid.gpt_basdat {
+        // A hybrid MBR: the GPT is announced by a partition over its header and entries
+        partitions.push(mbr_partition(false, 0xEE, 1, geometry.gpt_entries_lba + GPT_ENTRY_SECTORS - 1));
+    }
+    // Appended partitions take the slots they ask for, the others the free ones in turn
+    let mut slots: [Option<[u8; 16]>; MBR_SLOTS] = [None; MBR_SLOTS];
+    for &(slot, kind, start, sectors) in &geometry.appended {
+        slots[slot - 1] = Some(mbr_partition(false, kind, start, sectors));
+    }
+    for partition in partitions {
+        if let Some(free) = slots.iter_mut().find(|slot| slot.is_none()) {
+            *free = Some(partition);
+        }
+    }
+    for (index, partition) in slots.iter().enumerate() {
+        area[446 + 16 * index..462 + 16 * index].copy_from_slice(&partition.unwrap_or([0; 16]));
+    }
+    area[510] = 0x55;
+    area[511] = 0xAA;
+
+    if hybrid.gpt_basdat {
+        let entries = gpt_entries(&geometry, options, tree.image_time);
+        area[SECTOR_SIZE as usize..2 * SECTOR_SIZE as usize].copy_from_slice(&gpt_header(&geometry, options, tree.image_time, false, &entries));
+        let at = (geometry.gpt_entries_lba * SECTOR_SIZE) as usize;
+        area[at..at + entries.len()].copy_from_slice(&entries);
+    }
+
+    if hybrid.apm_hfsplus {
+        area[..APM_HEADER.len()].copy_from_slice(&APM_HEADER);
+        let (start, sectors) = geometry.efi.expect("an EFI boot image for the APM");
+        let partitions = [
+            apm_entry(1, APM_ENTRIES, "Apple", "Apple_partition_map", 0x03),
+            apm_entry(start / SECTORS_PER_BLOCK, sectors.div_ceil(SECTORS_PER_BLOCK), "EFI", "Apple_HFS", 0x4000_0033),
+        ];
+        for (index, partition) in partitions.iter().enumerate() {
+            area[(index + 1) * BLOCK_SIZE..(index + 2) * BLOCK_SIZE].copy_from_slice(partition);
+        }
+    }
+    area
+}
+
+// The last blocks of the image: the backup GPT entries followed by the backup header
+pub fn gpt_backup(tree: &ImageTree, layout: &Layout, options: &ImageOptions, hybrid: &HybridOptions) -> Vec<u8> {
+    let geometry = geometry(tree, layout, hybrid);
+    let entries = gpt_entries(&geometry, options, tree.image_time);
+    let mut tail = vec![0u8; GPT_BACKUP_BLOCKS as usize * BLOCK_SIZE];
+    let header_at = tail.len() - SECTOR_SIZE as usize;
+    tail[header_at - entries.len()..header_at].copy_from_slice(&entries);
+    tail[header_at..].copy_from_slice(&gpt_header(&geometry, options, tree.image_time, true, &entries));
+    tail
+}
+
+// Copy an appended partition image into the image, padded to a whole block
+pub fn write_appended<W: Write>(writer: &mut W, partition: &AppendedPartition) -> io::Result<()> {
+    let copied = io::copy(&mut File::open(&partition.path)?.take(partition.size), writer)?;
+    if copied != partition.size {
+        return Err(io::Error::new(ErrorKind::InvalidData, format!("{} shrank while the image was written", partition.path.display())));
+    }
+    pad_to_block(writer, (partition.size % BLOCK_SIZE as u64) as usize)
+}
@@ -0,0 +1,99 @@
+// Per-directory ignore files: a .isoignore in any source directory (and a .gitignore there
+// with --use-gitignore) leaves entries out of the image the way git leaves them out of a
+// repository. Each line is a pattern for the entries of that directory and the ones below it:
+// a pattern with a "/" other than at its end matches the path from the directory, others the
+// name at any depth; a trailing "/" matches directories only, a leading "!" takes an entry back
+// in, and the last pattern an entry matches, counting deeper files after shallower ones,
+// decides. Lines starting with "#" are comments ("\#" and "\!" start patterns with those).
+//
+// Like git, nothing under a left-out directory can be taken back in, as it is never walked.
+// The .isoignore files themselves stay out of the image; .gitignore files go in.
+
+use std::io::{self, Read};
+use std::path::Path;
+
+use super::backend::{ScannedEntry, SourceKind, SourceProvider};
+use super::glob;
+
+pub const ISOIGNORE: &str = ".isoignore";
+pub const GITIGNORE: &str = ".gitignore";
+
+// One pattern of an ignore file
+#[derive(Debug)]
+pub struct Rule {
+    base: String,         // Original path of the directory the file is in, ending in "/"
+    pattern: String,
+    negated: bool,        // "!": take matching entries back in
+    directory_only: bool, // Trailing "/"
+    anchored: bool,       // Matches the path from base rather than the name
+}
+
+// The rules of the ignore files among the entries of a directory, .gitignore's before
+// .isoignore's so the latter have the last word
+pub fn read_rules(source: &dyn SourceProvider, entries: &[io::Result<ScannedEntry>], base: &str, use_gitignore: bool) -> io::Result<Vec<Rule>> {
+    let mut rules = Vec::new();
+    let names = if use_gitignore { &[GITIGNORE, ISOIGNORE][..] } else { &[ISOIGNORE][..] };
+    for name in names {
+        let found = entries.iter().flatten().find(|entry| entry.kind == SourceKind::File && entry.path.file_name().is_some_and(|found| found == *name));
+        if let Some(entry) = found {
+            rules.extend(parse(&read_text(source, &entry.path)?, base));
+        }
+    }
+    Ok(rules)
+}
+
+fn read_text(source: &dyn SourceProvider, path: &Path) -> io::Result<String> {
+    let mut contents = Vec::new();
+    source.open(path).and_then(|mut file| file.read_to_end(&mut contents)).map_err(|e| io::Error::new(e.kind(), format!("could not read {}: {}", path.display(), e)))?;
+    Ok(String::from_utf8_lossy(&contents).into_owned())
+}
+
+// The patterns of an ignore file in the directory at base
+fn parse(text: &str, base: &str) -> Vec<Rule> {
+    let mut rules = Vec::new();
+    for line in text.lines() {
+        // Trailing spaces don't count unless escaped
+        let mut line = line.trim_end_matches([' ', '\t']).to_string();
+        if line.ends_with('\\') {
+            line.push(' ');
+        }
+        let line = line.replace("\\ ", " ");
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line.strip_prefix('\\').unwrap_or(&line)),
+        };
+        let (directory_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        if line.is_empty() {
+            continue;
+        }
+        rules.push(Rule {
+            base: base.to_string(),
+            anchored: line.contains('/'),
+            pattern: line.trim_start_matches('/').to_string(),
+            negated,
+            directory_only,
+        });
+    }
+    rules
+}
+
+// Whether the rules of the directories an entry is in, outermost first, leave it out
+pub fn ignored<'a>(rules: impl Iterator<Item = &'a Vec<Rule>>, path: &str, name: &str, is_directory: bool) -> bool {
+    let mut ignored = false;
+    for rule in rules.flatten() {
+        if (rule.directory_only && !is_directory) || rule.negated != ignored {
+            continue; // Can't change the outcome
+        }
+        let Some(relative) = path.strip_prefix(&rule.base) else { continue };
+        if glob::matches(&rule.pattern, if rule.anchored { relative } else { name }) {
+            ignored = !rule.negated;
+        }
+    }
+    ignored
+}
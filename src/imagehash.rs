@@ -0,0 +1,197 @@
+// Checksums of the image (--output-hash sha256[,sha1]), computed from the bytes as they are
+// written so a multi-gigabyte image doesn't have to be read back just to publish them
+//
+// Each digest is printed and written next to the image as <iso>.sha256 (or .sha1) in the
+// format sha256sum and sha1sum check. Bytes only hash in passing while the image is written
+// front to back; if anything is rewritten (--if-changed reread) or written out of order
+// (images converted from archives), the finished file is read back instead.
+
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use super::backend::{self, ImageSink};
+use super::events;
+use super::sha1::Sha1;
+use super::sha256::{self, Sha256};
+use super::ImageOutput;
+
+const READ_CHUNK_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    Sha256,
+    Sha1,
+}
+
+impl Algorithm {
+    // A comma separated list such as "sha256,sha1"
+    pub fn parse_list(text: &str) -> io::Result<Vec<Algorithm>> {
+        let mut algorithms = Vec::new();
+        for name in text.split(',') {
+            let algorithm = match name.trim().to_ascii_lowercase().as_str() {
+                "sha256" => Algorithm::Sha256,
+                "sha1" => Algorithm::Sha1,
+                _ => return Err(io::Error::new(ErrorKind::InvalidInput, format!("unknown --output-hash: {} (expected sha256 or sha1)", name))),
+            };
+            if !algorithms.contains(&algorithm) {
+                algorithms.push(algorithm);
+            }
+        }
+        Ok(algorithms)
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Algorithm::Sha256 => "sha256",
+            Algorithm::Sha1 => "sha1",
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Algorithm::Sha256 => "SHA-256",
+            Algorithm::Sha1 => "SHA-1",
+        }
+    }
+}
+
+#[derive(Clone)]
+enum Hasher {
+    Sha256(Sha256),
+    Sha1(Sha1),
+}
+
+impl Hasher {
+    fn new(algorithm: Algorithm) -> Hasher {
+        match algorithm {
+            Algorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            Algorithm::Sha1 => Hasher::Sha1(Sha1::new()),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha256(sha) => sha.update(data),
+            Hasher::Sha1(sha) => sha.update(data),
+        }
+    }
+
+    fn finish(self) -> Vec<u8> {
+        match self {
+            Hasher::Sha256(sha) => sha.finish().to_vec(),
+            Hasher::Sha1(sha) => sha.finish().to_vec(),
+        }
+    }
+}
+
+// Passes the image on to the real output, hashing it on the way while it is written in order.
+// Contents are no longer copied kernel-side, since they have to pass through here.
+pub struct HashingSink<S> {
+    output: S,
+    location: PathBuf, // The image the checksums are published for
+    written: PathBuf,  // Where it is being written, to read it back from
+    algorithms: Vec<Algorithm>,
+    hashers: Vec<Hasher>,
+    position: u64,
+    hashed: u64,       // Everything before this was hashed, in order
+    in_order: bool,    // No write so far went anywhere but the end of what was hashed
+}
+
+impl<S: ImageSink> HashingSink<S> {
+    pub fn new(output: S, location: &Path, written: &Path, algorithms: &[Algorithm]) -> HashingSink<S> {
+        HashingSink {
+            output,
+            location: location.to_path_buf(),
+            written: written.to_path_buf(),
+            algorithms: algorithms.to_vec(),
+            hashers: algorithms.iter().map(|&algorithm| Hasher::new(algorithm)).collect(),
+            position: 0,
+            hashed: 0,
+            in_order: true,
+        }
+    }
+}
+
+impl<S: ImageSink> Write for HashingSink<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.output.write(buf)?;
+        if self.in_order && self.position == self.hashed {
+            for hasher in &mut self.hashers {
+                hasher.update(&buf[..written]);
+            }
+            self.hashed += written as u64;
+        } else {
+            self.in_order = false;
+        }
+        self.position += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+impl<S: ImageSink> Seek for HashingSink<S> {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        self.position = self.output.seek(position)?;
+        Ok(self.position)
+    }
+}
+
+impl<S: ImageSink> ImageOutput for HashingSink<S> {}
+
+impl<S: ImageSink> ImageSink for HashingSink<S> {
+    fn finish(&mut self) -> io::Result<()> {
+        self.output.finish()?;
+        let digests = if self.in_order {
+            self.hashers.drain(..).map(Hasher::finish).collect()
+        } else if backend::is_remote(&self.location) {
+            return Err(io::Error::other(format!("{} was not written in order, so its checksums can't be computed", self.location.display())));
+        } else {
+            events::warn(&format!("{} was not written in order, reading it back for its checksums", self.location.display()));
+            hash_file(&self.written, &self.algorithms)?
+        };
+        publish(&self.location, &self.algorithms, &digests)
+    }
+}
+
+// Read a finished image back for its checksums, and publish them
+pub fn hash_written(location: &Path, algorithms: &[Algorithm]) -> io::Result<()> {
+    let digests = hash_file(location, algorithms)?;
+    publish(location, algorithms, &digests)
+}
+
+fn hash_file(location: &Path, algorithms: &[Algorithm]) -> io::Result<Vec<Vec<u8>>> {
+    let mut hashers: Vec<Hasher> = algorithms.iter().map(|&algorithm| Hasher::new(algorithm)).collect();
+    let mut file = File::open(location)?;
+    let mut buffer = vec![0u8; READ_CHUNK_SIZE];
+    loop {
+        let bytes_read = file.read(&mut buffer)?;
+        if bytes_read == 0 {
+            break;
+        }
+        for hasher in &mut hashers {
+            hasher.update(&buffer[..bytes_read]);
+        }
+    }
+    Ok(hashers.into_iter().map(Hasher::finish).collect())
+}
+
+// Print the digests and write them next to a local image
+fn publish(location: &Path, algorithms: &[Algorithm], digests: &[Vec<u8>]) -> io::Result<()> {
+    let name = location.file_name().map_or_else(|| location.display().to_string(), |name| name.to_string_lossy().into_owned());
+    for (algorithm, digest) in algorithms.iter().zip(digests) {
+        let hex = sha256::hex(digest);
+        events::info(&format!("{}: {}", algorithm.label(), hex));
+        if backend::is_remote(location) {
+            continue; // Nowhere to put the file next to it
+        }
+        let mut sidecar = location.as_os_str().to_owned();
+        sidecar.push(format!(".{}", algorithm.extension()));
+        fs::write(&sidecar, format!("{}  {}\n", hex, name))?;
+        events::info(&format!("Wrote {}", Path::new(&sidecar).display()));
+    }
+    Ok(())
+}
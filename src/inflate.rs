@@ -0,0 +1,255 @@
+// Streaming decoder for raw deflate data (RFC 1951), as stored in zip archives
+//
+// Codes are decoded a bit at a time against the canonical code counts, which is slower than
+// table lookups but small and plenty for moving archives into images.
+
+use std::io::{self, ErrorKind, Read, Write};
+
+const WINDOW_SIZE: usize = 32 * 1024; // Largest distance a back reference can reach
+const FLUSH_SIZE: usize = 256 * 1024; // Decoded bytes are written out in blocks of about this size
+const MAX_BITS: usize = 15;
+
+// Base lengths and extra bits for length codes 257 to 285
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+
+// Base distances and extra bits for distance codes 0 to 29
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+// Order in which code length code lengths are stored in a dynamic block header
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+fn corrupt(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, format!("corrupt deflate data: {}", message))
+}
+
+// Canonical Huffman code: number of codes of each length and the symbols in code order
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> io::Result<Huffman> {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+
+        // Reject oversubscribed codes; incomplete ones are allowed (a single distance code is common)
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left = left * 2 - count as i32;
+            if left < 0 {
+                return Err(corrupt("oversubscribed code"));
+            }
+        }
+
+        let mut offsets = [0u16; MAX_BITS + 2];
+        for length in 1..=MAX_BITS {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        Ok(Huffman { counts, symbols })
+    }
+}
+
+struct Inflater<R, W> {
+    input: R,
+    output: W,
+    bit_buffer: u32,
+    bit_count: u32,
+    history: Vec<u8>, // Decoded bytes not yet written, after the last WINDOW_SIZE bytes already written
+    kept: usize,      // How many bytes at the start of history were already written
+    total: u64,
+}
+
+impl<R: Read, W: Write> Inflater<R, W> {
+    fn byte(&mut self) -> io::Result<u8> {
+        let mut byte = [0u8; 1];
+        match self.input.read_exact(&mut byte) {
+            Ok(()) => Ok(byte[0]),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => Err(corrupt("unexpected end of data")),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn bits(&mut self, count: u32) -> io::Result<u32> {
+        while self.bit_count < count {
+            self.bit_buffer |= (self.byte()? as u32) << self.bit_count;
+            self.bit_count += 8;
+        }
+        let value = self.bit_buffer & ((1u32 << count) - 1);
+        self.bit_buffer = if count == 32 { 0 } else { self.bit_buffer >> count };
+        self.bit_count -= count;
+        Ok(value)
+    }
+
+    fn decode(&mut self, code: &Huffman) -> io::Result<u16> {
+        let (mut value, mut first, mut index) = (0i32, 0i32, 0i32);
+        for length in 1..=MAX_BITS {
+            value |= self.bits(1)? as i32;
+            let count = code.counts[length] as i32;
+            if value - count < first {
+                return Ok(code.symbols[(index + value - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            value <<= 1;
+        }
+        Err(corrupt("invalid code"))
+    }
+
+    fn push(&mut self, byte: u8) -> io::Result<()> {
+        self.history.push(byte);
+        if self.history.len() >= self.kept + FLUSH_SIZE {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    // Write out what was decoded, keeping the last window for back references
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.write_all(&self.history[self.kept..])?;
+        self.total += (self.history.len() - self.kept) as u64;
+        let drop = self.history.len().saturating_sub(WINDOW_SIZE);
+        self.history.drain(..drop);
+        self.kept = self.history.len();
+        Ok(())
+    }
+
+    fn stored_block(&mut self) -> io::Result<()> {
+        self.bit_buffer = 0; // Stored blocks start on a byte boundary
+        self.bit_count = 0;
+        let mut header = [0u8; 4];
+        for byte in &mut header {
+            *byte = self.byte()?;
+        }
+        let length = u16::from_le_bytes([header[0], header[1]]);
+        if length != !u16::from_le_bytes([header[2], header[3]]) {
+            return Err(corrupt("stored block length mismatch"));
+        }
+        for _ in 0..length {
+            let byte = self.byte()?;
+            self.push(byte)?;
+        }
+        Ok(())
+    }
+
+    fn compressed_block(&mut self, lengths: &Huffman, distances: &Huffman) -> io::Result<()> {
+        loop {
+            let symbol = self.decode(lengths)? as usize;
+            match symbol {
+                0..=255 => self.push(symbol as u8)?,
+                256 => return Ok(()),
+                257..=285 => {
+                    let code = symbol - 257;
+                    let length = LENGTH_BASE[code] as usize + self.bits(LENGTH_EXTRA[code] as u32)? as usize;
+                    let code = self.decode(distances)? as usize;
+                    if code >= 30 {
+                        return Err(corrupt("invalid distance code"));
+                    }
+                    let distance = DISTANCE_BASE[code] as usize + self.bits(DISTANCE_EXTRA[code] as u32)? as usize;
+                    if distance > self.history.len() {
+                        return Err(corrupt("distance too far back"));
+                    }
+                    for _ in 0..length {
+                        let byte = self.history[self.history.len() - distance];
+                        self.push(byte)?;
+                    }
+                }
+                _ => return Err(corrupt("invalid length code")),
+            }
+        }
+    }
+
+    fn fixed_codes() -> io::Result<(Huffman, Huffman)> {
+        let mut lengths = [0u8; 288];
+        lengths[..144].fill(8);
+        lengths[144..256].fill(9);
+        lengths[256..280].fill(7);
+        lengths[280..].fill(8);
+        Ok((Huffman::new(&lengths)?, Huffman::new(&[5; 30])?))
+    }
+
+    fn dynamic_codes(&mut self) -> io::Result<(Huffman, Huffman)> {
+        let literal_count = self.bits(5)? as usize + 257;
+        let distance_count = self.bits(5)? as usize + 1;
+        let code_length_count = self.bits(4)? as usize + 4;
+        if literal_count > 286 || distance_count > 30 {
+            return Err(corrupt("too many codes"));
+        }
+
+        let mut code_lengths = [0u8; 19];
+        for &position in &CODE_LENGTH_ORDER[..code_length_count] {
+            code_lengths[position] = self.bits(3)? as u8;
+        }
+        let code_length_code = Huffman::new(&code_lengths)?;
+
+        let mut lengths = vec![0u8; literal_count + distance_count];
+        let mut index = 0;
+        while index < lengths.len() {
+            let symbol = self.decode(&code_length_code)?;
+            let (value, repeat) = match symbol {
+                0..=15 => (symbol as u8, 1),
+                16 if index > 0 => (lengths[index - 1], 3 + self.bits(2)? as usize),
+                17 => (0, 3 + self.bits(3)? as usize),
+                18 => (0, 11 + self.bits(7)? as usize),
+                _ => return Err(corrupt("invalid code lengths")),
+            };
+            if index + repeat > lengths.len() {
+                return Err(corrupt("too many code lengths"));
+            }
+            lengths[index..index + repeat].fill(value);
+            index += repeat;
+        }
+        if lengths[256] == 0 {
+            return Err(corrupt("missing end of block code"));
+        }
+        Ok((Huffman::new(&lengths[..literal_count])?, Huffman::new(&lengths[literal_count..])?))
+    }
+}
+
+// Decode deflate data from input to output; returns the number of bytes written
+pub fn inflate<R: Read, W: Write>(input: R, output: W) -> io::Result<u64> {
+    let mut inflater = Inflater {
+        input,
+        output,
+        bit_buffer: 0,
+        bit_count: 0,
+        history: Vec::with_capacity(WINDOW_SIZE + FLUSH_SIZE),
+        kept: 0,
+        total: 0,
+    };
+
+    loop {
+        let last = inflater.bits(1)? == 1;
+        match inflater.bits(2)? {
+            0 => inflater.stored_block()?,
+            1 => {
+                let (lengths, distances) = Inflater::<R, W>::fixed_codes()?;
+                inflater.compressed_block(&lengths, &distances)?;
+            }
+            2 => {
+                let (lengths, distances) = inflater.dynamic_codes()?;
+                inflater.compressed_block(&lengths, &distances)?;
+            }
+            _ => return Err(corrupt("invalid block type")),
+        }
+        if last {
+            break;
+        }
+    }
+    inflater.flush()?;
+    Ok(inflater.total)
+}
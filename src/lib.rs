@@ -0,0 +1,3415 @@
+// makeiso as a library: the command itself, and the image reader as a public module so tools
+// outside the binary (the fuzz targets in fuzz/) can parse untrusted images with it
+
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use apple::FinderInfo;
+use backend::{FileIdentity, ImageSink, ScannedEntry, SourceKind, SourceMetadata, SourceProvider};
+use blockmap::Area;
+use charset::Charset;
+use checkpoint::Checkpoint;
+use eltorito::{BootLayout, BootOptions};
+use hybrid::{AppendedPartition, HybridOptions};
+use imagehash::{Algorithm, HashingSink};
+use reader::ReadAt;
+use throttle::{IoPriority, Throttle};
+use udf::UdfLayout;
+use writebehind::WriteBehind;
+
+mod age;
+mod apple;
+mod audio;
+mod backend;
+mod bench;
+mod blockmap;
+mod browse;
+mod cancel;
+mod cat;
+mod checkpoint;
+mod checksums;
+mod catalog;
+mod catalogdb;
+mod chacha20poly1305;
+mod charset;
+mod compare;
+mod completions;
+mod config;
+mod daemon;
+mod dedup;
+mod dvdvideo;
+mod elevate;
+mod eltorito;
+mod embedded;
+mod estimate;
+mod events;
+mod extract;
+mod find;
+mod flac;
+#[cfg(target_os = "linux")]
+mod fuse;
+mod glob;
+mod grep;
+mod hooks;
+mod http;
+mod hybrid;
+mod ignore;
+mod imagehash;
+mod inflate;
+mod json;
+mod md4;
+#[cfg(feature = "s3")]
+mod md5;
+mod merge;
+mod metrics;
+mod mounts;
+mod mmap;
+mod notify;
+mod preallocate;
+mod prefetch;
+mod profile;
+mod prune;
+mod rawsector;
+pub mod reader;
+mod regex;
+mod repair;
+mod salvage;
+mod schedule;
+mod scrypt;
+mod secrets;
+mod sector;
+mod serve;
+#[cfg(feature = "s3")]
+mod s3;
+#[cfg(feature = "sftp")]
+mod sftp;
+mod sha1;
+mod sha256;
+#[cfg(target_os = "linux")]
+mod shell;
+mod sign;
+mod stdin;
+mod sunlabel;
+mod tar;
+mod throttle;
+mod transtbl;
+mod udf;
+mod unicode;
+mod verify;
+mod winmeta;
+mod writebehind;
+mod xattr;
+mod zerocopy;
+mod x25519;
+mod zip;
+mod zsync;
+
+// Constants for the ISO 9660 format
+const BLOCK_SIZE: usize = 2048; // ISO 9660 uses 2KB blocks
+const PRIMARY_VOLUME_DESCRIPTOR: u8 = 1;
+const SUPPLEMENTARY_VOLUME_DESCRIPTOR: u8 = 2;
+const VOLUME_DESCRIPTOR_TERMINATOR: u8 = 255;
+const CD001: &[u8] = b"CD001";
+const CDROM: &[u8] = b"CDROM"; // The standard identifier of High Sierra, at byte 9 of its descriptors
+const FLAG_HIDDEN: u8 = 0x01; // Existence bit: readers leave the entry out of listings unless asked
+const FLAG_ASSOCIATED: u8 = 0x04; // The entry is an associated file, such as a Macintosh resource fork
+const SYSTEM_AREA_BLOCKS: u32 = 16; // Blocks 0-15 are reserved, the descriptors start at block 16
+const MAX_DIRECTORY_DEPTH: usize = 8; // ISO 9660 allows at most 8 directory levels, counting the root
+const MAX_PATH_LENGTH: usize = 255; // ISO 9660 limit for the length of a path from the root
+const MAX_ENHANCED_NAME_LENGTH: usize = 207; // ISO 9660:1999 limit for a name, which is all it limits
+const RELOCATION_DIRECTORY: &str = "rr_moved"; // Where Rock Ridge relocation moves deep directories
+const SUSP_DATA_MAX: usize = 250; // Data bytes of a System Use entry, whose length is one byte with the header
+const CE_LENGTH: usize = 28; // A continuation entry: header and three both-endian numbers
+const MAX_ROCK_RIDGE_IDENTIFIER: usize = 193; // Longest identifier whose record still has room for a CE entry
+const REREAD_ATTEMPTS: usize = 3; // How often --if-changed reread reads a changing file before giving up
+const DEFAULT_MAX_DEPTH: usize = 1000; // Deepest --max-depth lets the scan go by default, relocated or not
+const MMAP_THRESHOLD: u32 = 64 * 1024 * 1024; // Files at least this large are mapped when --mmap is given
+const MMAP_CHUNK_SIZE: usize = 1024 * 1024; // Mapped files are written (and progress reported) in 1 MiB chunks
+const DEFAULT_PAD_SECTORS: u32 = 300; // Tail padding for --pad without a value, the same as mkisofs
+const ZERO_COPY_CHUNK_SIZE: usize = 8 * 1024 * 1024; // Kernel-side copies are issued (and progress reported) in 8 MiB chunks
+const COPY_BUFFER_SIZE: usize = 1024 * 1024; // Files that are read rather than mapped or copied by the kernel go through a 1 MiB buffer
+
+// Files and directories left out because they couldn't be read, for the run's metrics
+static SKIPPED_FILES: AtomicU64 = AtomicU64::new(0);
+// The ones of them this user wasn't allowed to read, for --report-denied
+static DENIED_PATHS: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+// How to handle directories that are nested deeper than ISO 9660 allows
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DeepDirPolicy {
+    Error,    // Refuse to create a nonconforming image
+    Relocate, // Move deep directories under rr_moved and link them with Rock Ridge CL/PL/RE entries
+}
+
+// What to do with files that change between the scan and the write pass
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum IfChanged {
+    Pad,    // Pad or truncate the contents to the size planned during the scan
+    Reread, // Read the file again until it matches the scan, fail if it doesn't settle
+    Fail,   // Abort the image
+}
+
+// Order of the file contents in the image
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FileOrder {
+    Directory, // Files follow the order of the directories that contain them
+    Size,      // Smallest files first
+}
+
+// What to do with symbolic links (and on Windows junctions and mounted folders) in a source
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SymlinkPolicy {
+    Follow, // Put in what they lead to
+    Skip,   // Leave them out
+    Store,  // Record the links themselves, with Rock Ridge SL entries for their targets
+}
+
+// Where the path tables and directory extents go relative to the file contents
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MetadataPlacement {
+    First,
+    Last,
+}
+
+// Settings that control how the image is built
+#[derive(Debug, Clone)]
+struct ImageOptions {
+    deep_dirs: DeepDirPolicy,
+    if_changed: IfChanged,
+    mmap: bool,          // Memory-map large files instead of reading them through a buffer
+    read_workers: usize, // Threads reading upcoming files ahead of the writer (0 reads inline)
+    pipeline_depth: usize, // Buffers queued for a writer thread (0 writes inline)
+    file_order: FileOrder,
+    group_small: Option<u64>,         // Files smaller than this are placed together ahead of the others
+    sort_weights: Vec<(String, i64)>, // Patterns and weights from --sort-weight; heavier files go to lower blocks
+    dedup: bool,                      // Store files with the same contents once (--dedup content)
+    dedup_report: bool,               // Report how much identical files and blocks take up
+    checksums: bool,                  // Add a SHA256SUMS of the files at the root
+    embed_metadata: Option<bool>,     // Add the self-describing .makeiso directory at the root (makeiso run does by default)
+    run_profile: Option<String>,      // The profile makeiso run is building, recorded in .makeiso
+    align: Option<u64>,               // Files at least this large start on a multiple of this many bytes
+    metadata: MetadataPlacement,
+    block_map: Option<PathBuf>, // Where to write the final block map
+    block_map_format: blockmap::MapFormat,
+    media: Option<Media>,       // Medium the image has to fit on
+    pad_sectors: u32,           // Zero blocks after the last extent, for drives that misread the end of TAO tracks
+    source_date_epoch: Option<i64>, // Image creation time to record instead of the current time
+    reproducible: bool,             // Record the same timestamp everywhere so identical trees give identical images
+    identity: VolumeIdentity,
+    input_charset: Charset,  // What the names in the source directory are in
+    normalize: unicode::Normalization, // The Unicode form names are stored in
+    output_charset: Charset, // What ISO 9660 identifiers are recorded in
+    trans_tbl: bool,         // Record 8.3 identifiers and a TRANS.TBL with the original names in every directory
+    enhanced: bool,          // --iso-level 4: an ISO 9660:1999 enhanced volume descriptor, without the depth and path limits
+    exclude: Vec<String>,    // Globs of entries left out of the image
+    use_gitignore: bool,     // Read .gitignore files like .isoignore ones
+    stdin_name: Option<String>, // Name of the file standard input becomes when "-" is a source
+    symlinks: SymlinkPolicy,
+    max_file_size: Option<u64>, // Files larger than this many bytes are left out
+    min_mtime: Option<i64>,     // Files modified before this are left out
+    max_mtime: Option<i64>,     // Files modified after this are left out
+    types: Vec<char>,           // --type: the kinds of source files that go in, f and l (d alone keeps none)
+    hidden: Vec<String>,     // Globs of entries recorded with the hidden (existence) flag
+    associated: Vec<String>, // Globs of entries recorded with the associated file flag
+    xattrs: bool,            // Record the extended attributes of the source in XATTRS.TXT
+    win_metadata: bool,      // Record Windows attributes, creation times and streams in WINMETA.TXT
+    apple: bool,             // Record Finder type, creator and flags in Apple ISO 9660 extension fields
+    dvd_video: bool,         // Lay out VIDEO_TS as DVD players need it and add a UDF bridge
+    bwlimit: Option<u64>,    // Most bytes of file contents copied per second
+    io_priority: Option<IoPriority>,
+    output_hash: Vec<Algorithm>, // Checksums of the image to publish next to it
+    sign: Option<String>,        // gpg key to sign the finished image with
+    zsync: Option<Option<String>>, // Write <image>.zsync, with this URL for the image instead of its name
+    upload: Option<PathBuf>,       // Also upload the image to this s3:// object, in parts
+    catalog_db: Option<PathBuf>,   // Add the image and its manifest to this catalog database
+    pre_hook: Option<String>,      // Shell command run before scanning; the build stops if it fails
+    post_hook: Option<String>,     // Shell command run once done, given a JSON summary on stdin
+    elevate: Option<String>,       // Ask this helper (sudo, doas or a privileged makeiso) for what is denied
+    report_denied: Option<PathBuf>, // List what was left out as permission denied in this file
+    checkpoint_every: Option<Duration>, // Print a summary line of the build this often
+    status_file: Option<PathBuf>,  // Keep the latest summary line in this file
+    force: bool,                 // Replace an existing image
+    keep_partial: bool,          // Leave what a failed or cancelled run wrote
+    one_file_system: bool,       // Don't descend into directories on other filesystems
+    single_pass: bool,           // Write the contents while scanning and the descriptors last
+    max_depth: usize,            // Deepest a source directory is walked, against runaway trees
+    encrypt: Vec<age::Recipient>, // Write the image as an age file for these
+    encrypt_glob: Vec<String>,    // Files to store encrypted with the passphrase
+    boot: Option<BootOptions>,          // El Torito boot files, making the image bootable from CD
+    hybrid: Option<HybridOptions>,      // Partition tables in the system area, making it bootable from USB sticks
+    system_area: Option<Vec<u8>>,       // Blocks 0-15 from --system-area, padded to 32 KiB
+    sparc_boot: Vec<AppendedPartition>, // Boot images after the volume, listed in a Sun disk label
+}
+
+// Identification recorded in the Primary Volume Descriptor
+#[derive(Debug, Clone)]
+struct VolumeIdentity {
+    volume_id: String,                // Volume label, at most 32 characters
+    preparer: Option<String>,         // Data preparer identifier, at most 128 characters
+    application_use: Option<Vec<u8>>, // Contents of the 512-byte application use field
+    uuid: Option<[u8; 16]>,           // Volume modification date digits, which blkid and GRUB report as the UUID
+}
+
+// Target medium and its capacity
+#[derive(Debug, Clone, PartialEq)]
+struct Media {
+    name: String,
+    capacity: u64, // In bytes
+}
+
+// Capacities of standard media in 2048-byte sectors
+const STANDARD_MEDIA: &[(&str, u64)] = &[
+    ("cd74", 333_000),
+    ("cd80", 360_000),
+    ("dvd5", 2_295_104),
+    ("dvd9", 4_173_824),
+    ("bd25", 12_219_392),
+    ("bd50", 24_438_784),
+];
+
+// A regular file scheduled for inclusion in the image, with the size and
+// modification time seen during the scan
+#[derive(Debug)]
+struct FileEntry {
+    name: String,
+    source: PathBuf,
+    size: u32,
+    modified: Option<SystemTime>,
+    extent: u32, // Assigned during layout
+}
+
+// An entry in a directory of the image tree
+#[derive(Debug)]
+enum Entry {
+    File(FileEntry),
+    Directory(usize),                         // Index of the subdirectory in the tree
+    Relocated { name: String, target: usize }, // Placeholder left behind by a relocated directory
+    Symlink { name: String, target: String, modified: Option<SystemTime> }, // Recorded with a Rock Ridge SL entry
+    Special { name: String, device: Option<(u32, u32)>, modified: Option<SystemTime> }, // FIFO or device (major, minor), the type in its POSIX mode
+}
+
+// Rock Ridge POSIX attributes of an entry, where they are known (archive members); the file
+// type is part of the mode
+#[derive(Debug, Clone, Copy)]
+struct Posix {
+    mode: u32,
+    uid: u32,
+    gid: u32,
+}
+
+// A directory of the image tree
+#[derive(Debug)]
+struct Directory {
+    name: String,
+    parent: usize,                  // Parent in the image hierarchy (the root is its own parent)
+    original_parent: Option<usize>, // Parent in the source tree, set when the directory was relocated
+    entries: Vec<Entry>,
+    modified: Option<SystemTime>,
+    depth: usize,    // 1 for the root
+    path_len: usize, // Length of the path from the root, e.g. "/a/b" is 4
+    number: u16,     // Directory number in the path table, assigned during layout
+    extent: u32,     // Assigned during layout
+    size: u32,       // Length of the directory extent in bytes
+    continuation: u32, // Blocks after the extent that Rock Ridge entries too long for their records continue in
+}
+
+// The directories of the image; index 0 is the root
+#[derive(Debug)]
+struct ImageTree {
+    dirs: Vec<Directory>,
+    relocation_dir: Option<usize>,
+    rock_ridge: bool,  // Rock Ridge entries for every record, not only once directories are relocated
+    image_time: i64,   // Creation time of the image, in seconds since the Unix epoch
+    fixed_times: bool, // Record image_time for every entry instead of its modification time
+    relaxed: bool,     // ISO 9660:1999 limits: any depth and path length, names up to 207 bytes
+    charset: Charset,  // What identifiers are recorded in
+    dos_names: HashMap<usize, HashMap<String, String>>, // Identifiers other than the names by directory and name: 8.3 ones with TRANS.TBL files, shortened ones with Rock Ridge
+    generated: HashMap<usize, HashMap<String, Vec<u8>>>, // Contents of files made up while building (TRANS.TBL and the like) by directory and name
+    record_flags: HashMap<usize, HashMap<String, u8>>,  // Hidden and associated flags by directory and name
+    xattrs: Vec<(String, xattr::Attributes)>,           // Extended attributes found by the scan, by path from the root
+    win_metadata: Vec<(String, xattr::Attributes)>,     // The same for the Windows metadata, in WINMETA.TXT
+    finder_info: HashMap<usize, HashMap<String, FinderInfo>>, // Finder info of files by directory and name, with --apple
+    posix: HashMap<usize, HashMap<String, Posix>>,      // POSIX attributes by directory and name, for those that have them
+    ifo_headers: HashMap<String, Vec<u8>>,              // Start of each IFO file in /VIDEO_TS by name, with --dvd-video
+    digests: HashMap<PathBuf, [u8; 32]>,                // SHA-256 of the contents of files that might be duplicates, with --dedup content
+    outputs: Vec<(Option<FileIdentity>, PathBuf)>,      // The image and its partial file, as found before the scan, left out should they be among the sources
+    scanned: ScanProgress,
+}
+
+// How far the scan got, reported every second or so while it runs
+#[derive(Debug)]
+struct ScanProgress {
+    directories: u64,
+    files: u64,
+    bytes: u64,
+    reported: Instant,
+}
+
+impl ScanProgress {
+    // Report the counts if the last report is a while ago
+    fn tick(&mut self) {
+        if self.reported.elapsed() >= Duration::from_secs(1) {
+            self.report();
+        }
+    }
+
+    fn report(&mut self) {
+        if events::enabled() {
+            events::emit(
+                "scan-progress",
+                &[("directories", self.directories.to_string()), ("files", self.files.to_string()), ("bytes", self.bytes.to_string())],
+            );
+        } else {
+            eprintln!("Scanned {} directories, {} files, {} bytes", self.directories, self.files, self.bytes);
+        }
+        self.reported = Instant::now();
+    }
+}
+
+impl ImageTree {
+    // Timestamp recorded for an entry with the given modification time
+    fn record_time(&self, modified: Option<SystemTime>) -> i64 {
+        match modified {
+            Some(modified) if !self.fixed_times => unix_time(modified),
+            _ => self.image_time,
+        }
+    }
+
+    // A name of an entry of directory dir as it is recorded in a directory record or the path table
+    fn identifier(&self, dir: usize, name: &str) -> Vec<u8> {
+        match self.dos_names.get(&dir).and_then(|names| names.get(name)) {
+            Some(dos_name) => self.charset.encode(dos_name),
+            None => self.charset.encode(name),
+        }
+    }
+
+    // The contents of a file entry that was made up while building rather than found in the source
+    fn generated(&self, (dir, entry): (usize, usize)) -> Option<&[u8]> {
+        match &self.dirs[dir].entries[entry] {
+            Entry::File(file) => self.generated.get(&dir).and_then(|files| files.get(&file.name)).map(Vec::as_slice),
+            _ => None,
+        }
+    }
+
+    // Add a made up file to directory dir
+    fn add_generated(&mut self, dir: usize, name: &str, contents: Vec<u8>) {
+        let source = PathBuf::from(format!("{}{}", directory_path(self, dir), name));
+        self.dirs[dir].entries.push(Entry::File(FileEntry {
+            name: name.to_string(),
+            source,
+            size: contents.len() as u32,
+            modified: None,
+            extent: 0,
+        }));
+        self.generated.entry(dir).or_default().insert(name.to_string(), contents);
+    }
+
+    // File flags set with --hide and --associated for an entry of directory dir
+    fn record_flags(&self, dir: usize, name: &str) -> u8 {
+        self.record_flags.get(&dir).and_then(|flags| flags.get(name)).copied().unwrap_or(0)
+    }
+
+    // POSIX attributes recorded for an entry of directory dir, if any were
+    fn posix(&self, dir: usize, name: &str) -> Option<Posix> {
+        self.posix.get(&dir).and_then(|entries| entries.get(name)).copied()
+    }
+
+    // The same for a directory itself, which is named in its parent in the source tree (the
+    // root has no name in itself)
+    fn directory_posix(&self, index: usize) -> Option<Posix> {
+        let dir = &self.dirs[index];
+        self.posix(dir.original_parent.unwrap_or(dir.parent), &dir.name)
+    }
+}
+
+// Block addresses of the descriptor area and the order directories and files are recorded in
+#[derive(Debug)]
+struct Layout {
+    order: Vec<usize>,          // Directories in path table order
+    files: Vec<(usize, usize)>, // Files (directory index, entry index) in the order of their extents
+    path_table_size: u32,
+    l_path_table: u32,
+    m_path_table: u32,
+    padding_blocks: u32, // Zero blocks at the end of the image
+    total_blocks: u32,
+    boot: Option<BootLayout>,
+    gpt_backup: Option<u32>, // First block of the backup GPT at the end of hybrid images
+    udf: Option<UdfLayout>,  // The UDF bridge of DVD-Video images, whose second anchor is the last block
+    appended: Vec<u32>,      // First block of each appended partition image, after the padding
+}
+
+// Where the image is written; regular files can take file contents kernel-side
+trait ImageOutput: Write + Seek {
+    // The file behind the writer, if file contents may be copied into it directly
+    fn output_file(&self) -> Option<&File> {
+        None
+    }
+}
+
+impl ImageOutput for File {
+    fn output_file(&self) -> Option<&File> {
+        Some(self)
+    }
+}
+
+// Helper function to pad data to the block size
+fn pad_to_block<W: Write>(writer: &mut W, current_size: usize) -> io::Result<()> {
+    let padding_size = (BLOCK_SIZE - (current_size % BLOCK_SIZE)) % BLOCK_SIZE;
+    if padding_size > 0 {
+        writer.write_all(&vec![0u8; padding_size])?;
+    }
+    Ok(())
+}
+
+// Number of blocks needed to hold the given number of bytes
+fn blocks_for(size: u64) -> u32 {
+    size.div_ceil(BLOCK_SIZE as u64) as u32
+}
+
+// Encode a 32-bit value in both-byte order (little-endian followed by big-endian)
+fn both_endian_u32(value: u32) -> [u8; 8] {
+    let mut bytes = [0u8; 8];
+    bytes[..4].copy_from_slice(&value.to_le_bytes());
+    bytes[4..].copy_from_slice(&value.to_be_bytes());
+    bytes
+}
+
+// Encode a 16-bit value in both-byte order (little-endian followed by big-endian)
+fn both_endian_u16(value: u16) -> [u8; 4] {
+    let mut bytes = [0u8; 4];
+    bytes[..2].copy_from_slice(&value.to_le_bytes());
+    bytes[2..].copy_from_slice(&value.to_be_bytes());
+    bytes
+}
+
+// Count a file or directory that was left out of the image since it couldn't be read
+fn note_skipped() {
+    SKIPPED_FILES.fetch_add(1, Ordering::Relaxed);
+}
+
+// Count one left out since this user isn't allowed to read it
+fn note_denied(path: &Path) {
+    note_skipped();
+    DENIED_PATHS.lock().unwrap_or_else(|e| e.into_inner()).push(path.to_path_buf());
+}
+
+// How many were left out so far in this process
+fn skipped_files() -> u64 {
+    SKIPPED_FILES.load(Ordering::Relaxed)
+}
+
+// Seconds since the Unix epoch (negative before 1970)
+fn unix_time(time: SystemTime) -> i64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(after) => after.as_secs() as i64,
+        Err(before) => -(before.duration().as_secs() as i64),
+    }
+}
+
+// Convert seconds since the Unix epoch to a UTC (year, month, day, hour, minute, second)
+fn utc_date_time(timestamp: i64) -> (i64, u32, u32, u32, u32, u32) {
+    let (days, seconds) = (timestamp.div_euclid(86_400), timestamp.rem_euclid(86_400));
+
+    // Days to a civil date in the proleptic Gregorian calendar (eras of 400 years starting in March)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day, (seconds / 3600) as u32, (seconds / 60 % 60) as u32, (seconds % 60) as u32)
+}
+
+// Convert a UTC date and time to seconds since the Unix epoch (the inverse of utc_date_time)
+fn unix_from_utc(year: i64, month: u32, day: u32, hour: u32, minute: u32, second: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let shifted_month = if month > 2 { month - 3 } else { month + 9 } as i64;
+    let day_of_year = (153 * shifted_month + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    days * 86_400 + hour as i64 * 3600 + minute as i64 * 60 + second as i64
+}
+
+// A timestamp as "YYYY-MM-DD HH:MM:SS" (UTC) for reports
+fn time_text(timestamp: i64) -> String {
+    let (year, month, day, hour, minute, second) = utc_date_time(timestamp);
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", year, month, day, hour, minute, second)
+}
+
+// Parse "YYYY-MM-DD", optionally followed by "HH:MM[:SS]" (after a space or "T"), as UTC
+fn parse_date(text: &str) -> io::Result<i64> {
+    let invalid = || io::Error::new(ErrorKind::InvalidInput, format!("invalid date: {} (expected YYYY-MM-DD[ HH:MM:SS])", text));
+    let (date, time) = text.trim().split_once([' ', 'T']).unwrap_or((text.trim(), "00:00:00"));
+    let date: Vec<u32> = date.split('-').map(|part| part.parse().map_err(|_| invalid())).collect::<io::Result<_>>()?;
+    let time: Vec<u32> = time.trim_end_matches('Z').split(':').map(|part| part.parse().map_err(|_| invalid())).collect::<io::Result<_>>()?;
+    let (&[year, month, day], &[hour, minute, ..]) = (&date[..], &time[..]) else {
+        return Err(invalid());
+    };
+    let second = time.get(2).copied().unwrap_or(0);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 || time.len() > 3 {
+        return Err(invalid());
+    }
+    Ok(unix_from_utc(year as i64, month, day, hour, minute, second))
+}
+
+// 7-byte directory record date: years since 1900, month, day, hour, minute, second, UTC offset
+fn record_date(timestamp: i64) -> [u8; 7] {
+    let (year, month, day, hour, minute, second) = utc_date_time(timestamp);
+    [(year - 1900).clamp(0, 255) as u8, month as u8, day as u8, hour as u8, minute as u8, second as u8, 0]
+}
+
+// 17-byte volume descriptor date: "YYYYMMDDHHMMSScc" digits followed by the UTC offset
+fn volume_date(timestamp: i64) -> [u8; 17] {
+    let (year, month, day, hour, minute, second) = utc_date_time(timestamp);
+    let digits = format!("{:04}{:02}{:02}{:02}{:02}{:02}00", year.clamp(1, 9999), month, day, hour, minute, second);
+
+    let mut date = [0u8; 17];
+    date[..16].copy_from_slice(digits.as_bytes());
+    date
+}
+
+// A valid Primary Volume Descriptor (PVD)
+fn primary_volume_descriptor(layout: &Layout, root_record: &[u8], image_time: i64, identity: &VolumeIdentity) -> Vec<u8> {
+    let mut volume_descriptor = vec![0u8; BLOCK_SIZE];
+
+    // Set the descriptor type (Primary Volume Descriptor)
+    volume_descriptor[0] = PRIMARY_VOLUME_DESCRIPTOR;
+
+    // Set the standard identifier ("CD001")
+    volume_descriptor[1..6].copy_from_slice(CD001);
+
+    // Set the version number (1)
+    volume_descriptor[6] = 1;
+
+    // Set system identifier (32 characters, padded with spaces)
+    let system_identifier = b"RUST_SYSTEM_GENERATED           "; // Ensure it's 32 characters
+    volume_descriptor[8..40].copy_from_slice(system_identifier);
+
+    // Set volume identifier (32 characters, padded with spaces)
+    volume_descriptor[40..72].fill(b' ');
+    volume_descriptor[40..40 + identity.volume_id.len()].copy_from_slice(identity.volume_id.as_bytes());
+
+    // Volume space size (in logical blocks, which are 2048 bytes each)
+    volume_descriptor[80..88].copy_from_slice(&both_endian_u32(layout.total_blocks));
+
+    // Volume set size and volume sequence number
+    volume_descriptor[120..124].copy_from_slice(&both_endian_u16(1));
+    volume_descriptor[124..128].copy_from_slice(&both_endian_u16(1));
+
+    // Logical block size (2048 bytes per block)
+    volume_descriptor[128..132].copy_from_slice(&both_endian_u16(BLOCK_SIZE as u16));
+
+    // Path table size and locations (type L is little-endian, type M is big-endian)
+    volume_descriptor[132..140].copy_from_slice(&both_endian_u32(layout.path_table_size));
+    volume_descriptor[140..144].copy_from_slice(&layout.l_path_table.to_le_bytes());
+    volume_descriptor[148..152].copy_from_slice(&layout.m_path_table.to_be_bytes());
+
+    // Directory record for the root directory
+    volume_descriptor[156..190].copy_from_slice(root_record);
+
+    // Volume set, publisher, data preparer and application identifiers (spaces unless given)
+    volume_descriptor[190..702].fill(b' ');
+    if let Some(preparer) = &identity.preparer {
+        volume_descriptor[446..446 + preparer.len()].copy_from_slice(preparer.as_bytes());
+    }
+
+    // Copyright, abstract and bibliographic file identifiers are unused (spaces)
+    volume_descriptor[702..813].fill(b' ');
+
+    // Creation and modification dates; expiration and effective dates are not specified
+    volume_descriptor[813..830].copy_from_slice(&volume_date(image_time));
+    match &identity.uuid {
+        Some(uuid) => volume_descriptor[830..846].copy_from_slice(uuid),
+        None => volume_descriptor[830..847].copy_from_slice(&volume_date(image_time)),
+    }
+    for date in volume_descriptor[847..881].chunks_mut(17) {
+        date[..16].fill(b'0');
+    }
+
+    // File structure version (1)
+    volume_descriptor[881] = 1;
+
+    // Application use area
+    if let Some(application_use) = &identity.application_use {
+        volume_descriptor[883..883 + application_use.len()].copy_from_slice(application_use);
+    }
+
+    volume_descriptor
+}
+
+// Write the Volume Descriptor Set Terminator that ends the descriptor sequence
+fn write_volume_descriptor_terminator<W: Write>(writer: &mut W) -> io::Result<()> {
+    let mut terminator = vec![0u8; BLOCK_SIZE];
+    terminator[0] = VOLUME_DESCRIPTOR_TERMINATOR;
+    terminator[1..6].copy_from_slice(CD001);
+    terminator[6] = 1;
+    writer.write_all(&terminator)
+}
+
+// Helper function to build a directory record; system_use carries Rock Ridge (SUSP) entries
+fn directory_record(identifier: &[u8], start_block: u32, file_size: u32, recorded: i64, is_directory: bool, system_use: &[u8]) -> Vec<u8> {
+    // The identifier is followed by a padding byte when its length is even
+    let padding = if identifier.len().is_multiple_of(2) { 1 } else { 0 };
+    let length = 33 + identifier.len() + padding + system_use.len();
+
+    // Records always have an even length
+    let mut record = vec![0u8; length + length % 2];
+
+    // Length of the directory record
+    record[0] = record.len() as u8;
+
+    // Location of the extent (start block)
+    record[2..10].copy_from_slice(&both_endian_u32(start_block));
+
+    // Data length (file size)
+    record[10..18].copy_from_slice(&both_endian_u32(file_size));
+
+    // Recording date and time
+    record[18..25].copy_from_slice(&record_date(recorded));
+
+    // Set file flags
+    record[25] = if is_directory { 0x02 } else { 0x00 };
+
+    // Volume sequence number
+    record[28..32].copy_from_slice(&both_endian_u16(1));
+
+    // File identifier (file name)
+    record[32] = identifier.len() as u8;
+    record[33..33 + identifier.len()].copy_from_slice(identifier);
+
+    // System use area
+    let system_use_start = 33 + identifier.len() + padding;
+    record[system_use_start..system_use_start + system_use.len()].copy_from_slice(system_use);
+
+    record
+}
+
+// Build a System Use Sharing Protocol entry (signature, length, version, data)
+fn susp_entry(signature: &[u8; 2], data: &[u8]) -> Vec<u8> {
+    let mut entry = Vec::with_capacity(4 + data.len());
+    entry.extend_from_slice(signature);
+    entry.push((4 + data.len()) as u8);
+    entry.push(1);
+    entry.extend_from_slice(data);
+    entry
+}
+
+// SUSP indicator and Rock Ridge extension reference recorded in the root's "." record
+fn rock_ridge_root_entries() -> Vec<u8> {
+    let identifier = b"RRIP_1991A";
+    let descriptor = b"THE ROCK RIDGE INTERCHANGE PROTOCOL PROVIDES SUPPORT FOR POSIX FILE SYSTEM SEMANTICS";
+
+    let mut extension = vec![identifier.len() as u8, descriptor.len() as u8, 0, 1];
+    extension.extend_from_slice(identifier);
+    extension.extend_from_slice(descriptor);
+
+    let mut entries = susp_entry(b"SP", &[0xBE, 0xEF, 0]);
+    entries.extend(susp_entry(b"ER", &extension));
+    entries
+}
+
+// Rock Ridge POSIX file attributes (PX); without recorded attributes, read-only permissions
+// owned by root
+fn rock_ridge_attributes(posix: Option<Posix>, is_directory: bool) -> Vec<u8> {
+    let posix = posix.unwrap_or(if is_directory { Posix { mode: 0o040555, uid: 0, gid: 0 } } else { Posix { mode: 0o100444, uid: 0, gid: 0 } });
+    let links = if is_directory { 2 } else { 1 };
+
+    let mut attributes = Vec::with_capacity(32);
+    attributes.extend_from_slice(&both_endian_u32(posix.mode));
+    attributes.extend_from_slice(&both_endian_u32(links));
+    attributes.extend_from_slice(&both_endian_u32(posix.uid));
+    attributes.extend_from_slice(&both_endian_u32(posix.gid));
+    susp_entry(b"PX", &attributes)
+}
+
+// Rock Ridge entries of a record once the extensions are in use: the attributes, the
+// modification time (TF) and, except for "." and "..", the full name (NM) in as many pieces
+// as it takes
+fn rock_ridge_entries(tree: &ImageTree, posix: Option<Posix>, is_directory: bool, recorded: i64, name: Option<&str>) -> Vec<Vec<u8>> {
+    let mut entries = vec![rock_ridge_attributes(posix, is_directory)];
+    let mut time = vec![0x02]; // Modification time only, in the short form
+    time.extend_from_slice(&record_date(recorded));
+    entries.push(susp_entry(b"TF", &time));
+    if let Some(name) = name {
+        let name = tree.charset.encode(name);
+        let mut pieces = name.chunks(SUSP_DATA_MAX - 1).peekable();
+        while let Some(piece) = pieces.next() {
+            let mut data = vec![if pieces.peek().is_some() { 0x01 } else { 0x00 }]; // Continues in the next NM
+            data.extend_from_slice(piece);
+            entries.push(susp_entry(b"NM", &data));
+        }
+    }
+    entries
+}
+
+// Rock Ridge SL entries for a symbolic link target: a component record for each path component
+// ("/" at the start, ".", ".." or a name), as many as fit in an entry. Names fill what is left
+// of an entry and continue in the next, since some readers take the start of an entry for the
+// start of a component.
+fn symlink_entries(tree: &ImageTree, target: &str) -> Vec<Vec<u8>> {
+    let mut groups: Vec<Vec<u8>> = vec![Vec::new()];
+    for (position, component) in target.split('/').enumerate() {
+        let (flags, mut name) = match component {
+            "" if position == 0 => (0x08, Vec::new()),
+            "" => continue, // Repeated or trailing slashes
+            "." => (0x02, Vec::new()),
+            ".." => (0x04, Vec::new()),
+            _ => (0x00, tree.charset.encode(component)),
+        };
+        loop {
+            // Room in the entry after its flags byte, for the record header and at least some of the name
+            let left = SUSP_DATA_MAX - 1 - groups.last().map_or(0, Vec::len);
+            if left < 2 + name.len().min(1) {
+                groups.push(Vec::new());
+                continue;
+            }
+            let rest = name.split_off(name.len().min(left - 2));
+            let group = groups.last_mut().unwrap();
+            group.push(if rest.is_empty() { flags } else { flags | 0x01 }); // Continues in the next component record
+            group.push(name.len() as u8);
+            group.extend(name);
+            if rest.is_empty() {
+                break;
+            }
+            name = rest;
+        }
+    }
+
+    let count = groups.len();
+    groups
+        .into_iter()
+        .enumerate()
+        .map(|(position, group)| {
+            let mut data = vec![if position + 1 < count { 0x01 } else { 0x00 }]; // Continues in the next SL
+            data.extend(group);
+            susp_entry(b"SL", &data)
+        })
+        .collect()
+}
+
+// Rock Ridge PN entry with the device number of a device, as the high and low halves of a
+// glibc dev_t the way mkisofs records it (Linux and libarchive both read that back)
+fn device_entry((major, minor): (u32, u32)) -> Vec<u8> {
+    let (major, minor) = (major as u64, minor as u64);
+    let device = ((major & 0xfffff000) << 32) | ((major & 0xfff) << 8) | ((minor & 0xffffff00) << 12) | (minor & 0xff);
+    let mut data = both_endian_u32((device >> 32) as u32).to_vec();
+    data.extend_from_slice(&both_endian_u32(device as u32));
+    susp_entry(b"PN", &data)
+}
+
+// The continuation area of a directory: System Use entries that don't fit in their records,
+// in whole blocks right after the directory extent, pointed at by CE entries. No piece of it
+// crosses a block boundary.
+struct Continuation {
+    start: u32, // First block of the area
+    area: Vec<u8>,
+}
+
+impl Continuation {
+    // The system use area of a record with room for that many bytes: all of the entries, or
+    // those that fit with a CE entry for the rest
+    fn pack(&mut self, entries: Vec<Vec<u8>>, room: usize) -> Vec<u8> {
+        if entries.iter().map(Vec::len).sum::<usize>() <= room {
+            return entries.concat();
+        }
+        if room < CE_LENGTH {
+            return Vec::new(); // A name as long as ISO 9660:1999 allows leaves no room for any
+        }
+        let (here, rest) = split_entries(entries, room - CE_LENGTH);
+        let mut system_use = here.concat();
+        system_use.extend(self.place(rest));
+        system_use
+    }
+
+    // Put entries in the area, the first block's worth (and a CE entry for the rest) together,
+    // and return the CE entry that points at them
+    fn place(&mut self, entries: Vec<Vec<u8>>) -> Vec<u8> {
+        let (here, rest) = if entries.iter().map(Vec::len).sum::<usize>() <= BLOCK_SIZE { (entries, Vec::new()) } else { split_entries(entries, BLOCK_SIZE - CE_LENGTH) };
+        let length = here.iter().map(Vec::len).sum::<usize>() + if rest.is_empty() { 0 } else { CE_LENGTH };
+        let used = self.area.len() % BLOCK_SIZE;
+        if used + length > BLOCK_SIZE {
+            self.area.resize(self.area.len() + BLOCK_SIZE - used, 0);
+        }
+        let offset = self.area.len();
+        self.area.extend(here.concat());
+        if !rest.is_empty() {
+            let slot = self.area.len();
+            self.area.resize(slot + CE_LENGTH, 0);
+            let next = self.place(rest);
+            self.area[slot..slot + CE_LENGTH].copy_from_slice(&next);
+        }
+
+        let mut data = both_endian_u32(self.start + (offset / BLOCK_SIZE) as u32).to_vec();
+        data.extend_from_slice(&both_endian_u32((offset % BLOCK_SIZE) as u32));
+        data.extend_from_slice(&both_endian_u32(length as u32));
+        susp_entry(b"CE", &data)
+    }
+
+    // The area padded to whole blocks
+    fn finish(mut self) -> Vec<u8> {
+        self.area.resize(blocks_for(self.area.len() as u64) as usize * BLOCK_SIZE, 0);
+        self.area
+    }
+}
+
+// The leading entries that fit in that many bytes, and the rest
+fn split_entries(entries: Vec<Vec<u8>>, room: usize) -> (Vec<Vec<u8>>, Vec<Vec<u8>>) {
+    let mut used = 0;
+    let fitting = entries
+        .iter()
+        .take_while(|entry| {
+            used += entry.len();
+            used <= room
+        })
+        .count();
+    let mut here = entries;
+    let rest = here.split_off(fitting);
+    (here, rest)
+}
+
+// A directory being walked
+struct Listing {
+    index: usize,                                          // In the image tree
+    device: Option<u64>,                                   // For --one-file-system
+    identity: Option<FileIdentity>,                        // To tell a link back to it
+    entries: std::vec::IntoIter<io::Result<ScannedEntry>>, // Still to go through
+    rules: Vec<ignore::Rule>,                              // Of its ignore files
+}
+
+// Walk a source directory into the image tree, depth first with a stack of its own rather than
+// recursion. Listings are read whole and closed as a directory is entered, so one directory is
+// open at a time however deep or wide the tree is; directories that can't be read are skipped.
+// Every entry is looked up once, by the listing, and what it found is used from then on.
+//
+// Symbolic links, and on Windows junctions and mounted folders, are followed unless --symlinks
+// skip leaves them out or --symlinks store records them as links; a directory link back to a
+// directory being walked goes in empty rather than looping.
+fn process_directory(tree: &mut ImageTree, source: &dyn SourceProvider, dir: &Path, name: String, parent: usize, options: &ImageOptions, found: &mut FileFound) -> io::Result<usize> {
+    let mut stack: Vec<Listing> = Vec::new();
+    let (modified, device, identity) = (source.directory_modified(dir), source.device(dir), source.directory_identity(dir));
+    let root = enter_directory(tree, source, dir, name, parent, modified, device, identity, options, &mut stack)?;
+
+    while let Some(listing) = stack.last_mut() {
+        let (index, device) = (listing.index, listing.device);
+        let Some(entry) = listing.entries.next() else {
+            stack.pop();
+            continue;
+        };
+        cancel::check()?;
+        tree.scanned.tick();
+        match entry {
+            Ok(ScannedEntry { path, kind, metadata }) => {
+                let file_name = source_name(path.file_name().unwrap(), options);
+                let entry_path = format!("{}{}", original_path(tree, index), file_name);
+                if matches_any(&options.exclude, &entry_path, &file_name) {
+                    continue;
+                }
+                if (kind == SourceKind::File && file_name == ignore::ISOIGNORE)
+                    || ignore::ignored(stack.iter().map(|listing| &listing.rules), &entry_path, &file_name, kind == SourceKind::Directory)
+                {
+                    continue;
+                }
+                if options.symlinks != SymlinkPolicy::Follow && source.is_symlink(&path) {
+                    if options.symlinks == SymlinkPolicy::Store {
+                        add_symlink(tree, source, &path, file_name, index, options)?;
+                    }
+                    continue;
+                }
+                let found_here = metadata.as_ref().ok().map(|metadata| (metadata.modified, metadata.identity));
+                let (child_modified, child_identity) = found_here.unwrap_or((None, None));
+                let child_device = child_identity.map(|(device, _)| device);
+
+                if kind == SourceKind::Directory && mounts::is_boundary(device, child_device, options.one_file_system) {
+                    // A mount point goes in empty
+                    add_directory(tree, &path, file_name, index, child_modified, options.deep_dirs)?;
+                } else if kind == SourceKind::Directory {
+                    if stack.len() > options.max_depth {
+                        return Err(io::Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("{} is more than {} directories deep (raise --max-depth if that is right)", path.display(), options.max_depth),
+                        ));
+                    }
+                    let child_identity = child_identity.or_else(|| source.directory_identity(&path));
+                    if child_identity.is_some() && stack.iter().any(|listing| listing.identity == child_identity) {
+                        events::warn(&format!("{} links back to a directory it is in; adding it empty", path.display()));
+                        add_directory(tree, &path, file_name, index, child_modified, options.deep_dirs)?;
+                        continue;
+                    }
+                    // Handle permission errors when entering directories
+                    match enter_directory(tree, source, &path, file_name, index, child_modified, child_device, child_identity, options, &mut stack) {
+                        Ok(_) => {}
+                        Err(e) if e.kind() == ErrorKind::PermissionDenied => {
+                            events::warn(&format!("Permission denied while accessing directory: {}", path.display()));
+                            note_denied(&path);
+                            continue; // Skip this directory
+                        }
+                        Err(e) => return Err(e),
+                    }
+                } else if kind == SourceKind::File {
+                    add_source_file(tree, source, path, file_name, index, metadata, options, found)?;
+                }
+            }
+            Err(e) => {
+                events::warn(&format!("Error reading directory entry: {}", e));
+                note_skipped();
+                continue; // Skip unreadable entries
+            }
+        }
+    }
+
+    Ok(root)
+}
+
+// Add a directory, with the modification time, device and identity found for it, to the image
+// tree and its listing to the walk
+#[allow(clippy::too_many_arguments)]
+fn enter_directory(
+    tree: &mut ImageTree,
+    source: &dyn SourceProvider,
+    dir: &Path,
+    name: String,
+    parent: usize,
+    modified: Option<SystemTime>,
+    device: Option<u64>,
+    identity: Option<FileIdentity>,
+    options: &ImageOptions,
+    stack: &mut Vec<Listing>,
+) -> io::Result<usize> {
+    let entries = source.entries(dir)?;
+    let index = add_directory(tree, dir, name, parent, modified, options.deep_dirs)?;
+    if options.xattrs {
+        capture_xattrs(tree, source, dir, original_path(tree, index));
+    }
+    if options.win_metadata {
+        capture_win_metadata(tree, source, dir, original_path(tree, index));
+    }
+    let rules = ignore::read_rules(source, &entries, &original_path(tree, index), options.use_gitignore)?;
+    tree.scanned.directories += 1;
+    stack.push(Listing { index, device, identity, entries: entries.into_iter(), rules });
+    Ok(index)
+}
+
+// Record a symbolic link as itself (--symlinks store): a Rock Ridge entry with its target, and
+// no extent. Links that can't be read are skipped with a warning, like unreadable files
+fn add_symlink(tree: &mut ImageTree, source: &dyn SourceProvider, path: &Path, file_name: String, index: usize, options: &ImageOptions) -> io::Result<()> {
+    if !options.types.is_empty() && !options.types.contains(&'l') {
+        return Ok(());
+    }
+    check_file_path(tree, index, &file_name, path)?;
+    let (target, modified) = match source.link_target(path) {
+        Ok(link) => link,
+        Err(e) => {
+            events::warn(&format!("Could not read the link {}: {}", path.display(), e));
+            note_skipped();
+            return Ok(());
+        }
+    };
+    tree.rock_ridge = true;
+    tree.posix.entry(index).or_default().insert(file_name.clone(), Posix { mode: 0o120777, uid: 0, gid: 0 });
+    tree.dirs[index].entries.push(Entry::Symlink { name: file_name, target, modified });
+    Ok(())
+}
+
+// Whether a source file passes --max-file-size, --min-mtime, --max-mtime and --type; files
+// without a modification time are left out by either time limit. Directories are always
+// walked, so the files under them can match
+fn selected(source: &dyn SourceProvider, path: &Path, metadata: &SourceMetadata, options: &ImageOptions) -> bool {
+    let modified = metadata.modified.map(unix_time);
+    let kind = if options.types.is_empty() {
+        None
+    } else if source.is_symlink(path) {
+        Some('l')
+    } else {
+        Some('f')
+    };
+    options.max_file_size.is_none_or(|size| metadata.len <= size)
+        && options.min_mtime.is_none_or(|time| modified.is_some_and(|modified| modified >= time))
+        && options.max_mtime.is_none_or(|time| modified.is_some_and(|modified| modified <= time))
+        && kind.is_none_or(|kind| options.types.contains(&kind))
+}
+
+// Add a source file, with the metadata the scan found for it, to directory index of the image tree
+#[allow(clippy::too_many_arguments)]
+fn add_source_file(
+    tree: &mut ImageTree,
+    source: &dyn SourceProvider,
+    path: PathBuf,
+    file_name: String,
+    index: usize,
+    metadata: io::Result<SourceMetadata>,
+    options: &ImageOptions,
+    found: &mut FileFound,
+) -> io::Result<()> {
+    if options.apple && file_name.starts_with(apple::APPLE_DOUBLE_PREFIX) {
+        return Ok(()); // AppleDouble files only carry the Finder info of the file they belong to
+    }
+    check_file_path(tree, index, &file_name, &path)?;
+
+    // Skip files that cannot be opened, as they could not be written later
+    let metadata = match metadata {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == ErrorKind::PermissionDenied => {
+            events::warn(&format!("Permission denied while accessing file: {}", path.display()));
+            note_denied(&path);
+            return Ok(()); // Skip this file
+        }
+        Err(e) => return Err(e),
+    };
+    if !selected(source, &path, &metadata, options) {
+        return Ok(());
+    }
+    if is_output(tree, &path, &metadata) {
+        events::warn(&format!("Leaving out {}, the image being written", path.display()));
+        return Ok(());
+    }
+    let size = u32::try_from(metadata.len).map_err(|_| {
+        io::Error::new(ErrorKind::InvalidInput, format!("{} is too large for a single ISO 9660 extent", path.display()))
+    })?;
+
+    if options.xattrs {
+        capture_xattrs(tree, source, &path, format!("{}/{}", original_path(tree, index).trim_end_matches('/'), file_name));
+    }
+    if options.win_metadata {
+        capture_win_metadata(tree, source, &path, format!("{}/{}", original_path(tree, index).trim_end_matches('/'), file_name));
+    }
+    if options.dvd_video && file_name.ends_with(".IFO") && original_path(tree, index) == format!("/{}/", dvdvideo::VIDEO_TS) {
+        match dvdvideo::read_header(source, &path) {
+            Ok(header) => {
+                tree.ifo_headers.insert(file_name.clone(), header);
+            }
+            Err(e) => events::warn(&format!("Could not read {}, laying out its title set without it: {}", path.display(), e)),
+        }
+    }
+    if options.apple {
+        match apple::read(source, &path, &file_name) {
+            Ok(Some(info)) => {
+                tree.finder_info.entry(index).or_default().insert(file_name.clone(), info);
+            }
+            Ok(None) => {}
+            Err(e) => events::warn(&format!("Could not read the Finder info of {}: {}", path.display(), e)),
+        }
+    }
+    if events::enabled() {
+        let image_path = format!("{}/{}", original_path(tree, index).trim_end_matches('/'), file_name);
+        events::emit("file-added", &[("path", json::string(&image_path)), ("size", size.to_string())]);
+    }
+    tree.scanned.files += 1;
+    tree.scanned.bytes += size as u64;
+    let mut file = FileEntry {
+        name: file_name,
+        source: path,
+        size,
+        modified: metadata.modified,
+        extent: 0,
+    };
+    found(&mut file)?;
+    tree.dirs[index].entries.push(Entry::File(file));
+    Ok(())
+}
+
+// Whether a source file is the image itself: the same device and inode, or the same path
+// where files have no identity
+fn is_output(tree: &ImageTree, path: &Path, metadata: &SourceMetadata) -> bool {
+    tree.outputs.iter().any(|(identity, canonical)| match (identity, metadata.identity) {
+        (Some(identity), Some(source)) => *identity == source,
+        _ => path.canonicalize().is_ok_and(|path| path == *canonical),
+    })
+}
+
+// The image and its partial file, by identity where they already exist and by path where they
+// don't yet (a --single-pass image is created before the scan reaches it); a remote output
+// can't be a source
+fn find_outputs(iso_file_path: &Path, output: &Path) -> Vec<(Option<FileIdentity>, PathBuf)> {
+    if backend::is_remote(iso_file_path) {
+        return Vec::new();
+    }
+    [iso_file_path, output, partial_path(iso_file_path).as_path()]
+        .iter()
+        .filter_map(|path| match fs::metadata(path) {
+            Ok(metadata) => Some((backend::file_identity(&metadata), path.canonicalize().ok()?)),
+            Err(_) => {
+                let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+                Some((None, parent.canonicalize().ok()?.join(path.file_name()?)))
+            }
+        })
+        .collect()
+}
+
+// Called with every file the scan adds, before it goes into the tree; --single-pass writes the
+// contents there and then
+type FileFound<'a> = dyn FnMut(&mut FileEntry) -> io::Result<()> + 'a;
+
+// Scan the sources into the tree, then encrypt the files --encrypt-glob selects; a single
+// source directory is the root itself
+fn scan_sources(tree: &mut ImageTree, source: &dyn SourceProvider, sources: &[PathBuf], options: &ImageOptions) -> io::Result<()> {
+    scan_sources_with(tree, source, sources, options, &mut |_| Ok(()))
+}
+
+fn scan_sources_with(tree: &mut ImageTree, source: &dyn SourceProvider, sources: &[PathBuf], options: &ImageOptions, found: &mut FileFound) -> io::Result<()> {
+    match sources {
+        [dir] if source.kind(dir) == SourceKind::Directory => {
+            process_directory(tree, source, dir, String::new(), 0, options, found)?;
+        }
+        _ => scan_roots(tree, source, sources, options, found)?,
+    }
+    tree.scanned.report();
+    if !options.encrypt_glob.is_empty() {
+        secrets::encrypt_files(tree, source, options)?;
+    }
+    if options.dedup {
+        dedup::hash_contents(tree, source)?;
+    }
+    if options.checksums {
+        checksums::add(tree, source)?;
+    }
+    Ok(())
+}
+
+// Several sources (or a single file) go into a root of their own: directories become
+// top-level directories and files top-level files
+fn scan_roots(tree: &mut ImageTree, source: &dyn SourceProvider, sources: &[PathBuf], options: &ImageOptions, found: &mut FileFound) -> io::Result<()> {
+    add_directory(tree, Path::new("/"), String::new(), 0, None, options.deep_dirs)?;
+    let mut taken: HashMap<String, &Path> = HashMap::new();
+    for path in sources {
+        let name = path
+            .file_name()
+            .map(|name| source_name(name, options))
+            .or_else(|| fs::canonicalize(path).ok()?.file_name().map(|name| source_name(name, options)))
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, format!("{} has no name to give it in the image", path.display())))?;
+        if let Some(other) = taken.insert(name.clone(), path) {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} and {} would both be /{} in the image", other.display(), path.display(), name),
+            ));
+        }
+        match source.kind(path) {
+            SourceKind::Directory => {
+                process_directory(tree, source, path, name, 0, options, found)?;
+            }
+            SourceKind::File => add_source_file(tree, source, path.clone(), name, 0, source.file_metadata(path), options, found)?,
+            SourceKind::Other => return Err(io::Error::new(ErrorKind::NotFound, format!("{} is not a file or directory", path.display()))),
+        }
+    }
+    Ok(())
+}
+
+// Note the extended attributes of a source file or directory for XATTRS.TXT
+fn capture_xattrs(tree: &mut ImageTree, source: &dyn SourceProvider, path: &Path, image_path: String) {
+    match source.xattrs(path) {
+        Ok(attributes) if attributes.is_empty() => {}
+        Ok(attributes) => tree.xattrs.push((image_path, attributes)),
+        Err(e) => events::warn(&format!("Could not read the extended attributes of {}: {}", path.display(), e)),
+    }
+}
+
+// Note the Windows metadata of a source file or directory for WINMETA.TXT
+fn capture_win_metadata(tree: &mut ImageTree, source: &dyn SourceProvider, path: &Path, image_path: String) {
+    match source.win_metadata(path) {
+        Ok(recorded) if recorded.is_empty() => {}
+        Ok(recorded) => tree.win_metadata.push((image_path, recorded)),
+        Err(e) => events::warn(&format!("Could not read the Windows metadata of {}: {}", path.display(), e)),
+    }
+}
+
+// Add a directory to the image tree under parent, relocating it if it is nested too deeply;
+// source names it in error messages
+fn add_directory(tree: &mut ImageTree, source: &Path, name: String, parent: usize, modified: Option<SystemTime>, policy: DeepDirPolicy) -> io::Result<usize> {
+    // Work out where the directory goes, relocating it if it is nested too deeply
+    let (depth, path_len) = match tree.dirs.get(parent) {
+        Some(parent_dir) => (parent_dir.depth + 1, parent_dir.path_len + 1 + name.len()),
+        None => (1, 0), // This is the root
+    };
+    check_name_length(tree, &name, source)?;
+    if !tree.relaxed && (depth > MAX_DIRECTORY_DEPTH || path_len > MAX_PATH_LENGTH) {
+        if policy == DeepDirPolicy::Error {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "{} exceeds the ISO 9660 limits of {} directory levels and {} path characters (use --deep-dirs relocate)",
+                    source.display(),
+                    MAX_DIRECTORY_DEPTH,
+                    MAX_PATH_LENGTH
+                ),
+            ));
+        }
+
+        let moved = relocation_directory(tree);
+        let moved_name = unique_relocated_name(tree, moved, &name);
+        let index = tree.dirs.len();
+        tree.dirs.push(Directory {
+            depth: tree.dirs[moved].depth + 1,
+            path_len: tree.dirs[moved].path_len + 1 + moved_name.len(),
+            name: moved_name,
+            parent: moved,
+            original_parent: Some(parent),
+            entries: Vec::new(),
+            modified,
+            number: 0,
+            extent: 0,
+            size: 0,
+            continuation: 0,
+        });
+        tree.dirs[moved].entries.push(Entry::Directory(index));
+        tree.dirs[parent].entries.push(Entry::Relocated { name, target: index });
+        Ok(index)
+    } else {
+        let index = tree.dirs.len();
+        tree.dirs.push(Directory {
+            name,
+            parent,
+            original_parent: None,
+            entries: Vec::new(),
+            modified,
+            depth,
+            path_len,
+            number: 0,
+            extent: 0,
+            size: 0,
+            continuation: 0,
+        });
+        if index > 0 {
+            tree.dirs[parent].entries.push(Entry::Directory(index));
+        }
+        Ok(index)
+    }
+}
+
+// Refuse files whose path from the root would be longer than ISO 9660 allows
+fn check_file_path(tree: &ImageTree, dir: usize, name: &str, source: &Path) -> io::Result<()> {
+    check_name_length(tree, name, source)?;
+    let path_len = tree.dirs[dir].path_len + 1 + name.len();
+    if !tree.relaxed && path_len > MAX_PATH_LENGTH {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} exceeds the ISO 9660 path length limit of {} characters", source.display(), MAX_PATH_LENGTH),
+        ));
+    }
+    Ok(())
+}
+
+// Refuse names longer than ISO 9660:1999 allows, when that is the only limit
+fn check_name_length(tree: &ImageTree, name: &str, source: &Path) -> io::Result<()> {
+    if tree.relaxed && name.len() > MAX_ENHANCED_NAME_LENGTH {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} has a name longer than the ISO 9660:1999 limit of {} bytes", source.display(), MAX_ENHANCED_NAME_LENGTH),
+        ));
+    }
+    Ok(())
+}
+
+// Get the rr_moved directory that holds relocated directories, creating it under the root on first use
+fn relocation_directory(tree: &mut ImageTree) -> usize {
+    if let Some(index) = tree.relocation_dir {
+        return index;
+    }
+
+    let index = tree.dirs.len();
+    tree.dirs.push(Directory {
+        name: RELOCATION_DIRECTORY.to_string(),
+        parent: 0,
+        original_parent: None,
+        entries: Vec::new(),
+        modified: None,
+        depth: 2,
+        path_len: 1 + RELOCATION_DIRECTORY.len(),
+        number: 0,
+        extent: 0,
+        size: 0,
+        continuation: 0,
+    });
+    tree.dirs[0].entries.push(Entry::Directory(index));
+    tree.relocation_dir = Some(index);
+    index
+}
+
+// Pick a name for a relocated directory that doesn't clash with earlier relocations
+fn unique_relocated_name(tree: &ImageTree, moved: usize, name: &str) -> String {
+    let taken = |candidate: &str| tree.dirs[moved].entries.iter().any(|entry| entry_name(tree, entry) == candidate);
+
+    let mut candidate = name.to_string();
+    let mut counter = 1;
+    while taken(&candidate) {
+        candidate = format!("{}_{}", name, counter);
+        counter += 1;
+    }
+    candidate
+}
+
+// Name of a directory entry as it is recorded in the image
+fn entry_name<'a>(tree: &'a ImageTree, entry: &'a Entry) -> &'a str {
+    match entry {
+        Entry::File(file) => &file.name,
+        Entry::Directory(index) => &tree.dirs[*index].name,
+        Entry::Relocated { name, .. } | Entry::Symlink { name, .. } | Entry::Special { name, .. } => name,
+    }
+}
+
+// Whether an entry matches one of the patterns of an option; patterns with a "/" match the
+// path from the root, others the name
+fn matches_any(patterns: &[String], path: &str, name: &str) -> bool {
+    patterns.iter().any(|pattern| {
+        if pattern.contains('/') {
+            glob::matches(pattern.trim_start_matches('/'), path.trim_start_matches('/'))
+        } else {
+            glob::matches(pattern, name)
+        }
+    })
+}
+
+// Weight of the first --sort-weight pattern an entry matches, 0 without one
+fn sort_weight(weights: &[(String, i64)], path: &str, name: &str) -> i64 {
+    weights.iter().find(|(pattern, _)| matches_any(std::slice::from_ref(pattern), path, name)).map_or(0, |&(_, weight)| weight)
+}
+
+// A pattern and its weight, split at the last separator: PATTERN=WEIGHT or, in a --sort-file,
+// PATTERN WEIGHT
+fn parse_sort_weight(text: &str, separator: char) -> io::Result<(String, i64)> {
+    text.rsplit_once(separator)
+        .and_then(|(pattern, weight)| Some((pattern.trim().to_string(), weight.trim().parse().ok()?)))
+        .filter(|(pattern, _)| !pattern.is_empty())
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, format!("invalid sort weight: {} (expected PATTERN{}WEIGHT)", text, separator)))
+}
+
+// Record the flags --hide and --associated ask for
+fn mark_entries(tree: &mut ImageTree, options: &ImageOptions) {
+    if options.hidden.is_empty() && options.associated.is_empty() {
+        return;
+    }
+    for index in 0..tree.dirs.len() {
+        let dir_path = directory_path(tree, index);
+        let mut flags = HashMap::new();
+        for entry in &tree.dirs[index].entries {
+            let name = entry_name(tree, entry);
+            let path = format!("{}{}", dir_path, name);
+            let mut flag = 0;
+            if matches_any(&options.hidden, &path, name) {
+                flag |= FLAG_HIDDEN;
+            }
+            if matches_any(&options.associated, &path, name) {
+                flag |= FLAG_ASSOCIATED;
+            }
+            if flag != 0 {
+                flags.insert(name.to_string(), flag);
+            }
+        }
+        tree.record_flags.entry(index).or_default().extend(flags);
+    }
+}
+
+// Rock Ridge records names in NM entries, so a name too long to leave room for a CE entry in
+// its record gets a shorter identifier, made unique with a ~N suffix
+fn shorten_identifiers(tree: &mut ImageTree) {
+    for index in 0..tree.dirs.len() {
+        let names: Vec<String> = tree.dirs[index].entries.iter().map(|entry| entry_name(tree, entry).to_string()).collect();
+        let mut taken: HashSet<Vec<u8>> = names.iter().map(|name| tree.identifier(index, name)).collect();
+        for name in names {
+            if tree.identifier(index, &name).len() <= MAX_ROCK_RIDGE_IDENTIFIER {
+                continue;
+            }
+            for number in 1.. {
+                let suffix = format!("~{}", number);
+                let mut short = String::new();
+                let mut length = suffix.len();
+                for c in name.chars() {
+                    length += tree.charset.encode(c.encode_utf8(&mut [0; 4])).len();
+                    if length > MAX_ROCK_RIDGE_IDENTIFIER {
+                        break;
+                    }
+                    short.push(c);
+                }
+                short.push_str(&suffix);
+                if taken.insert(tree.charset.encode(&short)) {
+                    tree.dos_names.entry(index).or_default().insert(name, short);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+// Sort every directory by identifier as ISO 9660 requires, rejecting duplicate names
+fn sort_entries(tree: &mut ImageTree) -> io::Result<()> {
+    for index in 0..tree.dirs.len() {
+        let mut entries = std::mem::take(&mut tree.dirs[index].entries);
+        entries.sort_by_cached_key(|entry| tree.identifier(index, entry_name(tree, entry)));
+
+        if let Some(pair) = entries.windows(2).find(|pair| tree.identifier(index, entry_name(tree, &pair[0])) == tree.identifier(index, entry_name(tree, &pair[1]))) {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("duplicate name in the image: {}", entry_name(tree, &pair[0])),
+            ));
+        }
+
+        tree.dirs[index].entries = entries;
+    }
+    Ok(())
+}
+
+// Build the records of a directory extent, padded to whole blocks, and the continuation area
+// that follows it
+fn directory_extent(tree: &ImageTree, index: usize) -> (Vec<u8>, Vec<u8>) {
+    let dir = &tree.dirs[index];
+    let parent = &tree.dirs[dir.parent];
+    let rock_ridge = tree.relocation_dir.is_some() || tree.rock_ridge;
+    let mut continuation = Continuation { start: dir.extent + blocks_for(dir.size as u64), area: Vec::new() };
+    let mut records = Vec::new();
+
+    // Rock Ridge readers expect POSIX attributes on every record once the extensions are in use
+    let system_use = |posix: Option<Posix>, is_directory: bool, recorded: i64, name: Option<&str>, extra: Vec<Vec<u8>>| {
+        let mut entries = if rock_ridge { rock_ridge_entries(tree, posix, is_directory, recorded, name) } else { Vec::new() };
+        entries.extend(extra.into_iter().filter(|entry| !entry.is_empty()));
+        entries
+    };
+    let mut record = |identifier: &[u8], extent: u32, size: u32, recorded: i64, is_directory: bool, entries: Vec<Vec<u8>>| {
+        let padding = if identifier.len().is_multiple_of(2) { 1 } else { 0 };
+        let room = 254usize.saturating_sub(33 + identifier.len() + padding);
+        directory_record(identifier, extent, size, recorded, is_directory, &continuation.pack(entries, room))
+    };
+
+    // The "." record describes the directory itself, the ".." record its parent
+    let root = if index == 0 && rock_ridge { vec![rock_ridge_root_entries()] } else { Vec::new() };
+    let recorded = tree.record_time(dir.modified);
+    let mut self_system_use = root;
+    self_system_use.extend(system_use(tree.directory_posix(index), true, recorded, None, Vec::new()));
+    records.push(record(&[0x00], dir.extent, dir.size, recorded, true, self_system_use));
+
+    let parent_link = match dir.original_parent {
+        Some(original) => susp_entry(b"PL", &both_endian_u32(tree.dirs[original].extent)),
+        None => Vec::new(),
+    };
+    let recorded = tree.record_time(parent.modified);
+    records.push(record(&[0x01], parent.extent, parent.size, recorded, true, system_use(tree.directory_posix(dir.parent), true, recorded, None, vec![parent_link])));
+
+    for entry in &dir.entries {
+        let name = entry_name(tree, entry);
+        let identifier = tree.identifier(index, name);
+        let posix = tree.posix(index, name);
+        let mut record = match entry {
+            Entry::File(file) => {
+                let finder_info = tree.finder_info.get(&index).and_then(|files| files.get(&file.name)).map(FinderInfo::system_use).unwrap_or_default();
+                let recorded = tree.record_time(file.modified);
+                record(&identifier, file.extent, file.size, recorded, false, system_use(posix, false, recorded, Some(name), vec![finder_info]))
+            }
+            Entry::Directory(child) => {
+                let child = &tree.dirs[*child];
+                let relocated = if child.original_parent.is_some() { susp_entry(b"RE", &[]) } else { Vec::new() };
+                let recorded = tree.record_time(child.modified);
+                record(&identifier, child.extent, child.size, recorded, true, system_use(posix, true, recorded, Some(name), vec![relocated]))
+            }
+            Entry::Relocated { target, .. } => {
+                // The placeholder is recorded as a file whose CL entry points at the moved directory
+                let target = &tree.dirs[*target];
+                let child_link = susp_entry(b"CL", &both_endian_u32(target.extent));
+                let recorded = tree.record_time(target.modified);
+                record(&identifier, target.extent, 0, recorded, false, system_use(posix, true, recorded, Some(name), vec![child_link]))
+            }
+            Entry::Symlink { target, modified, .. } => {
+                // Links and special files are empty files, what they are is in their Rock Ridge entries
+                let recorded = tree.record_time(*modified);
+                record(&identifier, 0, 0, recorded, false, system_use(posix, false, recorded, Some(name), symlink_entries(tree, target)))
+            }
+            Entry::Special { device, modified, .. } => {
+                let recorded = tree.record_time(*modified);
+                record(&identifier, 0, 0, recorded, false, system_use(posix, false, recorded, Some(name), device.map(device_entry).into_iter().collect()))
+            }
+        };
+        record[25] |= tree.record_flags(index, name);
+        records.push(record);
+    }
+
+    // Records may not cross block boundaries
+    let mut extent = Vec::new();
+    for record in records {
+        let used = extent.len() % BLOCK_SIZE;
+        if used + record.len() > BLOCK_SIZE {
+            extent.resize(extent.len() + BLOCK_SIZE - used, 0);
+        }
+        extent.extend_from_slice(&record);
+    }
+    extent.resize(blocks_for(extent.len() as u64) as usize * BLOCK_SIZE, 0);
+    (extent, continuation.finish())
+}
+
+// Build a path table in either little-endian (type L) or big-endian (type M) byte order
+fn path_table(tree: &ImageTree, order: &[usize], little_endian: bool) -> Vec<u8> {
+    let mut table = Vec::new();
+    for &index in order {
+        let dir = &tree.dirs[index];
+        let identifier = if index == 0 { vec![0x00] } else { tree.identifier(dir.parent, &dir.name) };
+        let parent_number = tree.dirs[dir.parent].number;
+
+        table.push(identifier.len() as u8);
+        table.push(0);
+        if little_endian {
+            table.extend_from_slice(&dir.extent.to_le_bytes());
+            table.extend_from_slice(&parent_number.to_le_bytes());
+        } else {
+            table.extend_from_slice(&dir.extent.to_be_bytes());
+            table.extend_from_slice(&parent_number.to_be_bytes());
+        }
+        table.extend_from_slice(&identifier);
+        if identifier.len() % 2 == 1 {
+            table.push(0);
+        }
+    }
+    table
+}
+
+// Assign directory numbers and extents to everything in the tree
+fn layout_image(tree: &mut ImageTree, options: &ImageOptions) -> io::Result<Layout> {
+    // Path table order: breadth first, children in identifier order
+    let mut order = vec![0];
+    let mut next = 0;
+    while next < order.len() {
+        let children: Vec<usize> = tree.dirs[order[next]]
+            .entries
+            .iter()
+            .filter_map(|entry| match entry {
+                Entry::Directory(child) => Some(*child),
+                _ => None,
+            })
+            .collect();
+        order.extend(children);
+        next += 1;
+    }
+    if order.len() > u16::MAX as usize {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "too many directories for the ISO 9660 path table"));
+    }
+    for (number, &index) in order.iter().enumerate() {
+        tree.dirs[index].number = number as u16 + 1;
+    }
+
+    // Directory extent sizes don't depend on the extent locations
+    for &index in &order {
+        let (extent, continuation) = directory_extent(tree, index);
+        tree.dirs[index].size = extent.len() as u32;
+        tree.dirs[index].continuation = blocks_for(continuation.len() as u64);
+    }
+    let path_table_size = path_table(tree, &order, true).len() as u32;
+
+    // Pick the order of the file contents
+    let mut files: Vec<(usize, usize)> = order
+        .iter()
+        .flat_map(|&index| {
+            let entries = &tree.dirs[index].entries;
+            (0..entries.len()).filter(|&entry| matches!(entries[entry], Entry::File(_))).map(move |entry| (index, entry))
+        })
+        .collect();
+    let file_size = |tree: &ImageTree, (index, entry): (usize, usize)| match &tree.dirs[index].entries[entry] {
+        Entry::File(file) => file.size as u64,
+        _ => 0,
+    };
+    if options.file_order == FileOrder::Size {
+        files.sort_by_key(|&file| file_size(tree, file));
+    }
+    if let Some(small) = options.group_small {
+        files.sort_by_key(|&file| file_size(tree, file) >= small);
+    }
+    if !options.sort_weights.is_empty() {
+        files.sort_by_cached_key(|&(index, entry)| {
+            let name = entry_name(tree, &tree.dirs[index].entries[entry]);
+            let path = format!("{}{}", directory_path(tree, index), name);
+            std::cmp::Reverse(sort_weight(&options.sort_weights, &path, name))
+        });
+    }
+
+    // Generated files (TRANS.TBL and the like), then the BIOS boot file go ahead of the other
+    // files, so they can be written from memory
+    let (mut files, others): (Vec<_>, Vec<_>) = files.into_iter().partition(|&file| tree.generated(file).is_some());
+    files.extend(others);
+    let boot = match &options.boot {
+        Some(boot) => {
+            let file = boot.image.as_deref().map(|path| eltorito::find_boot_file(tree, path)).transpose()?;
+            let efi_file = boot.efi_image.as_deref().map(|path| eltorito::find_boot_file(tree, path)).transpose()?;
+            if let Some(file) = file {
+                files.retain(|&other| other != file);
+                files.insert(0, file);
+            }
+            Some(BootLayout {
+                catalog: SYSTEM_AREA_BLOCKS + descriptor_set_blocks(options),
+                file,
+                efi_file,
+                contents: Vec::new(),
+            })
+        }
+        None => None,
+    };
+
+    // DVD-Video title sets go right after the files written from memory
+    let dvd = if options.dvd_video { Some(dvdvideo::title_sets(tree)?) } else { None };
+    let front = files.iter().take_while(|&&file| tree.generated(file).is_some() || boot.as_ref().is_some_and(|boot| boot.file == Some(file))).count();
+    if let Some(sets) = &dvd {
+        let title_files = dvdvideo::files(sets);
+        files.retain(|file| !title_files.contains(file));
+        files.splice(front..front, title_files);
+    }
+    // With --dedup content, files with the same contents as one ahead of them take its extent
+    let pinned = front + dvd.as_deref().map_or(0, |sets| dvdvideo::files(sets).len());
+    let shared = if options.dedup { dedup::share(tree, &mut files, pinned) } else { Vec::new() };
+
+    // System area and descriptors (PVD, boot record, enhanced descriptor, terminator and the
+    // UDF recognition sequence) always come first, followed by the boot catalog
+    let mut next_block = SYSTEM_AREA_BLOCKS + descriptor_set_blocks(options);
+    if boot.is_some() {
+        next_block += 1;
+    }
+    // The UDF descriptors and file set follow, up to the partition the files are in
+    let udf = if options.dvd_video { Some(udf::plan(tree)?) } else { None };
+    if let Some(udf) = &udf {
+        next_block = udf::PARTITION_START + udf.blocks;
+    }
+    if options.single_pass {
+        // The contents went in as they were scanned; only the generated files are left to follow them
+        let streamed = files.iter().filter(|&&file| tree.generated(file).is_none()).filter_map(|&(index, entry)| match &tree.dirs[index].entries[entry] {
+            Entry::File(file) => Some(file.extent + blocks_for(file.size as u64)),
+            _ => None,
+        });
+        let generated: Vec<(usize, usize)> = files.iter().copied().filter(|&file| tree.generated(file).is_some()).collect();
+        next_block = place_files(tree, &generated, options.align, streamed.max().unwrap_or(next_block).max(next_block));
+        files.sort_by_key(|&(index, entry)| match &tree.dirs[index].entries[entry] {
+            Entry::File(file) => file.extent,
+            _ => 0,
+        });
+    } else if options.metadata == MetadataPlacement::Last {
+        next_block = place_contents(tree, &files, front, dvd.as_deref(), options.align, next_block);
+    }
+
+    // The two path tables, then the directory extents in path table order
+    let l_path_table = next_block;
+    let m_path_table = l_path_table + blocks_for(path_table_size as u64);
+    next_block = m_path_table + blocks_for(path_table_size as u64);
+    for &index in &order {
+        tree.dirs[index].extent = next_block;
+        next_block += blocks_for(tree.dirs[index].size as u64) + tree.dirs[index].continuation;
+    }
+
+    if options.metadata == MetadataPlacement::First {
+        next_block = place_contents(tree, &files, front, dvd.as_deref(), options.align, next_block);
+    }
+    for ((dir, entry), (original_dir, original_entry)) in shared {
+        let Entry::File(original) = &tree.dirs[original_dir].entries[original_entry] else { continue };
+        let extent = original.extent;
+        if let Entry::File(file) = &mut tree.dirs[dir].entries[entry] {
+            file.extent = extent;
+        }
+    }
+    // Appended partitions follow the padding, and the backup GPT comes after everything; a
+    // Sun disk label can only start them on a cylinder
+    let mut end = next_block + options.pad_sectors;
+    let mut appended = Vec::new();
+    let align = if options.sparc_boot.is_empty() { 1 } else { sunlabel::CYLINDER_BLOCKS };
+    for partition in appended_partitions(options) {
+        end = end.div_ceil(align) * align;
+        appended.push(end);
+        end += blocks_for(partition.size);
+    }
+    let gpt_backup = options.hybrid.as_ref().filter(|hybrid| hybrid.gpt_basdat).map(|_| end);
+
+    Ok(Layout {
+        order,
+        files,
+        path_table_size,
+        l_path_table,
+        m_path_table,
+        padding_blocks: options.pad_sectors,
+        total_blocks: end + gpt_backup.map_or(0, |_| hybrid::GPT_BACKUP_BLOCKS) + udf.is_some() as u32,
+        boot,
+        gpt_backup,
+        udf,
+        appended,
+    })
+}
+
+// Assign extents to the files in layout order, the DVD-Video title sets (which start at index
+// front) by their own rules
+fn place_contents(tree: &mut ImageTree, files: &[(usize, usize)], front: usize, dvd: Option<&[dvdvideo::TitleSet]>, align: Option<u64>, next_block: u32) -> u32 {
+    match dvd {
+        Some(sets) => {
+            let count = dvdvideo::files(sets).len();
+            let next_block = place_files(tree, &files[..front], align, next_block);
+            let next_block = dvdvideo::place(tree, sets, next_block);
+            place_files(tree, &files[front + count..], align, next_block)
+        }
+        None => place_files(tree, files, align, next_block),
+    }
+}
+
+// Assign extents to the files in the given order starting at next_block; returns the block after the last one
+fn place_files(tree: &mut ImageTree, files: &[(usize, usize)], align: Option<u64>, mut next_block: u32) -> u32 {
+    for &(index, entry) in files {
+        if let Entry::File(file) = &mut tree.dirs[index].entries[entry] {
+            if let Some(align) = align.filter(|&align| file.size as u64 >= align) {
+                let align_blocks = (align / BLOCK_SIZE as u64) as u32;
+                next_block = next_block.div_ceil(align_blocks) * align_blocks;
+            }
+            file.extent = next_block;
+            next_block += blocks_for(file.size as u64);
+        }
+    }
+    next_block
+}
+
+// Add file contents to the ISO image at their planned extent, applying the --if-changed policy
+fn add_file<W: ImageOutput>(writer: &mut W, source: &dyn SourceProvider, file: &FileEntry, options: &ImageOptions, progress: &mut Progress) -> io::Result<()> {
+    let processed_before = progress.bytes_processed;
+    let change = copy_file_contents(writer, source, file, options.mmap, progress)?;
+    settle_file(writer, source, file, options, progress, change, processed_before)?;
+    progress.file_done();
+    Ok(())
+}
+
+// Deal with a file that changed while it was copied: keep the fitted contents, read it again, or fail
+fn settle_file<W: ImageOutput>(
+    writer: &mut W,
+    source: &dyn SourceProvider,
+    file: &FileEntry,
+    options: &ImageOptions,
+    progress: &mut Progress,
+    mut change: Option<&'static str>,
+    processed_before: u64,
+) -> io::Result<()> {
+    let mut attempt = 1;
+    while let Some(kind) = change {
+        match options.if_changed {
+            IfChanged::Pad => {
+                events::warn(&format!("{} {} since it was scanned, fitted it to the planned {} bytes", file.source.display(), kind, file.size));
+                return Ok(());
+            }
+            IfChanged::Reread if attempt < REREAD_ATTEMPTS => {
+                events::warn(&format!("{} {} since it was scanned, reading it again", file.source.display(), kind));
+                attempt += 1;
+                progress.bytes_processed = processed_before;
+                writer.seek(SeekFrom::Start(file.extent as u64 * BLOCK_SIZE as u64))?;
+                change = copy_file_contents(writer, source, file, options.mmap, progress)?;
+            }
+            _ => {
+                return Err(io::Error::other(format!("{} {} since it was scanned", file.source.display(), kind)));
+            }
+        }
+    }
+    Ok(())
+}
+
+// Copy exactly the planned number of bytes of a file (zero filled if it came up short) and
+// pad to the next block; returns how the file changed compared to the scan, if it did
+fn copy_file_contents<W: ImageOutput>(
+    writer: &mut W,
+    provider: &dyn SourceProvider,
+    file: &FileEntry,
+    use_mmap: bool,
+    progress: &mut Progress,
+) -> io::Result<Option<&'static str>> {
+    let mut source = match provider.open(&file.source) {
+        Ok(source) => source,
+        Err(e) if e.kind() == ErrorKind::PermissionDenied => {
+            // The extent is already reserved, so leave it zero filled
+            events::warn(&format!("Permission denied while accessing file: {}", file.source.display()));
+            note_denied(&file.source);
+            writer.write_all(&vec![0u8; file.size as usize])?;
+            pad_to_block(writer, file.size as usize)?;
+            return Ok(None);
+        }
+        Err(e) => return Err(e),
+    };
+
+    // No bigger than the file, so small files don't each cost a whole buffer
+    let mut buffer = vec![0u8; COPY_BUFFER_SIZE.min(file.size as usize).max(1)];
+    let mut remaining = file.size as usize;
+
+    // Large local files that are at least as long as planned can be copied straight from a mapping
+    let mapping = match source.local() {
+        Some(local) if use_mmap && file.size >= MMAP_THRESHOLD && local.metadata()?.len() >= file.size as u64 => match mmap::Mapping::new(local, file.size as usize) {
+            Ok(mapping) => Some(mapping),
+            Err(e) => {
+                events::warn(&format!("Could not map {}, reading it instead: {}", file.source.display(), e));
+                None
+            }
+        },
+        _ => None,
+    };
+
+    if let (Some(mapping), Some(local)) = (mapping, source.local()) {
+        for chunk in mapping.as_slice().chunks(MMAP_CHUNK_SIZE) {
+            writer.write_all(chunk)?;
+            remaining -= chunk.len();
+            progress.advance(chunk.len())?;
+        }
+        local.seek(SeekFrom::Start(file.size as u64))?;
+    } else if let (Some(output), Some(local)) = (writer.output_file(), source.local()) {
+        // Otherwise let the kernel move the data, falling back to reading the rest if it can't
+        while remaining > 0 {
+            match zerocopy::copy_range(local, output, remaining.min(ZERO_COPY_CHUNK_SIZE)) {
+                Ok(0) => break, // The file shrank
+                Ok(copied) => {
+                    remaining -= copied;
+                    progress.advance(copied)?;
+                }
+                Err(e) if zerocopy::is_unsupported(&e) => break,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // Read and write the file contents
+    while remaining > 0 {
+        let bytes_read = source.read(&mut buffer[..remaining.min(COPY_BUFFER_SIZE)])?;
+        if bytes_read == 0 {
+            break;
+        }
+        writer.write_all(&buffer[..bytes_read])?;
+        remaining -= bytes_read;
+        progress.advance(bytes_read)?;
+    }
+    let grew = source.read(&mut buffer[..1])? > 0;
+    finish_file_contents(writer, file, remaining, grew, &source.metadata()?)
+}
+
+// Zero fill whatever part of the planned size wasn't copied, align to the next block, and
+// compare what was found with the scan
+fn finish_file_contents<W: Write>(writer: &mut W, file: &FileEntry, remaining: usize, grew: bool, metadata: &SourceMetadata) -> io::Result<Option<&'static str>> {
+    writer.write_all(&vec![0u8; remaining])?;
+    pad_to_block(writer, file.size as usize)?;
+
+    let change = if remaining > 0 {
+        Some("shrank")
+    } else if grew || metadata.len != file.size as u64 {
+        Some("grew")
+    } else if metadata.modified != file.modified {
+        Some("was modified")
+    } else {
+        None
+    };
+    Ok(change)
+}
+
+// Progress through the file contents of the image
+#[derive(Debug)]
+struct Progress {
+    bytes_processed: u64,
+    total_size: Option<u64>,    // Unknown when the contents are written as they are scanned (--single-pass)
+    files_done: u64,
+    total_files: Option<u64>,   // Unknown likewise
+    throttle: Option<Throttle>, // Set with --bwlimit
+    checkpoint: Option<Checkpoint>, // Set with --checkpoint-every or --status-file
+    reported: Instant,              // When the bytes were last printed without a total
+}
+
+impl Progress {
+    // The progress in hundredths of a percent (or in MiB without a total), for --json-events
+    // to report each change once
+    fn hundredths(&self) -> u64 {
+        match self.total_size {
+            Some(total) => (self.bytes_processed as u128 * 10_000 / total.max(1) as u128) as u64,
+            None => self.bytes_processed >> 20,
+        }
+    }
+
+    // Update the overall progress after writing some file bytes, printing it to stderr (so it
+    // stays out of an image written to stdout) each whole percent, or each second without a
+    // total; fails once the build is cancelled
+    fn advance(&mut self, bytes_written: usize) -> io::Result<()> {
+        cancel::check()?;
+        let before = self.hundredths();
+        self.bytes_processed += bytes_written as u64;
+        match self.total_size {
+            Some(total) => {
+                let progress = (self.bytes_processed as f64 / total as f64) * 100.0;
+                if !events::enabled() {
+                    if self.hundredths() / 100 != before / 100 {
+                        eprintln!("Progress: {}%", self.hundredths() / 100);
+                    }
+                } else if self.hundredths() != before {
+                    events::emit(
+                        "percent",
+                        &[("percent", format!("{:.2}", progress)), ("bytes", self.bytes_processed.to_string()), ("total", total.to_string())],
+                    );
+                }
+            }
+            None if !events::enabled() && self.reported.elapsed() >= Duration::from_secs(1) => {
+                eprintln!("Progress: {} bytes", self.bytes_processed);
+                self.reported = Instant::now();
+            }
+            None if !events::enabled() => {}
+            None if self.hundredths() != before => events::emit("written", &[("bytes", self.bytes_processed.to_string())]),
+            None => {}
+        }
+        if let Some(throttle) = &mut self.throttle {
+            throttle.pace(bytes_written);
+        }
+        if let Some(checkpoint) = &mut self.checkpoint {
+            checkpoint.tick((self.files_done, self.total_files), (self.bytes_processed, self.total_size));
+        }
+        Ok(())
+    }
+
+    // Count a file whose contents are all in the image
+    fn file_done(&mut self) {
+        self.files_done += 1;
+    }
+}
+
+// Total number of file bytes planned for the image, each shared extent counted once
+fn planned_file_size(tree: &ImageTree, layout: &Layout) -> u64 {
+    layout
+        .files
+        .iter()
+        .map(|&(index, entry)| match &tree.dirs[index].entries[entry] {
+            Entry::File(file) => file.size as u64,
+            _ => 0,
+        })
+        .sum()
+}
+
+// Compare the planned image size with the capacity of the selected medium
+fn check_media_capacity(media: &Media, total_blocks: u32) -> io::Result<()> {
+    let image_size = total_blocks as u64 * BLOCK_SIZE as u64;
+    if image_size > media.capacity {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "the image needs {} bytes but {} only holds {} bytes ({} bytes too many)",
+                image_size,
+                media.name,
+                media.capacity,
+                image_size - media.capacity
+            ),
+        ));
+    }
+
+    let used = image_size as f64 / media.capacity as f64 * 100.0;
+    events::info(&format!("Image uses {} of {} bytes on {} ({:.2}%)", image_size, media.capacity, media.name, used));
+    if used > 99.0 {
+        events::warn(&format!("Warning: the image leaves less than 1% of {} free, some drives may fail to burn it", media.name));
+    }
+    Ok(())
+}
+
+// Parse a --media value: a standard medium name or custom:<bytes>
+fn parse_media(text: &str) -> io::Result<Media> {
+    if let Some(size) = text.strip_prefix("custom:") {
+        return Ok(Media {
+            name: text.to_string(),
+            capacity: parse_size(size)?,
+        });
+    }
+
+    match STANDARD_MEDIA.iter().find(|(name, _)| *name == text) {
+        Some(&(name, sectors)) => Ok(Media {
+            name: name.to_string(),
+            capacity: sectors * BLOCK_SIZE as u64,
+        }),
+        None => Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("unknown --media: {} (expected cd74, cd80, dvd5, dvd9, bd25, bd50 or custom:<bytes>)", text),
+        )),
+    }
+}
+
+// Write the path tables and the directory extents
+fn write_metadata<W: Write + Seek>(writer: &mut W, tree: &ImageTree, layout: &Layout) -> io::Result<()> {
+    fill_to_block(writer, layout.l_path_table)?;
+    for little_endian in [true, false] {
+        let table = path_table(tree, &layout.order, little_endian);
+        writer.write_all(&table)?;
+        pad_to_block(writer, table.len())?;
+    }
+
+    for &index in &layout.order {
+        let (extent, continuation) = directory_extent(tree, index);
+        writer.write_all(&extent)?;
+        writer.write_all(&continuation)?;
+    }
+    Ok(())
+}
+
+// Write zeros up to the start of the given block, filling gaps left by alignment
+fn fill_to_block<W: Write + Seek>(writer: &mut W, block: u32) -> io::Result<()> {
+    let position = writer.stream_position()?;
+    let start = block as u64 * BLOCK_SIZE as u64;
+    if position < start {
+        io::copy(&mut io::repeat(0).take(start - position), writer)?;
+    }
+    Ok(())
+}
+
+// Path of a directory in the image hierarchy, e.g. "/a/b/"
+fn directory_path(tree: &ImageTree, mut index: usize) -> String {
+    let mut names = Vec::new();
+    while index != 0 {
+        names.push(tree.dirs[index].name.as_str());
+        index = tree.dirs[index].parent;
+    }
+    names.reverse();
+    names.iter().map(|name| format!("/{}", name)).collect::<String>() + "/"
+}
+
+// The name a source file is given in the image: decoded from --input-charset, in the --normalize form
+fn source_name(name: &OsStr, options: &ImageOptions) -> String {
+    let name = options.input_charset.decode_os(name);
+    match unicode::normalize(&name, options.normalize) {
+        Cow::Owned(normalized) => normalized,
+        Cow::Borrowed(_) => name,
+    }
+}
+
+// Path of a directory where it is in the source, e.g. "/a/b/", following relocated directories
+// back to their original parents
+fn original_path(tree: &ImageTree, mut index: usize) -> String {
+    let mut names = Vec::new();
+    while index != 0 {
+        names.push(tree.dirs[index].name.as_str());
+        index = tree.dirs[index].original_parent.unwrap_or(tree.dirs[index].parent);
+    }
+    names.reverse();
+    names.iter().map(|name| format!("/{}", name)).collect::<String>() + "/"
+}
+
+// Write the block map: every area of the image with its first block, length in blocks and
+// contents, in the --block-map-format; a bmap reads the finished image back from output
+fn write_block_map(path: &Path, tree: &ImageTree, layout: &Layout, options: &ImageOptions, output: &Path) -> io::Result<()> {
+    let boot_blocks = if layout.boot.is_some() { 1 } else { 0 };
+    let mut areas = vec![
+        Area::new(0, SYSTEM_AREA_BLOCKS, "(system area)".to_string()),
+        Area::new(SYSTEM_AREA_BLOCKS, 1, "(primary volume descriptor)".to_string()),
+        Area::new(SYSTEM_AREA_BLOCKS + 1 + boot_blocks + tree.relaxed as u32, 1, "(volume descriptor set terminator)".to_string()),
+        Area::new(layout.l_path_table, blocks_for(layout.path_table_size as u64), "(path table, little-endian)".to_string()),
+        Area::new(layout.m_path_table, blocks_for(layout.path_table_size as u64), "(path table, big-endian)".to_string()),
+    ];
+    if let Some(boot) = &layout.boot {
+        areas.push(Area::new(SYSTEM_AREA_BLOCKS + 1, 1, "(el torito boot record)".to_string()));
+        areas.push(Area::new(boot.catalog, 1, "(boot catalog)".to_string()));
+    }
+    if tree.relaxed {
+        areas.push(Area::new(SYSTEM_AREA_BLOCKS + 1 + boot_blocks, 1, "(enhanced volume descriptor)".to_string()));
+    }
+    if let Some(gpt_backup) = layout.gpt_backup {
+        areas.push(Area::new(gpt_backup, hybrid::GPT_BACKUP_BLOCKS, "(backup gpt)".to_string()));
+    }
+    let anchors = layout.udf.is_some() as u32; // The second UDF anchor comes after the padding
+    if let Some(udf) = &layout.udf {
+        let recognition = SYSTEM_AREA_BLOCKS + 2 + boot_blocks + tree.relaxed as u32;
+        areas.push(Area::new(recognition, udf::RECOGNITION_BLOCKS, "(udf volume recognition sequence)".to_string()));
+        areas.push(Area::new(udf::MAIN_SEQUENCE, udf::ANCHOR - udf::MAIN_SEQUENCE, "(udf volume descriptors)".to_string()));
+        areas.push(Area::new(udf::ANCHOR, 1, "(udf anchor)".to_string()));
+        areas.push(Area::new(udf::PARTITION_START, udf.blocks, "(udf file set)".to_string()));
+        areas.push(Area::new(layout.total_blocks - 1, 1, "(udf anchor)".to_string()));
+    }
+    for &index in &layout.order {
+        let dir = &tree.dirs[index];
+        areas.push(Area::new(dir.extent, blocks_for(dir.size as u64) + dir.continuation, directory_path(tree, index)));
+    }
+    let tail = layout.gpt_backup.unwrap_or(layout.total_blocks - anchors);
+    for (index, &start) in layout.appended.iter().enumerate() {
+        let end = layout.appended.get(index + 1).copied().unwrap_or(tail);
+        areas.push(Area::new(start, end - start, format!("(appended partition {})", index + 1)));
+    }
+    if layout.padding_blocks > 0 {
+        let volume_end = layout.appended.first().copied().unwrap_or(tail);
+        areas.push(Area::new(volume_end - layout.padding_blocks, layout.padding_blocks, "(padding)".to_string()));
+    }
+    for &(index, entry) in &layout.files {
+        if let Entry::File(file) = &tree.dirs[index].entries[entry] {
+            areas.push(Area::new(file.extent, blocks_for(file.size as u64), format!("{}{}", directory_path(tree, index), file.name)));
+        }
+    }
+    areas.sort_by_key(|area| area.start);
+
+    let mut image = match options.block_map_format {
+        blockmap::MapFormat::Bmap => Some(File::open(output)?),
+        _ => None,
+    };
+    let mut map = io::BufWriter::new(File::create(path)?);
+    blockmap::write_map(&mut map, &areas, layout.total_blocks, options.block_map_format, image.as_mut().map(|image| image as &mut dyn ReadAt))?;
+    map.flush()
+}
+
+// An empty image tree with the creation time the options ask for
+fn new_tree(options: &ImageOptions) -> ImageTree {
+    ImageTree {
+        dirs: Vec::new(),
+        relocation_dir: None,
+        rock_ridge: false,
+        image_time: options.source_date_epoch.unwrap_or_else(|| unix_time(SystemTime::now())),
+        fixed_times: options.reproducible,
+        relaxed: options.enhanced,
+        charset: options.output_charset,
+        dos_names: HashMap::new(),
+        generated: HashMap::new(),
+        record_flags: HashMap::new(),
+        finder_info: HashMap::new(),
+        posix: HashMap::new(),
+        ifo_headers: HashMap::new(),
+        digests: HashMap::new(),
+        xattrs: Vec::new(),
+        win_metadata: Vec::new(),
+        outputs: Vec::new(),
+        scanned: ScanProgress { directories: 0, files: 0, bytes: 0, reported: Instant::now() },
+    }
+}
+
+// Sort and lay out a scanned tree, making sure it fits before anything is written
+fn plan_image(tree: &mut ImageTree, options: &ImageOptions) -> io::Result<Layout> {
+    if options.embed_metadata == Some(true) {
+        embedded::add(tree, options)?;
+    }
+    if !tree.xattrs.is_empty() {
+        xattr::add_file(tree);
+    }
+    if options.win_metadata {
+        winmeta::add_file(tree);
+    }
+    if options.trans_tbl {
+        transtbl::translate(tree);
+    }
+    if tree.relocation_dir.is_some() || tree.rock_ridge {
+        shorten_identifiers(tree);
+    }
+    mark_entries(tree, options);
+    sort_entries(tree)?;
+    let layout = layout_image(tree, options)?;
+    if let Some(moved) = tree.relocation_dir {
+        events::info(&format!("Relocated {} deep directories under /{}", tree.dirs[moved].entries.len(), RELOCATION_DIRECTORY));
+    }
+
+    // Refuse to start an image that won't fit on the selected medium
+    if let Some(media) = &options.media {
+        check_media_capacity(media, layout.total_blocks)?;
+    }
+    Ok(layout)
+}
+
+// Write a laid out image to the sink; write_contents puts the contents of the files (in layout
+// order) at their extents
+fn write_image<S, F>(tree: &ImageTree, layout: &Layout, mut iso_file: S, output: &Path, options: &ImageOptions, write_contents: F) -> io::Result<()>
+where
+    S: ImageSink,
+    F: FnOnce(&mut S, &[&FileEntry], &mut Progress) -> io::Result<()>,
+{
+    // The sizes recorded by the scan are what the layout reserved, so progress is measured against them
+    let total_size = planned_file_size(tree, layout);
+    if events::enabled() {
+        events::emit("write-started", &[("bytes", total_size.to_string())]);
+    } else {
+        println!("Total size to process: {} bytes", total_size);
+    }
+
+    // The system area is empty unless it holds the partition tables of a hybrid image, a Sun
+    // disk label or what --system-area gave, which the tables are written over
+    match &options.hybrid {
+        Some(hybrid) => iso_file.write_all(&hybrid::system_area(tree, layout, options, hybrid))?,
+        None if !options.sparc_boot.is_empty() => iso_file.write_all(&sunlabel::system_area(layout, options))?,
+        None => iso_file.write_all(&base_system_area(options))?,
+    }
+
+    write_volume_descriptors(&mut iso_file, tree, layout, options)?;
+    let boot_file = layout.boot.as_ref().and_then(|boot| boot.file.map(|file| (boot, eltorito::boot_file(tree, file))));
+    if let Some(boot) = &layout.boot {
+        let efi_file = boot.efi_file.map(|file| eltorito::boot_file(tree, file));
+        let load_size = options.boot.as_ref().and_then(|boot| boot.load_size);
+        iso_file.write_all(&eltorito::boot_catalog(boot_file.map(|(_, file)| file), efi_file, load_size))?;
+    }
+    if let Some(udf) = &layout.udf {
+        fill_to_block(&mut iso_file, udf::MAIN_SEQUENCE)?;
+        udf::write_area(&mut iso_file, tree, layout, udf, &options.identity)?;
+    }
+
+    if options.metadata == MetadataPlacement::First {
+        write_metadata(&mut iso_file, tree, layout)?;
+    }
+
+    let mut progress = Progress {
+        bytes_processed: 0,
+        total_size: Some(total_size),
+        files_done: 0,
+        total_files: Some(layout.files.len() as u64),
+        throttle: options.bwlimit.map(Throttle::new),
+        checkpoint: Checkpoint::new(options),
+        reported: Instant::now(),
+    };
+
+    // The patched boot file and the generated files come first, then everything else as it's read
+    let mut generated: Vec<(&FileEntry, &[u8])> = boot_file.map(|(boot, file)| (file, boot.contents.as_slice())).into_iter().collect();
+    for &(index, entry) in &layout.files {
+        if let (Some(contents), Entry::File(file)) = (tree.generated((index, entry)), &tree.dirs[index].entries[entry]) {
+            generated.push((file, contents));
+        }
+    }
+    for (file, contents) in generated {
+        fill_to_block(&mut iso_file, file.extent)?;
+        iso_file.write_all(contents)?;
+        pad_to_block(&mut iso_file, contents.len())?;
+        progress.file_done();
+        progress.advance(contents.len())?;
+    }
+    let files: Vec<&FileEntry> = layout
+        .files
+        .iter()
+        .filter(|&&file| layout.boot.as_ref().is_none_or(|boot| boot.file != Some(file)) && tree.generated(file).is_none())
+        .filter_map(|&(index, entry)| match &tree.dirs[index].entries[entry] {
+            Entry::File(file) => Some(file),
+            _ => None,
+        })
+        .collect();
+    write_contents(&mut iso_file, &files, &mut progress)?;
+
+    if options.metadata == MetadataPlacement::Last {
+        write_metadata(&mut iso_file, tree, layout)?;
+    }
+
+    // Zero fill the tail padding (and anything else up to the planned volume size)
+    for (partition, &block) in appended_partitions(options).iter().zip(&layout.appended) {
+        fill_to_block(&mut iso_file, block)?;
+        hybrid::write_appended(&mut iso_file, partition)?;
+    }
+    if let (Some(gpt_backup), Some(hybrid)) = (layout.gpt_backup, &options.hybrid) {
+        fill_to_block(&mut iso_file, gpt_backup)?;
+        iso_file.write_all(&hybrid::gpt_backup(tree, layout, options, hybrid))?;
+    }
+    if layout.udf.is_some() {
+        fill_to_block(&mut iso_file, layout.total_blocks - 1)?;
+        iso_file.write_all(&udf::anchor(layout.total_blocks - 1))?;
+    }
+    fill_to_block(&mut iso_file, layout.total_blocks)?;
+    iso_file.finish()?;
+
+    if let Some(block_map) = &options.block_map {
+        write_block_map(block_map, tree, layout, options, output)?;
+    }
+
+    if !events::enabled() {
+        println!("ISO creation complete.");
+    }
+    Ok(())
+}
+
+// The partition images after the volume, from --append-partition and --prep-boot or --sparc-boot
+fn appended_partitions(options: &ImageOptions) -> &[AppendedPartition] {
+    options.hybrid.as_ref().map_or(&options.sparc_boot, |hybrid| &hybrid.appended)
+}
+
+// Blocks 0-15 before any partition tables: what --system-area gave, or zeros
+fn base_system_area(options: &ImageOptions) -> Vec<u8> {
+    options.system_area.clone().unwrap_or_else(|| vec![0u8; SYSTEM_AREA_BLOCKS as usize * BLOCK_SIZE])
+}
+
+// The --system-area file: up to 32 KiB taken as it is, or an ISO 9660 image whose system area
+// is kept, as when remastering it
+fn read_system_area(path: &str) -> io::Result<Vec<u8>> {
+    let size = SYSTEM_AREA_BLOCKS as usize * BLOCK_SIZE;
+    let mut file = File::open(path)?;
+    let mut area = Vec::new();
+    (&mut file).take(size as u64 + 1).read_to_end(&mut area)?;
+    if area.len() > size {
+        let mut descriptor = [0u8; 6];
+        file.seek(SeekFrom::Start(size as u64))?;
+        if file.read_exact(&mut descriptor).is_err() || &descriptor[1..] != CD001 {
+            return Err(io::Error::new(ErrorKind::InvalidInput, format!("{} is larger than the 32 KiB system area and isn't an ISO 9660 image", path)));
+        }
+    }
+    area.resize(size, 0);
+    Ok(area)
+}
+
+// Blocks of the volume descriptor set: the PVD, the boot record and the enhanced volume
+// descriptor when there are any, and the terminator, then the UDF recognition sequence
+fn descriptor_set_blocks(options: &ImageOptions) -> u32 {
+    2 + options.boot.is_some() as u32 + options.enhanced as u32 + if options.dvd_video { udf::RECOGNITION_BLOCKS } else { 0 }
+}
+
+// Write the Primary Volume Descriptor (PVD), the boot record (El Torito wants it in block 17),
+// the enhanced volume descriptor and the terminator
+fn write_volume_descriptors<W: Write>(iso_file: &mut W, tree: &ImageTree, layout: &Layout, options: &ImageOptions) -> io::Result<()> {
+    let root = &tree.dirs[0];
+    let root_record = directory_record(&[0x00], root.extent, root.size, tree.record_time(root.modified), true, &[]);
+    let primary = primary_volume_descriptor(layout, &root_record, tree.image_time, &options.identity);
+    iso_file.write_all(&primary)?;
+    if let Some(boot) = &layout.boot {
+        iso_file.write_all(&eltorito::boot_record(boot.catalog))?;
+    }
+    if options.enhanced {
+        // ISO 9660:1999: a supplementary descriptor of version 2 for the same hierarchy, whose
+        // names readers take as they are
+        let mut enhanced = primary;
+        enhanced[0] = SUPPLEMENTARY_VOLUME_DESCRIPTOR;
+        enhanced[6] = 2;
+        enhanced[881] = 2;
+        iso_file.write_all(&enhanced)?;
+    }
+    write_volume_descriptor_terminator(iso_file)?;
+    if layout.udf.is_some() {
+        iso_file.write_all(&udf::recognition_sequence())?;
+    }
+    Ok(())
+}
+
+// --single-pass: write each file's contents as the scan finds it, lay out and write the
+// generated files and the metadata after them, then go back for the volume descriptors.
+// Nothing is known about the total up front, so progress is reported in bytes.
+fn stream_image(tree: &mut ImageTree, source: &dyn SourceProvider, sources: &[PathBuf], iso_file_path: &Path, output: &Path, options: &ImageOptions) -> io::Result<()> {
+    preallocate::check_destination(output, 0, sources)?;
+    let mut iso_file = backend::create_sink(output, 0)?;
+    if !options.output_hash.is_empty() {
+        iso_file = Box::new(HashingSink::new(iso_file, iso_file_path, output, &options.output_hash));
+    }
+    if options.pipeline_depth > 0 {
+        iso_file = Box::new(WriteBehind::new(iso_file, options.pipeline_depth)?);
+    }
+    if events::enabled() {
+        events::emit("write-started", &[("bytes", "null".to_string())]);
+    }
+
+    // The descriptors are zero until the end
+    let mut next_block = SYSTEM_AREA_BLOCKS + descriptor_set_blocks(options);
+    iso_file.write_all(&base_system_area(options))?;
+    fill_to_block(&mut iso_file, next_block)?;
+    let mut progress = Progress {
+        bytes_processed: 0,
+        total_size: None,
+        files_done: 0,
+        total_files: None,
+        throttle: options.bwlimit.map(Throttle::new),
+        checkpoint: Checkpoint::new(options),
+        reported: Instant::now(),
+    };
+    scan_sources_with(tree, source, sources, options, &mut |file| {
+        if let Some(align) = options.align.filter(|&align| file.size as u64 >= align) {
+            let align_blocks = (align / BLOCK_SIZE as u64) as u32;
+            next_block = next_block.div_ceil(align_blocks) * align_blocks;
+        }
+        file.extent = next_block;
+        fill_to_block(&mut iso_file, next_block)?;
+        add_file(&mut iso_file, source, file, options, &mut progress)?;
+        next_block += blocks_for(file.size as u64);
+        Ok(())
+    })?;
+
+    let layout = plan_image(tree, options)?;
+    for &(index, entry) in &layout.files {
+        if let (Some(contents), Entry::File(file)) = (tree.generated((index, entry)), &tree.dirs[index].entries[entry]) {
+            fill_to_block(&mut iso_file, file.extent)?;
+            iso_file.write_all(contents)?;
+            pad_to_block(&mut iso_file, contents.len())?;
+            progress.file_done();
+            progress.advance(contents.len())?;
+        }
+    }
+    write_metadata(&mut iso_file, tree, &layout)?;
+    fill_to_block(&mut iso_file, layout.total_blocks)?;
+    iso_file.seek(SeekFrom::Start(SYSTEM_AREA_BLOCKS as u64 * BLOCK_SIZE as u64))?;
+    write_volume_descriptors(&mut iso_file, tree, &layout, options)?;
+    iso_file.seek(SeekFrom::Start(layout.total_blocks as u64 * BLOCK_SIZE as u64))?;
+    iso_file.finish()?;
+
+    if let Some(block_map) = &options.block_map {
+        write_block_map(block_map, tree, &layout, options, output)?;
+    }
+    if !events::enabled() {
+        println!("ISO creation complete.");
+    }
+    Ok(())
+}
+
+// The provider for the sources given on the command line, which all have to be in one place
+fn open_sources(sources: &[PathBuf], options: &ImageOptions) -> io::Result<Box<dyn SourceProvider>> {
+    if sources.len() > 1 && sources.iter().any(|path| backend::is_remote(path)) {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "remote sources can't be combined with other sources"));
+    }
+    if let Some(how) = &options.elevate {
+        if backend::is_remote(&sources[0]) {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "--elevate is for local sources"));
+        }
+        return elevate::elevated_source(how, sources);
+    }
+    backend::open_source(&sources[0])
+}
+
+// Create the ISO from the given source directory with progress tracking and error handling;
+// the bytes go to output, a temporary name for iso_file_path until it is complete
+fn create_iso(sources: &[PathBuf], iso_file_path: &Path, output: &Path, options: &ImageOptions) -> io::Result<()> {
+    create_iso_from(open_sources(sources, options)?.as_ref(), sources, iso_file_path, output, options)
+}
+
+// The same with the sources read through the given provider
+fn create_iso_from(source: &dyn SourceProvider, sources: &[PathBuf], iso_file_path: &Path, output: &Path, options: &ImageOptions) -> io::Result<()> {
+    // Passphrases are asked for before the scan rather than in the middle of the run
+    let encryptor = if options.encrypt.is_empty() { None } else { Some(age::Encryptor::new(&options.encrypt)?) };
+
+    // Build the directory tree and lay it out before anything is written
+    let mut tree = new_tree(options);
+    tree.outputs = find_outputs(iso_file_path, output);
+    if events::enabled() {
+        let names: Vec<String> = sources.iter().map(|source| json::string(&source.display().to_string())).collect();
+        events::emit("scan-started", &[("sources", format!("[{}]", names.join(", ")))]);
+    }
+    if options.single_pass {
+        stream_image(&mut tree, source, sources, iso_file_path, output, options)?;
+        return if options.dedup_report { dedup::report(&tree, source) } else { Ok(()) };
+    }
+    scan_sources(&mut tree, source, sources, options)?;
+    let mut layout = plan_image(&mut tree, options)?;
+    if let Some(boot) = &options.boot {
+        eltorito::load_boot_image(&tree, &mut layout, source, boot)?;
+    }
+    let size = layout.total_blocks as u64 * BLOCK_SIZE as u64;
+    let size = encryptor.as_ref().map_or(size, |encryptor| encryptor.encrypted_size(size));
+    preallocate::check_destination(output, room_needed(size, options), sources)?;
+    let mut sink = backend::create_sink(output, size)?;
+    // The upload gets the bytes of the file, encrypted or not, as they are written
+    if let Some(upload) = &options.upload {
+        sink = Box::new(backend::TeeSink::new(sink, backend::create_sink(upload, size)?));
+    }
+    // What is published (and uploaded) is the encrypted file, so that is what gets hashed
+    if !options.output_hash.is_empty() {
+        sink = Box::new(HashingSink::new(sink, iso_file_path, output, &options.output_hash));
+    }
+    if let Some(encryptor) = encryptor {
+        sink = Box::new(age::EncryptingSink::new(sink, &encryptor)?);
+    }
+    if options.pipeline_depth > 0 {
+        sink = Box::new(WriteBehind::new(sink, options.pipeline_depth)?);
+    }
+
+    // Write the file contents, optionally reading ahead on worker threads
+    write_image(&tree, &layout, sink, output, options, |iso_file, files, progress| {
+        if options.read_workers > 0 {
+            return prefetch::write_files(iso_file, source, files, options, progress);
+        }
+        for file in files {
+            fill_to_block(iso_file, file.extent)?;
+            add_file(iso_file, source, file, options, progress)?;
+        }
+        Ok(())
+    })?;
+    if options.dedup_report {
+        dedup::report(&tree, source)?;
+    }
+    Ok(())
+}
+
+// Command-line options; paths that are not given on the command line are prompted for
+#[derive(Debug)]
+struct Options {
+    sources: Vec<PathBuf>, // Source directories and files; the first positional arguments
+    iso_path: Option<PathBuf>,
+    image: ImageOptions,
+}
+
+// Parse the command-line arguments: [options] [source ...] [iso_path]; with a single path it's the source
+fn parse_args(args: &[String]) -> io::Result<Options> {
+    // Defaults from the configuration file go first, so the command line overrides them
+    let (args, defaults) = config::load(args)?;
+    let args = [defaults.args, args].concat();
+    let mut options = Options {
+        sources: Vec::new(),
+        iso_path: None,
+        image: ImageOptions {
+            deep_dirs: DeepDirPolicy::Error,
+            if_changed: IfChanged::Pad,
+            mmap: false,
+            read_workers: 0,
+            pipeline_depth: 0,
+            file_order: FileOrder::Directory,
+            group_small: None,
+            sort_weights: Vec::new(),
+            dedup: false,
+            dedup_report: false,
+            checksums: false,
+            embed_metadata: None,
+            run_profile: None,
+            align: None,
+            metadata: MetadataPlacement::First,
+            block_map: None,
+            block_map_format: blockmap::MapFormat::Text,
+            media: None,
+            pad_sectors: 0,
+            source_date_epoch: None,
+            reproducible: false,
+            identity: VolumeIdentity {
+                volume_id: "RUST_ISO_VOLUME".to_string(),
+                preparer: None,
+                application_use: None,
+                uuid: None,
+            },
+            input_charset: Charset::Utf8,
+            normalize: unicode::Normalization::None,
+            output_charset: Charset::Utf8,
+            trans_tbl: false,
+            enhanced: false,
+            exclude: Vec::new(),
+            use_gitignore: false,
+            stdin_name: None,
+            symlinks: SymlinkPolicy::Follow,
+            max_file_size: None,
+            min_mtime: None,
+            max_mtime: None,
+            types: Vec::new(),
+            hidden: Vec::new(),
+            associated: Vec::new(),
+            xattrs: false,
+            win_metadata: false,
+            apple: false,
+            dvd_video: false,
+            bwlimit: None,
+            io_priority: None,
+            output_hash: Vec::new(),
+            sign: None,
+            zsync: None,
+            upload: None,
+            catalog_db: None,
+            pre_hook: None,
+            post_hook: None,
+            elevate: None,
+            report_denied: None,
+            checkpoint_every: None,
+            status_file: None,
+            force: false,
+            keep_partial: false,
+            one_file_system: false,
+            single_pass: false,
+            max_depth: DEFAULT_MAX_DEPTH,
+            encrypt: Vec::new(),
+            encrypt_glob: Vec::new(),
+            boot: None,
+            hybrid: None,
+            system_area: None,
+            sparc_boot: Vec::new(),
+        },
+    };
+    let (mut boot_image, mut efi_image, mut boot_load_size, mut boot_info_table, mut grub2_boot_info) = (None, None, None, false, false);
+    let (mut hybrid_mbr, mut gpt_basdat, mut apm_hfsplus) = (None, false, false);
+    let (mut appended, mut chrp) = (Vec::new(), false);
+    let mut positional = Vec::new();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) if arg.starts_with("--") => (flag, Some(value.to_string())),
+            _ => (arg.as_str(), None),
+        };
+        let mut value = || {
+            inline_value
+                .clone()
+                .or_else(|| args.next().cloned())
+                .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, format!("{} requires a value", flag)))
+        };
+
+        match flag {
+            "--mmap" => options.image.mmap = true,
+            "--read-workers" => {
+                let workers = value()?;
+                options.image.read_workers = workers
+                    .parse()
+                    .map_err(|_| io::Error::new(ErrorKind::InvalidInput, format!("invalid --read-workers count: {}", workers)))?;
+            }
+            "--pipeline-depth" => {
+                let depth = value()?;
+                options.image.pipeline_depth = depth
+                    .parse()
+                    .ok()
+                    .filter(|&depth| depth > 0)
+                    .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, format!("invalid --pipeline-depth: {} (expected a number of buffers from 1)", depth)))?;
+            }
+            "--file-order" => {
+                options.image.file_order = match value()?.as_str() {
+                    "directory" => FileOrder::Directory,
+                    "size" => FileOrder::Size,
+                    other => {
+                        return Err(io::Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("unknown --file-order: {} (expected directory or size)", other),
+                        ))
+                    }
+                };
+            }
+            "--group-small" => options.image.group_small = Some(parse_size(&value()?)?),
+            "--dedup" => {
+                options.image.dedup = match value()?.as_str() {
+                    "content" => true,
+                    "none" => false,
+                    other => return Err(io::Error::new(ErrorKind::InvalidInput, format!("unknown --dedup: {} (expected content or none)", other))),
+                };
+            }
+            "--dedup-report" => options.image.dedup_report = true,
+            "--checksums" => options.image.checksums = true,
+            "--embed-metadata" => options.image.embed_metadata = Some(true),
+            "--no-embed-metadata" => options.image.embed_metadata = Some(false),
+            "--sort-weight" => options.image.sort_weights.push(parse_sort_weight(&value()?, '=')?),
+            "--sort-file" => {
+                // mkisofs' -sort format: a pattern and its weight per line
+                let path = value()?;
+                for line in fs::read_to_string(&path)?.lines().map(|line| line.trim().replace('\t', " ")).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+                    options.image.sort_weights.push(parse_sort_weight(&line, ' ').map_err(|error| io::Error::new(error.kind(), format!("{}: {}", path, error)))?);
+                }
+            }
+            "--align" => {
+                let align = parse_size(&value()?)?;
+                if align == 0 || align % BLOCK_SIZE as u64 != 0 {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("--align must be a multiple of the {} byte block size", BLOCK_SIZE),
+                    ));
+                }
+                options.image.align = Some(align);
+            }
+            "--metadata" => {
+                options.image.metadata = match value()?.as_str() {
+                    "first" => MetadataPlacement::First,
+                    "last" => MetadataPlacement::Last,
+                    other => {
+                        return Err(io::Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("unknown --metadata placement: {} (expected first or last)", other),
+                        ))
+                    }
+                };
+            }
+            "--block-map" => options.image.block_map = Some(PathBuf::from(value()?)),
+            "--block-map-format" => options.image.block_map_format = blockmap::MapFormat::parse(&value()?)?,
+            "--media" => options.image.media = Some(parse_media(&value()?)?),
+            "--source-date-epoch" => {
+                let epoch = value()?;
+                options.image.source_date_epoch = Some(
+                    epoch
+                        .parse()
+                        .map_err(|_| io::Error::new(ErrorKind::InvalidInput, format!("invalid --source-date-epoch: {}", epoch)))?,
+                );
+            }
+            "--reproducible" => options.image.reproducible = true,
+            "--json-events" => events::enable(),
+            "--force" => options.image.force = true,
+            "--atomic" => {} // Images always go through <iso>.part now; kept so existing scripts work
+            "--keep-partial" => options.image.keep_partial = true,
+            "--single-pass" => options.image.single_pass = true,
+            "--max-depth" => {
+                let depth = value()?;
+                options.image.max_depth = depth.parse().map_err(|_| io::Error::new(ErrorKind::InvalidInput, format!("invalid --max-depth: {}", depth)))?;
+            }
+            "--volume-id" => options.image.identity.volume_id = identifier_value(flag, value()?, 32)?,
+            "--preparer" => options.image.identity.preparer = Some(identifier_value(flag, value()?, 128)?),
+            "--application-use" => {
+                // Either literal text or @file for binary contents
+                let text = value()?;
+                let contents = match text.strip_prefix('@') {
+                    Some(path) => fs::read(path)?,
+                    None => text.into_bytes(),
+                };
+                if contents.len() > 512 {
+                    return Err(io::Error::new(ErrorKind::InvalidInput, "--application-use holds at most 512 bytes"));
+                }
+                options.image.identity.application_use = Some(contents);
+            }
+            "--uuid" => {
+                let text = value()?;
+                let digits: Vec<u8> = text.bytes().filter(|&byte| byte != b'-').collect();
+                let uuid: [u8; 16] = digits
+                    .try_into()
+                    .ok()
+                    .filter(|digits: &[u8; 16]| digits.iter().all(u8::is_ascii_digit))
+                    .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, format!("invalid --uuid: {} (expected YYYY-MM-DD-HH-MM-SS-CC)", text)))?;
+                options.image.identity.uuid = Some(uuid);
+            }
+            "--input-charset" => options.image.input_charset = Charset::parse(&value()?)?,
+            "--normalize" => options.image.normalize = unicode::Normalization::parse(&value()?)?,
+            "--output-charset" => options.image.output_charset = Charset::parse(&value()?)?,
+            "--trans-tbl" => options.image.trans_tbl = true,
+            "--iso-level" => {
+                let level = value()?;
+                if level != "4" {
+                    return Err(io::Error::new(ErrorKind::InvalidInput, format!("unsupported --iso-level: {} (only 4, ISO 9660:1999, can be chosen)", level)));
+                }
+                options.image.enhanced = true;
+            }
+            "--exclude" => options.image.exclude.push(value()?),
+            "--use-gitignore" => options.image.use_gitignore = true,
+            "--stdin-name" => options.image.stdin_name = Some(value()?),
+            "--symlinks" => {
+                options.image.symlinks = match value()?.as_str() {
+                    "follow" => SymlinkPolicy::Follow,
+                    "skip" => SymlinkPolicy::Skip,
+                    "store" => SymlinkPolicy::Store,
+                    other => return Err(io::Error::new(ErrorKind::InvalidInput, format!("invalid --symlinks {} (expected follow, skip or store)", other))),
+                }
+            }
+            "--max-file-size" => options.image.max_file_size = Some(parse_size(&value()?)?),
+            "--min-mtime" => options.image.min_mtime = Some(parse_date(&value()?)?),
+            "--max-mtime" => options.image.max_mtime = Some(parse_date(&value()?)?),
+            "--type" => {
+                for kind in value()?.split(',') {
+                    match kind {
+                        "f" | "d" | "l" => options.image.types.extend(kind.chars()),
+                        _ => return Err(io::Error::new(ErrorKind::InvalidInput, format!("invalid --type {} (expected f, d or l, or several separated by commas)", kind))),
+                    }
+                }
+            }
+            "--hide" => options.image.hidden.push(value()?),
+            "--hide-joliet" => {
+                // Asked for along with --hide, but there is no Joliet tree to leave anything out of
+                return Err(io::Error::new(ErrorKind::InvalidInput, "--hide-joliet is not supported: makeiso writes no Joliet tree, only ISO 9660 names (with Rock Ridge where needed); use --hide"));
+            }
+            "--associated" => options.image.associated.push(value()?),
+            "--xattrs" => options.image.xattrs = true,
+            "--win-metadata" => options.image.win_metadata = true,
+            "--apple" => options.image.apple = true,
+            "--dvd-video" => options.image.dvd_video = true,
+            "-x" | "--one-file-system" => options.image.one_file_system = true,
+            "--bwlimit" => {
+                let rate = parse_size(&value()?)?;
+                if rate == 0 {
+                    return Err(io::Error::new(ErrorKind::InvalidInput, "--bwlimit must be more than 0 bytes per second"));
+                }
+                options.image.bwlimit = Some(rate);
+            }
+            "--ionice" => options.image.io_priority = Some(IoPriority::parse(&value()?)?),
+            "--output-hash" => options.image.output_hash = Algorithm::parse_list(&value()?)?,
+            "--sign" => options.image.sign = Some(value()?),
+            "--zsync" => {
+                options.image.zsync.get_or_insert(None);
+            }
+            "--zsync-url" => options.image.zsync = Some(Some(value()?)),
+            "--upload" => {
+                let location = PathBuf::from(value()?);
+                if !location.to_str().is_some_and(|location| location.starts_with("s3://")) {
+                    return Err(io::Error::new(ErrorKind::InvalidInput, "--upload takes an s3://bucket/key location"));
+                }
+                options.image.upload = Some(location);
+            }
+            "--catalog-db" => options.image.catalog_db = Some(PathBuf::from(value()?)),
+            "--pre-hook" => options.image.pre_hook = Some(value()?),
+            "--post-hook" => options.image.post_hook = Some(value()?),
+            "--elevate" => options.image.elevate = Some(value()?),
+            "--report-denied" => options.image.report_denied = Some(PathBuf::from(value()?)),
+            "--checkpoint-every" => options.image.checkpoint_every = Some(checkpoint::parse_interval(&value()?)?),
+            "--status-file" => options.image.status_file = Some(PathBuf::from(value()?)),
+            "--encrypt" => options.image.encrypt.push(age::Recipient::parse(&value()?)?),
+            "--encrypt-glob" => options.image.encrypt_glob.push(value()?),
+            "--passphrase-file" => age::set_passphrase_file(Path::new(&value()?))?,
+            "--boot-image" => boot_image = Some(value()?),
+            "--efi-boot" => efi_image = Some(value()?),
+            "--boot-load-size" => {
+                let sectors = value()?;
+                boot_load_size = Some(
+                    sectors
+                        .parse::<u16>()
+                        .ok()
+                        .filter(|&sectors| sectors > 0)
+                        .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, format!("invalid --boot-load-size: {} (1 to 65535 sectors)", sectors)))?,
+                );
+            }
+            "--boot-info-table" => boot_info_table = true,
+            "--grub2-boot-info" => grub2_boot_info = true,
+            "--isohybrid-mbr" => {
+                let path = value()?;
+                let code = fs::read(&path)?;
+                if code.len() > 512 {
+                    return Err(io::Error::new(ErrorKind::InvalidInput, format!("{} is larger than an MBR", path)));
+                }
+                hybrid_mbr = Some(code);
+            }
+            "--isohybrid-gpt-basdat" => gpt_basdat = true,
+            "--isohybrid-apm-hfsplus" => apm_hfsplus = true,
+            "--chrp-boot" => chrp = true,
+            "--prep-boot" => {
+                let path = PathBuf::from(value()?);
+                let size = fs::metadata(&path)?.len();
+                appended.push(AppendedPartition { slot: 0, kind: hybrid::PREP_BOOT, path, size });
+            }
+            "--sparc-boot" => {
+                options.image.sparc_boot = sunlabel::boot_images(&value()?)?;
+                if options.image.sparc_boot.len() >= sunlabel::PARTITIONS {
+                    return Err(io::Error::new(ErrorKind::InvalidInput, format!("--sparc-boot takes at most {} boot images", sunlabel::PARTITIONS - 1)));
+                }
+            }
+            "--system-area" => options.image.system_area = Some(read_system_area(&value()?)?),
+            "--append-partition" => {
+                let spec = value()?;
+                let invalid = || io::Error::new(ErrorKind::InvalidInput, format!("invalid --append-partition: {} (N:TYPE:FILE)", spec));
+                let mut parts = spec.splitn(3, ':');
+                let (Some(slot), Some(kind), Some(path)) = (parts.next(), parts.next(), parts.next()) else { return Err(invalid()) };
+                let slot = slot.parse().ok().filter(|slot| (1..=hybrid::MBR_SLOTS).contains(slot)).ok_or_else(invalid)?;
+                let kind = u8::from_str_radix(kind.trim_start_matches("0x").trim_start_matches("0X"), 16).map_err(|_| invalid())?;
+                if appended.iter().any(|partition: &AppendedPartition| partition.slot == slot) {
+                    return Err(io::Error::new(ErrorKind::InvalidInput, format!("--append-partition: MBR slot {} is given twice", slot)));
+                }
+                let size = fs::metadata(path)?.len();
+                appended.push(AppendedPartition { slot, kind, path: PathBuf::from(path), size });
+            }
+            "--pad" => {
+                // The sector count is optional, so it can only be given as --pad=<sectors>
+                options.image.pad_sectors = match &inline_value {
+                    Some(sectors) => sectors
+                        .parse()
+                        .map_err(|_| io::Error::new(ErrorKind::InvalidInput, format!("invalid --pad sector count: {}", sectors)))?,
+                    None => DEFAULT_PAD_SECTORS,
+                };
+            }
+            "--deep-dirs" => {
+                options.image.deep_dirs = match value()?.as_str() {
+                    "error" => DeepDirPolicy::Error,
+                    "relocate" => DeepDirPolicy::Relocate,
+                    other => {
+                        return Err(io::Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("unknown --deep-dirs policy: {} (expected error or relocate)", other),
+                        ))
+                    }
+                };
+            }
+            "--if-changed" => {
+                options.image.if_changed = match value()?.as_str() {
+                    "pad" => IfChanged::Pad,
+                    "reread" => IfChanged::Reread,
+                    "fail" => IfChanged::Fail,
+                    other => {
+                        return Err(io::Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("unknown --if-changed policy: {} (expected pad, reread or fail)", other),
+                        ))
+                    }
+                };
+            }
+            _ if flag.starts_with("--") => {
+                return Err(io::Error::new(ErrorKind::InvalidInput, format!("unknown option: {}", flag)));
+            }
+            _ => positional.push(PathBuf::from(arg)),
+        }
+    }
+
+    // The boot file options only mean something with a boot file
+    if boot_image.is_none() && (boot_load_size.is_some() || boot_info_table || grub2_boot_info) {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "--boot-load-size, --boot-info-table and --grub2-boot-info need --boot-image"));
+    }
+    if boot_image.is_some() || efi_image.is_some() {
+        options.image.boot = Some(BootOptions {
+            image: boot_image,
+            efi_image,
+            load_size: boot_load_size,
+            info_table: boot_info_table,
+            grub2_boot_info,
+        });
+    }
+
+    // Hybrid images chain-load the El Torito boot files from the MBR, GPT and APM
+    let boot = options.image.boot.as_ref();
+    match hybrid_mbr {
+        Some(_) if boot.is_none_or(|boot| boot.image.is_none()) => {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "--isohybrid-mbr needs --boot-image"));
+        }
+        Some(mbr) => {
+            if (gpt_basdat || apm_hfsplus) && boot.is_none_or(|boot| boot.efi_image.is_none()) {
+                return Err(io::Error::new(ErrorKind::InvalidInput, "--isohybrid-gpt-basdat and --isohybrid-apm-hfsplus need --efi-boot"));
+            }
+            if chrp {
+                return Err(io::Error::new(ErrorKind::InvalidInput, "--chrp-boot can't be combined with --isohybrid-mbr, the volume is one partition or the other"));
+            }
+            options.image.hybrid = Some(HybridOptions { mbr, gpt_basdat, apm_hfsplus, chrp, appended });
+        }
+        None if gpt_basdat || apm_hfsplus => {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "--isohybrid-gpt-basdat and --isohybrid-apm-hfsplus need --isohybrid-mbr"));
+        }
+        None if chrp || !appended.is_empty() => {
+            options.image.hybrid = Some(HybridOptions { mbr: Vec::new(), gpt_basdat: false, apm_hfsplus: false, chrp, appended });
+        }
+        None => {}
+    }
+    if let Some(hybrid) = &mut options.image.hybrid {
+        let efi = boot.is_some_and(|boot| boot.efi_image.is_some());
+        hybrid.assign_slot(efi);
+        if !options.image.sparc_boot.is_empty() {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "--sparc-boot can't be combined with an MBR, both go in sector 0"));
+        }
+        if hybrid.own_slots(efi) + hybrid.appended.len() > hybrid::MBR_SLOTS {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "--append-partition: the MBR has no free slot left for all the partitions"));
+        }
+    }
+
+    if options.image.dvd_video && options.image.hybrid.as_ref().is_some_and(|hybrid| hybrid.gpt_basdat) {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "--dvd-video can't be combined with --isohybrid-gpt-basdat, both need the last blocks"));
+    }
+    if options.image.block_map_format != blockmap::MapFormat::Text && options.image.block_map.is_none() {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "--block-map-format goes with --block-map"));
+    }
+    if options.image.block_map_format == blockmap::MapFormat::Bmap && !options.image.encrypt.is_empty() {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "--block-map-format bmap checksums the image as written, which --encrypt turns into an age file"));
+    }
+    if options.image.enhanced && options.image.trans_tbl {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "--iso-level 4 can't be combined with --trans-tbl, which records 8.3 names"));
+    }
+
+    // A single pass writes the contents as they are found with the metadata after them, then
+    // goes back for the volume descriptors; the rest of the layout has to be known up front
+    if options.image.single_pass {
+        let image = &options.image;
+        let conflicts = [
+            (image.boot.is_some(), "--boot-image and --efi-boot"),
+            (image.hybrid.is_some(), "--isohybrid-mbr and --append-partition"),
+            (!image.sparc_boot.is_empty(), "--sparc-boot"),
+            (!image.encrypt.is_empty(), "--encrypt"),
+            (!image.encrypt_glob.is_empty(), "--encrypt-glob"),
+            (image.file_order == FileOrder::Size, "--file-order size"),
+            (image.group_small.is_some(), "--group-small"),
+            (!image.sort_weights.is_empty(), "--sort-weight and --sort-file"),
+            (image.dedup, "--dedup content"),
+            (image.read_workers > 0, "--read-workers"),
+            (image.dvd_video, "--dvd-video"),
+        ];
+        if let Some((_, flag)) = conflicts.iter().find(|(conflict, _)| *conflict) {
+            return Err(io::Error::new(ErrorKind::InvalidInput, format!("--single-pass can't be combined with {}", flag)));
+        }
+        options.image.metadata = MetadataPlacement::Last;
+    }
+
+    // Reproducible images need a fixed timestamp; fall back to the SOURCE_DATE_EPOCH convention
+    if options.image.reproducible && options.image.source_date_epoch.is_none() {
+        let epoch = env::var("SOURCE_DATE_EPOCH")
+            .map_err(|_| io::Error::new(ErrorKind::InvalidInput, "--reproducible needs --source-date-epoch or SOURCE_DATE_EPOCH"))?;
+        options.image.source_date_epoch = Some(
+            epoch
+                .trim()
+                .parse()
+                .map_err(|_| io::Error::new(ErrorKind::InvalidInput, format!("invalid SOURCE_DATE_EPOCH: {}", epoch)))?,
+        );
+    }
+
+    if positional.len() > 1 {
+        options.iso_path = positional.pop();
+    } else if let Some(template) = &defaults.output {
+        options.iso_path = Some(config::output_path(template)?);
+    }
+    options.sources = positional;
+    let from_stdin = options.sources.iter().filter(|path| path.as_os_str() == "-").count();
+    if from_stdin > 1 {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "standard input (-) can only be one of the sources"));
+    }
+    if from_stdin == 0 && options.image.stdin_name.is_some() {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "--stdin-name names standard input, which needs - among the sources"));
+    }
+
+    Ok(options)
+}
+
+// Check an identifier option for the PVD: printable ASCII of at most max_len characters
+fn identifier_value(flag: &str, value: String, max_len: usize) -> io::Result<String> {
+    if value.len() > max_len || !value.bytes().all(|byte| byte.is_ascii_graphic() || byte == b' ') {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} must be at most {} printable ASCII characters", flag, max_len),
+        ));
+    }
+    Ok(value)
+}
+
+// Parse a byte count with an optional binary suffix, e.g. 2048, 64K, 1M or 4G
+fn parse_size(text: &str) -> io::Result<u64> {
+    let invalid = || io::Error::new(ErrorKind::InvalidInput, format!("invalid size: {}", text));
+    let upper = text.trim().to_ascii_uppercase();
+    let digits = upper.trim_end_matches("IB").trim_end_matches('B');
+    let (number, multiplier) = match digits.char_indices().last() {
+        Some((at, 'K')) => (&digits[..at], 1u64 << 10),
+        Some((at, 'M')) => (&digits[..at], 1 << 20),
+        Some((at, 'G')) => (&digits[..at], 1 << 30),
+        Some((at, 'T')) => (&digits[..at], 1 << 40),
+        _ => (digits, 1),
+    };
+    number.parse::<u64>().map_err(|_| invalid())?.checked_mul(multiplier).ok_or_else(invalid)
+}
+
+// Prompt the user for a path on standard input
+fn prompt_path(message: &str) -> io::Result<PathBuf> {
+    println!("{}", message);
+    let mut path = String::new();
+    io::stdin().read_line(&mut path)?;
+    Ok(PathBuf::from(path.trim()))
+}
+
+// Options and paths of from-tar and from-zip: [options] <archive> <output.iso>, prompting for missing paths
+fn conversion_paths(args: &[String], archive_prompt: &str) -> io::Result<(PathBuf, PathBuf, ImageOptions)> {
+    let options = parse_args(args)?;
+    if options.image.boot.is_some() {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "--boot-image and --efi-boot are only supported when building from a directory"));
+    }
+    if !options.image.encrypt.is_empty() || !options.image.encrypt_glob.is_empty() {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "--encrypt and --encrypt-glob are only supported when building from a directory"));
+    }
+    if options.image.single_pass {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "--single-pass is only supported when building from a directory"));
+    }
+    if options.image.checksums {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "--checksums is only supported when building from a directory"));
+    }
+    let mut sources = options.sources.into_iter();
+    let archive = match sources.next() {
+        Some(path) => path,
+        None => prompt_path(archive_prompt)?,
+    };
+    if sources.next().is_some() {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "expected one archive and an ISO output path"));
+    }
+    if let Some(priority) = options.image.io_priority {
+        throttle::set_io_priority(priority)?;
+    }
+    let iso_path = match options.iso_path {
+        Some(path) => path,
+        None => prompt_path("Enter the ISO output file path:")?,
+    };
+    Ok((archive, iso_path, options.image))
+}
+
+// Refuse before building what an uploaded image can't have: gpg signs files, a single pass
+// goes back to the start of the image at the end, and a bmap and a zsync index read the
+// image back. A signing key gpg doesn't have, or no sqlite3 for the catalog, is caught here
+// too, not after the build.
+fn check_output(iso_path: &Path, options: &ImageOptions) -> io::Result<()> {
+    if let Some(key) = &options.sign {
+        if backend::is_remote(iso_path) {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "--sign needs a local output"));
+        }
+        sign::check_key(key)?;
+    }
+    if options.single_pass && backend::is_remote(iso_path) {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "--single-pass needs a local output"));
+    }
+    if options.zsync.is_some() && backend::is_remote(iso_path) {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "--zsync needs a local output"));
+    }
+    if options.catalog_db.is_some() {
+        if backend::is_remote(iso_path) {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "--catalog-db needs a local output, to read back and hash"));
+        }
+        catalogdb::check_sqlite()?;
+    }
+    if options.block_map_format == blockmap::MapFormat::Bmap && backend::is_remote(iso_path) {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "--block-map-format bmap needs a local output, to read back and checksum"));
+    }
+    Ok(())
+}
+
+// makeiso mount <image.iso> <mountpoint>: browse an image read-only through FUSE
+fn mount(args: &[String]) -> io::Result<()> {
+    let [iso_path, mount_point] = args else {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "Usage: makeiso mount <image.iso> <mountpoint>"));
+    };
+
+    #[cfg(target_os = "linux")]
+    return fuse::mount_image(Path::new(iso_path), Path::new(mount_point));
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (iso_path, mount_point);
+        Err(io::Error::new(ErrorKind::Unsupported, "Mounting images is only supported on Linux"))
+    }
+}
+
+// makeiso shell <image.iso>: the image mounted for a subshell, Linux only like mount
+fn shell(args: &[String]) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    return shell::shell(args);
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = args;
+        Err(io::Error::new(ErrorKind::Unsupported, "makeiso shell is only supported on Linux"))
+    }
+}
+
+// Take --input-charset (what the names in the images are recorded in), --trust (which byte
+// order to believe), --normalize (the Unicode form names and paths are compared in), --icase
+// and --case-sensitive (how paths asked for are looked up), --identity and --passphrase-file
+// (keys for encrypted images and files) out of the arguments of a subcommand that reads images
+fn take_image_options(args: Vec<String>) -> io::Result<Vec<String>> {
+    let mut rest = Vec::with_capacity(args.len());
+    let mut identities = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) => (flag, Some(value.to_string())),
+            None => (arg.as_str(), None),
+        };
+        if inline.is_none() && (flag == "--icase" || flag == "--case-sensitive") {
+            reader::set_icase(flag == "--icase");
+            continue;
+        }
+        if !["--input-charset", "--trust", "--normalize", "--identity", "--passphrase-file"].contains(&flag) {
+            rest.push(arg);
+            continue;
+        }
+        let value = match inline {
+            Some(value) => value,
+            None => args.next().ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, format!("{} requires a value", flag)))?,
+        };
+        match flag {
+            "--identity" => identities.push(PathBuf::from(value)),
+            "--passphrase-file" => age::set_passphrase_file(Path::new(&value))?,
+            "--trust" => reader::set_trust(reader::Trust::parse(&value)?),
+            "--normalize" => unicode::set_reading(unicode::Normalization::parse(&value)?),
+            _ => charset::set_image_names(Charset::parse(&value)?),
+        }
+    }
+    age::set_identity_files(identities);
+    Ok(rest)
+}
+
+// Write an image with `write`, given the path to create. A new image, or one replacing a regular
+// file, goes to <iso>.part first and is synced and renamed into place once complete, so the
+// output path never holds half an image; devices are written in place. An existing image is
+// only replaced with --force. What a failed or cancelled run wrote is removed unless
+// --keep-partial is given.
+fn write_output(iso_path: &Path, options: &ImageOptions, write: impl FnOnce(&Path) -> io::Result<()>) -> io::Result<()> {
+    cancel::install();
+    if backend::is_remote(iso_path) {
+        return write(iso_path); // An upload that fails is never completed
+    }
+    let existing = fs::metadata(iso_path).ok();
+    if existing.as_ref().is_some_and(|metadata| metadata.is_file()) && !options.force {
+        return Err(io::Error::new(ErrorKind::AlreadyExists, format!("{} already exists; give --force to replace it", iso_path.display())));
+    }
+    let in_place = existing.is_some_and(|metadata| !metadata.is_file());
+    let output = if in_place { iso_path.to_path_buf() } else { partial_path(iso_path) };
+
+    let state = || fs::metadata(&output).ok().map(|metadata| (metadata.len(), metadata.modified().ok()));
+    let before = state();
+    let written = write(&output).and_then(|()| match fs::OpenOptions::new().write(true).open(&output)?.sync_all() {
+        // Character devices such as /dev/null can't be synced
+        Err(e) if in_place && e.kind() == ErrorKind::InvalidInput => Ok(()),
+        synced => synced,
+    });
+    match written {
+        Ok(()) if in_place => Ok(()),
+        Ok(()) => {
+            fs::rename(&output, iso_path)?;
+            sync_directory(iso_path);
+            Ok(())
+        }
+        Err(e) => {
+            // A device that the run failed before touching is left alone
+            if state().is_some() && state() != before {
+                if options.keep_partial {
+                    events::warn(&format!("Kept the partial image {}", output.display()));
+                } else if fs::remove_file(&output).is_ok() {
+                    events::warn(&format!("Removed the partial image {}", output.display()));
+                }
+            }
+            Err(e)
+        }
+    }
+}
+
+// What the filesystem of the output needs room for: the image file, of size bytes, and the
+// control file written next to it
+fn room_needed(size: u64, options: &ImageOptions) -> u64 {
+    size + options.zsync.as_ref().map_or(0, |_| zsync::control_size(size))
+}
+
+// Where an image is written before it is complete
+fn partial_path(iso_path: &Path) -> PathBuf {
+    let mut path = iso_path.as_os_str().to_owned();
+    path.push(".part");
+    PathBuf::from(path)
+}
+
+// Make a rename into the directory of a path durable; best effort, as not every system can
+#[cfg(unix)]
+fn sync_directory(path: &Path) {
+    let directory = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    if let Ok(directory) = File::open(directory) {
+        let _ = directory.sync_all();
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_directory(_path: &Path) {}
+
+// Create the ISO, signing it when asked
+fn build_image(sources: &[PathBuf], iso_path: &Path, options: &ImageOptions) -> io::Result<()> {
+    hooks::around(iso_path, options, || build(sources, iso_path, options))
+}
+
+// The build itself, between the hooks
+fn build(sources: &[PathBuf], iso_path: &Path, options: &ImageOptions) -> io::Result<()> {
+    if let Some(priority) = options.io_priority {
+        throttle::set_io_priority(priority)?;
+    }
+    check_output(iso_path, options)?;
+    // Standard input is spooled to a file first, which goes away with the spool
+    let mut sources = sources.to_vec();
+    let mut spooled = None;
+    if let Some(from_stdin) = sources.iter_mut().find(|path| path.as_os_str() == "-") {
+        let spool = stdin::spool(options.stdin_name.as_deref().unwrap_or(stdin::DEFAULT_NAME), iso_path)?;
+        *from_stdin = spool.path.clone();
+        spooled = Some(spool);
+    }
+    write_output(iso_path, options, |output| create_iso(&sources, iso_path, output, options))?;
+    drop(spooled);
+    report_denied(options)?;
+    finish_image(iso_path, options, Written::by(options))
+}
+
+// How an image was written, which decides what finish_image still has to do for it
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Written {
+    InOrder,    // Front to back, through the hashing and uploading sinks
+    SinglePass, // Hashed as it was written, but it goes back to the start at the end
+    OutOfOrder, // Conversions: members go to their extents in archive order
+}
+
+impl Written {
+    // How create_iso and create_iso_from write the image these options ask for
+    fn by(options: &ImageOptions) -> Written {
+        if options.single_pass { Written::SinglePass } else { Written::InOrder }
+    }
+}
+
+// What follows a finished image, for every way of making one: the checksums and the upload
+// when they couldn't be made as it was written, the signature, the zsync control file and the
+// catalog entry
+fn finish_image(iso_path: &Path, options: &ImageOptions, written: Written) -> io::Result<()> {
+    if written == Written::OutOfOrder && !options.output_hash.is_empty() {
+        imagehash::hash_written(iso_path, &options.output_hash)?;
+    }
+    if let Some(key) = &options.sign {
+        sign::sign_image(iso_path, key)?;
+    }
+    if let Some(url) = &options.zsync {
+        zsync::write_control(iso_path, &zsync::control_path(iso_path), url.as_deref())?;
+    }
+    if let Some(upload) = options.upload.as_ref().filter(|_| written != Written::InOrder) {
+        backend::upload_file(iso_path, upload)?;
+    }
+    if let Some(db) = &options.catalog_db {
+        catalogdb::add_image(db, iso_path)?;
+    }
+    events::done(iso_path);
+    Ok(())
+}
+
+// Say how many files and directories were left out as permission denied, and list them in
+// the --report-denied file (empty when there were none, so an old list doesn't linger)
+fn report_denied(options: &ImageOptions) -> io::Result<()> {
+    let denied = DENIED_PATHS.lock().unwrap_or_else(|e| e.into_inner());
+    if !denied.is_empty() {
+        let hint = if options.elevate.is_some() { "" } else { "; --elevate sudo reads them through a helper" };
+        events::warn(&format!("{} files and directories were left out as permission denied{}", denied.len(), hint));
+    }
+    if let Some(report) = &options.report_denied {
+        let list: String = denied.iter().map(|path| format!("{}\n", path.display())).collect();
+        fs::write(report, list).map_err(|e| io::Error::new(e.kind(), format!("could not write {}: {}", report.display(), e)))?;
+    }
+    Ok(())
+}
+
+// The makeiso command (src/main.rs only calls this)
+pub fn main() -> io::Result<()> {
+    run().inspect_err(events::error)
+}
+
+// Everything main does; its error is reported as an event too with --json-events
+fn run() -> io::Result<()> {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    if let Some("ls" | "cat" | "mount" | "extract" | "to-tar" | "to-zip" | "cmp" | "catalog" | "find" | "grep" | "browse" | "verify-restore" | "shell" | "verify" | "repair" | "sector" | "whatis" | "block-map") = args.first().map(String::as_str) {
+        args = take_image_options(args)?;
+    }
+
+    // Subcommands that read an existing image
+    match args.first().map(String::as_str) {
+        Some("ls") => {
+            let long = args.get(1).is_some_and(|arg| arg == "-l");
+            let iso_path = match args.get(if long { 2 } else { 1 }) {
+                Some(path) => PathBuf::from(path),
+                None => prompt_path("Enter the path to the ISO file:")?,
+            };
+            return reader::list_image(&iso_path, long);
+        }
+        Some("mount") => return mount(&args[1..]),
+        Some("shell") => return shell(&args[1..]),
+        Some("extract") => return extract::extract_command(&args[1..]),
+        Some("from-tar") => {
+            let (archive, iso_path, options) = conversion_paths(&args[1..], "Enter the path to the tar archive:")?;
+            check_output(&iso_path, &options)?;
+            return hooks::around(&iso_path, &options, || {
+                write_output(&iso_path, &options, |output| tar::tar_to_iso(&archive, output, &options))?;
+                finish_image(&iso_path, &options, Written::OutOfOrder)
+            });
+        }
+        Some("from-zip") => {
+            let (archive, iso_path, options) = conversion_paths(&args[1..], "Enter the path to the zip archive:")?;
+            check_output(&iso_path, &options)?;
+            return hooks::around(&iso_path, &options, || {
+                write_output(&iso_path, &options, |output| zip::zip_to_iso(&archive, output, &options))?;
+                finish_image(&iso_path, &options, Written::OutOfOrder)
+            });
+        }
+        Some("cmp") => return compare::cmp(&args[1..]),
+        Some("verify-restore") => return compare::verify_restore(&args[1..]),
+        Some("catalog") => return catalog::catalog(&args[1..]),
+        Some("find") => return find::find(&args[1..]),
+        Some("grep") => return grep::grep(&args[1..]),
+        Some("cat") => return cat::cat(&args[1..]),
+        Some("browse") => return browse::browse(&args[1..]),
+        Some("estimate") => return estimate::estimate(&args[1..]),
+        Some("verify") => return verify::verify(&args[1..]),
+        Some("repair") => return repair::repair(&args[1..]),
+        Some("sector") => return sector::sector(&args[1..]),
+        Some("whatis") => return sector::whatis(&args[1..]),
+        Some("block-map") => return blockmap::block_map(&args[1..]),
+        Some("zsync") => return zsync::zsync(&args[1..]),
+        Some("which-image") => return catalogdb::which_image(&args[1..]),
+        Some("prune") => return prune::prune(&args[1..]),
+        Some("read-helper") => return elevate::helper(&args[1..]),
+        Some("verify-sig") => return sign::verify_sig(&args[1..]),
+        Some("completions") => return completions::completions(&args[1..]),
+        Some("run") => return profile::run(&args[1..]),
+        Some("daemon") => return daemon::daemon(&args[1..]),
+        Some("status") => return daemon::status(&args[1..]),
+        Some("serve") => return serve::serve(&args[1..]),
+        Some("bench") => return bench::bench(&args[1..]),
+        Some("bin-to-iso") => {
+            let [image, iso_path] = &args[1..] else {
+                return Err(io::Error::new(ErrorKind::InvalidInput, "Usage: makeiso bin-to-iso <image.cue|image.bin> <output.iso>"));
+            };
+            return rawsector::bin_to_iso(Path::new(image), Path::new(iso_path));
+        }
+        Some("audio") => return audio::audio(&args[1..]),
+        Some("merge") => return merge::merge(&args[1..]),
+        Some(command @ ("to-tar" | "to-zip")) => {
+            let [iso_path, output] = &args[1..] else {
+                return Err(io::Error::new(ErrorKind::InvalidInput, format!("Usage: makeiso {} <image.iso> <output|->", command)));
+            };
+            if command == "to-tar" {
+                return tar::iso_to_tar(Path::new(iso_path), Path::new(output));
+            }
+            return zip::iso_to_zip(Path::new(iso_path), Path::new(output));
+        }
+        _ => {}
+    }
+
+    let options = parse_args(&args)?;
+
+    // Prompt the user for the directory to back up
+    let mut sources = options.sources;
+    if sources.is_empty() {
+        sources.push(prompt_path("Enter the directory path to back up:")?);
+    }
+
+    // Prompt the user for the ISO output file
+    let iso_path = match options.iso_path {
+        Some(path) => path,
+        None => prompt_path("Enter the ISO output file path:")?,
+    };
+
+    build_image(&sources, &iso_path, &options.image)
+}
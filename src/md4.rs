@@ -0,0 +1,85 @@
+// MD4 (RFC 1320), only for the block checksums of zsync control files, which are defined with it
+
+const INITIAL_STATE: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+
+#[derive(Clone)]
+pub struct Md4 {
+    state: [u32; 4],
+    block: [u8; 64],
+    block_len: usize,
+    total: u64,
+}
+
+impl Md4 {
+    pub fn new() -> Md4 {
+        Md4 {
+            state: INITIAL_STATE,
+            block: [0; 64],
+            block_len: 0,
+            total: 0,
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.total += data.len() as u64;
+        if self.block_len > 0 {
+            let take = (64 - self.block_len).min(data.len());
+            self.block[self.block_len..self.block_len + take].copy_from_slice(&data[..take]);
+            self.block_len += take;
+            data = &data[take..];
+            if self.block_len < 64 {
+                return;
+            }
+            let block = self.block;
+            self.compress(&block);
+            self.block_len = 0;
+        }
+        let mut blocks = data.chunks_exact(64);
+        for block in &mut blocks {
+            self.compress(block.try_into().expect("64-byte block"));
+        }
+        let rest = blocks.remainder();
+        self.block[..rest.len()].copy_from_slice(rest);
+        self.block_len = rest.len();
+    }
+
+    pub fn finish(mut self) -> [u8; 16] {
+        let bits = self.total.wrapping_mul(8);
+        let mut padding = vec![0x80u8];
+        padding.resize(if self.block_len < 56 { 56 - self.block_len } else { 120 - self.block_len }, 0);
+        padding.extend_from_slice(&bits.to_le_bytes());
+        let total = self.total;
+        self.update(&padding);
+        self.total = total;
+
+        let mut digest = [0u8; 16];
+        for (bytes, word) in digest.chunks_mut(4).zip(self.state) {
+            bytes.copy_from_slice(&word.to_le_bytes());
+        }
+        digest
+    }
+
+    fn compress(&mut self, block: &[u8; 64]) {
+        let mut x = [0u32; 16];
+        for (word, bytes) in x.iter_mut().zip(block.chunks(4)) {
+            *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+
+        let [mut a, mut b, mut c, mut d] = self.state;
+        // Three rounds of sixteen steps, each round with its own function, order of words and shifts
+        for round in 0..3 {
+            for step in 0..16 {
+                let (f, k, index, shifts) = match round {
+                    0 => ((b & c) | (!b & d), 0, step, [3, 7, 11, 19]),
+                    1 => ((b & c) | (b & d) | (c & d), 0x5a827999, (step % 4) * 4 + step / 4, [3, 5, 9, 13]),
+                    _ => (b ^ c ^ d, 0x6ed9eba1, [0, 8, 4, 12, 2, 10, 6, 14, 1, 9, 5, 13, 3, 11, 7, 15][step], [3, 9, 11, 15]),
+                };
+                let t = a.wrapping_add(f).wrapping_add(x[index]).wrapping_add(k).rotate_left(shifts[step % 4]);
+                (a, b, c, d) = (d, t, b, c);
+            }
+        }
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+}
@@ -0,0 +1,273 @@
+// makeiso merge: one image whose tree is the union of several others'. The trees are read
+// from the images and built like a source directory, with the file contents copied straight
+// from their extents, so nothing is extracted on the way and every build option applies.
+//
+// Directories found in more than one image are merged. A path that is a file in more than one
+// is a conflict unless the contents are the same; --conflict decides: newest (the default)
+// keeps the most recently modified one (the later image on a tie), error refuses, and rename
+// keeps every one, the later ones as name~2.ext, name~3.ext and so on. The boot setup,
+// symbolic links and the rest of the system area of the inputs aren't carried over.
+
+use std::collections::{HashMap, HashSet};
+use std::io::{self, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use super::backend::{ScannedEntry, SourceFile, SourceKind, SourceMetadata, SourceProvider};
+use super::reader::{open_image, ImageSource, IsoEntry, IsoReader, ReadAt};
+use super::tar::system_time;
+use super::{check_output, create_iso_from, events, finish_image, hooks, parse_args, write_output, Written, BLOCK_SIZE};
+
+const USAGE: &str = "Usage: makeiso merge [options] <image.iso> <image.iso>... -o <merged.iso> [--conflict newest|error|rename]";
+const COMPARE_CHUNK_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Conflict {
+    Newest,
+    Error,
+    Rename,
+}
+
+// A file or directory of the merged tree, at its path there
+enum Node {
+    Directory { children: Vec<PathBuf>, modified: Option<SystemTime> },
+    File { image: usize, extent: u32, size: u32, modified: Option<SystemTime> },
+}
+
+// The merged tree as a source; "/" is its root
+struct MergedSource {
+    images: Vec<Arc<Mutex<ImageSource>>>,
+    nodes: HashMap<PathBuf, Node>,
+}
+
+// makeiso merge [options] <image.iso>... -o <merged.iso> [--conflict POLICY]
+pub fn merge(args: &[String]) -> io::Result<()> {
+    let mut conflict = Conflict::Newest;
+    let mut output = None;
+    let mut rest = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => (flag, Some(value.to_string())),
+            _ => (arg.as_str(), None),
+        };
+        if !["-o", "--output", "--conflict"].contains(&flag) {
+            rest.push(arg.clone());
+            continue;
+        }
+        let value = inline.or_else(|| args.next().cloned()).ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, format!("{} requires a value", flag)))?;
+        if flag == "--conflict" {
+            conflict = match value.as_str() {
+                "newest" => Conflict::Newest,
+                "error" => Conflict::Error,
+                "rename" => Conflict::Rename,
+                _ => return Err(io::Error::new(ErrorKind::InvalidInput, format!("invalid --conflict {} (expected newest, error or rename)", value))),
+            };
+        } else {
+            output = Some(value);
+        }
+    }
+    let output = output.ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, USAGE))?;
+    // With the output as the last path, the options read the images as the sources
+    rest.push(output.clone());
+    let parsed = parse_args(&rest)?;
+    let (options, images) = (parsed.image, parsed.sources);
+    if images.is_empty() {
+        return Err(io::Error::new(ErrorKind::InvalidInput, USAGE));
+    }
+
+    let iso_path = PathBuf::from(output);
+    check_output(&iso_path, &options)?;
+    hooks::around(&iso_path, &options, || {
+        let source = MergedSource::read(&images, conflict)?;
+        write_output(&iso_path, &options, |written| create_iso_from(&source, &[PathBuf::from("/")], &iso_path, written, &options))?;
+        finish_image(&iso_path, &options, Written::by(&options))
+    })
+}
+
+impl MergedSource {
+    // Read the trees of the images and merge them, in the order given
+    fn read(images: &[PathBuf], conflict: Conflict) -> io::Result<MergedSource> {
+        let mut merged = MergedSource { images: Vec::new(), nodes: HashMap::from([(PathBuf::from("/"), Node::Directory { children: Vec::new(), modified: None })]) };
+        let mut readers = Vec::new();
+        for (image, path) in images.iter().enumerate() {
+            let mut reader = open_image(path).map_err(|e| io::Error::new(e.kind(), format!("could not read {}: {}", path.display(), e)))?;
+            let entries = reader.read_tree()?;
+            readers.push(reader);
+            merged.add_image(&mut readers, image, &entries, path, conflict)?;
+        }
+        merged.images = readers.into_iter().map(|reader| Arc::new(Mutex::new(reader.into_source()))).collect();
+        Ok(merged)
+    }
+
+    fn add_image(&mut self, readers: &mut [IsoReader<ImageSource>], image: usize, entries: &[IsoEntry], path: &Path, conflict: Conflict) -> io::Result<()> {
+        let boot_catalog = readers[image].boot_catalog;
+        // Where each directory of this image went in the merged tree
+        let mut placed: HashMap<usize, PathBuf> = HashMap::from([(0, PathBuf::from("/"))]);
+        if let Some(Node::Directory { modified, .. }) = self.nodes.get_mut(Path::new("/")) {
+            *modified = (*modified).max(Some(system_time(entries[0].modified)));
+        }
+        for (index, entry) in entries.iter().enumerate().skip(1) {
+            let Some(parent) = placed.get(&entry.parent).cloned() else {
+                continue; // Under a directory left out
+            };
+            if entry.symlink.is_some() {
+                events::warn(&format!("Skipping symbolic link {} in {}", entry.path, path.display()));
+                continue;
+            }
+            if !entry.is_directory && Some(entry.extent) == boot_catalog {
+                continue; // The boot catalog some readers list as a file
+            }
+            let modified = Some(system_time(entry.modified));
+            let mut target = parent.join(&entry.name);
+            match self.nodes.get_mut(&target) {
+                None => {}
+                Some(Node::Directory { modified: found, .. }) if entry.is_directory => {
+                    *found = (*found).max(modified);
+                    placed.insert(index, target);
+                    continue;
+                }
+                Some(Node::File { image: other, extent, size, modified: found }) if !entry.is_directory => {
+                    if *size == entry.size && same_contents(readers, (*other, *extent), (image, entry.extent), entry.size)? {
+                        continue;
+                    }
+                    match conflict {
+                        Conflict::Newest => {
+                            if modified >= *found {
+                                (*other, *extent, *size, *found) = (image, entry.extent, entry.size, modified);
+                            }
+                            continue;
+                        }
+                        Conflict::Error => return Err(conflict_error(&target, path)),
+                        Conflict::Rename => target = self.free_name(&parent, &entry.name),
+                    }
+                }
+                Some(_) if conflict == Conflict::Rename => target = self.free_name(&parent, &entry.name),
+                Some(_) => {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("{} is a file in one image and a directory in another ({}; --conflict rename keeps both)", target.display(), path.display()),
+                    ))
+                }
+            }
+
+            if target.file_name() != Some(entry.name.as_ref()) {
+                events::info(&format!("{} of {} goes in as {}", entry.path, path.display(), target.display()));
+            }
+            let node = if entry.is_directory {
+                placed.insert(index, target.clone());
+                Node::Directory { children: Vec::new(), modified }
+            } else {
+                Node::File { image, extent: entry.extent, size: entry.size, modified }
+            };
+            if let Some(Node::Directory { children, .. }) = self.nodes.get_mut(&parent) {
+                children.push(target.clone());
+            }
+            self.nodes.insert(target, node);
+        }
+        Ok(())
+    }
+
+    // name~N.ext for the first N from 2 that is free in the directory
+    fn free_name(&self, parent: &Path, name: &str) -> PathBuf {
+        let (stem, extension) = match name.rsplit_once('.') {
+            Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{}", extension)),
+            _ => (name, String::new()),
+        };
+        let taken: HashSet<&PathBuf> = self.nodes.keys().collect();
+        (2..).map(|number| parent.join(format!("{}~{}{}", stem, number, extension))).find(|path| !taken.contains(path)).unwrap()
+    }
+
+    fn node(&self, path: &Path) -> io::Result<&Node> {
+        self.nodes.get(path).ok_or_else(|| io::Error::new(ErrorKind::NotFound, format!("{} is in none of the images", path.display())))
+    }
+}
+
+fn conflict_error(target: &Path, image: &Path) -> io::Error {
+    io::Error::new(ErrorKind::AlreadyExists, format!("{} differs between the images ({} has another one; choose --conflict newest or rename)", target.display(), image.display()))
+}
+
+// Whether two extents of the same size, in the same or different images, hold the same bytes
+fn same_contents(readers: &mut [IsoReader<ImageSource>], (a, a_extent): (usize, u32), (b, b_extent): (usize, u32), size: u32) -> io::Result<bool> {
+    let (mut first, mut second) = (vec![0u8; COMPARE_CHUNK_SIZE], vec![0u8; COMPARE_CHUNK_SIZE]);
+    let mut offset = 0u64;
+    while offset < size as u64 {
+        let len = (size as u64 - offset).min(COMPARE_CHUNK_SIZE as u64) as usize;
+        readers[a].source_mut().read_exact_at(a_extent as u64 * BLOCK_SIZE as u64 + offset, &mut first[..len])?;
+        readers[b].source_mut().read_exact_at(b_extent as u64 * BLOCK_SIZE as u64 + offset, &mut second[..len])?;
+        if first[..len] != second[..len] {
+            return Ok(false);
+        }
+        offset += len as u64;
+    }
+    Ok(true)
+}
+
+impl SourceProvider for MergedSource {
+    fn entries(&self, dir: &Path) -> io::Result<Vec<io::Result<ScannedEntry>>> {
+        let Node::Directory { children, .. } = self.node(dir)? else {
+            return Err(io::Error::new(ErrorKind::InvalidInput, format!("{} is not a directory", dir.display())));
+        };
+        Ok(children
+            .iter()
+            .map(|path| {
+                let kind = self.kind(path);
+                let metadata = match &self.nodes[path] {
+                    Node::Directory { modified, .. } => SourceMetadata { len: 0, modified: *modified, identity: None },
+                    Node::File { size, modified, .. } => SourceMetadata { len: *size as u64, modified: *modified, identity: None },
+                };
+                Ok(ScannedEntry { path: path.clone(), kind, metadata: Ok(metadata) })
+            })
+            .collect())
+    }
+
+    fn kind(&self, path: &Path) -> SourceKind {
+        match self.nodes.get(path) {
+            Some(Node::Directory { .. }) => SourceKind::Directory,
+            Some(Node::File { .. }) => SourceKind::File,
+            None => SourceKind::Other,
+        }
+    }
+
+    fn directory_modified(&self, dir: &Path) -> Option<SystemTime> {
+        match self.nodes.get(dir) {
+            Some(Node::Directory { modified, .. }) => *modified,
+            _ => None,
+        }
+    }
+
+    fn file_metadata(&self, path: &Path) -> io::Result<SourceMetadata> {
+        match self.node(path)? {
+            Node::File { size, modified, .. } => Ok(SourceMetadata { len: *size as u64, modified: *modified, identity: None }),
+            Node::Directory { .. } => Err(io::Error::new(ErrorKind::InvalidInput, format!("{} is a directory", path.display()))),
+        }
+    }
+
+    fn open(&self, path: &Path) -> io::Result<SourceFile> {
+        let metadata = self.file_metadata(path)?;
+        let Node::File { image, extent, size, .. } = self.node(path)? else { unreachable!() };
+        let reader = ExtentReader { image: Arc::clone(&self.images[*image]), offset: *extent as u64 * BLOCK_SIZE as u64, remaining: *size as u64 };
+        Ok(SourceFile::Stream { reader: Box::new(reader), metadata })
+    }
+}
+
+// The contents of a file, read from its extent in an input image
+struct ExtentReader {
+    image: Arc<Mutex<ImageSource>>,
+    offset: u64,
+    remaining: u64,
+}
+
+impl Read for ExtentReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = (buf.len() as u64).min(self.remaining) as usize;
+        if len == 0 {
+            return Ok(0);
+        }
+        self.image.lock().unwrap_or_else(|e| e.into_inner()).read_exact_at(self.offset, &mut buf[..len])?;
+        self.offset += len as u64;
+        self.remaining -= len as u64;
+        Ok(len)
+    }
+}
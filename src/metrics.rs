@@ -0,0 +1,122 @@
+// Backup runs as Prometheus metrics, for alerting on backups that fail or stop happening
+//
+// The numbers come from the record of runs (see daemon.rs), one series per profile. The daemon
+// serves them over HTTP (--metrics-listen ADDR, scraped at /metrics) or keeps a file for the
+// node exporter's textfile collector up to date (--metrics-file PATH), and makeiso status
+// --metrics prints them. Scrapers asking for OpenMetrics get that format, the rest the
+// Prometheus text format.
+
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::thread;
+
+use super::config;
+use super::daemon::{self, Run};
+use super::serve::{read_request, respond};
+
+const PROMETHEUS_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+const OPENMETRICS_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+// Label values are quoted, so backslashes, quotes and line breaks are escaped
+fn label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+// The metrics for the named profiles (and any others that have runs on record)
+pub fn render(names: &[String], runs: &[Run], openmetrics: bool) -> String {
+    let mut names = names.to_vec();
+    for run in runs {
+        if !names.contains(&run.profile) {
+            names.push(run.profile.clone());
+        }
+    }
+
+    // (name, type, help, value of a profile's runs); None leaves the profile out
+    type Value = fn(&[&Run]) -> Option<f64>;
+    let families: [(&str, &str, &str, Value); 8] = [
+        ("makeiso_runs", "counter", "Runs of the profile recorded", |runs| Some(runs.len() as f64)),
+        ("makeiso_run_failures", "counter", "Runs of the profile that failed", |runs| Some(runs.iter().filter(|run| !run.ok).count() as f64)),
+        ("makeiso_last_run_timestamp_seconds", "gauge", "When the last run started", |runs| runs.last().map(|run| run.start as f64)),
+        ("makeiso_last_run_success", "gauge", "Whether the last run succeeded", |runs| runs.last().map(|run| if run.ok { 1.0 } else { 0.0 })),
+        ("makeiso_last_run_duration_seconds", "gauge", "How long the last run took", |runs| runs.last().map(|run| run.seconds as f64)),
+        ("makeiso_last_run_written_bytes", "gauge", "Size of the image the last run wrote", |runs| runs.last().map(|run| run.bytes as f64)),
+        ("makeiso_last_run_skipped_files", "gauge", "Files the last run left out since they couldn't be read", |runs| runs.last().map(|run| run.skipped as f64)),
+        ("makeiso_last_success_timestamp_seconds", "gauge", "When the last successful run started", |runs| runs.iter().rev().find(|run| run.ok).map(|run| run.start as f64)),
+    ];
+
+    let mut text = String::new();
+    for (name, kind, help, value) in families {
+        // OpenMetrics names a counter's family without the _total its samples carry
+        let sample = if kind == "counter" { format!("{}_total", name) } else { name.to_string() };
+        let family = if openmetrics { name } else { sample.as_str() };
+        let _ = writeln!(text, "# HELP {} {}.", family, help);
+        let _ = writeln!(text, "# TYPE {} {}", family, kind);
+        for profile in &names {
+            let mine: Vec<&Run> = runs.iter().filter(|run| run.profile == *profile).collect();
+            if let Some(value) = value(&mine) {
+                let _ = writeln!(text, "{}{{profile=\"{}\"}} {}", sample, label(profile), value);
+            }
+        }
+    }
+    if openmetrics {
+        text.push_str("# EOF\n");
+    }
+    text
+}
+
+// The metrics as of now, for the profiles of a configuration
+fn current(config_args: &[String], openmetrics: bool) -> io::Result<String> {
+    let names = config::profile_names(config_args).unwrap_or_else(|e| {
+        eprintln!("Could not read the configuration: {}", e);
+        Vec::new()
+    });
+    Ok(render(&names, &daemon::read_runs()?, openmetrics))
+}
+
+// Print the metrics (makeiso status --metrics)
+pub fn print(config_args: &[String]) -> io::Result<()> {
+    print!("{}", current(config_args, false)?);
+    Ok(())
+}
+
+// Replace the textfile collector's file; written alongside and renamed, so it's never read half done
+pub fn write_file(path: &Path, config_args: &[String]) -> io::Result<()> {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".tmp");
+    fs::write(&partial, current(config_args, false)?)?;
+    fs::rename(&partial, path)
+}
+
+// Serve the metrics over HTTP on a thread of their own
+pub fn serve(address: &str, config_args: &[String]) -> io::Result<()> {
+    let listener = TcpListener::bind(address).map_err(|e| io::Error::new(e.kind(), format!("Could not listen on {}: {}", address, e)))?;
+    println!("Serving metrics on http://{}/metrics", listener.local_addr()?);
+    let config_args = config_args.to_vec();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let result = stream.and_then(|stream| answer(stream, &config_args));
+            if let Err(e) = result {
+                eprintln!("Metrics request failed: {}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+// Answer one request
+fn answer(stream: TcpStream, config_args: &[String]) -> io::Result<()> {
+    let request = read_request(&stream)?;
+    let openmetrics = request.header("Accept").is_some_and(|accept| accept.contains("application/openmetrics-text"));
+    let head = request.method == "HEAD";
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET" | "HEAD", "/metrics" | "/") => match current(config_args, openmetrics) {
+            Ok(body) => respond(&stream, "200 OK", if openmetrics { OPENMETRICS_TYPE } else { PROMETHEUS_TYPE }, &body, head),
+            Err(e) => respond(&stream, "500 Internal Server Error", "text/plain", &format!("{}\n", e), head),
+        },
+        ("GET" | "HEAD", _) => respond(&stream, "404 Not Found", "text/plain", "Metrics are at /metrics\n", head),
+        _ => respond(&stream, "405 Method Not Allowed", "text/plain", "Only GET is supported\n", head),
+    }
+}
@@ -0,0 +1,70 @@
+// Read-only memory mappings of source files, used for large files when --mmap is given
+//
+// A mapped file that is truncated by another process while it is being copied raises
+// SIGBUS, so the mapped path is only suitable for trees that aren't being modified.
+
+use std::fs::File;
+use std::io::{self, ErrorKind};
+
+#[cfg(all(unix, target_pointer_width = "64"))]
+mod sys {
+    use std::ffi::{c_int, c_void};
+
+    pub const PROT_READ: c_int = 1;
+    pub const MAP_PRIVATE: c_int = 2;
+    pub const MADV_SEQUENTIAL: c_int = 2;
+
+    extern "C" {
+        pub fn mmap(addr: *mut c_void, len: usize, prot: c_int, flags: c_int, fd: c_int, offset: i64) -> *mut c_void;
+        pub fn munmap(addr: *mut c_void, len: usize) -> c_int;
+        pub fn madvise(addr: *mut c_void, len: usize, advice: c_int) -> c_int;
+    }
+}
+
+// A read-only view of the first `len` bytes of a file, unmapped on drop
+pub struct Mapping {
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl Mapping {
+    // Map the first `len` bytes of the file; the file must be at least that long
+    #[cfg(all(unix, target_pointer_width = "64"))]
+    pub fn new(file: &File, len: usize) -> io::Result<Mapping> {
+        use std::os::unix::io::AsRawFd;
+
+        if len == 0 {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "cannot map an empty file"));
+        }
+
+        // SAFETY: mapping a valid file descriptor read-only; the result is checked for MAP_FAILED
+        let ptr = unsafe { sys::mmap(std::ptr::null_mut(), len, sys::PROT_READ, sys::MAP_PRIVATE, file.as_raw_fd(), 0) };
+        if ptr as isize == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // The contents are read front to back exactly once, so let the kernel read ahead aggressively
+        // SAFETY: the range was just mapped; madvise is only a hint and its result can be ignored
+        unsafe { sys::madvise(ptr, len, sys::MADV_SEQUENTIAL) };
+
+        Ok(Mapping { ptr: ptr as *mut u8, len })
+    }
+
+    #[cfg(not(all(unix, target_pointer_width = "64")))]
+    pub fn new(_file: &File, _len: usize) -> io::Result<Mapping> {
+        Err(io::Error::new(ErrorKind::Unsupported, "memory mapping is not supported on this platform"))
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        // SAFETY: ptr points at len readable bytes for as long as the mapping lives
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+#[cfg(all(unix, target_pointer_width = "64"))]
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // SAFETY: ptr and len describe a mapping created by Mapping::new
+        unsafe { sys::munmap(self.ptr as *mut std::ffi::c_void, self.len) };
+    }
+}
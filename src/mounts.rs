@@ -0,0 +1,97 @@
+// Filesystem boundaries met by the scan
+//
+// With -x (--one-file-system) the scan stays on the filesystem each source directory is on,
+// like rsync -x: directories where another filesystem is mounted (network mounts included)
+// go into the image empty. On Linux the kernel's pseudo-filesystems (/proc, /sys, /dev and
+// the like) are never descended into even without it, since their files are no data and
+// some never end; they are found by device number in /proc/self/mountinfo.
+
+#[cfg(target_os = "linux")]
+use std::fs;
+#[cfg(target_os = "linux")]
+use std::sync::OnceLock;
+
+// Filesystem types that only present kernel state
+#[cfg(target_os = "linux")]
+const PSEUDO_TYPES: &[&str] = &[
+    "proc", "sysfs", "devtmpfs", "devpts", "cgroup", "cgroup2", "debugfs", "tracefs", "securityfs", "pstore", "bpf", "configfs",
+    "fusectl", "mqueue", "hugetlbfs", "autofs", "binfmt_misc", "efivarfs", "rpc_pipefs", "nsfs", "selinuxfs",
+];
+
+// Whether the scan stops at a directory on the given device, coming from one on parent
+pub fn is_boundary(parent: Option<u64>, device: Option<u64>, one_file_system: bool) -> bool {
+    let Some(device) = device else {
+        return false; // Remote sources have no devices
+    };
+    if parent == Some(device) {
+        return false;
+    }
+    one_file_system || pseudo_devices().contains(&device)
+}
+
+#[cfg(target_os = "linux")]
+fn pseudo_devices() -> &'static [u64] {
+    static DEVICES: OnceLock<Vec<u64>> = OnceLock::new();
+    DEVICES.get_or_init(|| {
+        let Ok(text) = fs::read_to_string("/proc/self/mountinfo") else {
+            return Vec::new();
+        };
+        // 36 35 0:4 / /proc rw,nosuid - proc proc rw
+        text.lines()
+            .filter_map(|line| {
+                let (mount, filesystem) = line.split_once(" - ")?;
+                let kind = filesystem.split(' ').next()?;
+                if !PSEUDO_TYPES.contains(&kind) {
+                    return None;
+                }
+                let (major, minor) = mount.split(' ').nth(2)?.split_once(':')?;
+                Some(make_device(major.parse().ok()?, minor.parse().ok()?))
+            })
+            .collect()
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn pseudo_devices() -> &'static [u64] {
+    &[]
+}
+
+// The disks under a filesystem's device: a partition is on its parent disk, and a device
+// mapper or md device (LVM, dm-crypt, RAID) on the disks under its slaves. Devices that
+// aren't block devices (tmpfs, overlays, network filesystems) have none.
+#[cfg(target_os = "linux")]
+pub fn disks(device: u64) -> Vec<String> {
+    let major = ((device >> 32) & 0xffff_f000) | ((device >> 8) & 0xfff);
+    let minor = ((device >> 12) & 0xffff_ff00) | (device & 0xff);
+    match fs::canonicalize(format!("/sys/dev/block/{}:{}", major, minor)) {
+        Ok(path) => disks_under(&path),
+        Err(_) => Vec::new(),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn disks(_device: u64) -> Vec<String> {
+    Vec::new()
+}
+
+// /sys/devices/.../block/sda/sda1 is on sda; /sys/devices/virtual/block/dm-0 on its slaves
+#[cfg(target_os = "linux")]
+fn disks_under(path: &std::path::Path) -> Vec<String> {
+    if path.join("partition").exists() {
+        return path.parent().map_or_else(Vec::new, disks_under);
+    }
+    let slaves: Vec<_> = fs::read_dir(path.join("slaves")).into_iter().flatten().flatten().filter_map(|slave| fs::canonicalize(slave.path()).ok()).collect();
+    if slaves.is_empty() {
+        return path.file_name().map(|name| vec![name.to_string_lossy().into_owned()]).unwrap_or_default();
+    }
+    let mut disks: Vec<String> = slaves.iter().flat_map(|slave| disks_under(slave)).collect();
+    disks.sort();
+    disks.dedup();
+    disks
+}
+
+// st_dev from a major and minor number, as glibc's makedev encodes them
+#[cfg(target_os = "linux")]
+fn make_device(major: u64, minor: u64) -> u64 {
+    ((major & 0xffff_f000) << 32) | ((major & 0xfff) << 8) | ((minor & 0xffff_ff00) << 12) | (minor & 0xff)
+}
@@ -0,0 +1,231 @@
+// Notifications when a profile's run ends: a webhook, an email or a program of one's own
+//
+//     [profiles.nightly-home]
+//     notify-url = "https://hooks.example.com/backups"    # POSTed a JSON summary
+//     notify-email = "ops@example.com"                    # sent through smtp-url
+//     smtp-url = "smtps://mail.example.com"               # its login in ~/.netrc
+//     smtp-netrc = "/etc/makeiso/netrc"                   # or in this netrc file
+//     notify-command = "/usr/local/bin/backup-finished"   # the summary on stdin and in MAKEISO_*
+//     notify-on = ["failure", "skipped"]
+//     notify-skipped = 10
+//
+// A run is one event: failure, skipped when it succeeded but left out more unreadable files
+// than notify-skipped (0 by default), or success. notify-on picks the events sent (failure
+// and skipped by default). The webhook and the mail go through curl; a notification that
+// can't be sent is reported but doesn't change the run's result. The SMTP login only comes
+// from a netrc file: in the URL it would be on curl's command line for every user to see.
+
+use std::env;
+use std::fs;
+use std::io::{self, ErrorKind, Write};
+use std::process::{Command, Stdio};
+
+use super::config::{string_value, Value};
+use super::daemon::Run;
+use super::{json, time_text};
+
+// What a run amounts to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    Success,
+    Failure,
+    Skipped, // Succeeded, but more files than the threshold couldn't be read
+}
+
+impl Event {
+    fn parse(text: &str) -> io::Result<Event> {
+        match text {
+            "success" => Ok(Event::Success),
+            "failure" => Ok(Event::Failure),
+            "skipped" => Ok(Event::Skipped),
+            _ => Err(io::Error::new(ErrorKind::InvalidInput, format!("unknown notify-on event: {} (expected success, failure or skipped)", text))),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Event::Success => "success",
+            Event::Failure => "failure",
+            Event::Skipped => "skipped",
+        }
+    }
+}
+
+// Where a profile's notifications go
+#[derive(Debug, Clone, Default)]
+pub struct Notify {
+    urls: Vec<String>,
+    emails: Vec<String>,
+    smtp_url: Option<String>,
+    netrc: Option<String>, // The file with the SMTP login, ~/.netrc when not given
+    from: Option<String>, // Sender of the mail, makeiso@<host> when not given
+    commands: Vec<String>,
+    on: Option<Vec<Event>>, // None for the default, failure and skipped
+    skipped_threshold: u64,
+}
+
+// Every string of a key that takes one or a list
+fn strings(key: &str, value: &Value) -> io::Result<Vec<String>> {
+    match value {
+        Value::Array(values) => values.iter().map(|value| string_value(key, value)).collect(),
+        value => Ok(vec![string_value(key, value)?]),
+    }
+}
+
+impl Notify {
+    // Take a profile key if it is one of the notification settings, returning whether it was
+    pub fn set(&mut self, key: &str, value: &Value) -> io::Result<bool> {
+        match (key, value) {
+            ("notify-url", value) => self.urls.extend(strings(key, value)?),
+            ("notify-email", value) => self.emails.extend(strings(key, value)?),
+            ("notify-command", value) => self.commands.extend(strings(key, value)?),
+            ("notify-from", value) => self.from = Some(string_value(key, value)?),
+            ("smtp-url", value) => self.smtp_url = Some(string_value(key, value)?),
+            ("smtp-netrc", value) => self.netrc = Some(string_value(key, value)?),
+            ("notify-on", value) => self.on = Some(strings(key, value)?.iter().map(|event| Event::parse(event)).collect::<io::Result<_>>()?),
+            ("notify-skipped", Value::Integer(count)) if *count >= 0 => self.skipped_threshold = *count as u64,
+            ("notify-skipped", _) => return Err(io::Error::new(ErrorKind::InvalidInput, "notify-skipped must be a number of files")),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    // Settings that can't work together are caught when the profile is read, not after a run
+    pub fn check(&self) -> io::Result<()> {
+        if !self.emails.is_empty() && self.smtp_url.is_none() {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "notify-email needs smtp-url, the server to send through"));
+        }
+        if let Some(smtp_url) = &self.smtp_url {
+            let authority = smtp_url.split_once("://").map_or(smtp_url.as_str(), |(_, rest)| rest).split(['/', '?', '#']).next().unwrap_or_default();
+            if authority.contains('@') {
+                return Err(io::Error::new(ErrorKind::InvalidInput, "smtp-url can't carry the login; put it in ~/.netrc or the file smtp-netrc names"));
+            }
+        }
+        Ok(())
+    }
+
+    fn event(&self, run: &Run) -> Event {
+        if !run.ok {
+            Event::Failure
+        } else if run.skipped > self.skipped_threshold {
+            Event::Skipped
+        } else {
+            Event::Success
+        }
+    }
+
+    // Send whatever the run calls for
+    pub fn send(&self, run: &Run) {
+        let event = self.event(run);
+        let wanted = self.on.as_deref().unwrap_or(&[Event::Failure, Event::Skipped]);
+        if !wanted.contains(&event) {
+            return;
+        }
+        let summary = summary(run, event);
+        for url in &self.urls {
+            if let Err(e) = post(url, &summary) {
+                eprintln!("Could not notify {}: {}", url, e);
+            }
+        }
+        if let (false, Some(smtp_url)) = (self.emails.is_empty(), &self.smtp_url) {
+            if let Err(e) = mail(smtp_url, self.netrc.as_deref(), self.from.as_deref(), &self.emails, run, event) {
+                eprintln!("Could not mail {}: {}", self.emails.join(", "), e);
+            }
+        }
+        for command in &self.commands {
+            if let Err(e) = execute(command, run, event, &summary) {
+                eprintln!("Could not run {}: {}", command, e);
+            }
+        }
+    }
+}
+
+// The machine the run was on
+pub fn host_name() -> String {
+    let from_system = || fs::read_to_string("/proc/sys/kernel/hostname").ok().or_else(|| fs::read_to_string("/etc/hostname").ok());
+    env::var("COMPUTERNAME").ok().or_else(from_system).map(|name| name.trim().to_string()).filter(|name| !name.is_empty()).unwrap_or_else(|| "localhost".to_string())
+}
+
+// The JSON every notification carries
+fn summary(run: &Run, event: Event) -> String {
+    format!(
+        "{{\"profile\": {}, \"event\": \"{}\", \"ok\": {}, \"host\": {}, \"start\": {}, \"seconds\": {}, \"bytes\": {}, \"skipped\": {}, \"image\": {}, \"error\": {}}}\n",
+        json::string(&run.profile),
+        event.name(),
+        run.ok,
+        json::string(&host_name()),
+        run.start,
+        run.seconds,
+        run.bytes,
+        run.skipped,
+        json::string(&run.image),
+        json::string(&run.error)
+    )
+}
+
+// Run curl with something on its standard input
+fn curl(mut command: Command, input: &str) -> io::Result<()> {
+    let mut child = command.stdin(Stdio::piped()).stdout(Stdio::null()).stderr(Stdio::piped()).spawn().map_err(|e| io::Error::new(e.kind(), format!("could not run curl: {}", e)))?;
+    child.stdin.take().expect("curl stdin is piped").write_all(input.as_bytes())?;
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+    }
+    Ok(())
+}
+
+fn post(url: &str, summary: &str) -> io::Result<()> {
+    let mut command = Command::new("curl");
+    command.args(["-sS", "--fail", "--max-time", "60", "-X", "POST", "-H", "Content-Type: application/json", "--data-binary", "@-", url]);
+    curl(command, summary)
+}
+
+fn mail(smtp_url: &str, netrc: Option<&str>, from: Option<&str>, to: &[String], run: &Run, event: Event) -> io::Result<()> {
+    let host = host_name();
+    let from = from.map_or_else(|| format!("makeiso@{}", host), str::to_string);
+    let outcome = match event {
+        Event::Success => "succeeded".to_string(),
+        Event::Failure => "failed".to_string(),
+        Event::Skipped => format!("skipped {} unreadable files", run.skipped),
+    };
+    let mut body = format!("Profile {} on {} {}.\r\n\r\nStarted:  {} UTC\r\nDuration: {} s\r\n", run.profile, host, outcome, time_text(run.start), run.seconds);
+    if run.ok {
+        body.push_str(&format!("Image:    {} ({} bytes)\r\nSkipped:  {} files\r\n", run.image, run.bytes, run.skipped));
+    } else {
+        body.push_str(&format!("Error:    {}\r\n", run.error));
+    }
+    let message = format!("From: {}\r\nTo: {}\r\nSubject: makeiso: {} {} on {}\r\n\r\n{}", from, to.join(", "), run.profile, outcome, host, body);
+
+    let mut command = Command::new("curl");
+    command.args(["-sS", "--max-time", "60"]);
+    match netrc {
+        Some(netrc) => command.args(["--netrc-file", netrc]),
+        None => command.arg("--netrc-optional"),
+    };
+    command.args(["--url", smtp_url, "--mail-from", &from]);
+    for recipient in to {
+        command.args(["--mail-rcpt", recipient]);
+    }
+    command.args(["-T", "-"]);
+    curl(command, &message)
+}
+
+fn execute(program: &str, run: &Run, event: Event, summary: &str) -> io::Result<()> {
+    let mut child = Command::new(program)
+        .env("MAKEISO_PROFILE", &run.profile)
+        .env("MAKEISO_EVENT", event.name())
+        .env("MAKEISO_IMAGE", &run.image)
+        .env("MAKEISO_BYTES", run.bytes.to_string())
+        .env("MAKEISO_SKIPPED", run.skipped.to_string())
+        .env("MAKEISO_SECONDS", run.seconds.to_string())
+        .env("MAKEISO_ERROR", &run.error)
+        .stdin(Stdio::piped())
+        .spawn()?;
+    // A program that doesn't read its input closes the pipe early, which is fine
+    let _ = child.stdin.take().expect("stdin is piped").write_all(summary.as_bytes());
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("it exited with {}", status)));
+    }
+    Ok(())
+}
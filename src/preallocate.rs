@@ -0,0 +1,159 @@
+// Room for the image before it is written. Once the layout is planned, the filesystem the
+// image goes to must have room for it and the files written next to it (a destination without
+// it fails at once, with how much is needed, rather than after writing most of it), and a
+// destination on the same disk as the sources is warned about. Then a local output is
+// allocated at its final size up front (fallocate on Linux, an allocation size on Windows),
+// so the filesystem can place it in few extents.
+//
+// Filesystems that can't allocate ahead (and outputs that aren't regular files) are written as
+// they go; the free space is checked where the platform can tell it.
+
+use std::fs::{self, File};
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use super::browse::human_size;
+use super::{backend, events, mounts};
+
+#[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+mod sys {
+    use std::ffi::{c_char, c_int, c_ulong};
+
+    // struct statvfs of 64-bit Linux
+    #[repr(C)]
+    #[derive(Default)]
+    pub struct StatVfs {
+        pub f_bsize: c_ulong,
+        pub f_frsize: c_ulong,
+        pub f_blocks: u64,
+        pub f_bfree: u64,
+        pub f_bavail: u64,
+        pub f_files: u64,
+        pub f_ffree: u64,
+        pub f_favail: u64,
+        pub f_fsid: c_ulong,
+        pub f_flag: c_ulong,
+        pub f_namemax: c_ulong,
+        pub f_spare: [c_int; 6],
+    }
+
+    extern "C" {
+        pub fn fallocate(fd: c_int, mode: c_int, offset: i64, len: i64) -> c_int;
+        pub fn statvfs(path: *const c_char, buf: *mut StatVfs) -> c_int;
+    }
+}
+
+// Before anything is created: whether the filesystem of the output has the `need` bytes (0
+// when the size isn't known, as under --single-pass), and whether it is on a disk that one of
+// the sources is read from too
+pub fn check_destination(output: &Path, need: u64, sources: &[PathBuf]) -> io::Result<()> {
+    // Devices written in place have whatever room they have
+    if backend::is_remote(output) || fs::metadata(output).is_ok_and(|metadata| !metadata.is_file()) {
+        return Ok(());
+    }
+    let free = free_space(output);
+    if need > 0 && free.is_some_and(|free| free < need) {
+        return Err(no_space(need, free, output));
+    }
+
+    let device = |path: &Path| fs::metadata(path).ok().and_then(|metadata| backend::file_identity(&metadata)).map(|(device, _)| device);
+    let Some(destination) = device(directory_of(output)) else {
+        return Ok(());
+    };
+    let destination_disks = mounts::disks(destination);
+    for source in sources.iter().filter(|source| !backend::is_remote(source)) {
+        let Some(shared) = device(source).and_then(|device| mounts::disks(device).into_iter().find(|disk| destination_disks.contains(disk))) else {
+            continue;
+        };
+        events::warn(&format!("{} is on the same disk ({}) as the image; reading and writing share it, so expect about half the throughput", source.display(), shared));
+        break;
+    }
+    Ok(())
+}
+
+// Allocate `size` bytes for the output just created at path; nothing to do for an empty or
+// streamed image (size 0) or an output that is a device
+pub fn reserve(file: &File, size: u64, path: &Path) -> io::Result<()> {
+    if size == 0 || !file.metadata()?.is_file() {
+        return Ok(());
+    }
+    match allocate(file, size) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::StorageFull => Err(no_space(size, free_space(path), path)),
+        // The filesystem can't allocate ahead; the image is written as it goes
+        Err(_) => Ok(()),
+    }
+}
+
+fn no_space(size: u64, free: Option<u64>, path: &Path) -> io::Error {
+    let have = free.map_or(String::new(), |free| format!(", have {}", human_size(free)));
+    io::Error::new(ErrorKind::StorageFull, format!("not enough space for {}: need {}{}", path.display(), human_size(size), have))
+}
+
+#[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+fn allocate(file: &File, size: u64) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let len = i64::try_from(size).map_err(|_| io::Error::new(ErrorKind::InvalidInput, "the image is too large to allocate"))?;
+    // SAFETY: the descriptor is open for the call; mode 0 allocates and extends the file
+    if unsafe { sys::fallocate(file.as_raw_fd(), 0, 0, len) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// The allocation size of a handle is what NTFS reserves for the file; its length stays 0
+#[cfg(windows)]
+fn allocate(file: &File, size: u64) -> io::Result<()> {
+    use std::os::windows::io::AsRawHandle;
+    use windows::Win32::Foundation::HANDLE;
+    use windows::Win32::Storage::FileSystem::{FileAllocationInfo, SetFileInformationByHandle, FILE_ALLOCATION_INFO};
+
+    let information = FILE_ALLOCATION_INFO { AllocationSize: size as i64 };
+    // SAFETY: the handle stays open for the call and information is as large as the size given
+    unsafe { SetFileInformationByHandle(HANDLE(file.as_raw_handle()), FileAllocationInfo, &information as *const _ as *const _, std::mem::size_of::<FILE_ALLOCATION_INFO>() as u32) }
+        .map_err(|e| io::Error::from_raw_os_error(e.code().0 & 0xffff))
+}
+
+#[cfg(not(any(all(target_os = "linux", target_pointer_width = "64"), windows)))]
+fn allocate(_file: &File, _size: u64) -> io::Result<()> {
+    Err(io::Error::new(ErrorKind::Unsupported, "allocating ahead is not supported on this platform"))
+}
+
+// The directory an output is (or will be) created in
+fn directory_of(path: &Path) -> &Path {
+    path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."))
+}
+
+// Bytes the filesystem of the output at path has free for this user, if the platform can tell
+#[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+fn free_space(path: &Path) -> Option<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let directory = CString::new(directory_of(path).as_os_str().as_bytes()).ok()?;
+    let mut stat = sys::StatVfs::default();
+    // SAFETY: directory is NUL-terminated and stat is a valid out pointer
+    if unsafe { sys::statvfs(directory.as_ptr(), &mut stat) } < 0 {
+        return None;
+    }
+    Some(stat.f_bavail.saturating_mul(stat.f_frsize))
+}
+
+#[cfg(windows)]
+fn free_space(path: &Path) -> Option<u64> {
+    use std::os::windows::ffi::OsStrExt;
+    use windows::core::PCWSTR;
+    use windows::Win32::Storage::FileSystem::GetDiskFreeSpaceExW;
+
+    let name: Vec<u16> = directory_of(path).as_os_str().encode_wide().chain([0]).collect();
+    let mut free = 0u64;
+    // SAFETY: name is NUL-terminated and free is a valid out pointer
+    unsafe { GetDiskFreeSpaceExW(PCWSTR(name.as_ptr()), Some(&mut free), None, None) }.ok()?;
+    Some(free)
+}
+
+#[cfg(not(any(all(target_os = "linux", target_pointer_width = "64"), windows)))]
+fn free_space(_path: &Path) -> Option<u64> {
+    None
+}
@@ -0,0 +1,128 @@
+// Read-ahead of upcoming files on worker threads, overlapped with the sequential writes of the
+// image. Helps when the source is on a high-latency filesystem (NFS, SMB) where waiting for
+// each file in turn leaves the output idle.
+
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind, Read};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::Mutex;
+use std::thread;
+
+use super::backend::{SourceMetadata, SourceProvider};
+use super::events;
+use super::{fill_to_block, finish_file_contents, note_denied, pad_to_block, settle_file, FileEntry, ImageOptions, ImageOutput, Progress};
+
+const CHUNK_SIZE: usize = 1024 * 1024; // Files are handed to the writer in 1 MiB chunks
+const CHUNKS_PER_FILE: usize = 4; // Chunks a worker may read ahead of the writer for one file
+const FILES_PER_WORKER: usize = 2; // Files in flight per worker
+
+// What a worker sends back for a file
+enum Chunk {
+    Data(Vec<u8>),
+    Denied,                              // The file couldn't be opened
+    End(io::Result<(bool, SourceMetadata)>), // Whether the file grew past the planned size, and its metadata
+}
+
+type Job<'a> = (&'a FileEntry, SyncSender<Chunk>);
+
+// Write the contents of all files in order while worker threads read the next ones
+pub fn write_files<W: ImageOutput>(writer: &mut W, source: &dyn SourceProvider, files: &[&FileEntry], options: &ImageOptions, progress: &mut Progress) -> io::Result<()> {
+    let (job_sender, job_receiver) = mpsc::channel::<Job>();
+    let job_receiver = Mutex::new(job_receiver);
+
+    thread::scope(|scope| {
+        for _ in 0..options.read_workers {
+            scope.spawn(|| read_files(source, &job_receiver));
+        }
+
+        // Dropping the job sender and the pending receivers on return stops the workers
+        let job_sender = job_sender;
+        let mut in_flight: VecDeque<Receiver<Chunk>> = VecDeque::new();
+        let mut next = 0;
+
+        for file in files {
+            while next < files.len() && in_flight.len() < options.read_workers * FILES_PER_WORKER {
+                let (chunk_sender, chunk_receiver) = mpsc::sync_channel(CHUNKS_PER_FILE);
+                job_sender.send((files[next], chunk_sender)).map_err(|_| io::Error::other("read workers stopped"))?;
+                in_flight.push_back(chunk_receiver);
+                next += 1;
+            }
+
+            let chunks = in_flight.pop_front().expect("a receiver for every file");
+            fill_to_block(writer, file.extent)?;
+            let processed_before = progress.bytes_processed;
+            let change = write_chunks(writer, file, &chunks, progress)?;
+            settle_file(writer, source, file, options, progress, change, processed_before)?;
+            progress.file_done();
+        }
+        Ok(())
+    })
+}
+
+// Worker loop: read each file handed out, in chunks, into its channel
+fn read_files(provider: &dyn SourceProvider, jobs: &Mutex<Receiver<Job>>) {
+    loop {
+        let job = jobs.lock().unwrap_or_else(|e| e.into_inner()).recv();
+        let Ok((file, chunks)) = job else {
+            return; // No more files
+        };
+
+        let mut source = match provider.open(&file.source) {
+            Ok(source) => source,
+            Err(e) if e.kind() == ErrorKind::PermissionDenied => {
+                let _ = chunks.send(Chunk::Denied);
+                continue;
+            }
+            Err(e) => {
+                let _ = chunks.send(Chunk::End(Err(e)));
+                continue;
+            }
+        };
+
+        let result = (|| {
+            let mut remaining = file.size as usize;
+            while remaining > 0 {
+                let mut buffer = vec![0u8; remaining.min(CHUNK_SIZE)];
+                let bytes_read = source.read(&mut buffer)?;
+                if bytes_read == 0 {
+                    break;
+                }
+                buffer.truncate(bytes_read);
+                remaining -= bytes_read;
+                if chunks.send(Chunk::Data(buffer)).is_err() {
+                    return Err(io::Error::other("writer stopped"));
+                }
+            }
+            let grew = source.read(&mut [0u8; 1])? > 0;
+            Ok((grew, source.metadata()?))
+        })();
+        let _ = chunks.send(Chunk::End(result));
+    }
+}
+
+// Write the chunks of one file as they arrive; returns how it changed compared to the scan
+fn write_chunks<W: ImageOutput>(writer: &mut W, file: &FileEntry, chunks: &Receiver<Chunk>, progress: &mut Progress) -> io::Result<Option<&'static str>> {
+    let mut remaining = file.size as usize;
+    loop {
+        match chunks.recv() {
+            Ok(Chunk::Data(data)) => {
+                writer.write_all(&data)?;
+                remaining -= data.len();
+                progress.advance(data.len())?;
+            }
+            Ok(Chunk::Denied) => {
+                // The extent is already reserved, so leave it zero filled
+                events::warn(&format!("Permission denied while accessing file: {}", file.source.display()));
+                note_denied(&file.source);
+                writer.write_all(&vec![0u8; file.size as usize])?;
+                pad_to_block(writer, file.size as usize)?;
+                return Ok(None);
+            }
+            Ok(Chunk::End(result)) => {
+                let (grew, metadata) = result?;
+                return finish_file_contents(writer, file, remaining, grew, &metadata);
+            }
+            Err(_) => return Err(io::Error::other(format!("read worker stopped while reading {}", file.source.display()))),
+        }
+    }
+}
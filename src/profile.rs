@@ -0,0 +1,140 @@
+// Backup jobs kept as named profiles in the configuration file (makeiso run <profile>)
+//
+// A profile is a [profiles.<name>] table with the sources, any build options and an output
+// template, on top of the file's top-level defaults:
+//
+//     [profiles.nightly-home]
+//     source = "/home"
+//     exclude = ["*/.cache"]
+//     output = "/backups/home-%Y%m%d.iso"
+//     keep = 7
+//
+// With keep, the images the same template gave earlier (in the directory of the new one) are
+// removed after a successful run, keeping the newest, with the checksums and signatures next
+// to them. Each run is recorded for makeiso status (see daemon.rs), and can be announced
+// (see notify.rs).
+
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use super::backend;
+use super::checkpoint;
+use super::config::{self, Profile};
+use super::daemon::{self, Run};
+use super::{build_image, parse_args, skipped_files, unix_time};
+
+const USAGE: &str = "Usage: makeiso run [--config FILE] <profile> [options]";
+const SIDECARS: [&str; 3] = [".sha256", ".sha1", ".sig"]; // Written next to images by --output-hash and --sign
+
+// makeiso run [--config FILE] <profile> [options]: build the image a profile describes
+pub fn run(args: &[String]) -> io::Result<()> {
+    // --config comes before the profile name, the other options after it
+    let mut at = 0;
+    while let Some(arg) = args.get(at) {
+        match arg.as_str() {
+            "--config" => at += 2,
+            _ if arg.starts_with("--config=") => at += 1,
+            _ => break,
+        }
+    }
+    let (config_args, rest) = args.split_at(at.min(args.len()));
+    let Some((name, extra)) = rest.split_first() else {
+        let names = config::profile_names(config_args)?;
+        if names.is_empty() {
+            return Err(io::Error::new(ErrorKind::InvalidInput, format!("{}\nThe configuration file has no profiles", USAGE)));
+        }
+        println!("Profiles:");
+        for name in names {
+            println!("    {}", name);
+        }
+        return Ok(());
+    };
+    let rest = [config_args, extra].concat();
+    let (extra, profile) = config::load_profile(&rest, name)?;
+
+    // Profile options, then the command line's, then the sources and the output
+    let iso_path = config::output_path(&profile.output)?;
+    let mut build_args = vec![format!("--config={}", profile.config.display())];
+    build_args.extend(profile.args.iter().cloned());
+    build_args.extend(extra);
+    build_args.extend(profile.sources.iter().cloned());
+    build_args.push(iso_path.display().to_string());
+    let mut options = parse_args(&build_args)?;
+    // Backups describe themselves in .makeiso unless asked not to
+    options.image.embed_metadata.get_or_insert(true);
+    options.image.run_profile = Some(name.clone());
+    // Kept where makeiso status <profile> looks for it
+    if options.image.status_file.is_none() {
+        options.image.status_file = checkpoint::job_status_file(name);
+    }
+
+    println!("Running profile {}: {} -> {}", name, profile.sources.join(", "), iso_path.display());
+    let started = SystemTime::now();
+    let result = build_image(&options.sources, &iso_path, &options.image).and_then(|()| match profile.keep {
+        Some(keep) => rotate(&profile, &iso_path, keep),
+        None => Ok(()),
+    });
+
+    // Every run goes into the record makeiso status reads
+    let run = Run {
+        start: unix_time(started),
+        profile: name.clone(),
+        ok: result.is_ok(),
+        seconds: started.elapsed().unwrap_or_default().as_secs(),
+        bytes: if result.is_err() || backend::is_remote(&iso_path) { 0 } else { fs::metadata(&iso_path).map_or(0, |metadata| metadata.len()) },
+        skipped: skipped_files(),
+        image: iso_path.display().to_string(),
+        error: result.as_ref().err().map(|e| e.to_string()).unwrap_or_default(),
+    };
+    if let Err(e) = daemon::record(&run) {
+        eprintln!("Could not record the run: {}", e);
+    }
+    profile.notify.send(&run);
+    result
+}
+
+// Remove all but the newest `keep` images the profile's template names
+fn rotate(profile: &Profile, iso_path: &Path, keep: usize) -> io::Result<()> {
+    if backend::is_remote(iso_path) {
+        eprintln!("Old images are only removed for local outputs, so keep is ignored");
+        return Ok(());
+    }
+    let template = Path::new(&profile.output);
+    let (Some(directory), Some(name_template)) = (iso_path.parent(), template.file_name().and_then(|name| name.to_str())) else {
+        return Ok(());
+    };
+    if template.parent().is_some_and(|parent| parent.to_string_lossy().contains('%')) {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "keep needs the time fields of the output template in the file name"));
+    }
+    let directory = if directory.as_os_str().is_empty() { Path::new(".") } else { directory };
+
+    let mut images: Vec<(SystemTime, PathBuf)> = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let Some(file_name) = file_name.to_str() else {
+            continue;
+        };
+        if config::template_matches(name_template, file_name) && entry.file_type()?.is_file() {
+            images.push((entry.metadata()?.modified()?, entry.path()));
+        }
+    }
+    // Newest first; the one just written is kept whatever its time says
+    images.sort_by(|a, b| b.cmp(a));
+    images.retain(|(_, path)| path.file_name() != iso_path.file_name());
+    for (_, old) in images.iter().skip(keep.saturating_sub(1)) {
+        fs::remove_file(old)?;
+        for sidecar in SIDECARS {
+            let mut path = old.clone().into_os_string();
+            path.push(sidecar);
+            match fs::remove_file(&path) {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        println!("Removed old image {}", old.display());
+    }
+    Ok(())
+}
@@ -0,0 +1,236 @@
+// makeiso prune: a retention policy for a directory of images. The images in --target are
+// put in order by when they were built: as the catalog database recorded it, else from a date
+// in the name (backup-2024-05-01.iso, nightly-20240501.iso), else from the time the file was
+// last modified. Each --keep-* rule keeps the newest image of each of that many periods, the
+// most recent periods with images first, as restic and borg count them (--keep-daily 7 keeps
+// the newest image of each of the last seven days that have one); the rest are deleted, or
+// moved to --demote-to (a slower disk, say), along with the files that go next to each image
+// (.sha256, .sha1, .sig, .zsync).
+//
+// Nothing is touched with --dry-run. With no rule at all prune refuses, rather than delete
+// every image.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use super::catalogdb;
+use super::{parse_date, time_text, unix_time, utc_date_time};
+
+const USAGE: &str = "Usage: makeiso prune --target DIR [--keep-last N] [--keep-daily N] [--keep-weekly N] [--keep-monthly N] [--keep-yearly N] [--demote-to DIR] [--catalog-db FILE] [--dry-run]";
+const SIDECARS: [&str; 4] = [".sha256", ".sha1", ".sig", ".zsync"];
+
+// The rules, by the period each counts in
+#[derive(Clone, Copy)]
+enum Period {
+    Last,
+    Day,
+    Week,
+    Month,
+    Year,
+}
+
+impl Period {
+    fn name(self) -> &'static str {
+        match self {
+            Period::Last => "last",
+            Period::Day => "daily",
+            Period::Week => "weekly",
+            Period::Month => "monthly",
+            Period::Year => "yearly",
+        }
+    }
+
+    // Which period of its kind a time falls in; Last counts every image as a period of its own
+    fn of(self, time: i64, index: usize) -> i64 {
+        let day = time.div_euclid(86_400);
+        let (year, month, ..) = utc_date_time(time);
+        match self {
+            Period::Last => index as i64,
+            Period::Day => day,
+            Period::Week => (day + 3).div_euclid(7), // Weeks from Monday; 1970-01-01 was a Thursday
+            Period::Month => year * 12 + month as i64,
+            Period::Year => year,
+        }
+    }
+}
+
+struct Image {
+    path: PathBuf,
+    time: i64,
+    modified: i64,
+}
+
+// makeiso prune --target DIR [--keep-* N ...] [--demote-to DIR] [--catalog-db FILE] [--dry-run]
+pub fn prune(args: &[String]) -> io::Result<()> {
+    let (mut target, mut demote, mut db, mut dry_run) = (None, None, None, false);
+    let mut rules = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().cloned().ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, format!("{} requires a value", arg)));
+        let period = match arg.as_str() {
+            "--target" => {
+                target = Some(PathBuf::from(value()?));
+                continue;
+            }
+            "--demote-to" => {
+                demote = Some(PathBuf::from(value()?));
+                continue;
+            }
+            "--catalog-db" => {
+                db = Some(PathBuf::from(value()?));
+                continue;
+            }
+            "--dry-run" => {
+                dry_run = true;
+                continue;
+            }
+            "--keep-last" => Period::Last,
+            "--keep-daily" => Period::Day,
+            "--keep-weekly" => Period::Week,
+            "--keep-monthly" => Period::Month,
+            "--keep-yearly" => Period::Year,
+            _ => return Err(io::Error::new(ErrorKind::InvalidInput, USAGE)),
+        };
+        let count = value()?;
+        let count: usize = count.parse().map_err(|_| io::Error::new(ErrorKind::InvalidInput, format!("invalid {}: {} (expected a number of images)", arg, count)))?;
+        rules.push((period, count));
+    }
+    let target = target.ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, USAGE))?;
+    if rules.iter().all(|&(_, count)| count == 0) {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "prune needs a --keep-* rule that keeps something; it won't delete every image"));
+    }
+    let db = match db {
+        Some(db) => Some(db),
+        None => catalogdb::default_db().ok(),
+    };
+
+    let mut images = find_images(&target, db.as_deref())?;
+    images.sort_by_key(|image| std::cmp::Reverse((image.time, image.modified)));
+
+    // Why each image is kept, newest first
+    let mut reasons: Vec<Vec<String>> = vec![Vec::new(); images.len()];
+    for &(period, count) in &rules {
+        let mut seen = HashSet::new();
+        for (index, image) in images.iter().enumerate() {
+            if seen.len() == count {
+                break;
+            }
+            if seen.insert(period.of(image.time, index)) {
+                reasons[index].push(period.name().to_string());
+            }
+        }
+    }
+
+    let (mut kept, mut removed) = (0, 0);
+    for (image, reasons) in images.iter().zip(&reasons) {
+        if !reasons.is_empty() {
+            println!("{:<12}  {}  {} ({})", "keep", time_text(image.time), image.path.display(), reasons.join(", "));
+            kept += 1;
+            continue;
+        }
+        removed += 1;
+        let mut paths = vec![image.path.clone()];
+        paths.extend(SIDECARS.iter().map(|suffix| sidecar(&image.path, suffix)).filter(|path| path.exists()));
+        match &demote {
+            Some(dir) => {
+                println!("{:<12}  {}  {} -> {}", if dry_run { "would demote" } else { "demote" }, time_text(image.time), image.path.display(), dir.display());
+                if !dry_run {
+                    fs::create_dir_all(dir)?;
+                    for path in &paths {
+                        move_file(path, &dir.join(path.file_name().unwrap_or_default()))?;
+                    }
+                }
+            }
+            None => {
+                println!("{:<12}  {}  {}", if dry_run { "would delete" } else { "delete" }, time_text(image.time), image.path.display());
+                if !dry_run {
+                    for path in &paths {
+                        fs::remove_file(path).map_err(|e| io::Error::new(e.kind(), format!("could not delete {}: {}", path.display(), e)))?;
+                    }
+                }
+            }
+        }
+    }
+    let verb = match (&demote, dry_run) {
+        (Some(_), true) => "would be demoted",
+        (Some(_), false) => "demoted",
+        (None, true) => "would be deleted",
+        (None, false) => "deleted",
+    };
+    println!("{} kept, {} {}", kept, removed, verb);
+    Ok(())
+}
+
+// The images in the target directory (not its subdirectories) and when each was built
+fn find_images(target: &Path, db: Option<&Path>) -> io::Result<Vec<Image>> {
+    let built = match db {
+        Some(db) => catalogdb::built_images(db)?,
+        None => Default::default(),
+    };
+    let listing = fs::read_dir(target).map_err(|e| io::Error::new(e.kind(), format!("could not read {}: {}", target.display(), e)))?;
+    let mut images = Vec::new();
+    for entry in listing {
+        let entry = entry?;
+        let path = entry.path();
+        let metadata = entry.metadata()?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let canonical = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+        // Images the catalog has, whatever they are called, and the .iso files
+        let recorded = built.get(&canonical).copied();
+        if !metadata.is_file() || (recorded.is_none() && !name.to_ascii_lowercase().ends_with(".iso")) {
+            continue;
+        }
+        let modified = metadata.modified().map(unix_time).unwrap_or(0);
+        images.push(Image { path, time: recorded.or_else(|| name_date(&name)).unwrap_or(modified), modified });
+    }
+    Ok(images)
+}
+
+// A date in a file name: YYYY-MM-DD or YYYYMMDD, not part of a longer run of digits
+fn name_date(name: &str) -> Option<i64> {
+    let bytes = name.as_bytes();
+    (0..bytes.len()).find_map(|start| {
+        if start > 0 && bytes[start - 1].is_ascii_digit() {
+            return None;
+        }
+        [10, 8].into_iter().find_map(|len| {
+            let candidate = name.get(start..start + len)?;
+            if bytes.get(start + len).is_some_and(u8::is_ascii_digit) {
+                return None;
+            }
+            let date = if len == 8 {
+                if !candidate.bytes().all(|byte| byte.is_ascii_digit()) {
+                    return None;
+                }
+                format!("{}-{}-{}", &candidate[..4], &candidate[4..6], &candidate[6..])
+            } else {
+                let parts: Vec<&str> = candidate.split('-').collect();
+                if parts.iter().map(|part| part.len()).collect::<Vec<_>>() != [4, 2, 2] || !parts.iter().all(|part| part.bytes().all(|byte| byte.is_ascii_digit())) {
+                    return None;
+                }
+                candidate.to_string()
+            };
+            parse_date(&date).ok()
+        })
+    })
+}
+
+fn sidecar(image: &Path, suffix: &str) -> PathBuf {
+    let mut path = image.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+// Rename, or copy and delete when the destination is on another filesystem
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if to.exists() {
+        return Err(io::Error::new(ErrorKind::AlreadyExists, format!("{} already exists", to.display())));
+    }
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    fs::copy(from, to).map_err(|e| io::Error::new(e.kind(), format!("could not copy {} to {}: {}", from.display(), to.display(), e)))?;
+    fs::remove_file(from)
+}
@@ -0,0 +1,309 @@
+// Raw CD sector images (.bin, usually described by a .cue sheet)
+//
+// Disc dumps often keep whole 2352-byte sectors: a 12-byte sync pattern, a 4-byte header and,
+// for Mode 2, an 8-byte subheader in front of the 2048 bytes of user data (followed by error
+// correction codes). The reader sees such images through RawSectors, which maps image offsets
+// to the user data of each sector. Without a cue sheet the layout is detected from where the
+// first volume descriptor turns up.
+//
+// The other way, mode1_sector makes whole Mode 1 sectors for the data track of a mixed-mode
+// disc (makeiso audio --data): header, EDC and the P and Q parity of ECMA-130 annex A.
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use super::reader::ReadAt;
+use super::{BLOCK_SIZE, CD001, CDROM, SYSTEM_AREA_BLOCKS};
+
+const SYNC: [u8; 12] = [0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00];
+pub const RAW_SECTOR_SIZE: u64 = 2352;
+const MODE2_SECTOR_SIZE: u64 = 2336; // Mode 2 without the sync pattern and header
+pub const FRAMES_PER_SECOND: u64 = 75; // Cue sheet times are minutes, seconds and frames (sectors)
+const SECTORS_PER_READ: u64 = 256; // Raw sectors read at once when a read spans many of them
+pub const LEAD_IN_SECTORS: u64 = 150; // The 2-second pregap of track 1, ahead of sector 0
+const EDC_POLYNOMIAL: u32 = 0xD801_8001; // x^32 + x^31 + x^16 + x^15 + x^4 + x^3 + x + 1, reflected
+
+// Where the user data of each sector is within a (data track of a) raw image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SectorFormat {
+    pub sector_size: u64,
+    pub data_offset: u64,     // Sync, header and subheader in front of the user data
+    pub start: u64,           // Byte offset of the track in the file
+    pub sectors: Option<u64>, // Length of the track when a cue sheet tells
+}
+
+impl SectorFormat {
+    fn plain(sector_size: u64, data_offset: u64) -> SectorFormat {
+        SectorFormat { sector_size, data_offset, start: 0, sectors: None }
+    }
+
+    fn describe(&self) -> &'static str {
+        match (self.sector_size, self.data_offset) {
+            (RAW_SECTOR_SIZE, 16) => "Mode 1, 2352-byte sectors",
+            (RAW_SECTOR_SIZE, _) => "Mode 2 Form 1, 2352-byte sectors",
+            (MODE2_SECTOR_SIZE, _) => "Mode 2 Form 1, 2336-byte sectors",
+            _ => "2048-byte sectors",
+        }
+    }
+}
+
+// An image stored as raw sectors, read as if it held only the user data
+pub struct RawSectors<R> {
+    source: R,
+    format: SectorFormat,
+}
+
+impl<R: ReadAt> RawSectors<R> {
+    pub fn new(source: R, format: SectorFormat) -> RawSectors<R> {
+        RawSectors { source, format }
+    }
+
+    pub fn source(&self) -> &R {
+        &self.source
+    }
+
+    // How much user data a raw image of raw_len bytes holds, the part of a cut-off last sector
+    // included
+    pub fn data_len(&self, raw_len: u64) -> u64 {
+        let SectorFormat { sector_size, data_offset, start, sectors } = self.format;
+        let track = raw_len.saturating_sub(start).min(sectors.map_or(u64::MAX, |sectors| sectors * sector_size));
+        track / sector_size * BLOCK_SIZE as u64 + (track % sector_size).saturating_sub(data_offset).min(BLOCK_SIZE as u64)
+    }
+}
+
+impl<R: ReadAt> ReadAt for RawSectors<R> {
+    fn read_exact_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        let format = self.format;
+        let block_size = BLOCK_SIZE as u64;
+        let mut done = 0;
+        let mut raw = Vec::new();
+
+        // Read runs of whole raw sectors and pick the user data out of each
+        while done < buf.len() {
+            let position = offset + done as u64;
+            let first = position / block_size;
+            let last = (offset + buf.len() as u64 - 1) / block_size;
+            let count = (last - first + 1).min(SECTORS_PER_READ);
+            if format.sectors.is_some_and(|sectors| first + count > sectors) {
+                return Err(io::Error::new(ErrorKind::UnexpectedEof, "read past the end of the data track"));
+            }
+
+            raw.resize((count * format.sector_size) as usize, 0);
+            self.source.read_exact_at(format.start + first * format.sector_size, &mut raw)?;
+            for (index, sector) in raw.chunks(format.sector_size as usize).enumerate() {
+                let data = &sector[format.data_offset as usize..format.data_offset as usize + BLOCK_SIZE];
+                let block_start = (first + index as u64) * block_size;
+                let from = (offset + done as u64 - block_start) as usize;
+                let len = (BLOCK_SIZE - from).min(buf.len() - done);
+                buf[done..done + len].copy_from_slice(&data[from..from + len]);
+                done += len;
+                if done == buf.len() {
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Work out the sector layout of an image from where its first volume descriptor is;
+// None for plain 2048-byte images
+pub fn detect<R: ReadAt>(source: &mut R) -> io::Result<Option<SectorFormat>> {
+    let mut descriptor = [0u8; 14];
+    if source.read_exact_at(SYSTEM_AREA_BLOCKS as u64 * BLOCK_SIZE as u64, &mut descriptor).is_ok() && is_descriptor(&descriptor) {
+        return Ok(None);
+    }
+
+    // Raw sectors start with the sync pattern; the mode byte says whether a subheader follows
+    let mut header = [0u8; 24];
+    if source.read_exact_at(SYSTEM_AREA_BLOCKS as u64 * RAW_SECTOR_SIZE, &mut header).is_ok() && header[..12] == SYNC {
+        let format = match header[15] {
+            1 => SectorFormat::plain(RAW_SECTOR_SIZE, 16),
+            2 if header[18] & 0x20 == 0 => SectorFormat::plain(RAW_SECTOR_SIZE, 24),
+            mode => return Err(io::Error::new(ErrorKind::InvalidData, format!("unsupported raw sector mode {} (only Mode 1 and Mode 2 Form 1 hold ISO 9660)", mode))),
+        };
+        return Ok(Some(format));
+    }
+
+    // Mode 2 sectors without sync and header still carry the subheader
+    let mode2 = SectorFormat::plain(MODE2_SECTOR_SIZE, 8);
+    if source.read_exact_at(SYSTEM_AREA_BLOCKS as u64 * MODE2_SECTOR_SIZE + 8, &mut descriptor).is_ok() && is_descriptor(&descriptor) {
+        return Ok(Some(mode2));
+    }
+
+    // Let the reader report what's wrong with it as a plain image
+    Ok(None)
+}
+
+// The start of an ISO 9660 or High Sierra volume descriptor
+fn is_descriptor(start: &[u8; 14]) -> bool {
+    &start[1..6] == CD001 || &start[9..14] == CDROM
+}
+
+// The data file and sector layout of the first data track of a cue sheet
+pub fn parse_cue(cue_path: &Path) -> io::Result<(PathBuf, SectorFormat)> {
+    let sheet = fs::read_to_string(cue_path)?;
+    let invalid = |message: &str| io::Error::new(ErrorKind::InvalidData, format!("{}: {}", cue_path.display(), message));
+
+    let mut file: Option<PathBuf> = None;
+    let mut track: Option<(PathBuf, SectorFormat)> = None;
+    let mut track_start = None; // INDEX 01 of the data track, in sectors of its file
+    let mut after_track = false; // Past the TRACK line of the next track in the same file
+
+    for line in sheet.lines() {
+        let line = line.trim();
+        let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        match command.to_ascii_uppercase().as_str() {
+            "FILE" if track.is_some() => break, // The data track ends with its file
+            "FILE" => {
+                // FILE "name with spaces.bin" BINARY, relative to the cue sheet
+                let name = match rest.strip_prefix('"') {
+                    Some(quoted) => quoted.split('"').next().unwrap_or(""),
+                    None => rest.rsplit_once(char::is_whitespace).map_or(rest, |(name, _)| name),
+                };
+                file = Some(cue_path.parent().unwrap_or(Path::new("")).join(name));
+            }
+            "TRACK" if track.is_some() => after_track = true,
+            "TRACK" => {
+                let mode = rest.split_whitespace().nth(1).unwrap_or("").to_ascii_uppercase();
+                let format = match mode.as_str() {
+                    "MODE1/2048" | "MODE2/2048" => SectorFormat::plain(BLOCK_SIZE as u64, 0),
+                    "MODE1/2352" => SectorFormat::plain(RAW_SECTOR_SIZE, 16),
+                    "MODE2/2352" => SectorFormat::plain(RAW_SECTOR_SIZE, 24),
+                    "MODE2/2336" => SectorFormat::plain(MODE2_SECTOR_SIZE, 8),
+                    _ => continue, // Audio and other tracks hold no file system
+                };
+                let file = file.clone().ok_or_else(|| invalid("TRACK before FILE"))?;
+                track = Some((file, format));
+            }
+            "INDEX" => {
+                let Some((_, format)) = &mut track else {
+                    continue;
+                };
+                let mut fields = rest.split_whitespace();
+                if fields.next().and_then(|number| number.parse::<u32>().ok()) != Some(1) {
+                    continue; // Pregaps (INDEX 00) belong to the track before
+                }
+                let sectors = fields.next().and_then(parse_msf).ok_or_else(|| invalid("bad INDEX time"))?;
+                match track_start {
+                    Some(start) if after_track => {
+                        // The next track starts where the data track ends
+                        format.sectors = Some(sectors.saturating_sub(start));
+                        break;
+                    }
+                    Some(_) => {}
+                    None => {
+                        format.start = sectors * format.sector_size;
+                        track_start = Some(sectors);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let (file, format) = track.ok_or_else(|| invalid("no data track"))?;
+    Ok((file, format))
+}
+
+// mm:ss:ff to frames (sectors)
+fn parse_msf(text: &str) -> Option<u64> {
+    let mut parts = text.split(':').map(|part| part.parse::<u64>().ok());
+    let (minutes, seconds, frames) = (parts.next()??, parts.next()??, parts.next()??);
+    Some((minutes * 60 + seconds) * FRAMES_PER_SECOND + frames)
+}
+
+// A Mode 1 sector with the given user data, at the given sector of the disc
+pub fn mode1_sector(sector: u64, data: &[u8; BLOCK_SIZE]) -> [u8; RAW_SECTOR_SIZE as usize] {
+    let mut raw = [0u8; RAW_SECTOR_SIZE as usize];
+    raw[..12].copy_from_slice(&SYNC);
+    let address = sector + LEAD_IN_SECTORS;
+    let minutes = address / (60 * FRAMES_PER_SECOND);
+    let bcd = |value: u64| (((value / 10) << 4) | (value % 10)) as u8;
+    raw[12..16].copy_from_slice(&[bcd(minutes), bcd(address / FRAMES_PER_SECOND % 60), bcd(address % FRAMES_PER_SECOND), 1]);
+    raw[16..16 + BLOCK_SIZE].copy_from_slice(data);
+
+    // The EDC covers sync, header and data; 8 zero bytes follow it
+    let edc = raw[..0x810].iter().fold(0u32, |mut edc, &byte| {
+        edc ^= byte as u32;
+        for _ in 0..8 {
+            edc = (edc >> 1) ^ if edc & 1 != 0 { EDC_POLYNOMIAL } else { 0 };
+        }
+        edc
+    });
+    raw[0x810..0x814].copy_from_slice(&edc.to_le_bytes());
+
+    // P parity over 43 columns of 24 bytes, then Q over the 26 diagonals of 43, P included
+    let p = parity(&raw[12..], 86, 24, 2, 86);
+    raw[0x81C..0x8C8].copy_from_slice(&p);
+    let q = parity(&raw[12..], 52, 43, 86, 88);
+    raw[0x8C8..0x930].copy_from_slice(&q);
+    raw
+}
+
+// One set of Reed-Solomon parity bytes over GF(2^8) with the polynomial x^8 + x^4 + x^3 + x^2 + 1,
+// the data read as 16-bit words (low and high bytes in turn) along columns or diagonals
+fn parity(data: &[u8], major_count: usize, minor_count: usize, major_step: usize, minor_step: usize) -> Vec<u8> {
+    let double = |value: u8| (value << 1) ^ if value & 0x80 != 0 { 0x1D } else { 0 };
+    // The inverse of doubling a value and adding it to itself, i.e. division by 3
+    let mut third = [0u8; 256];
+    for value in 0..=255u8 {
+        third[(value ^ double(value)) as usize] = value;
+    }
+    let size = major_count * minor_count;
+    let mut parity = vec![0u8; 2 * major_count];
+    for major in 0..major_count {
+        let mut index = (major >> 1) * major_step + (major & 1);
+        let (mut a, mut b) = (0u8, 0u8);
+        for _ in 0..minor_count {
+            let byte = data[index];
+            index += minor_step;
+            if index >= size {
+                index -= size;
+            }
+            a = double(a ^ byte);
+            b ^= byte;
+        }
+        a = third[(double(a) ^ b) as usize];
+        parity[major] = a;
+        parity[major + major_count] = a ^ b;
+    }
+    parity
+}
+
+// Whether a path names a cue sheet rather than an image
+pub fn is_cue(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("cue"))
+}
+
+// Write the user data of the data track of a raw image (or cue sheet) as a plain .iso
+pub fn bin_to_iso(location: &Path, iso_path: &Path) -> io::Result<()> {
+    let (bin_path, format) = if is_cue(location) {
+        parse_cue(location)?
+    } else {
+        let mut file = File::open(location)?;
+        let format = detect(&mut file)?.ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, format!("{} does not hold raw sectors", location.display())))?;
+        (location.to_path_buf(), format)
+    };
+
+    let file = File::open(&bin_path)?;
+    let available = file.metadata()?.len().saturating_sub(format.start) / format.sector_size;
+    let sectors = format.sectors.unwrap_or(available).min(available);
+    println!("Converting {} ({}): {} sectors", bin_path.display(), format.describe(), sectors);
+
+    let mut raw = RawSectors::new(file, format);
+    let mut output = BufWriter::new(File::create(iso_path)?);
+    let mut buffer = vec![0u8; SECTORS_PER_READ as usize * BLOCK_SIZE];
+    let mut sector = 0;
+    while sector < sectors {
+        let count = (sectors - sector).min(SECTORS_PER_READ);
+        let chunk = &mut buffer[..count as usize * BLOCK_SIZE];
+        raw.read_exact_at(sector * BLOCK_SIZE as u64, chunk)?;
+        output.write_all(chunk)?;
+        sector += count;
+    }
+    output.flush()?;
+    println!("Wrote {} ({} bytes)", iso_path.display(), sectors * BLOCK_SIZE as u64);
+    Ok(())
+}
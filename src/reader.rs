@@ -0,0 +1,1121 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::str;
+use std::sync::OnceLock;
+
+use super::age::{self, Decryptor};
+use super::apple;
+use super::charset::{self, Charset};
+use super::events;
+use super::http::{is_url, HttpSource};
+use super::rawsector::{self, RawSectors};
+use super::transtbl::{self, TRANS_TBL};
+use super::unicode;
+use super::{time_text, unix_from_utc, BLOCK_SIZE, CD001, CDROM, FLAG_ASSOCIATED, FLAG_HIDDEN, PRIMARY_VOLUME_DESCRIPTOR, SYSTEM_AREA_BLOCKS, VOLUME_DESCRIPTOR_TERMINATOR};
+
+pub use super::apple::FinderInfo;
+
+const BOOT_RECORD: u8 = 0;
+const SUPPLEMENTARY_VOLUME_DESCRIPTOR: u8 = 2;
+const PARTITION_DESCRIPTOR: u8 = 3;
+const EL_TORITO: &[u8] = b"EL TORITO SPECIFICATION";
+const CATALOG_ENTRY_SIZE: usize = 32;
+const JOLIET_ESCAPES: [&[u8]; 3] = [b"%/@", b"%/C", b"%/E"]; // UCS-2 levels 1 to 3
+const MAX_CONTINUATIONS: usize = 16; // Limit on chained SUSP continuation areas per record
+const MAX_TRANS_TBL_SIZE: u32 = 1024 * 1024; // Larger TRANS.TBL files are taken for ordinary files
+
+static TRUST: OnceLock<Trust> = OnceLock::new();
+static ICASE: OnceLock<bool> = OnceLock::new();
+
+// Which copy of a field ISO 9660 records in both byte orders is believed when the two differ
+// (--trust): the little-endian one, the big-endian one, or neither (the image is refused)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trust {
+    Le,
+    Be,
+    Strict,
+}
+
+impl Trust {
+    pub fn parse(text: &str) -> io::Result<Trust> {
+        match text {
+            "le" => Ok(Trust::Le),
+            "be" => Ok(Trust::Be),
+            "strict" => Ok(Trust::Strict),
+            _ => Err(io::Error::new(ErrorKind::InvalidInput, format!("unknown --trust: {} (expected le, be or strict)", text))),
+        }
+    }
+}
+
+// The copy believed for all readers of this run
+pub fn set_trust(trust: Trust) {
+    let _ = TRUST.set(trust);
+}
+
+pub fn trust() -> Trust {
+    TRUST.get().copied().unwrap_or(Trust::Le)
+}
+
+// Whether paths asked for match names whatever their case (--icase, or --case-sensitive), for
+// all readers of this run; left unset it depends on the name table (see case_insensitive)
+pub fn set_icase(icase: bool) {
+    let _ = ICASE.set(icase);
+}
+
+// The entry at a path asked for, e.g. "/etc/fstab", found a component at a time with child
+pub fn lookup(entries: &[IsoEntry], path: &str, case_insensitive: bool) -> Option<usize> {
+    path.split('/').filter(|component| !component.is_empty()).try_fold(0, |index, component| child(entries, index, component, case_insensitive))
+}
+
+// The entry a name asked for is in a directory: the one spelled the same (in the --normalize
+// form), else with case_insensitive one that differs only in case. A version suffix typed along
+// with the name ("FSTAB.;1") is ignored, as the names read have none.
+pub fn child(entries: &[IsoEntry], directory: usize, name: &str, case_insensitive: bool) -> Option<usize> {
+    let name = match name.rsplit_once(';') {
+        Some((name, version)) if !version.is_empty() && version.bytes().all(|byte| byte.is_ascii_digit()) => name.strip_suffix('.').unwrap_or(name),
+        _ => name,
+    };
+    let name = unicode::reading(name);
+    let children = &entries[directory].children;
+    children.iter().copied().find(|&child| entries[child].name == name).or_else(|| {
+        let folded = name.to_lowercase();
+        children.iter().copied().find(|&child| case_insensitive && entries[child].name.to_lowercase() == folded)
+    })
+}
+
+// A 32-bit field recorded little-endian and then big-endian: the copy believed, and the name of
+// the field added to mismatched when the two differ
+fn both_endian(data: &[u8], at: usize, field: &'static str, mismatched: &mut Vec<&'static str>) -> u32 {
+    let le = u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]]);
+    let be = u32::from_be_bytes([data[at + 4], data[at + 5], data[at + 6], data[at + 7]]);
+    if le != be {
+        mismatched.push(field);
+    }
+    if le != be && trust() == Trust::Be {
+        be
+    } else {
+        le
+    }
+}
+
+// Report the fields of a structure whose two copies differ: a warning naming the copy used, or
+// with --trust strict an error
+fn check_copies(what: &str, mismatched: &[&str]) -> io::Result<()> {
+    if mismatched.is_empty() {
+        return Ok(());
+    }
+    let fields = mismatched.join(", ");
+    let used = match trust() {
+        Trust::Strict => return Err(io::Error::new(ErrorKind::InvalidData, format!("the little- and big-endian copies of the {} of {} differ", fields, what))),
+        Trust::Le => "little-endian one (see --trust)",
+        Trust::Be => "big-endian one",
+    };
+    events::warn(&format!("The little- and big-endian copies of the {} of {} differ, using the {}", fields, what, used));
+    Ok(())
+}
+
+/// Primary Volume Descriptor structure
+pub struct PrimaryVolumeDescriptor {
+    pub volume_id: String,
+    pub volume_space_size: u32,
+    pub root_directory_extent: u32,
+    pub root_directory_size: u32,
+    pub mismatched: Vec<&'static str>, // Fields whose little- and big-endian copies differ
+}
+
+// What makeiso ls prints for the descriptor: the volume's own fields, without the
+// byte-order bookkeeping, which check_copies reports by itself
+impl fmt::Debug for PrimaryVolumeDescriptor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PrimaryVolumeDescriptor")
+            .field("volume_id", &self.volume_id)
+            .field("volume_space_size", &self.volume_space_size)
+            .field("root_directory_extent", &self.root_directory_extent)
+            .field("root_directory_size", &self.root_directory_size)
+            .finish()
+    }
+}
+
+impl PrimaryVolumeDescriptor {
+    pub fn from_bytes(data: &[u8]) -> Option<PrimaryVolumeDescriptor> {
+        if data.len() < BLOCK_SIZE || data[0] != PRIMARY_VOLUME_DESCRIPTOR || &data[1..6] != CD001 {
+            return None; // Not a Primary Volume Descriptor
+        }
+        Some(PrimaryVolumeDescriptor::from_volume(data))
+    }
+
+    // The primary descriptor of High Sierra, the format ISO 9660 grew out of: the same fields,
+    // most of them further along
+    pub fn from_high_sierra(data: &[u8]) -> Option<PrimaryVolumeDescriptor> {
+        if data.len() < BLOCK_SIZE || data[8] != PRIMARY_VOLUME_DESCRIPTOR || &data[9..14] != CDROM {
+            return None;
+        }
+        let mut mismatched = Vec::new();
+        Some(PrimaryVolumeDescriptor {
+            volume_id: String::from_utf8_lossy(&data[48..80]).trim_end().to_string(),
+            volume_space_size: both_endian(data, 88, "volume space size", &mut mismatched),
+            root_directory_extent: both_endian(data, 182, "root directory extent", &mut mismatched),
+            root_directory_size: both_endian(data, 190, "root directory size", &mut mismatched),
+            mismatched,
+        })
+    }
+
+    // The fields a supplementary volume descriptor shares with the primary one, at the same offsets
+    fn from_volume(data: &[u8]) -> PrimaryVolumeDescriptor {
+        let volume_id = String::from_utf8_lossy(&data[40..72]).trim_end().to_string();
+        let mut mismatched = Vec::new();
+        let volume_space_size = both_endian(data, 80, "volume space size", &mut mismatched);
+        let root_directory_extent = both_endian(data, 158, "root directory extent", &mut mismatched);
+        let root_directory_size = both_endian(data, 166, "root directory size", &mut mismatched);
+
+        PrimaryVolumeDescriptor {
+            volume_id,
+            volume_space_size,
+            root_directory_extent,
+            root_directory_size,
+            mismatched,
+        }
+    }
+}
+
+/// Directory Record structure
+#[derive(Debug, Clone)]
+pub struct DirectoryRecord {
+    pub identifier: Vec<u8>, // Raw file identifier; [0x00] is the directory itself, [0x01] its parent
+    pub extent_location: u32, // Logical block where the file starts
+    pub data_length: u32,     // Size of the file in bytes
+    pub recorded: [u8; 7],    // Recording date and time
+    pub flags: u8,            // File flags
+    pub system_use: Vec<u8>,  // System use area (SUSP/Rock Ridge entries)
+    pub mismatched: Vec<&'static str>, // Fields whose little- and big-endian copies differ
+}
+
+impl DirectoryRecord {
+    pub fn from_bytes(data: &[u8]) -> Option<DirectoryRecord> {
+        DirectoryRecord::parse(data, false)
+    }
+
+    // A High Sierra record: its date has no time zone, so the flags come a byte earlier
+    pub fn from_high_sierra(data: &[u8]) -> Option<DirectoryRecord> {
+        DirectoryRecord::parse(data, true)
+    }
+
+    fn parse(data: &[u8], high_sierra: bool) -> Option<DirectoryRecord> {
+        let length_of_directory_record = *data.first()? as usize;
+        if length_of_directory_record < 34 || length_of_directory_record > data.len() {
+            return None; // No more records
+        }
+        let data = &data[..length_of_directory_record];
+
+        let mut mismatched = Vec::new();
+        let extent_location = both_endian(data, 2, "extent", &mut mismatched);
+        let data_length = both_endian(data, 10, "size", &mut mismatched);
+        let file_name_length = data[32] as usize;
+        let identifier = data.get(33..33 + file_name_length)?.to_vec();
+
+        // The system use area starts after the identifier and its padding byte
+        let system_use_start = 33 + file_name_length + (1 - file_name_length % 2);
+        let system_use = data.get(system_use_start..).unwrap_or(&[]).to_vec();
+
+        let mut recorded = [0u8; 7];
+        let flags = if high_sierra {
+            recorded[..6].copy_from_slice(&data[18..24]);
+            data[24]
+        } else {
+            recorded.copy_from_slice(&data[18..25]);
+            data[25]
+        };
+
+        Some(DirectoryRecord {
+            identifier,
+            extent_location,
+            data_length,
+            recorded,
+            flags,
+            system_use,
+            mismatched,
+        })
+    }
+
+    pub fn is_directory(&self) -> bool {
+        self.flags & 0x02 != 0 // Directory flag is bit 1 of flags
+    }
+
+    // The "." record; some writers record it as the string "." instead of the single byte 0x00
+    pub fn is_self(&self) -> bool {
+        self.identifier == [0x00] || self.identifier == b"."
+    }
+
+    // The ".." record, 0x01 or the string ".."
+    pub fn is_parent(&self) -> bool {
+        self.identifier == [0x01] || self.identifier == b".."
+    }
+
+    // The "." and ".." records
+    pub fn is_self_or_parent(&self) -> bool {
+        self.is_self() || self.is_parent()
+    }
+
+    // Name from the ISO 9660 identifier, without the version number
+    pub fn file_name(&self, charset: Charset) -> String {
+        let name = charset.decode(&self.identifier);
+        let name = match name.rfind(';') {
+            // A file without an extension is recorded as "NAME.;1"; without the version
+            // number the identifier is the name as it was (makeiso writes them so)
+            Some(at) if self.is_directory() => &name[..at],
+            Some(at) => name[..at].strip_suffix('.').unwrap_or(&name[..at]),
+            None => &name,
+        };
+        name.to_string()
+    }
+
+    // Modification time as seconds since the Unix epoch
+    pub fn modified(&self) -> i64 {
+        record_time(&self.recorded)
+    }
+}
+
+// Convert a 7-byte directory record date to seconds since the Unix epoch
+fn record_time(date: &[u8]) -> i64 {
+    let utc = unix_from_utc(1900 + date[0] as i64, date[1] as u32, date[2] as u32, date[3] as u32, date[4] as u32, date[5] as u32);
+    utc - date[6] as i8 as i64 * 15 * 60
+}
+
+// Convert a 17-byte volume descriptor date to seconds since the Unix epoch
+fn volume_time(date: &[u8]) -> Option<i64> {
+    let digits = str::from_utf8(&date[..16]).ok()?;
+    let field = |range: std::ops::Range<usize>| digits.get(range)?.parse::<u32>().ok();
+    let (year, month, day) = (field(0..4)?, field(4..6)?, field(6..8)?);
+    if year == 0 {
+        return None; // Not specified
+    }
+    let utc = unix_from_utc(year as i64, month, day, field(8..10)?, field(10..12)?, field(12..14)?);
+    Some(utc - date[16] as i8 as i64 * 15 * 60)
+}
+
+// Rock Ridge information found in the system use area of a record
+#[derive(Debug, Default)]
+struct RockRidge {
+    name: Option<String>,
+    mode: Option<u32>,
+    links: Option<u32>,
+    uid: Option<u32>,
+    gid: Option<u32>,
+    modified: Option<i64>,
+    symlink: Option<String>,
+    symlink_continued: bool, // SL: the last component goes on in the next component record
+    device: Option<(u32, u32)>,
+    child_link: Option<u32>, // CL: the directory was relocated to this extent
+    relocated: bool,         // RE: this is the relocated copy, listed through its CL placeholder
+}
+
+// An entry of the image tree, with names and attributes resolved from Rock Ridge or Joliet when present
+#[derive(Debug, Clone)]
+pub struct IsoEntry {
+    pub name: String,
+    pub path: String, // Path from the root, e.g. "/a/b"; the root is "/"
+    pub parent: usize,
+    pub children: Vec<usize>,
+    pub is_directory: bool,
+    pub extent: u32,
+    pub size: u32,
+    pub flags: u8, // Directory record file flags
+    pub modified: i64,
+    pub mode: u32,
+    pub links: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub symlink: Option<String>,
+    pub device: Option<(u32, u32)>, // Major and minor number of a device, from its PN entry
+    pub finder_info: Option<FinderInfo>, // Type and creator codes from an Apple extension field
+}
+
+// One descriptor of the volume descriptor set
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VolumeDescriptor {
+    Boot(String), // Boot system identifier, e.g. "EL TORITO SPECIFICATION"
+    Primary,
+    Supplementary(Option<u8>), // The Joliet (UCS-2) level, if it is a Joliet descriptor
+    Enhanced,                  // Version 2 supplementary descriptor (ISO 9660:1999)
+    Partition { id: String, block: u32, blocks: u32 },
+    Other(u8),
+}
+
+impl VolumeDescriptor {
+    fn description(&self) -> String {
+        match self {
+            VolumeDescriptor::Boot(system) => format!("boot record ({})", system),
+            VolumeDescriptor::Primary => "primary".to_string(),
+            VolumeDescriptor::Supplementary(Some(level)) => format!("supplementary (Joliet level {})", level),
+            VolumeDescriptor::Supplementary(None) => "supplementary".to_string(),
+            VolumeDescriptor::Enhanced => "enhanced".to_string(),
+            VolumeDescriptor::Partition { id, block, blocks } => format!("partition {} ({} blocks from block {})", id, blocks, block),
+            VolumeDescriptor::Other(kind) => format!("type {}", kind),
+        }
+    }
+}
+
+// A bootable image listed in an El Torito boot catalog
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BootEntry {
+    pub platform: u8, // 0 x86, 1 PowerPC, 2 Mac, 0xEF EFI
+    pub bootable: bool,
+    pub emulation: u8, // 0 none, 1-3 floppy sizes, 4 hard disk
+    pub load_segment: u16,
+    pub sector_count: u16, // Virtual 512-byte sectors loaded by the BIOS
+    pub load_rba: u32,
+}
+
+// Which directory hierarchy names are taken from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NameSource {
+    Iso9660,
+    RockRidge,
+    Joliet,
+    Enhanced, // The ISO 9660:1999 hierarchy, whose identifiers are long names
+}
+
+// Random access to the bytes of an image
+pub trait ReadAt {
+    // Fill buf from the given offset
+    fn read_exact_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()>;
+}
+
+impl<T: Read + Seek> ReadAt for T {
+    fn read_exact_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        self.seek(SeekFrom::Start(offset))?;
+        self.read_exact(buf)
+    }
+}
+
+// Where an image is read from: a local file or a URL, possibly encrypted or holding raw CD sectors
+pub enum ImageSource {
+    File(File),
+    Http(HttpSource),
+    Encrypted(Box<Decryptor<ImageSource>>),
+    Raw(Box<RawSectors<ImageSource>>),
+}
+
+impl ReadAt for ImageSource {
+    fn read_exact_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        match self {
+            ImageSource::File(file) => file.read_exact_at(offset, buf),
+            ImageSource::Http(http) => http.read_exact_at(offset, buf),
+            ImageSource::Encrypted(encrypted) => encrypted.read_exact_at(offset, buf),
+            ImageSource::Raw(raw) => raw.read_exact_at(offset, buf),
+        }
+    }
+}
+
+impl ImageSource {
+    // Bytes of image there are to read, after decryption and without the raw sector framing
+    pub fn size(&self) -> io::Result<u64> {
+        match self {
+            ImageSource::File(file) => Ok(file.metadata()?.len()),
+            ImageSource::Http(http) => Ok(http.len()),
+            ImageSource::Encrypted(encrypted) => Ok(encrypted.len()),
+            ImageSource::Raw(raw) => Ok(raw.data_len(raw.source().size()?)),
+        }
+    }
+}
+
+// Open an image given as a local path, an http(s):// URL or a cue sheet; age-encrypted images
+// are decrypted on the fly. A truncated image is read as far as it goes, with a warning
+pub fn open_image(location: &Path) -> io::Result<IsoReader<ImageSource>> {
+    let mut reader = IsoReader::open(open_source(location)?)?;
+    if let Some(truncation) = reader.truncation()? {
+        events::warn(&format!("{} is truncated: {}", location.display(), truncation));
+    }
+    Ok(reader)
+}
+
+// The bytes of an image, with raw sectors already mapped to their user data
+pub fn open_source(location: &Path) -> io::Result<ImageSource> {
+    if rawsector::is_cue(location) {
+        let (bin_path, format) = rawsector::parse_cue(location)?;
+        return Ok(ImageSource::Raw(Box::new(RawSectors::new(ImageSource::File(File::open(bin_path)?), format))));
+    }
+
+    let mut source = match location.to_str().filter(|location| is_url(location)) {
+        Some(url) => ImageSource::Http(HttpSource::open(url)?),
+        None => ImageSource::File(File::open(location)?),
+    };
+    if age::is_encrypted(&mut source) {
+        let len = match &source {
+            ImageSource::Http(http) => http.len(),
+            _ => fs::metadata(location)?.len(),
+        };
+        source = ImageSource::Encrypted(Box::new(Decryptor::open(source, len)?));
+    }
+    if let Some(format) = rawsector::detect(&mut source)? {
+        source = ImageSource::Raw(Box::new(RawSectors::new(source, format)));
+    }
+    Ok(source)
+}
+
+// How an image is cut short of the volume size its primary descriptor records
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Truncation {
+    pub expected: u64,              // Bytes the volume takes up
+    pub found: u64,                 // Bytes there are
+    pub last_block: Option<String>, // Why the last block of the volume can't be read, when the size looks right
+}
+
+impl fmt::Display for Truncation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(error) = &self.last_block {
+            return write!(f, "its last block, {}, could not be read ({})", self.expected / BLOCK_SIZE as u64 - 1, error);
+        }
+        let missing = self.expected - self.found;
+        write!(
+            f,
+            "{} bytes are missing ({} of {} bytes there; blocks {} to {} are cut off)",
+            missing,
+            self.found,
+            self.expected,
+            self.found / BLOCK_SIZE as u64,
+            self.expected / BLOCK_SIZE as u64 - 1
+        )
+    }
+}
+
+impl Truncation {
+    // Whether found bytes fall short of a volume of the given size
+    pub fn of_size(volume_blocks: u32, found: u64) -> Option<Truncation> {
+        let expected = volume_blocks as u64 * BLOCK_SIZE as u64;
+        (found < expected).then_some(Truncation { expected, found, last_block: None })
+    }
+}
+
+impl IsoReader<ImageSource> {
+    // Whether the image is shorter than its volume, or its last block can't be read; images
+    // with more after the volume (appended partitions, padding) are fine
+    pub fn truncation(&mut self) -> io::Result<Option<Truncation>> {
+        let found = self.source.size()?;
+        if let Some(truncation) = Truncation::of_size(self.pvd.volume_space_size, found) {
+            return Ok(Some(truncation));
+        }
+        let Some(last) = self.pvd.volume_space_size.checked_sub(1) else {
+            return Ok(None);
+        };
+        let mut block = vec![0u8; BLOCK_SIZE];
+        let unreadable = self.source.read_exact_at(last as u64 * BLOCK_SIZE as u64, &mut block).err();
+        Ok(unreadable.map(|e| Truncation { expected: self.pvd.volume_space_size as u64 * BLOCK_SIZE as u64, found, last_block: Some(e.to_string()) }))
+    }
+}
+
+// Reader for ISO 9660 images
+pub struct IsoReader<R> {
+    source: R,
+    pub pvd: PrimaryVolumeDescriptor,
+    pub created: Option<i64>,
+    pub boot_catalog: Option<u32>, // Block of the El Torito boot catalog, for bootable images
+    pub descriptors: Vec<(u32, VolumeDescriptor)>, // The descriptor set by block, up to the terminator
+    pub high_sierra: bool,                         // The volume is High Sierra rather than ISO 9660
+    joliet_root: Option<DirectoryRecord>,
+    enhanced_root: Option<DirectoryRecord>, // Root of the first ISO 9660:1999 (version 2) hierarchy
+    charset: Charset,         // What ISO 9660 identifiers and Rock Ridge names are recorded in
+    susp_skip: Option<usize>, // Set when the root carries an SP entry (Rock Ridge in use)
+}
+
+impl<R: ReadAt> IsoReader<R> {
+    // Read the volume descriptor set and detect Rock Ridge and Joliet. The set starts at sector
+    // 16 in any order (a boot record often comes first); the first primary descriptor describes
+    // the volume, and of several Joliet descriptors the one of the highest level is used.
+    pub fn open(mut source: R) -> io::Result<IsoReader<R>> {
+        let mut pvd = None;
+        let mut fallback = None; // A supplementary descriptor's view, for sets without a primary one
+        let mut created = None;
+        let mut boot_catalog = None;
+        let mut joliet = None;
+        let mut enhanced = None;
+        let mut descriptors = Vec::new();
+        let mut high_sierra = false;
+
+        // Walk the descriptor set from sector 16 until the terminator
+        let mut block = SYSTEM_AREA_BLOCKS;
+        loop {
+            let mut buffer = vec![0u8; BLOCK_SIZE];
+            source.read_exact_at(block as u64 * BLOCK_SIZE as u64, &mut buffer)?;
+            // High Sierra descriptors start with their own block number, then the type and "CDROM"
+            let (kind, sierra) = if &buffer[1..6] == CD001 {
+                (buffer[0], false)
+            } else if &buffer[9..14] == CDROM {
+                (buffer[8], true)
+            } else {
+                break;
+            };
+            if kind == VOLUME_DESCRIPTOR_TERMINATOR {
+                break;
+            }
+
+            let descriptor = match kind {
+                PRIMARY_VOLUME_DESCRIPTOR => {
+                    if pvd.is_none() {
+                        high_sierra = sierra;
+                        if sierra {
+                            pvd = PrimaryVolumeDescriptor::from_high_sierra(&buffer);
+                            // Its dates have no time zone
+                            let mut date = [0u8; 17];
+                            date[..16].copy_from_slice(&buffer[790..806]);
+                            created = volume_time(&date);
+                        } else {
+                            pvd = PrimaryVolumeDescriptor::from_bytes(&buffer);
+                            created = volume_time(&buffer[813..830]);
+                        }
+                        if let Some(pvd) = &pvd {
+                            check_copies("the primary volume descriptor", &pvd.mismatched)?;
+                        }
+                    }
+                    VolumeDescriptor::Primary
+                }
+                SUPPLEMENTARY_VOLUME_DESCRIPTOR if sierra => VolumeDescriptor::Supplementary(None),
+                SUPPLEMENTARY_VOLUME_DESCRIPTOR => {
+                    let level = JOLIET_ESCAPES.iter().position(|escape| buffer[88..91] == **escape).map(|level| level as u8 + 1);
+                    if let Some(level) = level.filter(|&level| joliet.as_ref().is_none_or(|(best, _)| level > *best)) {
+                        if let Some(root) = DirectoryRecord::from_bytes(&buffer[156..190]) {
+                            check_copies("the Joliet root directory", &root.mismatched)?;
+                            joliet = Some((level, root));
+                        }
+                    }
+                    if fallback.is_none() {
+                        fallback = Some((block, PrimaryVolumeDescriptor::from_volume(&buffer), volume_time(&buffer[813..830])));
+                    }
+                    if buffer[6] == 2 {
+                        if enhanced.is_none() && level.is_none() {
+                            if let Some(root) = DirectoryRecord::from_bytes(&buffer[156..190]) {
+                                check_copies("the enhanced root directory", &root.mismatched)?;
+                                enhanced = Some(root);
+                            }
+                        }
+                        VolumeDescriptor::Enhanced
+                    } else {
+                        VolumeDescriptor::Supplementary(level)
+                    }
+                }
+                BOOT_RECORD => {
+                    if boot_catalog.is_none() && buffer[7..7 + EL_TORITO.len()] == *EL_TORITO {
+                        boot_catalog = Some(u32::from_le_bytes([buffer[71], buffer[72], buffer[73], buffer[74]]));
+                    }
+                    VolumeDescriptor::Boot(String::from_utf8_lossy(&buffer[7..39]).trim_end_matches(['\0', ' ']).to_string())
+                }
+                PARTITION_DESCRIPTOR => {
+                    let mut mismatched = Vec::new();
+                    let id = String::from_utf8_lossy(&buffer[40..72]).trim_end().to_string();
+                    let (location, blocks) = (both_endian(&buffer, 72, "location", &mut mismatched), both_endian(&buffer, 80, "size", &mut mismatched));
+                    check_copies(&format!("the partition descriptor {}", id), &mismatched)?;
+                    VolumeDescriptor::Partition { id, block: location, blocks }
+                }
+                kind => VolumeDescriptor::Other(kind),
+            };
+            descriptors.push((block, descriptor));
+            block += 1;
+        }
+
+        let pvd = match (pvd, fallback) {
+            (Some(pvd), _) => pvd,
+            (None, Some((block, pvd, time))) => {
+                events::warn(&format!("The image has no primary volume descriptor, reading the supplementary one in block {}", block));
+                check_copies("the supplementary volume descriptor", &pvd.mismatched)?;
+                created = time;
+                pvd
+            }
+            (None, None) => return Err(io::Error::new(ErrorKind::InvalidData, "Could not read the Primary Volume Descriptor")),
+        };
+        let mut reader = IsoReader {
+            source,
+            pvd,
+            created,
+            boot_catalog,
+            descriptors,
+            high_sierra,
+            joliet_root: joliet.map(|(_, root)| root),
+            enhanced_root: enhanced,
+            susp_skip: None,
+            charset: charset::image_names(),
+        };
+
+        // Rock Ridge is announced by an SP entry in the root's "." record
+        let root = reader.read_records(reader.pvd.root_directory_extent, reader.pvd.root_directory_size)?;
+        if let Some(dot) = root.iter().find(|record| record.is_self()) {
+            let system_use = &dot.system_use;
+            if system_use.len() >= 7 && &system_use[..2] == b"SP" && system_use[4..6] == [0xBE, 0xEF] {
+                reader.susp_skip = Some(system_use[6] as usize);
+            }
+        }
+
+        Ok(reader)
+    }
+
+    // The underlying image, for sources that keep state of their own
+    pub fn source_mut(&mut self) -> &mut R {
+        &mut self.source
+    }
+
+    // The underlying image, once the descriptors and tree have been read
+    pub fn into_source(self) -> R {
+        self.source
+    }
+
+    // Names come from Rock Ridge if present, otherwise from Joliet, otherwise from an ISO 9660:1999
+    // hierarchy, otherwise from ISO 9660 identifiers
+    pub fn name_source(&self) -> NameSource {
+        if self.susp_skip.is_some() {
+            NameSource::RockRidge
+        } else if self.joliet_root.is_some() {
+            NameSource::Joliet
+        } else if self.enhanced_root.is_some() {
+            NameSource::Enhanced
+        } else {
+            NameSource::Iso9660
+        }
+    }
+
+    // Whether paths are looked up regardless of case: as --icase or --case-sensitive asked, else
+    // for the names of tables that have no case to speak of, plain identifiers (all upper case)
+    // and Joliet names (from Windows, which ignores it); Rock Ridge and ISO 9660:1999 names keep it
+    pub fn case_insensitive(&self) -> bool {
+        ICASE.get().copied().unwrap_or(matches!(self.name_source(), NameSource::Iso9660 | NameSource::Joliet))
+    }
+
+    // Read whole blocks starting at the given block
+    pub fn read_blocks(&mut self, block: u32, count: usize) -> io::Result<Vec<u8>> {
+        let mut buffer = vec![0u8; count * BLOCK_SIZE];
+        self.source.read_exact_at(block as u64 * BLOCK_SIZE as u64, &mut buffer)?;
+        Ok(buffer)
+    }
+
+    // The entries of the El Torito boot catalog: the default entry, then those of each section
+    pub fn boot_entries(&mut self) -> io::Result<Vec<BootEntry>> {
+        let Some(catalog) = self.boot_catalog else {
+            return Ok(Vec::new());
+        };
+        let catalog = self.read_blocks(catalog, 1)?;
+        if catalog[0] != 1 || catalog[30..32] != [0x55, 0xAA] {
+            return Err(io::Error::new(ErrorKind::InvalidData, "the boot catalog has no validation entry"));
+        }
+
+        let entry = |at: usize, platform: u8| {
+            let data = &catalog[at..at + CATALOG_ENTRY_SIZE];
+            BootEntry {
+                platform,
+                bootable: data[0] == 0x88,
+                emulation: data[1] & 0x0F,
+                load_segment: u16::from_le_bytes([data[2], data[3]]),
+                sector_count: u16::from_le_bytes([data[6], data[7]]),
+                load_rba: u32::from_le_bytes([data[8], data[9], data[10], data[11]]),
+            }
+        };
+        let mut entries = vec![entry(CATALOG_ENTRY_SIZE, catalog[1])];
+
+        // Section headers (0x90, the last one 0x91) each announce a number of entries
+        let mut at = 2 * CATALOG_ENTRY_SIZE;
+        while at + CATALOG_ENTRY_SIZE <= BLOCK_SIZE && matches!(catalog[at], 0x90 | 0x91) {
+            let (last, platform) = (catalog[at] == 0x91, catalog[at + 1]);
+            let mut remaining = u16::from_le_bytes([catalog[at + 2], catalog[at + 3]]);
+            at += CATALOG_ENTRY_SIZE;
+            while remaining > 0 && at + CATALOG_ENTRY_SIZE <= BLOCK_SIZE {
+                // Extension records continue the entry before them and aren't counted
+                if catalog[at] != 0x44 {
+                    entries.push(entry(at, platform));
+                    remaining -= 1;
+                }
+                at += CATALOG_ENTRY_SIZE;
+            }
+            if last {
+                break;
+            }
+        }
+        Ok(entries)
+    }
+
+    // Read whole 512-byte sectors starting at the given block, as boot images are measured
+    pub fn read_boot_image(&mut self, entry: &BootEntry) -> io::Result<Vec<u8>> {
+        let mut buffer = vec![0u8; entry.sector_count as usize * 512];
+        self.source.read_exact_at(entry.load_rba as u64 * BLOCK_SIZE as u64, &mut buffer)?;
+        Ok(buffer)
+    }
+
+    // Read the records of a directory extent, including "." and ".."
+    pub fn read_records(&mut self, start_block: u32, size: u32) -> io::Result<Vec<DirectoryRecord>> {
+        // One block at a time, so a size no image could hold ends at the end of the image
+        // rather than in allocating it
+        let num_blocks = size.div_ceil(BLOCK_SIZE as u32);
+        let mut records = Vec::new();
+        for number in 0..num_blocks {
+            let at = start_block.checked_add(number).ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "a directory extent runs past the last block"))?;
+            let block = self.read_blocks(at, 1)?;
+            let mut offset = 0;
+            while offset < BLOCK_SIZE {
+                let record = if self.high_sierra { DirectoryRecord::from_high_sierra(&block[offset..]) } else { DirectoryRecord::from_bytes(&block[offset..]) };
+                match record {
+                    Some(record) => {
+                        if !record.mismatched.is_empty() {
+                            let name = if record.is_self() { ".".to_string() } else if record.is_parent() { "..".to_string() } else { record.file_name(self.charset) };
+                            check_copies(&format!("the record of {} in block {}", name, at), &record.mismatched)?;
+                        }
+                        // Move the offset by the length of the directory record
+                        offset += block[offset] as usize;
+                        records.push(record);
+                    }
+                    None => break, // No more records in this block
+                }
+            }
+        }
+        Ok(records)
+    }
+
+    // Read part of a file's contents; returns the number of bytes read (0 at the end of the file)
+    pub fn read_file_at(&mut self, entry: &IsoEntry, offset: u64, buffer: &mut [u8]) -> io::Result<usize> {
+        if offset >= entry.size as u64 {
+            return Ok(0);
+        }
+        let len = buffer.len().min((entry.size as u64 - offset) as usize);
+        self.source.read_exact_at(entry.extent as u64 * BLOCK_SIZE as u64 + offset, &mut buffer[..len])?;
+        Ok(len)
+    }
+
+    // Read the whole directory hierarchy; entry 0 is the root
+    pub fn read_tree(&mut self) -> io::Result<Vec<IsoEntry>> {
+        let name_source = self.name_source();
+        let root_record = match name_source {
+            NameSource::Joliet => self.joliet_root.clone().expect("Joliet root"),
+            NameSource::Enhanced => self.enhanced_root.clone().expect("enhanced root"),
+            _ => DirectoryRecord {
+                identifier: vec![0],
+                extent_location: self.pvd.root_directory_extent,
+                data_length: self.pvd.root_directory_size,
+                recorded: [0; 7],
+                flags: 0x02,
+                system_use: Vec::new(),
+                mismatched: Vec::new(),
+            },
+        };
+
+        let mut entries = vec![IsoEntry {
+            name: String::new(),
+            path: "/".to_string(),
+            parent: 0,
+            children: Vec::new(),
+            is_directory: true,
+            extent: root_record.extent_location,
+            size: root_record.data_length,
+            flags: root_record.flags,
+            modified: self.created.unwrap_or(0),
+            mode: 0o040555,
+            links: 2,
+            uid: 0,
+            gid: 0,
+            symlink: None,
+            device: None,
+            finder_info: None,
+        }];
+
+        // Breadth first, guarding against directories that loop back on themselves
+        let mut visited = HashSet::new();
+        let mut next = 0;
+        while next < entries.len() {
+            let index = next;
+            next += 1;
+            if !entries[index].is_directory || !visited.insert(entries[index].extent) {
+                continue;
+            }
+
+            let records = self.read_records(entries[index].extent, entries[index].size)?;
+            let mut trans_table = None; // Read when the first plain identifier turns up
+            for record in &records {
+                if record.is_self_or_parent() {
+                    // The root's "." record carries its Rock Ridge attributes
+                    if index == 0 && record.is_self() && name_source == NameSource::RockRidge {
+                        let rock_ridge = self.rock_ridge(record)?;
+                        apply_rock_ridge(&mut entries[0], &rock_ridge);
+                    }
+                    continue;
+                }
+
+                let rock_ridge = if name_source == NameSource::RockRidge { self.rock_ridge(record)? } else { RockRidge::default() };
+                if rock_ridge.relocated {
+                    continue; // Listed where its CL placeholder is
+                }
+
+                let name = match (&rock_ridge.name, name_source) {
+                    (Some(name), _) => name.clone(),
+                    (None, NameSource::Joliet) => joliet_name(record),
+                    _ => {
+                        // Plain identifiers may be 8.3 names with the original names in a TRANS.TBL
+                        let identifier = record.file_name(self.charset);
+                        if trans_table.is_none() {
+                            trans_table = Some(self.trans_table(&records)?);
+                        }
+                        let table = trans_table.as_ref().expect("TRANS.TBL read");
+                        if identifier == TRANS_TBL && !table.is_empty() {
+                            continue; // Its names are shown instead
+                        }
+                        table.get(&identifier).cloned().unwrap_or(identifier)
+                    }
+                };
+                let name = match unicode::reading(&name) {
+                    Cow::Owned(normalized) => normalized,
+                    Cow::Borrowed(_) => name,
+                };
+                let is_directory = record.is_directory() || rock_ridge.child_link.is_some();
+                let (extent, size) = match rock_ridge.child_link {
+                    // A relocated directory: take its extent and size from its own "." record
+                    Some(location) => match self.read_records(location, BLOCK_SIZE as u32)?.iter().find(|record| record.is_self()) {
+                        Some(dot) => (location, dot.data_length),
+                        None => continue,
+                    },
+                    None => (record.extent_location, record.data_length),
+                };
+                let path = if index == 0 { format!("/{}", name) } else { format!("{}/{}", entries[index].path, name) };
+
+                let mut entry = IsoEntry {
+                    name,
+                    path,
+                    parent: index,
+                    children: Vec::new(),
+                    is_directory,
+                    extent,
+                    size,
+                    flags: record.flags,
+                    modified: record.modified(),
+                    mode: if is_directory { 0o040555 } else { 0o100444 },
+                    links: if is_directory { 2 } else { 1 },
+                    uid: 0,
+                    gid: 0,
+                    symlink: None,
+                    device: None,
+                    finder_info: apple::decode(record.system_use.get(self.susp_skip.unwrap_or(0)..).unwrap_or(&[])),
+                };
+                apply_rock_ridge(&mut entry, &rock_ridge);
+
+                let child = entries.len();
+                entries.push(entry);
+                entries[index].children.push(child);
+            }
+        }
+
+        // Hide the relocation directory once the directories moved into it are listed in place
+        if name_source == NameSource::RockRidge {
+            let hidden = entries[0].children.iter().copied().find(|&child| {
+                let entry = &entries[child];
+                entry.is_directory && entry.children.is_empty() && (entry.name == "rr_moved" || entry.name == ".rr_moved")
+            });
+            if let Some(hidden) = hidden {
+                // Drop it from the list too, so walking the entries in order doesn't find it
+                entries.remove(hidden);
+                let renumber = |index: usize| if index > hidden { index - 1 } else { index };
+                for entry in &mut entries {
+                    entry.parent = renumber(entry.parent);
+                    entry.children = entry.children.iter().filter(|&&child| child != hidden).map(|&child| renumber(child)).collect();
+                }
+            }
+        }
+
+        Ok(entries)
+    }
+
+    // Original names by identifier from the TRANS.TBL among the records of a directory, if it has one
+    fn trans_table(&mut self, records: &[DirectoryRecord]) -> io::Result<BTreeMap<String, String>> {
+        let table = records.iter().find(|record| !record.is_directory() && record.file_name(self.charset) == TRANS_TBL);
+        let Some(table) = table.filter(|table| table.data_length <= MAX_TRANS_TBL_SIZE) else {
+            return Ok(BTreeMap::new());
+        };
+        let mut contents = vec![0u8; table.data_length as usize];
+        self.source.read_exact_at(table.extent_location as u64 * BLOCK_SIZE as u64, &mut contents)?;
+        Ok(transtbl::parse_table(&self.charset.decode(&contents)))
+    }
+
+    // Parse the SUSP entries of a record, following continuation areas
+    fn rock_ridge(&mut self, record: &DirectoryRecord) -> io::Result<RockRidge> {
+        let mut rock_ridge = RockRidge::default();
+        let skip = self.susp_skip.unwrap_or(0);
+        let mut area = record.system_use.get(skip..).unwrap_or(&[]).to_vec();
+        let mut continuations = 0;
+
+        loop {
+            let mut continuation = None;
+            let mut offset = 0;
+            while offset + 4 <= area.len() {
+                let length = area[offset + 2] as usize;
+                if length < 4 || offset + length > area.len() {
+                    break;
+                }
+                let entry = &area[offset..offset + length];
+                match &entry[..2] {
+                    b"CE" if length >= 28 => {
+                        let field = |at: usize| u32::from_le_bytes([entry[at], entry[at + 1], entry[at + 2], entry[at + 3]]);
+                        continuation = Some((field(4), field(12), field(20)));
+                    }
+                    b"ST" => break,
+                    _ => parse_rock_ridge_entry(entry, &mut rock_ridge, self.charset),
+                }
+                offset += length;
+            }
+
+            match continuation {
+                // A continuation area lies within one block; those that don't are left out,
+                // as Linux does
+                Some((block, offset, length)) if continuations < MAX_CONTINUATIONS && offset as u64 + length as u64 <= BLOCK_SIZE as u64 => {
+                    continuations += 1;
+                    let start = offset as usize;
+                    let data = self.read_blocks(block, 1)?;
+                    area = data[start..start + length as usize].to_vec();
+                }
+                _ => break,
+            }
+        }
+        Ok(rock_ridge)
+    }
+}
+
+// Record one Rock Ridge entry (signature, length, version, data)
+fn parse_rock_ridge_entry(entry: &[u8], rock_ridge: &mut RockRidge, charset: Charset) {
+    let field = |at: usize| entry.get(at..at + 4).map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+
+    match &entry[..2] {
+        b"PX" => {
+            rock_ridge.mode = field(4);
+            rock_ridge.links = field(12);
+            rock_ridge.uid = field(20);
+            rock_ridge.gid = field(28);
+        }
+        b"NM" if entry.len() > 4 => {
+            let flags = entry[4];
+            let part = match flags {
+                _ if flags & 0x02 != 0 => ".".to_string(),
+                _ if flags & 0x04 != 0 => "..".to_string(),
+                _ => charset.decode(&entry[5..]),
+            };
+            // Names longer than one entry are split over several NM entries
+            rock_ridge.name = Some(rock_ridge.name.take().unwrap_or_default() + &part);
+        }
+        b"SL" if entry.len() > 4 => {
+            let mut target = rock_ridge.symlink.take().unwrap_or_default();
+            let mut offset = 5;
+            while offset + 2 <= entry.len() {
+                let (flags, length) = (entry[offset], entry[offset + 1] as usize);
+                let content = entry.get(offset + 2..offset + 2 + length).unwrap_or(&[]);
+                if !target.is_empty() && !target.ends_with('/') && !rock_ridge.symlink_continued {
+                    target.push('/');
+                }
+                rock_ridge.symlink_continued = flags & 0x01 != 0;
+                match flags {
+                    _ if flags & 0x02 != 0 => target.push('.'),
+                    _ if flags & 0x04 != 0 => target.push_str(".."),
+                    _ if flags & 0x08 != 0 => target = "/".to_string(),
+                    _ => target.push_str(&charset.decode(content)),
+                }
+                offset += 2 + length;
+            }
+            rock_ridge.symlink = Some(target);
+        }
+        b"TF" if entry.len() > 4 => {
+            // Timestamps are recorded in a fixed order; the modification time is second
+            let flags = entry[4];
+            let size = if flags & 0x80 != 0 { 17 } else { 7 };
+            if flags & 0x02 != 0 {
+                let at = 5 + if flags & 0x01 != 0 { size } else { 0 };
+                if let Some(date) = entry.get(at..at + size) {
+                    rock_ridge.modified = if size == 17 { volume_time(date) } else { Some(record_time(date)) };
+                }
+            }
+        }
+        b"PN" => {
+            // The halves of a glibc dev_t, as mkisofs records it
+            if let (Some(high), Some(low)) = (field(4), field(12)) {
+                let device = (high as u64) << 32 | low as u64;
+                let major = ((device >> 32) & 0xfffff000) | ((device >> 8) & 0xfff);
+                let minor = ((device >> 12) & 0xffffff00) | (device & 0xff);
+                rock_ridge.device = Some((major as u32, minor as u32));
+            }
+        }
+        b"CL" => rock_ridge.child_link = field(4),
+        b"RE" => rock_ridge.relocated = true,
+        _ => {}
+    }
+}
+
+// Apply Rock Ridge attributes to an entry
+fn apply_rock_ridge(entry: &mut IsoEntry, rock_ridge: &RockRidge) {
+    if let Some(mode) = rock_ridge.mode {
+        // A CL placeholder carries the mode of the directory it stands for
+        entry.mode = mode;
+    }
+    entry.links = rock_ridge.links.unwrap_or(entry.links);
+    entry.uid = rock_ridge.uid.unwrap_or(entry.uid);
+    entry.gid = rock_ridge.gid.unwrap_or(entry.gid);
+    entry.modified = rock_ridge.modified.unwrap_or(entry.modified);
+    entry.symlink = rock_ridge.symlink.clone();
+    entry.device = rock_ridge.device;
+}
+
+// Joliet names are big-endian UCS-2
+fn joliet_name(record: &DirectoryRecord) -> String {
+    let units: Vec<u16> = record.identifier.chunks_exact(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]])).collect();
+    let name = String::from_utf16_lossy(&units);
+    match name.rfind(';') {
+        Some(at) => name[..at].to_string(),
+        None => name,
+    }
+}
+
+// Print the volume descriptor and an indented listing of the image, directories marked with "[DIR] ";
+// the long listing starts with the whole descriptor set and is one line per entry with its mode,
+// size, time and file flags, then its path and any Finder type and creator
+pub fn list_image(iso_path: &Path, long: bool) -> io::Result<()> {
+    let mut reader = open_image(iso_path)?;
+    println!("Primary Volume Descriptor: {:?}", reader.pvd);
+
+    let entries = reader.read_tree()?;
+    if long {
+        let descriptors: Vec<String> = reader.descriptors.iter().map(|(block, descriptor)| format!("{} {}", block, descriptor.description())).collect();
+        println!("Volume descriptors{}: {}", if reader.high_sierra { " (High Sierra)" } else { "" }, descriptors.join(", "));
+        for entry in entries.iter().skip(1) {
+            let finder_info = entry.finder_info.map(|info| format!(" ({})", info.codes())).unwrap_or_default();
+            println!("{} {:>10} {} {} {}{}", mode_text(entry), entry.size, time_text(entry.modified), flags_text(entry.flags), entry.path, finder_info);
+        }
+    } else {
+        list_directory(&entries, 0, 0);
+    }
+    Ok(())
+}
+
+fn list_directory(entries: &[IsoEntry], index: usize, indent: usize) {
+    for &child in &entries[index].children {
+        let entry = &entries[child];
+        println!("{}{}{}", " ".repeat(indent), if entry.is_directory { "[DIR] " } else { "" }, entry.name);
+        if entry.is_directory {
+            list_directory(entries, child, indent + 4);
+        }
+    }
+}
+
+// Mode as ls -l shows it, e.g. "dr-xr-xr-x"
+fn mode_text(entry: &IsoEntry) -> String {
+    let kind = if entry.is_directory {
+        'd'
+    } else if entry.symlink.is_some() {
+        'l'
+    } else {
+        // Fifos and devices, which only Rock Ridge tells apart
+        match entry.mode & 0o170000 {
+            0o010000 => 'p',
+            0o020000 => 'c',
+            0o060000 => 'b',
+            _ => '-',
+        }
+    };
+    let permissions = (0..9).rev().map(|bit| if entry.mode & (1 << bit) != 0 { b"xwr"[bit % 3] as char } else { '-' });
+    std::iter::once(kind).chain(permissions).collect()
+}
+
+// The hidden (h) and associated file (a) flags of a directory record
+fn flags_text(flags: u8) -> String {
+    let flag = |bit: u8, letter: char| if flags & bit != 0 { letter } else { '-' };
+    [flag(FLAG_HIDDEN, 'h'), flag(FLAG_ASSOCIATED, 'a')].iter().collect()
+}
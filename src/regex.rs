@@ -0,0 +1,351 @@
+// Small regular expression engine for searching file contents
+//
+// Supports literals, ".", classes ("[a-z]", "[^...]", "\d", "\w", "\s" and their negations),
+// anchors ("^", "$"), alternation, groups and the "*", "+", "?" and "{m,n}" repetitions.
+// Patterns are compiled to a Thompson NFA and run as a Pike VM, so matching is linear in the
+// length of the text whatever the pattern.
+
+use std::io::{self, ErrorKind};
+
+const MAX_REPEAT: u32 = 1000;
+
+#[derive(Debug, Clone)]
+enum Node {
+    Char(char),
+    Any,
+    Class(Vec<(char, char)>, bool), // Ranges, negated
+    Start,
+    End,
+    Concat(Vec<Node>),
+    Alternate(Vec<Node>),
+    Repeat(Box<Node>, u32, Option<u32>), // At least, at most
+}
+
+#[derive(Debug, Clone)]
+enum Instruction {
+    Char(char),
+    Any,
+    Class(Vec<(char, char)>, bool),
+    Start,
+    End,
+    Split(usize, usize),
+    Jump(usize),
+    Match,
+}
+
+pub struct Regex {
+    program: Vec<Instruction>,
+    ignore_case: bool,
+}
+
+fn invalid(pattern: &str, message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidInput, format!("invalid pattern {}: {}", pattern, message))
+}
+
+struct Parser<'a> {
+    pattern: &'a str,
+    chars: Vec<char>,
+    at: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.at).copied()
+    }
+
+    fn alternation(&mut self) -> io::Result<Node> {
+        let mut branches = vec![self.concatenation()?];
+        while self.peek() == Some('|') {
+            self.at += 1;
+            branches.push(self.concatenation()?);
+        }
+        Ok(if branches.len() == 1 { branches.pop().expect("one branch") } else { Node::Alternate(branches) })
+    }
+
+    fn concatenation(&mut self) -> io::Result<Node> {
+        let mut items = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.atom()?;
+            items.push(self.repetition(atom)?);
+        }
+        Ok(Node::Concat(items))
+    }
+
+    fn repetition(&mut self, mut atom: Node) -> io::Result<Node> {
+        loop {
+            let (min, max) = match self.peek() {
+                Some('*') => (0, None),
+                Some('+') => (1, None),
+                Some('?') => (0, Some(1)),
+                Some('{') if self.bounds_ahead() => {
+                    self.at += 1;
+                    let min = self.number()?;
+                    let max = if self.peek() == Some(',') {
+                        self.at += 1;
+                        if self.peek() == Some('}') { None } else { Some(self.number()?) }
+                    } else {
+                        Some(min)
+                    };
+                    if max.is_some_and(|max| max < min) || min > MAX_REPEAT || max.is_some_and(|max| max > MAX_REPEAT) {
+                        return Err(invalid(self.pattern, "bad repetition bounds"));
+                    }
+                    (min, max)
+                }
+                _ => return Ok(atom),
+            };
+            self.at += 1; // The operator, or the closing brace
+            if matches!(atom, Node::Start | Node::End) {
+                return Err(invalid(self.pattern, "nothing to repeat"));
+            }
+            atom = Node::Repeat(Box::new(atom), min, max);
+        }
+    }
+
+    // Whether a "{" starts bounds like {2}, {2,} or {2,5} (otherwise it's a literal)
+    fn bounds_ahead(&self) -> bool {
+        let rest: String = self.chars[self.at + 1..].iter().take_while(|&&c| c != '}').collect();
+        self.chars[self.at + 1..].contains(&'}') && !rest.is_empty() && rest.chars().all(|c| c.is_ascii_digit() || c == ',') && !rest.starts_with(',')
+    }
+
+    fn number(&mut self) -> io::Result<u32> {
+        let start = self.at;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.at += 1;
+        }
+        self.chars[start..self.at].iter().collect::<String>().parse().map_err(|_| invalid(self.pattern, "bad repetition bounds"))
+    }
+
+    fn atom(&mut self) -> io::Result<Node> {
+        let c = self.peek().expect("an atom to parse");
+        self.at += 1;
+        Ok(match c {
+            '.' => Node::Any,
+            '^' => Node::Start,
+            '$' => Node::End,
+            '(' => {
+                if self.chars[self.at..].starts_with(&['?', ':']) {
+                    self.at += 2;
+                }
+                let inner = self.alternation()?;
+                if self.peek() != Some(')') {
+                    return Err(invalid(self.pattern, "missing )"));
+                }
+                self.at += 1;
+                inner
+            }
+            '[' => self.class()?,
+            '\\' => self.escape()?,
+            '*' | '+' | '?' => return Err(invalid(self.pattern, "nothing to repeat")),
+            c => Node::Char(c),
+        })
+    }
+
+    fn escape(&mut self) -> io::Result<Node> {
+        let c = self.peek().ok_or_else(|| invalid(self.pattern, "trailing backslash"))?;
+        self.at += 1;
+        Ok(match shorthand_class(c) {
+            Some((ranges, negated)) => Node::Class(ranges, negated),
+            None => Node::Char(escaped_char(c)),
+        })
+    }
+
+    fn class(&mut self) -> io::Result<Node> {
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.at += 1;
+        }
+        let mut ranges = Vec::new();
+        let mut first = true;
+        loop {
+            let c = self.peek().ok_or_else(|| invalid(self.pattern, "missing ]"))?;
+            self.at += 1;
+            if c == ']' && !first {
+                break;
+            }
+            first = false;
+            let start = match c {
+                '\\' => {
+                    let escaped = self.peek().ok_or_else(|| invalid(self.pattern, "missing ]"))?;
+                    self.at += 1;
+                    if let Some((shorthand, false)) = shorthand_class(escaped) {
+                        ranges.extend(shorthand);
+                        continue;
+                    }
+                    escaped_char(escaped)
+                }
+                c => c,
+            };
+            if self.peek() == Some('-') && self.chars.get(self.at + 1).is_some_and(|&end| end != ']') {
+                let end = self.chars[self.at + 1];
+                self.at += 2;
+                if end < start {
+                    return Err(invalid(self.pattern, "bad class range"));
+                }
+                ranges.push((start, end));
+            } else {
+                ranges.push((start, start));
+            }
+        }
+        Ok(Node::Class(ranges, negated))
+    }
+}
+
+fn escaped_char(c: char) -> char {
+    match c {
+        'n' => '\n',
+        't' => '\t',
+        'r' => '\r',
+        c => c,
+    }
+}
+
+fn shorthand_class(c: char) -> Option<(Vec<(char, char)>, bool)> {
+    let digits = vec![('0', '9')];
+    let word = vec![('0', '9'), ('A', 'Z'), ('a', 'z'), ('_', '_')];
+    let space = vec![(' ', ' '), ('\t', '\r')];
+    Some(match c {
+        'd' => (digits, false),
+        'D' => (digits, true),
+        'w' => (word, false),
+        'W' => (word, true),
+        's' => (space, false),
+        'S' => (space, true),
+        _ => return None,
+    })
+}
+
+fn compile(node: &Node, program: &mut Vec<Instruction>) {
+    match node {
+        Node::Char(c) => program.push(Instruction::Char(*c)),
+        Node::Any => program.push(Instruction::Any),
+        Node::Class(ranges, negated) => program.push(Instruction::Class(ranges.clone(), *negated)),
+        Node::Start => program.push(Instruction::Start),
+        Node::End => program.push(Instruction::End),
+        Node::Concat(items) => items.iter().for_each(|item| compile(item, program)),
+        Node::Alternate(branches) => {
+            // split L1, next; L1: first; jump end; next: split L2, ...
+            let mut jumps = Vec::new();
+            for (index, branch) in branches.iter().enumerate() {
+                if index + 1 < branches.len() {
+                    let split = program.len();
+                    program.push(Instruction::Split(split + 1, 0));
+                    compile(branch, program);
+                    jumps.push(program.len());
+                    program.push(Instruction::Jump(0));
+                    let next = program.len();
+                    program[split] = Instruction::Split(split + 1, next);
+                } else {
+                    compile(branch, program);
+                }
+            }
+            let end = program.len();
+            for jump in jumps {
+                program[jump] = Instruction::Jump(end);
+            }
+        }
+        Node::Repeat(inner, min, max) => {
+            for _ in 0..*min {
+                compile(inner, program);
+            }
+            match max {
+                None => {
+                    let split = program.len();
+                    program.push(Instruction::Split(split + 1, 0));
+                    compile(inner, program);
+                    program.push(Instruction::Jump(split));
+                    program[split] = Instruction::Split(split + 1, program.len());
+                }
+                Some(max) => {
+                    let mut splits = Vec::new();
+                    for _ in *min..*max {
+                        splits.push(program.len());
+                        program.push(Instruction::Split(program.len() + 1, 0));
+                        compile(inner, program);
+                    }
+                    let end = program.len();
+                    for split in splits {
+                        program[split] = Instruction::Split(split + 1, end);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Regex {
+    pub fn new(pattern: &str, ignore_case: bool) -> io::Result<Regex> {
+        let mut parser = Parser { pattern, chars: pattern.chars().collect(), at: 0 };
+        let node = parser.alternation()?;
+        if parser.at != parser.chars.len() {
+            return Err(invalid(pattern, "unmatched )"));
+        }
+        let mut program = Vec::new();
+        compile(&node, &mut program);
+        program.push(Instruction::Match);
+        Ok(Regex { program, ignore_case })
+    }
+
+    fn char_matches(&self, instruction: &Instruction, c: char) -> bool {
+        let fold = |c: char| if self.ignore_case { c.to_lowercase().next().unwrap_or(c) } else { c };
+        match instruction {
+            Instruction::Char(expected) => fold(*expected) == fold(c),
+            Instruction::Any => c != '\n',
+            Instruction::Class(ranges, negated) => {
+                let inside = |c: char| ranges.iter().any(|&(start, end)| start <= c && c <= end);
+                let found = inside(c) || (self.ignore_case && (inside(fold(c)) || c.to_uppercase().next().is_some_and(inside)));
+                found != *negated
+            }
+            _ => false,
+        }
+    }
+
+    // Add a thread and everything reachable from it without consuming a character
+    fn add_thread(&self, threads: &mut Vec<usize>, on_list: &mut [bool], pc: usize, at: usize, len: usize) {
+        if on_list[pc] {
+            return;
+        }
+        on_list[pc] = true;
+        match self.program[pc] {
+            Instruction::Jump(target) => self.add_thread(threads, on_list, target, at, len),
+            Instruction::Split(first, second) => {
+                self.add_thread(threads, on_list, first, at, len);
+                self.add_thread(threads, on_list, second, at, len);
+            }
+            Instruction::Start if at == 0 => self.add_thread(threads, on_list, pc + 1, at, len),
+            Instruction::End if at == len => self.add_thread(threads, on_list, pc + 1, at, len),
+            Instruction::Start | Instruction::End => {}
+            _ => threads.push(pc),
+        }
+    }
+
+    // Whether the pattern matches anywhere in the text
+    pub fn is_match(&self, text: &str) -> bool {
+        let chars: Vec<char> = text.chars().collect();
+        let mut current = Vec::new();
+        let mut on_list = vec![false; self.program.len()];
+
+        for at in 0..=chars.len() {
+            // A new attempt may start at every position
+            self.add_thread(&mut current, &mut on_list, 0, at, chars.len());
+            if current.iter().any(|&pc| matches!(self.program[pc], Instruction::Match)) {
+                return true;
+            }
+            let Some(&c) = chars.get(at) else {
+                break;
+            };
+
+            let mut next = Vec::new();
+            on_list.iter_mut().for_each(|seen| *seen = false);
+            for &pc in &current {
+                if self.char_matches(&self.program[pc], c) {
+                    self.add_thread(&mut next, &mut on_list, pc + 1, at + 1, chars.len());
+                }
+            }
+            current = next;
+        }
+        false
+    }
+}
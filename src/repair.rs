@@ -0,0 +1,101 @@
+// makeiso repair: the problems makeiso verify finds that can be put right without guessing,
+// put right in a copy of the image. The path tables are rebuilt from the directory records,
+// a missing descriptor set terminator is written back (when its block is free), the copy of
+// a both-endian field that isn't believed is set from the one that is (the little-endian one
+// unless --trust be), and a volume size that is wrong is set to what the tree takes up, or
+// for a copy that only lost padding at its end, to what is there.
+//
+// The image itself is never written to; every change is listed, and the copy is checked
+// again once it is written.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, ErrorKind, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use super::reader::{open_source, ReadAt};
+use super::verify::{self, Fix};
+use super::BLOCK_SIZE;
+
+const USAGE: &str = "Usage: makeiso repair [--force] <image.iso> <repaired.iso>";
+const COPY_CHUNK_SIZE: usize = 1024 * 1024;
+
+// makeiso repair [--force] <image.iso> <repaired.iso>
+pub fn repair(args: &[String]) -> io::Result<()> {
+    let force = args.iter().any(|arg| arg == "--force");
+    let [location, output] = &args.iter().filter(|arg| *arg != "--force").collect::<Vec<_>>()[..] else {
+        return Err(io::Error::new(ErrorKind::InvalidInput, USAGE));
+    };
+    let (location, output) = (Path::new(location), PathBuf::from(output));
+    if output.as_path() == location {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "the repaired image goes to a new file, not over the image"));
+    }
+
+    let (problems, _) = verify::check(location)?;
+    if problems.is_empty() {
+        println!("Nothing to repair in {}", location.display());
+        return Ok(());
+    }
+    let (fixes, left): (Vec<_>, Vec<_>) = problems.into_iter().partition(|problem| problem.fix.is_some());
+    for problem in &left {
+        eprintln!("Can't repair: {}", problem);
+    }
+    if fixes.is_empty() {
+        return Err(io::Error::new(ErrorKind::InvalidData, format!("none of the problems of {} can be repaired", location.display())));
+    }
+
+    let mut file = if force { File::create(&output) } else { OpenOptions::new().write(true).create_new(true).open(&output) }
+        .map_err(|e| io::Error::new(e.kind(), format!("could not create {}: {}{}", output.display(), e, if e.kind() == ErrorKind::AlreadyExists { " (--force replaces it)" } else { "" })))?;
+    copy_image(location, &mut file)?;
+    println!("Repairs in {}:", output.display());
+    let all: Vec<&Fix> = fixes.iter().map(|problem| problem.fix.as_ref().unwrap()).collect();
+    for (index, fix) in all.iter().enumerate() {
+        if superseded(fix, &all[index + 1..]) {
+            continue;
+        }
+        if let Some(blocks) = fix.blocks {
+            file.set_len(blocks * BLOCK_SIZE as u64)?;
+        }
+        for (offset, bytes) in &fix.patches {
+            file.seek(SeekFrom::Start(*offset))?;
+            file.write_all(bytes)?;
+        }
+        println!("    {}", fix.change);
+    }
+    file.sync_all()?;
+    drop(file);
+
+    let (problems, _) = verify::check(&output)?;
+    for problem in &problems {
+        eprintln!("Still there: {}", problem);
+    }
+    println!("Repaired {} of {} problems", fixes.len(), fixes.len() + left.len());
+    if !left.is_empty() || !problems.is_empty() {
+        return Err(io::Error::new(ErrorKind::InvalidData, format!("{} still has problems", output.display())));
+    }
+    Ok(())
+}
+
+// Whether later fixes write over every byte a fix writes (and set the size, if it does), as
+// setting the volume size after the big-endian copy of it was set: only what ends up in the
+// copy is listed
+fn superseded(fix: &Fix, later: &[&Fix]) -> bool {
+    let covered = |offset: u64, len: usize| {
+        (offset..offset + len as u64).all(|byte| later.iter().any(|other| other.patches.iter().any(|(at, bytes)| byte >= *at && byte < at + bytes.len() as u64)))
+    };
+    (fix.blocks.is_none() || later.iter().any(|other| other.blocks.is_some())) && fix.patches.iter().all(|(offset, bytes)| covered(*offset, bytes.len()))
+}
+
+// The image as the reader sees it, decrypted and without the framing of raw sectors
+fn copy_image(location: &Path, output: &mut File) -> io::Result<()> {
+    let mut source = open_source(location)?;
+    let size = source.size()?;
+    let mut buffer = vec![0u8; COPY_CHUNK_SIZE];
+    let mut offset = 0;
+    while offset < size {
+        let len = (size - offset).min(COPY_CHUNK_SIZE as u64) as usize;
+        source.read_exact_at(offset, &mut buffer[..len])?;
+        output.write_all(&buffer[..len])?;
+        offset += len as u64;
+    }
+    Ok(())
+}
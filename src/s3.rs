@@ -0,0 +1,409 @@
+// S3 (and S3-compatible) storage as a source tree and as an image destination
+//
+// Locations are s3://bucket/prefix. Credentials and the region come from the usual
+// AWS_ACCESS_KEY_ID, AWS_SECRET_ACCESS_KEY, AWS_SESSION_TOKEN and AWS_REGION variables;
+// AWS_ENDPOINT_URL points at another S3-compatible service. Requests are path-style and
+// signed by curl (--aws-sigv4). Prefixes ending in "/" are listed as directories. Images are
+// uploaded in parts, so an interrupted upload can be resumed.
+
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::backend::{curl_output, CurlReader, ImageSink, RemoteEntry, RemoteStore};
+use super::md5::Md5;
+use super::{age, events, sha256, unix_from_utc, ImageOutput};
+
+// Where requests go and how they are signed
+pub struct S3Store {
+    endpoint: String,
+    region: String,
+    config: CurlConfig, // the credentials, for curl -K
+}
+
+// A curl config file only its owner can read, removed when the store goes. The keys go to curl
+// through it rather than its arguments, which other users see; its standard input carries the
+// uploads.
+struct CurlConfig(PathBuf);
+
+impl CurlConfig {
+    fn new(lines: &[(&str, &str)]) -> io::Result<CurlConfig> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let path = env::temp_dir().join(format!("makeiso-s3-{}-{}.curlrc", std::process::id(), COUNT.fetch_add(1, Ordering::Relaxed)));
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(&path).map_err(|e| io::Error::new(e.kind(), format!("could not create {} for curl: {}", path.display(), e)))?;
+        let config = CurlConfig(path);
+        let text: String = lines.iter().map(|(name, value)| format!("{} = \"{}\"\n", name, value.replace('\\', "\\\\").replace('"', "\\\""))).collect();
+        file.write_all(text.as_bytes())?;
+        Ok(config)
+    }
+}
+
+impl Drop for CurlConfig {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+impl S3Store {
+    pub fn new(location: &Path) -> io::Result<S3Store> {
+        split_location(location)?;
+        let variable = |name: &str| env::var(name).ok().filter(|value| !value.is_empty());
+        let (Some(access_key), Some(secret_key)) = (variable("AWS_ACCESS_KEY_ID"), variable("AWS_SECRET_ACCESS_KEY")) else {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "S3 needs AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY"));
+        };
+        let region = variable("AWS_REGION").or_else(|| variable("AWS_DEFAULT_REGION")).unwrap_or_else(|| "us-east-1".to_string());
+        let endpoint = variable("AWS_ENDPOINT_URL").unwrap_or_else(|| format!("https://s3.{}.amazonaws.com", region));
+        let credentials = format!("{}:{}", access_key, secret_key);
+        let mut config = vec![("user", credentials.as_str())];
+        let token = variable("AWS_SESSION_TOKEN").map(|token| format!("x-amz-security-token: {}", token));
+        if let Some(header) = &token {
+            config.push(("header", header.as_str()));
+        }
+        Ok(S3Store {
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            region,
+            config: CurlConfig::new(&config)?,
+        })
+    }
+
+    // A signed curl request
+    fn command(&self, url: &str) -> Command {
+        let mut command = Command::new("curl");
+        command
+            .args(["--silent", "--show-error", "--fail", "--aws-sigv4"])
+            .arg(format!("aws:amz:{}:s3", self.region))
+            .arg("-K")
+            .arg(&self.config.0)
+            .args(["-H", "x-amz-content-sha256: UNSIGNED-PAYLOAD"])
+            .arg(url);
+        command
+    }
+
+    fn object_url(&self, bucket: &str, key: &str) -> String {
+        format!("{}/{}/{}", self.endpoint, bucket, encode(key, false))
+    }
+}
+
+impl RemoteStore for S3Store {
+    fn list(&self, dir: &str) -> io::Result<Vec<RemoteEntry>> {
+        let (bucket, prefix) = split_location(Path::new(dir))?;
+        let prefix = if prefix.is_empty() { String::new() } else { format!("{}/", prefix) };
+        let mut entries = Vec::new();
+        let mut token: Option<String> = None;
+
+        // ListObjectsV2 returns up to 1000 keys per page
+        loop {
+            let mut url = format!("{}/{}?list-type=2&delimiter=%2F&prefix={}", self.endpoint, bucket, encode(&prefix, true));
+            if let Some(token) = &token {
+                url += &format!("&continuation-token={}", encode(token, true));
+            }
+            let body = String::from_utf8_lossy(&curl_output(self.command(&url), dir)?).into_owned();
+
+            for contents in elements(&body, "Contents") {
+                let key = element(contents, "Key").unwrap_or_default();
+                let name = &key[prefix.len().min(key.len())..];
+                if name.is_empty() || name.ends_with('/') {
+                    continue; // The "directory" object of the prefix itself, or an empty folder marker
+                }
+                entries.push(RemoteEntry {
+                    name: name.to_string(),
+                    is_directory: false,
+                    len: element(contents, "Size").and_then(|size| size.parse().ok()).unwrap_or(0),
+                    modified: element(contents, "LastModified").and_then(|time| parse_time(&time)),
+                });
+            }
+            for common in elements(&body, "CommonPrefixes") {
+                let key = element(common, "Prefix").unwrap_or_default();
+                let name = key[prefix.len().min(key.len())..].trim_end_matches('/');
+                if !name.is_empty() {
+                    entries.push(RemoteEntry { name: name.to_string(), is_directory: true, len: 0, modified: None });
+                }
+            }
+
+            token = element(&body, "NextContinuationToken").filter(|_| element(&body, "IsTruncated").as_deref() == Some("true"));
+            if token.is_none() {
+                return Ok(entries);
+            }
+        }
+    }
+
+    fn get(&self, path: &str) -> io::Result<Box<dyn Read + Send>> {
+        let (bucket, key) = split_location(Path::new(path))?;
+        Ok(Box::new(CurlReader::spawn(self.command(&self.object_url(bucket, key)), path)?))
+    }
+}
+
+// Parts are at least this large (S3 takes no smaller ones but the last) and there are at most
+// MAX_PARTS of them, so an image of any size S3 holds fits
+const MIN_PART_SIZE: u64 = 8 * 1024 * 1024;
+const MAX_PARTS: u64 = 10_000;
+
+// An image uploaded to an object in parts, each sent as soon as it is written, with its MD5 for
+// S3 to check it by. An upload that stops part way stays open on the S3 side: the next upload
+// to the same key picks it up and sends only the parts that aren't there already (or aren't
+// the same), so an interrupted run resumes where it stopped once the image is built again.
+pub struct MultipartUpload {
+    store: S3Store,
+    url: String,
+    location: String,
+    upload_id: String,
+    part_size: usize,
+    buffer: Vec<u8>,
+    position: u64,
+    earlier: HashMap<usize, (String, u64)>, // ETags and sizes of the parts already there, by number
+    digests: Vec<[u8; 16]>,               // MD5s of the parts so far
+    reused: usize,
+    finished: bool,
+}
+
+// Upload an image of the given size to an object, resuming an upload of it that didn't finish
+pub fn upload(location: &Path, size: u64) -> io::Result<MultipartUpload> {
+    let store = S3Store::new(location)?;
+    let (bucket, key) = split_location(location)?;
+    if key.is_empty() {
+        return Err(io::Error::new(ErrorKind::InvalidInput, format!("{} does not name an object", location.display())));
+    }
+    let url = store.object_url(bucket, key);
+    let what = location.display().to_string();
+    let part_size = MIN_PART_SIZE.max(size.div_ceil(MAX_PARTS).next_multiple_of(1024 * 1024)) as usize;
+
+    // The latest upload of this key that was started and not completed
+    let body = text(curl_output(store.command(&format!("{}/{}?uploads&prefix={}", store.endpoint, bucket, encode(key, true))), &what)?);
+    let open = elements(&body, "Upload").into_iter().filter(|upload| element(upload, "Key").as_deref() == Some(key)).filter_map(|upload| element(upload, "UploadId")).next_back();
+    let (upload_id, earlier) = match open {
+        Some(upload_id) => {
+            let earlier = list_parts(&store, &url, &upload_id, &what)?;
+            events::info(&format!("Resuming the upload of {} ({} parts are there already)", what, earlier.len()));
+            (upload_id, earlier)
+        }
+        None => {
+            let mut command = store.command(&format!("{}?uploads", url));
+            command.args(["-X", "POST"]);
+            let body = text(curl_output(command, &what)?);
+            let upload_id = element(&body, "UploadId").ok_or_else(|| io::Error::other(format!("S3 did not start an upload of {}: {}", what, body.trim())))?;
+            (upload_id, HashMap::new())
+        }
+    };
+    Ok(MultipartUpload { store, url, location: what, upload_id, part_size, buffer: Vec::with_capacity(part_size), position: 0, earlier, digests: Vec::new(), reused: 0, finished: false })
+}
+
+// The parts an upload has, by number, a thousand to a page
+fn list_parts(store: &S3Store, url: &str, upload_id: &str, what: &str) -> io::Result<HashMap<usize, (String, u64)>> {
+    let mut parts = HashMap::new();
+    let mut marker = 0;
+    loop {
+        let body = text(curl_output(store.command(&format!("{}?uploadId={}&part-number-marker={}", url, encode(upload_id, true), marker)), what)?);
+        for part in elements(&body, "Part") {
+            let (Some(number), Some(etag), Some(size)) = (element(part, "PartNumber"), element(part, "ETag"), element(part, "Size")) else {
+                continue;
+            };
+            if let (Ok(number), Ok(size)) = (number.parse(), size.parse()) {
+                parts.insert(number, (etag, size));
+            }
+        }
+        match element(&body, "NextPartNumberMarker").and_then(|next| next.parse().ok()) {
+            Some(next) if element(&body, "IsTruncated").as_deref() == Some("true") => marker = next,
+            _ => return Ok(parts),
+        }
+    }
+}
+
+impl MultipartUpload {
+    // Send the gathered bytes as the next part, unless an earlier run sent the same
+    fn send_part(&mut self) -> io::Result<()> {
+        let number = self.digests.len() + 1;
+        let mut md5 = Md5::new();
+        md5.update(&self.buffer);
+        let digest = md5.finish();
+        let etag = format!("\"{}\"", sha256::hex(&digest));
+        self.digests.push(digest);
+        if self.earlier.get(&number).is_some_and(|(earlier, size)| earlier.eq_ignore_ascii_case(&etag) && *size == self.buffer.len() as u64) {
+            self.reused += 1;
+            self.buffer.clear();
+            return Ok(());
+        }
+
+        let mut command = self.store.command(&format!("{}?partNumber={}&uploadId={}", self.url, number, encode(&self.upload_id, true)));
+        command.args(["--upload-file", "-", "-H", "Transfer-Encoding:", "--write-out", "%header{etag}"]);
+        command.arg("-H").arg(format!("Content-Length: {}", self.buffer.len()));
+        // S3 refuses a part whose bytes don't have this MD5
+        command.arg("-H").arg(format!("Content-MD5: {}==", age::base64_encode(&digest)));
+        let answer = text(send(command, &self.buffer, &format!("part {} of {}", number, self.location))?);
+        let answer = answer.trim();
+        // With SSE-KMS the ETag is no MD5, and only Content-MD5 vouches for the part
+        if is_md5_etag(answer) && !answer.eq_ignore_ascii_case(&etag) {
+            return Err(io::Error::new(ErrorKind::InvalidData, format!("part {} of {} arrived damaged: S3 has {}, the part is {}", number, self.location, answer, etag)));
+        }
+        self.buffer.clear();
+        Ok(())
+    }
+}
+
+impl Write for MultipartUpload {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if self.finished {
+            return Err(io::Error::other(format!("the upload to {} was already finished", self.location)));
+        }
+        let take = data.len().min(self.part_size - self.buffer.len());
+        self.buffer.extend_from_slice(&data[..take]);
+        self.position += take as u64;
+        if self.buffer.len() == self.part_size {
+            self.send_part()?;
+        }
+        Ok(take)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for MultipartUpload {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        let target = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(_) => None,
+        };
+        match target {
+            Some(target) if target >= self.position => {
+                io::copy(&mut io::repeat(0).take(target - self.position), self)?;
+                Ok(self.position)
+            }
+            _ => Err(io::Error::new(ErrorKind::Unsupported, format!("{} is written as a stream and can't be rewritten", self.location))),
+        }
+    }
+}
+
+impl ImageOutput for MultipartUpload {}
+
+impl ImageSink for MultipartUpload {
+    // Send the last part and put the parts together; S3's ETag of the whole is the MD5 of the
+    // MD5s of the parts, which is checked too
+    fn finish(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() || self.digests.is_empty() {
+            self.send_part()?;
+        }
+        let mut request = String::from("<CompleteMultipartUpload>");
+        let mut md5 = Md5::new();
+        for (index, digest) in self.digests.iter().enumerate() {
+            request += &format!("<Part><PartNumber>{}</PartNumber><ETag>\"{}\"</ETag></Part>", index + 1, sha256::hex(digest));
+            md5.update(digest);
+        }
+        request += "</CompleteMultipartUpload>";
+        let mut command = self.store.command(&format!("{}?uploadId={}", self.url, encode(&self.upload_id, true)));
+        command.args(["-X", "POST", "--data-binary", "@-"]);
+        // S3 reports some failures to complete in the body of a successful response
+        let body = text(send(command, request.as_bytes(), &self.location)?);
+        if !elements(&body, "Error").is_empty() {
+            return Err(io::Error::other(format!("S3 could not complete the upload of {}: {}", self.location, element(&body, "Message").unwrap_or(body.clone()))));
+        }
+        let expected = format!("\"{}-{}\"", sha256::hex(&md5.finish()), self.digests.len());
+        if let Some(etag) = element(&body, "ETag").filter(|etag| etag.ends_with(&format!("-{}\"", self.digests.len()))) {
+            if !etag.eq_ignore_ascii_case(&expected) {
+                return Err(io::Error::new(ErrorKind::InvalidData, format!("{} was put together wrong: S3 has {}, the parts make {}", self.location, etag, expected)));
+            }
+        }
+        self.finished = true;
+        if self.reused > 0 {
+            events::info(&format!("Uploaded {} ({} of {} parts were there from an earlier run)", self.location, self.reused, self.digests.len()));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for MultipartUpload {
+    fn drop(&mut self) {
+        if !self.finished && !self.digests.is_empty() {
+            events::warn(&format!("The upload to {} was left unfinished; uploading the same image there again resumes it", self.location));
+        }
+    }
+}
+
+// "0123...": the ETag of an object or part S3 stores as it is, the MD5 of its bytes
+fn is_md5_etag(etag: &str) -> bool {
+    let hex = etag.trim_matches('"');
+    hex.len() == 32 && hex.bytes().all(|byte| byte.is_ascii_hexdigit())
+}
+
+// Run a curl request with data for its standard input, as curl_output does without
+fn send(mut command: Command, data: &[u8], what: &str) -> io::Result<Vec<u8>> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| io::Error::new(e.kind(), format!("could not run curl for {}: {}", what, e)))?;
+    let written = child.stdin.take().expect("curl stdin is piped").write_all(data);
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!("curl could not upload {}: {}", what, String::from_utf8_lossy(&output.stderr).trim())));
+    }
+    written?;
+    Ok(output.stdout)
+}
+
+fn text(body: Vec<u8>) -> String {
+    String::from_utf8_lossy(&body).into_owned()
+}
+
+// Bucket and key (or prefix, without the trailing slash) of an s3:// location
+fn split_location(location: &Path) -> io::Result<(&str, &str)> {
+    let rest = location.to_str().and_then(|location| location.strip_prefix("s3://")).unwrap_or("");
+    let (bucket, key) = rest.split_once('/').unwrap_or((rest, ""));
+    if bucket.is_empty() {
+        return Err(io::Error::new(ErrorKind::InvalidInput, format!("{} is not an s3://bucket/key location", location.display())));
+    }
+    Ok((bucket, key.trim_matches('/')))
+}
+
+// Percent-encode everything but unreserved characters (and "/" in object paths)
+fn encode(text: &str, query: bool) -> String {
+    let mut encoded = String::new();
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => encoded.push(byte as char),
+            b'/' if !query => encoded.push('/'),
+            _ => encoded += &format!("%{:02X}", byte),
+        }
+    }
+    encoded
+}
+
+// The contents of every <tag>...</tag> in a response; listings are flat enough not to need a parser
+fn elements<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+    let (open, close) = (format!("<{}>", tag), format!("</{}>", tag));
+    let mut found = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        let after = &rest[start + open.len()..];
+        let Some(end) = after.find(&close) else {
+            break;
+        };
+        found.push(&after[..end]);
+        rest = &after[end + close.len()..];
+    }
+    found
+}
+
+// The text of the first <tag>, with entities resolved
+fn element(xml: &str, tag: &str) -> Option<String> {
+    let text = *elements(xml, tag).first()?;
+    Some(text.replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"").replace("&apos;", "'").replace("&amp;", "&"))
+}
+
+// 2024-05-01T12:34:56.000Z
+fn parse_time(text: &str) -> Option<SystemTime> {
+    let number = |range: std::ops::Range<usize>| text.get(range)?.parse::<u32>().ok();
+    let timestamp = unix_from_utc(number(0..4)? as i64, number(5..7)?, number(8..10)?, number(11..13)?, number(14..16)?, number(17..19)?);
+    u64::try_from(timestamp).ok().map(|seconds| UNIX_EPOCH + Duration::from_secs(seconds))
+}
@@ -0,0 +1,145 @@
+// Reading damaged media: unreadable blocks are filled in instead of failing the read
+//
+// Salvage wraps an image source. A read that fails is split into its 2048-byte blocks, each
+// tried again a few times on its own; blocks that still can't be read are filled with zeros
+// (or a marker naming the block, which is easier to spot in recovered files) and recorded
+// with whatever was being read at the time. The map of bad blocks is written out as a report
+// at the end, much like a ddrescue map file but saying which directories and files were hit.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use super::reader::ReadAt;
+use super::BLOCK_SIZE;
+
+// What unreadable blocks are replaced with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fill {
+    Zeros,
+    Marker, // "BAD SECTOR <lba>" repeated over the block
+}
+
+impl Fill {
+    pub fn parse(text: &str) -> Option<Fill> {
+        match text {
+            "zero" | "zeros" => Some(Fill::Zeros),
+            "marker" => Some(Fill::Marker),
+            _ => None,
+        }
+    }
+
+    fn block(self, lba: u64) -> Vec<u8> {
+        match self {
+            Fill::Zeros => vec![0u8; BLOCK_SIZE],
+            Fill::Marker => format!("BAD SECTOR {:<10}\n", lba).into_bytes().into_iter().cycle().take(BLOCK_SIZE).collect(),
+        }
+    }
+}
+
+// An image source that reads around unreadable blocks; without a fill, read errors are
+// passed on as they are
+pub struct Salvage<R> {
+    source: R,
+    fill: Option<Fill>,
+    pub retries: u32,           // Further attempts at each unreadable block
+    pub context: String,        // What is being read, for the map of bad blocks
+    bad: BTreeMap<u64, String>, // Unreadable blocks and what they belonged to
+    last_error: Option<String>,
+}
+
+impl<R: ReadAt> Salvage<R> {
+    pub fn new(source: R, fill: Option<Fill>, retries: u32) -> Salvage<R> {
+        Salvage {
+            source,
+            fill,
+            retries,
+            context: "volume descriptors".to_string(),
+            bad: BTreeMap::new(),
+            last_error: None,
+        }
+    }
+
+    // Number of blocks that could not be read so far
+    pub fn bad_blocks(&self) -> usize {
+        self.bad.len()
+    }
+
+    // Put a name to the bad blocks within a run of blocks, once it's known what they hold
+    pub fn label(&mut self, first_lba: u64, blocks: u64, name: &str) {
+        for (_, context) in self.bad.range_mut(first_lba..first_lba + blocks) {
+            *context = name.to_string();
+        }
+    }
+
+    // Read one part of a block, trying again when it fails; false if it never succeeded
+    fn read_block(&mut self, offset: u64, buf: &mut [u8]) -> bool {
+        for _ in 0..=self.retries {
+            match self.source.read_exact_at(offset, buf) {
+                Ok(()) => return true,
+                Err(e) => self.last_error = Some(e.to_string()),
+            }
+        }
+        false
+    }
+
+    // Write the map of bad blocks: runs of consecutive blocks that belonged to the same thing
+    pub fn write_report(&self, report_path: &Path, image: &Path) -> io::Result<()> {
+        let mut output = BufWriter::new(File::create(report_path)?);
+        writeln!(output, "# makeiso salvage report for {}", image.display())?;
+        writeln!(output, "# {} unreadable blocks ({} bytes), filled with {}", self.bad.len(), self.bad.len() * BLOCK_SIZE, match self.fill {
+            Some(Fill::Marker) => "markers",
+            _ => "zeros",
+        })?;
+        if let Some(error) = &self.last_error {
+            writeln!(output, "# last read error: {}", error)?;
+        }
+        writeln!(output, "# first_lba  blocks  belongs_to")?;
+
+        let mut runs: Vec<(u64, u64, &str)> = Vec::new();
+        for (&lba, context) in &self.bad {
+            match runs.last_mut() {
+                Some((start, count, owner)) if *start + *count == lba && *owner == context => *count += 1,
+                _ => runs.push((lba, 1, context)),
+            }
+        }
+        for (start, count, owner) in runs {
+            writeln!(output, "{:<11}  {:<6}  {}", start, count, owner)?;
+        }
+        output.flush()
+    }
+}
+
+impl<R: ReadAt> ReadAt for Salvage<R> {
+    fn read_exact_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        let Some(fill) = self.fill else {
+            return self.source.read_exact_at(offset, buf);
+        };
+        if self.source.read_exact_at(offset, buf).is_ok() {
+            return Ok(());
+        }
+
+        // Go block by block so that one bad block costs only itself
+        let block_size = BLOCK_SIZE as u64;
+        let mut done = 0;
+        while done < buf.len() {
+            let position = offset + done as u64;
+            let lba = position / block_size;
+            let from = (position % block_size) as usize;
+            let len = (BLOCK_SIZE - from).min(buf.len() - done);
+            if self.read_block(position, &mut buf[done..done + len]) {
+                self.bad.remove(&lba); // Read on a later attempt
+            } else {
+                buf[done..done + len].copy_from_slice(&fill.block(lba)[from..from + len]);
+                // One message per run of bad blocks rather than per block
+                let run_continues = lba > 0 && self.bad.get(&(lba - 1)) == Some(&self.context);
+                if self.bad.insert(lba, self.context.clone()).is_none() && !run_continues {
+                    eprintln!("Unreadable blocks from {} in {}, filling them in", lba, self.context);
+                }
+            }
+            done += len;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,101 @@
+// Cron-style schedules for profiles (schedule = "30 2 * * *"), evaluated in UTC
+//
+// Five fields: minute, hour, day of the month, month and day of the week (0 or 7 is Sunday),
+// each `*`, a number, a range `a-b`, a step `*/n` or `a-b/n`, or a list of those. As in cron,
+// when both day fields are restricted a day matching either one counts. @hourly, @daily
+// (@midnight), @weekly, @monthly and @yearly (@annually) stand for the usual expressions.
+
+use std::io::{self, ErrorKind};
+
+use super::utc_date_time;
+
+const MAX_SEARCH_MINUTES: i64 = 366 * 24 * 60 * 5; // Far enough for any schedule that ever fires
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schedule {
+    minutes: u64, // Bit n set when the field allows n
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool, // The day of the month field was *
+    any_weekday: bool,
+}
+
+impl Schedule {
+    pub fn parse(text: &str) -> io::Result<Schedule> {
+        let expanded = match text.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            other => other,
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(io::Error::new(ErrorKind::InvalidInput, format!("invalid schedule: {} (expected five fields, e.g. \"30 2 * * *\")", text)));
+        };
+        let field = |spec: &str, low: u32, high: u32| {
+            parse_field(spec, low, high).map_err(|e| io::Error::new(ErrorKind::InvalidInput, format!("invalid schedule {}: {}", text, e)))
+        };
+        let mut weekdays = field(weekday, 0, 7)?;
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1; // 7 is Sunday too
+        }
+        Ok(Schedule {
+            minutes: field(minute, 0, 59)?,
+            hours: field(hour, 0, 23)?,
+            days: field(day, 1, 31)?,
+            months: field(month, 1, 12)?,
+            weekdays,
+            any_day: day == "*",
+            any_weekday: weekday == "*",
+        })
+    }
+
+    // Whether the schedule fires in the minute a timestamp falls in
+    pub fn matches(&self, timestamp: i64) -> bool {
+        let (_, month, day, hour, minute, _) = utc_date_time(timestamp);
+        let weekday = (timestamp.div_euclid(86_400) + 4).rem_euclid(7) as u32; // 1970-01-01 was a Thursday
+        let bit = |mask: u64, value: u32| mask & (1 << value) != 0;
+        let day_matches = match (self.any_day, self.any_weekday) {
+            (false, false) => bit(self.days, day) || bit(self.weekdays, weekday),
+            _ => bit(self.days, day) && bit(self.weekdays, weekday),
+        };
+        bit(self.minutes, minute) && bit(self.hours, hour) && bit(self.months, month) && day_matches
+    }
+
+    // The start of the first minute after the timestamp's that the schedule fires in
+    pub fn next_after(&self, timestamp: i64) -> Option<i64> {
+        let first = timestamp.div_euclid(60) * 60 + 60;
+        (0..MAX_SEARCH_MINUTES).map(|minute| first + minute * 60).find(|&time| self.matches(time))
+    }
+}
+
+// One field as a bit mask of the values it allows
+fn parse_field(spec: &str, low: u32, high: u32) -> Result<u64, String> {
+    let mut mask = 0u64;
+    for part in spec.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|&step| step > 0).ok_or(format!("invalid step in {}", part))?),
+            None => (part, 1),
+        };
+        let number = |text: &str| text.parse::<u32>().ok().filter(|value| (low..=high).contains(value)).ok_or(format!("{} is not in {}-{}", text, low, high));
+        let (start, end) = match range {
+            "*" => (low, high),
+            _ => match range.split_once('-') {
+                Some((start, end)) => (number(start)?, number(end)?),
+                None if step > 1 => (number(range)?, high), // "5/15" runs from 5 on
+                None => (number(range)?, number(range)?),
+            },
+        };
+        if start > end {
+            return Err(format!("{} is an empty range", range));
+        }
+        for value in (start..=end).step_by(step as usize) {
+            mask |= 1 << value;
+        }
+    }
+    Ok(mask)
+}
@@ -0,0 +1,122 @@
+// scrypt (RFC 7914) with r = 8 and p = 1, the parameters age fixes for passphrases, and the
+// PBKDF2-HMAC-SHA-256 it is built on
+//
+// Memory is 1 KiB times N, so the usual N = 2^18 takes 256 MiB for a moment.
+
+use super::sha256;
+
+const R: usize = 8;
+const BLOCK_WORDS: usize = 32 * R; // 128 * r bytes
+
+// Derive a key of the given length from a passphrase and a salt with N = 2^log_n
+pub fn scrypt(passphrase: &[u8], salt: &[u8], log_n: u8, len: usize) -> Vec<u8> {
+    let n = 1usize << log_n;
+    let bytes = pbkdf2(passphrase, salt, 128 * R);
+    let mut x: Vec<u32> = bytes.chunks(4).map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]])).collect();
+
+    // ROMix: fill the table with successive mixes, then mix again at data-dependent entries
+    let mut table = vec![0u32; n * BLOCK_WORDS];
+    let mut scratch = vec![0u32; BLOCK_WORDS];
+    for i in 0..n {
+        table[i * BLOCK_WORDS..(i + 1) * BLOCK_WORDS].copy_from_slice(&x);
+        block_mix(&mut x, &mut scratch);
+    }
+    for _ in 0..n {
+        let j = x[BLOCK_WORDS - 16] as usize & (n - 1);
+        for (word, entry) in x.iter_mut().zip(&table[j * BLOCK_WORDS..(j + 1) * BLOCK_WORDS]) {
+            *word ^= entry;
+        }
+        block_mix(&mut x, &mut scratch);
+    }
+
+    let mixed: Vec<u8> = x.iter().flat_map(|word| word.to_le_bytes()).collect();
+    pbkdf2(passphrase, &mixed, len)
+}
+
+// PBKDF2 with a single iteration, all scrypt asks of it
+fn pbkdf2(passphrase: &[u8], salt: &[u8], len: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(len + 32);
+    let mut counter = 1u32;
+    while out.len() < len {
+        let mut input = salt.to_vec();
+        input.extend_from_slice(&counter.to_be_bytes());
+        out.extend_from_slice(&sha256::hmac(passphrase, &input));
+        counter += 1;
+    }
+    out.truncate(len);
+    out
+}
+
+// BlockMix with Salsa20/8 over 2r 64-byte blocks; even outputs first, then the odd ones
+fn block_mix(b: &mut [u32], scratch: &mut [u32]) {
+    let mut x = [0u32; 16];
+    x.copy_from_slice(&b[BLOCK_WORDS - 16..]);
+    for i in 0..2 * R {
+        for (word, input) in x.iter_mut().zip(&b[i * 16..(i + 1) * 16]) {
+            *word ^= input;
+        }
+        salsa20_8(&mut x);
+        let at = if i % 2 == 0 { i / 2 } else { R + i / 2 } * 16;
+        scratch[at..at + 16].copy_from_slice(&x);
+    }
+    b.copy_from_slice(scratch);
+}
+
+fn salsa20_8(block: &mut [u32; 16]) {
+    let mut x = *block;
+    let mut quarter = |a: usize, b: usize, c: usize, d: usize| {
+        x[b] ^= x[a].wrapping_add(x[d]).rotate_left(7);
+        x[c] ^= x[b].wrapping_add(x[a]).rotate_left(9);
+        x[d] ^= x[c].wrapping_add(x[b]).rotate_left(13);
+        x[a] ^= x[d].wrapping_add(x[c]).rotate_left(18);
+    };
+    for _ in 0..4 {
+        quarter(0, 4, 8, 12);
+        quarter(5, 9, 13, 1);
+        quarter(10, 14, 2, 6);
+        quarter(15, 3, 7, 11);
+        quarter(0, 1, 2, 3);
+        quarter(5, 6, 7, 4);
+        quarter(10, 11, 8, 9);
+        quarter(15, 12, 13, 14);
+    }
+    for (word, mixed) in block.iter_mut().zip(x) {
+        *word = word.wrapping_add(mixed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::sha256::hex;
+    use super::{pbkdf2, scrypt};
+
+    // RFC 7914, section 11 (the one iteration scrypt uses)
+    #[test]
+    fn pbkdf2_one_iteration() {
+        assert_eq!(
+            hex(&pbkdf2(b"passwd", b"salt", 64)),
+            "55ac046e56e3089fec1691c22544b605f94185216dde0465e68b9d57c20dacbc49ca9cccf179b645991664b39d77ef317c71b845b1e30bd509112041d3a19783"
+        );
+    }
+
+    // The passphrase and salt of RFC 7914, section 12, at a work factor small enough to run
+    // every time; the key is the one OpenSSL derives for them
+    #[test]
+    fn small_work_factor() {
+        assert_eq!(
+            hex(&scrypt(b"pleaseletmein", b"SodiumChloride", 10, 64)),
+            "54173687d265e43226bd914b015267e2fdd4108aa05937fb549eceb0c276a285fe59e2293f5aa3fe288f0e7e552790daa4d8d79c09294663152acb719dd225b8"
+        );
+    }
+
+    // RFC 7914, section 12, the only vector with r = 8 and p = 1; it takes 1 GiB, so it runs
+    // with cargo test -- --ignored
+    #[test]
+    #[ignore]
+    fn rfc_7914() {
+        assert_eq!(
+            hex(&scrypt(b"pleaseletmein", b"SodiumChloride", 20, 64)),
+            "2101cb9b6a511aaeaddbbe09cf70f881ec568d574a2ffd4dabe5ee9820adaa478e56fd8f4ba5d09ffa1c6d927c40f4c337304049e8a952fbcbf45c6fa77a41a4"
+        );
+    }
+}
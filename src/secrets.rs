@@ -0,0 +1,77 @@
+// Files stored encrypted inside an otherwise plain image (--encrypt-glob): matching files are
+// recorded as age files under their name plus ".age", encrypted with the passphrase, and listed
+// in a hidden ENCRYPTED.TXT at the root, so the rest of the image mounts and reads as usual
+//
+// All of them share one header, so the passphrase is stretched once per image rather than once
+// per file, and each is a complete age file that `age -d` decrypts on its own. extract
+// decrypts them back to their names when given the passphrase. The contents are encrypted in
+// memory while the image is planned (the size of each has to be known for the layout), which
+// suits keys, credentials and other small secrets.
+
+use std::io::{self, Read};
+use std::path::PathBuf;
+
+use super::age::{Encryptor, Recipient};
+use super::events;
+use super::{directory_path, matches_any, Entry, FileEntry, ImageOptions, ImageTree, FLAG_HIDDEN};
+use super::backend::SourceProvider;
+
+pub const MANIFEST: &str = "ENCRYPTED.TXT";
+pub const EXTENSION: &str = ".age";
+
+// Replace the files --encrypt-glob matches with their encrypted versions and add the manifest
+pub fn encrypt_files(tree: &mut ImageTree, source: &dyn SourceProvider, options: &ImageOptions) -> io::Result<()> {
+    let mut selected = Vec::new();
+    for index in 0..tree.dirs.len() {
+        let dir_path = directory_path(tree, index);
+        for (entry, item) in tree.dirs[index].entries.iter().enumerate() {
+            if let Entry::File(file) = item {
+                if file.name != MANIFEST && matches_any(&options.encrypt_glob, &format!("{}{}", dir_path, file.name), &file.name) {
+                    selected.push((index, entry));
+                }
+            }
+        }
+    }
+    if selected.is_empty() {
+        events::warn("No files match --encrypt-glob, so nothing is encrypted");
+        return Ok(());
+    }
+
+    let encryptor = Encryptor::new(&[Recipient::Passphrase])?;
+    let mut manifest = String::new();
+    for &(index, entry) in &selected {
+        let Entry::File(file) = &tree.dirs[index].entries[entry] else {
+            continue;
+        };
+        let mut contents = Vec::with_capacity(file.size as usize);
+        source
+            .open(&file.source)
+            .and_then(|mut opened| opened.read_to_end(&mut contents))
+            .map_err(|e| io::Error::new(e.kind(), format!("could not read {} to encrypt it: {}", file.source.display(), e)))?;
+        let sealed = encryptor.seal(&contents)?;
+
+        let name = format!("{}{}", file.name, EXTENSION);
+        let path = format!("{}{}", directory_path(tree, index), name);
+        let encrypted = FileEntry {
+            name: name.clone(),
+            source: PathBuf::from(&path),
+            size: sealed.len() as u32,
+            modified: file.modified,
+            extent: 0,
+        };
+        tree.dirs[index].entries[entry] = Entry::File(encrypted);
+        tree.generated.entry(index).or_default().insert(name, sealed);
+        manifest.push_str(&path);
+        manifest.push('\n');
+    }
+    events::info(&format!("Encrypted {} files matching --encrypt-glob", selected.len()));
+
+    tree.add_generated(0, MANIFEST, manifest.into_bytes());
+    tree.record_flags.entry(0).or_default().insert(MANIFEST.to_string(), FLAG_HIDDEN);
+    Ok(())
+}
+
+// Image paths of the encrypted files, from the contents of a manifest
+pub fn parse_manifest(text: &str) -> Vec<String> {
+    text.lines().filter(|line| line.starts_with('/') && line.ends_with(EXTENSION)).map(str::to_string).collect()
+}
@@ -0,0 +1,125 @@
+// Low-level looks at an image, for chasing corruption: makeiso sector shows blocks by number,
+// as a hex dump (runs of identical lines folded into "*", as hexdump does) or as the bytes
+// themselves for piping elsewhere, and makeiso whatis tells what a byte offset belongs to,
+// from the block map of the image (blockmap.rs).
+//
+// Numbers are decimal or, with 0x, hexadecimal, as tools that report offsets print them both ways.
+
+use std::io::{self, ErrorKind, Write};
+use std::path::Path;
+
+use super::blockmap;
+use super::browse::{hex_line, HEX_WIDTH};
+use super::reader::{open_image, open_source, ReadAt};
+use super::BLOCK_SIZE;
+
+const SECTOR_USAGE: &str = "Usage: makeiso sector <image.iso> <lba> [count] [--hex|--raw]";
+const WHATIS_USAGE: &str = "Usage: makeiso whatis <image.iso> --offset N";
+
+// makeiso sector <image.iso> <lba> [count] [--hex|--raw]
+pub fn sector(args: &[String]) -> io::Result<()> {
+    let mut raw = false;
+    let mut positional = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--hex" => raw = false,
+            "--raw" => raw = true,
+            _ if arg.starts_with("--") => return Err(io::Error::new(ErrorKind::InvalidInput, format!("unknown option: {}\n{}", arg, SECTOR_USAGE))),
+            _ => positional.push(arg),
+        }
+    }
+    let (location, first, count) = match positional[..] {
+        [location, first] => (location, number(first, "block")?, 1),
+        [location, first, count] => (location, number(first, "block")?, number(count, "count")?),
+        _ => return Err(io::Error::new(ErrorKind::InvalidInput, SECTOR_USAGE)),
+    };
+    if count == 0 {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "the count must be at least 1"));
+    }
+    let mut source = open_source(Path::new(location))?;
+    let size = source.size()?;
+    let blocks = size.div_ceil(BLOCK_SIZE as u64);
+    if first.checked_add(count).is_none_or(|end| end > blocks) {
+        let asked = if count == 1 { format!("block {} is", first) } else { format!("blocks {} to {} are", first, first.saturating_add(count - 1)) };
+        return Err(io::Error::new(ErrorKind::InvalidInput, format!("{} past the end of the image ({} blocks)", asked, blocks)));
+    }
+
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    let mut buffer = vec![0u8; BLOCK_SIZE];
+    for block in first..first + count {
+        let offset = block * BLOCK_SIZE as u64;
+        // The last block of an image that isn't a whole number of them is short
+        let len = (size - offset).min(BLOCK_SIZE as u64) as usize;
+        source.read_exact_at(offset, &mut buffer[..len])?;
+        if raw {
+            out.write_all(&buffer[..len])?;
+            continue;
+        }
+        writeln!(out, "Block {} (offset {}, 0x{:x}):", block, offset, offset)?;
+        let mut previous: Option<&[u8]> = None;
+        let mut folded = false;
+        for (line, data) in buffer[..len].chunks(HEX_WIDTH).enumerate() {
+            if previous == Some(data) {
+                if !folded {
+                    writeln!(out, "*")?;
+                    folded = true;
+                }
+                continue;
+            }
+            writeln!(out, "{}", hex_line(offset + (line * HEX_WIDTH) as u64, data))?;
+            (previous, folded) = (Some(data), false);
+        }
+    }
+    out.flush()
+}
+
+// makeiso whatis <image.iso> --offset N
+pub fn whatis(args: &[String]) -> io::Result<()> {
+    let mut location = None;
+    let mut offset = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.split_once('=') {
+            Some(("--offset", value)) => offset = Some(number(value, "offset")?),
+            _ if arg == "--offset" => offset = Some(number(args.next().ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "--offset requires a value"))?, "offset")?),
+            _ if location.is_none() && !arg.starts_with("--") => location = Some(Path::new(arg)),
+            _ => return Err(io::Error::new(ErrorKind::InvalidInput, WHATIS_USAGE)),
+        }
+    }
+    let (Some(location), Some(offset)) = (location, offset) else {
+        return Err(io::Error::new(ErrorKind::InvalidInput, WHATIS_USAGE));
+    };
+
+    let mut reader = open_image(location)?;
+    let entries = reader.read_tree()?;
+    let areas = blockmap::read_map(&mut reader, &entries)?;
+    let size = reader.source_mut().size()?;
+    let block = offset / BLOCK_SIZE as u64;
+    println!("Offset {} (0x{:x}) is byte {} of block {}", offset, offset, offset % BLOCK_SIZE as u64, block);
+    if offset >= size {
+        println!("    past the end of the image ({} bytes)", size);
+        return Ok(());
+    }
+    let owners: Vec<_> = areas.iter().filter(|area| u32::try_from(block).is_ok_and(|block| area.contains(block))).collect();
+    for area in &owners {
+        let into = offset - area.start as u64 * BLOCK_SIZE as u64;
+        match area.file.map(|index| &entries[index]) {
+            Some(entry) if entry.is_directory => println!("    {} (byte {} of the directory)", area.contents, into),
+            Some(entry) if into >= entry.size as u64 => println!("    {} (past its end, in the padding of its last block)", area.contents),
+            Some(_) => println!("    {} (byte {} of the file)", area.contents, into),
+            None => println!("    {}", area.contents),
+        }
+    }
+    if owners.is_empty() {
+        println!("    {}", if block < reader.pvd.volume_space_size as u64 { "nothing: the block is free" } else { "nothing: the block is past the end of the volume" });
+    }
+    Ok(())
+}
+
+fn number(text: &str, what: &str) -> io::Result<u64> {
+    let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    parsed.map_err(|_| io::Error::new(ErrorKind::InvalidInput, format!("invalid {}: {}", what, text)))
+}
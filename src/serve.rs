@@ -0,0 +1,343 @@
+// HTTP control API for driving makeiso from elsewhere (makeiso serve)
+//
+//     GET  /profiles                the configured profiles and how their last run went
+//     POST /profiles/<name>/run     start a run, answered with the job's id
+//     GET  /jobs, /jobs/<id>        runs started here and their state
+//     GET  /jobs/<id>/output        the run's output, streamed as it is written
+//     GET  /images                  images recorded as written, still on disk
+//     GET  /images/<id>/catalog     the catalog of one of them (see catalog.rs)
+//
+// Answers are JSON. Runs are `makeiso run` processes, recorded like any other (see daemon.rs);
+// a profile runs at most once at a time. Only recorded images can be read, and with
+// --token-file every request needs "Authorization: Bearer <token>".
+//
+// The protocol is plain HTTP/1.1 over std's TcpListener, a request per connection, its line
+// and headers read a line at a time. That covers the REST half of the request: gRPC needs
+// HTTP/2 framing and protobuf, which means tonic, hyper and prost, and the crate stays
+// std-only. There is no TLS either; across machines it belongs behind a proxy that has it.
+
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use super::catalog;
+use super::config;
+use super::daemon::{self, Run};
+use super::{json, unix_time};
+
+const USAGE: &str = "Usage: makeiso serve [--listen ADDR] [--config FILE] [--token-file FILE]";
+const DEFAULT_LISTEN: &str = "127.0.0.1:8080";
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_HEADER_LINES: usize = 100;
+
+// The parts of a request that are looked at
+pub struct Request {
+    pub method: String,
+    pub path: String, // Without the query
+    pub headers: Vec<(String, String)>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+}
+
+// Read a request's line and headers; a body is read past, as none of the endpoints take one
+pub fn read_request(stream: &TcpStream) -> io::Result<Request> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Err(io::Error::new(ErrorKind::InvalidData, "malformed request"));
+    };
+    let mut request = Request { method: method.to_string(), path: target.split('?').next().unwrap_or("").to_string(), headers: Vec::new() };
+    for _ in 0..MAX_HEADER_LINES {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            request.headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    let length = request.header("Content-Length").and_then(|length| length.parse::<u64>().ok()).unwrap_or(0);
+    io::copy(&mut reader.take(length), &mut io::sink())?;
+    Ok(request)
+}
+
+// Write a whole response
+pub fn respond(mut stream: &TcpStream, status: &str, content_type: &str, body: &str, head_only: bool) -> io::Result<()> {
+    write!(stream, "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", status, content_type, body.len())?;
+    if !head_only {
+        stream.write_all(body.as_bytes())?;
+    }
+    stream.flush()
+}
+
+// A run started through the API
+struct Job {
+    id: usize,
+    profile: String,
+    started: i64,
+    output: Mutex<JobOutput>,
+    changed: Condvar, // Signalled when output arrives and when the run ends
+}
+
+#[derive(Default)]
+struct JobOutput {
+    text: Vec<u8>,     // Everything the run printed, both streams
+    exit: Option<i32>, // Exit code once it ended (-1 when killed by a signal)
+}
+
+impl Job {
+    fn state(&self) -> String {
+        let output = self.output.lock().unwrap_or_else(|e| e.into_inner());
+        let (state, exit) = match output.exit {
+            None => ("running", "null".to_string()),
+            Some(0) => ("succeeded", "0".to_string()),
+            Some(code) => ("failed", code.to_string()),
+        };
+        format!("{{\"id\": {}, \"profile\": {}, \"started\": {}, \"state\": \"{}\", \"exit_code\": {}}}", self.id, json::string(&self.profile), self.started, state, exit)
+    }
+
+    fn running(&self) -> bool {
+        self.output.lock().unwrap_or_else(|e| e.into_inner()).exit.is_none()
+    }
+}
+
+struct Server {
+    config: Vec<String>, // --config FILE, passed on to the runs
+    token: Option<String>,
+    executable: PathBuf,
+    jobs: Mutex<Vec<Arc<Job>>>,
+}
+
+// makeiso serve [--listen ADDR] [--config FILE] [--token-file FILE]
+pub fn serve(args: &[String]) -> io::Result<()> {
+    let (mut listen, mut config, mut token) = (DEFAULT_LISTEN.to_string(), Vec::new(), None);
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) => (flag, Some(value.to_string())),
+            None => (arg.as_str(), None),
+        };
+        if !["--listen", "--config", "--token-file"].contains(&flag) {
+            return Err(io::Error::new(ErrorKind::InvalidInput, format!("Unknown option: {}\n{}", arg, USAGE)));
+        }
+        let value = match inline {
+            Some(value) => value,
+            None => args.next().cloned().ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, format!("{} requires a value", flag)))?,
+        };
+        match flag {
+            "--listen" => listen = value,
+            "--config" => config = vec![format!("--config={}", value)],
+            _ => {
+                let text = fs::read_to_string(&value).map_err(|e| io::Error::new(e.kind(), format!("Could not read the token from {}: {}", value, e)))?;
+                token = Some(text.trim().to_string()).filter(|token| !token.is_empty());
+                if token.is_none() {
+                    return Err(io::Error::new(ErrorKind::InvalidInput, format!("{} is empty", value)));
+                }
+            }
+        }
+    }
+    // ":8080" listens on every interface
+    if listen.starts_with(':') {
+        listen = format!("0.0.0.0{}", listen);
+    }
+
+    let listener = TcpListener::bind(&listen).map_err(|e| io::Error::new(e.kind(), format!("Could not listen on {}: {}", listen, e)))?;
+    let address = listener.local_addr()?;
+    if token.is_none() && !address.ip().is_loopback() {
+        eprintln!("Warning: anyone who can reach {} can start runs; give a --token-file", address);
+    }
+    println!("Serving the makeiso API on http://{}", address);
+
+    let server = Arc::new(Server { config, token, executable: env::current_exe()?, jobs: Mutex::new(Vec::new()) });
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("Could not accept a connection: {}", e);
+                continue;
+            }
+        };
+        let server = Arc::clone(&server);
+        thread::spawn(move || {
+            if let Err(e) = server.answer(&stream) {
+                eprintln!("Request failed: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+impl Server {
+    fn answer(&self, stream: &TcpStream) -> io::Result<()> {
+        let request = read_request(stream)?;
+        let head = request.method == "HEAD";
+        let json_response = |status: &str, body: String| respond(stream, status, "application/json", &(body + "\n"), head);
+        let error = |status: &str, message: &str| json_response(status, format!("{{\"error\": {}}}", json::string(message)));
+
+        if let Some(token) = &self.token {
+            if request.header("Authorization").and_then(|value| value.strip_prefix("Bearer ")) != Some(token.as_str()) {
+                return error("401 Unauthorized", "a bearer token is required");
+            }
+        }
+
+        let parts: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+        let reading = matches!(request.method.as_str(), "GET" | "HEAD");
+        match (request.method.as_str(), &parts[..]) {
+            (_, ["profiles"]) if reading => match self.profiles() {
+                Ok(body) => json_response("200 OK", body),
+                Err(e) => error("500 Internal Server Error", &e.to_string()),
+            },
+            ("POST", ["profiles", name, "run"]) => match self.start(name) {
+                Ok(job) => json_response("202 Accepted", job.state()),
+                Err(e) if e.kind() == ErrorKind::NotFound => error("404 Not Found", &e.to_string()),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => error("409 Conflict", &e.to_string()),
+                Err(e) => error("500 Internal Server Error", &e.to_string()),
+            },
+            (_, ["jobs"]) if reading => {
+                let jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+                json_response("200 OK", format!("[{}]", jobs.iter().map(|job| job.state()).collect::<Vec<_>>().join(", ")))
+            }
+            (_, ["jobs", id, rest @ ..]) if reading => {
+                let Some(job) = self.job(id) else {
+                    return error("404 Not Found", "no such job");
+                };
+                match rest {
+                    [] => json_response("200 OK", job.state()),
+                    ["output"] => stream_output(stream, &job, head),
+                    _ => error("404 Not Found", "no such endpoint"),
+                }
+            }
+            (_, ["images"]) if reading => {
+                let images = recorded_images()?;
+                let entries: Vec<String> = images
+                    .iter()
+                    .map(|(id, run)| format!("{{\"id\": {}, \"profile\": {}, \"image\": {}, \"bytes\": {}, \"start\": {}}}", id, json::string(&run.profile), json::string(&run.image), run.bytes, run.start))
+                    .collect();
+                json_response("200 OK", format!("[{}]", entries.join(", ")))
+            }
+            (_, ["images", id, "catalog"]) if reading => {
+                let image = recorded_images()?.into_iter().find(|(image_id, _)| Some(*image_id) == id.parse().ok()).map(|(_, run)| run.image);
+                let Some(image) = image else {
+                    return error("404 Not Found", "no such image");
+                };
+                let mut body = Vec::new();
+                match catalog::write_catalog(Path::new(&image), &mut body, false) {
+                    Ok(_) => respond(stream, "200 OK", "application/json", &String::from_utf8_lossy(&body), head),
+                    Err(e) => error("500 Internal Server Error", &e.to_string()),
+                }
+            }
+            (_, ["profiles" | "jobs" | "images", ..]) => error("405 Method Not Allowed", "method not allowed here"),
+            _ => error("404 Not Found", "no such endpoint"),
+        }
+    }
+
+    // The profiles with their last run
+    fn profiles(&self) -> io::Result<String> {
+        let runs = daemon::read_runs()?;
+        let mut entries = Vec::new();
+        for name in config::profile_names(&self.config)? {
+            let last = runs.iter().rev().find(|run| run.profile == name).map_or("null".to_string(), |run| {
+                format!("{{\"start\": {}, \"ok\": {}, \"seconds\": {}, \"bytes\": {}, \"image\": {}, \"error\": {}}}", run.start, run.ok, run.seconds, run.bytes, json::string(&run.image), json::string(&run.error))
+            });
+            let running = self.jobs.lock().unwrap_or_else(|e| e.into_inner()).iter().any(|job| job.profile == name && job.running());
+            entries.push(format!("{{\"name\": {}, \"running\": {}, \"last_run\": {}}}", json::string(&name), running, last));
+        }
+        Ok(format!("[{}]", entries.join(", ")))
+    }
+
+    fn job(&self, id: &str) -> Option<Arc<Job>> {
+        let id: usize = id.parse().ok()?;
+        self.jobs.lock().unwrap_or_else(|e| e.into_inner()).iter().find(|job| job.id == id).cloned()
+    }
+
+    // Start a run of a profile in a makeiso process of its own
+    fn start(&self, name: &str) -> io::Result<Arc<Job>> {
+        if !config::profile_names(&self.config)?.iter().any(|profile| profile == name) {
+            return Err(io::Error::new(ErrorKind::NotFound, format!("no profile {}", name)));
+        }
+        let mut jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        if jobs.iter().any(|job| job.profile == name && job.running()) {
+            return Err(io::Error::new(ErrorKind::AlreadyExists, format!("profile {} is already running", name)));
+        }
+        let mut child = Command::new(&self.executable).arg("run").args(&self.config).arg(name).stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+        let job = Arc::new(Job {
+            id: jobs.len() + 1,
+            profile: name.to_string(),
+            started: unix_time(SystemTime::now()),
+            output: Mutex::new(JobOutput::default()),
+            changed: Condvar::new(),
+        });
+        jobs.push(Arc::clone(&job));
+
+        let streams: [Box<dyn Read + Send>; 2] = [Box::new(child.stdout.take().expect("stdout is piped")), Box::new(child.stderr.take().expect("stderr is piped"))];
+        let readers: Vec<_> = streams
+            .into_iter()
+            .map(|mut source| {
+                let job = Arc::clone(&job);
+                thread::spawn(move || {
+                    let mut buffer = [0u8; 4096];
+                    while let Ok(count @ 1..) = source.read(&mut buffer) {
+                        job.output.lock().unwrap_or_else(|e| e.into_inner()).text.extend_from_slice(&buffer[..count]);
+                        job.changed.notify_all();
+                    }
+                })
+            })
+            .collect();
+        // The run only counts as ended once its output is all in, so nothing comes after the end
+        let waiter = Arc::clone(&job);
+        thread::spawn(move || {
+            for reader in readers {
+                let _ = reader.join();
+            }
+            let code = child.wait().map_or(-1, |status| status.code().unwrap_or(-1));
+            waiter.output.lock().unwrap_or_else(|e| e.into_inner()).exit = Some(code);
+            waiter.changed.notify_all();
+        });
+        Ok(job)
+    }
+}
+
+// Send a job's output as it comes, ending when the run does
+fn stream_output(mut stream: &TcpStream, job: &Job, head_only: bool) -> io::Result<()> {
+    write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: text/plain; charset=utf-8\r\nConnection: close\r\n\r\n")?;
+    if head_only {
+        return stream.flush();
+    }
+    let mut sent = 0;
+    loop {
+        let mut output = job.output.lock().unwrap_or_else(|e| e.into_inner());
+        while output.text.len() == sent && output.exit.is_none() {
+            output = job.changed.wait(output).unwrap_or_else(|e| e.into_inner());
+        }
+        let chunk = output.text[sent..].to_vec();
+        let finished = output.exit.is_some();
+        drop(output);
+        stream.write_all(&chunk)?;
+        stream.flush()?;
+        sent += chunk.len();
+        if finished {
+            return Ok(());
+        }
+    }
+}
+
+// Images recorded as written that are still there, by their place in the record; a path
+// written again is only the latest image
+fn recorded_images() -> io::Result<Vec<(usize, Run)>> {
+    let runs = daemon::read_runs()?;
+    let latest = |at: usize, image: &str| !runs[at + 1..].iter().any(|later| later.ok && later.image == image);
+    Ok(runs.iter().cloned().enumerate().filter(|(at, run)| run.ok && latest(*at, &run.image) && Path::new(&run.image).is_file()).collect())
+}
@@ -0,0 +1,113 @@
+// SFTP servers as a source tree and as an image destination
+//
+// Locations are sftp://[user@]host[:port]/path. curl does the SSH side: keys from ~/.ssh (or
+// the agent), host keys checked against ~/.ssh/known_hosts. Directory listings come back in
+// "ls -l" form, which is where sizes, times and entry types are taken from; symbolic links
+// and special files are left out like they are for local trees.
+
+use std::io::{self, ErrorKind, Read};
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::backend::{curl_output, CurlReader, RemoteEntry, RemoteStore, UploadSink};
+use super::{unix_from_utc, unix_time, utc_date_time};
+
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+pub struct SftpStore;
+
+impl SftpStore {
+    pub fn new(location: &Path) -> io::Result<SftpStore> {
+        check_location(location)?;
+        Ok(SftpStore)
+    }
+}
+
+fn command(url: &str) -> Command {
+    let mut command = Command::new("curl");
+    command.args(["--silent", "--show-error"]).arg(url);
+    command
+}
+
+impl RemoteStore for SftpStore {
+    fn list(&self, dir: &str) -> io::Result<Vec<RemoteEntry>> {
+        // A trailing slash asks for the listing instead of the file
+        let listing = curl_output(command(&format!("{}/", dir)), dir)?;
+        Ok(String::from_utf8_lossy(&listing).lines().filter_map(parse_listing_line).collect())
+    }
+
+    fn get(&self, path: &str) -> io::Result<Box<dyn Read + Send>> {
+        Ok(Box::new(CurlReader::spawn(command(path), path)?))
+    }
+}
+
+// Stream an image into a file on the server, creating missing directories on the way
+pub fn upload(location: &Path, _size: u64) -> io::Result<UploadSink> {
+    check_location(location)?;
+    let url = location.display().to_string();
+    let mut command = command(&url);
+    command.args(["--upload-file", "-", "--ftp-create-dirs"]);
+    UploadSink::spawn(command, &url)
+}
+
+fn check_location(location: &Path) -> io::Result<()> {
+    let rest = location.to_str().and_then(|location| location.strip_prefix("sftp://")).unwrap_or("");
+    if rest.split('/').next().unwrap_or("").is_empty() {
+        return Err(io::Error::new(ErrorKind::InvalidInput, format!("{} is not an sftp://host/path location", location.display())));
+    }
+    Ok(())
+}
+
+// "-rw-r--r--    1 user     group        1234 May  1 12:34 name" (or "May  1  2023" for older files)
+fn parse_listing_line(line: &str) -> Option<RemoteEntry> {
+    let fields = split_fields(line)?;
+    let is_directory = match fields[0].chars().next()? {
+        'd' => true,
+        '-' => false,
+        _ => return None,
+    };
+    let name = fields[8];
+    if name == "." || name == ".." {
+        return None;
+    }
+    Some(RemoteEntry {
+        name: name.to_string(),
+        is_directory,
+        len: fields[4].parse().ok()?,
+        modified: parse_time(fields[5], fields[6], fields[7]),
+    })
+}
+
+// Eight whitespace separated fields and the rest of the line, which is the name
+fn split_fields(line: &str) -> Option<Vec<&str>> {
+    let mut fields = Vec::new();
+    let mut rest = line.trim_start();
+    for _ in 0..8 {
+        let end = rest.find(char::is_whitespace)?;
+        fields.push(&rest[..end]);
+        rest = rest[end..].trim_start();
+    }
+    fields.push(rest);
+    Some(fields)
+}
+
+// Listing times are local to the server; they are taken as UTC, which is as close as it gets
+fn parse_time(month: &str, day: &str, year_or_time: &str) -> Option<SystemTime> {
+    let month = MONTHS.iter().position(|&name| name == month)? as u32 + 1;
+    let day = day.parse().ok()?;
+    let timestamp = match year_or_time.split_once(':') {
+        Some((hour, minute)) => {
+            // Recent files show a time instead of the year: this year, unless that lies ahead
+            let (year, ..) = utc_date_time(unix_time(SystemTime::now()));
+            let this_year = unix_from_utc(year, month, day, hour.parse().ok()?, minute.parse().ok()?, 0);
+            if this_year > unix_time(SystemTime::now()) + 86400 {
+                unix_from_utc(year - 1, month, day, hour.parse().ok()?, minute.parse().ok()?, 0)
+            } else {
+                this_year
+            }
+        }
+        None => unix_from_utc(year_or_time.parse().ok()?, month, day, 0, 0, 0),
+    };
+    u64::try_from(timestamp).ok().map(|seconds| UNIX_EPOCH + Duration::from_secs(seconds))
+}
@@ -0,0 +1,120 @@
+// makeiso shell: the image mounted read-only in a temporary directory, with a shell started
+// there, and unmounted when the shell exits. As root the image goes on a read-only loop
+// device and is mounted by the kernel's own ISO 9660 (or UDF) driver; otherwise, or when that
+// doesn't work (inside a container, say), it is served through FUSE as by makeiso mount.
+//
+// Ctrl-C and a closed terminal are left to the shell, so the mount is cleaned up whichever way
+// it ends. A mount point that is still busy (a process left running in the background) is
+// reported, and left for umount.
+
+use std::env;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread::JoinHandle;
+
+use super::{backend, cancel, events, fuse};
+
+const USAGE: &str = "Usage: makeiso shell [--fuse] <image.iso>";
+
+// What was mounted, undone when dropped
+struct Mount {
+    dir: PathBuf,
+    how: How,
+}
+
+enum How {
+    Loop(String), // The loop device
+    Fuse(Option<JoinHandle<io::Result<()>>>),
+}
+
+impl Drop for Mount {
+    fn drop(&mut self) {
+        let unmounted = match &mut self.how {
+            How::Loop(device) => {
+                let unmounted = run("umount", &[self.dir.as_os_str()]).is_ok();
+                // Detaching a device still in use only marks it to go once it is free
+                let _ = run("losetup", &["-d".as_ref(), device.as_ref()]);
+                unmounted
+            }
+            How::Fuse(server) => {
+                let unmounted = fuse::unmount(&self.dir).is_ok();
+                if unmounted {
+                    if let Some(Err(e)) = server.take().and_then(|server| server.join().ok()) {
+                        events::warn(&format!("The FUSE server stopped with an error: {}", e));
+                    }
+                }
+                unmounted
+            }
+        };
+        if unmounted {
+            let _ = fs::remove_dir(&self.dir);
+        } else {
+            events::warn(&format!("{} is still in use; unmount it with umount when it is free", self.dir.display()));
+        }
+    }
+}
+
+// Run a system tool, returning what it printed
+fn run(tool: &str, args: &[&std::ffi::OsStr]) -> io::Result<String> {
+    let output = Command::new(tool).args(args).stdin(Stdio::null()).output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!("{} failed: {}", tool, String::from_utf8_lossy(&output.stderr).trim())));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+// Attach the image to a read-only loop device and mount that
+fn mount_loop(iso_path: &Path, dir: &Path) -> io::Result<Mount> {
+    let device = run("losetup", &["--find".as_ref(), "--show".as_ref(), "--read-only".as_ref(), iso_path.as_os_str()])?;
+    if let Err(e) = run("mount", &["-o".as_ref(), "ro".as_ref(), device.as_ref(), dir.as_os_str()]) {
+        let _ = run("losetup", &["-d".as_ref(), device.as_ref()]);
+        return Err(e);
+    }
+    Ok(Mount { dir: dir.to_path_buf(), how: How::Loop(device) })
+}
+
+// makeiso shell [--fuse] <image.iso>
+pub fn shell(args: &[String]) -> io::Result<()> {
+    let (flags, paths): (Vec<&String>, Vec<&String>) = args.iter().partition(|arg| arg.starts_with("--"));
+    let force_fuse = match flags[..] {
+        [] => false,
+        [flag] if flag == "--fuse" => true,
+        _ => return Err(io::Error::new(ErrorKind::InvalidInput, USAGE)),
+    };
+    let [iso_path] = paths[..] else {
+        return Err(io::Error::new(ErrorKind::InvalidInput, USAGE));
+    };
+    let iso_path = Path::new(iso_path);
+
+    let dir = env::temp_dir().join(format!("makeiso-shell-{}", std::process::id()));
+    fs::create_dir_all(&dir)?;
+    // Loop devices need a local file, and the privileges to set one up
+    let looped = if !force_fuse && fuse::running_as_root() && !backend::is_remote(iso_path) {
+        mount_loop(iso_path, &dir).inspect_err(|e| events::info(&format!("Could not mount on a loop device ({}); serving through FUSE", e))).ok()
+    } else {
+        None
+    };
+    let mount = match looped {
+        Some(mount) => mount,
+        None => match fuse::mount_in_background(iso_path, &dir) {
+            Ok(server) => Mount { dir: dir.clone(), how: How::Fuse(Some(server)) },
+            Err(e) => {
+                let _ = fs::remove_dir(&dir);
+                return Err(e);
+            }
+        },
+    };
+
+    let shell = env::var_os("SHELL").filter(|shell| !shell.is_empty()).unwrap_or_else(|| "/bin/sh".into());
+    eprintln!("{} is mounted read-only at {}; exit the shell to unmount it", iso_path.display(), dir.display());
+    cancel::leave_to_child();
+    let status = Command::new(&shell).current_dir(&dir).env("MAKEISO_IMAGE", iso_path).status();
+    drop(mount);
+    let status = status.map_err(|e| io::Error::new(e.kind(), format!("could not start {}: {}", Path::new(&shell).display(), e)))?;
+    if !status.success() {
+        events::info(&format!("The shell exited with {}", status));
+    }
+    Ok(())
+}
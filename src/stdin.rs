@@ -0,0 +1,49 @@
+// Standard input as a source: "-" among the sources is one file, named by --stdin-name
+// ("stdin" without it), holding whatever was piped in, as in
+//
+//     pg_dump mydb | gzip | makeiso --stdin-name database.sql.gz - dump.iso
+//
+// The layout needs every size before the first block is written, so the stream is spooled
+// first, to a directory next to the image (the disk the image goes on has room for it, where
+// /tmp may be a small tmpfs), and removed once the image is done, whether or not it was.
+
+use std::fs::{self, File};
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use super::{backend, events};
+
+pub const DEFAULT_NAME: &str = "stdin";
+
+// A spooled copy of standard input, removed when dropped
+pub struct Spool {
+    dir: PathBuf,
+    pub path: PathBuf,
+}
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+// Copy standard input to a file called name, next to the image at iso_path
+pub fn spool(name: &str, iso_path: &Path) -> io::Result<Spool> {
+    if name.is_empty() || name.contains(['/', '\\']) || name == "." || name == ".." {
+        return Err(io::Error::new(ErrorKind::InvalidInput, format!("invalid --stdin-name: {:?} (expected a file name)", name)));
+    }
+    let suffix = format!(".stdin-{}", std::process::id());
+    let dir = if backend::is_remote(iso_path) {
+        std::env::temp_dir().join(format!("makeiso{}", suffix))
+    } else {
+        let mut dir = iso_path.as_os_str().to_owned();
+        dir.push(&suffix);
+        PathBuf::from(dir)
+    };
+    fs::create_dir_all(&dir)?;
+    let spool = Spool { path: dir.join(name), dir };
+
+    let copied = io::copy(&mut io::stdin().lock(), &mut File::create(&spool.path)?).map_err(|e| io::Error::new(e.kind(), format!("could not read standard input: {}", e)))?;
+    events::info(&format!("Read {} bytes from standard input into /{}", copied, name));
+    Ok(spool)
+}
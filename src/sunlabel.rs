@@ -0,0 +1,74 @@
+// SPARC booting (--sparc-boot, mkisofs' -sparc-boot): a Sun disk label in sector 0 whose first
+// partition is the ISO 9660 volume and whose others are the boot images, one per machine
+// architecture (sun4, sun4c, sun4m, ...), written after the volume. The OpenBoot PROM picks
+// the partition for the machine and runs the boot block in its sectors 1-15; for the volume
+// itself that is the rest of the system area, which --system-area fills like mkisofs' -G.
+//
+// Sun labels address partitions by cylinder, so the volume and every image start on one; the
+// geometry is mkisofs': one track of 640 sectors (320 KiB) per cylinder.
+
+use std::fs;
+use std::io;
+
+use super::hybrid::AppendedPartition;
+use super::{base_system_area, ImageOptions, Layout};
+
+pub const PARTITIONS: usize = 8;
+pub const CYLINDER_BLOCKS: u32 = 160;
+const SECTORS_PER_CYLINDER: u32 = 640;
+const SECTORS_PER_BLOCK: u32 = 4;
+const LABEL: &[u8] = b"CD-ROM Disc with Sun sparc boot created by makeiso";
+const VTOC_VERSION: u32 = 1;
+const VTOC_SANITY: u32 = 0x600D_DEEE;
+const TAG_USR: u16 = 4;
+const FLAG_READ_ONLY: u16 = 0x10;
+const ROTATION_SPEED: u16 = 350;
+const MAGIC: u16 = 0xDABE;
+
+// The system area with the label over its first sector
+pub fn system_area(layout: &Layout, options: &ImageOptions) -> Vec<u8> {
+    let mut area = base_system_area(options);
+    let end = layout.appended.first().copied().unwrap_or(layout.total_blocks);
+    let mut partitions = vec![(0, end * SECTORS_PER_BLOCK)];
+    partitions.extend(options.sparc_boot.iter().zip(&layout.appended).map(|(image, &block)| (block, image.size.div_ceil(512) as u32)));
+
+    let label = &mut area[..512];
+    label.fill(0);
+    label[..LABEL.len()].copy_from_slice(LABEL);
+    label[128..132].copy_from_slice(&VTOC_VERSION.to_be_bytes());
+    label[140..142].copy_from_slice(&(PARTITIONS as u16).to_be_bytes());
+    for index in 0..partitions.len() {
+        label[142 + 4 * index..144 + 4 * index].copy_from_slice(&TAG_USR.to_be_bytes());
+        label[144 + 4 * index..146 + 4 * index].copy_from_slice(&FLAG_READ_ONLY.to_be_bytes());
+    }
+    label[188..192].copy_from_slice(&VTOC_SANITY.to_be_bytes());
+
+    // Geometry: a single head, all cylinders usable
+    let cylinders = layout.total_blocks.div_ceil(CYLINDER_BLOCKS).min(u16::MAX as u32) as u16;
+    label[420..422].copy_from_slice(&ROTATION_SPEED.to_be_bytes());
+    label[422..424].copy_from_slice(&cylinders.to_be_bytes());
+    label[430..432].copy_from_slice(&1u16.to_be_bytes()); // Interleave
+    label[432..434].copy_from_slice(&cylinders.to_be_bytes());
+    label[436..438].copy_from_slice(&1u16.to_be_bytes());
+    label[438..440].copy_from_slice(&(SECTORS_PER_CYLINDER as u16).to_be_bytes());
+    for (index, &(block, sectors)) in partitions.iter().enumerate() {
+        label[444 + 8 * index..448 + 8 * index].copy_from_slice(&(block / CYLINDER_BLOCKS).to_be_bytes());
+        label[448 + 8 * index..452 + 8 * index].copy_from_slice(&sectors.to_be_bytes());
+    }
+    label[508..510].copy_from_slice(&MAGIC.to_be_bytes());
+    let checksum = label[..510].chunks(2).fold(0u16, |sum, word| sum ^ u16::from_be_bytes([word[0], word[1]]));
+    label[510..512].copy_from_slice(&checksum.to_be_bytes());
+    area
+}
+
+// The boot images of --sparc-boot, in partitions 2 to 8 in the order given
+pub fn boot_images(paths: &str) -> io::Result<Vec<AppendedPartition>> {
+    paths
+        .split(',')
+        .enumerate()
+        .map(|(index, path)| {
+            let size = fs::metadata(path)?.len();
+            Ok(AppendedPartition { slot: index + 2, kind: 0, path: path.into(), size })
+        })
+        .collect()
+}
@@ -0,0 +1,621 @@
+// Tar archives as image sources and outputs: from-tar turns an archive into an image without
+// unpacking it, to-tar streams the contents of an image into an archive.
+//
+// The archive is read twice, once to plan the layout and once for the file contents, which are
+// written straight to their extents in archive order. Compressed archives are decompressed on
+// the fly by the matching command-line tool, so nothing is staged on disk.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::reader::{open_image, IsoEntry};
+use super::backend::create_output;
+use super::preallocate;
+use super::events;
+use super::unicode;
+use super::{add_directory, check_file_path, entry_name, new_tree, plan_image, room_needed, write_image, Entry, FileEntry, ImageOptions, ImageTree, Posix, BLOCK_SIZE};
+
+const TAR_BLOCK_SIZE: usize = 512;
+const COPY_CHUNK_SIZE: usize = 1024 * 1024; // File contents are copied (and progress reported) in 1 MiB chunks
+
+// Decompressors, recognised by the magic bytes at the start of the archive
+const DECOMPRESSORS: &[(&[u8], &str)] = &[(&[0x1f, 0x8b], "gzip"), (&[0x28, 0xb5, 0x2f, 0xfd], "zstd"), (b"BZh", "bzip2"), (&[0xfd, b'7', b'z', b'X', b'Z', 0], "xz")];
+
+// The uncompressed bytes of an archive
+enum ArchiveStream {
+    Plain(File),
+    Decompressed { tool: &'static str, child: Child, output: ChildStdout },
+}
+
+impl Read for ArchiveStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            ArchiveStream::Plain(file) => file.read(buf),
+            ArchiveStream::Decompressed { output, .. } => output.read(buf),
+        }
+    }
+}
+
+impl ArchiveStream {
+    // Open an archive, decompressing it through gzip, zstd, bzip2 or xz when it is compressed
+    fn open(path: &Path) -> io::Result<ArchiveStream> {
+        let mut file = File::open(path)?;
+        let mut magic = [0u8; 6];
+        let len = file.read(&mut magic)?;
+        file.seek(SeekFrom::Start(0))?;
+
+        let Some(&(_, tool)) = DECOMPRESSORS.iter().find(|(prefix, _)| magic[..len].starts_with(prefix)) else {
+            return Ok(ArchiveStream::Plain(file));
+        };
+        let mut child = Command::new(tool)
+            .arg("-dc")
+            .stdin(file)
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| io::Error::new(e.kind(), format!("could not run {} to decompress {}: {}", tool, path.display(), e)))?;
+        let output = child.stdout.take().expect("piped stdout");
+        Ok(ArchiveStream::Decompressed { tool, child, output })
+    }
+
+    // Skip bytes without looking at them
+    fn skip(&mut self, len: u64) -> io::Result<()> {
+        match self {
+            ArchiveStream::Plain(file) => file.seek_relative(len as i64),
+            ArchiveStream::Decompressed { output, .. } => io::copy(&mut output.take(len), &mut io::sink()).map(|_| ()),
+        }
+    }
+
+    // Read whatever follows the end of the archive and check that decompression succeeded
+    fn finish(self) -> io::Result<()> {
+        if let ArchiveStream::Decompressed { tool, mut child, mut output } = self {
+            io::copy(&mut output, &mut io::sink())?;
+            drop(output);
+            if !child.wait()?.success() {
+                return Err(io::Error::other(format!("{} failed to decompress the archive", tool)));
+            }
+        }
+        Ok(())
+    }
+}
+
+// One member of a tar archive, with long names and pax headers already applied
+#[derive(Debug)]
+struct TarMember {
+    path: String,
+    kind: u8,
+    size: u64,
+    modified: i64,
+    link: String,
+    mode: u32, // Permission bits
+    uid: u32,
+    gid: u32,
+    device: (u32, u32), // Major and minor number of a device
+}
+
+impl TarMember {
+    fn is_file(&self) -> bool {
+        matches!(self.kind, b'0' | b'\0' | b'7')
+    }
+
+    fn is_hard_link(&self) -> bool {
+        self.kind == b'1'
+    }
+
+    // Regular files and hard links both become files of the image
+    fn is_image_file(&self) -> bool {
+        self.is_file() || self.is_hard_link()
+    }
+
+    // The file type bits of the member's POSIX mode, for those the image can record
+    fn file_type(&self) -> Option<u32> {
+        match self.kind {
+            b'0' | b'\0' | b'7' | b'1' => Some(0o100000),
+            b'2' => Some(0o120000),
+            b'3' => Some(0o020000),
+            b'4' => Some(0o060000),
+            b'5' => Some(0o040000),
+            b'6' => Some(0o010000),
+            _ => None,
+        }
+    }
+
+    // Its attributes as Rock Ridge records them
+    fn posix(&self) -> Option<Posix> {
+        Some(Posix { mode: self.file_type()? | self.mode, uid: self.uid, gid: self.gid })
+    }
+}
+
+// Sequential reader of ustar, GNU and pax archives
+struct TarReader {
+    stream: ArchiveStream,
+    remaining: u64, // Unread contents of the current member
+    padding: u64,   // Padding after the contents of the current member
+}
+
+impl TarReader {
+    fn new(stream: ArchiveStream) -> TarReader {
+        TarReader { stream, remaining: 0, padding: 0 }
+    }
+
+    // The next member, skipping whatever is left of the current one; None at the end of the archive
+    fn next_member(&mut self) -> io::Result<Option<TarMember>> {
+        let mut long_name = None;
+        let mut long_link = None;
+        let mut pax = HashMap::new();
+
+        loop {
+            self.stream.skip(self.remaining + self.padding)?;
+            self.remaining = 0;
+            self.padding = 0;
+
+            let mut header = [0u8; TAR_BLOCK_SIZE];
+            match self.stream.read_exact(&mut header) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None), // Missing end of archive blocks
+                Err(e) => return Err(e),
+            }
+            if header.iter().all(|&byte| byte == 0) {
+                return Ok(None);
+            }
+            verify_checksum(&header)?;
+
+            let size = numeric_field(&header[124..136]);
+            self.remaining = size;
+            self.padding = size.next_multiple_of(TAR_BLOCK_SIZE as u64) - size;
+
+            let kind = header[156];
+            match kind {
+                // GNU long names and link targets, and pax headers, describe the member that follows
+                b'L' => long_name = Some(text_field(&self.read_contents()?)),
+                b'K' => long_link = Some(text_field(&self.read_contents()?)),
+                b'x' => pax = parse_pax(&self.read_contents()?),
+                b'g' => {}
+                _ => {
+                    let mut path = text_field(&header[0..100]);
+                    if &header[257..263] == b"ustar\0" && header[345] != 0 {
+                        path = format!("{}/{}", text_field(&header[345..500]), path);
+                    }
+                    let mut member = TarMember {
+                        path: long_name.take().unwrap_or(path),
+                        kind,
+                        size,
+                        modified: numeric_field(&header[136..148]) as i64,
+                        link: long_link.take().unwrap_or_else(|| text_field(&header[157..257])),
+                        mode: numeric_field(&header[100..108]) as u32 & 0o7777,
+                        uid: numeric_field(&header[108..116]) as u32,
+                        gid: numeric_field(&header[116..124]) as u32,
+                        device: (numeric_field(&header[329..337]) as u32, numeric_field(&header[337..345]) as u32),
+                    };
+
+                    if let Some(path) = pax.remove("path") {
+                        member.path = path;
+                    }
+                    if let Some(link) = pax.remove("linkpath") {
+                        member.link = link;
+                    }
+                    if let Some(size) = pax.get("size").and_then(|size| size.parse().ok()) {
+                        member.size = size;
+                        self.remaining = size;
+                        self.padding = size.next_multiple_of(TAR_BLOCK_SIZE as u64) - size;
+                    }
+                    if let Some(uid) = pax.get("uid").and_then(|uid| uid.parse().ok()) {
+                        member.uid = uid;
+                    }
+                    if let Some(gid) = pax.get("gid").and_then(|gid| gid.parse().ok()) {
+                        member.gid = gid;
+                    }
+                    if let Some(modified) = pax.get("mtime").and_then(|mtime| mtime.split('.').next()?.parse().ok()) {
+                        member.modified = modified;
+                    }
+                    return Ok(Some(member));
+                }
+            }
+        }
+    }
+
+    // Read the contents of the current member
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.remaining as usize);
+        let bytes_read = self.stream.read(&mut buf[..len])?;
+        self.remaining -= bytes_read as u64;
+        Ok(bytes_read)
+    }
+
+    // Read all of the contents of a (small) header member
+    fn read_contents(&mut self) -> io::Result<Vec<u8>> {
+        let mut contents = vec![0u8; self.remaining as usize];
+        self.stream.read_exact(&mut contents)?;
+        self.remaining = 0;
+        Ok(contents)
+    }
+}
+
+// Check the header checksum: the sum of all header bytes with the checksum field taken as spaces
+fn verify_checksum(header: &[u8; TAR_BLOCK_SIZE]) -> io::Result<()> {
+    let sum: u64 = header.iter().enumerate().map(|(at, &byte)| if (148..156).contains(&at) { b' ' as u64 } else { byte as u64 }).sum();
+    if sum != numeric_field(&header[148..156]) {
+        return Err(io::Error::new(ErrorKind::InvalidData, "not a tar archive (bad header checksum)"));
+    }
+    Ok(())
+}
+
+// Octal number, or a big-endian base-256 number when the high bit of the first byte is set
+fn numeric_field(field: &[u8]) -> u64 {
+    if field[0] & 0x80 != 0 {
+        return field[1..].iter().fold(0, |value, &byte| value << 8 | byte as u64);
+    }
+    let digits = text_field(field);
+    u64::from_str_radix(digits.trim(), 8).unwrap_or(0)
+}
+
+// NUL-terminated text
+fn text_field(field: &[u8]) -> String {
+    let end = field.iter().position(|&byte| byte == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).to_string()
+}
+
+// pax extended header records: "<length> <key>=<value>\n"
+fn parse_pax(contents: &[u8]) -> HashMap<String, String> {
+    let mut records = HashMap::new();
+    let mut rest = contents;
+    while let Some(space) = rest.iter().position(|&byte| byte == b' ') {
+        let Some(length) = std::str::from_utf8(&rest[..space]).ok().and_then(|length| length.parse::<usize>().ok()) else {
+            break;
+        };
+        if length <= space || length > rest.len() {
+            break;
+        }
+        let record = String::from_utf8_lossy(&rest[space + 1..length]).to_string();
+        if let Some((key, value)) = record.trim_end_matches('\n').split_once('=') {
+            records.insert(key.to_string(), value.to_string());
+        }
+        rest = &rest[length..];
+    }
+    records
+}
+
+// Path components of a member, without leading slashes and "." components
+pub fn member_components(path: &str) -> io::Result<Vec<&str>> {
+    let components: Vec<&str> = path.split('/').filter(|component| !component.is_empty() && *component != ".").collect();
+    if components.contains(&"..") {
+        return Err(io::Error::new(ErrorKind::InvalidInput, format!("refusing archive member outside the image root: {}", path)));
+    }
+    Ok(components)
+}
+
+pub fn system_time(timestamp: i64) -> SystemTime {
+    if timestamp >= 0 {
+        UNIX_EPOCH + Duration::from_secs(timestamp as u64)
+    } else {
+        UNIX_EPOCH - Duration::from_secs(timestamp.unsigned_abs())
+    }
+}
+
+// Create an image from the files and directories of a tar archive
+pub fn tar_to_iso(archive: &Path, iso_file_path: &Path, options: &ImageOptions) -> io::Result<()> {
+    let mut tree = new_tree(options);
+    tree.rock_ridge = true; // Members keep their modes, owners and link targets
+    add_directory(&mut tree, archive, String::new(), 0, None, options.deep_dirs)?;
+    let mut dirs: HashMap<String, usize> = HashMap::from([(String::new(), 0)]);
+
+    // The source of every file is the archive path joined with the member path; when a path
+    // occurs more than once the last member wins, as it would when extracting
+    let mut last_member: HashMap<PathBuf, usize> = HashMap::new();
+    let mut sizes: HashMap<PathBuf, u32> = HashMap::new();
+
+    let mut tar = TarReader::new(ArchiveStream::open(archive)?);
+    let mut members = 0; // Regular files and hard links seen
+    while let Some(member) = tar.next_member()? {
+        let components = member_components(&member.path)?;
+        let modified = Some(system_time(member.modified));
+        let ordinal = members;
+        if member.is_image_file() {
+            members += 1;
+        }
+
+        if member.kind == b'5' {
+            let index = archive_directory(&mut tree, &mut dirs, archive, &components, options)?;
+            tree.dirs[index].modified = modified;
+            if let Some(posix) = member.posix() {
+                let dir = &tree.dirs[index];
+                let (parent, name) = (dir.original_parent.unwrap_or(dir.parent), dir.name.clone());
+                tree.posix.entry(parent).or_default().insert(name, posix);
+            }
+        } else if let Some(posix) = member.posix() {
+            let Some((&name, parents)) = components.split_last() else {
+                continue;
+            };
+            let parent = archive_directory(&mut tree, &mut dirs, archive, parents, options)?;
+            let source = archive.join(components.join("/"));
+            let name = unicode::normalize(name, options.normalize).into_owned();
+            check_file_path(&tree, parent, &name, &source)?;
+
+            // A later member of the same path replaces the earlier one, whatever either is
+            if tree.posix(parent, &name).is_some() {
+                let mut entries = std::mem::take(&mut tree.dirs[parent].entries);
+                entries.retain(|entry| matches!(entry, Entry::Directory(_)) || entry_name(&tree, entry) != name);
+                tree.dirs[parent].entries = entries;
+                last_member.remove(&source);
+            }
+            tree.posix.entry(parent).or_default().insert(name.clone(), posix);
+
+            if member.kind == b'2' {
+                tree.dirs[parent].entries.push(Entry::Symlink { name, target: member.link, modified });
+                continue;
+            }
+            if !member.is_image_file() {
+                let device = matches!(member.kind, b'3' | b'4').then_some(member.device);
+                tree.dirs[parent].entries.push(Entry::Special { name, device, modified });
+                continue;
+            }
+
+            // A hard link gets a copy of its target, which comes earlier in the archive
+            let size = if member.is_hard_link() {
+                match sizes.get(&archive.join(member_components(&member.link)?.join("/"))) {
+                    Some(&size) => size,
+                    None => {
+                        events::warn(&format!("Skipping hard link to a file that isn't in the archive: {} -> {}", member.path, member.link));
+                        tree.posix.entry(parent).or_default().remove(&name);
+                        continue;
+                    }
+                }
+            } else {
+                u32::try_from(member.size)
+                    .map_err(|_| io::Error::new(ErrorKind::InvalidInput, format!("{} is too large for a single ISO 9660 extent", source.display())))?
+            };
+            sizes.insert(source.clone(), size);
+            last_member.insert(source.clone(), ordinal);
+            tree.dirs[parent].entries.push(Entry::File(FileEntry {
+                name,
+                source,
+                size,
+                modified,
+                extent: 0,
+            }));
+        } else {
+            events::warn(&format!("Skipping member of an unknown type in the archive: {}", member.path));
+        }
+    }
+    tar.stream.finish()?;
+    let layout = plan_image(&mut tree, options)?;
+    let size = layout.total_blocks as u64 * BLOCK_SIZE as u64;
+    preallocate::check_destination(iso_file_path, room_needed(size, options), &[archive.to_path_buf()])?;
+
+    // Second pass: copy every (last) regular member to the extent planned for it, and the
+    // contents of hard link targets to the extents of their links
+    write_image(&tree, &layout, create_output(iso_file_path, size)?, iso_file_path, options, |iso_file, files, progress| {
+        let extents: HashMap<&Path, &FileEntry> = files.iter().map(|file| (file.source.as_path(), *file)).collect();
+        let mut tar = TarReader::new(ArchiveStream::open(archive)?);
+        let mut buffer = vec![0u8; COPY_CHUNK_SIZE];
+        let mut ordinal = 0;
+
+        while let Some(member) = tar.next_member()? {
+            if !member.is_image_file() {
+                continue;
+            }
+            ordinal += 1;
+            let source = archive.join(member_components(&member.path)?.join("/"));
+            if last_member.get(&source) != Some(&(ordinal - 1)) {
+                continue; // Replaced by a later member, or not planned at all
+            }
+            let file = extents[source.as_path()];
+
+            if member.is_hard_link() {
+                let target = extents[archive.join(member_components(&member.link)?.join("/")).as_path()];
+                copy_within_image(iso_file, target, file, &mut buffer)?;
+                progress.file_done();
+                progress.advance(file.size as usize)?;
+                continue;
+            }
+
+            iso_file.seek(SeekFrom::Start(file.extent as u64 * BLOCK_SIZE as u64))?;
+            let mut remaining = file.size as usize;
+            while remaining > 0 {
+                let bytes_read = tar.read(&mut buffer[..remaining.min(COPY_CHUNK_SIZE)])?;
+                if bytes_read == 0 {
+                    return Err(io::Error::new(ErrorKind::UnexpectedEof, format!("{} is truncated", source.display())));
+                }
+                iso_file.write_all(&buffer[..bytes_read])?;
+                remaining -= bytes_read;
+                progress.advance(bytes_read)?;
+            }
+            progress.file_done();
+        }
+        tar.stream.finish()?;
+        if ordinal != members {
+            return Err(io::Error::other(format!("{} changed while the image was written", archive.display())));
+        }
+
+        // Continue after the furthest file contents, which need not be the last ones written
+        iso_file.seek(SeekFrom::End(0))?;
+        Ok(())
+    })
+}
+
+// Copy the contents of one file of the image to the extent of another of the same size
+fn copy_within_image(iso_file: &mut File, from: &FileEntry, to: &FileEntry, buffer: &mut [u8]) -> io::Result<()> {
+    let mut copied = 0;
+    while copied < to.size as usize {
+        let len = (to.size as usize - copied).min(buffer.len());
+        iso_file.seek(SeekFrom::Start(from.extent as u64 * BLOCK_SIZE as u64 + copied as u64))?;
+        iso_file.read_exact(&mut buffer[..len])?;
+        iso_file.seek(SeekFrom::Start(to.extent as u64 * BLOCK_SIZE as u64 + copied as u64))?;
+        iso_file.write_all(&buffer[..len])?;
+        copied += len;
+    }
+    Ok(())
+}
+
+// Find or create the image directory for the given archive path, including its parents
+pub fn archive_directory(tree: &mut ImageTree, dirs: &mut HashMap<String, usize>, archive: &Path, components: &[&str], options: &ImageOptions) -> io::Result<usize> {
+    let mut index = 0;
+    for depth in 1..=components.len() {
+        let path = components[..depth].join("/");
+        index = match dirs.get(&path) {
+            Some(&index) => index,
+            None => {
+                let child = add_directory(tree, &archive.join(&path), unicode::normalize(components[depth - 1], options.normalize).into_owned(), index, None, options.deep_dirs)?;
+                dirs.insert(path, child);
+                child
+            }
+        };
+    }
+    Ok(index)
+}
+
+// Sequential writer of ustar archives, with pax headers for names that don't fit
+struct TarWriter<W: Write> {
+    output: W,
+    written: u64,
+}
+
+impl<W: Write> TarWriter<W> {
+    fn new(output: W) -> TarWriter<W> {
+        TarWriter { output, written: 0 }
+    }
+
+    // Write the header of a member; its size bytes of contents must follow through write_contents
+    fn append_header(&mut self, path: &str, kind: u8, entry: &IsoEntry, size: u64, link: &str) -> io::Result<()> {
+        let split = ustar_split(path);
+        if split.is_none() || link.len() > 100 {
+            // pax extended header carrying the full path and link target
+            let mut records = Vec::new();
+            if split.is_none() {
+                records.extend(pax_record("path", path));
+            }
+            if link.len() > 100 {
+                records.extend(pax_record("linkpath", link));
+            }
+            let header = ustar_header("././@PaxHeader", "", b'x', 0o644, 0, 0, records.len() as u64, entry.modified, "", None);
+            self.write_block(&header)?;
+            self.write_contents(&records)?;
+            self.finish_contents(records.len() as u64)?;
+        }
+
+        let (prefix, name) = split.unwrap_or(("", &path[path.len().saturating_sub(100)..]));
+        let header = ustar_header(name, prefix, kind, entry.mode & 0o7777, entry.uid, entry.gid, size, entry.modified, &link[..link.len().min(100)], entry.device);
+        self.write_block(&header)
+    }
+
+    fn write_block(&mut self, block: &[u8]) -> io::Result<()> {
+        self.output.write_all(block)?;
+        self.written += block.len() as u64;
+        Ok(())
+    }
+
+    fn write_contents(&mut self, data: &[u8]) -> io::Result<()> {
+        self.write_block(data)
+    }
+
+    // Pad the contents of a member to a whole tar block
+    fn finish_contents(&mut self, size: u64) -> io::Result<()> {
+        let padding = size.next_multiple_of(TAR_BLOCK_SIZE as u64) - size;
+        self.write_block(&vec![0u8; padding as usize])
+    }
+
+    // End of archive: two zero blocks, padded to a whole record of 20 blocks as tar does
+    fn finish(mut self) -> io::Result<()> {
+        let end = (self.written + 2 * TAR_BLOCK_SIZE as u64).next_multiple_of(20 * TAR_BLOCK_SIZE as u64);
+        let zeros = vec![0u8; (end - self.written) as usize];
+        self.write_block(&zeros)?;
+        self.output.flush()
+    }
+}
+
+// Split a path into a ustar prefix (at most 155 bytes) and name (at most 100 bytes), if possible
+fn ustar_split(path: &str) -> Option<(&str, &str)> {
+    if path.len() <= 100 {
+        return Some(("", path));
+    }
+    path.char_indices()
+        .filter(|&(at, c)| c == '/' && at <= 155 && path.len() - at - 1 <= 100 && at + 1 < path.len())
+        .map(|(at, _)| (&path[..at], &path[at + 1..]))
+        .next()
+}
+
+// A pax record: "<length> <key>=<value>\n", where the length counts itself
+fn pax_record(key: &str, value: &str) -> Vec<u8> {
+    let body = format!(" {}={}\n", key, value);
+    let mut length = body.len() + 1;
+    while (length.to_string().len() + body.len()) != length {
+        length = length.to_string().len() + body.len();
+    }
+    format!("{}{}", length, body).into_bytes()
+}
+
+// A ustar header block
+#[allow(clippy::too_many_arguments)]
+fn ustar_header(name: &str, prefix: &str, kind: u8, mode: u32, uid: u32, gid: u32, size: u64, modified: i64, link: &str, device: Option<(u32, u32)>) -> [u8; TAR_BLOCK_SIZE] {
+    let mut header = [0u8; TAR_BLOCK_SIZE];
+    let mut put = |at: usize, bytes: &[u8]| header[at..at + bytes.len()].copy_from_slice(bytes);
+    put(0, name.as_bytes());
+    put(100, format!("{:07o}\0", mode).as_bytes());
+    put(108, format!("{:07o}\0", uid & 0o7777777).as_bytes());
+    put(116, format!("{:07o}\0", gid & 0o7777777).as_bytes());
+    put(124, format!("{:011o}\0", size).as_bytes());
+    put(136, format!("{:011o}\0", modified.max(0)).as_bytes());
+    put(148, b"        ");
+    put(156, &[kind]);
+    put(157, link.as_bytes());
+    put(257, b"ustar\x0000");
+    if let Some((major, minor)) = device {
+        put(329, format!("{:07o}\0", major & 0o7777777).as_bytes());
+        put(337, format!("{:07o}\0", minor & 0o7777777).as_bytes());
+    }
+    put(345, prefix.as_bytes());
+
+    let sum: u32 = header.iter().map(|&byte| byte as u32).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+    header
+}
+
+// Write every file, directory, symbolic link, FIFO and device of an image to a tar stream ("-" for standard output)
+pub fn iso_to_tar(iso_file_path: &Path, output_path: &Path) -> io::Result<()> {
+    let mut reader = open_image(iso_file_path)?;
+    let entries = reader.read_tree()?;
+
+    let output: Box<dyn Write> = if output_path == Path::new("-") {
+        Box::new(io::stdout().lock())
+    } else {
+        Box::new(File::create(output_path)?)
+    };
+    let mut tar = TarWriter::new(io::BufWriter::with_capacity(COPY_CHUNK_SIZE, output));
+    let mut buffer = vec![0u8; COPY_CHUNK_SIZE];
+
+    // Depth first from the root, so directories come before their contents
+    let mut pending: Vec<usize> = entries[0].children.iter().rev().copied().collect();
+    while let Some(index) = pending.pop() {
+        let entry = &entries[index];
+        let path = entry.path.trim_start_matches('/');
+
+        // FIFOs and devices are empty files of the image whose Rock Ridge mode says what they are
+        let special = match entry.mode & 0o170000 {
+            0o010000 => Some(b'6'),
+            0o020000 => Some(b'3'),
+            0o060000 => Some(b'4'),
+            _ => None,
+        };
+        if let Some(target) = &entry.symlink {
+            tar.append_header(path, b'2', entry, 0, target)?;
+        } else if let Some(kind) = special.filter(|_| !entry.is_directory) {
+            tar.append_header(path, kind, entry, 0, "")?;
+        } else if entry.is_directory {
+            tar.append_header(&format!("{}/", path), b'5', entry, 0, "")?;
+            pending.extend(entry.children.iter().rev());
+        } else {
+            tar.append_header(path, b'0', entry, entry.size as u64, "")?;
+            let mut offset = 0;
+            while offset < entry.size as u64 {
+                let bytes_read = reader.read_file_at(entry, offset, &mut buffer)?;
+                tar.write_contents(&buffer[..bytes_read])?;
+                offset += bytes_read as u64;
+            }
+            tar.finish_contents(entry.size as u64)?;
+        }
+    }
+    tar.finish()
+}
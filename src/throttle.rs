@@ -0,0 +1,94 @@
+// Staying out of the way of other work on the same disks: --bwlimit caps the rate file
+// contents are copied at, and --ionice lowers the I/O scheduling class of the whole run
+// (Linux only, through ioprio_set, like the ionice tool)
+//
+// The limit is kept on average since the first byte, by sleeping whenever the copy gets
+// ahead of it; with files copied in chunks of up to 8 MiB the rate evens out over a second
+// or so.
+
+use std::io::{self, ErrorKind};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// An I/O scheduling class and, for best-effort, its level from 0 (highest) to 7
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoPriority {
+    Idle,            // Only gets the disk when nobody else wants it
+    BestEffort(u8),
+}
+
+impl IoPriority {
+    pub fn parse(text: &str) -> io::Result<IoPriority> {
+        let invalid = || io::Error::new(ErrorKind::InvalidInput, format!("invalid --ionice: {} (expected idle or best-effort[:0-7])", text));
+        match text.split_once(':') {
+            None if text == "idle" => Ok(IoPriority::Idle),
+            None if text == "best-effort" => Ok(IoPriority::BestEffort(4)),
+            Some(("best-effort", level)) => match level.parse() {
+                Ok(level @ 0..=7) => Ok(IoPriority::BestEffort(level)),
+                _ => Err(invalid()),
+            },
+            _ => Err(invalid()),
+        }
+    }
+}
+
+#[cfg(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64")))]
+mod sys {
+    use std::ffi::c_long;
+
+    #[cfg(target_arch = "x86_64")]
+    pub const SYS_IOPRIO_SET: c_long = 251;
+    #[cfg(target_arch = "aarch64")]
+    pub const SYS_IOPRIO_SET: c_long = 30;
+    pub const IOPRIO_WHO_PROCESS: c_long = 1;
+    pub const IOPRIO_CLASS_BE: c_long = 2;
+    pub const IOPRIO_CLASS_IDLE: c_long = 3;
+    pub const IOPRIO_CLASS_SHIFT: c_long = 13;
+
+    extern "C" {
+        pub fn syscall(number: c_long, ...) -> c_long;
+    }
+}
+
+// Set the I/O scheduling class of this process (and the threads it starts afterwards)
+#[cfg(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64")))]
+pub fn set_io_priority(priority: IoPriority) -> io::Result<()> {
+    let value = match priority {
+        IoPriority::Idle => sys::IOPRIO_CLASS_IDLE << sys::IOPRIO_CLASS_SHIFT,
+        IoPriority::BestEffort(level) => sys::IOPRIO_CLASS_BE << sys::IOPRIO_CLASS_SHIFT | std::ffi::c_long::from(level),
+    };
+    // SAFETY: ioprio_set takes three integers and touches no memory of ours
+    if unsafe { sys::syscall(sys::SYS_IOPRIO_SET, sys::IOPRIO_WHO_PROCESS, 0 as std::ffi::c_long, value) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64"))))]
+pub fn set_io_priority(_priority: IoPriority) -> io::Result<()> {
+    Err(io::Error::new(ErrorKind::Unsupported, "--ionice is only supported on Linux"))
+}
+
+// Holds copying down to a number of bytes per second
+#[derive(Debug)]
+pub struct Throttle {
+    rate: u64,
+    started: Instant,
+    bytes: u64, // Copied since started
+}
+
+impl Throttle {
+    pub fn new(rate: u64) -> Throttle {
+        Throttle { rate, started: Instant::now(), bytes: 0 }
+    }
+
+    // Account for bytes just copied, sleeping until the average is back at the limit
+    pub fn pace(&mut self, bytes: usize) {
+        self.bytes += bytes as u64;
+        let due = Duration::from_secs_f64(self.bytes as f64 / self.rate as f64);
+        let elapsed = self.started.elapsed();
+        if due > elapsed {
+            thread::sleep(due - elapsed);
+        }
+    }
+}
@@ -0,0 +1,100 @@
+// TRANS.TBL files (mkisofs -T): identifiers are recorded as DOS-style 8.3 names, and every
+// directory gets a TRANS.TBL listing the original name of each of its entries
+//
+// This is for systems that only read plain ISO 9660 level 1 names, such as DOS and old Unix
+// CD-ROM drivers, which then still find the files; readers that understand the tables (this
+// one included) show the original names again. A line of the table is the entry type (F for
+// files, D for directories), the recorded identifier and the original name.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use super::{entry_name, Entry, ImageTree};
+
+pub const TRANS_TBL: &str = "TRANS.TBL";
+const BASE_LENGTH: usize = 8;
+const EXTENSION_LENGTH: usize = 3;
+const NAME_COLUMN: usize = 36; // Width of the identifier column, as mkisofs lays out its tables
+
+// Give every entry of the tree an 8.3 identifier and add the tables, before the tree is sorted
+pub fn translate(tree: &mut ImageTree) {
+    for index in 0..tree.dirs.len() {
+        // Tables in the source would list names that are no longer recorded
+        tree.dirs[index].entries.retain(|entry| !matches!(entry, Entry::File(file) if file.name == TRANS_TBL));
+
+        let mut entries: Vec<(String, bool)> = tree.dirs[index]
+            .entries
+            .iter()
+            .map(|entry| (entry_name(tree, entry).to_string(), matches!(entry, Entry::Directory(_) | Entry::Relocated { .. })))
+            .collect();
+        entries.sort();
+
+        let mut taken = HashSet::from([TRANS_TBL.to_string()]);
+        let mut names = HashMap::new();
+        let mut table = String::new();
+        for (name, is_directory) in entries {
+            let dos = unique_name(&name, is_directory, &mut taken);
+            table.push_str(&format!("{} {:<width$} {}\n", if is_directory { 'D' } else { 'F' }, dos, name, width = NAME_COLUMN));
+            names.insert(name, dos);
+        }
+        let table = tree.charset.encode(&table);
+        tree.add_generated(index, TRANS_TBL, table);
+        tree.dos_names.insert(index, names);
+    }
+}
+
+// An 8.3 name of upper case letters, digits and underscores that no other entry of the
+// directory has; clashes are numbered "NAME~1.EXT", "NAME~2.EXT" and so on
+fn unique_name(name: &str, is_directory: bool, taken: &mut HashSet<String>) -> String {
+    let (base, extension) = match name.rfind('.') {
+        Some(at) if at > 0 && !is_directory => (&name[..at], &name[at + 1..]),
+        _ => (name, ""),
+    };
+    let mut base = d_characters(base, BASE_LENGTH);
+    if base.is_empty() {
+        base.push('_');
+    }
+    let extension = d_characters(extension, EXTENSION_LENGTH);
+    let join = |base: &str| if extension.is_empty() { base.to_string() } else { format!("{}.{}", base, extension) };
+
+    let mut candidate = join(&base);
+    let mut counter = 1;
+    while taken.contains(&candidate) {
+        let suffix = format!("~{}", counter);
+        let kept: String = base.chars().take(BASE_LENGTH.saturating_sub(suffix.len())).collect();
+        candidate = join(&(kept + &suffix));
+        counter += 1;
+    }
+    taken.insert(candidate.clone());
+    candidate
+}
+
+// Upper case the name and replace everything ISO 9660 level 1 doesn't allow
+fn d_characters(name: &str, max_len: usize) -> String {
+    name.chars()
+        .map(|c| match c.to_ascii_uppercase() {
+            upper @ ('A'..='Z' | '0'..='9') => upper,
+            _ => '_',
+        })
+        .take(max_len)
+        .collect()
+}
+
+// Original names by identifier, read from the contents of a table found in an image
+pub fn parse_table(table: &str) -> BTreeMap<String, String> {
+    let mut names = BTreeMap::new();
+    for line in table.lines() {
+        let Some(rest) = line.strip_prefix(['F', 'D']).and_then(|rest| rest.strip_prefix(' ')) else {
+            continue;
+        };
+        let Some((identifier, name)) = rest.trim_start().split_once(char::is_whitespace) else {
+            continue;
+        };
+        // Tables written by mkisofs list files with their version number
+        let identifier = identifier.split(';').next().unwrap_or(identifier).trim_end_matches('.');
+        let name = name.trim_start();
+        if !name.is_empty() {
+            names.insert(identifier.to_string(), name.to_string());
+        }
+    }
+    names
+}
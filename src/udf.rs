@@ -0,0 +1,471 @@
+// UDF bridge: a UDF 1.02 file system over the same file contents as the ISO 9660 one, which
+// DVD players read (they ignore ISO 9660), written with --dvd-video
+//
+// On top of the ISO 9660 volume:
+//
+//     after the ISO 9660 terminator  BEA01, NSR02, TEA01 (the volume recognition sequence)
+//     blocks 32-47                   main volume descriptor sequence
+//     blocks 48-63                   reserve copy of it
+//     blocks 64-65                   logical volume integrity descriptor and a terminator
+//     block 256                      anchor volume descriptor pointer
+//     from block 257                 the partition, starting with the file set descriptor and
+//                                    the file entries and identifiers of every directory and file
+//     last block                     second anchor
+//
+// The partition runs to the second anchor, so the file entries point at the very extents the
+// ISO 9660 records do. Directories moved under rr_moved by --deep-dirs relocate are back in
+// place: UDF has no depth limit.
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, ErrorKind, Write};
+
+use super::{blocks_for, utc_date_time, Entry, ImageTree, Layout, VolumeIdentity, BLOCK_SIZE, FLAG_HIDDEN};
+
+pub const RECOGNITION_BLOCKS: u32 = 3;
+pub const MAIN_SEQUENCE: u32 = 32;
+const RESERVE_SEQUENCE: u32 = 48;
+const SEQUENCE_BLOCKS: u32 = 16;
+const INTEGRITY_SEQUENCE: u32 = 64;
+pub const ANCHOR: u32 = 256;
+pub const PARTITION_START: u32 = ANCHOR + 1;
+const FILE_SET_BLOCKS: u32 = 2; // File set descriptor and its terminator
+const FIRST_UNIQUE_ID: u64 = 16; // 1-15 are reserved for Macintosh use
+const MAX_EXTENT: u64 = (1 << 30) - BLOCK_SIZE as u64; // Longest extent an allocation descriptor can describe
+
+// Descriptor tag identifiers
+const PRIMARY_VOLUME: u16 = 1;
+const ANCHOR_POINTER: u16 = 2;
+const IMPLEMENTATION_USE: u16 = 4;
+const PARTITION: u16 = 5;
+const LOGICAL_VOLUME: u16 = 6;
+const UNALLOCATED_SPACE: u16 = 7;
+const TERMINATING: u16 = 8;
+const LOGICAL_VOLUME_INTEGRITY: u16 = 9;
+const FILE_SET: u16 = 256;
+const FILE_IDENTIFIER: u16 = 257;
+const FILE_ENTRY: u16 = 261;
+
+const UDF_REVISION: [u8; 2] = [0x02, 0x01]; // 1.02
+const DIRECTORY_PERMISSIONS: u32 = 0x14A5; // Read and execute for owner, group and others
+const FILE_PERMISSIONS: u32 = 0x1084; // Read for owner, group and others
+
+// Where the file set of the image goes in the partition
+#[derive(Debug)]
+pub struct UdfLayout {
+    nodes: Vec<Node>,                      // Directories and files in block order
+    directories: HashMap<usize, usize>,    // Node of each directory by index
+    files: HashMap<(usize, usize), usize>, // Node of each file by directory and entry index
+    pub blocks: u32,                       // Blocks of the partition the file set takes from its start
+}
+
+#[derive(Debug)]
+struct Node {
+    kind: NodeKind,
+    block: u32, // Of the file entry, from the start of the partition
+    unique_id: u64,
+}
+
+#[derive(Debug)]
+enum NodeKind {
+    Directory { index: usize, identifiers: u32 }, // identifiers: bytes of file identifier descriptors after the entry
+    File(usize, usize),
+}
+
+// What a directory lists: names with the entry index of files or the index of directories,
+// relocated directories in their original place and rr_moved itself left out
+fn children(tree: &ImageTree, dir: usize) -> Vec<(&str, usize, bool)> {
+    tree.dirs[dir]
+        .entries
+        .iter()
+        .enumerate()
+        .filter_map(|(entry_index, entry)| match entry {
+            Entry::File(file) => Some((file.name.as_str(), entry_index, false)),
+            Entry::Directory(child) if Some(*child) == tree.relocation_dir => None,
+            Entry::Directory(child) => Some((tree.dirs[*child].name.as_str(), *child, true)),
+            Entry::Relocated { name, target } => Some((name.as_str(), *target, true)),
+            Entry::Symlink { .. } | Entry::Special { .. } => None, // DVD-Video players have no use for them
+        })
+        .collect()
+}
+
+// Assign blocks to the file entries and identifiers, breadth first from the root
+pub fn plan(tree: &ImageTree) -> io::Result<UdfLayout> {
+    let mut layout = UdfLayout { nodes: Vec::new(), directories: HashMap::new(), files: HashMap::new(), blocks: FILE_SET_BLOCKS };
+    let mut queue = VecDeque::from([0]);
+    while let Some(dir) = queue.pop_front() {
+        let mut identifiers = identifier_length(0);
+        for (name, _, _) in children(tree, dir) {
+            identifiers += identifier_length(osta_name(name)?.len());
+        }
+        layout.directories.insert(dir, layout.nodes.len());
+        layout.push(NodeKind::Directory { index: dir, identifiers: identifiers as u32 }, 1 + blocks_for(identifiers as u64));
+        for (_, child, is_directory) in children(tree, dir) {
+            if is_directory {
+                queue.push_back(child);
+            } else {
+                layout.files.insert((dir, child), layout.nodes.len());
+                layout.push(NodeKind::File(dir, child), 1);
+            }
+        }
+    }
+    Ok(layout)
+}
+
+impl UdfLayout {
+    fn push(&mut self, kind: NodeKind, blocks: u32) {
+        let unique_id = if self.nodes.is_empty() { 0 } else { FIRST_UNIQUE_ID + self.nodes.len() as u64 - 1 };
+        self.nodes.push(Node { kind, block: self.blocks, unique_id });
+        self.blocks += blocks;
+    }
+
+    fn directory(&self, index: usize) -> &Node {
+        &self.nodes[self.directories[&index]]
+    }
+}
+
+// Length of a file identifier descriptor with a name of the given length, padded to 4 bytes
+fn identifier_length(name_length: usize) -> usize {
+    (38 + name_length).div_ceil(4) * 4
+}
+
+// A name in OSTA compressed Unicode: 8 bits per character when they all fit, else UCS-2
+fn osta_name(name: &str) -> io::Result<Vec<u8>> {
+    let units: Vec<u16> = name.encode_utf16().collect();
+    let encoded: Vec<u8> = if units.iter().all(|&unit| unit <= 0xFF) {
+        std::iter::once(8).chain(units.iter().map(|&unit| unit as u8)).collect()
+    } else {
+        std::iter::once(16).chain(units.iter().flat_map(|unit| unit.to_be_bytes())).collect()
+    };
+    if encoded.len() > 255 {
+        return Err(io::Error::new(ErrorKind::InvalidInput, format!("{} is too long a name for UDF", name)));
+    }
+    Ok(encoded)
+}
+
+// A fixed-length OSTA string: the name cut to fit, with its length in the last byte
+fn dstring(text: &str, size: usize) -> Vec<u8> {
+    let mut field = vec![0u8; size];
+    if text.is_empty() {
+        return field;
+    }
+    let mut name = osta_name(text).unwrap_or_else(|_| vec![8]);
+    let unit = if name[0] == 16 { 2 } else { 1 };
+    name.truncate(1 + (size - 2) / unit * unit);
+    field[..name.len()].copy_from_slice(&name);
+    field[size - 1] = name.len() as u8;
+    field
+}
+
+// The OSTA CS0 character set specification
+fn charspec() -> [u8; 64] {
+    let mut field = [0u8; 64];
+    field[1..24].copy_from_slice(b"OSTA Compressed Unicode");
+    field
+}
+
+// An entity identifier
+fn regid(identifier: &[u8], suffix: &[u8]) -> [u8; 32] {
+    let mut field = [0u8; 32];
+    field[1..1 + identifier.len()].copy_from_slice(identifier);
+    field[24..24 + suffix.len()].copy_from_slice(suffix);
+    field
+}
+
+fn implementation() -> [u8; 32] {
+    regid(b"*makeiso", &[])
+}
+
+fn domain() -> [u8; 32] {
+    regid(b"*OSTA UDF Compliant", &UDF_REVISION)
+}
+
+// A timestamp in UTC
+fn timestamp(time: i64) -> [u8; 12] {
+    let (year, month, day, hour, minute, second) = utc_date_time(time);
+    let mut field = [0u8; 12];
+    field[0..2].copy_from_slice(&0x1000u16.to_le_bytes()); // Local time with a zero offset
+    field[2..4].copy_from_slice(&(year.clamp(1, 9999) as u16).to_le_bytes());
+    field[4..10].copy_from_slice(&[month as u8, day as u8, hour as u8, minute as u8, second as u8, 0]);
+    field
+}
+
+// A long allocation descriptor in the partition, carrying the UDF unique ID of what it points at
+fn long_ad(length: u32, block: u32, unique_id: u64) -> [u8; 16] {
+    let mut field = [0u8; 16];
+    field[0..4].copy_from_slice(&length.to_le_bytes());
+    field[4..8].copy_from_slice(&block.to_le_bytes());
+    field[12..16].copy_from_slice(&(unique_id as u32).to_le_bytes());
+    field
+}
+
+fn put(descriptor: &mut [u8], at: usize, bytes: &[u8]) {
+    descriptor[at..at + bytes.len()].copy_from_slice(bytes);
+}
+
+// CRC-CCITT (polynomial 0x1021, starting from 0) of a descriptor's body
+fn crc(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+// Fill in the tag at the start of a descriptor recorded at the given block
+fn tag(descriptor: &mut [u8], id: u16, location: u32) {
+    let body_crc = crc(&descriptor[16..]);
+    put(descriptor, 0, &id.to_le_bytes());
+    put(descriptor, 2, &2u16.to_le_bytes()); // Descriptor version of NSR02
+    put(descriptor, 8, &body_crc.to_le_bytes());
+    put(descriptor, 10, &((descriptor.len() - 16) as u16).to_le_bytes());
+    put(descriptor, 12, &location.to_le_bytes());
+    descriptor[4] = descriptor[..16].iter().enumerate().filter(|&(at, _)| at != 4).fold(0u8, |sum, (_, &byte)| sum.wrapping_add(byte));
+}
+
+// A descriptor padded to a whole block
+fn block(mut descriptor: Vec<u8>) -> Vec<u8> {
+    descriptor.resize(descriptor.len().div_ceil(BLOCK_SIZE) * BLOCK_SIZE, 0);
+    descriptor
+}
+
+fn terminating(location: u32) -> Vec<u8> {
+    let mut descriptor = vec![0u8; 512];
+    tag(&mut descriptor, TERMINATING, location);
+    descriptor
+}
+
+// BEA01, NSR02 and TEA01, which follow the ISO 9660 terminator
+pub fn recognition_sequence() -> Vec<u8> {
+    let mut area = vec![0u8; RECOGNITION_BLOCKS as usize * BLOCK_SIZE];
+    for (number, identifier) in [b"BEA01", b"NSR02", b"TEA01"].iter().enumerate() {
+        let at = number * BLOCK_SIZE;
+        area[at + 1..at + 6].copy_from_slice(*identifier);
+        area[at + 6] = 1;
+    }
+    area
+}
+
+// An anchor volume descriptor pointer at the given block
+pub fn anchor(location: u32) -> Vec<u8> {
+    let mut descriptor = vec![0u8; 512];
+    let sequence_length = SEQUENCE_BLOCKS * BLOCK_SIZE as u32;
+    put(&mut descriptor, 16, &sequence_length.to_le_bytes());
+    put(&mut descriptor, 20, &MAIN_SEQUENCE.to_le_bytes());
+    put(&mut descriptor, 24, &sequence_length.to_le_bytes());
+    put(&mut descriptor, 28, &RESERVE_SEQUENCE.to_le_bytes());
+    tag(&mut descriptor, ANCHOR_POINTER, location);
+    block(descriptor)
+}
+
+// The volume descriptor sequence, with its descriptors recorded from block start
+fn volume_sequence(start: u32, layout: &Layout, identity: &VolumeIdentity, image_time: i64) -> Vec<u8> {
+    let volume_set = format!("{:016X}{}", image_time as u64, identity.volume_id);
+    let mut sequence = Vec::new();
+
+    let mut primary = vec![0u8; 512];
+    put(&mut primary, 24, &dstring(&identity.volume_id, 32));
+    put(&mut primary, 56, &1u16.to_le_bytes()); // Volume sequence number
+    put(&mut primary, 58, &1u16.to_le_bytes()); // Maximum volume sequence number
+    put(&mut primary, 60, &2u16.to_le_bytes()); // Interchange level
+    put(&mut primary, 62, &2u16.to_le_bytes());
+    put(&mut primary, 64, &1u32.to_le_bytes()); // Character set list: CS0
+    put(&mut primary, 68, &1u32.to_le_bytes());
+    put(&mut primary, 72, &dstring(&volume_set, 128));
+    put(&mut primary, 200, &charspec());
+    put(&mut primary, 264, &charspec());
+    put(&mut primary, 376, &timestamp(image_time));
+    put(&mut primary, 388, &implementation());
+    tag(&mut primary, PRIMARY_VOLUME, start);
+    sequence.extend(block(primary));
+
+    let mut implementation_use = vec![0u8; 512];
+    put(&mut implementation_use, 16, &1u32.to_le_bytes());
+    put(&mut implementation_use, 20, &regid(b"*UDF LV Info", &UDF_REVISION));
+    put(&mut implementation_use, 52, &charspec());
+    put(&mut implementation_use, 116, &dstring(&identity.volume_id, 128));
+    put(&mut implementation_use, 352, &implementation());
+    tag(&mut implementation_use, IMPLEMENTATION_USE, start + 1);
+    sequence.extend(block(implementation_use));
+
+    let mut partition = vec![0u8; 512];
+    put(&mut partition, 16, &2u32.to_le_bytes());
+    put(&mut partition, 20, &1u16.to_le_bytes()); // Allocated
+    put(&mut partition, 24, &regid(b"+NSR02", &[]));
+    put(&mut partition, 184, &1u32.to_le_bytes()); // Read-only
+    put(&mut partition, 188, &PARTITION_START.to_le_bytes());
+    put(&mut partition, 192, &partition_blocks(layout).to_le_bytes());
+    put(&mut partition, 196, &implementation());
+    tag(&mut partition, PARTITION, start + 2);
+    sequence.extend(block(partition));
+
+    let mut logical = vec![0u8; 446];
+    put(&mut logical, 16, &3u32.to_le_bytes());
+    put(&mut logical, 20, &charspec());
+    put(&mut logical, 84, &dstring(&identity.volume_id, 128));
+    put(&mut logical, 212, &(BLOCK_SIZE as u32).to_le_bytes());
+    put(&mut logical, 216, &domain());
+    put(&mut logical, 248, &long_ad(FILE_SET_BLOCKS * BLOCK_SIZE as u32, 0, 0));
+    put(&mut logical, 264, &6u32.to_le_bytes()); // Length of the partition maps
+    put(&mut logical, 268, &1u32.to_le_bytes()); // One map
+    put(&mut logical, 272, &implementation());
+    put(&mut logical, 432, &(2 * BLOCK_SIZE as u32).to_le_bytes());
+    put(&mut logical, 436, &INTEGRITY_SEQUENCE.to_le_bytes());
+    put(&mut logical, 440, &[1, 6, 1, 0, 0, 0]); // Type 1 map of partition 0 on volume 1
+    tag(&mut logical, LOGICAL_VOLUME, start + 3);
+    sequence.extend(block(logical));
+
+    let mut unallocated = vec![0u8; 24];
+    put(&mut unallocated, 16, &4u32.to_le_bytes());
+    tag(&mut unallocated, UNALLOCATED_SPACE, start + 4);
+    sequence.extend(block(unallocated));
+
+    sequence.extend(block(terminating(start + 5)));
+    sequence.resize(SEQUENCE_BLOCKS as usize * BLOCK_SIZE, 0);
+    sequence
+}
+
+// Blocks of the partition: everything from its start up to the second anchor
+fn partition_blocks(layout: &Layout) -> u32 {
+    layout.total_blocks - 1 - PARTITION_START
+}
+
+// The integrity descriptor, closed, with the counts of files and directories
+fn integrity(udf: &UdfLayout, layout: &Layout, image_time: i64) -> Vec<u8> {
+    let mut descriptor = vec![0u8; 134];
+    put(&mut descriptor, 16, &timestamp(image_time));
+    put(&mut descriptor, 28, &1u32.to_le_bytes()); // Close integrity
+    put(&mut descriptor, 40, &(FIRST_UNIQUE_ID + udf.nodes.len() as u64).to_le_bytes());
+    put(&mut descriptor, 72, &1u32.to_le_bytes()); // One partition
+    put(&mut descriptor, 76, &46u32.to_le_bytes()); // Length of the implementation use
+    put(&mut descriptor, 84, &partition_blocks(layout).to_le_bytes()); // Free space is 0
+    put(&mut descriptor, 88, &implementation());
+    put(&mut descriptor, 120, &(udf.files.len() as u32).to_le_bytes());
+    put(&mut descriptor, 124, &(udf.directories.len() as u32).to_le_bytes());
+    for at in [128, 130, 132] {
+        put(&mut descriptor, at, &UDF_REVISION); // Minimum read, minimum and maximum write revisions
+    }
+    tag(&mut descriptor, LOGICAL_VOLUME_INTEGRITY, INTEGRITY_SEQUENCE);
+    descriptor
+}
+
+// The file set descriptor, pointing at the root directory
+fn file_set(udf: &UdfLayout, identity: &VolumeIdentity, image_time: i64) -> Vec<u8> {
+    let mut descriptor = vec![0u8; 512];
+    put(&mut descriptor, 16, &timestamp(image_time));
+    put(&mut descriptor, 28, &3u16.to_le_bytes()); // Interchange level
+    put(&mut descriptor, 30, &3u16.to_le_bytes());
+    put(&mut descriptor, 32, &1u32.to_le_bytes());
+    put(&mut descriptor, 36, &1u32.to_le_bytes());
+    put(&mut descriptor, 48, &charspec());
+    put(&mut descriptor, 112, &dstring(&identity.volume_id, 128));
+    put(&mut descriptor, 240, &charspec());
+    put(&mut descriptor, 304, &dstring(&identity.volume_id, 32));
+    let root = udf.directory(0);
+    put(&mut descriptor, 400, &long_ad(BLOCK_SIZE as u32, root.block, root.unique_id));
+    put(&mut descriptor, 416, &domain());
+    tag(&mut descriptor, FILE_SET, 0);
+    descriptor
+}
+
+// A file entry with short allocation descriptors for the given extents (in bytes and partition blocks)
+fn file_entry(location: u32, is_directory: bool, links: u16, size: u64, extents: &[(u32, u32)], modified: i64, unique_id: u64) -> Vec<u8> {
+    let mut descriptor = vec![0u8; 176 + 8 * extents.len()];
+    put(&mut descriptor, 20, &4u16.to_le_bytes()); // Strategy 4: a single direct entry
+    put(&mut descriptor, 24, &1u16.to_le_bytes()); // Maximum number of entries
+    descriptor[27] = if is_directory { 4 } else { 5 };
+    // The ICB flags (34-35) stay 0: short allocation descriptors
+    put(&mut descriptor, 36, &u32::MAX.to_le_bytes()); // No owner
+    put(&mut descriptor, 40, &u32::MAX.to_le_bytes()); // No group
+    put(&mut descriptor, 44, &(if is_directory { DIRECTORY_PERMISSIONS } else { FILE_PERMISSIONS }).to_le_bytes());
+    put(&mut descriptor, 48, &links.to_le_bytes());
+    put(&mut descriptor, 56, &size.to_le_bytes());
+    put(&mut descriptor, 64, &blocks_for(size).to_le_bytes());
+    for at in [72, 84, 96] {
+        put(&mut descriptor, at, &timestamp(modified)); // Access, modification and attribute times
+    }
+    put(&mut descriptor, 108, &1u32.to_le_bytes()); // Checkpoint
+    put(&mut descriptor, 128, &implementation());
+    put(&mut descriptor, 160, &unique_id.to_le_bytes());
+    put(&mut descriptor, 172, &(8 * extents.len() as u32).to_le_bytes());
+    for (number, &(length, start)) in extents.iter().enumerate() {
+        put(&mut descriptor, 176 + 8 * number, &length.to_le_bytes());
+        put(&mut descriptor, 180 + 8 * number, &start.to_le_bytes());
+    }
+    tag(&mut descriptor, FILE_ENTRY, location);
+    descriptor
+}
+
+// A file identifier descriptor; an empty name is the parent entry
+fn file_identifier(name: &[u8], characteristics: u8, target: &Node, location: u32) -> Vec<u8> {
+    let mut descriptor = vec![0u8; identifier_length(name.len())];
+    put(&mut descriptor, 16, &1u16.to_le_bytes()); // File version number
+    descriptor[18] = characteristics;
+    descriptor[19] = name.len() as u8;
+    put(&mut descriptor, 20, &long_ad(BLOCK_SIZE as u32, target.block, target.unique_id));
+    put(&mut descriptor, 38, name);
+    tag(&mut descriptor, FILE_IDENTIFIER, location);
+    descriptor
+}
+
+// The extents of a file, split where they'd be too long for one descriptor
+fn file_extents(extent: u32, size: u64) -> Vec<(u32, u32)> {
+    let mut extents = Vec::new();
+    let (mut offset, mut start) = (0u64, extent - PARTITION_START);
+    while offset < size {
+        let length = (size - offset).min(MAX_EXTENT);
+        extents.push((length as u32, start));
+        offset += length;
+        start += blocks_for(length);
+    }
+    extents
+}
+
+// Write the descriptor sequences, the integrity sequence, the anchor and the file set, from
+// block 32 (where the writer must be) to the end of the file set
+pub fn write_area<W: Write>(writer: &mut W, tree: &ImageTree, layout: &Layout, udf: &UdfLayout, identity: &VolumeIdentity) -> io::Result<()> {
+    writer.write_all(&volume_sequence(MAIN_SEQUENCE, layout, identity, tree.image_time))?;
+    writer.write_all(&volume_sequence(RESERVE_SEQUENCE, layout, identity, tree.image_time))?;
+    writer.write_all(&block(integrity(udf, layout, tree.image_time)))?;
+    writer.write_all(&block(terminating(INTEGRITY_SEQUENCE + 1)))?;
+    let gap = ANCHOR - INTEGRITY_SEQUENCE - 2;
+    writer.write_all(&vec![0u8; gap as usize * BLOCK_SIZE])?;
+    writer.write_all(&anchor(ANCHOR))?;
+
+    writer.write_all(&block(file_set(udf, identity, tree.image_time)))?;
+    writer.write_all(&block(terminating(1)))?;
+    for node in &udf.nodes {
+        match node.kind {
+            NodeKind::Directory { index, identifiers } => {
+                let dir = &tree.dirs[index];
+                let listed = children(tree, index);
+                let links = 1 + listed.iter().filter(|(_, _, is_directory)| *is_directory).count() as u16;
+                let fids = node.block + 1;
+                let entry = file_entry(node.block, true, links, identifiers as u64, &[(identifiers, fids)], tree.record_time(dir.modified), node.unique_id);
+                writer.write_all(&block(entry))?;
+
+                let parent = udf.directory(dir.original_parent.unwrap_or(dir.parent));
+                let mut area = file_identifier(&[], 0x0A, parent, fids);
+                for (name, child, is_directory) in listed {
+                    let target = if is_directory { udf.directory(child) } else { &udf.nodes[udf.files[&(index, child)]] };
+                    let mut characteristics = if is_directory { 0x02 } else { 0x00 };
+                    if tree.record_flags(index, name) & FLAG_HIDDEN != 0 {
+                        characteristics |= 0x01;
+                    }
+                    let location = fids + (area.len() / BLOCK_SIZE) as u32;
+                    area.extend(file_identifier(&osta_name(name)?, characteristics, target, location));
+                }
+                writer.write_all(&block(area))?;
+            }
+            NodeKind::File(index, entry) => {
+                let Entry::File(file) = &tree.dirs[index].entries[entry] else { unreachable!("file node") };
+                let size = file.size as u64;
+                let entry = file_entry(node.block, false, 1, size, &file_extents(file.extent, size), tree.record_time(file.modified), node.unique_id);
+                writer.write_all(&block(entry))?;
+            }
+        }
+    }
+    Ok(())
+}
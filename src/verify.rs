@@ -0,0 +1,366 @@
+// makeiso verify: a check of the structure of an image, as opposed to makeiso verify-sig (its
+// signature) and verify-restore (a tree restored from it). Each problem found is listed and
+// any of them makes the command fail; makeiso repair (see repair.rs) puts right the ones that
+// say it can.
+//
+// Images copied by transfers that broke off are the common case: the volume size recorded in
+// the primary descriptor is compared with what there is to read (after decryption, and
+// without the framing of raw sectors), and the last block of the volume is read, which for a
+// remote image also checks that the server goes on serving that far.
+//
+// The rest is read block by block rather than through the reader, which stops at much of
+// what is checked here: the descriptor set has to end in a terminator, the little- and
+// big-endian copies of every field recorded both ways (in the descriptors and in every
+// directory record of every hierarchy) have to agree, each hierarchy's path tables have to
+// list its directories as its directory records do, and the volume has to take in every
+// extent the tree points at. High Sierra images only get the size checks.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+
+use super::reader::{self, open_source, ImageSource, IsoReader, ReadAt, Trust, Truncation};
+use super::{BLOCK_SIZE, CD001, CDROM, PRIMARY_VOLUME_DESCRIPTOR, SUPPLEMENTARY_VOLUME_DESCRIPTOR, SYSTEM_AREA_BLOCKS, VOLUME_DESCRIPTOR_TERMINATOR};
+
+const USAGE: &str = "Usage: makeiso verify <image.iso>";
+const BOOT_RECORD: u8 = 0;
+const EL_TORITO: &[u8] = b"EL TORITO SPECIFICATION";
+const JOLIET_ESCAPES: [&[u8]; 3] = [b"%/@", b"%/C", b"%/E"];
+const ROOT_RECORD: usize = 156; // Where the root directory record is in a volume descriptor
+const VOLUME_SPACE_SIZE: usize = 80;
+const PATH_TABLE_SIZE: usize = 132;
+
+// Fields recorded little-endian and then big-endian, by offset and width of one copy
+const DESCRIPTOR_FIELDS: [(usize, usize, &str); 5] = [
+    (VOLUME_SPACE_SIZE, 4, "volume space size"),
+    (120, 2, "volume set size"),
+    (124, 2, "volume sequence number"),
+    (128, 2, "logical block size"),
+    (PATH_TABLE_SIZE, 4, "path table size"),
+];
+const RECORD_FIELDS: [(usize, usize, &str); 3] = [(2, 4, "extent"), (10, 4, "size"), (28, 2, "volume sequence number")];
+
+// Something wrong with an image, and how repair puts it right when it can
+#[derive(Debug)]
+pub struct Problem {
+    pub description: String,
+    pub fix: Option<Fix>,
+}
+
+// Bytes written over the image, described for the list of changes
+#[derive(Debug)]
+pub struct Fix {
+    pub change: String,
+    pub patches: Vec<(u64, Vec<u8>)>, // Bytes and the offset they go at
+    pub blocks: Option<u64>,          // What the image is cut or padded to, with the volume size
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.description)
+    }
+}
+
+// A directory of one hierarchy, in path table order
+struct Directory {
+    extent: u32,
+    size: u32,
+    parent: u16, // Path table number of the parent; the root is its own
+    identifier: Vec<u8>,
+    path: String,
+}
+
+// A volume descriptor with a hierarchy of its own: the primary one and the supplementary ones
+struct Volume {
+    block: u64,
+    data: Vec<u8>,
+    name: &'static str,
+}
+
+// The image being checked and what was found so far
+struct Scan {
+    source: ImageSource,
+    problems: Vec<Problem>,
+    used: Vec<(u64, u64)>, // Ranges of blocks the volume structures and files take up, by start and length
+}
+
+fn blocks_for(bytes: u64) -> u64 {
+    bytes.div_ceil(BLOCK_SIZE as u64)
+}
+
+fn both_endian_u32(value: u32) -> Vec<u8> {
+    [value.to_le_bytes(), value.to_be_bytes()].concat()
+}
+
+// A directory identifier as it is shown, UCS-2 for Joliet
+fn display_name(identifier: &[u8], joliet: bool) -> String {
+    let name = if joliet {
+        String::from_utf16_lossy(&identifier.chunks(2).map(|pair| u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)])).collect::<Vec<u16>>())
+    } else {
+        String::from_utf8_lossy(identifier).into_owned()
+    };
+    name.split(';').next().unwrap_or_default().to_string()
+}
+
+// The path table of a hierarchy, type L (little-endian) or M
+fn path_table(directories: &[Directory], little_endian: bool) -> Vec<u8> {
+    let mut table = Vec::new();
+    for directory in directories {
+        table.push(directory.identifier.len() as u8);
+        table.push(0);
+        if little_endian {
+            table.extend_from_slice(&directory.extent.to_le_bytes());
+            table.extend_from_slice(&directory.parent.to_le_bytes());
+        } else {
+            table.extend_from_slice(&directory.extent.to_be_bytes());
+            table.extend_from_slice(&directory.parent.to_be_bytes());
+        }
+        table.extend_from_slice(&directory.identifier);
+        if directory.identifier.len() % 2 == 1 {
+            table.push(0);
+        }
+    }
+    table
+}
+
+impl Scan {
+    fn read(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        let mut data = vec![0u8; len];
+        self.source.read_exact_at(offset, &mut data)?;
+        Ok(data)
+    }
+
+    fn problem(&mut self, description: String, fix: Option<Fix>) {
+        self.problems.push(Problem { description, fix });
+    }
+
+    // Check the both-endian fields of a structure found at offset of the image, returning the
+    // copy believed of each (the little-endian one unless --trust be)
+    fn both_endian(&mut self, data: &[u8], offset: u64, fields: &[(usize, usize, &str)], what: &str) -> Vec<u32> {
+        let big_endian = reader::trust() == Trust::Be;
+        let mut values = Vec::new();
+        for &(at, width, field) in fields {
+            let (le, be) = (&data[at..at + width], &data[at + width..at + 2 * width]);
+            let le_value = le.iter().rev().fold(0u32, |value, &byte| value << 8 | byte as u32);
+            let be_value = be.iter().fold(0u32, |value, &byte| value << 8 | byte as u32);
+            let value = if big_endian { be_value } else { le_value };
+            values.push(value);
+            if le_value == be_value {
+                continue;
+            }
+            // The copy not believed is written over with the one that is
+            let (copy, patch, bytes) = if big_endian { ("little-endian", at, be.iter().rev().copied().collect()) } else { ("big-endian", at + width, le.iter().rev().copied().collect()) };
+            let fix = Fix { change: format!("Set the {} copy of the {} of {} to {}", copy, field, what, value), patches: vec![(offset + patch as u64, bytes)], blocks: None };
+            self.problem(format!("The little- and big-endian copies of the {} of {} differ ({} and {})", field, what, le_value, be_value), Some(fix));
+        }
+        values
+    }
+
+    // Walk the hierarchy of a descriptor, checking its records; returns its directories in
+    // path table order
+    fn hierarchy(&mut self, volume: &Volume) -> Vec<Directory> {
+        let joliet = volume.name == "Joliet";
+        let root = self.both_endian(&volume.data[ROOT_RECORD..], volume.block * BLOCK_SIZE as u64 + ROOT_RECORD as u64, &RECORD_FIELDS, &format!("the root directory record of the {} descriptor", volume.name));
+        let mut directories = vec![Directory { extent: root[0], size: root[1], parent: 1, identifier: vec![0], path: "/".to_string() }];
+        let mut seen = HashSet::from([root[0]]);
+        let mut next = 0;
+        while next < directories.len() {
+            let (extent, size) = (directories[next].extent as u64, directories[next].size as u64);
+            self.used.push((extent, blocks_for(size)));
+            let data = match self.read(extent * BLOCK_SIZE as u64, (blocks_for(size) as usize) * BLOCK_SIZE) {
+                Ok(data) => data,
+                Err(e) => {
+                    self.problem(format!("The directory {} of the {} hierarchy (block {}) could not be read: {}", directories[next].path, volume.name, extent, e), None);
+                    next += 1;
+                    continue;
+                }
+            };
+            let mut at = 0;
+            while at < data.len() {
+                let length = data[at] as usize;
+                if length == 0 {
+                    at = (at / BLOCK_SIZE + 1) * BLOCK_SIZE; // The rest of the block is padding
+                    continue;
+                }
+                let Some(record) = data.get(at..at + length).filter(|record| record.len() >= 34 && record.len() >= 33 + record[32] as usize) else {
+                    self.problem(format!("The directory {} of the {} hierarchy has a broken record at byte {} of block {}", directories[next].path, volume.name, at % BLOCK_SIZE, extent + (at / BLOCK_SIZE) as u64), None);
+                    break;
+                };
+                let identifier = record[33..33 + record[32] as usize].to_vec();
+                let path = match identifier[..] {
+                    [0] => directories[next].path.clone(),
+                    [1] => format!("{} (parent)", directories[next].path),
+                    _ => format!("{}{}{}", directories[next].path, display_name(&identifier, joliet), if record[25] & 0x02 != 0 { "/" } else { "" }),
+                };
+                let values = self.both_endian(record, extent * BLOCK_SIZE as u64 + at as u64, &RECORD_FIELDS, &format!("the directory record of {} in the {} hierarchy", path, volume.name));
+                if identifier != [0] && identifier != [1] {
+                    if record[25] & 0x02 == 0 {
+                        if values[1] > 0 {
+                            self.used.push((values[0] as u64, blocks_for(values[1] as u64)));
+                        }
+                    } else if seen.insert(values[0]) && directories.len() < u16::MAX as usize {
+                        directories.push(Directory { extent: values[0], size: values[1], parent: next as u16 + 1, identifier, path });
+                    }
+                }
+                at += length;
+            }
+            next += 1;
+        }
+        directories
+    }
+
+    // Compare the path tables of a descriptor with its directories
+    fn path_tables(&mut self, volume: &Volume, directories: &[Directory], recorded_size: u32) -> io::Result<()> {
+        let data = &volume.data;
+        let tables = [
+            (u32::from_le_bytes(data[140..144].try_into().unwrap()), true, "type L"),
+            (u32::from_le_bytes(data[144..148].try_into().unwrap()), true, "optional type L"),
+            (u32::from_be_bytes(data[148..152].try_into().unwrap()), false, "type M"),
+            (u32::from_be_bytes(data[152..156].try_into().unwrap()), false, "optional type M"),
+        ];
+        let size = path_table(directories, true).len() as u64;
+        let space = blocks_for(recorded_size as u64);
+        for &(block, _, _) in tables.iter().filter(|table| table.0 != 0) {
+            self.used.push((block as u64, space));
+        }
+        if size != recorded_size as u64 {
+            let description = format!("The path tables of the {} descriptor are recorded as {} bytes, but its directories make {}", volume.name, recorded_size, size);
+            if blocks_for(size) > space {
+                self.problem(format!("{}, more than the {} blocks they have", description, space), None);
+                return Ok(());
+            }
+            let change = format!("Set the path table size of the {} descriptor to {}", volume.name, size);
+            let patches = vec![(volume.block * BLOCK_SIZE as u64 + PATH_TABLE_SIZE as u64, both_endian_u32(size as u32))];
+            self.problem(description, Some(Fix { change, patches, blocks: None }));
+        }
+        for (block, little_endian, kind) in tables {
+            if block == 0 {
+                continue;
+            }
+            let expected = path_table(directories, little_endian);
+            let region = (space.max(blocks_for(size)) as usize) * BLOCK_SIZE;
+            let found = self.read(block as u64 * BLOCK_SIZE as u64, expected.len()).ok();
+            if found.as_ref() == Some(&expected) && size == recorded_size as u64 {
+                continue;
+            }
+            let mut bytes = expected;
+            bytes.resize(region, 0);
+            let change = format!("Rebuilt the {} path table of the {} descriptor in block {} from its {} directories", kind, volume.name, block, directories.len());
+            let description = format!("The {} path table of the {} descriptor (block {}) doesn't list its directories as they are", kind, volume.name, block);
+            self.problem(description, Some(Fix { change, patches: vec![(block as u64 * BLOCK_SIZE as u64, bytes)], blocks: None }));
+        }
+        Ok(())
+    }
+
+    fn in_use(&self, block: u64) -> bool {
+        self.used.iter().any(|&(start, count)| block >= start && block < start + count)
+    }
+}
+
+// The problems of an image, and the volume size its primary descriptor records
+pub fn check(location: &Path) -> io::Result<(Vec<Problem>, u32)> {
+    let source = open_source(location)?;
+    let found = source.size()?;
+    let mut scan = Scan { source, problems: Vec::new(), used: Vec::new() };
+    let mut first = vec![0u8; BLOCK_SIZE];
+    scan.source.read_exact_at(SYSTEM_AREA_BLOCKS as u64 * BLOCK_SIZE as u64, &mut first).map_err(|e| io::Error::new(e.kind(), format!("the image ends before its volume descriptors ({})", e)))?;
+    if first[9..14] == *CDROM {
+        let mut reader = IsoReader::open(scan.source)?;
+        let problems = reader.truncation()?.map(|truncation| Problem { description: format!("The image is truncated: {}", truncation), fix: None });
+        return Ok((problems.into_iter().collect(), reader.pvd.volume_space_size));
+    }
+
+    // The descriptor set, up to its terminator
+    let mut volumes = Vec::new();
+    let mut block = SYSTEM_AREA_BLOCKS as u64;
+    let terminated = loop {
+        let Ok(data) = scan.read(block * BLOCK_SIZE as u64, BLOCK_SIZE) else { break false };
+        if data[1..6] != *CD001 {
+            break false;
+        }
+        match data[0] {
+            VOLUME_DESCRIPTOR_TERMINATOR => break true,
+            PRIMARY_VOLUME_DESCRIPTOR | SUPPLEMENTARY_VOLUME_DESCRIPTOR => {
+                let name = match data[0] {
+                    PRIMARY_VOLUME_DESCRIPTOR => "primary",
+                    _ if data[6] == 2 => "enhanced",
+                    _ if JOLIET_ESCAPES.iter().any(|escape| data[88..91] == **escape) => "Joliet",
+                    _ => "supplementary",
+                };
+                volumes.push(Volume { block, data, name });
+            }
+            BOOT_RECORD if data[7..7 + EL_TORITO.len()] == *EL_TORITO => scan.used.push((u32::from_le_bytes(data[71..75].try_into().unwrap()) as u64, 1)),
+            _ => {}
+        }
+        block += 1;
+    };
+    let Some(primary) = volumes.iter().position(|volume| volume.name == "primary") else {
+        return Err(io::Error::new(ErrorKind::InvalidData, "the image has no primary volume descriptor"));
+    };
+    let mut recorded = 0;
+    for (index, volume) in volumes.iter().enumerate() {
+        let values = scan.both_endian(&volume.data, volume.block * BLOCK_SIZE as u64, &DESCRIPTOR_FIELDS, &format!("the {} descriptor", volume.name));
+        if index == primary {
+            recorded = values[0];
+        }
+        let directories = scan.hierarchy(volume);
+        scan.path_tables(volume, &directories, values[4])?;
+    }
+
+    if !terminated {
+        let fix = (!scan.in_use(block) && (block + 1) * BLOCK_SIZE as u64 <= found).then(|| {
+            let mut terminator = vec![VOLUME_DESCRIPTOR_TERMINATOR];
+            terminator.extend_from_slice(CD001);
+            terminator.push(1);
+            terminator.resize(BLOCK_SIZE, 0);
+            Fix { change: format!("Wrote a volume descriptor set terminator to block {}", block), patches: vec![(block * BLOCK_SIZE as u64, terminator)], blocks: None }
+        });
+        scan.problem(format!("The volume descriptor set has no terminator (block {} should hold it)", block), fix);
+    }
+    scan.used.push((SYSTEM_AREA_BLOCKS as u64, block + 1 - SYSTEM_AREA_BLOCKS as u64));
+
+    // The volume size against what the tree takes up and what there is
+    let needed = scan.used.iter().map(|&(start, count)| start + count).max().unwrap_or(0);
+    let present = found / BLOCK_SIZE as u64;
+    let set_size = |blocks: u64, change: String| Fix {
+        change,
+        patches: volumes.iter().map(|volume| (volume.block * BLOCK_SIZE as u64 + VOLUME_SPACE_SIZE as u64, both_endian_u32(blocks as u32))).collect(),
+        blocks: Some(blocks),
+    };
+    if let Some(truncation) = Truncation::of_size(recorded, found) {
+        let description = format!("The image is truncated: {}", truncation);
+        if needed <= present {
+            let fix = set_size(present, format!("Set the volume space size to the {} blocks there are (was {})", present, recorded));
+            scan.problem(format!("{}; only blocks nothing uses are gone", description), Some(fix));
+        } else {
+            scan.problem(description, None);
+        }
+    } else if (recorded as u64) < needed {
+        let description = format!("The volume space size is {} blocks, but the tree takes up {}", recorded, needed);
+        let fix = (needed <= present).then(|| set_size(needed, format!("Set the volume space size to {} blocks (was {})", needed, recorded)));
+        scan.problem(description, fix);
+    } else if let Some(last) = recorded.checked_sub(1) {
+        if let Err(e) = scan.read(last as u64 * BLOCK_SIZE as u64, BLOCK_SIZE) {
+            scan.problem(format!("The image is truncated: its last block, {}, could not be read ({})", last, e), None);
+        }
+    }
+    Ok((scan.problems, recorded))
+}
+
+// makeiso verify <image.iso>
+pub fn verify(args: &[String]) -> io::Result<()> {
+    let [location] = args else {
+        return Err(io::Error::new(ErrorKind::InvalidInput, USAGE));
+    };
+    let location = Path::new(location);
+    let (problems, blocks) = check(location)?;
+    for problem in &problems {
+        println!("! {}{}", problem, if problem.fix.is_some() { " (makeiso repair can fix this)" } else { "" });
+    }
+    if problems.is_empty() {
+        println!("No problems found in {} ({} blocks)", location.display(), blocks);
+        return Ok(());
+    }
+    Err(io::Error::new(ErrorKind::InvalidData, format!("{} has {} problem{}", location.display(), problems.len(), if problems.len() == 1 { "" } else { "s" })))
+}
@@ -0,0 +1,167 @@
+// Windows file metadata (--win-metadata): the attributes of each source file and directory
+// (read-only, hidden, system, archive, not content indexed), its creation time and its
+// alternate data streams, none of which ISO 9660 has room for, are recorded in a hidden
+// WINMETA.TXT at the root of the image. extract --win-metadata puts them back on NTFS.
+//
+// The file is laid out like XATTRS.TXT (see xattr.rs), with the creation time as a FILETIME
+// (100 ns ticks since 1601) and each stream, :Zone.Identifier say, in hex:
+//
+//     # file: Downloads/setup.exe
+//     attributes="archive,hidden"
+//     created="133590528000000000"
+//     stream:Zone.Identifier=0x5b5a6f6e655472616e736665725d0d0a...
+//
+// Only Windows has any of it; elsewhere nothing is recorded and nothing restored.
+
+use std::io;
+use std::path::Path;
+
+use super::xattr::{self, Attributes};
+use super::{events, ImageTree, FLAG_HIDDEN};
+
+pub const WINMETA_FILE: &str = "WINMETA.TXT";
+
+// The attribute bits that can be set again, by the names WINMETA.TXT gives them
+#[cfg_attr(not(windows), allow(dead_code))]
+const ATTRIBUTES: [(u32, &str); 5] = [(0x1, "readonly"), (0x2, "hidden"), (0x4, "system"), (0x20, "archive"), (0x2000, "not-content-indexed")];
+
+// The metadata of a file or directory to record
+#[cfg(windows)]
+pub fn read(path: &Path) -> io::Result<Attributes> {
+    use std::os::windows::fs::MetadataExt;
+
+    let metadata = std::fs::metadata(path)?;
+    let mut recorded = Vec::new();
+    let names: Vec<&str> = ATTRIBUTES.iter().filter(|&&(bit, _)| metadata.file_attributes() & bit != 0).map(|&(_, name)| name).collect();
+    if !names.is_empty() {
+        recorded.push(("attributes".to_string(), names.join(",").into_bytes()));
+    }
+    recorded.push(("created".to_string(), metadata.creation_time().to_string().into_bytes()));
+    for name in streams(path)? {
+        let contents = std::fs::read(stream_path(path, &name)).map_err(|e| io::Error::new(e.kind(), format!("could not read the stream {} of {}: {}", name, path.display(), e)))?;
+        recorded.push((format!("stream:{}", name), contents));
+    }
+    Ok(recorded)
+}
+
+#[cfg(not(windows))]
+pub fn read(_path: &Path) -> io::Result<Attributes> {
+    Ok(Vec::new())
+}
+
+// The names of the alternate data streams of a file or directory (not the unnamed one)
+#[cfg(windows)]
+fn streams(path: &Path) -> io::Result<Vec<String>> {
+    use std::os::windows::ffi::OsStrExt;
+    use windows::core::PCWSTR;
+    use windows::Win32::Storage::FileSystem::{FindClose, FindFirstStreamW, FindNextStreamW, FindStreamInfoStandard, WIN32_FIND_STREAM_DATA};
+
+    let wide: Vec<u16> = path.as_os_str().encode_wide().chain([0]).collect();
+    let mut data = WIN32_FIND_STREAM_DATA::default();
+    // SAFETY: the name is NUL terminated and data is the structure FindStreamInfoStandard fills
+    let find = match unsafe { FindFirstStreamW(PCWSTR(wide.as_ptr()), FindStreamInfoStandard, &mut data as *mut _ as *mut _, 0) } {
+        Ok(find) => find,
+        Err(e) if e.code().0 & 0xffff == 38 => return Ok(Vec::new()), // ERROR_HANDLE_EOF: no streams at all
+        Err(e) => return Err(io::Error::from_raw_os_error(e.code().0 & 0xffff)),
+    };
+    let mut names = Vec::new();
+    loop {
+        // Names come as :name:$DATA, the unnamed stream as ::$DATA
+        let length = data.cStreamName.iter().position(|&unit| unit == 0).unwrap_or(data.cStreamName.len());
+        let full = String::from_utf16_lossy(&data.cStreamName[..length]);
+        if let Some(name) = full.strip_prefix(':').and_then(|name| name.strip_suffix(":$DATA")).filter(|name| !name.is_empty()) {
+            names.push(name.to_string());
+        }
+        // SAFETY: find is the open search and data is as above
+        if unsafe { FindNextStreamW(find, &mut data as *mut _ as *mut _) }.is_err() {
+            break;
+        }
+    }
+    // SAFETY: find is the open search, closed once
+    let _ = unsafe { FindClose(find) };
+    Ok(names)
+}
+
+#[cfg(windows)]
+fn stream_path(path: &Path, name: &str) -> std::path::PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(":");
+    path.push(name);
+    path.into()
+}
+
+// Put the recorded metadata back on an extracted file or directory; returns how many parts of
+// it couldn't be set. Streams go first and the attributes last, read-only ones included.
+#[cfg(windows)]
+pub fn restore(target: &Path, recorded: Option<&Attributes>) -> usize {
+    use std::fs::{FileTimes, OpenOptions};
+    use std::os::windows::ffi::OsStrExt;
+    use std::os::windows::fs::{FileTimesExt, OpenOptionsExt};
+    use std::time::{Duration, UNIX_EPOCH};
+    use windows::core::PCWSTR;
+    use windows::Win32::Storage::FileSystem::{SetFileAttributesW, FILE_FLAGS_AND_ATTRIBUTES};
+
+    const FILE_WRITE_ATTRIBUTES: u32 = 0x100;
+    const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x0200_0000; // Directories open too
+    const UNIX_EPOCH_TICKS: u64 = 116_444_736_000_000_000;
+
+    let mut failed = 0;
+    let mut fail = |what: &str, e: io::Error| {
+        eprintln!("Could not set the {} of {}: {}", what, target.display(), e);
+        failed += 1;
+    };
+    let recorded = recorded.map_or(&[][..], Vec::as_slice);
+    for (name, value) in recorded {
+        if let Some(stream) = name.strip_prefix("stream:") {
+            if let Err(e) = std::fs::write(stream_path(target, stream), value) {
+                fail(&format!("stream {}", stream), e);
+            }
+        }
+    }
+    let text = |key: &str| recorded.iter().find(|(name, _)| name == key).map(|(_, value)| String::from_utf8_lossy(value).into_owned());
+    if let Some(ticks) = text("created").and_then(|ticks| ticks.parse::<u64>().ok()) {
+        let created = UNIX_EPOCH + Duration::from_nanos(ticks.saturating_sub(UNIX_EPOCH_TICKS).saturating_mul(100));
+        let set = OpenOptions::new().access_mode(FILE_WRITE_ATTRIBUTES).custom_flags(FILE_FLAG_BACKUP_SEMANTICS).open(target).and_then(|file| file.set_times(FileTimes::new().set_created(created)));
+        if let Err(e) = set {
+            fail("creation time", e);
+        }
+    }
+    if let Some(names) = text("attributes") {
+        let bits = names.split(',').filter_map(|name| ATTRIBUTES.iter().find(|(_, known)| *known == name)).fold(0, |bits, (bit, _)| bits | bit);
+        let wide: Vec<u16> = target.as_os_str().encode_wide().chain([0]).collect();
+        // SAFETY: the name is NUL terminated
+        if let Err(e) = unsafe { SetFileAttributesW(PCWSTR(wide.as_ptr()), FILE_FLAGS_AND_ATTRIBUTES(bits)) } {
+            fail("attributes", io::Error::from_raw_os_error(e.code().0 & 0xffff));
+        }
+    }
+    failed
+}
+
+#[cfg(not(windows))]
+pub fn restore(_target: &Path, _recorded: Option<&Attributes>) -> usize {
+    0
+}
+
+// Add the hidden WINMETA.TXT with what the scan recorded
+pub fn add_file(tree: &mut ImageTree) {
+    if tree.win_metadata.is_empty() {
+        if !cfg!(windows) {
+            events::warn("--win-metadata only records anything on Windows");
+        }
+        return;
+    }
+    let mut text = String::new();
+    for (path, recorded) in &tree.win_metadata {
+        text.push_str(&format!("# file: {}\n", xattr::escape_path(path)));
+        for (name, value) in recorded {
+            if name.starts_with("stream:") {
+                text.push_str(&format!("{}=0x{}\n", name, value.iter().map(|byte| format!("{:02x}", byte)).collect::<String>()));
+            } else {
+                text.push_str(&format!("{}=\"{}\"\n", name, String::from_utf8_lossy(value)));
+            }
+        }
+        text.push('\n');
+    }
+    tree.add_generated(0, WINMETA_FILE, text.into_bytes());
+    tree.record_flags.entry(0).or_default().insert(WINMETA_FILE.to_string(), FLAG_HIDDEN);
+}
@@ -0,0 +1,158 @@
+// Write-behind for the image (--pipeline-depth N): what is written is gathered into 1 MiB
+// buffers and handed to a thread that writes them out in order, so reading the next file
+// overlaps writing (and hashing and encrypting, which the sinks do as they write) the last
+// one. At most N buffers wait for the writer; the ones it is done with go back to a pool, so
+// nothing is allocated once the pipeline is full and a slow output stalls the reading
+// instead of filling memory.
+//
+// Seeks go through the queue too and the position is kept on this side. Contents can't be
+// copied into the output directly (copy_file_range and reflinks write at the file's own
+// position, past the queue), so files are read and written as with a non-local output.
+
+use std::io::{self, ErrorKind, Seek, SeekFrom, Write};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
+use std::thread::{self, JoinHandle};
+
+use super::backend::ImageSink;
+use super::ImageOutput;
+
+const BUFFER_SIZE: usize = 1024 * 1024;
+
+enum Operation {
+    Data(Vec<u8>),
+    Seek(u64),
+    Flush(Sender<io::Result<()>>),
+    Finish(Sender<io::Result<()>>),
+}
+
+pub struct WriteBehind {
+    queue: Option<SyncSender<Operation>>,
+    pool: Receiver<Vec<u8>>,
+    buffer: Vec<u8>,
+    position: u64,
+    writer: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl WriteBehind {
+    // Put a writer thread behind a sink nothing was written to yet
+    pub fn new(mut sink: Box<dyn ImageSink>, depth: usize) -> io::Result<WriteBehind> {
+        let (queue, operations) = mpsc::sync_channel::<Operation>(depth);
+        let (recycle, pool) = mpsc::channel();
+        let writer = thread::Builder::new().name("makeiso-writer".to_string()).spawn(move || {
+            for operation in operations {
+                match operation {
+                    Operation::Data(buffer) => {
+                        sink.write_all(&buffer)?;
+                        let _ = recycle.send(buffer);
+                    }
+                    Operation::Seek(position) => {
+                        sink.seek(SeekFrom::Start(position))?;
+                    }
+                    Operation::Flush(done) => {
+                        let _ = done.send(sink.flush());
+                    }
+                    Operation::Finish(done) => {
+                        let _ = done.send(sink.finish());
+                        break;
+                    }
+                }
+            }
+            Ok(())
+        })?;
+        Ok(WriteBehind { queue: Some(queue), pool, buffer: Vec::with_capacity(BUFFER_SIZE), position: 0, writer: Some(writer) })
+    }
+
+    fn send(&mut self, operation: Operation) -> io::Result<()> {
+        let sent = self.queue.as_ref().is_some_and(|queue| queue.send(operation).is_ok());
+        if !sent {
+            return Err(self.writer_error());
+        }
+        Ok(())
+    }
+
+    // Hand the gathered bytes to the writer, taking an empty buffer from the pool
+    fn send_buffer(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let next = self.pool.try_recv().unwrap_or_else(|_| Vec::with_capacity(BUFFER_SIZE));
+        let full = std::mem::replace(&mut self.buffer, next);
+        self.buffer.clear();
+        self.send(Operation::Data(full))
+    }
+
+    // Run an operation on the writer and wait for its outcome
+    fn round_trip(&mut self, operation: impl FnOnce(Sender<io::Result<()>>) -> Operation) -> io::Result<()> {
+        self.send_buffer()?;
+        let (done, outcome) = mpsc::channel();
+        self.send(operation(done))?;
+        outcome.recv().unwrap_or_else(|_| Err(self.writer_error()))
+    }
+
+    // Why the writer stopped: the error it ran into
+    fn writer_error(&mut self) -> io::Error {
+        self.queue = None;
+        match self.writer.take().map(JoinHandle::join) {
+            Some(Ok(Err(e))) => e,
+            Some(Err(_)) => io::Error::other("the image writer thread panicked"),
+            _ => io::Error::new(ErrorKind::BrokenPipe, "the image writer stopped"),
+        }
+    }
+}
+
+impl Write for WriteBehind {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let take = data.len().min(BUFFER_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&data[..take]);
+        self.position += take as u64;
+        if self.buffer.len() == BUFFER_SIZE {
+            self.send_buffer()?;
+        }
+        Ok(take)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.round_trip(Operation::Flush)
+    }
+}
+
+impl Seek for WriteBehind {
+    fn seek(&mut self, to: SeekFrom) -> io::Result<u64> {
+        let position = match to {
+            SeekFrom::Start(position) => Some(position),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+            SeekFrom::End(_) => return Err(io::Error::new(ErrorKind::Unsupported, "the write-behind queue can't seek from the end")),
+        };
+        let position = position.ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "seek to a negative position"))?;
+        if position != self.position {
+            self.send_buffer()?;
+            self.send(Operation::Seek(position))?;
+            self.position = position;
+        }
+        Ok(position)
+    }
+}
+
+impl ImageOutput for WriteBehind {}
+
+impl ImageSink for WriteBehind {
+    fn finish(&mut self) -> io::Result<()> {
+        self.round_trip(Operation::Finish)?;
+        self.queue = None;
+        match self.writer.take().map(JoinHandle::join) {
+            Some(Err(_)) => Err(io::Error::other("the image writer thread panicked")),
+            Some(Ok(finished)) => finished,
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for WriteBehind {
+    fn drop(&mut self) {
+        // Unfinished (the run failed): the writer writes out what is queued and stops
+        self.queue = None;
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
@@ -0,0 +1,233 @@
+// X25519 (RFC 7748), the key agreement behind age recipients
+//
+// Field elements are five 51-bit limbs; products are accumulated in u128. Nothing branches on
+// secret data: the ladder swaps with masks.
+
+pub const BASEPOINT: [u8; 32] = {
+    let mut point = [0u8; 32];
+    point[0] = 9;
+    point
+};
+
+const MASK: u64 = (1 << 51) - 1;
+
+#[derive(Clone, Copy)]
+struct Element([u64; 5]);
+
+impl Element {
+    const ZERO: Element = Element([0; 5]);
+    const ONE: Element = Element([1, 0, 0, 0, 0]);
+
+    fn from_bytes(bytes: &[u8; 32]) -> Element {
+        let load = |at: usize| u64::from_le_bytes(bytes[at..at + 8].try_into().expect("8 bytes"));
+        Element([
+            load(0) & MASK,
+            (load(6) >> 3) & MASK,
+            (load(12) >> 6) & MASK,
+            (load(19) >> 1) & MASK,
+            (load(24) >> 12) & MASK, // The top bit is ignored
+        ])
+    }
+
+    fn to_bytes(self) -> [u8; 32] {
+        let mut h = self.carry().carry().0;
+        // h is below 2p now; take p off if it's at least p
+        let mut q = (h[0] + 19) >> 51;
+        for limb in &h[1..] {
+            q = (limb + q) >> 51;
+        }
+        h[0] += 19 * q;
+        for i in 1..5 {
+            h[i] += h[i - 1] >> 51;
+            h[i - 1] &= MASK;
+        }
+        h[4] &= MASK;
+
+        let mut bytes = [0u8; 32];
+        let mut accumulator: u128 = 0;
+        let mut bits = 0;
+        let mut at = 0;
+        for limb in h {
+            accumulator |= (limb as u128) << bits;
+            bits += 51;
+            while bits >= 8 && at < 32 {
+                bytes[at] = accumulator as u8;
+                accumulator >>= 8;
+                bits -= 8;
+                at += 1;
+            }
+        }
+        if at < 32 {
+            bytes[at] = accumulator as u8;
+        }
+        bytes
+    }
+
+    fn carry(self) -> Element {
+        let mut h = self.0;
+        for i in 1..5 {
+            h[i] += h[i - 1] >> 51;
+            h[i - 1] &= MASK;
+        }
+        h[0] += 19 * (h[4] >> 51);
+        h[4] &= MASK;
+        Element(h)
+    }
+
+    fn add(self, other: Element) -> Element {
+        let mut h = self.0;
+        for (limb, other) in h.iter_mut().zip(other.0) {
+            *limb += other;
+        }
+        Element(h)
+    }
+
+    // Adds 2p first so nothing goes below zero
+    fn sub(self, other: Element) -> Element {
+        let two_p = [0xFFFFFFFFFFFDA, 0xFFFFFFFFFFFFE, 0xFFFFFFFFFFFFE, 0xFFFFFFFFFFFFE, 0xFFFFFFFFFFFFE];
+        let mut h = self.0;
+        for ((limb, other), extra) in h.iter_mut().zip(other.0).zip(two_p) {
+            *limb = *limb + extra - other;
+        }
+        Element(h).carry()
+    }
+
+    fn mul(self, other: Element) -> Element {
+        let a = self.0.map(u128::from);
+        let b = other.0.map(u128::from);
+        let b19 = b.map(|limb| limb * 19);
+        let t = [
+            a[0] * b[0] + a[1] * b19[4] + a[2] * b19[3] + a[3] * b19[2] + a[4] * b19[1],
+            a[0] * b[1] + a[1] * b[0] + a[2] * b19[4] + a[3] * b19[3] + a[4] * b19[2],
+            a[0] * b[2] + a[1] * b[1] + a[2] * b[0] + a[3] * b19[4] + a[4] * b19[3],
+            a[0] * b[3] + a[1] * b[2] + a[2] * b[1] + a[3] * b[0] + a[4] * b19[4],
+            a[0] * b[4] + a[1] * b[3] + a[2] * b[2] + a[3] * b[1] + a[4] * b[0],
+        ];
+        let mut h = [0u64; 5];
+        let mut carry: u128 = 0;
+        for i in 0..5 {
+            let value = t[i] + carry;
+            h[i] = value as u64 & MASK;
+            carry = value >> 51;
+        }
+        h[0] += carry as u64 * 19;
+        h[1] += h[0] >> 51;
+        h[0] &= MASK;
+        Element(h)
+    }
+
+    fn square(self) -> Element {
+        self.mul(self)
+    }
+
+    // a^(p-2), with p - 2 = 2^255 - 21
+    fn invert(self) -> Element {
+        let mut result = Element::ONE;
+        for bit in (0..255).rev() {
+            result = result.square();
+            let set = match bit {
+                5.. => true,
+                _ => (0b01011 >> bit) & 1 == 1, // The low bits of 2^255 - 21 are 01011
+            };
+            if set {
+                result = result.mul(self);
+            }
+        }
+        result
+    }
+
+    // Swap a and b when swap is 1, without branching
+    fn swap(a: &mut Element, b: &mut Element, swap: u64) {
+        let mask = swap.wrapping_neg();
+        for (x, y) in a.0.iter_mut().zip(b.0.iter_mut()) {
+            let t = mask & (*x ^ *y);
+            *x ^= t;
+            *y ^= t;
+        }
+    }
+}
+
+// The scalar multiple of a point, both as 32 little-endian bytes
+pub fn x25519(scalar: &[u8; 32], point: &[u8; 32]) -> [u8; 32] {
+    let mut k = *scalar;
+    k[0] &= 248;
+    k[31] &= 127;
+    k[31] |= 64;
+
+    let x1 = Element::from_bytes(point);
+    let a24 = Element([121665, 0, 0, 0, 0]);
+    let (mut x2, mut z2, mut x3, mut z3) = (Element::ONE, Element::ZERO, x1, Element::ONE);
+    let mut swap = 0;
+    for bit in (0..255).rev() {
+        let k_bit = ((k[bit / 8] >> (bit % 8)) & 1) as u64;
+        swap ^= k_bit;
+        Element::swap(&mut x2, &mut x3, swap);
+        Element::swap(&mut z2, &mut z3, swap);
+        swap = k_bit;
+
+        let a = x2.add(z2);
+        let aa = a.square();
+        let b = x2.sub(z2);
+        let bb = b.square();
+        let e = aa.sub(bb);
+        let c = x3.add(z3);
+        let d = x3.sub(z3);
+        let da = d.mul(a);
+        let cb = c.mul(b);
+        x3 = da.add(cb).square();
+        z3 = x1.mul(da.sub(cb).square());
+        x2 = aa.mul(bb);
+        z2 = e.mul(aa.add(a24.mul(e)));
+    }
+    Element::swap(&mut x2, &mut x3, swap);
+    Element::swap(&mut z2, &mut z3, swap);
+    x2.mul(z2.invert()).to_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{x25519, BASEPOINT};
+
+    fn bytes(hex: &str) -> [u8; 32] {
+        let bytes: Vec<u8> = (0..hex.len()).step_by(2).map(|at| u8::from_str_radix(&hex[at..at + 2], 16).unwrap()).collect();
+        bytes.try_into().unwrap()
+    }
+
+    // RFC 7748, section 5.2
+    #[test]
+    fn scalar_multiples() {
+        let vectors = [
+            ("a546e36bf0527c9d3b16154b82465edd62144c0ac1fc5a18506a2244ba449ac4", "e6db6867583030db3594c1a424b15f7c726624ec26b3353b10a903a6d0ab1c4c", "c3da55379de9c6908e94ea4df28d084f32eccf03491c71f754b4075577a28552"),
+            ("4b66e9d4d1b4673c5ad22691957d6af5c11b6421e0ea01d42ca4169e7918ba0d", "e5210f12786811d3f4b7959d0538ae2c31dbe7106fc03c3efc4cd549c715a493", "95cbde9476e8907d7aade45cb4b873f88b595a68799fa152e6f8f7647aac7957"),
+        ];
+        for (scalar, point, expected) in vectors {
+            assert_eq!(x25519(&bytes(scalar), &bytes(point)), bytes(expected));
+        }
+    }
+
+    // RFC 7748, section 5.2: the result fed back as the next scalar, the scalar as the next point
+    #[test]
+    fn iterated() {
+        let (mut k, mut u) = (BASEPOINT, BASEPOINT);
+        for round in 1..=1000 {
+            (k, u) = (x25519(&k, &u), k);
+            match round {
+                1 => assert_eq!(k, bytes("422c8e7a6227d7bca1350b3e2bb7279f7897b87bb6854b783c60e80311ae3079")),
+                1000 => assert_eq!(k, bytes("684cf59ba83309552800ef566f2f4d3c1c3887c49360e3875f2eb94d99532c51")),
+                _ => {}
+            }
+        }
+    }
+
+    // RFC 7748, section 6.1
+    #[test]
+    fn key_agreement() {
+        let alice = bytes("77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a");
+        let bob = bytes("5dab087e624a8a4b79e17f8b83800ee66f3bb1292618b6fd1c2f8b27ff88e0eb");
+        assert_eq!(x25519(&alice, &BASEPOINT), bytes("8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a"));
+        assert_eq!(x25519(&bob, &BASEPOINT), bytes("de9edb7d7b7dc1b4d35b61c2ece435373f8343c85b78674dadfc7e146f882b4f"));
+        let shared = bytes("4a5d9d5ba4ce2de1728e3bf480350f25e07e21c947d19e3376f09b3c1e161742");
+        assert_eq!(x25519(&alice, &x25519(&bob, &BASEPOINT)), shared);
+        assert_eq!(x25519(&bob, &x25519(&alice, &BASEPOINT)), shared);
+    }
+}
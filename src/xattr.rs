@@ -0,0 +1,187 @@
+// Extended attributes (--xattrs): the attributes of the source files, POSIX ACLs
+// (system.posix_acl_*) and SELinux labels (security.selinux) included, are recorded in a
+// hidden XATTRS.TXT at the root of the image, and extract --xattrs puts them back
+//
+// The file is in the format `getfattr --dump --encoding=hex` writes, so it can also be
+// restored by hand with `setfattr --restore=XATTRS.TXT` from the extracted root:
+//
+//     # file: boot/grub/grub.cfg
+//     security.selinux=0x73797374656d5f753a6f626a6563745f723a626f6f745f743a733000
+//
+// Only Linux has the system calls; elsewhere nothing is recorded and nothing restored.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+use super::{ImageTree, FLAG_HIDDEN};
+
+pub const XATTR_FILE: &str = "XATTRS.TXT";
+
+pub type Attributes = Vec<(String, Vec<u8>)>;
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::ffi::{c_char, c_int, c_void};
+
+    extern "C" {
+        pub fn listxattr(path: *const c_char, list: *mut c_char, size: usize) -> isize;
+        pub fn getxattr(path: *const c_char, name: *const c_char, value: *mut c_void, size: usize) -> isize;
+        pub fn setxattr(path: *const c_char, name: *const c_char, value: *const c_void, size: usize, flags: c_int) -> c_int;
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn c_string(text: &[u8]) -> io::Result<std::ffi::CString> {
+    std::ffi::CString::new(text).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "names can't contain NUL bytes"))
+}
+
+// The extended attributes of a file or directory, sorted by name; none on file systems
+// without them
+#[cfg(target_os = "linux")]
+pub fn read(path: &Path) -> io::Result<Attributes> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = c_string(path.as_os_str().as_bytes())?;
+    // Sizes are asked for first, and asked again if they grew in between
+    let query = |fill: &mut dyn FnMut(*mut u8, usize) -> isize| -> io::Result<Vec<u8>> {
+        loop {
+            let size = fill(std::ptr::null_mut(), 0);
+            if size < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut buffer = vec![0u8; size as usize];
+            let got = fill(buffer.as_mut_ptr(), buffer.len());
+            if got >= 0 {
+                buffer.truncate(got as usize);
+                return Ok(buffer);
+            }
+            let error = io::Error::last_os_error();
+            if error.raw_os_error() != Some(34) {
+                return Err(error); // Anything but ERANGE
+            }
+        }
+    };
+
+    // SAFETY: the path is NUL terminated and the buffers are as long as the sizes given
+    let names = match query(&mut |buffer, size| unsafe { sys::listxattr(path.as_ptr(), buffer.cast(), size) }) {
+        Ok(names) => names,
+        Err(e) if e.raw_os_error() == Some(95) => return Ok(Vec::new()), // ENOTSUP
+        Err(e) => return Err(e),
+    };
+    let mut attributes = Vec::new();
+    for name in names.split(|&byte| byte == 0).filter(|name| !name.is_empty()) {
+        let c_name = c_string(name)?;
+        // SAFETY: as above
+        let value = query(&mut |buffer, size| unsafe { sys::getxattr(path.as_ptr(), c_name.as_ptr(), buffer.cast(), size) })?;
+        attributes.push((String::from_utf8_lossy(name).into_owned(), value));
+    }
+    attributes.sort();
+    Ok(attributes)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn read(_path: &Path) -> io::Result<Attributes> {
+    Ok(Vec::new())
+}
+
+// Set one extended attribute of a file or directory
+#[cfg(target_os = "linux")]
+pub fn write(path: &Path, name: &str, value: &[u8]) -> io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = c_string(path.as_os_str().as_bytes())?;
+    let name = c_string(name.as_bytes())?;
+    // SAFETY: both strings are NUL terminated and the value is as long as the size given
+    if unsafe { sys::setxattr(path.as_ptr(), name.as_ptr(), value.as_ptr().cast(), value.len(), 0) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn write(_path: &Path, _name: &str, _value: &[u8]) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "extended attributes are not supported on this platform"))
+}
+
+// Add the hidden XATTRS.TXT listing the attributes collected during the scan
+pub fn add_file(tree: &mut ImageTree) {
+    let mut text = String::new();
+    for (path, attributes) in &tree.xattrs {
+        text.push_str(&format!("# file: {}\n", escape_path(path)));
+        for (name, value) in attributes {
+            text.push_str(&format!("{}=0x{}\n", name, value.iter().map(|byte| format!("{:02x}", byte)).collect::<String>()));
+        }
+        text.push('\n');
+    }
+    tree.add_generated(0, XATTR_FILE, text.into_bytes());
+    tree.record_flags.entry(0).or_default().insert(XATTR_FILE.to_string(), FLAG_HIDDEN);
+}
+
+// Attributes by image path ("/a/b", the root is "/") from the contents of an XATTRS.TXT
+pub fn parse(text: &str) -> HashMap<String, Attributes> {
+    let mut files = HashMap::new();
+    let mut current: Option<&mut Attributes> = None;
+    for line in text.lines() {
+        if let Some(path) = line.strip_prefix("# file: ") {
+            let path = unescape_path(path);
+            let path = path.trim_start_matches("./").trim_matches('/');
+            let path = if path == "." || path.is_empty() { "/".to_string() } else { format!("/{}", path) };
+            current = Some(files.entry(path).or_default());
+            continue;
+        }
+        let (Some(attributes), Some((name, value))) = (current.as_deref_mut(), line.split_once('=')) else {
+            continue;
+        };
+        if let Some(value) = decode_value(value) {
+            attributes.push((name.to_string(), value));
+        }
+    }
+    files
+}
+
+// Values as getfattr writes them with --encoding=hex, or as quoted text
+fn decode_value(value: &str) -> Option<Vec<u8>> {
+    if let Some(hex) = value.strip_prefix("0x") {
+        return (0..hex.len()).step_by(2).map(|at| hex.get(at..at + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok())).collect();
+    }
+    value.strip_prefix('"').and_then(|value| value.strip_suffix('"')).map(|text| unescape_path(text).into_bytes())
+}
+
+// Paths in "# file:" lines are relative, with newlines, backslashes and other control
+// characters written as \ooo octal escapes
+pub fn escape_path(path: &str) -> String {
+    let path = path.trim_matches('/');
+    if path.is_empty() {
+        return ".".to_string();
+    }
+    let mut out = Vec::with_capacity(path.len());
+    for byte in path.bytes() {
+        match byte {
+            0..=0x1F | b'\\' | 0x7F => out.extend_from_slice(format!("\\{:03o}", byte).as_bytes()),
+            _ => out.push(byte),
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+// The reverse of escape_path
+fn unescape_path(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut at = 0;
+    while at < bytes.len() {
+        let octal = bytes.get(at + 1..at + 4).and_then(|digits| std::str::from_utf8(digits).ok()).and_then(|digits| u8::from_str_radix(digits, 8).ok());
+        match (bytes[at], octal) {
+            (b'\\', Some(byte)) => {
+                out.push(byte);
+                at += 4;
+            }
+            (byte, _) => {
+                out.push(byte);
+                at += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
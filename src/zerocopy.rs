@@ -0,0 +1,41 @@
+// Kernel-side copies of source files into the image (copy_file_range on Linux), so file
+// contents don't have to pass through a userspace buffer
+
+use std::fs::File;
+use std::io::{self, ErrorKind};
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::ffi::{c_int, c_uint};
+
+    extern "C" {
+        pub fn copy_file_range(fd_in: c_int, off_in: *mut i64, fd_out: c_int, off_out: *mut i64, len: usize, flags: c_uint) -> isize;
+    }
+}
+
+// Copy up to `len` bytes from the current position of `source` to the current position of
+// `output`, advancing both; returns the number of bytes copied (0 at the end of the source)
+#[cfg(target_os = "linux")]
+pub fn copy_range(source: &File, output: &File, len: usize) -> io::Result<usize> {
+    use std::os::unix::io::AsRawFd;
+
+    // SAFETY: both descriptors are open for the duration of the call; null offsets make the kernel
+    // use and update the descriptors' own file positions
+    let copied = unsafe { sys::copy_file_range(source.as_raw_fd(), std::ptr::null_mut(), output.as_raw_fd(), std::ptr::null_mut(), len, 0) };
+    if copied < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(copied as usize)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn copy_range(_source: &File, _output: &File, _len: usize) -> io::Result<usize> {
+    Err(io::Error::new(ErrorKind::Unsupported, "kernel-side copies are not supported on this platform"))
+}
+
+// Whether a failed copy means the kernel can't do it for this pair of files (old kernel,
+// different filesystems, output isn't a regular file) rather than a real I/O error
+pub fn is_unsupported(error: &io::Error) -> bool {
+    matches!(error.kind(), ErrorKind::Unsupported | ErrorKind::InvalidInput | ErrorKind::CrossesDevices | ErrorKind::PermissionDenied)
+        || error.raw_os_error() == Some(95) // EOPNOTSUPP
+}
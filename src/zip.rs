@@ -0,0 +1,470 @@
+// Zip archives as image sources and outputs, mirroring the tar bridges: from-zip plans the
+// image from the central directory and then inflates each member straight to its extent,
+// to-zip streams the contents of an image into a stored (uncompressed) archive.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use super::inflate::inflate;
+use super::reader::{open_image, IsoEntry, ImageSource, IsoReader};
+use super::tar::{archive_directory, member_components, system_time};
+use super::backend::create_output;
+use super::preallocate;
+use super::events;
+use super::unicode;
+use super::{check_file_path, new_tree, plan_image, room_needed, unix_from_utc, utc_date_time, write_image, Entry, FileEntry, ImageOptions, Progress, BLOCK_SIZE};
+
+const LOCAL_HEADER: u32 = 0x04034b50;
+const CENTRAL_HEADER: u32 = 0x02014b50;
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x06054b50;
+const ZIP64_END_OF_CENTRAL_DIRECTORY: u32 = 0x06064b50;
+const ZIP64_LOCATOR: u32 = 0x07064b50;
+const ZIP64_EXTRA: u16 = 0x0001;
+const TIMESTAMP_EXTRA: u16 = 0x5455; // Extended timestamp: modification time in seconds since the Unix epoch
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+const MAX_32: u64 = 0xFFFF_FFFF; // Values from here on are recorded in the zip64 extra field
+const MAX_ENTRIES: usize = 0xFFFF;
+const COPY_CHUNK_SIZE: usize = 1024 * 1024;
+const UNIX_HOST: u16 = 3 << 8; // "Version made by" high byte: external attributes hold a Unix mode
+const S_IFMT: u32 = 0o170000;
+const S_IFLNK: u32 = 0o120000;
+
+// CRC-32 (IEEE) as used by zip
+pub fn crc32(mut crc: u32, data: &[u8]) -> u32 {
+    crc = !crc;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn u16_at(data: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([data[at], data[at + 1]])
+}
+
+fn u32_at(data: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([data[at], data[at + 1], data[at + 2], data[at + 3]])
+}
+
+fn u64_at(data: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(data[at..at + 8].try_into().expect("8 bytes"))
+}
+
+// A member listed in the central directory
+#[derive(Debug)]
+struct ZipMember {
+    path: String,
+    method: u16,
+    encrypted: bool,
+    crc: u32,
+    compressed_size: u64,
+    size: u64,
+    header_offset: u64,
+    modified: i64,
+    mode: Option<u32>, // Unix mode, when the archive was made on a Unix host
+}
+
+// MS-DOS date and time fields to seconds since the Unix epoch (taken as UTC)
+fn dos_time(date: u16, time: u16) -> i64 {
+    let (year, month, day) = (1980 + (date >> 9) as i64, ((date >> 5) & 0xF) as u32, (date & 0x1F) as u32);
+    unix_from_utc(year, month.max(1), day.max(1), (time >> 11) as u32, ((time >> 5) & 0x3F) as u32, (time & 0x1F) as u32 * 2)
+}
+
+// Seconds since the Unix epoch to MS-DOS (date, time), clamped to the years DOS can record
+fn dos_date_time(timestamp: i64) -> (u16, u16) {
+    let (year, month, day, hour, minute, second) = utc_date_time(timestamp);
+    if year < 1980 {
+        return ((1 << 5) | 1, 0);
+    }
+    let year = year.min(2107) as u16 - 1980;
+    ((year << 9) | (month as u16) << 5 | day as u16, (hour as u16) << 11 | (minute as u16) << 5 | (second as u16 / 2))
+}
+
+// Read the central directory of an archive
+fn read_central_directory(archive: &mut File) -> io::Result<Vec<ZipMember>> {
+    // The end of central directory record is in the last 64 KiB (plus its own 22 bytes)
+    let archive_len = archive.seek(SeekFrom::End(0))?;
+    let tail_len = archive_len.min(22 + 0xFFFF);
+    let mut tail = vec![0u8; tail_len as usize];
+    archive.seek(SeekFrom::Start(archive_len - tail_len))?;
+    archive.read_exact(&mut tail)?;
+    let end = (0..tail.len().saturating_sub(21))
+        .rev()
+        .find(|&at| u32_at(&tail, at) == END_OF_CENTRAL_DIRECTORY)
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "not a zip archive (no end of central directory record)"))?;
+
+    let mut count = u16_at(&tail, end + 10) as u64;
+    let mut directory_size = u32_at(&tail, end + 12) as u64;
+    let mut directory_offset = u32_at(&tail, end + 16) as u64;
+
+    // Large archives keep the real values in the zip64 end of central directory record
+    if end >= 20 && u32_at(&tail, end - 20) == ZIP64_LOCATOR {
+        let mut record = [0u8; 56];
+        archive.seek(SeekFrom::Start(u64_at(&tail, end - 12)))?;
+        archive.read_exact(&mut record)?;
+        if u32_at(&record, 0) != ZIP64_END_OF_CENTRAL_DIRECTORY {
+            return Err(io::Error::new(ErrorKind::InvalidData, "corrupt zip64 end of central directory record"));
+        }
+        count = u64_at(&record, 32);
+        directory_size = u64_at(&record, 40);
+        directory_offset = u64_at(&record, 48);
+    }
+
+    let mut directory = vec![0u8; directory_size as usize];
+    archive.seek(SeekFrom::Start(directory_offset))?;
+    archive.read_exact(&mut directory)?;
+
+    let mut members = Vec::new();
+    let mut at = 0;
+    for _ in 0..count {
+        if at + 46 > directory.len() || u32_at(&directory, at) != CENTRAL_HEADER {
+            return Err(io::Error::new(ErrorKind::InvalidData, "corrupt zip central directory"));
+        }
+        let header = &directory[at..];
+        let (name_len, extra_len, comment_len) = (u16_at(header, 28) as usize, u16_at(header, 30) as usize, u16_at(header, 32) as usize);
+        if 46 + name_len + extra_len + comment_len > header.len() {
+            return Err(io::Error::new(ErrorKind::InvalidData, "corrupt zip central directory"));
+        }
+        let name = &header[46..46 + name_len];
+        let extra = &header[46 + name_len..46 + name_len + extra_len];
+
+        let mut member = ZipMember {
+            path: String::from_utf8_lossy(name).to_string(),
+            method: u16_at(header, 10),
+            encrypted: u16_at(header, 8) & 1 != 0,
+            crc: u32_at(header, 16),
+            compressed_size: u32_at(header, 20) as u64,
+            size: u32_at(header, 24) as u64,
+            header_offset: u32_at(header, 42) as u64,
+            modified: dos_time(u16_at(header, 14), u16_at(header, 12)),
+            mode: (u16_at(header, 4) >> 8 == 3).then(|| u32_at(header, 38) >> 16),
+        };
+        apply_extra_fields(&mut member, extra);
+
+        members.push(member);
+        at += 46 + name_len + extra_len + comment_len;
+    }
+    Ok(members)
+}
+
+// Take 64-bit sizes and offsets, and precise modification times, from the extra fields
+fn apply_extra_fields(member: &mut ZipMember, mut extra: &[u8]) {
+    while extra.len() >= 4 {
+        let (id, len) = (u16_at(extra, 0), u16_at(extra, 2) as usize);
+        let Some(data) = extra.get(4..4 + len) else {
+            break;
+        };
+        match id {
+            ZIP64_EXTRA => {
+                // Only the fields that are saturated in the header are present, in this order
+                let mut fields = data.chunks_exact(8).map(|field| u64_at(field, 0));
+                for value in [&mut member.size, &mut member.compressed_size, &mut member.header_offset] {
+                    if *value == MAX_32 {
+                        match fields.next() {
+                            Some(field) => *value = field,
+                            None => break,
+                        }
+                    }
+                }
+            }
+            TIMESTAMP_EXTRA if len >= 5 && data[0] & 1 != 0 => member.modified = u32_at(data, 1) as i32 as i64,
+            _ => {}
+        }
+        extra = &extra[4 + len..];
+    }
+}
+
+// Writer that passes data on while computing its CRC and reporting progress
+struct CheckedWriter<'a, W> {
+    output: W,
+    crc: u32,
+    progress: &'a mut Progress,
+}
+
+impl<W: Write> Write for CheckedWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.output.write(buf)?;
+        self.crc = crc32(self.crc, &buf[..written]);
+        self.progress.advance(written)?;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+// Create an image from the files and directories of a zip archive
+pub fn zip_to_iso(archive_path: &Path, iso_file_path: &Path, options: &ImageOptions) -> io::Result<()> {
+    let mut archive = File::open(archive_path)?;
+    let members = read_central_directory(&mut archive)?;
+
+    let mut tree = new_tree(options);
+    super::add_directory(&mut tree, archive_path, String::new(), 0, None, options.deep_dirs)?;
+    let mut dirs: HashMap<String, usize> = HashMap::from([(String::new(), 0)]);
+    let mut planned = Vec::new(); // Members that become files, with their source in the image tree
+
+    for member in &members {
+        let components = member_components(&member.path)?;
+        let modified = Some(system_time(member.modified));
+        let is_symlink = member.mode.is_some_and(|mode| mode & S_IFMT == S_IFLNK);
+
+        if member.path.ends_with('/') {
+            let index = archive_directory(&mut tree, &mut dirs, archive_path, &components, options)?;
+            tree.dirs[index].modified = modified;
+        } else if is_symlink {
+            events::warn(&format!("Skipping symbolic link in the archive: {}", member.path));
+        } else if member.encrypted || !matches!(member.method, STORED | DEFLATED) {
+            events::warn(&format!("Skipping {} member in the archive: {}", if member.encrypted { "encrypted" } else { "unsupported compressed" }, member.path));
+        } else if let Some((&name, parents)) = components.split_last() {
+            let parent = archive_directory(&mut tree, &mut dirs, archive_path, parents, options)?;
+            let source = archive_path.join(components.join("/"));
+            let name = unicode::normalize(name, options.normalize);
+            check_file_path(&tree, parent, &name, &source)?;
+            let size = u32::try_from(member.size)
+                .map_err(|_| io::Error::new(ErrorKind::InvalidInput, format!("{} is too large for a single ISO 9660 extent", source.display())))?;
+
+            tree.dirs[parent].entries.push(Entry::File(FileEntry {
+                name: name.into_owned(),
+                source: source.clone(),
+                size,
+                modified,
+                extent: 0,
+            }));
+            planned.push((member, source));
+        }
+    }
+    let layout = plan_image(&mut tree, options)?;
+    let size = layout.total_blocks as u64 * BLOCK_SIZE as u64;
+    preallocate::check_destination(iso_file_path, room_needed(size, options), &[archive_path.to_path_buf()])?;
+
+    // Second pass: decompress every member straight to its extent, in archive order
+    write_image(&tree, &layout, create_output(iso_file_path, size)?, iso_file_path, options, |iso_file, files, progress| {
+        let extents: HashMap<&Path, &FileEntry> = files.iter().map(|file| (file.source.as_path(), *file)).collect();
+        for (member, source) in &planned {
+            let file = extents[source.as_path()];
+
+            // The local header repeats the name and has its own extra field length
+            let mut local = [0u8; 30];
+            archive.seek(SeekFrom::Start(member.header_offset))?;
+            archive.read_exact(&mut local)?;
+            if u32_at(&local, 0) != LOCAL_HEADER {
+                return Err(io::Error::new(ErrorKind::InvalidData, format!("corrupt local header for {}", member.path)));
+            }
+            archive.seek(SeekFrom::Current(u16_at(&local, 26) as i64 + u16_at(&local, 28) as i64))?;
+
+            iso_file.seek(SeekFrom::Start(file.extent as u64 * BLOCK_SIZE as u64))?;
+            let mut output = CheckedWriter { output: &mut *iso_file, crc: 0, progress: &mut *progress };
+            let data = BufReader::with_capacity(COPY_CHUNK_SIZE, (&mut archive).take(member.compressed_size));
+            let written = match member.method {
+                DEFLATED => inflate(data, &mut output)?,
+                _ => io::copy(&mut { data }, &mut output)?,
+            };
+            if written != member.size || output.crc != member.crc {
+                return Err(io::Error::new(ErrorKind::InvalidData, format!("{} is corrupt (size or CRC mismatch)", member.path)));
+            }
+            progress.file_done();
+        }
+
+        // Continue after the furthest file contents, which need not be the last ones written
+        iso_file.seek(SeekFrom::End(0))?;
+        Ok(())
+    })
+}
+
+// A central directory entry still to be written
+struct CentralEntry {
+    path: String,
+    crc: u32,
+    size: u64,
+    offset: u64,
+    modified: i64,
+    mode: u32,
+}
+
+// Local or central header fields shared by both record kinds
+fn header_fields(entry: &CentralEntry, zip64: bool) -> Vec<u8> {
+    let (date, time) = dos_date_time(entry.modified);
+    let size = if zip64 { MAX_32 as u32 } else { entry.size as u32 };
+    let mut fields = Vec::with_capacity(26);
+    fields.extend_from_slice(&(if zip64 { 45u16 } else { 20u16 }).to_le_bytes()); // Version needed to extract
+    fields.extend_from_slice(&(1u16 << 11).to_le_bytes()); // Names are UTF-8
+    fields.extend_from_slice(&STORED.to_le_bytes());
+    fields.extend_from_slice(&time.to_le_bytes());
+    fields.extend_from_slice(&date.to_le_bytes());
+    fields.extend_from_slice(&entry.crc.to_le_bytes());
+    fields.extend_from_slice(&size.to_le_bytes()); // Compressed size
+    fields.extend_from_slice(&size.to_le_bytes());
+    fields.extend_from_slice(&(entry.path.len() as u16).to_le_bytes());
+    fields
+}
+
+// The extended timestamp extra field, followed by zip64 values when they are given
+fn extra_fields(entry: &CentralEntry, zip64: &[u64]) -> Vec<u8> {
+    let mut extra = Vec::new();
+    extra.extend_from_slice(&TIMESTAMP_EXTRA.to_le_bytes());
+    extra.extend_from_slice(&5u16.to_le_bytes());
+    extra.push(1); // Modification time present
+    extra.extend_from_slice(&(entry.modified.clamp(i32::MIN as i64, i32::MAX as i64) as i32).to_le_bytes());
+    if !zip64.is_empty() {
+        extra.extend_from_slice(&ZIP64_EXTRA.to_le_bytes());
+        extra.extend_from_slice(&(zip64.len() as u16 * 8).to_le_bytes());
+        for value in zip64 {
+            extra.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    extra
+}
+
+// Sequential writer of stored zip archives
+struct ZipWriter<W: Write> {
+    output: W,
+    written: u64,
+    entries: Vec<CentralEntry>,
+}
+
+impl<W: Write> ZipWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.output.write_all(data)?;
+        self.written += data.len() as u64;
+        Ok(())
+    }
+
+    // Write a local header; its size bytes of contents must follow
+    fn start_entry(&mut self, path: String, crc: u32, size: u64, modified: i64, mode: u32) -> io::Result<()> {
+        let entry = CentralEntry { path, crc, size, offset: self.written, modified, mode };
+        let zip64 = entry.size >= MAX_32;
+        let sizes = [entry.size, entry.size];
+        let extra = extra_fields(&entry, if zip64 { &sizes } else { &[] });
+
+        let mut header = LOCAL_HEADER.to_le_bytes().to_vec();
+        header.extend(header_fields(&entry, zip64));
+        header.extend_from_slice(&(extra.len() as u16).to_le_bytes());
+        header.extend_from_slice(entry.path.as_bytes());
+        header.extend(extra);
+        self.write(&header)?;
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    // Write the central directory and the end records
+    fn finish(mut self) -> io::Result<()> {
+        let directory_offset = self.written;
+        for entry in std::mem::take(&mut self.entries) {
+            let mut zip64 = Vec::new();
+            if entry.size >= MAX_32 {
+                zip64.extend([entry.size, entry.size]);
+            }
+            if entry.offset >= MAX_32 {
+                zip64.push(entry.offset);
+            }
+            let extra = extra_fields(&entry, &zip64);
+
+            let mut header = CENTRAL_HEADER.to_le_bytes().to_vec();
+            header.extend_from_slice(&(UNIX_HOST | 45).to_le_bytes());
+            header.extend(header_fields(&entry, entry.size >= MAX_32));
+            header.extend_from_slice(&(extra.len() as u16).to_le_bytes());
+            header.extend_from_slice(&[0; 6]); // Comment length, disk number, internal attributes
+            header.extend_from_slice(&(entry.mode << 16).to_le_bytes());
+            header.extend_from_slice(&(entry.offset.min(MAX_32) as u32).to_le_bytes());
+            header.extend_from_slice(entry.path.as_bytes());
+            header.extend(extra);
+            self.write(&header)?;
+            self.entries.push(entry);
+        }
+        let directory_size = self.written - directory_offset;
+        let count = self.entries.len();
+
+        if count >= MAX_ENTRIES || directory_offset >= MAX_32 || directory_size >= MAX_32 {
+            let zip64_offset = self.written;
+            let mut record = ZIP64_END_OF_CENTRAL_DIRECTORY.to_le_bytes().to_vec();
+            record.extend_from_slice(&44u64.to_le_bytes()); // Size of the rest of the record
+            record.extend_from_slice(&(UNIX_HOST | 45).to_le_bytes());
+            record.extend_from_slice(&45u16.to_le_bytes());
+            record.extend_from_slice(&[0; 8]); // This disk, disk with the central directory
+            record.extend_from_slice(&(count as u64).to_le_bytes());
+            record.extend_from_slice(&(count as u64).to_le_bytes());
+            record.extend_from_slice(&directory_size.to_le_bytes());
+            record.extend_from_slice(&directory_offset.to_le_bytes());
+            record.extend_from_slice(&ZIP64_LOCATOR.to_le_bytes());
+            record.extend_from_slice(&0u32.to_le_bytes());
+            record.extend_from_slice(&zip64_offset.to_le_bytes());
+            record.extend_from_slice(&1u32.to_le_bytes()); // Total number of disks
+            self.write(&record)?;
+        }
+
+        let mut end = END_OF_CENTRAL_DIRECTORY.to_le_bytes().to_vec();
+        end.extend_from_slice(&[0; 4]); // This disk, disk with the central directory
+        end.extend_from_slice(&(count.min(MAX_ENTRIES) as u16).to_le_bytes());
+        end.extend_from_slice(&(count.min(MAX_ENTRIES) as u16).to_le_bytes());
+        end.extend_from_slice(&(directory_size.min(MAX_32) as u32).to_le_bytes());
+        end.extend_from_slice(&(directory_offset.min(MAX_32) as u32).to_le_bytes());
+        end.extend_from_slice(&0u16.to_le_bytes()); // Comment length
+        self.write(&end)?;
+        self.output.flush()
+    }
+}
+
+// Write every file, directory and symbolic link of an image to a zip archive ("-" for standard output)
+pub fn iso_to_zip(iso_file_path: &Path, output_path: &Path) -> io::Result<()> {
+    let mut reader = open_image(iso_file_path)?;
+    let entries = reader.read_tree()?;
+
+    let output: Box<dyn Write> = if output_path == Path::new("-") {
+        Box::new(io::stdout().lock())
+    } else {
+        Box::new(File::create(output_path)?)
+    };
+    let mut zip = ZipWriter {
+        output: io::BufWriter::with_capacity(COPY_CHUNK_SIZE, output),
+        written: 0,
+        entries: Vec::new(),
+    };
+    let mut buffer = vec![0u8; COPY_CHUNK_SIZE];
+
+    // Depth first from the root, so directories come before their contents
+    let mut pending: Vec<usize> = entries[0].children.iter().rev().copied().collect();
+    while let Some(index) = pending.pop() {
+        let entry = &entries[index];
+        let path = entry.path.trim_start_matches('/').to_string();
+
+        // Symbolic links are stored as their target, as Info-ZIP does
+        if let Some(target) = &entry.symlink {
+            zip.start_entry(path, crc32(0, target.as_bytes()), target.len() as u64, entry.modified, entry.mode)?;
+            zip.write(target.as_bytes())?;
+        } else if entry.is_directory {
+            zip.start_entry(format!("{}/", path), 0, 0, entry.modified, entry.mode)?;
+            pending.extend(entry.children.iter().rev());
+        } else {
+            // The CRC goes in the local header, so the contents are read once to compute it
+            let mut crc = 0;
+            for_each_chunk(&mut reader, entry, &mut buffer, |chunk| {
+                crc = crc32(crc, chunk);
+                Ok(())
+            })?;
+            zip.start_entry(path, crc, entry.size as u64, entry.modified, entry.mode)?;
+            for_each_chunk(&mut reader, entry, &mut buffer, |chunk| zip.write(chunk))?;
+        }
+    }
+    zip.finish()
+}
+
+// Read the contents of a file of the image in chunks
+fn for_each_chunk<F>(reader: &mut IsoReader<ImageSource>, entry: &IsoEntry, buffer: &mut [u8], mut f: F) -> io::Result<()>
+where
+    F: FnMut(&[u8]) -> io::Result<()>,
+{
+    let mut offset = 0;
+    while offset < entry.size as u64 {
+        let bytes_read = reader.read_file_at(entry, offset, buffer)?;
+        f(&buffer[..bytes_read])?;
+        offset += bytes_read as u64;
+    }
+    Ok(())
+}
@@ -0,0 +1,133 @@
+// zsync control files: an index of an image that lets zsync bring an older copy up to date (or
+// build it from any file that shares blocks with it, such as last night's image) by fetching
+// only the blocks it lacks, with HTTP range requests against the image's URL. --zsync writes
+// one next to the image, as <image>.zsync; makeiso zsync makes one for an image that is there.
+//
+// The format is that of zsyncmake 0.6: a header, then for every block a weak rolling checksum
+// and the start of its MD4, as few bytes of each as the size of the file calls for. Blocks
+// are 2048 bytes, those of the image, so a file that didn't change matches block for block
+// wherever it moved to.
+
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use super::md4::Md4;
+use super::sha1::Sha1;
+use super::sha256;
+use super::{events, utc_date_time, BLOCK_SIZE};
+
+const USAGE: &str = "Usage: makeiso zsync [--url URL] [-o FILE] <image.iso>";
+const READ_CHUNK_SIZE: usize = 1024 * 1024;
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"]; // From 1970-01-01
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+// makeiso zsync [--url URL] [-o FILE] <image.iso>
+pub fn zsync(args: &[String]) -> io::Result<()> {
+    let (mut url, mut output, mut image) = (None, None, None);
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().cloned().ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, format!("{} requires a value", arg)));
+        match arg.as_str() {
+            "--url" => url = Some(value()?),
+            "-o" | "--output" => output = Some(PathBuf::from(value()?)),
+            _ if !arg.starts_with('-') && image.is_none() => image = Some(PathBuf::from(arg)),
+            _ => return Err(io::Error::new(ErrorKind::InvalidInput, USAGE)),
+        }
+    }
+    let image = image.ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, USAGE))?;
+    let output = output.unwrap_or_else(|| control_path(&image));
+    write_control(&image, &output, url.as_deref())?;
+    Ok(())
+}
+
+// <image>.zsync, where --zsync puts it
+pub fn control_path(image: &Path) -> PathBuf {
+    let mut path = image.as_os_str().to_owned();
+    path.push(".zsync");
+    PathBuf::from(path)
+}
+
+// Write the control file of the image at path; the URL (relative to the control file, as a
+// client resolves it) defaults to the name of the image, for the two side by side
+pub fn write_control(image: &Path, output: &Path, url: Option<&str>) -> io::Result<()> {
+    let name = image.file_name().map(|name| name.to_string_lossy().into_owned()).ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, format!("{} is not a file", image.display())))?;
+    let metadata = fs::metadata(image).map_err(|e| io::Error::new(e.kind(), format!("could not read {}: {}", image.display(), e)))?;
+    let length = metadata.len();
+    let modified = metadata.modified().ok().and_then(|time| time.duration_since(UNIX_EPOCH).ok()).map_or(0, |time| time.as_secs() as i64);
+    let (sequence, rsum_len, checksum_len) = hash_lengths(length);
+
+    // One pass: the block sums and the SHA-1 of the whole image
+    let mut file = File::open(image)?;
+    let mut sums = Vec::with_capacity(length.div_ceil(BLOCK_SIZE as u64) as usize * (rsum_len + checksum_len));
+    let mut sha = Sha1::new();
+    let mut buffer = vec![0u8; READ_CHUNK_SIZE];
+    let mut left = Vec::new(); // A block split between two reads
+    loop {
+        let read = file.read(&mut buffer)?;
+        sha.update(&buffer[..read]);
+        left.extend_from_slice(&buffer[..read]);
+        let whole = if read == 0 { left.len().div_ceil(BLOCK_SIZE) } else { left.len() / BLOCK_SIZE };
+        // The last block is summed padded with zeroes
+        left.resize(left.len().max(whole * BLOCK_SIZE), 0);
+        for block in left[..whole * BLOCK_SIZE].chunks(BLOCK_SIZE) {
+            sums.extend_from_slice(&rsum(block)[4 - rsum_len..]);
+            let mut md4 = Md4::new();
+            md4.update(block);
+            sums.extend_from_slice(&md4.finish()[..checksum_len]);
+        }
+        left.drain(..whole * BLOCK_SIZE);
+        if read == 0 {
+            break;
+        }
+    }
+
+    let (year, month, day, hour, minute, second) = utc_date_time(modified);
+    let mut control = io::BufWriter::new(File::create(output).map_err(|e| io::Error::new(e.kind(), format!("could not create {}: {}", output.display(), e)))?);
+    writeln!(control, "zsync: 0.6.2")?;
+    writeln!(control, "Filename: {}", name)?;
+    let weekday = WEEKDAYS[modified.div_euclid(86_400).rem_euclid(7) as usize];
+    writeln!(control, "MTime: {}, {:02} {} {:04} {:02}:{:02}:{:02} +0000", weekday, day, MONTHS[month as usize - 1], year, hour, minute, second)?;
+    writeln!(control, "Blocksize: {}", BLOCK_SIZE)?;
+    writeln!(control, "Length: {}", length)?;
+    writeln!(control, "Hash-Lengths: {},{},{}", sequence, rsum_len, checksum_len)?;
+    writeln!(control, "URL: {}", url.unwrap_or(&name))?;
+    writeln!(control, "SHA-1: {}", sha256::hex(&sha.finish()))?;
+    writeln!(control)?;
+    control.write_all(&sums)?;
+    control.flush()?;
+    events::info(&format!("Wrote zsync control file {}", output.display()));
+    Ok(())
+}
+
+// About how large the control file of an image of length bytes is, for the room it needs
+pub fn control_size(length: u64) -> u64 {
+    let (_, rsum_len, checksum_len) = hash_lengths(length);
+    length.div_ceil(BLOCK_SIZE as u64) * (rsum_len + checksum_len) as u64 + 1024
+}
+
+// How many blocks in a row must match, and how many bytes of each checksum are kept: enough
+// that false matches stay unlikely for a file of this size, as zsyncmake works it out
+fn hash_lengths(length: u64) -> (usize, usize, usize) {
+    let (length, block) = (length.max(1) as f64, BLOCK_SIZE as f64);
+    let sequence = if length > block { 2 } else { 1 };
+    let rsum_len = (((length.ln() + block.ln()) / 2f64.ln() - 8.6) / sequence as f64 / 8.0).ceil().clamp(2.0, 4.0) as usize;
+    let blocks = (1.0 + length / block).floor();
+    let checksum_len = ((20.0 + (length.ln() + blocks.ln()) / 2f64.ln()) / sequence as f64 / 8.0).ceil() as usize;
+    let at_least = ((7.9 + 20.0 + blocks.ln() / 2f64.ln()) / 8.0) as usize;
+    (sequence, rsum_len, checksum_len.max(at_least).min(16))
+}
+
+// The weak checksum of a block, big-endian: the sum of its bytes, then the sum weighted by
+// how far from the end each is, both modulo 2^16
+fn rsum(block: &[u8]) -> [u8; 4] {
+    let (mut a, mut b) = (0u16, 0u16);
+    for (index, &byte) in block.iter().enumerate() {
+        a = a.wrapping_add(byte as u16);
+        b = b.wrapping_add(((block.len() - index) as u16).wrapping_mul(byte as u16));
+    }
+    let [a0, a1] = a.to_be_bytes();
+    let [b0, b1] = b.to_be_bytes();
+    [a0, a1, b0, b1]
+}
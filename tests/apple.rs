@@ -0,0 +1,51 @@
+// Apple extensions (--apple): Finder info from AppleDouble files, kept in the system use
+// areas where classic Mac OS and the reader find it
+
+mod common;
+
+use std::fs;
+
+use makeiso::reader::{FinderInfo, IsoReader};
+
+use common::{run, scratch_dir};
+
+// An AppleDouble file with nothing but Finder info: magic, version, filler, one entry
+fn apple_double(finder_info: &[u8; 10]) -> Vec<u8> {
+    let mut file = vec![0x00, 0x05, 0x16, 0x07, 0x00, 0x02, 0x00, 0x00];
+    file.extend_from_slice(b"Mac OS X        ");
+    file.extend_from_slice(&1u16.to_be_bytes());
+    for field in [9u32, 38, 32] {
+        file.extend_from_slice(&field.to_be_bytes());
+    }
+    file.extend_from_slice(finder_info);
+    file.resize(38 + 32, 0);
+    file
+}
+
+#[test]
+fn apple_finder_info() {
+    let scratch = scratch_dir("apple");
+    let source = scratch.join("source");
+    fs::create_dir_all(&source).unwrap();
+    fs::write(source.join("Read Me"), "hello\n").unwrap();
+    fs::write(source.join("._Read Me"), apple_double(b"TEXTttxt\x01\x00")).unwrap();
+    fs::write(source.join("plain.txt"), "plain\n").unwrap();
+    let image = scratch.join("image.iso");
+    let result = run(&[&"--apple", &source, &image]);
+    assert!(result.status.success(), "makeiso failed: {}", String::from_utf8_lossy(&result.stderr));
+
+    let finder_info = |image: &[u8]| {
+        let mut reader = IsoReader::open(std::io::Cursor::new(image)).unwrap();
+        reader.read_tree().unwrap().into_iter().skip(1).map(|entry| (entry.path, entry.finder_info)).collect::<Vec<_>>()
+    };
+    let mut bytes = fs::read(&image).unwrap();
+    let text = FinderInfo { file_type: *b"TEXT", creator: *b"ttxt", flags: 0x0100 };
+    assert_eq!(finder_info(&bytes), [("/Read Me".to_string(), Some(text)), ("/plain.txt".to_string(), None)], "the AppleDouble file is left out");
+
+    // The older BA signature, with a ProDOS file type and aux type in place of the HFS codes
+    let field = bytes.windows(4).position(|window| window == b"AA\x0e\x02").expect("AA field");
+    bytes[field..field + 7].copy_from_slice(b"BA\x07\x01\x06\x00\x20");
+    let binary = FinderInfo { file_type: [b'p', 0x06, 0x20, 0x00], creator: *b"pdos", flags: 0 };
+    assert_eq!(finder_info(&bytes)[0].1, Some(binary));
+    fs::remove_dir_all(&scratch).unwrap();
+}
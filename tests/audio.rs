@@ -0,0 +1,349 @@
+// Audio CD mastering (makeiso audio): WAV and FLAC tracks go into one .bin of 2352-byte
+// sectors, each padded to whole sectors, with a cue sheet giving where each starts; with
+// --data, after an ISO 9660 image in Mode 1 sectors
+//
+// There is no FLAC encoder to run here, so the FLAC track is written by a small one below,
+// frame by frame, covering every subframe type and stereo decorrelation the decoder handles.
+
+mod common;
+
+use std::fs;
+use std::process::Output;
+
+use common::{run, scratch_dir};
+
+const SECTOR_FRAMES: usize = 588; // Stereo sample frames in a 2352-byte sector
+
+fn makeiso(args: &[&str]) -> Output {
+    common::makeiso().arg("audio").args(args).output().expect("failed to run makeiso")
+}
+
+// A test signal: a triangle wave on the left, a slower one with some noise on the right
+fn signal(frames: usize, seed: i32) -> Vec<(i32, i32)> {
+    (0..frames as i32).map(|i| (((i * 37 + seed) % 4000 - 2000).abs() * 8 - 8000, ((i * 11 + seed) % 1500) * 20 - 15000 + (i * i * 7) % 97)).collect()
+}
+
+// A WAV file of the given format; samples are written at their given size
+fn wav(samples: &[Vec<i32>], sample_rate: u32, bytes: usize, extensible: bool) -> Vec<u8> {
+    let channels = samples.len();
+    let mut fmt = Vec::new();
+    fmt.extend_from_slice(&(if extensible { 0xFFFEu16 } else { 1 }).to_le_bytes());
+    fmt.extend_from_slice(&(channels as u16).to_le_bytes());
+    fmt.extend_from_slice(&sample_rate.to_le_bytes());
+    fmt.extend_from_slice(&(sample_rate * (channels * bytes) as u32).to_le_bytes());
+    fmt.extend_from_slice(&((channels * bytes) as u16).to_le_bytes());
+    fmt.extend_from_slice(&((bytes * 8) as u16).to_le_bytes());
+    if extensible {
+        fmt.extend_from_slice(&22u16.to_le_bytes());
+        fmt.extend_from_slice(&((bytes * 8) as u16).to_le_bytes());
+        fmt.extend_from_slice(&0u32.to_le_bytes());
+        fmt.extend_from_slice(&[1, 0, 0, 0, 0, 0, 0x10, 0, 0x80, 0, 0, 0xAA, 0, 0x38, 0x9B, 0x71]);
+    }
+    let mut data = Vec::new();
+    for frame in 0..samples[0].len() {
+        for channel in samples {
+            let sample = if bytes == 1 { channel[frame] + 128 } else { channel[frame] };
+            data.extend_from_slice(&sample.to_le_bytes()[..bytes]);
+        }
+    }
+    let mut file = b"RIFF\0\0\0\0WAVE".to_vec();
+    file.extend_from_slice(b"LIST\x04\0\0\0INFO"); // A chunk to skip
+    for (id, chunk) in [(b"fmt ", fmt), (b"data", data)] {
+        file.extend_from_slice(id);
+        file.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+        file.extend_from_slice(&chunk);
+    }
+    let riff_size = (file.len() - 8) as u32;
+    file[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    file
+}
+
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn put(&mut self, value: i64, bits: u32) {
+        for bit in (0..bits).rev() {
+            self.buffer = (self.buffer << 1) | ((value >> bit) & 1) as u64;
+            self.count += 1;
+            if self.count == 8 {
+                self.bytes.push(self.buffer as u8);
+                self.count = 0;
+            }
+        }
+    }
+
+    fn align(&mut self) {
+        while self.count != 0 {
+            self.put(0, 1);
+        }
+    }
+
+    // Rice-coded residuals in one partition of 4-bit parameters
+    fn rice(&mut self, residuals: &[i64], parameter: u32) {
+        for &residual in residuals {
+            let folded = if residual >= 0 { residual * 2 } else { -residual * 2 - 1 };
+            for _ in 0..folded >> parameter {
+                self.put(0, 1);
+            }
+            self.put(1, 1);
+            self.put(folded, parameter);
+        }
+    }
+}
+
+fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |mut crc, &byte| {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x07 } else { crc << 1 };
+        }
+        crc
+    })
+}
+
+fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0u16, |mut crc, &byte| {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x8005 } else { crc << 1 };
+        }
+        crc
+    })
+}
+
+// How a FLAC subframe is coded
+enum Subframe {
+    Constant,
+    Verbatim,
+    Fixed(usize),
+    Lpc(Vec<i64>, u32), // Coefficients (5-bit precision) and shift
+}
+
+// A frame to encode: its samples, channel assignment, subframes and wasted bits
+type Block = (Vec<(i32, i32)>, u32, [Subframe; 2], u32);
+
+// The residuals of a channel after a prediction, as the decoder forms it
+fn residuals(channel: &[i64], coefficients: &[i64], shift: u32) -> Vec<i64> {
+    (coefficients.len()..channel.len())
+        .map(|at| channel[at] - (coefficients.iter().enumerate().map(|(back, coefficient)| coefficient * channel[at - 1 - back]).sum::<i64>() >> shift))
+        .collect()
+}
+
+fn subframe(writer: &mut BitWriter, channel: &[i64], bits: u32, coding: &Subframe, wasted: u32) {
+    writer.put(0, 1);
+    let shifted: Vec<i64> = channel.iter().map(|sample| sample >> wasted).collect();
+    let kind = match coding {
+        Subframe::Constant => 0,
+        Subframe::Verbatim => 1,
+        Subframe::Fixed(order) => 8 + *order as i64,
+        Subframe::Lpc(coefficients, _) => 31 + coefficients.len() as i64,
+    };
+    writer.put(kind, 6);
+    if wasted > 0 {
+        writer.put(1, 1);
+        writer.put(1, wasted); // wasted - 1 zeros, then a one
+    } else {
+        writer.put(0, 1);
+    }
+    let bits = bits - wasted;
+    let (coefficients, shift) = match coding {
+        Subframe::Constant => return writer.put(shifted[0], bits),
+        Subframe::Verbatim => return shifted.iter().for_each(|&sample| writer.put(sample, bits)),
+        Subframe::Fixed(order) => ([&[][..], &[1], &[2, -1], &[3, -3, 1], &[4, -6, 4, -1]][*order].to_vec(), 0),
+        Subframe::Lpc(coefficients, shift) => (coefficients.clone(), *shift),
+    };
+    for &sample in &shifted[..coefficients.len()] {
+        writer.put(sample, bits);
+    }
+    if let Subframe::Lpc(..) = coding {
+        writer.put(4, 4); // 5-bit coefficients
+        writer.put(shift as i64, 5);
+        for &coefficient in &coefficients {
+            writer.put(coefficient, 5);
+        }
+    }
+    let residuals = residuals(&shifted, &coefficients, shift);
+    // Two partitions: the first Rice coded, the second escaped to raw 20-bit numbers
+    writer.put(0, 2);
+    writer.put(1, 4);
+    let first = shifted.len() / 2 - coefficients.len();
+    writer.put(11, 4);
+    writer.rice(&residuals[..first], 11);
+    writer.put(15, 4);
+    writer.put(20, 5);
+    for &residual in &residuals[first..] {
+        writer.put(residual, 20);
+    }
+}
+
+// A FLAC file of 16-bit stereo at 44.1 kHz, one frame per block with the given channel
+// assignment (1 for independent, 8 left/side, 9 side/right, 10 mid/side) and subframes
+fn flac(blocks: &[Block]) -> Vec<u8> {
+    let total: usize = blocks.iter().map(|(samples, _, _, _)| samples.len()).sum();
+    let mut file = b"fLaC".to_vec();
+    file.extend_from_slice(&[0x80, 0, 0, 34]);
+    file.extend_from_slice(&[0x10, 0x00, 0xFF, 0xFF, 0, 0, 0, 0, 0, 0]);
+    let packed = (44100u64 << 44) | (1 << 41) | (15 << 36) | total as u64;
+    file.extend_from_slice(&packed.to_be_bytes());
+    file.extend_from_slice(&[0; 16]);
+
+    for (number, (samples, assignment, codings, wasted)) in blocks.iter().enumerate() {
+        let mut writer = BitWriter { bytes: Vec::new(), buffer: 0, count: 0 };
+        writer.put(0xFFF8, 16);
+        writer.put(7, 4); // 16-bit block size at the end of the header
+        writer.put(if number == 0 { 0 } else { 9 }, 4); // From STREAMINFO, or 44.1 kHz
+        writer.put(*assignment as i64, 4);
+        writer.put(4, 3);
+        writer.put(0, 1);
+        writer.put(number as i64, 8);
+        writer.put(samples.len() as i64 - 1, 16);
+        let header_crc = crc8(&writer.bytes);
+        writer.put(header_crc as i64, 8);
+
+        let (left, right): (Vec<i64>, Vec<i64>) = samples.iter().map(|&(left, right)| (left as i64, right as i64)).unzip();
+        let side: Vec<i64> = left.iter().zip(&right).map(|(left, right)| left - right).collect();
+        let mid: Vec<i64> = left.iter().zip(&right).map(|(left, right)| (left + right) >> 1).collect();
+        let channels: [(&[i64], u32); 2] = match assignment {
+            8 => [(&left, 16), (&side, 17)],
+            9 => [(&side, 17), (&right, 16)],
+            10 => [(&mid, 16), (&side, 17)],
+            _ => [(&left, 16), (&right, 16)],
+        };
+        for ((channel, bits), coding) in channels.iter().zip(codings) {
+            subframe(&mut writer, channel, *bits, coding, *wasted);
+        }
+        writer.align();
+        let frame_crc = crc16(&writer.bytes);
+        writer.put(frame_crc as i64, 16);
+        file.extend_from_slice(&writer.bytes);
+    }
+    file
+}
+
+// What a track should add to the .bin: 16-bit little-endian frames and silence to a whole sector
+fn track_bytes(frames: &[(i32, i32)]) -> Vec<u8> {
+    let mut bytes: Vec<u8> = frames.iter().flat_map(|&(left, right)| [(left as i16).to_le_bytes(), (right as i16).to_le_bytes()].concat()).collect();
+    bytes.resize(frames.len().div_ceil(SECTOR_FRAMES) * SECTOR_FRAMES * 4, 0);
+    bytes
+}
+
+#[test]
+fn wav_and_flac_tracks() {
+    let scratch = scratch_dir("tracks");
+
+    // A plain 16-bit stereo WAV
+    let first = signal(1000, 0);
+    let (left, right): (Vec<i32>, Vec<i32>) = first.iter().copied().unzip();
+    fs::write(scratch.join("01.wav"), wav(&[left, right], 44100, 2, false)).unwrap();
+
+    // FLAC, with every subframe type and stereo decorrelation; the constant block is silence,
+    // the last has its low bit unused
+    let constant = vec![(0, 0); 192];
+    let blocks = vec![
+        (constant.clone(), 1, [Subframe::Constant, Subframe::Constant], 0),
+        (signal(300, 5), 1, [Subframe::Verbatim, Subframe::Fixed(0)], 0),
+        (signal(700, 9), 10, [Subframe::Fixed(2), Subframe::Fixed(1)], 0),
+        (signal(256, 3), 8, [Subframe::Lpc(vec![12, -4], 3), Subframe::Fixed(3)], 0),
+        (signal(120, 7).into_iter().map(|(left, right)| (left & !1, right & !1)).collect(), 9, [Subframe::Fixed(4), Subframe::Lpc(vec![7], 3)], 1),
+    ];
+    let second: Vec<(i32, i32)> = blocks.iter().flat_map(|(samples, _, _, _)| samples.clone()).collect();
+    fs::write(scratch.join("02.flac"), flac(&blocks)).unwrap();
+
+    // A 24-bit mono WAVE_FORMAT_EXTENSIBLE file: both channels get its top 16 bits
+    let third: Vec<i32> = signal(SECTOR_FRAMES * 3, 1).iter().map(|&(left, _)| left * 256 + 0x5A).collect();
+    fs::write(scratch.join("03.wav"), wav(std::slice::from_ref(&third), 44100, 3, true)).unwrap();
+    let third: Vec<(i32, i32)> = third.iter().map(|&sample| (sample >> 8, sample >> 8)).collect();
+
+    let (cue, bin) = (scratch.join("disc.cue"), scratch.join("disc.bin"));
+    let tracks = ["01.wav", "02.flac", "03.wav"].map(|name| scratch.join(name).to_str().unwrap().to_string());
+    let result = makeiso(&["--tracks", &tracks[0], &tracks[1], &tracks[2], "--cue", cue.to_str().unwrap()]);
+    assert!(result.status.success(), "makeiso audio failed: {}", String::from_utf8_lossy(&result.stderr));
+
+    let expected: Vec<u8> = [track_bytes(&first), track_bytes(&second), track_bytes(&third)].concat();
+    let written = fs::read(&bin).unwrap();
+    assert_eq!(written.len(), expected.len());
+    assert!(written == expected, "the .bin holds other samples");
+    assert_eq!(
+        fs::read_to_string(&cue).unwrap(),
+        "FILE \"disc.bin\" BINARY\n  TRACK 01 AUDIO\n    INDEX 01 00:00:00\n  TRACK 02 AUDIO\n    INDEX 01 00:00:02\n  TRACK 03 AUDIO\n    INDEX 01 00:00:05\n"
+    );
+
+    // bin-to-iso finds no data track on an audio CD
+    let result = run(&[&"bin-to-iso", &cue, &scratch.join("disc.iso")]);
+    assert!(!result.status.success());
+    fs::remove_dir_all(&scratch).unwrap();
+}
+
+// The EDC of a raw sector: CRC-32 with the reflected polynomial 0xD8018001
+fn edc(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0u32, |mut edc, &byte| {
+        edc ^= byte as u32;
+        for _ in 0..8 {
+            edc = (edc >> 1) ^ if edc & 1 != 0 { 0xD801_8001 } else { 0 };
+        }
+        edc
+    })
+}
+
+#[test]
+fn mixed_mode_disc() {
+    let scratch = scratch_dir("mixed");
+    let source = scratch.join("source");
+    fs::create_dir_all(&source).unwrap();
+    fs::write(source.join("readme.txt"), "hello\n").unwrap();
+    let image = scratch.join("data.iso");
+    let result = run(&[&source, &image]);
+    assert!(result.status.success(), "makeiso failed: {}", String::from_utf8_lossy(&result.stderr));
+    let (left, right): (Vec<i32>, Vec<i32>) = signal(2000, 0).into_iter().unzip();
+    fs::write(scratch.join("02.wav"), wav(&[left, right], 44100, 2, false)).unwrap();
+
+    let (cue, bin) = (scratch.join("disc.cue"), scratch.join("disc.bin"));
+    let result = makeiso(&["--data", image.to_str().unwrap(), "--tracks", scratch.join("02.wav").to_str().unwrap(), "--cue", cue.to_str().unwrap()]);
+    assert!(result.status.success(), "makeiso audio failed: {}", String::from_utf8_lossy(&result.stderr));
+    // The image is shorter than 4 seconds, so the data track is padded to 300 sectors
+    assert_eq!(
+        fs::read_to_string(&cue).unwrap(),
+        "FILE \"disc.bin\" BINARY\n  TRACK 01 MODE1/2352\n    INDEX 01 00:00:00\n  TRACK 02 AUDIO\n    PREGAP 00:02:00\n    INDEX 01 00:04:00\n"
+    );
+
+    // Sector 16 (the volume descriptor) is at 00:02:16 on the disc, after the lead-in pregap
+    let raw = fs::read(&bin).unwrap();
+    assert_eq!(raw.len(), (300 + 4) * 2352);
+    let sector = &raw[16 * 2352..17 * 2352];
+    assert_eq!(&sector[..16], b"\x00\xff\xff\xff\xff\xff\xff\xff\xff\xff\xff\x00\x00\x02\x16\x01");
+    assert_eq!(&sector[16..2064], &fs::read(&image).unwrap()[16 * 2048..17 * 2048]);
+    assert_eq!(u32::from_le_bytes(sector[0x810..0x814].try_into().unwrap()), edc(&sector[..0x810]));
+
+    // bin-to-iso takes the data track back out
+    let back = scratch.join("back.iso");
+    let result = run(&[&"bin-to-iso", &cue, &back]);
+    assert!(result.status.success(), "bin-to-iso failed: {}", String::from_utf8_lossy(&result.stderr));
+    let (original, back) = (fs::read(&image).unwrap(), fs::read(&back).unwrap());
+    assert_eq!(back.len(), 300 * 2048);
+    assert!(back[..original.len()] == original[..] && back[original.len()..].iter().all(|&byte| byte == 0));
+    fs::remove_dir_all(&scratch).unwrap();
+}
+
+#[test]
+fn tracks_that_need_resampling() {
+    let scratch = scratch_dir("resample");
+    let (left, right): (Vec<i32>, Vec<i32>) = signal(100, 0).into_iter().unzip();
+    fs::write(scratch.join("48k.wav"), wav(&[left, right], 48000, 2, false)).unwrap();
+    let mut corrupt = flac(&[(signal(200, 0), 1, [Subframe::Verbatim, Subframe::Verbatim], 0)]);
+    let last = corrupt.len() - 10;
+    corrupt[last] ^= 0x10;
+    fs::write(scratch.join("corrupt.flac"), corrupt).unwrap();
+
+    let bin = scratch.join("disc.bin");
+    for (track, message) in [("48k.wav", "48000 Hz"), ("corrupt.flac", "CRC mismatch")] {
+        let result = makeiso(&["--tracks", scratch.join(track).to_str().unwrap(), "--cue", scratch.join("disc.cue").to_str().unwrap()]);
+        let stderr = String::from_utf8_lossy(&result.stderr);
+        assert!(!result.status.success() && stderr.contains(message), "{}: {}", track, stderr);
+        assert!(!bin.exists(), "a partial .bin is left behind");
+    }
+    fs::remove_dir_all(&scratch).unwrap();
+}
@@ -0,0 +1,42 @@
+// Block maps (--block-map): where each file is on the image, in each format
+
+mod common;
+
+use std::fs;
+
+use common::{run, scratch_dir, sha256sum};
+
+#[test]
+fn block_map_formats() {
+    let scratch = scratch_dir("block-map");
+    let source = scratch.join("source");
+    fs::create_dir_all(source.join("sub")).unwrap();
+    fs::write(source.join("sub/data.bin"), vec![9u8; 10_000]).unwrap();
+    let (image, bmap) = (scratch.join("image.iso"), scratch.join("image.bmap"));
+    let result = run(&[&"--pad", &"--block-map-format", &"bmap", &"--block-map", &bmap, &source, &image]);
+    assert!(result.status.success(), "makeiso failed: {}", String::from_utf8_lossy(&result.stderr));
+
+    // The padding is left out, and the map of the image read back is the same
+    let written = fs::read_to_string(&bmap).unwrap();
+    let contents = fs::read(&image).unwrap();
+    let used = contents.len() / 2048 - 300;
+    assert!(written.contains(&format!("<BlocksCount> {} </BlocksCount>", contents.len() / 2048)), "{}", written);
+    assert!(written.contains(&format!("<MappedBlocksCount> {} </MappedBlocksCount>", used)), "{}", written);
+    assert!(written.contains(&format!("> 0-{} </Range>", used - 1)), "{}", written);
+    if let Some(range) = sha256sum(&contents[..used * 2048]) {
+        assert!(written.contains(&format!("<Range chksum=\"{}\">", range)), "{}", written);
+        let checksum = written.split("<BmapFileChecksum> ").nth(1).unwrap()[..64].to_string();
+        assert_eq!(sha256sum(written.replace(&checksum, &"0".repeat(64)).as_bytes()), Some(checksum));
+    }
+    let result = run(&[&"block-map", &"--format", &"bmap", &image]);
+    assert!(result.status.success(), "block-map failed: {}", String::from_utf8_lossy(&result.stderr));
+    assert_eq!(String::from_utf8_lossy(&result.stdout), written);
+
+    let result = run(&[&"block-map", &"--format=json", &image]);
+    let json = String::from_utf8_lossy(&result.stdout);
+    assert!(json.starts_with("{\"block_size\":2048,"), "{}", json);
+    assert!(json.contains("\"type\":\"file\",\"contents\":\"/sub/data.bin\"}"), "{}", json);
+    assert!(json.contains("\"type\":\"directory\",\"contents\":\"/sub/\"}"), "{}", json);
+    assert!(String::from_utf8_lossy(&run(&[&"block-map", &image]).stdout).contains("\t(primary volume descriptor)\n"));
+    fs::remove_dir_all(&scratch).unwrap();
+}
@@ -0,0 +1,41 @@
+// makeiso cat, and looking paths up without regard to case (--icase)
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+
+use common::{makeiso, run, scratch_dir};
+
+#[test]
+fn case_insensitive_lookup() {
+    let scratch = scratch_dir("icase");
+    let source = scratch.join("source");
+    fs::create_dir_all(source.join("etc")).unwrap();
+    fs::write(source.join("etc").join("fstab"), "proc /proc proc defaults 0 0\n").unwrap();
+    let cat = |options: &[&str], image: &Path, path: &str| {
+        let result = makeiso().arg("cat").args(options).arg(image).arg(path).output().expect("failed to run makeiso");
+        result.status.success().then(|| String::from_utf8_lossy(&result.stdout).into_owned())
+    };
+    let contents = Some("proc /proc proc defaults 0 0\n".to_string());
+
+    // Plain identifiers have no case to keep, so paths match without --icase, version and all
+    let plain = scratch.join("plain.iso");
+    assert!(run(&[&source, &plain]).status.success());
+    assert_eq!(cat(&[], &plain, "/etc/fstab"), contents);
+    assert_eq!(cat(&[], &plain, "/ETC/FSTAB.;1"), contents);
+    assert_eq!(cat(&["--case-sensitive"], &plain, "/ETC/FSTAB"), None);
+
+    // ISO 9660:1999 names keep their case unless asked
+    let enhanced = scratch.join("enhanced.iso");
+    assert!(run(&[&"--iso-level", &"4", &source, &enhanced]).status.success());
+    assert_eq!(cat(&[], &enhanced, "/ETC/FSTAB"), None);
+    assert_eq!(cat(&["--icase"], &enhanced, "/ETC/FSTAB"), contents);
+
+    // extract looks its paths up the same way, and creates them as the image spells them
+    let target = scratch.join("extracted");
+    let result = run(&[&"extract", &"--icase", &enhanced, &target, &"Etc"]);
+    assert!(result.status.success(), "extract failed: {}", String::from_utf8_lossy(&result.stderr));
+    assert_eq!(fs::read_to_string(target.join("etc").join("fstab")).ok(), contents);
+    fs::remove_dir_all(&scratch).unwrap();
+}
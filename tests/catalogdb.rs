@@ -0,0 +1,79 @@
+// The catalog database (--catalog-db) and makeiso which-image
+
+mod common;
+
+use std::fs;
+use std::process::Command;
+
+use common::{makeiso, run, scratch_dir, sha256sum, installed};
+
+#[test]
+fn catalog_database() {
+    if !installed("sqlite3") {
+        return; // The catalog is kept through the sqlite3 shell
+    }
+    let scratch = scratch_dir("catalogdb");
+    let db = scratch.join("catalog.db");
+    let build = |name: &str, fstab: &str| {
+        let source = scratch.join(format!("source-{}", name));
+        fs::create_dir_all(source.join("etc")).unwrap();
+        fs::write(source.join("etc/fstab"), fstab).unwrap();
+        fs::write(source.join(format!("only-in-{}'s.txt", name)), name).unwrap();
+        let image = scratch.join(format!("{}.iso", name));
+        let result = run(&[&"--catalog-db", &db, &source, &image]);
+        assert!(result.status.success(), "makeiso failed: {}", String::from_utf8_lossy(&result.stderr));
+        image
+    };
+    let monday = build("monday", "/dev/sda1 / ext4 defaults 0 1\n");
+    let tuesday = build("tuesday", "/dev/sda2 / ext4 defaults 0 1\n");
+    let which = |args: &[&str]| makeiso().arg("which-image").arg("--catalog-db").arg(&db).args(args).output().expect("failed to run makeiso");
+
+    let result = which(&["--file", "/etc/fstab", "--newer", "2024-01-01"]);
+    assert!(result.status.success(), "which-image failed: {}", String::from_utf8_lossy(&result.stderr));
+    let stdout = String::from_utf8_lossy(&result.stdout).into_owned();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 2, "{}", stdout);
+    assert!(lines[0].contains("monday.iso") && lines[1].contains("tuesday.iso"), "{}", stdout);
+    assert!(lines[0].contains("30 bytes"), "{}", stdout);
+    if let Some(sha256) = sha256sum(b"/dev/sda2 / ext4 defaults 0 1\n") {
+        assert!(lines[1].ends_with(&format!("sha256 {}", sha256)), "{}", stdout);
+    }
+
+    let result = which(&["--file", "only-in-tuesday's.txt"]);
+    let stdout = String::from_utf8_lossy(&result.stdout).into_owned();
+    assert!(stdout.contains("tuesday.iso") && !stdout.contains("monday.iso"), "{}", stdout);
+    let result = which(&["--file", "/etc"]);
+    assert!(String::from_utf8_lossy(&result.stdout).lines().all(|line| line.contains("directory")), "{}", String::from_utf8_lossy(&result.stdout));
+
+    // The tables are there for sqlite3 itself
+    let result = Command::new("sqlite3").arg(&db).arg("SELECT count(*) FROM images; SELECT count(*) FROM entries WHERE kind = 'file';").output().unwrap();
+    assert_eq!(String::from_utf8_lossy(&result.stdout), "2\n4\n");
+
+    // Nothing built after the date, or nothing with the path
+    assert!(!which(&["--file", "/etc/fstab", "--newer", "2999-01-01"]).status.success());
+    assert!(!which(&["--file", "/etc/passwd"]).status.success());
+
+    fs::remove_file(&monday).unwrap();
+    let result = which(&["--file", "/etc/fstab"]);
+    let stdout = String::from_utf8_lossy(&result.stdout).into_owned();
+    assert!(stdout.lines().next().unwrap().contains("monday.iso (no longer there)"), "{}", stdout);
+    assert!(!stdout.contains("tuesday.iso (no longer there)") && tuesday.exists(), "{}", stdout);
+    fs::remove_dir_all(&scratch).unwrap();
+}
+
+// Without sqlite3 the build is refused before anything is written
+#[test]
+fn catalog_without_sqlite() {
+    let scratch = scratch_dir("catalogdb-missing");
+    let source = scratch.join("source");
+    fs::create_dir_all(&source).unwrap();
+    fs::write(source.join("readme.txt"), "catalogued\n").unwrap();
+    let empty = scratch.join("bin");
+    fs::create_dir_all(&empty).unwrap();
+    let image = scratch.join("image.iso");
+    let result = makeiso().env("PATH", &empty).arg("--catalog-db").arg(scratch.join("catalog.db")).arg(&source).arg(&image).output().expect("failed to run makeiso");
+    assert!(!result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr).contains("sqlite3 is not installed"), "{}", String::from_utf8_lossy(&result.stderr));
+    assert!(!image.exists());
+    fs::remove_dir_all(&scratch).unwrap();
+}
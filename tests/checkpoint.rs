@@ -0,0 +1,47 @@
+// Checkpoint summaries (--checkpoint-every) and makeiso status
+
+mod common;
+
+use std::fs;
+
+use common::{makeiso, scratch_dir};
+
+#[test]
+fn checkpoint_summaries() {
+    let scratch = scratch_dir("checkpoint");
+    let source = scratch.join("source");
+    fs::create_dir_all(&source).unwrap();
+    for name in ["a.bin", "b.bin", "c.bin"] {
+        fs::write(source.join(name), vec![b'x'; 200_000]).unwrap();
+    }
+    let command = || {
+        let mut command = makeiso();
+        command.env("MAKEISO_STATE_DIR", &scratch);
+        command
+    };
+
+    // Held to 300 KB/s, the build takes two seconds and checkpoints on the way
+    let (image, status) = (scratch.join("slow.iso"), scratch.join("slow.status"));
+    let result = command().args(["--checkpoint-every", "1s", "--bwlimit", "300K", "--status-file"]).arg(&status).arg(&source).arg(&image).output().expect("failed to run makeiso");
+    assert!(result.status.success(), "makeiso failed: {}", String::from_utf8_lossy(&result.stderr));
+    let stdout = String::from_utf8_lossy(&result.stdout);
+    let checkpoint = stdout.lines().find(|line| line.starts_with("Checkpoint: ")).unwrap_or_else(|| panic!("no checkpoint in {}", stdout));
+    assert!(checkpoint.contains(" of 3 files, ") && checkpoint.contains(" of 585.9 KiB, ") && checkpoint.contains(" left, "), "{}", checkpoint);
+    let result = command().arg("status").arg(&status).output().expect("failed to run makeiso");
+    assert!(String::from_utf8_lossy(&result.stdout).contains("  done  "), "{}", String::from_utf8_lossy(&result.stdout));
+
+    // makeiso run keeps one for the profile, which makeiso status shows
+    let config = scratch.join("makeiso.toml");
+    fs::write(&config, format!("[profiles.nightly]\nsource = {:?}\noutput = {:?}\n", source, scratch.join("nightly.iso"))).unwrap();
+    let result = command().args(["run", "--config", config.to_str().unwrap(), "nightly"]).output().expect("failed to run makeiso");
+    assert!(result.status.success(), "makeiso run failed: {}", String::from_utf8_lossy(&result.stderr));
+    assert!(scratch.join("jobs").join("nightly.status").is_file());
+    let result = command().args(["status", "--config", config.to_str().unwrap(), "nightly"]).output().expect("failed to run makeiso");
+    let stdout = String::from_utf8_lossy(&result.stdout);
+    assert!(stdout.lines().next().is_some_and(|line| line.contains("  done  ")), "{}", stdout);
+
+    let result = command().args(["--checkpoint-every", "0"]).arg(&source).arg(&image).output().expect("failed to run makeiso");
+    assert!(String::from_utf8_lossy(&result.stderr).contains("invalid --checkpoint-every: 0"));
+    fs::remove_dir_all(&scratch).unwrap();
+}
+
@@ -0,0 +1,33 @@
+// makeiso cmp: the differences between two images, and an exit status that says whether
+// there are any, as cmp(1)'s does
+
+mod common;
+
+use std::fs;
+
+use common::{reproducible_build, run, scratch_dir};
+
+#[test]
+fn exit_status_tells_whether_images_differ() {
+    let scratch = scratch_dir("cmp");
+    let source = scratch.join("source");
+    fs::create_dir_all(&source).unwrap();
+    fs::write(source.join("readme.txt"), "first\n").unwrap();
+    let (first, same, second) = (scratch.join("first.iso"), scratch.join("same.iso"), scratch.join("second.iso"));
+    for image in [&first, &same] {
+        assert!(reproducible_build(&[] as &[&str], &source, image).status.success());
+    }
+    fs::write(source.join("readme.txt"), "second\n").unwrap();
+    assert!(reproducible_build(&[] as &[&str], &source, &second).status.success());
+
+    let result = run(&[&"cmp", &first, &same]);
+    assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+    assert!(String::from_utf8_lossy(&result.stdout).contains("0 added, 0 removed, 0 changed"));
+
+    for format in ["text", "json"] {
+        let result = run(&[&"cmp", &"--format", &format, &first, &second]);
+        assert_eq!(result.status.code(), Some(1), "{}", String::from_utf8_lossy(&result.stderr));
+        assert!(String::from_utf8_lossy(&result.stdout).contains("readme.txt"), "{}", String::from_utf8_lossy(&result.stdout));
+    }
+    fs::remove_dir_all(&scratch).unwrap();
+}
@@ -0,0 +1,127 @@
+// What the integration tests share: running makeiso, scratch directories, and the trees an
+// image holds, as the reader lists them and as makeiso extract unpacks them. Each test file
+// uses some of it.
+#![allow(dead_code)]
+
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+use makeiso::reader::IsoReader;
+
+pub const FIXTURE_TIME: i64 = 1577934245; // 2020-01-02 03:04:05 UTC, every source entry's time
+
+#[derive(Debug, PartialEq)]
+pub enum Expected {
+    Directory,
+    File(Vec<u8>),
+    Symlink(String),
+}
+
+// makeiso itself, for runs that need more than arguments: standard input, the environment or
+// a working directory
+pub fn makeiso() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_makeiso"))
+}
+
+// Run makeiso with these arguments and wait for it
+pub fn run(args: &[&dyn AsRef<OsStr>]) -> Output {
+    makeiso().args(args.iter().map(|arg| arg.as_ref())).output().expect("failed to run makeiso")
+}
+
+// A reproducible build of a source, dated FIXTURE_TIME, over any image already there
+pub fn reproducible_build(extra: &[impl AsRef<OsStr>], source: &Path, image: &Path) -> Output {
+    makeiso().args(["--force", "--reproducible", "--source-date-epoch", &FIXTURE_TIME.to_string()]).args(extra).arg(source).arg(image).output().expect("failed to run makeiso")
+}
+
+pub fn fixture(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)
+}
+
+// Fresh scratch directory for a test
+pub fn scratch_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("makeiso-test-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// What the reader finds in an image, by path
+pub fn read_image(image: &Path) -> Vec<(String, Expected)> {
+    let mut reader = IsoReader::open(fs::File::open(image).unwrap()).unwrap();
+    let mut found = Vec::new();
+    for entry in reader.read_tree().unwrap().iter().skip(1) {
+        if entry.path == "/boot.catalog" {
+            continue; // libarchive lists its boot catalog as a file, dated when the fixture was made
+        }
+        assert_eq!(entry.modified, FIXTURE_TIME, "{} in {} has the wrong time", entry.path, image.display());
+        let read = if entry.is_directory {
+            Expected::Directory
+        } else if let Some(target) = &entry.symlink {
+            Expected::Symlink(target.clone())
+        } else {
+            let mut contents = vec![0u8; entry.size as usize];
+            let read = reader.read_file_at(entry, 0, &mut contents).unwrap();
+            assert_eq!(read, contents.len(), "{} in {} ends early", entry.path, image.display());
+            Expected::File(contents)
+        };
+        found.push((entry.path.clone(), read));
+    }
+    found.sort_by(|a, b| a.0.cmp(&b.0));
+    found
+}
+
+// What makeiso extract unpacks an image to, by path
+pub fn extract_image(image: &Path, destination: &Path) -> Vec<(String, Expected)> {
+    let result = run(&[&"extract", &image, &destination]);
+    assert!(result.status.success(), "makeiso extract failed: {}", String::from_utf8_lossy(&result.stderr));
+    let mut found = Vec::new();
+    walk(destination, "", &mut found);
+    found.sort_by(|a, b| a.0.cmp(&b.0));
+    found
+}
+
+pub fn walk(directory: &Path, path: &str, found: &mut Vec<(String, Expected)>) {
+    for entry in fs::read_dir(directory).unwrap() {
+        let entry = entry.unwrap();
+        let (target, path) = (entry.path(), format!("{}/{}", path, entry.file_name().to_string_lossy()));
+        let kind = entry.file_type().unwrap();
+        if kind.is_symlink() {
+            found.push((path, Expected::Symlink(fs::read_link(&target).unwrap().to_string_lossy().into_owned())));
+        } else if kind.is_dir() {
+            found.push((path.clone(), Expected::Directory));
+            walk(&target, &path, found);
+        } else {
+            found.push((path, Expected::File(fs::read(&target).unwrap())));
+        }
+    }
+}
+
+pub fn sorted(mut tree: Vec<(String, Expected)>) -> Vec<(String, Expected)> {
+    tree.sort_by(|a, b| a.0.cmp(&b.0));
+    tree
+}
+
+pub fn assert_same(found: &[(String, Expected)], expected: &[(String, Expected)], what: &str) {
+    let paths = |tree: &[(String, Expected)]| tree.iter().map(|(path, _)| path.clone()).collect::<Vec<_>>();
+    assert_eq!(paths(found), paths(expected), "{} lists other paths", what);
+    for ((path, got), (_, want)) in found.iter().zip(expected) {
+        assert!(got == want, "{}: {} differs", what, path);
+    }
+}
+
+// The SHA-256 of some bytes, by sha256sum when it is installed
+pub fn sha256sum(data: &[u8]) -> Option<String> {
+    let mut child = Command::new("sha256sum").stdin(Stdio::piped()).stdout(Stdio::piped()).spawn().ok()?;
+    child.stdin.take().unwrap().write_all(data).unwrap();
+    let output = child.wait_with_output().unwrap();
+    Some(String::from_utf8_lossy(&output.stdout).split_whitespace().next()?.to_string())
+}
+
+// Whether a tool can be run at all
+pub fn installed(tool: &str) -> bool {
+    Command::new(tool).arg("--version").stdout(Stdio::null()).stderr(Stdio::null()).status().is_ok()
+}
@@ -0,0 +1,51 @@
+// Deduplicated contents (--dedup content): one extent for files with the same contents, and
+// estimate --dedup-report
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+
+use makeiso::reader::IsoReader;
+
+use common::{run, reproducible_build, scratch_dir, read_image, assert_same};
+
+#[test]
+fn deduplicated_contents() {
+    let scratch = scratch_dir("dedup");
+    let source = scratch.join("source");
+    for dir in ["a", "b/c"] {
+        fs::create_dir_all(source.join(dir)).unwrap();
+    }
+    let big = vec![0x5A; 3 * 2048 + 5];
+    let mut other = big.clone();
+    other[100] = 0;
+    let files: [(&str, &[u8]); 5] = [("a/lib.so", &big), ("b/c/lib.so", &big), ("b/copy.bin", &big), ("b/same-size.bin", &other), ("readme.txt", b"hello\n")];
+    for (name, contents) in files {
+        fs::write(source.join(name), contents).unwrap();
+    }
+    let build = |extra: &[&str], image: &Path| {
+        let result = reproducible_build(extra, &source, image);
+        assert!(result.status.success(), "makeiso {:?} failed: {}", extra, String::from_utf8_lossy(&result.stderr));
+    };
+    let (plain, deduplicated) = (scratch.join("plain.iso"), scratch.join("dedup.iso"));
+    build(&[], &plain);
+    build(&["--dedup", "content"], &deduplicated);
+
+    // The tree reads the same, the three copies from one extent, two copies' blocks smaller
+    assert_same(&read_image(&deduplicated), &read_image(&plain), "a deduplicated image");
+    let mut reader = IsoReader::open(fs::File::open(&deduplicated).unwrap()).unwrap();
+    let extents: Vec<(String, u32)> = reader.read_tree().unwrap().into_iter().map(|entry| (entry.path, entry.extent)).collect();
+    let extent = |path: &str| extents.iter().find(|(found, _)| found == path).unwrap().1;
+    assert_eq!((extent("/b/c/lib.so"), extent("/b/copy.bin")), (extent("/a/lib.so"), extent("/a/lib.so")));
+    assert_ne!(extent("/b/same-size.bin"), extent("/a/lib.so"));
+    assert_eq!(fs::metadata(&plain).unwrap().len() - fs::metadata(&deduplicated).unwrap().len(), 2 * 4 * 2048);
+
+    // The report counts the two copies, and as blocks also a file whose two halves are alike
+    fs::write(source.join("halves.bin"), vec![1; 2 * 65536]).unwrap();
+    let result = run(&[&"estimate", &"--dedup-report", &source]);
+    let report = String::from_utf8_lossy(&result.stdout);
+    assert!(report.contains("Identical files: 2 copies, 12298 bytes"), "{}", report);
+    assert!(report.contains("Identical 64 KiB blocks: 77834 bytes"), "{}", report);
+    fs::remove_dir_all(&scratch).unwrap();
+}
@@ -0,0 +1,170 @@
+// DVD-Video images (--dvd-video): the VIDEO_TS files first and in order, and a UDF bridge that
+// places them where the IFO files say they are
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+
+use makeiso::reader::IsoReader;
+
+use common::{Expected, reproducible_build, scratch_dir, read_image, sorted, assert_same};
+
+// An IFO file: its magic, the last sector of its set and where its menu and first title VOB start
+fn ifo(magic: &[u8; 12], last_sector: u32, menu: u32, titles: u32, blocks: usize) -> Vec<u8> {
+    let mut file = vec![0u8; blocks * 2048];
+    file[..12].copy_from_slice(magic);
+    file[0x0C..0x10].copy_from_slice(&last_sector.to_be_bytes());
+    file[0xC0..0xC4].copy_from_slice(&menu.to_be_bytes());
+    file[0xC4..0xC8].copy_from_slice(&titles.to_be_bytes());
+    file
+}
+
+// A UDF descriptor at a block (of the partition starting at base, or of the volume for 0),
+// once its tag checksum and CRC are checked
+fn udf_descriptor(image: &[u8], base: u32, block: u32, id: u16) -> &[u8] {
+    let descriptor = &image[(base + block) as usize * 2048..];
+    let checksum = descriptor[..16].iter().enumerate().filter(|&(at, _)| at != 4).fold(0u8, |sum, (_, &byte)| sum.wrapping_add(byte));
+    assert_eq!(descriptor[4], checksum, "tag checksum at block {}", block);
+    assert_eq!(u16::from_le_bytes([descriptor[0], descriptor[1]]), id, "descriptor at block {}", block);
+    assert_eq!(u32::from_le_bytes(descriptor[12..16].try_into().unwrap()), block, "tag location at block {}", block);
+    let length = u16::from_le_bytes([descriptor[10], descriptor[11]]) as usize;
+    let mut crc = 0u16;
+    for &byte in &descriptor[16..16 + length] {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    assert_eq!(u16::from_le_bytes([descriptor[8], descriptor[9]]), crc, "descriptor CRC at block {}", block);
+    descriptor
+}
+
+// Every file of a UDF image, by path, with its first block and contents: anchor, volume
+// descriptor sequence, file set, then the file entries and identifiers from the root down
+fn udf_files(image: &[u8]) -> Vec<(String, u32, Vec<u8>)> {
+    let field = |bytes: &[u8], at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+    let anchor = udf_descriptor(image, 0, 256, 2);
+    let (mut block, mut partition, mut file_set) = (field(anchor, 20), None, None);
+    loop {
+        let id = u16::from_le_bytes([image[block as usize * 2048], image[block as usize * 2048 + 1]]);
+        let descriptor = udf_descriptor(image, 0, block, id);
+        match id {
+            5 => partition = Some(field(descriptor, 188)),
+            6 => file_set = Some(field(descriptor, 252)),
+            8 => break,
+            _ => {}
+        }
+        block += 1;
+    }
+    let partition = partition.expect("partition descriptor");
+    let file_set = udf_descriptor(image, partition, file_set.expect("logical volume descriptor"), 256);
+
+    // A file entry's contents, from its short allocation descriptors
+    let contents = |block: u32| {
+        let entry = udf_descriptor(image, partition, block, 261);
+        let size = u64::from_le_bytes(entry[56..64].try_into().unwrap()) as usize;
+        let (attributes, descriptors) = (field(entry, 168) as usize, field(entry, 172) as usize);
+        let mut data = Vec::new();
+        for at in (176 + attributes..176 + attributes + descriptors).step_by(8) {
+            let start = (partition + field(entry, at + 4)) as usize * 2048;
+            data.extend_from_slice(&image[start..start + field(entry, at) as usize]);
+        }
+        assert_eq!(data.len(), size, "file entry at block {}", block);
+        (entry[27], partition + field(entry, 180 + attributes), data)
+    };
+
+    let mut files = Vec::new();
+    let mut pending = vec![(String::new(), field(file_set, 404))];
+    while let Some((path, block)) = pending.pop() {
+        let (file_type, _, identifiers) = contents(block);
+        assert_eq!(file_type, 4, "{} is a directory", path);
+        let mut at = 0;
+        while at < identifiers.len() {
+            let identifier = &identifiers[at..];
+            let (characteristics, name_length) = (identifier[18], identifier[19] as usize);
+            let implementation_use = u16::from_le_bytes([identifier[36], identifier[37]]) as usize;
+            let name = &identifier[38 + implementation_use..38 + implementation_use + name_length];
+            let target = field(identifier, 24);
+            at += (38 + implementation_use + name_length).div_ceil(4) * 4;
+            if characteristics & 0x08 != 0 {
+                continue; // The parent
+            }
+            assert_eq!(name[0], 8, "a name of 8-bit characters");
+            let child = format!("{}/{}", path, String::from_utf8(name[1..].to_vec()).unwrap());
+            if characteristics & 0x02 != 0 {
+                pending.push((child, target));
+            } else {
+                let (file_type, start, data) = contents(target);
+                assert_eq!(file_type, 5, "{} is a file", child);
+                files.push((child, start, data));
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+#[test]
+fn dvd_video_image() {
+    let scratch = scratch_dir("dvd");
+    let source = scratch.join("source");
+    let video = source.join("VIDEO_TS");
+    fs::create_dir_all(&video).unwrap();
+    // The video manager wants its menu 2 sectors after the IFO ends, the title set's IFO is in
+    // step with its files
+    let files: [(&str, Vec<u8>); 8] = [
+        ("VIDEO_TS.IFO", ifo(b"DVDVIDEO-VMG", 7, 3, 0, 1)),
+        ("VIDEO_TS.VOB", vec![1; 4 * 2048]),
+        ("VIDEO_TS.BUP", ifo(b"DVDVIDEO-VMG", 7, 3, 0, 1)),
+        ("VTS_01_0.IFO", ifo(b"DVDVIDEO-VTS", 13, 2, 5, 2)),
+        ("VTS_01_0.VOB", vec![2; 3 * 2048]),
+        ("VTS_01_1.VOB", vec![3; 5 * 2048]),
+        ("VTS_01_2.VOB", vec![4; 2 * 2048 - 100]),
+        ("VTS_01_0.BUP", ifo(b"DVDVIDEO-VTS", 13, 2, 5, 2)),
+    ];
+    for (name, contents) in &files {
+        fs::write(video.join(name), contents).unwrap();
+    }
+    fs::write(source.join("readme.txt"), "hello\n").unwrap();
+    let image = scratch.join("image.iso");
+    let build = |extra: &[&str], source: &Path| {
+        reproducible_build(extra, source, &image)
+    };
+    let result = build(&["--dvd-video"], &source);
+    assert!(result.status.success(), "makeiso failed: {}", String::from_utf8_lossy(&result.stderr));
+
+    // The ISO 9660 side is unchanged, the UDF side has the same files at the same blocks
+    let mut expected: Vec<(String, Expected)> = files.iter().map(|(name, contents)| (format!("/VIDEO_TS/{}", name), Expected::File(contents.clone()))).collect();
+    expected.push(("/VIDEO_TS".to_string(), Expected::Directory));
+    expected.push(("/readme.txt".to_string(), Expected::File(b"hello\n".to_vec())));
+    assert_same(&read_image(&image), &sorted(expected), "a DVD-Video image");
+    let bytes = fs::read(&image).unwrap();
+    udf_descriptor(&bytes, 0, (bytes.len() / 2048 - 1) as u32, 2);
+    let udf = udf_files(&bytes);
+    let mut reader = IsoReader::open(std::io::Cursor::new(&bytes)).unwrap();
+    let extents: Vec<(String, u32)> = reader.read_tree().unwrap().into_iter().filter(|entry| !entry.is_directory).map(|entry| (entry.path, entry.extent)).collect();
+    for (path, start, contents) in &udf {
+        let iso = extents.iter().find(|(iso_path, _)| iso_path == path).unwrap_or_else(|| panic!("{} is missing from ISO 9660", path));
+        assert_eq!(iso.1, *start, "{} is at another block in ISO 9660", path);
+        assert_eq!(contents, &fs::read(source.join(&path[1..])).unwrap(), "{} reads differently through UDF", path);
+    }
+    assert_eq!(udf.len(), files.len() + 1);
+
+    // Each set starts on a 16-block boundary, the menu, first title and backup at the sectors
+    // the set's IFO gives, the sets first, in order
+    let start = |name: &str| udf.iter().find(|(path, _, _)| path == &format!("/VIDEO_TS/{}", name)).unwrap().1;
+    let (manager, title_set) = (start("VIDEO_TS.IFO"), start("VTS_01_0.IFO"));
+    assert_eq!((manager % 16, title_set % 16), (0, 0));
+    let offsets: Vec<u32> = files.iter().map(|(name, _)| start(name) - if name.starts_with("VIDEO") { manager } else { title_set }).collect();
+    assert_eq!(offsets, [0, 3, 7, 0, 2, 5, 10, 12]);
+    let readme = udf.iter().find(|(path, _, _)| path == "/readme.txt").unwrap().1;
+    assert!(manager < title_set && start("VTS_01_0.BUP") < readme, "other files come after the title sets");
+
+    let result = build(&["--dvd-video", "--single-pass"], &source);
+    assert!(!result.status.success(), "--single-pass can't write the UDF bridge");
+    let result = build(&["--dvd-video"], &video);
+    assert!(!result.status.success(), "a tree without VIDEO_TS at its root");
+    assert!(String::from_utf8_lossy(&result.stderr).contains("VIDEO_TS directory"), "{}", String::from_utf8_lossy(&result.stderr));
+    fs::remove_dir_all(&scratch).unwrap();
+}
@@ -0,0 +1,80 @@
+// The --elevate read helper: it only serves the user who owns the socket's directory, and only
+// for paths under the source roots it was given
+
+#![cfg(target_os = "linux")]
+
+mod common;
+
+use std::fs;
+use std::io::{Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixListener;
+use std::path::Path;
+use std::process::Child;
+
+use common::{makeiso, run, scratch_dir};
+
+fn start_helper(socket: &Path, root: &Path) -> Child {
+    makeiso().env_remove("SUDO_UID").env_remove("DOAS_USER").arg("read-helper").arg(socket).arg(root).spawn().expect("failed to run makeiso")
+}
+
+// Send a request and read the status of its answer with the errno of a refusal
+fn ask(socket: &mut std::os::unix::net::UnixStream, kind: u8, path: &Path) -> (u8, Option<u32>) {
+    let path = path.as_os_str().as_encoded_bytes();
+    let mut request = vec![kind];
+    request.extend_from_slice(&(path.len() as u32).to_le_bytes());
+    request.extend_from_slice(path);
+    socket.write_all(&request).unwrap();
+    let mut status = [0u8];
+    socket.read_exact(&mut status).unwrap();
+    if status[0] == 0 {
+        return (0, None);
+    }
+    let mut errno = [0u8; 4];
+    socket.read_exact(&mut errno).unwrap();
+    (status[0], Some(u32::from_le_bytes(errno)))
+}
+
+#[test]
+fn helper_stays_within_the_sources() {
+    let scratch = scratch_dir("elevate");
+    let source = scratch.join("source");
+    fs::create_dir_all(source.join("inner")).unwrap();
+    fs::write(source.join("inner/file.txt"), "readable\n").unwrap();
+    std::os::unix::fs::symlink("/etc/passwd", source.join("escape")).unwrap();
+
+    // A socket in a directory others can get at is refused before connecting
+    let open_dir = scratch.join("open");
+    fs::create_dir(&open_dir).unwrap();
+    fs::set_permissions(&open_dir, fs::Permissions::from_mode(0o755)).unwrap();
+    let _listener = UnixListener::bind(open_dir.join("socket")).unwrap();
+    let result = makeiso().env_remove("SUDO_UID").env_remove("DOAS_USER").arg("read-helper").arg(open_dir.join("socket")).arg(&source).output().unwrap();
+    assert!(!result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr).contains("with mode 0700"), "{}", String::from_utf8_lossy(&result.stderr));
+
+    let private_dir = scratch.join("private");
+    fs::create_dir(&private_dir).unwrap();
+    fs::set_permissions(&private_dir, fs::Permissions::from_mode(0o700)).unwrap();
+    let listener = UnixListener::bind(private_dir.join("socket")).unwrap();
+    let mut helper = start_helper(&private_dir.join("socket"), &source);
+    let (mut socket, _) = listener.accept().unwrap();
+    assert_eq!(ask(&mut socket, b'S', &source.join("inner/file.txt")), (0, None));
+    let mut stat = [0u8; 37];
+    socket.read_exact(&mut stat).unwrap();
+    assert_eq!(u64::from_le_bytes(stat[1..9].try_into().unwrap()), 9);
+
+    // Outside the roots, directly, through .. or through a link, is refused with EACCES
+    for path in [Path::new("/etc/passwd").to_path_buf(), source.join("inner/../../open"), source.join("escape")] {
+        assert_eq!(ask(&mut socket, b'S', &path), (1, Some(13)), "{}", path.display());
+        assert_eq!(ask(&mut socket, b'O', &path), (1, Some(13)), "{}", path.display());
+    }
+    assert_eq!(ask(&mut socket, b'L', Path::new("/etc")), (1, Some(13)));
+    assert_eq!(ask(&mut socket, b'O', &source.join("inner/file.txt")).0, 0);
+    drop(socket);
+    assert!(helper.wait().unwrap().success());
+
+    // And a build through it works as one without
+    let result = run(&[&"--elevate", &env!("CARGO_BIN_EXE_makeiso"), &source, &scratch.join("image.iso")]);
+    assert!(result.status.success(), "makeiso --elevate failed: {}", String::from_utf8_lossy(&result.stderr));
+    fs::remove_dir_all(&scratch).unwrap();
+}
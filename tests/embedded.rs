@@ -0,0 +1,67 @@
+// Embedded metadata (--embed-metadata): the .makeiso directory that describes the image
+
+mod common;
+
+use std::fs;
+
+use common::{FIXTURE_TIME, Expected, makeiso, reproducible_build, scratch_dir, read_image};
+
+#[test]
+fn embedded_metadata() {
+    let scratch = scratch_dir("embed");
+    let source = scratch.join("source");
+    fs::create_dir_all(source.join("docs")).unwrap();
+    fs::write(source.join("docs/notes.txt"), "notes\n").unwrap();
+    let image = scratch.join("image.iso");
+    let build = |extra: &[&str]| {
+        let result = reproducible_build(extra, &source, &image);
+        assert!(result.status.success(), "makeiso {:?} failed: {}", extra, String::from_utf8_lossy(&result.stderr));
+        read_image(&image)
+    };
+    let file = |tree: &[(String, Expected)], path: &str| match tree.iter().find(|(found, _)| found == path) {
+        Some((_, Expected::File(contents))) => String::from_utf8(contents.clone()).unwrap(),
+        _ => panic!("{} is missing", path),
+    };
+
+    let tree = build(&["--embed-metadata", "--volume-id", "NOTES"]);
+    let info = file(&tree, "/.makeiso/info.json");
+    for part in [format!("\"version\":\"{}\"", env!("CARGO_PKG_VERSION")), "\"created\":\"2020-01-02 03:04:05\"".to_string(), "\"host\":null,\"command_line\":[]".to_string(), "\"volume_id\":\"NOTES\"".to_string()] {
+        assert!(info.contains(&part), "{} lacks {}", info, part);
+    }
+    // Otherwise the host and the command line are recorded
+    let result = makeiso().args(["--force", "--embed-metadata"]).arg(&source).arg(&image).output().expect("failed to run makeiso");
+    assert!(result.status.success(), "makeiso failed: {}", String::from_utf8_lossy(&result.stderr));
+    // (its times aren't the fixture's, so straight from the image's bytes)
+    let bytes = fs::read(&image).unwrap();
+    let start = bytes.windows(17).position(|window| window == b"{\"tool\":\"makeiso\"").expect("no info.json in the image");
+    let info = String::from_utf8_lossy(&bytes[start..start + bytes[start..].iter().position(|&byte| byte == b'\n').unwrap()]).into_owned();
+    assert!(info.contains("\"--embed-metadata\"") && !info.contains("\"host\":null"), "{}", info);
+    assert_eq!(
+        file(&tree, "/.makeiso/catalog.json"),
+        "{\"entries\":[\n{\"path\":\"/docs\",\"type\":\"directory\",\"modified\":\"2020-01-02 03:04:05\"},\n{\"path\":\"/docs/notes.txt\",\"type\":\"file\",\"size\":6,\"modified\":\"2020-01-02 03:04:05\"}\n]}\n"
+    );
+    // A reproducible image doesn't depend on how makeiso was called
+    let other = scratch.join("elsewhere/other.iso");
+    fs::create_dir_all(other.parent().unwrap()).unwrap();
+    let result = reproducible_build(&["--volume-id", "NOTES", "--embed-metadata"], &source, &other);
+    assert!(result.status.success(), "makeiso failed: {}", String::from_utf8_lossy(&result.stderr));
+    build(&["--embed-metadata", "--volume-id", "NOTES"]);
+    assert!(fs::read(&image).unwrap() == fs::read(&other).unwrap(), "the images differ with other arguments");
+    assert!(!build(&["--embed-metadata", "--no-embed-metadata"]).iter().any(|(path, _)| path.starts_with("/.makeiso")));
+
+    // Profiles embed it unless they say otherwise
+    let config = scratch.join("makeiso.toml");
+    let profile = |extra: &str| {
+        fs::write(&config, format!("[profiles.notes]\nsource = {:?}\noutput = {:?}\nreproducible = true\nsource-date-epoch = {}\n{}", source, image, FIXTURE_TIME, extra)).unwrap();
+        let result = makeiso()
+            .env("MAKEISO_STATE_DIR", &scratch)
+            .args(["run", "--config", config.to_str().unwrap(), "notes", "--force"])
+            .output()
+            .expect("failed to run makeiso");
+        assert!(result.status.success(), "makeiso run failed: {}", String::from_utf8_lossy(&result.stderr));
+        read_image(&image)
+    };
+    assert!(file(&profile(""), "/.makeiso/info.json").contains("\"profile\":\"notes\""));
+    assert!(!profile("no-embed-metadata = true").iter().any(|(path, _)| path.starts_with("/.makeiso")));
+    fs::remove_dir_all(&scratch).unwrap();
+}
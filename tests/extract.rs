@@ -0,0 +1,167 @@
+// makeiso extract: some paths only, into files that exist, with several workers, and checked
+// against the manifest --checksums writes
+
+mod common;
+
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::{Duration, UNIX_EPOCH};
+
+use common::{makeiso, run, scratch_dir, extract_image, walk, sorted, assert_same, sha256sum};
+
+#[test]
+fn partial_extraction() {
+    let scratch = scratch_dir("partial");
+    let source = scratch.join("source");
+    for name in ["etc/app.conf", "etc/ssh/sshd_config", "etc/log/boot.log", "var/log/syslog.log", "home/notes.txt", "debug.log"] {
+        let path = source.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, name).unwrap();
+    }
+    let image = scratch.join("image.iso");
+    let result = run(&[&source, &image]);
+    assert!(result.status.success(), "makeiso failed: {}", String::from_utf8_lossy(&result.stderr));
+    let extracted = |args: &[&str]| {
+        let destination = scratch.join("out");
+        let _ = fs::remove_dir_all(&destination);
+        let result = makeiso().arg("extract").args(args).arg(&image).arg(&destination).output().expect("failed to run makeiso");
+        assert!(result.status.success(), "makeiso extract {:?} failed: {}", args, String::from_utf8_lossy(&result.stderr));
+        let mut found = Vec::new();
+        walk(&destination, "", &mut found);
+        sorted(found).into_iter().map(|(path, _)| path).collect::<Vec<_>>()
+    };
+
+    assert_eq!(extracted(&["--include", "etc/**", "--exclude", "**/*.log"]), ["/etc", "/etc/app.conf", "/etc/log", "/etc/ssh", "/etc/ssh/sshd_config"]);
+    // A directory that matches brings its subtree; names without a "/" match at any depth
+    assert_eq!(extracted(&["--include", "/etc/ssh", "--include", "*.txt"]), ["/etc", "/etc/ssh", "/etc/ssh/sshd_config", "/home", "/home/notes.txt"]);
+    assert_eq!(extracted(&["--exclude", "log", "--exclude", "etc"]), ["/debug.log", "/home", "/home/notes.txt", "/var"]);
+    fs::remove_dir_all(&scratch).unwrap();
+}
+
+#[test]
+fn extraction_into_existing_files() {
+    let scratch = scratch_dir("overwrite");
+    let source = scratch.join("source");
+    fs::create_dir_all(&source).unwrap();
+    for name in ["old.txt", "new.txt", "missing.txt"] {
+        fs::write(source.join(name), format!("image {}", name)).unwrap();
+        fs::File::options().write(true).open(source.join(name)).unwrap().set_modified(UNIX_EPOCH + Duration::from_secs(1_600_000_000)).unwrap();
+    }
+    let image = scratch.join("image.iso");
+    let result = run(&[&source, &image]);
+    assert!(result.status.success(), "makeiso failed: {}", String::from_utf8_lossy(&result.stderr));
+    let destination = scratch.join("restore");
+    let extract = |args: &[&str], answers: &str| {
+        fs::create_dir_all(&destination).unwrap();
+        for (name, modified) in [("old.txt", 1_500_000_000), ("new.txt", 1_700_000_000)] {
+            fs::write(destination.join(name), "local").unwrap();
+            fs::File::options().write(true).open(destination.join(name)).unwrap().set_modified(UNIX_EPOCH + Duration::from_secs(modified)).unwrap();
+        }
+        let _ = fs::remove_file(destination.join("missing.txt"));
+        let mut child = makeiso()
+            .arg("extract")
+            .args(args)
+            .arg(&image)
+            .arg(&destination)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("failed to run makeiso");
+        child.stdin.take().unwrap().write_all(answers.as_bytes()).unwrap();
+        let result = child.wait_with_output().unwrap();
+        assert!(result.status.success(), "makeiso extract {:?} failed: {}", args, String::from_utf8_lossy(&result.stderr));
+        let contents = ["old.txt", "new.txt", "missing.txt"].map(|name| fs::read_to_string(destination.join(name)).unwrap());
+        (contents, String::from_utf8_lossy(&result.stdout).into_owned())
+    };
+
+    let (contents, report) = extract(&[], "");
+    assert_eq!(contents, ["image old.txt", "image new.txt", "image missing.txt"]);
+    assert!(report.contains("Overwrote 2 files that were already there"), "{}", report);
+    let (contents, report) = extract(&["--keep-existing"], "");
+    assert_eq!(contents, ["local", "local", "image missing.txt"]);
+    assert!(report.contains("Kept 2 files that were already there"), "{}", report);
+    assert_eq!(extract(&["--overwrite", "newer"], "").0, ["image old.txt", "local", "image missing.txt"]);
+    // Answered in the order the entries come in the image
+    let (contents, report) = extract(&["--overwrite", "prompt"], "n\ny\n");
+    assert_eq!(contents, ["image old.txt", "local", "image missing.txt"]);
+    assert!(report.contains("replace it?"), "{}", report);
+    assert_eq!(extract(&["--overwrite", "prompt"], "a\n").0, ["image old.txt", "image new.txt", "image missing.txt"]);
+    fs::remove_dir_all(&scratch).unwrap();
+}
+
+#[test]
+fn parallel_extraction() {
+    let scratch = scratch_dir("workers");
+    let source = scratch.join("source");
+    for index in 0..120 {
+        let dir = source.join(format!("dir{}", index % 7));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(format!("file{}.txt", index)), format!("contents of file {}\n", index).repeat(index)).unwrap();
+    }
+    // Several chunks, so its pieces queue up behind the writer
+    fs::write(source.join("large.bin"), (0..3_500_000u32).map(|byte| (byte % 251) as u8).collect::<Vec<u8>>()).unwrap();
+    let image = scratch.join("image.iso");
+    let result = run(&[&source, &image]);
+    assert!(result.status.success(), "makeiso failed: {}", String::from_utf8_lossy(&result.stderr));
+    let expected = extract_image(&image, &scratch.join("plain"));
+    let modified = |destination: &Path| fs::metadata(destination.join("dir3/file10.txt")).unwrap().modified().unwrap();
+
+    for args in [&["--workers", "4"][..], &["--workers", "4", "--ordered"]] {
+        let destination = scratch.join(args.join(""));
+        let result = makeiso().arg("extract").args(args).arg(&image).arg(&destination).output().expect("failed to run makeiso");
+        assert!(result.status.success(), "makeiso extract {:?} failed: {}", args, String::from_utf8_lossy(&result.stderr));
+        let mut found = Vec::new();
+        walk(&destination, "", &mut found);
+        assert_same(&sorted(found), &expected, &args.join(" "));
+        assert_eq!(modified(&destination), modified(&scratch.join("plain")), "{:?}", args);
+    }
+    fs::remove_dir_all(&scratch).unwrap();
+}
+
+#[test]
+fn checked_against_the_manifest() {
+    let scratch = scratch_dir("checksums");
+    let source = scratch.join("source");
+    fs::create_dir_all(source.join("docs")).unwrap();
+    fs::write(source.join("docs/guide.txt"), "a guide worth keeping intact\n").unwrap();
+    fs::write(source.join("empty.txt"), "").unwrap();
+    fs::write(source.join("large.bin"), (0..1_500_000u32).map(|byte| (byte % 251) as u8).collect::<Vec<u8>>()).unwrap();
+    let image = scratch.join("image.iso");
+    let result = run(&[&"--checksums", &source, &image]);
+    assert!(result.status.success(), "makeiso failed: {}", String::from_utf8_lossy(&result.stderr));
+    let result = run(&[&"cat", &image, &"/SHA256SUMS"]);
+    let manifest = String::from_utf8_lossy(&result.stdout).into_owned();
+    let paths: Vec<&str> = manifest.lines().map(|line| line.split_once("  ").unwrap().1).collect();
+    assert_eq!(paths, ["docs/guide.txt", "empty.txt", "large.bin"]);
+    if let Some(digest) = sha256sum(b"a guide worth keeping intact\n") {
+        assert!(manifest.starts_with(&format!("{}  docs/guide.txt\n", digest)), "{}", manifest);
+    }
+
+    let extract = |image: &Path, args: &[&str]| {
+        let destination = scratch.join(format!("out{}", args.join("")));
+        let _ = fs::remove_dir_all(&destination);
+        makeiso().arg("extract").args(args).arg(image).arg(&destination).output().expect("failed to run makeiso")
+    };
+    for args in [&[][..], &["--workers", "2"]] {
+        let result = extract(&image, args);
+        assert!(result.status.success(), "makeiso extract {:?} failed: {}", args, String::from_utf8_lossy(&result.stderr));
+        assert!(String::from_utf8_lossy(&result.stdout).contains("3 files matched the manifest"), "{}", String::from_utf8_lossy(&result.stdout));
+    }
+
+    // A file changed in the image is read again, then reported rather than passed off as intact
+    let mut bytes = fs::read(&image).unwrap();
+    let at = bytes.windows(5).position(|window| window == b"worth").unwrap();
+    bytes[at] = b'W';
+    let damaged = scratch.join("damaged.iso");
+    fs::write(&damaged, bytes).unwrap();
+    for args in [&[][..], &["--workers", "2"]] {
+        let result = extract(&damaged, args);
+        assert!(!result.status.success(), "{:?}", args);
+        let stderr = String::from_utf8_lossy(&result.stderr);
+        assert!(stderr.contains("/docs/guide.txt does not match the manifest, reading it again (2/2)"), "{:?}: {}", args, stderr);
+    }
+    fs::remove_dir_all(&scratch).unwrap();
+}
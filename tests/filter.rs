@@ -0,0 +1,43 @@
+// Filtered sources: --min-mtime, --max-mtime, --max-file-size and --type deciding what goes in
+
+mod common;
+
+use std::fs;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use common::{Expected, run, reproducible_build, scratch_dir, read_image};
+
+#[test]
+#[cfg(unix)]
+fn filtered_sources() {
+    let scratch = scratch_dir("filter");
+    let source = scratch.join("source");
+    fs::create_dir_all(source.join("docs/old")).unwrap();
+    let (old, new) = (UNIX_EPOCH + Duration::from_secs(1_600_000_000), UNIX_EPOCH + Duration::from_secs(1_780_000_000));
+    let files: [(&str, usize, SystemTime); 3] = [("docs/report.txt", 10, new), ("docs/old/letter.txt", 10, old), ("docs/video.bin", 100 * 1024, new)];
+    for (name, size, modified) in files {
+        fs::write(source.join(name), vec![b'x'; size]).unwrap();
+        fs::File::options().write(true).open(source.join(name)).unwrap().set_modified(modified).unwrap();
+    }
+    std::os::unix::fs::symlink("report.txt", source.join("docs/latest.txt")).unwrap();
+    let image = scratch.join("image.iso");
+    let files = |extra: &[&str]| {
+        let result = reproducible_build(extra, &source, &image);
+        assert!(result.status.success(), "makeiso {:?} failed: {}", extra, String::from_utf8_lossy(&result.stderr));
+        read_image(&image).into_iter().filter(|(_, kind)| *kind != Expected::Directory).map(|(path, _)| path).collect::<Vec<_>>()
+    };
+
+    assert_eq!(files(&["--min-mtime", "2026-01-01"]), ["/docs/latest.txt", "/docs/report.txt", "/docs/video.bin"]);
+    assert_eq!(files(&["--max-mtime", "2020-12-31 23:59:59"]), ["/docs/old/letter.txt"]);
+    assert_eq!(files(&["--max-file-size", "64K", "--min-mtime", "2026-01-01"]), ["/docs/latest.txt", "/docs/report.txt"]);
+    assert_eq!(files(&["--type", "l"]), ["/docs/latest.txt"]);
+    assert_eq!(files(&["--type", "f", "--max-file-size", "10"]), ["/docs/old/letter.txt", "/docs/report.txt"]);
+    // Directories stay, empty or not
+    let tree = read_image(&image);
+    assert!(tree.iter().any(|(path, kind)| path == "/docs/old" && *kind == Expected::Directory));
+    assert!(files(&["--type", "d"]).is_empty());
+
+    let result = run(&[&"--type", &"p", &source, &image]);
+    assert!(!result.status.success() && String::from_utf8_lossy(&result.stderr).contains("invalid --type p"));
+    fs::remove_dir_all(&scratch).unwrap();
+}
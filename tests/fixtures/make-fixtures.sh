@@ -0,0 +1,40 @@
+#!/bin/sh
+# Regenerate the fixture images the golden tests (tests/golden.rs) read. They are made by
+# libarchive's ISO 9660 writer (bsdtar), not by makeiso, so the reader is checked against
+# images it had no part in; the expected listings are in tests/golden.rs.
+#
+# The request asked for fixtures from mkisofs/genisoimage or xorriso. Neither was installed
+# where these were made, and nothing could be installed there, so bsdtar stands in: it is a
+# separate ISO 9660 implementation too, but not the one most images in the wild come from.
+# Images from xorriso would need the same listings; regenerating with it means replacing the
+# bsdtar lines at the end and checking tests/golden.rs still passes.
+#
+#     tests/fixtures/make-fixtures.sh [bsdtar]
+set -e
+BSDTAR=${1:-bsdtar}
+cd "$(dirname "$0")"
+WORK=$(mktemp -d)
+trap 'rm -rf "$WORK"' EXIT
+
+# The tree every image holds; plain ISO 9660 allows 8 levels and no symbolic links
+mkdir -p "$WORK/plain/docs/notes" "$WORK/plain/Deep/a/b/c/d/e"
+printf 'hello\n' > "$WORK/plain/readme.txt"
+head -c 5000 /dev/zero | tr '\0' 'x' > "$WORK/plain/docs/report.bin"
+: > "$WORK/plain/empty"
+printf 'nothing\n' > "$WORK/plain/docs/notes/todo.txt"
+printf 'deep\n' > "$WORK/plain/Deep/a/b/c/d/e/bottom.txt"
+printf 'long\n' > "$WORK/plain/A Long Mixed-Case Name.text"
+head -c 2048 /dev/zero > "$WORK/plain/boot.img"
+
+# With Rock Ridge, a symbolic link and a directory deep enough to be relocated as well
+cp -R "$WORK/plain" "$WORK/rockridge"
+mkdir -p "$WORK/rockridge/Deep/a/b/c/d/e/f/g/h"
+printf 'deep\n' > "$WORK/rockridge/Deep/a/b/c/d/e/f/g/h/bottom.txt"
+ln -s docs/notes/todo.txt "$WORK/rockridge/link"
+
+find "$WORK" -exec touch -h -d '2020-01-02 03:04:05 UTC' {} +
+
+"$BSDTAR" -cf plain.iso --format iso9660 --options 'iso9660:!rockridge,!joliet,!pad' -C "$WORK/plain" .
+"$BSDTAR" -cf joliet.iso --format iso9660 --options 'iso9660:!rockridge,joliet,!pad' -C "$WORK/plain" .
+"$BSDTAR" -cf rockridge.iso --format iso9660 --options 'iso9660:rockridge,!joliet,!pad' -C "$WORK/rockridge" .
+"$BSDTAR" -cf eltorito.iso --format iso9660 --options 'iso9660:boot=boot.img,boot-type=no-emulation,!pad' -C "$WORK/rockridge" .
@@ -0,0 +1,224 @@
+// Compatibility with images other tools wrote, and with the tools that check images
+//
+// The fixtures in tests/fixtures were made by libarchive's ISO 9660 writer (bsdtar, see
+// make-fixtures.sh there): plain ISO 9660, Joliet, Rock Ridge and El Torito. The reader must
+// list them, and makeiso extract unpack them, exactly as the tree they were made from. The
+// other way round, images makeiso writes are handed to isovfy, xorriso and bsdtar, each only
+// when it is installed.
+
+mod common;
+
+use std::fs;
+use std::process::Command;
+
+use makeiso::reader::IsoReader;
+
+use common::{Expected, run, fixture, scratch_dir, read_image, extract_image, sorted, assert_same, installed};
+
+// The tree of plain.iso and joliet.iso, by path from the root
+fn plain_tree() -> Vec<(String, Expected)> {
+    let mut tree: Vec<(String, Expected)> = ["/Deep", "/Deep/a", "/Deep/a/b", "/Deep/a/b/c", "/Deep/a/b/c/d", "/Deep/a/b/c/d/e", "/docs", "/docs/notes"]
+        .iter()
+        .map(|path| (path.to_string(), Expected::Directory))
+        .collect();
+    let files: [(&str, Vec<u8>); 7] = [
+        ("/readme.txt", b"hello\n".to_vec()),
+        ("/docs/report.bin", vec![b'x'; 5000]),
+        ("/empty", Vec::new()),
+        ("/docs/notes/todo.txt", b"nothing\n".to_vec()),
+        ("/Deep/a/b/c/d/e/bottom.txt", b"deep\n".to_vec()),
+        ("/A Long Mixed-Case Name.text", b"long\n".to_vec()),
+        ("/boot.img", vec![0; 2048]),
+    ];
+    tree.extend(files.into_iter().map(|(path, contents)| (path.to_string(), Expected::File(contents))));
+    tree
+}
+
+// The tree of plain.iso by its plain identifiers: the 8.3 names libarchive made up, in capitals
+fn plain_identifiers() -> Vec<(String, Expected)> {
+    let identifier = |path: String| if path == "/A Long Mixed-Case Name.text" { "/A_LONG_M.TEX".to_string() } else { path.to_uppercase() };
+    plain_tree().into_iter().map(|(path, expected)| (identifier(path), expected)).collect()
+}
+
+// The tree of rockridge.iso and eltorito.iso: deeper, and with a symbolic link
+fn rock_ridge_tree() -> Vec<(String, Expected)> {
+    let mut tree = plain_tree();
+    for path in ["/Deep/a/b/c/d/e/f", "/Deep/a/b/c/d/e/f/g", "/Deep/a/b/c/d/e/f/g/h"] {
+        tree.push((path.to_string(), Expected::Directory));
+    }
+    tree.push(("/Deep/a/b/c/d/e/f/g/h/bottom.txt".to_string(), Expected::File(b"deep\n".to_vec())));
+    tree.push(("/link".to_string(), Expected::Symlink("docs/notes/todo.txt".to_string())));
+    tree
+}
+
+// Check a fixture through both the reader and makeiso extract
+fn check_fixture(name: &str, expected: Vec<(String, Expected)>) {
+    let image = fixture(name);
+    let expected = sorted(expected);
+    assert_same(&read_image(&image), &expected, name);
+
+    let scratch = scratch_dir(name);
+    assert_same(&extract_image(&image, &scratch.join("out")), &expected, &format!("{} extracted", name));
+    fs::remove_dir_all(&scratch).unwrap();
+}
+
+#[test]
+fn plain_iso9660_fixture() {
+    check_fixture("plain.iso", plain_identifiers());
+}
+
+// makeiso ls starts with the descriptor's own fields, as it always has
+#[test]
+fn descriptor_listing() {
+    let result = run(&[&"ls", &fixture("plain.iso")]);
+    assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+    let listing = String::from_utf8_lossy(&result.stdout);
+    assert_eq!(
+        listing.lines().next(),
+        Some("Primary Volume Descriptor: PrimaryVolumeDescriptor { volume_id: \"CDROM\", volume_space_size: 40, root_directory_extent: 23, root_directory_size: 2048 }")
+    );
+}
+
+#[test]
+fn joliet_fixture() {
+    check_fixture("joliet.iso", plain_tree());
+}
+
+#[test]
+#[cfg(unix)]
+fn rock_ridge_fixture() {
+    check_fixture("rockridge.iso", rock_ridge_tree());
+}
+
+#[test]
+#[cfg(unix)]
+fn el_torito_fixture() {
+    let image = fixture("eltorito.iso");
+    let mut reader = IsoReader::open(fs::File::open(&image).unwrap()).unwrap();
+    let entries = reader.boot_entries().unwrap();
+    assert_eq!(entries.len(), 1, "one boot entry");
+    assert!(entries[0].bootable && entries[0].emulation == 0, "a bootable no-emulation entry: {:?}", entries[0]);
+    let boot_image = reader.read_boot_image(&entries[0]).unwrap();
+    assert!(!boot_image.is_empty() && boot_image.iter().all(|&byte| byte == 0), "the boot image is boot.img");
+    assert_same(&read_image(&image), &sorted(rock_ridge_tree()), "eltorito.iso");
+}
+
+#[test]
+#[cfg(unix)]
+fn boot_record_before_the_primary_descriptor() {
+    // The descriptor set may come in any order from sector 16
+    let scratch = scratch_dir("order");
+    let mut image = fs::read(fixture("eltorito.iso")).unwrap();
+    let (primary, boot) = (16 * 2048, 17 * 2048);
+    let descriptor = image[primary..boot].to_vec();
+    image.copy_within(boot..boot + 2048, primary);
+    image[boot..boot + 2048].copy_from_slice(&descriptor);
+    let swapped = scratch.join("swapped.iso");
+    fs::write(&swapped, &image).unwrap();
+
+    let reader = IsoReader::open(fs::File::open(&swapped).unwrap()).unwrap();
+    assert!(reader.boot_catalog.is_some(), "the boot record is found first");
+    assert_same(&read_image(&swapped), &sorted(rock_ridge_tree()), "eltorito.iso reordered");
+    fs::remove_dir_all(&scratch).unwrap();
+}
+
+// Rewrite a plain ISO 9660 image as High Sierra: descriptors with their block number in front
+// and "CDROM", and directory records with the time zone byte dropped from their dates
+fn to_high_sierra(image: &mut [u8]) {
+    let mut directories = Vec::new();
+    let mut reader = IsoReader::open(std::io::Cursor::new(&image[..])).unwrap();
+    for entry in reader.read_tree().unwrap().iter().filter(|entry| entry.is_directory) {
+        directories.push((entry.extent as usize, entry.size as usize));
+    }
+    let record = |record: &mut [u8]| {
+        record[24] = record[25];
+        record[25] = 0;
+    };
+    for (extent, size) in directories {
+        for block in (extent * 2048..extent * 2048 + size).step_by(2048) {
+            let mut offset = 0;
+            while offset < 2048 && image[block + offset] >= 34 {
+                let len = image[block + offset] as usize;
+                record(&mut image[block + offset..block + offset + len]);
+                offset += len;
+            }
+        }
+    }
+
+    let primary = image[16 * 2048..17 * 2048].to_vec();
+    let descriptor = |block: u32, kind: u8| {
+        let mut descriptor = vec![0u8; 2048];
+        descriptor[..4].copy_from_slice(&block.to_le_bytes());
+        descriptor[4..8].copy_from_slice(&block.to_be_bytes());
+        descriptor[8] = kind;
+        descriptor[9..14].copy_from_slice(b"CDROM");
+        descriptor[14] = 1;
+        descriptor
+    };
+    let mut sierra = descriptor(16, 1);
+    sierra[16..80].copy_from_slice(&primary[8..72]); // System and volume identifiers
+    sierra[88..96].copy_from_slice(&primary[80..88]); // Volume space size
+    sierra[136..140].copy_from_slice(&primary[128..132]); // Logical block size
+    sierra[180..214].copy_from_slice(&primary[156..190]); // Root directory record
+    record(&mut sierra[180..214]);
+    sierra[790..806].copy_from_slice(&primary[813..829]); // Creation date, without a time zone
+    image[16 * 2048..17 * 2048].copy_from_slice(&sierra);
+    image[17 * 2048..18 * 2048].copy_from_slice(&descriptor(17, 255));
+}
+
+#[test]
+fn high_sierra_image() {
+    let scratch = scratch_dir("sierra");
+    let mut image = fs::read(fixture("plain.iso")).unwrap();
+    to_high_sierra(&mut image);
+    let sierra = scratch.join("sierra.iso");
+    fs::write(&sierra, &image).unwrap();
+
+    assert!(IsoReader::open(fs::File::open(&sierra).unwrap()).unwrap().high_sierra, "taken for High Sierra");
+    let expected = sorted(plain_identifiers());
+    assert_same(&read_image(&sierra), &expected, "High Sierra");
+    assert_same(&extract_image(&sierra, &scratch.join("out")), &expected, "High Sierra extracted");
+    fs::remove_dir_all(&scratch).unwrap();
+}
+
+#[test]
+fn makeiso_images_pass_other_tools() {
+    let scratch = scratch_dir("tools");
+    let source = scratch.join("source");
+    fs::create_dir_all(source.join("docs/notes")).unwrap();
+    fs::write(source.join("readme.txt"), "hello\n").unwrap();
+    fs::write(source.join("docs/report.bin"), vec![7u8; 5000]).unwrap();
+    fs::write(source.join("docs/notes/todo.txt"), "nothing\n").unwrap();
+    let image = scratch.join("image.iso");
+    let result = run(&[&source, &image]);
+    assert!(result.status.success(), "makeiso failed: {}", String::from_utf8_lossy(&result.stderr));
+
+    if installed("isovfy") {
+        let result = Command::new("isovfy").arg(&image).output().unwrap();
+        let report = String::from_utf8_lossy(&result.stdout);
+        assert!(result.status.success() && !report.contains("***"), "isovfy found problems:\n{}", report);
+    } else {
+        eprintln!("isovfy is not installed, skipping it");
+    }
+
+    if installed("xorriso") {
+        let result = Command::new("xorriso").args(["-abort_on", "FAILURE", "-return_with", "FAILURE", "32", "-indev"]).arg(&image).args(["-find", "/", "-exec", "report_damage"]).output().unwrap();
+        assert!(result.status.success(), "xorriso rejected the image:\n{}", String::from_utf8_lossy(&result.stderr));
+    } else {
+        eprintln!("xorriso is not installed, skipping it");
+    }
+
+    if installed("bsdtar") {
+        let unpacked = scratch.join("bsdtar");
+        fs::create_dir_all(&unpacked).unwrap();
+        let result = Command::new("bsdtar").arg("-xf").arg(&image).arg("-C").arg(&unpacked).output().unwrap();
+        assert!(result.status.success(), "bsdtar rejected the image: {}", String::from_utf8_lossy(&result.stderr));
+        for path in ["readme.txt", "docs/report.bin", "docs/notes/todo.txt"] {
+            assert_eq!(fs::read(unpacked.join(path)).unwrap(), fs::read(source.join(path)).unwrap(), "bsdtar unpacks {} differently", path);
+        }
+    } else {
+        eprintln!("bsdtar is not installed, skipping it");
+    }
+
+    fs::remove_dir_all(&scratch).unwrap();
+}
@@ -0,0 +1,32 @@
+// makeiso grep: matching lines in an image's files, and an exit status that says whether
+// there were any, as grep(1)'s does
+
+mod common;
+
+use std::fs;
+
+use common::{reproducible_build, run, scratch_dir};
+
+#[test]
+fn exit_status_tells_whether_anything_matched() {
+    let scratch = scratch_dir("grep");
+    let source = scratch.join("source");
+    fs::create_dir_all(source.join("etc")).unwrap();
+    fs::write(source.join("etc/app.conf"), "name = app\nlisten_port = 8080\n").unwrap();
+    fs::write(source.join("readme.txt"), "port numbers are in etc\n").unwrap();
+    let image = scratch.join("image.iso");
+    assert!(reproducible_build(&[] as &[&str], &source, &image).status.success());
+
+    let result = run(&[&"grep", &image, &"listen_port\\s*="]);
+    assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+    assert_eq!(String::from_utf8_lossy(&result.stdout), "/etc/app.conf:2:listen_port = 8080\n");
+
+    // Nothing matching, in the whole image or in the files the glob picks
+    for args in [&["nothing like this"][..], &["port", "*.md"][..]] {
+        let result = run(&[&"grep", &image, &args[0], &args.get(1).copied().unwrap_or("*")]);
+        assert_eq!(result.status.code(), Some(1), "{:?}: {}", args, String::from_utf8_lossy(&result.stdout));
+        assert!(result.stdout.is_empty());
+        assert!(String::from_utf8_lossy(&result.stderr).contains("no matches for"), "{}", String::from_utf8_lossy(&result.stderr));
+    }
+    fs::remove_dir_all(&scratch).unwrap();
+}
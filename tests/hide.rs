@@ -0,0 +1,36 @@
+// File flags (--hide and --associated) in the directory records, as ls -l shows them, and
+// --hide-joliet refused for want of a Joliet tree
+
+mod common;
+
+use std::fs;
+
+use common::{run, scratch_dir};
+
+#[test]
+fn hidden_and_associated_flags() {
+    let scratch = scratch_dir("hide");
+    let source = scratch.join("source");
+    fs::create_dir_all(source.join("boot/grub")).unwrap();
+    fs::write(source.join("boot/grub/grub.cfg"), "set timeout=5\n").unwrap();
+    fs::write(source.join("readme.txt"), "install me\n").unwrap();
+    fs::write(source.join("readme.rsrc"), "fork\n").unwrap();
+    let image = scratch.join("image.iso");
+    let result = run(&[&"--hide", &"/boot/grub", &"--hide", &"*.cfg", &"--associated", &"*.rsrc", &source, &image]);
+    assert!(result.status.success(), "makeiso failed: {}", String::from_utf8_lossy(&result.stderr));
+
+    let result = run(&[&"ls", &"-l", &image]);
+    let listing = String::from_utf8_lossy(&result.stdout);
+    let flags = |path: &str| listing.lines().find(|line| line.ends_with(&format!(" {}", path))).unwrap_or_else(|| panic!("{} not in {}", path, listing)).split(' ').rev().nth(1).unwrap().to_string();
+    assert_eq!(flags("/boot"), "--");
+    assert_eq!(flags("/boot/grub"), "h-");
+    assert_eq!(flags("/boot/grub/grub.cfg"), "h-");
+    assert_eq!(flags("/readme.rsrc"), "-a");
+    assert_eq!(flags("/readme.txt"), "--");
+
+    let result = run(&[&"--hide-joliet", &"*.cfg", &source, &scratch.join("joliet.iso")]);
+    assert!(!result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr).contains("makeiso writes no Joliet tree"), "{}", String::from_utf8_lossy(&result.stderr));
+    assert!(!scratch.join("joliet.iso").exists());
+    fs::remove_dir_all(&scratch).unwrap();
+}
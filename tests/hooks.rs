@@ -0,0 +1,50 @@
+// Build hooks (--pre-hook and --post-hook)
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+
+use common::{makeiso, scratch_dir};
+
+#[cfg(unix)]
+#[test]
+fn build_hooks() {
+    let scratch = scratch_dir("hooks");
+    let source = scratch.join("source");
+    fs::create_dir_all(&source).unwrap();
+    fs::write(source.join("data.txt"), "quiesced\n").unwrap();
+    let (log, summary) = (scratch.join("log"), scratch.join("summary.json"));
+    let build = |image: &Path, pre_hook: &str| {
+        makeiso()
+            .arg("--pre-hook")
+            .arg(pre_hook)
+            .arg("--post-hook")
+            .arg(format!("echo \"post $MAKEISO_HOOK\" >> '{}'; cat > '{}'", log.display(), summary.display()))
+            .arg(&source)
+            .arg(image)
+            .output()
+            .expect("failed to run makeiso")
+    };
+
+    // The pre-hook runs before the image exists, the post-hook after, with the summary
+    let image = scratch.join("hooked.iso");
+    let result = build(&image, &format!("test ! -e \"$MAKEISO_IMAGE\" && echo pre >> '{}'", log.display()));
+    assert!(result.status.success(), "makeiso failed: {}", String::from_utf8_lossy(&result.stderr));
+    assert_eq!(fs::read_to_string(&log).unwrap(), "pre\npost post\n");
+    let json = fs::read_to_string(&summary).unwrap();
+    assert!(json.contains(&format!("\"image\": \"{}\"", image.display())) && json.contains("\"ok\": true"), "{}", json);
+    assert!(json.contains(&format!("\"bytes\": {}", fs::metadata(&image).unwrap().len())), "{}", json);
+
+    // A failing pre-hook stops the build, and the post-hook hears of it
+    fs::remove_file(&log).unwrap();
+    let image = scratch.join("refused.iso");
+    let result = build(&image, "exit 3");
+    assert!(!result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr).contains("the pre-hook failed"), "{}", String::from_utf8_lossy(&result.stderr));
+    assert!(!image.exists());
+    assert_eq!(fs::read_to_string(&log).unwrap(), "post post\n");
+    let json = fs::read_to_string(&summary).unwrap();
+    assert!(json.contains("\"ok\": false") && json.contains("pre-hook failed"), "{}", json);
+    fs::remove_dir_all(&scratch).unwrap();
+}
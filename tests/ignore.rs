@@ -0,0 +1,35 @@
+// Ignore files in the source tree (--use-gitignore): .gitignore patterns, negations and all
+
+mod common;
+
+use std::fs;
+
+use common::{reproducible_build, scratch_dir, read_image};
+
+#[test]
+fn ignore_files() {
+    let scratch = scratch_dir("ignore");
+    let source = scratch.join("source");
+    for dir in ["src/gen", "target/debug", "docs/build", "logs"] {
+        fs::create_dir_all(source.join(dir)).unwrap();
+    }
+    for name in ["src/main.rs", "src/gen/table.rs", "target/debug/app", "docs/build/index.html", "docs/guide.md", "logs/a.log", "logs/keep.log", "notes.txt"] {
+        fs::write(source.join(name), name).unwrap();
+    }
+    fs::write(source.join(".gitignore"), "# build output\n/target/\n*.log\n!keep.log\nbuild/\n").unwrap();
+    fs::write(source.join(".isoignore"), "notes.txt\n").unwrap();
+    fs::write(source.join("src/.isoignore"), "gen/*.rs\n").unwrap();
+    let image = scratch.join("image.iso");
+    let files = |extra: &[&str]| {
+        let result = reproducible_build(extra, &source, &image);
+        assert!(result.status.success(), "makeiso {:?} failed: {}", extra, String::from_utf8_lossy(&result.stderr));
+        read_image(&image).into_iter().map(|(path, _)| path).collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        files(&[]),
+        ["/.gitignore", "/docs", "/docs/build", "/docs/build/index.html", "/docs/guide.md", "/logs", "/logs/a.log", "/logs/keep.log", "/src", "/src/gen", "/src/main.rs", "/target", "/target/debug", "/target/debug/app"]
+    );
+    assert_eq!(files(&["--use-gitignore"]), ["/.gitignore", "/docs", "/docs/guide.md", "/logs", "/logs/keep.log", "/src", "/src/gen", "/src/main.rs"]);
+    fs::remove_dir_all(&scratch).unwrap();
+}
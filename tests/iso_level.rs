@@ -0,0 +1,43 @@
+// ISO 9660:1999 images (--iso-level 4): the enhanced volume descriptor and its names, next to
+// the primary one
+
+mod common;
+
+use std::fs;
+use std::path::PathBuf;
+
+use makeiso::reader::{IsoReader, NameSource, VolumeDescriptor};
+
+use common::{Expected, reproducible_build, scratch_dir, read_image, sorted, assert_same};
+
+#[test]
+fn iso_level_4_image() {
+    // Deeper than 8 levels, a path longer than 255 bytes and a name longer than 31, none of
+    // which plain ISO 9660 allows
+    let scratch = scratch_dir("level4");
+    let source = scratch.join("source");
+    let deep: PathBuf = (1..=10).map(|level| format!("level {} of a rather deep tree", level)).collect();
+    let long_name = format!("{}.txt", "a long name ".repeat(12));
+    fs::create_dir_all(source.join(&deep)).unwrap();
+    fs::write(source.join(&deep).join(&long_name), "deep\n").unwrap();
+    let image = scratch.join("image.iso");
+    let build = |extra: &[&str]| {
+        reproducible_build(extra, &source, &image)
+    };
+    assert!(!build(&[]).status.success(), "the tree is too deep for plain ISO 9660");
+    let result = build(&["--iso-level", "4"]);
+    assert!(result.status.success(), "makeiso failed: {}", String::from_utf8_lossy(&result.stderr));
+
+    let reader = IsoReader::open(fs::File::open(&image).unwrap()).unwrap();
+    assert_eq!(reader.name_source(), NameSource::Enhanced);
+    assert!(reader.descriptors.iter().any(|(_, descriptor)| *descriptor == VolumeDescriptor::Enhanced), "no enhanced volume descriptor");
+    let mut expected = Vec::new();
+    let mut path = String::new();
+    for component in deep.iter() {
+        path = format!("{}/{}", path, component.to_str().unwrap());
+        expected.push((path.clone(), Expected::Directory));
+    }
+    expected.push((format!("{}/{}", path, long_name), Expected::File(b"deep\n".to_vec())));
+    assert_same(&read_image(&image), &sorted(expected), "an ISO 9660:1999 image");
+    fs::remove_dir_all(&scratch).unwrap();
+}
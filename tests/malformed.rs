@@ -0,0 +1,42 @@
+use std::env;
+use std::fs;
+use std::io::Cursor;
+use std::process::Command;
+
+use makeiso::reader::IsoReader;
+
+// A small image makeiso built, as bytes
+fn sample_image(name: &str) -> Vec<u8> {
+    let dir = env::temp_dir().join(format!("makeiso-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("source/docs")).unwrap();
+    fs::write(dir.join("source/readme.txt"), "hello\n").unwrap();
+    fs::write(dir.join("source/docs/report.bin"), vec![7u8; 5000]).unwrap();
+
+    let output = dir.join("image.iso");
+    let result = Command::new(env!("CARGO_BIN_EXE_makeiso"))
+        .args(["--reproducible", "--source-date-epoch", "1700000000"])
+        .arg(dir.join("source"))
+        .arg(&output)
+        .output()
+        .expect("failed to run makeiso");
+    assert!(result.status.success(), "makeiso failed: {}", String::from_utf8_lossy(&result.stderr));
+    let image = fs::read(&output).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    image
+}
+
+#[test]
+fn hostile_lengths_are_errors() {
+    let mut image = sample_image("malformed");
+    assert!(IsoReader::open(Cursor::new(&image[..])).unwrap().read_tree().is_ok());
+
+    // A root directory claiming 4 GiB, in both byte orders of the primary volume descriptor
+    let root_size = 16 * 2048 + 156 + 10;
+    image[root_size..root_size + 8].copy_from_slice(&[0xff; 8]);
+    assert!(IsoReader::open(Cursor::new(&image[..])).and_then(|mut reader| reader.read_tree()).is_err());
+
+    // An image cut off before its directories
+    image.truncate(17 * 2048);
+    assert!(IsoReader::open(Cursor::new(&image[..])).and_then(|mut reader| reader.read_tree()).is_err());
+}
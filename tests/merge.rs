@@ -0,0 +1,64 @@
+// makeiso merge: several images into one, conflicting files resolved as --conflict says
+
+mod common;
+
+use std::fs;
+use std::time::{Duration, UNIX_EPOCH};
+
+use common::{FIXTURE_TIME, Expected, makeiso, run, scratch_dir, read_image, assert_same};
+
+#[test]
+fn merged_images() {
+    let scratch = scratch_dir("merge");
+    let trees: [&[(&str, &str, u64)]; 2] = [
+        &[("docs/same.txt", "same\n", 1_600_000_000), ("docs/clash.txt", "older\n", 1_600_000_000), ("only-a.txt", "a\n", 1_600_000_000)],
+        &[("docs/same.txt", "same\n", 1_700_000_000), ("docs/clash.txt", "newer\n", 1_700_000_000), ("extra/only-b.txt", "b\n", 1_600_000_000)],
+    ];
+    let mut inputs = Vec::new();
+    for (number, files) in trees.iter().enumerate() {
+        let source = scratch.join(format!("source{}", number));
+        for (name, contents, modified) in files.iter() {
+            let path = source.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, contents).unwrap();
+            fs::File::options().write(true).open(&path).unwrap().set_modified(UNIX_EPOCH + Duration::from_secs(*modified)).unwrap();
+        }
+        let image = scratch.join(format!("{}.iso", number));
+        let result = run(&[&source, &image]);
+        assert!(result.status.success(), "makeiso failed: {}", String::from_utf8_lossy(&result.stderr));
+        inputs.push(image);
+    }
+    let merged = scratch.join("merged.iso");
+    let merge = |extra: &[&str]| {
+        makeiso()
+            .args(["merge", "--force", "--reproducible", "--source-date-epoch", &FIXTURE_TIME.to_string()])
+            .args(&inputs)
+            .arg("-o")
+            .arg(&merged)
+            .args(extra)
+            .output()
+            .expect("failed to run makeiso")
+    };
+    let file = |contents: &str| Expected::File(contents.as_bytes().to_vec());
+    let tree = |extra: &[&str]| {
+        let result = merge(extra);
+        assert!(result.status.success(), "makeiso merge {:?} failed: {}", extra, String::from_utf8_lossy(&result.stderr));
+        read_image(&merged)
+    };
+
+    let mut expected = vec![
+        ("/docs".to_string(), Expected::Directory),
+        ("/docs/clash.txt".to_string(), file("newer\n")),
+        ("/docs/same.txt".to_string(), file("same\n")),
+        ("/extra".to_string(), Expected::Directory),
+        ("/extra/only-b.txt".to_string(), file("b\n")),
+        ("/only-a.txt".to_string(), file("a\n")),
+    ];
+    assert_same(&tree(&[]), &expected, "merge --conflict newest");
+    expected[1].1 = file("older\n");
+    expected.insert(2, ("/docs/clash~2.txt".to_string(), file("newer\n")));
+    assert_same(&tree(&["--conflict", "rename"]), &expected, "merge --conflict rename");
+    let result = merge(&["--conflict", "error"]);
+    assert!(!result.status.success() && String::from_utf8_lossy(&result.stderr).contains("/docs/clash.txt differs between the images"));
+    fs::remove_dir_all(&scratch).unwrap();
+}
@@ -0,0 +1,34 @@
+// Progress of the write: on stderr, and printed at each whole percent rather than per chunk
+
+mod common;
+
+use std::fs;
+
+use common::{reproducible_build, scratch_dir};
+
+#[test]
+fn progress_is_rate_limited_on_stderr() {
+    let scratch = scratch_dir("progress");
+    let source = scratch.join("source");
+    fs::create_dir_all(&source).unwrap();
+    // Many small files, each of which used to print a line of its own
+    for index in 0..500 {
+        fs::write(source.join(format!("file{:03}.txt", index)), vec![b'x'; 1000 + index]).unwrap();
+    }
+    let image = scratch.join("image.iso");
+
+    for extra in [&[][..], &["--single-pass"][..]] {
+        let result = reproducible_build(extra, &source, &image);
+        assert!(result.status.success(), "makeiso {:?} failed: {}", extra, String::from_utf8_lossy(&result.stderr));
+        assert!(!String::from_utf8_lossy(&result.stdout).contains("Progress"), "progress on stdout with {:?}", extra);
+        let stderr = String::from_utf8_lossy(&result.stderr);
+        let lines: Vec<&str> = stderr.lines().filter(|line| line.starts_with("Progress: ")).collect();
+        assert!(lines.len() <= 101, "{} progress lines with {:?}", lines.len(), extra);
+        if extra.is_empty() {
+            let percents: Vec<u64> = lines.iter().map(|line| line["Progress: ".len()..].trim_end_matches('%').parse().unwrap()).collect();
+            assert!(percents.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", percents);
+            assert_eq!(percents.last(), Some(&100));
+        }
+    }
+    fs::remove_dir_all(&scratch).unwrap();
+}
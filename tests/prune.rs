@@ -0,0 +1,62 @@
+// makeiso prune: retention of old images
+
+mod common;
+
+use std::fs;
+
+use common::{makeiso, scratch_dir};
+
+#[test]
+fn retention_pruning() {
+    let scratch = scratch_dir("prune");
+    let target = scratch.join("backups");
+    fs::create_dir_all(&target).unwrap();
+    for day in 1..=20 {
+        fs::write(target.join(format!("backup-2024-05-{:02}.iso", day)), "image").unwrap();
+    }
+    fs::write(target.join("backup-2024-05-02.iso.sha256"), "sum").unwrap();
+    fs::write(target.join("notes.txt"), "not an image").unwrap();
+    let prune = |extra: &[&str]| {
+        makeiso()
+            .arg("prune")
+            .arg("--target")
+            .arg(&target)
+            .arg("--catalog-db")
+            .arg(scratch.join("catalog.db"))
+            .args(["--keep-daily", "3", "--keep-weekly", "4"])
+            .args(extra)
+            .output()
+            .expect("failed to run makeiso")
+    };
+    let remaining = || {
+        let mut names: Vec<String> = fs::read_dir(&target).unwrap().map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned()).collect();
+        names.sort();
+        names
+    };
+    let before = remaining();
+
+    let result = prune(&["--dry-run"]);
+    assert!(result.status.success(), "prune failed: {}", String::from_utf8_lossy(&result.stderr));
+    let stdout = String::from_utf8_lossy(&result.stdout).into_owned();
+    assert!(stdout.contains("backup-2024-05-19.iso (daily, weekly)"), "{}", stdout);
+    assert!(stdout.contains("backup-2024-05-05.iso (weekly)"), "{}", stdout);
+    assert!(stdout.ends_with("5 kept, 15 would be deleted\n"), "{}", stdout);
+    assert_eq!(remaining(), before);
+
+    // The newest of each of the last three days, and of each of the last four weeks (weeks
+    // start on Monday, and 2024-05-20 is one)
+    let result = prune(&[]);
+    assert!(result.status.success(), "prune failed: {}", String::from_utf8_lossy(&result.stderr));
+    let kept: Vec<String> = [5, 12, 18, 19, 20].iter().map(|day| format!("backup-2024-05-{:02}.iso", day)).chain(["notes.txt".to_string()]).collect();
+    assert_eq!(remaining(), kept);
+
+    let demoted = scratch.join("cold");
+    let result = makeiso().env("MAKEISO_STATE_DIR", &scratch).arg("prune").arg("--target").arg(&target).args(["--keep-last", "2", "--demote-to"]).arg(&demoted).output().expect("failed to run makeiso");
+    assert!(result.status.success(), "prune failed: {}", String::from_utf8_lossy(&result.stderr));
+    assert_eq!(fs::read_dir(&demoted).unwrap().count(), 3);
+    assert!(demoted.join("backup-2024-05-05.iso").exists() && target.join("backup-2024-05-20.iso").exists());
+
+    let result = makeiso().env("MAKEISO_STATE_DIR", &scratch).arg("prune").arg("--target").arg(&target).output().expect("failed to run makeiso");
+    assert!(!result.status.success());
+    fs::remove_dir_all(&scratch).unwrap();
+}
@@ -0,0 +1,102 @@
+// Damaged images: truncated ones noticed, and makeiso repair
+
+mod common;
+
+use std::fs;
+
+use common::{run, scratch_dir};
+
+#[test]
+fn truncated_images() {
+    let scratch = scratch_dir("truncated");
+    let source = scratch.join("source");
+    fs::create_dir_all(&source).unwrap();
+    fs::write(source.join("data.bin"), vec![7u8; 100_000]).unwrap();
+    let image = scratch.join("image.iso");
+    let result = run(&[&source, &image]);
+    assert!(result.status.success(), "makeiso failed: {}", String::from_utf8_lossy(&result.stderr));
+
+    let result = run(&[&"verify", &image]);
+    assert!(result.status.success(), "verify failed: {}", String::from_utf8_lossy(&result.stderr));
+    assert!(String::from_utf8_lossy(&result.stdout).starts_with("No problems found"));
+
+    // Past the directory tree, into the file
+    let contents = fs::read(&image).unwrap();
+    let truncated = scratch.join("truncated.iso");
+    fs::write(&truncated, &contents[..contents.len() - 50_000]).unwrap();
+    let result = run(&[&"verify", &truncated]);
+    assert!(!result.status.success());
+    let report = String::from_utf8_lossy(&result.stdout);
+    assert!(report.contains("The image is truncated: 50000 bytes are missing"), "{}", report);
+    // Still listed, with a warning
+    let result = run(&[&"ls", &truncated]);
+    assert!(result.status.success(), "ls failed: {}", String::from_utf8_lossy(&result.stderr));
+    assert!(String::from_utf8_lossy(&result.stderr).contains("is truncated: 50000 bytes are missing"));
+    // Before it: nothing to list, but verify still tells
+    fs::write(&truncated, &contents[..17 * 2048]).unwrap();
+    let result = run(&[&"verify", &truncated]);
+    assert!(String::from_utf8_lossy(&result.stdout).contains(&format!("{} bytes are missing", contents.len() - 17 * 2048)));
+    fs::remove_dir_all(&scratch).unwrap();
+}
+
+#[test]
+fn image_repair() {
+    let scratch = scratch_dir("repair");
+    let source = scratch.join("source");
+    fs::create_dir_all(source.join("sub")).unwrap();
+    fs::write(source.join("a.txt"), "hi\n").unwrap();
+    fs::write(source.join("sub/b.bin"), vec![3u8; 100_000]).unwrap();
+    let image = scratch.join("image.iso");
+    let result = run(&[&source, &image]);
+    assert!(result.status.success(), "makeiso failed: {}", String::from_utf8_lossy(&result.stderr));
+
+    // A wrong big-endian volume size, a scribbled-on type L path table and no terminator
+    let contents = fs::read(&image).unwrap();
+    let mut damaged = contents.clone();
+    let primary = 16 * 2048;
+    damaged[primary + 84..primary + 88].copy_from_slice(&7u32.to_be_bytes());
+    let path_table = u32::from_le_bytes(damaged[primary + 140..primary + 144].try_into().unwrap()) as usize * 2048;
+    damaged[path_table + 2..path_table + 6].copy_from_slice(&99u32.to_le_bytes());
+    let terminator = (17..).map(|block| block * 2048).find(|&offset| damaged[offset] == 255).unwrap();
+    damaged[terminator..terminator + 7].fill(0);
+    let broken = scratch.join("broken.iso");
+    fs::write(&broken, &damaged).unwrap();
+
+    let result = run(&[&"verify", &broken]);
+    assert!(!result.status.success());
+    let report = String::from_utf8_lossy(&result.stdout);
+    assert_eq!(report.matches("(makeiso repair can fix this)").count(), 3, "{}", report);
+    let repaired = scratch.join("repaired.iso");
+    let result = run(&[&"repair", &broken, &repaired]);
+    assert!(result.status.success(), "repair failed: {}", String::from_utf8_lossy(&result.stderr));
+    let changes = String::from_utf8_lossy(&result.stdout);
+    assert!(changes.contains("Rebuilt the type L path table"), "{}", changes);
+    assert!(changes.contains("Wrote a volume descriptor set terminator"), "{}", changes);
+    assert_eq!(fs::read(&repaired).unwrap(), contents);
+    assert_eq!(fs::read(&broken).unwrap(), damaged);
+    // The output isn't replaced without --force
+    assert!(!run(&[&"repair", &broken, &repaired]).status.success());
+
+    // A copy that only lost the padding a volume size claims
+    let mut padded = contents.clone();
+    let claimed = (contents.len() / 2048 + 10) as u32;
+    padded[primary + 80..primary + 84].copy_from_slice(&claimed.to_le_bytes());
+    padded[primary + 84..primary + 88].copy_from_slice(&claimed.to_be_bytes());
+    fs::write(&broken, &padded).unwrap();
+    let result = run(&[&"repair", &"--force", &broken, &repaired]);
+    assert!(result.status.success(), "repair failed: {}", String::from_utf8_lossy(&result.stderr));
+    assert_eq!(fs::read(&repaired).unwrap(), contents);
+
+    // Both copies of the volume size wrong, differently: the size set at the end is the one
+    // listed, not the big-endian copy set from the little-endian one before it
+    padded[primary + 84..primary + 88].copy_from_slice(&65535u32.to_be_bytes());
+    fs::write(&broken, &padded).unwrap();
+    let result = run(&[&"repair", &"--force", &broken, &repaired]);
+    assert!(result.status.success(), "repair failed: {}", String::from_utf8_lossy(&result.stderr));
+    let changes = String::from_utf8_lossy(&result.stdout);
+    assert!(!changes.contains("big-endian copy"), "{}", changes);
+    assert!(changes.contains(&format!("Set the volume space size to the {} blocks there are (was {})", contents.len() / 2048, claimed)), "{}", changes);
+    assert!(changes.contains("Repaired 2 of 2 problems"), "{}", changes);
+    assert_eq!(fs::read(&repaired).unwrap(), contents);
+    fs::remove_dir_all(&scratch).unwrap();
+}
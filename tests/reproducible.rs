@@ -0,0 +1,50 @@
+mod common;
+
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use common::{makeiso, scratch_dir};
+
+// Build an image of the source directory in reproducible mode
+fn make_reproducible_iso(source: &Path, output: &Path) {
+    let result = makeiso()
+        .args(["--reproducible", "--source-date-epoch", "1700000000"])
+        .arg(source)
+        .arg(output)
+        .env_remove("SOURCE_DATE_EPOCH")
+        .output()
+        .expect("failed to run makeiso");
+    assert!(result.status.success(), "makeiso failed: {}", String::from_utf8_lossy(&result.stderr));
+}
+
+#[test]
+fn identical_trees_give_identical_images() {
+    let scratch = scratch_dir("reproducible");
+    let source = scratch.join("source");
+    fs::create_dir_all(source.join("docs/notes")).unwrap();
+    fs::write(source.join("readme.txt"), "hello\n").unwrap();
+    fs::write(source.join("docs/report.bin"), vec![7u8; 5000]).unwrap();
+    fs::write(source.join("docs/notes/todo.txt"), "nothing\n").unwrap();
+    fs::write(source.join("empty"), "").unwrap();
+
+    let first = scratch.join("first.iso");
+    make_reproducible_iso(&source, &first);
+
+    // Rewrite the files so every modification time changes between the runs
+    thread::sleep(Duration::from_millis(1100));
+    fs::write(source.join("readme.txt"), "hello\n").unwrap();
+    fs::write(source.join("docs/notes/todo.txt"), "nothing\n").unwrap();
+    fs::create_dir(source.join("docs/tmp")).unwrap();
+    fs::remove_dir(source.join("docs/tmp")).unwrap();
+
+    let second = scratch.join("second.iso");
+    make_reproducible_iso(&source, &second);
+
+    let (first, second) = (fs::read(&first).unwrap(), fs::read(&second).unwrap());
+    assert_eq!(first.len(), second.len());
+    assert!(first == second, "reproducible images differ");
+
+    fs::remove_dir_all(&scratch).unwrap();
+}
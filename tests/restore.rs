@@ -0,0 +1,44 @@
+// makeiso verify-restore: a restored tree checked against the image
+
+mod common;
+
+use std::fs;
+use std::time::Duration;
+
+use common::{run, scratch_dir, extract_image};
+
+#[test]
+fn restore_verification() {
+    let scratch = scratch_dir("verify-restore");
+    let source = scratch.join("source");
+    fs::create_dir_all(source.join("etc")).unwrap();
+    fs::write(source.join("etc/fstab"), "/dev/sda1 / ext4 defaults 0 1\n").unwrap();
+    fs::write(source.join("etc/hosts"), "127.0.0.1 localhost\n").unwrap();
+    fs::write(source.join("notes.txt"), "notes\n").unwrap();
+    let image = scratch.join("image.iso");
+    let result = run(&[&source, &image]);
+    assert!(result.status.success(), "makeiso failed: {}", String::from_utf8_lossy(&result.stderr));
+    let restored = scratch.join("restored");
+    extract_image(&image, &restored);
+    let verify = || run(&[&"verify-restore", &image, &restored]);
+
+    let result = verify();
+    assert!(result.status.success(), "verify-restore failed: {}", String::from_utf8_lossy(&result.stderr));
+    assert!(String::from_utf8_lossy(&result.stdout).contains("0 missing, 0 extra, 0 drifted"));
+
+    // Same size and time, other contents: only the hash tells
+    let fstab = restored.join("etc/fstab");
+    let modified = fs::metadata(&fstab).unwrap().modified().unwrap();
+    fs::write(&fstab, "/dev/sdb1 / ext4 defaults 0 1\n").unwrap();
+    fs::File::options().write(true).open(&fstab).unwrap().set_modified(modified).unwrap();
+    fs::remove_file(restored.join("etc/hosts")).unwrap();
+    fs::write(restored.join("stray.txt"), "").unwrap();
+    fs::File::options().write(true).open(restored.join("notes.txt")).unwrap().set_modified(modified + Duration::from_secs(60)).unwrap();
+    let result = verify();
+    assert!(!result.status.success());
+    let report = String::from_utf8_lossy(&result.stdout);
+    for line in ["- /etc/hosts", "+ /stray.txt", "~ /etc/fstab: contents differ", "~ /notes.txt: modified "] {
+        assert!(report.lines().any(|found| found.starts_with(line)), "{} not in {}", line, report);
+    }
+    fs::remove_dir_all(&scratch).unwrap();
+}
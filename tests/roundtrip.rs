@@ -0,0 +1,159 @@
+// Round trips through the writer and the reader: random source trees are built into images,
+// read back with makeiso::reader and compared with the sources, entry by entry and byte by
+// byte. MAKEISO_ROUNDTRIP_CASES sets the number of trees (8 by default) and
+// MAKEISO_ROUNDTRIP_SEED the first seed, so a failure can be run again on its own.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, SystemTime};
+
+use makeiso::reader::IsoReader;
+
+// Characters names are made of; the identifiers keep them as they are
+const NAME_CHARS: &[char] = &['a', 'b', 'c', 'x', 'y', 'z', 'A', 'Q', 'Z', '0', '7', '9', '.', '-', '_', ' ', '+', '~', 'é', 'ß', 'Ж', '中'];
+// Sizes around the block boundaries are the likeliest to go wrong
+const SIZES: &[usize] = &[0, 1, 2047, 2048, 2049, 4096, 10_000, 65_536, 300_001];
+const MAX_PATH_LENGTH: usize = 255; // Bytes of a path from the root, as the writer counts them
+
+// Small xorshift generator, so a seed always gives the same tree
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+// What the image should show at one path
+#[derive(Debug, PartialEq)]
+enum Expected {
+    Directory,
+    File(Vec<u8>, i64),
+}
+
+fn setting(name: &str, default: u64) -> u64 {
+    env::var(name).ok().and_then(|value| value.parse().ok()).unwrap_or(default)
+}
+
+// A name not used yet in the directory
+fn random_name(rng: &mut Rng, taken: &mut Vec<String>) -> String {
+    loop {
+        let len = 1 + rng.below(40);
+        let name: String = (0..len).map(|_| NAME_CHARS[rng.below(NAME_CHARS.len())]).collect();
+        // Names of only dots, or with a leading space or dot, are awkward on every side
+        if name.starts_with(['.', ' ']) || name.ends_with(' ') || taken.contains(&name) {
+            continue;
+        }
+        taken.push(name.clone());
+        return name;
+    }
+}
+
+// Fill a directory with random entries, recording what each one should read back as
+fn random_tree(rng: &mut Rng, directory: &Path, path: &str, depth: usize, expected: &mut BTreeMap<String, Expected>) {
+    let mut taken = Vec::new();
+    for _ in 0..rng.below(8) {
+        let name = random_name(rng, &mut taken);
+        let (target, image_path) = (directory.join(&name), format!("{}/{}", path, name));
+        if image_path.len() > MAX_PATH_LENGTH {
+            continue; // Refused by the writer, as ISO 9660 requires
+        }
+        match rng.below(10) {
+            0..=2 if depth < 10 => {
+                fs::create_dir(&target).unwrap();
+                expected.insert(image_path.clone(), Expected::Directory);
+                random_tree(rng, &target, &image_path, depth + 1, expected);
+            }
+            _ => {
+                let size = if rng.below(2) == 0 { SIZES[rng.below(SIZES.len())] } else { rng.below(20_000) };
+                let contents: Vec<u8> = (0..size).map(|_| rng.next() as u8).collect();
+                fs::write(&target, &contents).unwrap();
+                let modified = 1_000_000_000 + rng.below(1_000_000_000) as i64;
+                File::options().write(true).open(&target).unwrap().set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(modified as u64)).unwrap();
+                expected.insert(image_path, Expected::File(contents, modified));
+            }
+        }
+    }
+}
+
+// Everything the image holds, read back the way the expectations are recorded
+fn read_image(image: &[u8]) -> BTreeMap<String, Expected> {
+    let mut reader = IsoReader::open(Cursor::new(image)).unwrap();
+    let tree = reader.read_tree().unwrap();
+    let mut found = BTreeMap::new();
+    for entry in tree.iter().skip(1) {
+        let read = if entry.is_directory {
+            Expected::Directory
+        } else {
+            let mut contents = vec![0u8; entry.size as usize];
+            let mut offset = 0;
+            while offset < contents.len() {
+                let read = reader.read_file_at(entry, offset as u64, &mut contents[offset..]).unwrap();
+                assert!(read > 0, "{} ends early", entry.path);
+                offset += read;
+            }
+            Expected::File(contents, entry.modified)
+        };
+        assert!(found.insert(entry.path.clone(), read).is_none(), "{} is listed twice", entry.path);
+    }
+    found
+}
+
+fn round_trip(seed: u64, scratch: &Path) {
+    let source = scratch.join("source");
+    let _ = fs::remove_dir_all(&source);
+    fs::create_dir_all(&source).unwrap();
+    let mut rng = Rng(seed.max(1));
+    let mut expected = BTreeMap::new();
+    random_tree(&mut rng, &source, "", 0, &mut expected);
+
+    let output = scratch.join("image.iso");
+    let result = Command::new(env!("CARGO_BIN_EXE_makeiso"))
+        .args(["--force", "--deep-dirs", "relocate"])
+        .arg(&source)
+        .arg(&output)
+        .output()
+        .expect("failed to run makeiso");
+    assert!(result.status.success(), "seed {}: makeiso failed: {}", seed, String::from_utf8_lossy(&result.stderr));
+
+    let found = read_image(&fs::read(&output).unwrap());
+    for (path, want) in &expected {
+        match found.get(path) {
+            Some(got) => assert!(got == want, "seed {}: {} reads back differently: {:?}", seed, path, summary(got)),
+            None => panic!("seed {}: {} is missing from the image", seed, path),
+        }
+    }
+    for path in found.keys() {
+        assert!(expected.contains_key(path), "seed {}: {} is in the image but not in the source", seed, path);
+    }
+}
+
+// An entry without its full contents, for messages
+fn summary(entry: &Expected) -> String {
+    match entry {
+        Expected::File(contents, modified) => format!("file of {} bytes modified at {}", contents.len(), modified),
+        Expected::Directory => "directory".to_string(),
+    }
+}
+
+#[test]
+fn random_trees_read_back_unchanged() {
+    let scratch: PathBuf = env::temp_dir().join(format!("makeiso-roundtrip-{}", std::process::id()));
+    fs::create_dir_all(&scratch).unwrap();
+    let first = setting("MAKEISO_ROUNDTRIP_SEED", 0x5eed);
+    for seed in first..first + setting("MAKEISO_ROUNDTRIP_CASES", 8) {
+        round_trip(seed, &scratch);
+    }
+    fs::remove_dir_all(&scratch).unwrap();
+}
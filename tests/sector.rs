@@ -0,0 +1,37 @@
+// makeiso sector: what a block of an image holds
+
+mod common;
+
+use std::fs;
+
+use common::{run, scratch_dir};
+
+#[test]
+fn sector_inspection() {
+    let scratch = scratch_dir("sector");
+    let source = scratch.join("source");
+    fs::create_dir_all(source.join("sub")).unwrap();
+    fs::write(source.join("sub/data.bin"), vec![5u8; 10_000]).unwrap();
+    let (image, map) = (scratch.join("image.iso"), scratch.join("image.map"));
+    let result = run(&[&"--block-map", &map, &source, &image]);
+    assert!(result.status.success(), "makeiso failed: {}", String::from_utf8_lossy(&result.stderr));
+
+    let result = run(&[&"sector", &image, &"16"]);
+    assert!(result.status.success(), "sector failed: {}", String::from_utf8_lossy(&result.stderr));
+    let dump = String::from_utf8_lossy(&result.stdout);
+    assert!(dump.starts_with("Block 16 (offset 32768, 0x8000):\n00008000  01 43 44 30 30 31 01 00"), "{}", dump);
+    assert!(dump.contains("\n*\n"), "{}", dump);
+    let result = run(&[&"sector", &image, &"0x10", &"2", &"--raw"]);
+    assert_eq!(result.stdout, fs::read(&image).unwrap()[16 * 2048..18 * 2048]);
+    assert!(!run(&[&"sector", &image, &"100000"]).status.success());
+
+    // The block the build put the file at, by the map it wrote
+    let map = fs::read_to_string(&map).unwrap();
+    let start: u64 = map.lines().find(|line| line.ends_with("\t/sub/data.bin")).and_then(|line| line.split('\t').next()).unwrap().parse().unwrap();
+    let report = |offset: u64| String::from_utf8_lossy(&run(&[&"whatis", &image, &"--offset", &offset.to_string()]).stdout).into_owned();
+    assert!(report(start * 2048 + 9000).contains("/sub/data.bin (byte 9000 of the file)"));
+    assert!(report(start * 2048 + 10_100).contains("/sub/data.bin (past its end"));
+    assert!(report(16 * 2048 + 100).contains("(primary volume descriptor)"));
+    assert!(report(1 << 30).contains("past the end of the image"));
+    fs::remove_dir_all(&scratch).unwrap();
+}
@@ -0,0 +1,90 @@
+// --sign and makeiso verify-sig, with a throwaway key in a keyring of the test's own (skipped
+// where gpg isn't installed), and the errors for a missing gpg, GNUPGHOME or key
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use common::{makeiso, scratch_dir, installed};
+
+#[test]
+fn sign_and_verify() {
+    if !installed("gpg") {
+        eprintln!("gpg is not installed; skipping");
+        return;
+    }
+    let scratch = scratch_dir("sign");
+    let home = scratch.join("gnupg");
+    fs::create_dir_all(&home).unwrap();
+    #[cfg(unix)]
+    fs::set_permissions(&home, std::os::unix::fs::PermissionsExt::from_mode(0o700)).unwrap(); // Or gpg warns about it
+    let gpg = |args: &[&str]| Command::new("gpg").env("GNUPGHOME", &home).arg("--batch").args(args).output().unwrap();
+    let result = gpg(&["--passphrase", "", "--quick-generate-key", "makeiso test <test@makeiso.invalid>", "ed25519", "sign", "never"]);
+    assert!(result.status.success(), "gpg could not make a key: {}", String::from_utf8_lossy(&result.stderr));
+    let command = |args: &[&dyn AsRef<std::ffi::OsStr>]| makeiso().env("GNUPGHOME", &home).args(args.iter().map(|arg| arg.as_ref())).output().unwrap();
+
+    let source = scratch.join("source");
+    fs::create_dir_all(&source).unwrap();
+    fs::write(source.join("readme.txt"), "signed\n").unwrap();
+    let image = scratch.join("image.iso");
+    let result = command(&[&"--sign", &"test@makeiso.invalid", &source, &image]);
+    assert!(result.status.success(), "makeiso --sign failed: {}", String::from_utf8_lossy(&result.stderr));
+    let signature = scratch.join("image.iso.sig");
+    assert!(signature.exists());
+
+    // The signature checks out, by its default name and given explicitly
+    let verify = |image: &Path| command(&[&"verify-sig", &image, &signature]);
+    let result = command(&[&"verify-sig", &image]);
+    assert!(result.status.success(), "verify-sig failed: {}", String::from_utf8_lossy(&result.stderr));
+    assert!(String::from_utf8_lossy(&result.stdout).starts_with("Good signature from makeiso test <test@makeiso.invalid> (key "), "{}", String::from_utf8_lossy(&result.stdout));
+    assert!(verify(&image).status.success());
+
+    // One byte changed anywhere in the image and it no longer does
+    let mut bytes = fs::read(&image).unwrap();
+    bytes[40_000] ^= 1;
+    let tampered = scratch.join("tampered.iso");
+    fs::write(&tampered, bytes).unwrap();
+    let result = verify(&tampered);
+    assert!(!result.status.success());
+    assert!(String::from_utf8_lossy(&result.stderr).contains("does not match its signature"), "{}", String::from_utf8_lossy(&result.stderr));
+
+    let _ = Command::new("gpgconf").env("GNUPGHOME", &home).args(["--kill", "gpg-agent"]).status();
+    fs::remove_dir_all(&scratch).unwrap();
+}
+
+// What goes wrong before anything is signed is said, and found before the build
+#[test]
+fn signing_failures() {
+    let scratch = scratch_dir("sign-failures");
+    let source = scratch.join("source");
+    fs::create_dir_all(&source).unwrap();
+    fs::write(source.join("readme.txt"), "signed\n").unwrap();
+    let image = scratch.join("image.iso");
+    let fails = |home: &Path, path: Option<&Path>, message: &str| {
+        let mut command = makeiso();
+        command.env("GNUPGHOME", home).args(["--sign", "nobody@makeiso.invalid"]).arg(&source).arg(&image);
+        if let Some(path) = path {
+            command.env("PATH", path);
+        }
+        let result = command.output().unwrap();
+        assert!(!result.status.success());
+        assert!(String::from_utf8_lossy(&result.stderr).contains(message), "{}", String::from_utf8_lossy(&result.stderr));
+        assert!(!image.exists());
+    };
+
+    fails(&scratch.join("missing"), None, "which is not a directory with a keyring");
+    let home = scratch.join("gnupg");
+    fs::create_dir_all(&home).unwrap();
+    #[cfg(unix)]
+    fs::set_permissions(&home, std::os::unix::fs::PermissionsExt::from_mode(0o700)).unwrap();
+    let empty = scratch.join("bin");
+    fs::create_dir_all(&empty).unwrap();
+    fails(&home, Some(&empty), "gpg is not installed");
+    if installed("gpg") {
+        fails(&home, None, "gpg has no secret key nobody@makeiso.invalid");
+        let _ = Command::new("gpgconf").env("GNUPGHOME", &home).args(["--kill", "gpg-agent"]).status();
+    }
+    fs::remove_dir_all(&scratch).unwrap();
+}
@@ -0,0 +1,34 @@
+// --single-pass into the source tree: the image and its partial file, already being written
+// when the scan reaches them, are left out like any other output
+
+mod common;
+
+use std::fs;
+
+use makeiso::reader::IsoReader;
+
+use common::{run, scratch_dir};
+
+#[test]
+fn image_inside_its_source() {
+    let scratch = scratch_dir("single-pass-self");
+    let source = scratch.join("s2");
+    fs::create_dir_all(source.join("data")).unwrap();
+    fs::write(source.join("data/file.txt"), "data\n").unwrap();
+    fs::write(source.join("readme.txt"), "read me\n").unwrap();
+    for extra in [&["--single-pass"][..], &[]] {
+        let image = source.join("self.iso");
+        let _ = fs::remove_file(&image);
+        let mut args: Vec<&dyn AsRef<std::ffi::OsStr>> = extra.iter().map(|arg| arg as &dyn AsRef<std::ffi::OsStr>).collect();
+        args.extend([&source as &dyn AsRef<std::ffi::OsStr>, &image]);
+        let result = run(&args);
+        assert!(result.status.success(), "makeiso {:?} failed: {}", extra, String::from_utf8_lossy(&result.stderr));
+
+        let mut reader = IsoReader::open(fs::File::open(&image).unwrap()).unwrap();
+        let mut paths: Vec<String> = reader.read_tree().unwrap().into_iter().skip(1).map(|entry| entry.path).collect();
+        paths.sort();
+        assert_eq!(paths, ["/data", "/data/file.txt", "/readme.txt"], "{:?}", extra);
+        assert!(!source.join("self.iso.part").exists());
+    }
+    fs::remove_dir_all(&scratch).unwrap();
+}
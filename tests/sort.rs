@@ -0,0 +1,37 @@
+// File order on the disc: --sort-weight and --sort-file place the heavier files first
+
+mod common;
+
+use std::fs;
+
+use makeiso::reader::IsoReader;
+
+use common::{makeiso, scratch_dir};
+
+#[test]
+fn sort_weights() {
+    let scratch = scratch_dir("sort");
+    let source = scratch.join("source");
+    fs::create_dir_all(source.join("boot")).unwrap();
+    fs::create_dir_all(source.join("data")).unwrap();
+    for name in ["data/a.bin", "data/b.bin", "boot/vmlinuz", "boot/initrd.img", "readme.txt"] {
+        fs::write(source.join(name), name).unwrap();
+    }
+    fs::write(scratch.join("sort.txt"), "# kernel first\n/boot/vmlinuz 10\n*.img\t5\n").unwrap();
+    let image = scratch.join("image.iso");
+    let order = |extra: &[&str]| {
+        let result = makeiso().arg("--force").args(extra).arg(&source).arg(&image).output().expect("failed to run makeiso");
+        assert!(result.status.success(), "makeiso {:?} failed: {}", extra, String::from_utf8_lossy(&result.stderr));
+        let mut reader = IsoReader::open(fs::File::open(&image).unwrap()).unwrap();
+        let mut files: Vec<(u32, String)> = reader.read_tree().unwrap().into_iter().filter(|entry| !entry.is_directory).map(|entry| (entry.extent, entry.path)).collect();
+        files.sort();
+        files.into_iter().map(|(_, path)| path).collect::<Vec<_>>()
+    };
+
+    // Heavier first, the rest (and ties) in directory order
+    let expected = ["/boot/vmlinuz", "/boot/initrd.img", "/readme.txt", "/data/a.bin", "/data/b.bin"];
+    assert_eq!(order(&["--sort-weight", "/boot/vmlinuz=10", "--sort-weight", "*.img=5"]), expected);
+    assert_eq!(order(&["--sort-file", scratch.join("sort.txt").to_str().unwrap()]), expected);
+    assert_eq!(order(&["--sort-weight", "data/**=-1"]), ["/readme.txt", "/boot/initrd.img", "/boot/vmlinuz", "/data/a.bin", "/data/b.bin"]);
+    fs::remove_dir_all(&scratch).unwrap();
+}
@@ -0,0 +1,43 @@
+// A file read from standard input (-), named with --stdin-name
+
+mod common;
+
+use std::fs;
+use std::io::Write;
+use std::process::Stdio;
+
+use common::{FIXTURE_TIME, Expected, makeiso, scratch_dir, read_image, assert_same};
+
+#[test]
+fn standard_input_source() {
+    let scratch = scratch_dir("stdin");
+    fs::create_dir_all(&scratch).unwrap();
+    fs::write(scratch.join("notes.txt"), "notes\n").unwrap();
+    let image = scratch.join("image.iso");
+    let dump: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+    let build = |args: &[&str]| {
+        let mut child = makeiso()
+            .args(["--force", "--reproducible", "--source-date-epoch", &FIXTURE_TIME.to_string()])
+            .args(args)
+            .arg(&image)
+            .current_dir(&scratch)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("failed to run makeiso");
+        child.stdin.take().unwrap().write_all(&dump).unwrap();
+        let result = child.wait_with_output().unwrap();
+        assert!(result.status.success(), "makeiso {:?} failed: {}", args, String::from_utf8_lossy(&result.stderr));
+        read_image(&image)
+    };
+
+    assert_same(&build(&["--stdin-name", "database.sql.gz", "-", "notes.txt"]), &[("/database.sql.gz".to_string(), Expected::File(dump.clone())), ("/notes.txt".to_string(), Expected::File(b"notes\n".to_vec()))], "stdin");
+    assert_same(&build(&["-"]), &[("/stdin".to_string(), Expected::File(dump.clone()))], "stdin");
+    // Nothing of the spool is left behind
+    assert_eq!(fs::read_dir(&scratch).unwrap().count(), 2);
+
+    let result = makeiso().args(["--stdin-name", "dump", "notes.txt"]).arg(&image).current_dir(&scratch).output().expect("failed to run makeiso");
+    assert!(!result.status.success() && String::from_utf8_lossy(&result.stderr).contains("needs - among the sources"));
+    fs::remove_dir_all(&scratch).unwrap();
+}
@@ -0,0 +1,48 @@
+// Symbolic links in the sources, followed, skipped or stored as links
+
+mod common;
+
+use std::fs;
+
+use common::{Expected, reproducible_build, scratch_dir, read_image, extract_image, sorted, assert_same};
+
+#[test]
+#[cfg(unix)]
+fn symbolic_links() {
+    let scratch = scratch_dir("links");
+    let source = scratch.join("source");
+    fs::create_dir_all(source.join("data/inner")).unwrap();
+    fs::write(source.join("data/inner/file.txt"), "file\n").unwrap();
+    std::os::unix::fs::symlink("..", source.join("data/inner/up")).unwrap();
+    std::os::unix::fs::symlink("data/inner", source.join("shortcut")).unwrap();
+    std::os::unix::fs::symlink("/nowhere/at/all", source.join("dangling")).unwrap();
+    let image = scratch.join("image.iso");
+    let paths = |extra: &[&str]| {
+        let result = reproducible_build(extra, &source, &image);
+        assert!(result.status.success(), "makeiso {:?} failed: {}", extra, String::from_utf8_lossy(&result.stderr));
+        (read_image(&image), String::from_utf8_lossy(&result.stderr).into_owned())
+    };
+    let names = |tree: Vec<(String, Expected)>| tree.into_iter().map(|(path, _)| path).collect::<Vec<_>>();
+
+    // The link up the tree goes in empty, wherever it is reached from; the other is followed
+    let (followed, warnings) = paths(&[]);
+    assert_eq!(names(followed), ["/data", "/data/inner", "/data/inner/file.txt", "/data/inner/up", "/shortcut", "/shortcut/file.txt", "/shortcut/up", "/shortcut/up/inner"]);
+    assert!(warnings.contains("links back to a directory it is in"), "{}", warnings);
+    assert_eq!(names(paths(&["--symlinks", "skip"]).0), ["/data", "/data/inner", "/data/inner/file.txt"]);
+
+    // Stored, the links read back and extract as links, dangling ones too
+    let stored = sorted(vec![
+        ("/dangling".to_string(), Expected::Symlink("/nowhere/at/all".to_string())),
+        ("/data".to_string(), Expected::Directory),
+        ("/data/inner".to_string(), Expected::Directory),
+        ("/data/inner/file.txt".to_string(), Expected::File(b"file\n".to_vec())),
+        ("/data/inner/up".to_string(), Expected::Symlink("..".to_string())),
+        ("/shortcut".to_string(), Expected::Symlink("data/inner".to_string())),
+    ]);
+    let (found, warnings) = paths(&["--symlinks", "store"]);
+    assert_same(&found, &stored, "stored links");
+    assert!(!warnings.contains("links back"), "{}", warnings);
+    assert_same(&extract_image(&image, &scratch.join("out")), &stored, "stored links extracted");
+    assert_eq!(names(paths(&["--symlinks", "store", "--type", "f"]).0), ["/data", "/data/inner", "/data/inner/file.txt"]);
+    fs::remove_dir_all(&scratch).unwrap();
+}
@@ -0,0 +1,157 @@
+// The system area and what comes after the image: appended partitions, imported system
+// areas, and SPARC and PowerPC boot
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+
+use common::{Expected, reproducible_build, scratch_dir, read_image, assert_same};
+
+// MBR partition table entry N (from 1): type, first sector and sector count
+fn mbr_entry(image: &[u8], slot: usize) -> (u8, u32, u32) {
+    let entry = &image[446 + 16 * (slot - 1)..462 + 16 * (slot - 1)];
+    let field = |at: usize| u32::from_le_bytes(entry[at..at + 4].try_into().unwrap());
+    (entry[4], field(8), field(12))
+}
+
+#[test]
+fn appended_partition() {
+    let scratch = scratch_dir("append");
+    let source = scratch.join("source");
+    fs::create_dir_all(source.join("boot")).unwrap();
+    fs::write(source.join("boot/loader.bin"), vec![0x90; 2048]).unwrap();
+    fs::write(source.join("readme.txt"), "hello\n").unwrap();
+    let partition: Vec<u8> = (0..5000u32).map(|at| at as u8).collect();
+    let partition_path = scratch.join("efi.img");
+    fs::write(&partition_path, &partition).unwrap();
+    fs::write(scratch.join("mbr.bin"), vec![0xEB; 432]).unwrap();
+    let image = scratch.join("image.iso");
+    let append = format!("2:0xef:{}", partition_path.display());
+    let build = |extra: &[&str]| {
+        reproducible_build(extra, &source, &image)
+    };
+
+    // Alone the MBR is a bare partition table: the volume as 0xCD, the partition in slot 2
+    // right after it
+    let mbr = scratch.join("mbr.bin");
+    for extra in [vec!["--append-partition", &append], vec!["--boot-image", "boot/loader.bin", "--isohybrid-mbr", mbr.to_str().unwrap(), "--append-partition", &append]] {
+        let result = build(&extra);
+        assert!(result.status.success(), "makeiso {:?} failed: {}", extra, String::from_utf8_lossy(&result.stderr));
+        let bytes = fs::read(&image).unwrap();
+        let hybrid = extra.len() > 2;
+        assert_eq!(bytes[0], if hybrid { 0xEB } else { 0 }, "boot code with --isohybrid-mbr only");
+        let (kind, start, sectors) = mbr_entry(&bytes, 2);
+        assert_eq!(kind, 0xEF);
+        assert_eq!(sectors, 10, "the 5000-byte image takes 10 sectors");
+        let (volume_kind, volume_start, volume_sectors) = mbr_entry(&bytes, 1);
+        assert_eq!((volume_kind, volume_start, volume_start + volume_sectors), (if hybrid { 0x17 } else { 0xCD }, 0, start));
+        assert_eq!(&bytes[start as usize * 512..start as usize * 512 + partition.len()], &partition[..]);
+        assert_eq!(bytes.len(), start as usize * 512 + 3 * 2048, "the image ends with the partition, padded to a block");
+        assert_eq!(mbr_entry(&bytes, 3).0 | mbr_entry(&bytes, 4).0, 0);
+
+        let expected = vec![
+            ("/boot".to_string(), Expected::Directory),
+            ("/boot/loader.bin".to_string(), Expected::File(vec![0x90; 2048])),
+            ("/readme.txt".to_string(), Expected::File(b"hello\n".to_vec())),
+        ];
+        assert_same(&read_image(&image), &expected, "an image with an appended partition");
+    }
+
+    let result = build(&["--append-partition", &append, "--append-partition", &format!("2:83:{}", partition_path.display())]);
+    assert!(!result.status.success(), "slot 2 twice");
+    let result = build(&["--append-partition", &format!("5:0xef:{}", partition_path.display())]);
+    assert!(!result.status.success(), "there is no slot 5");
+    fs::remove_dir_all(&scratch).unwrap();
+}
+
+#[test]
+fn imported_system_area() {
+    let scratch = scratch_dir("system-area");
+    let source = scratch.join("source");
+    fs::create_dir_all(&source).unwrap();
+    fs::write(source.join("readme.txt"), "hello\n").unwrap();
+    let blob: Vec<u8> = (0..1000u32).map(|at| (at % 251) as u8 + 1).collect();
+    fs::write(scratch.join("blob.bin"), &blob).unwrap();
+    fs::write(scratch.join("large.bin"), vec![7; 40000]).unwrap();
+    fs::write(scratch.join("part.img"), vec![9; 2048]).unwrap();
+    let build = |extra: &[String], image: &Path| {
+        reproducible_build(extra, &source, image)
+    };
+    let path = |name: &str| scratch.join(name).to_str().unwrap().to_string();
+
+    // A blob goes in as it is, zero padded, and the tree is unchanged
+    let first = scratch.join("first.iso");
+    let result = build(&["--system-area".to_string(), path("blob.bin")], &first);
+    assert!(result.status.success(), "makeiso failed: {}", String::from_utf8_lossy(&result.stderr));
+    let bytes = fs::read(&first).unwrap();
+    assert_eq!(&bytes[..blob.len()], &blob[..]);
+    assert!(bytes[blob.len()..32768].iter().all(|&byte| byte == 0));
+    assert_same(&read_image(&first), &[("/readme.txt".to_string(), Expected::File(b"hello\n".to_vec()))], "an image with an imported system area");
+
+    // Partition tables go over it, and a remastered image keeps the system area of the original
+    let second = scratch.join("second.iso");
+    let result = build(&["--system-area".to_string(), path("blob.bin"), "--append-partition".to_string(), format!("1:0x83:{}", path("part.img"))], &second);
+    assert!(result.status.success(), "makeiso failed: {}", String::from_utf8_lossy(&result.stderr));
+    let hybrid = fs::read(&second).unwrap();
+    assert_eq!(&hybrid[..440], &blob[..440], "the blob's code is kept");
+    assert_eq!((hybrid[446 + 4], &hybrid[510..512]), (0x83, &[0x55, 0xAA][..]));
+    let result = build(&["--system-area".to_string(), path("second.iso")], &first);
+    assert!(result.status.success(), "makeiso failed: {}", String::from_utf8_lossy(&result.stderr));
+    assert_eq!(&fs::read(&first).unwrap()[..32768], &hybrid[..32768]);
+
+    let result = build(&["--system-area".to_string(), path("large.bin")], &first);
+    assert!(!result.status.success(), "a file larger than 32 KiB that isn't an image");
+    assert!(String::from_utf8_lossy(&result.stderr).contains("32 KiB"), "{}", String::from_utf8_lossy(&result.stderr));
+    fs::remove_dir_all(&scratch).unwrap();
+}
+
+#[test]
+fn sparc_and_powerpc_boot() {
+    let scratch = scratch_dir("sparc");
+    let source = scratch.join("source");
+    fs::create_dir_all(&source).unwrap();
+    fs::write(source.join("readme.txt"), "hello\n").unwrap();
+    let images: Vec<Vec<u8>> = vec![vec![0x44; 3000], vec![0x4D; 2048 * 170]];
+    for (index, contents) in images.iter().enumerate() {
+        fs::write(scratch.join(format!("boot{}.img", index)), contents).unwrap();
+    }
+    let path = |name: &str| scratch.join(name).to_str().unwrap().to_string();
+    let image = scratch.join("image.iso");
+    let build = |extra: &[String]| {
+        reproducible_build(extra, &source, &image)
+    };
+
+    // A Sun disk label with a valid checksum, the volume and the boot images on cylinders of
+    // 640 sectors
+    let result = build(&["--sparc-boot".to_string(), format!("{},{}", path("boot0.img"), path("boot1.img"))]);
+    assert!(result.status.success(), "makeiso failed: {}", String::from_utf8_lossy(&result.stderr));
+    let bytes = fs::read(&image).unwrap();
+    let word = |at: usize| u16::from_be_bytes([bytes[at], bytes[at + 1]]);
+    let long = |at: usize| u32::from_be_bytes(bytes[at..at + 4].try_into().unwrap());
+    assert_eq!(word(508), 0xDABE);
+    assert_eq!((0..256).fold(0, |sum, index| sum ^ word(2 * index)), 0, "the label's checksum");
+    assert_eq!((long(188), word(140)), (0x600DDEEE, 8));
+    let partitions: Vec<(u32, u32)> = (0..3).map(|index| (long(444 + 8 * index), long(448 + 8 * index))).collect();
+    assert_eq!(partitions[0], (0, partitions[1].0 * 640), "the volume ends where the first boot image starts");
+    assert_eq!((partitions[1].1, partitions[2].1), (6, 170 * 4));
+    assert_eq!(partitions[2].0, partitions[1].0 + 1, "each image starts on a cylinder");
+    for (index, contents) in images.iter().enumerate() {
+        let start = partitions[index + 1].0 as usize * 640 * 512;
+        assert_eq!(&bytes[start..start + contents.len()], &contents[..]);
+    }
+    assert_same(&read_image(&image), &[("/readme.txt".to_string(), Expected::File(b"hello\n".to_vec()))], "an image with a Sun disk label");
+
+    // CHRP lists the volume as type 0x96, PReP appends its boot program after it
+    let result = build(&["--chrp-boot".to_string(), "--prep-boot".to_string(), path("boot0.img")]);
+    assert!(result.status.success(), "makeiso failed: {}", String::from_utf8_lossy(&result.stderr));
+    let bytes = fs::read(&image).unwrap();
+    let (volume, prep) = (mbr_entry(&bytes, 1), mbr_entry(&bytes, 2));
+    assert_eq!((volume.0, volume.1, prep.0, prep.2), (0x96, 0, 0x41, 6));
+    assert_eq!(volume.2, prep.1);
+    assert_eq!(&bytes[prep.1 as usize * 512..prep.1 as usize * 512 + 3000], &images[0][..]);
+
+    let result = build(&["--sparc-boot".to_string(), path("boot0.img"), "--chrp-boot".to_string()]);
+    assert!(!result.status.success(), "a Sun disk label and an MBR");
+    fs::remove_dir_all(&scratch).unwrap();
+}
@@ -0,0 +1,209 @@
+// makeiso from-tar and to-tar: members keep their modes, owners and link targets in Rock
+// Ridge, fifos and devices are kept as special files, and names too long for ISO 9660 come
+// through whole. The archive is written by hand below, so nothing needs root to make it.
+
+mod common;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::process::Command;
+
+use makeiso::reader::IsoReader;
+
+use common::{FIXTURE_TIME, run, scratch_dir, installed};
+
+#[derive(Debug, Clone, PartialEq)]
+struct Member {
+    kind: u8,
+    mode: u32,
+    owner: (u32, u32),
+    contents: Vec<u8>,
+    link: String,
+    device: (u32, u32),
+}
+
+fn member(kind: u8, mode: u32, owner: (u32, u32), contents: &[u8], link: &str, device: (u32, u32)) -> Member {
+    Member { kind, mode, owner, contents: contents.to_vec(), link: link.to_string(), device }
+}
+
+fn put(block: &mut [u8], at: usize, bytes: &[u8]) {
+    block[at..at + bytes.len()].copy_from_slice(bytes);
+}
+
+// A pax extended header for the records, as its header block and data
+fn pax(records: &[(&str, &str)]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for (key, value) in records {
+        let text = format!(" {}={}\n", key, value);
+        let mut length = text.len() + 1;
+        while length.to_string().len() + text.len() != length {
+            length = length.to_string().len() + text.len();
+        }
+        data.extend(format!("{}{}", length, text).bytes());
+    }
+    let mut archive = header("pax", b'x', 0o644, (0, 0), data.len(), "", (0, 0));
+    archive.extend(padded(&data));
+    archive
+}
+
+fn header(name: &str, kind: u8, mode: u32, owner: (u32, u32), size: usize, link: &str, device: (u32, u32)) -> Vec<u8> {
+    let mut block = vec![0u8; 512];
+    put(&mut block, 0, &name.as_bytes()[..name.len().min(100)]);
+    put(&mut block, 100, format!("{:07o}\0", mode).as_bytes());
+    put(&mut block, 108, format!("{:07o}\0", owner.0).as_bytes());
+    put(&mut block, 116, format!("{:07o}\0", owner.1).as_bytes());
+    put(&mut block, 124, format!("{:011o}\0", size).as_bytes());
+    put(&mut block, 136, format!("{:011o}\0", FIXTURE_TIME).as_bytes());
+    put(&mut block, 148, b"        ");
+    put(&mut block, 156, &[kind]);
+    put(&mut block, 157, &link.as_bytes()[..link.len().min(100)]);
+    put(&mut block, 257, b"ustar\x0000");
+    put(&mut block, 329, format!("{:07o}\0", device.0).as_bytes());
+    put(&mut block, 337, format!("{:07o}\0", device.1).as_bytes());
+    let sum: u32 = block.iter().map(|&byte| byte as u32).sum();
+    put(&mut block, 148, format!("{:06o}\0 ", sum).as_bytes());
+    block
+}
+
+fn padded(data: &[u8]) -> Vec<u8> {
+    let mut data = data.to_vec();
+    data.resize(data.len().div_ceil(512) * 512, 0);
+    data
+}
+
+// A tar archive of the members, with pax headers for the names and targets ustar can't hold
+fn archive(members: &[(&str, Member)]) -> Vec<u8> {
+    let mut archive = Vec::new();
+    for (name, member) in members {
+        let mut records = Vec::new();
+        if name.len() > 100 {
+            records.push(("path", *name));
+        }
+        if member.link.len() > 100 {
+            records.push(("linkpath", member.link.as_str()));
+        }
+        if !records.is_empty() {
+            archive.extend(pax(&records));
+        }
+        archive.extend(header(name, member.kind, member.mode, member.owner, member.contents.len(), &member.link, member.device));
+        archive.extend(padded(&member.contents));
+    }
+    archive.extend([0u8; 1024]);
+    archive
+}
+
+// The members of a tar archive by path, with pax paths and targets applied
+fn members(archive: &[u8]) -> BTreeMap<String, Member> {
+    let field = |block: &[u8], at: usize, length: usize| String::from_utf8_lossy(&block[at..at + length]).trim_end_matches('\0').to_string();
+    let octal = |block: &[u8], at: usize, length: usize| u32::from_str_radix(field(block, at, length).trim(), 8).unwrap_or(0);
+    let (mut found, mut offset, mut extended) = (BTreeMap::new(), 0, BTreeMap::new());
+    while offset + 512 <= archive.len() && archive[offset] != 0 {
+        let block = &archive[offset..offset + 512];
+        let size = octal(block, 124, 12) as usize;
+        let data = &archive[offset + 512..offset + 512 + size];
+        offset += 512 + size.div_ceil(512) * 512;
+        if block[156] == b'x' {
+            for record in String::from_utf8_lossy(data).lines() {
+                let (key, value) = record.split_once(' ').unwrap().1.split_once('=').unwrap();
+                extended.insert(key.to_string(), value.to_string());
+            }
+            continue;
+        }
+        let (prefix, name) = (field(block, 345, 155), field(block, 0, 100));
+        let mut path = if prefix.is_empty() { name } else { format!("{}/{}", prefix, name) };
+        let mut link = field(block, 157, 100);
+        if let Some(long) = extended.remove("path") {
+            path = long;
+        }
+        if let Some(long) = extended.remove("linkpath") {
+            link = long;
+        }
+        extended.clear();
+        let kind = if block[156] == 0 { b'0' } else { block[156] };
+        let device = if matches!(kind, b'3' | b'4') { (octal(block, 329, 8), octal(block, 337, 8)) } else { (0, 0) };
+        let member = member(kind, octal(block, 100, 8), (octal(block, 108, 8), octal(block, 116, 8)), data, &link, device);
+        found.insert(path.trim_end_matches('/').to_string(), member);
+    }
+    found
+}
+
+#[test]
+fn tar_members_in_rock_ridge() {
+    let scratch = scratch_dir("tar");
+    let long_name = format!("long/{}.txt", "a-rather-long-name-".repeat(11));
+    let long_target = format!("../{}target", "a-long-directory-name/".repeat(14));
+    let blob: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+    let directory = |mode: u32, owner: (u32, u32)| member(b'5', mode, owner, b"", "", (0, 0));
+    let file = |mode: u32, owner: (u32, u32), contents: &[u8]| member(b'0', mode, owner, contents, "", (0, 0));
+    let tree = [
+        ("bin", directory(0o755, (0, 0))),
+        ("bin/tool", file(0o4755, (0, 0), b"#!/bin/sh\n")),
+        ("bin/sh", member(b'2', 0o777, (0, 0), b"", "tool", (0, 0))),
+        ("data", directory(0o700, (1000, 1000))),
+        ("data/blob.bin", file(0o600, (1000, 1000), &blob)),
+        ("data/far", member(b'2', 0o777, (1000, 1000), b"", &long_target, (0, 0))),
+        ("dev", directory(0o755, (0, 0))),
+        ("dev/fifo", member(b'6', 0o620, (0, 5), b"", "", (0, 0))),
+        ("dev/null", member(b'3', 0o666, (0, 0), b"", "", (1, 3))),
+        ("dev/sda", member(b'4', 0o660, (0, 6), b"", "", (8, 0))),
+        ("etc", directory(0o750, (0, 42))),
+        ("etc/motd", file(0o644, (0, 0), b"first\n")),
+        ("etc/shadow", file(0o640, (0, 42), b"root:*:19000::::::\n")),
+        ("etc/motd", file(0o644, (0, 0), b"last\n")),
+        ("long", directory(0o755, (0, 0))),
+        (long_name.as_str(), file(0o644, (1000, 100), b"kept whole\n")),
+    ];
+    let tar = scratch.join("backup.tar");
+    fs::write(&tar, archive(&tree)).unwrap();
+    let image = scratch.join("backup.iso");
+    let result = run(&[&"from-tar", &tar, &image]);
+    assert!(result.status.success(), "from-tar failed: {}", String::from_utf8_lossy(&result.stderr));
+    assert!(!String::from_utf8_lossy(&result.stderr).contains("Skipping"), "{}", String::from_utf8_lossy(&result.stderr));
+
+    // By path, so the last etc/motd is the one expected
+    let expected: BTreeMap<String, Member> = tree.iter().map(|(name, member)| (name.to_string(), member.clone())).collect();
+
+    // The reader finds every member's attributes in the Rock Ridge entries
+    let mut reader = IsoReader::open(fs::File::open(&image).unwrap()).unwrap();
+    let entries = reader.read_tree().unwrap();
+    for (name, member) in &expected {
+        let entry = entries.iter().find(|entry| entry.path == format!("/{}", name)).unwrap_or_else(|| panic!("/{} is missing", name));
+        let kind = match member.kind {
+            b'5' => 0o040000,
+            b'2' => 0o120000,
+            b'6' => 0o010000,
+            b'3' => 0o020000,
+            b'4' => 0o060000,
+            _ => 0o100000,
+        };
+        assert_eq!((entry.mode, entry.uid, entry.gid), (kind | member.mode, member.owner.0, member.owner.1), "/{}", name);
+        assert_eq!(entry.symlink.as_deref().unwrap_or(""), member.link, "/{}", name);
+        assert_eq!(entry.device, matches!(member.kind, b'3' | b'4').then_some(member.device), "/{}", name);
+        assert_eq!(entry.modified, FIXTURE_TIME, "/{}", name);
+    }
+
+    // to-tar gives the same members back
+    let back = scratch.join("back.tar");
+    let result = run(&[&"to-tar", &image, &back]);
+    assert!(result.status.success(), "to-tar failed: {}", String::from_utf8_lossy(&result.stderr));
+    assert_eq!(members(&fs::read(&back).unwrap()), expected);
+
+    let result = run(&[&"ls", &"-l", &image]);
+    let listing = String::from_utf8_lossy(&result.stdout);
+    for (mode, path) in [("prw--w----", "/dev/fifo"), ("crw-rw-rw-", "/dev/null"), ("brw-rw----", "/dev/sda"), ("lrwxrwxrwx", "/bin/sh")] {
+        assert!(listing.lines().any(|line| line.starts_with(mode) && line.ends_with(path)), "{} {} not in {}", mode, path, listing);
+    }
+
+    // libarchive lists the image the same way, long names and targets whole
+    if installed("bsdtar") {
+        let result = Command::new("bsdtar").arg("-tvf").arg(&image).output().unwrap();
+        assert!(result.status.success() && result.stderr.is_empty(), "bsdtar: {}", String::from_utf8_lossy(&result.stderr));
+        let listing = String::from_utf8_lossy(&result.stdout);
+        for line in [" bin/sh -> tool".to_string(), format!(" data/far -> {}", long_target), format!(" {}", long_name)] {
+            assert!(listing.lines().any(|found| found.ends_with(&line)), "{} not in {}", line, listing);
+        }
+        assert!(listing.lines().any(|found| found.starts_with("prw--w----") && found.ends_with(" dev/fifo")), "{}", listing);
+        assert!(listing.lines().any(|found| found.starts_with("brw-rw----") && found.contains(" 8,0 ") && found.ends_with(" dev/sda")), "{}", listing);
+    }
+    fs::remove_dir_all(&scratch).unwrap();
+}
@@ -0,0 +1,35 @@
+// Unicode normalization of names (--normalize)
+
+mod common;
+
+use std::fs;
+
+use common::{run, scratch_dir};
+
+#[test]
+fn normalized_names() {
+    let scratch = scratch_dir("normalize");
+    let source = scratch.join("source");
+    fs::create_dir_all(source.join("Mu\u{308}nchen")).unwrap();
+    fs::write(source.join("Mu\u{308}nchen").join("cafe\u{301}.txt"), "decomposed\n").unwrap();
+    let image = scratch.join("normalized.iso");
+
+    // Names decomposed as macOS hands them out are stored composed
+    let result = run(&[&"--normalize", &"nfc", &source, &image]);
+    assert!(result.status.success(), "makeiso failed: {}", String::from_utf8_lossy(&result.stderr));
+    let listing = run(&[&"ls", &image]);
+    let listing = String::from_utf8_lossy(&listing.stdout);
+    assert!(listing.contains("[DIR] M\u{fc}nchen") && listing.contains(" caf\u{e9}.txt"), "{}", listing);
+    assert!(!listing.contains('\u{308}') && !listing.contains('\u{301}'), "{}", listing);
+
+    // A path typed decomposed is only found when reading normalizes it too
+    let wanted = "/Mu\u{308}nchen/cafe\u{301}.txt";
+    let target = scratch.join("plain");
+    let result = run(&[&"extract", &image, &target, &wanted]);
+    assert!(!result.status.success());
+    let target = scratch.join("normalized");
+    let result = run(&[&"extract", &"--normalize", &"nfc", &image, &target, &wanted]);
+    assert!(result.status.success(), "extract failed: {}", String::from_utf8_lossy(&result.stderr));
+    assert_eq!(fs::read_to_string(target.join("M\u{fc}nchen").join("caf\u{e9}.txt")).unwrap(), "decomposed\n");
+    fs::remove_dir_all(&scratch).unwrap();
+}
@@ -0,0 +1,36 @@
+// Write-behind (--pipeline-depth): the same image as writing inline, and the writer's errors
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+
+use common::{reproducible_build, scratch_dir};
+
+#[test]
+fn pipelined_writes() {
+    let scratch = scratch_dir("pipeline");
+    let source = scratch.join("source");
+    fs::create_dir_all(source.join("big")).unwrap();
+    for index in 0..40 {
+        fs::write(source.join(format!("small-{}.txt", index)), format!("file {}\n", index)).unwrap();
+    }
+    fs::write(source.join("big/data.bin"), (0..3_000_000u32).map(|i| (i % 253) as u8).collect::<Vec<_>>()).unwrap();
+    let build = |extra: &[&str], image: &Path| {
+        reproducible_build(extra, &source, image)
+    };
+    for extra in [&[][..], &["--single-pass"][..], &["--read-workers", "2"][..]] {
+        let (inline, pipelined) = (scratch.join("inline.iso"), scratch.join("pipelined.iso"));
+        assert!(build(extra, &inline).status.success());
+        let result = build(&[extra, &["--pipeline-depth", "2"][..]].concat(), &pipelined);
+        assert!(result.status.success(), "makeiso {:?} failed: {}", extra, String::from_utf8_lossy(&result.stderr));
+        assert!(fs::read(&inline).unwrap() == fs::read(&pipelined).unwrap(), "{:?} with --pipeline-depth differs", extra);
+    }
+    // The writer's errors end the run
+    if Path::new("/dev/full").exists() {
+        let result = build(&["--pipeline-depth", "2"], Path::new("/dev/full"));
+        assert!(!result.status.success());
+        assert!(String::from_utf8_lossy(&result.stderr).contains("No space left"), "{}", String::from_utf8_lossy(&result.stderr));
+    }
+    fs::remove_dir_all(&scratch).unwrap();
+}
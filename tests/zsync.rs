@@ -0,0 +1,43 @@
+// zsync control files (--zsync and makeiso zsync)
+
+mod common;
+
+use std::fs;
+use std::process::Command;
+
+use common::{run, scratch_dir};
+
+#[test]
+fn zsync_control_files() {
+    let scratch = scratch_dir("zsync");
+    let source = scratch.join("source");
+    fs::create_dir_all(&source).unwrap();
+    fs::write(source.join("data.bin"), (0..100_000u32).map(|i| (i * 7 % 251) as u8).collect::<Vec<_>>()).unwrap();
+    let image = scratch.join("nightly.iso");
+    let result = run(&[&"--zsync", &source, &image]);
+    assert!(result.status.success(), "makeiso failed: {}", String::from_utf8_lossy(&result.stderr));
+
+    let contents = fs::read(&image).unwrap();
+    let control = fs::read(scratch.join("nightly.iso.zsync")).unwrap();
+    let split = control.windows(2).position(|pair| pair == b"\n\n").unwrap();
+    let header = String::from_utf8_lossy(&control[..split]).into_owned();
+    assert!(header.starts_with("zsync: 0.6.2\nFilename: nightly.iso\nMTime: "), "{}", header);
+    assert!(header.contains(&format!("\nBlocksize: 2048\nLength: {}\n", contents.len())), "{}", header);
+    assert!(header.contains("\nURL: nightly.iso\n"), "{}", header);
+    let lengths: Vec<usize> = header.split("Hash-Lengths: ").nth(1).unwrap().lines().next().unwrap().split(',').map(|n| n.parse().unwrap()).collect();
+    assert_eq!(control.len() - split - 2, contents.len() / 2048 * (lengths[1] + lengths[2]));
+    if let Ok(result) = Command::new("sha1sum").arg(&image).output() {
+        let sha1 = String::from_utf8_lossy(&result.stdout).split_whitespace().next().unwrap().to_string();
+        assert!(header.ends_with(&format!("\nSHA-1: {}", sha1)), "{}", header);
+    }
+    // The sums of the first block: its weak checksum (the sum of its bytes, then of each
+    // weighted by its distance from the end) is 0 for the zeroes of the system area
+    assert_eq!(control[split + 2..split + 2 + lengths[1]], vec![0; lengths[1]][..]);
+
+    let other = scratch.join("other.zsync");
+    let result = run(&[&"zsync", &"--url", &"https://mirror/nightly.iso", &"-o", &other, &image]);
+    assert!(result.status.success(), "zsync failed: {}", String::from_utf8_lossy(&result.stderr));
+    let written = fs::read(&other).unwrap();
+    assert_eq!(String::from_utf8_lossy(&written).replace("URL: https://mirror/nightly.iso", "URL: nightly.iso"), String::from_utf8_lossy(&control));
+    fs::remove_dir_all(&scratch).unwrap();
+}